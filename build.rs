@@ -3,9 +3,50 @@
 // have to keep features always on, and do conditional compilation within the
 // source code
 
+// Generates the default HandlerTable of src/instructions/execute.rs from the
+// opcode definitions: insts::OP_<NAME> is executed by `fn handle_<name>`, so
+// an opcode without a handler fails the build.
+fn generate_execute_table() {
+    use ckb_vm_definitions::instructions::{
+        INSTRUCTION_OPCODE_NAMES, MINIMAL_OPCODE, OP_CUSTOM_TRACE_END, OP_UNLOADED,
+    };
+    use std::env;
+    use std::fmt::Write;
+    use std::fs;
+    use std::path::Path;
+
+    // Opcodes left to handle_invalid: OP_UNLOADED marks instructions not
+    // decoded yet, and OP_CUSTOM_TRACE_END only ends traces of the ASM
+    // backend.
+    let unhandled = [OP_UNLOADED, OP_CUSTOM_TRACE_END];
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut table = String::new();
+    table.push_str("// Generated by build.rs from the opcode definitions, do not edit.\n");
+    table.push_str("impl<Mac: Machine> HandlerTable<Mac> {\n");
+    table.push_str("    pub const DEFAULT: [InstructionHandler<Mac>; HANDLER_TABLE_SIZE] = {\n");
+    table.push_str("        let mut table = [handle_invalid::<Mac> as InstructionHandler<Mac>; HANDLER_TABLE_SIZE];\n");
+    for (index, name) in INSTRUCTION_OPCODE_NAMES.iter().enumerate() {
+        if unhandled.contains(&(MINIMAL_OPCODE + index as u16)) {
+            continue;
+        }
+        writeln!(
+            table,
+            "        table[(insts::OP_{} - MINIMAL_OPCODE) as usize] = handle_{}::<Mac>;",
+            name,
+            name.to_lowercase()
+        )
+        .expect("write table");
+    }
+    table.push_str("        table\n    };\n}\n");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR");
+    fs::write(Path::new(&out_dir).join("execute_table.rs"), table).expect("write table");
+}
+
 fn main() {
     use std::env;
 
+    generate_execute_table();
+
     let target_family = env::var("CARGO_CFG_TARGET_FAMILY").unwrap_or_default();
     let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
     let is_windows = target_family == "windows";
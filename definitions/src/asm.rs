@@ -50,7 +50,7 @@ pub struct AsmCoreMachine {
     pub chaos_seed: u32,
     pub load_reservation_address: u64,
     pub reset_signal: u8,
-    pub isa: u8,
+    pub isa: u64,
     pub version: u32,
 
     pub memory_size: u64,
@@ -68,12 +68,12 @@ pub struct AsmCoreMachine {
 }
 
 impl AsmCoreMachine {
-    pub fn new(isa: u8, version: u32, max_cycles: u64) -> Box<AsmCoreMachine> {
+    pub fn new(isa: u64, version: u32, max_cycles: u64) -> Box<AsmCoreMachine> {
         Self::new_with_memory(isa, version, max_cycles, RISCV_MAX_MEMORY)
     }
 
     pub fn new_with_memory(
-        isa: u8,
+        isa: u64,
         version: u32,
         max_cycles: u64,
         memory_size: usize,
@@ -0,0 +1,550 @@
+// Renders instructions in the internal format as assembly text, following
+// the operand order of the RISC-V assembler:
+//
+// assert_eq!(disassemble(pack_r(OP_ADD, A0, A1, A2)), "add a0, a1, a2");
+// assert_eq!(disassemble(pack_i(OP_LD_VERSION1, A0, SP, 8)), "ld a0, 8(sp)");
+//
+// Immediates are shown as decoded: branch and jump offsets are relative to
+// the instruction, and LUI/AUIPC show the upper 20 bits. Instructions
+// created by macro-op fusion keep the operands of their internal format.
+use crate::encoding::{opcode, unpack_b, unpack_i, unpack_r, unpack_r4, unpack_r5, unpack_s};
+use crate::encoding::{unpack_j, unpack_u};
+use crate::instructions::{self as insts, try_instruction_opcode_name, Instruction};
+use crate::instructions::{InstructionOpcode, MINIMAL_OPCODE};
+use crate::registers::REGISTER_ABI_NAMES;
+use std::fmt;
+
+#[rustfmt::skip]
+pub const FLOAT_REGISTER_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3",
+    "ft4", "ft5", "ft6", "ft7",
+    "fs0", "fs1", "fa0", "fa1",
+    "fa2", "fa3", "fa4", "fa5",
+    "fa6", "fa7", "fs2", "fs3",
+    "fs4", "fs5", "fs6", "fs7",
+    "fs8", "fs9", "fs10", "fs11",
+    "ft8", "ft9", "ft10", "ft11",
+];
+
+// Rounding mode encoded in instructions to use the one of fcsr.
+const DYNAMIC_ROUNDING_MODE: u8 = 0b111;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    FloatRegister(u8),
+    VectorRegister(u8),
+    Immediate(i64),
+    // offset(base), as used by loads and stores.
+    Memory { base: u8, offset: i32 },
+    // (base), as used by atomic and vector memory instructions.
+    Address(u8),
+    Csr(u16),
+    // Static rounding mode of floating point instructions, omitted when the
+    // instruction uses the dynamic one.
+    RoundingMode(u8),
+    // vtype immediate of VSETVLI and VSETIVLI.
+    VectorType(i32),
+    // Predecessor or successor set of FENCE, i, o, r and w from bit 3 to 0.
+    FenceSet(u8),
+    // v0.t, the instruction is masked.
+    VectorMask,
+}
+
+fn register_name(index: u8, names: &[&str; 32], prefix: char) -> String {
+    match names.get(usize::from(index)) {
+        Some(name) => name.to_string(),
+        None => format!("{}{}", prefix, index),
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operand::Register(r) => write!(f, "{}", register_name(r, &REGISTER_ABI_NAMES, 'x')),
+            Operand::FloatRegister(r) => {
+                write!(f, "{}", register_name(r, &FLOAT_REGISTER_ABI_NAMES, 'f'))
+            }
+            Operand::VectorRegister(r) => write!(f, "v{}", r),
+            Operand::Immediate(imm) => write!(f, "{}", imm),
+            Operand::Memory { base, offset } => {
+                write!(f, "{}({})", offset, Operand::Register(base))
+            }
+            Operand::Address(base) => write!(f, "({})", Operand::Register(base)),
+            Operand::Csr(csr) => write!(f, "0x{:x}", csr),
+            Operand::RoundingMode(rm) => match rm {
+                0 => write!(f, "rne"),
+                1 => write!(f, "rtz"),
+                2 => write!(f, "rdn"),
+                3 => write!(f, "rup"),
+                4 => write!(f, "rmm"),
+                _ => write!(f, "{}", rm),
+            },
+            Operand::VectorType(vtype) => {
+                let sew = 8 << ((vtype >> 3) & 0b111);
+                let lmul = match vtype & 0b111 {
+                    0 => "m1",
+                    1 => "m2",
+                    2 => "m4",
+                    3 => "m8",
+                    5 => "mf8",
+                    6 => "mf4",
+                    7 => "mf2",
+                    _ => "reserved",
+                };
+                let ta = if vtype & (1 << 6) != 0 { "ta" } else { "tu" };
+                let ma = if vtype & (1 << 7) != 0 { "ma" } else { "mu" };
+                write!(f, "e{}, {}, {}, {}", sew, lmul, ta, ma)
+            }
+            Operand::FenceSet(set) => {
+                if set == 0 {
+                    return write!(f, "0");
+                }
+                for (bit, name) in ['i', 'o', 'r', 'w'].iter().enumerate() {
+                    if set & (0b1000 >> bit) != 0 {
+                        write!(f, "{}", name)?;
+                    }
+                }
+                Ok(())
+            }
+            Operand::VectorMask => write!(f, "v0.t"),
+        }
+    }
+}
+
+// An instruction split into its assembler mnemonic and operands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub opcode: InstructionOpcode,
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+        Ok(())
+    }
+}
+
+// Assembler mnemonic of an opcode, e.g. ld for LD_VERSION1 and fadd.s for
+// FADD_S. Opcodes specific to CKB VM keep their internal names.
+fn mnemonic(op: InstructionOpcode, name: &str) -> String {
+    use insts::*;
+    let name = name
+        .strip_suffix("_VERSION0")
+        .or_else(|| name.strip_suffix("_VERSION1"))
+        .unwrap_or(name);
+    let mnemonic = match op {
+        OP_FENCEI => "fence.i",
+        OP_ADDUW => "add.uw",
+        OP_SH1ADDUW => "sh1add.uw",
+        OP_SH2ADDUW => "sh2add.uw",
+        OP_SH3ADDUW => "sh3add.uw",
+        OP_SLLIUW => "slli.uw",
+        OP_SEXTB => "sext.b",
+        OP_SEXTH => "sext.h",
+        OP_ZEXTH => "zext.h",
+        OP_ORCB => "orc.b",
+        OP_UNLOADED | OP_WIDE_MUL..=OP_CUSTOM_TRACE_END => return name.to_lowercase(),
+        _ => return name.to_lowercase().replace('_', "."),
+    };
+    mnemonic.to_string()
+}
+
+fn with_rounding_mode(mut operands: Vec<Operand>, rm: u8) -> Vec<Operand> {
+    if rm != DYNAMIC_ROUNDING_MODE {
+        operands.push(Operand::RoundingMode(rm));
+    }
+    operands
+}
+
+// Operands of vector arithmetic instructions, packed as R4type(vd, vs2, src,
+// vm), see encoding::to_riscv.
+fn vector_operands(op: InstructionOpcode, name: &str, i: Instruction) -> Vec<Operand> {
+    use insts::*;
+    use Operand::*;
+    let (vd, vs2, src, vm) = unpack_r4(i);
+    let src = if name.ends_with("_VX")
+        || name.ends_with("_VXM")
+        || name.ends_with("_V_X")
+        || name.ends_with("_S_X")
+    {
+        Register(src)
+    } else if name.ends_with("_VI") || name.ends_with("_VIM") || name.ends_with("_V_I") {
+        match op {
+            OP_VSLL_VI | OP_VSRL_VI | OP_VSRA_VI => Immediate(i64::from(src & 0x1f)),
+            // Sign extends the 5-bit immediate.
+            _ => Immediate(i64::from(((src << 3) as i8) >> 3)),
+        }
+    } else {
+        VectorRegister(src)
+    };
+    let mut operands = match op {
+        OP_VMERGE_VVM | OP_VMERGE_VXM | OP_VMERGE_VIM => {
+            return vec![
+                VectorRegister(vd),
+                VectorRegister(vs2),
+                src,
+                VectorRegister(0),
+            ];
+        }
+        OP_VMV_V_V | OP_VMV_V_X | OP_VMV_V_I | OP_VMV_S_X => vec![VectorRegister(vd), src],
+        OP_VMV_X_S => vec![Register(vd), VectorRegister(vs2)],
+        // Multiply-add instructions take the multiplier before vs2.
+        OP_VMADD_VV | OP_VMADD_VX | OP_VNMSUB_VV | OP_VNMSUB_VX | OP_VMACC_VV | OP_VMACC_VX
+        | OP_VNMSAC_VV | OP_VNMSAC_VX => vec![VectorRegister(vd), src, VectorRegister(vs2)],
+        _ => vec![VectorRegister(vd), VectorRegister(vs2), src],
+    };
+    if vm == 0 {
+        operands.push(VectorMask);
+    }
+    operands
+}
+
+fn operands(op: InstructionOpcode, name: &str, i: Instruction) -> Vec<Operand> {
+    use insts::*;
+    use Operand::*;
+    match op {
+        OP_UNLOADED | OP_ECALL | OP_EBREAK | OP_FENCEI | OP_MRET | OP_CUSTOM_TRACE_END => vec![],
+        OP_FENCE => {
+            let (_, pred, succ) = unpack_r(i);
+            vec![FenceSet(pred), FenceSet(succ)]
+        }
+        OP_LUI | OP_AUIPC => {
+            let (rd, imm) = unpack_u(i);
+            vec![Register(rd), Immediate(i64::from((imm as u32) >> 12))]
+        }
+        OP_JAL | OP_FAR_JUMP_REL | OP_FAR_JUMP_ABS | OP_CUSTOM_LOAD_IMM => {
+            let (rd, imm) = unpack_j(i);
+            vec![Register(rd), Immediate(i64::from(imm))]
+        }
+        OP_CUSTOM_LOAD_UIMM => {
+            let (rd, imm) = unpack_u(i);
+            vec![Register(rd), Immediate(i64::from(imm as u32))]
+        }
+        OP_JALR_VERSION0 | OP_JALR_VERSION1 | OP_LB_VERSION0 | OP_LB_VERSION1 | OP_LH_VERSION0
+        | OP_LH_VERSION1 | OP_LW_VERSION0 | OP_LW_VERSION1 | OP_LD_VERSION0 | OP_LD_VERSION1
+        | OP_LBU_VERSION0 | OP_LBU_VERSION1 | OP_LHU_VERSION0 | OP_LHU_VERSION1
+        | OP_LWU_VERSION0 | OP_LWU_VERSION1 => {
+            let (rd, rs1, imm) = unpack_i(i);
+            vec![
+                Register(rd),
+                Memory {
+                    base: rs1,
+                    offset: imm,
+                },
+            ]
+        }
+        OP_FLW | OP_FLD => {
+            let (rd, rs1, imm) = unpack_i(i);
+            vec![
+                FloatRegister(rd),
+                Memory {
+                    base: rs1,
+                    offset: imm,
+                },
+            ]
+        }
+        OP_SB | OP_SH | OP_SW | OP_SD => {
+            let (rs1, rs2, imm) = unpack_s(i);
+            vec![
+                Register(rs2),
+                Memory {
+                    base: rs1,
+                    offset: imm,
+                },
+            ]
+        }
+        OP_FSW | OP_FSD => {
+            let (rs1, rs2, imm) = unpack_s(i);
+            vec![
+                FloatRegister(rs2),
+                Memory {
+                    base: rs1,
+                    offset: imm,
+                },
+            ]
+        }
+        OP_BEQ | OP_BNE | OP_BLT | OP_BGE | OP_BLTU | OP_BGEU => {
+            let (rs1, rs2, imm) = unpack_b(i);
+            vec![Register(rs1), Register(rs2), Immediate(i64::from(imm))]
+        }
+        OP_ADDI | OP_SLTI | OP_SLTIU | OP_XORI | OP_ORI | OP_ANDI | OP_ADDIW | OP_SLLI
+        | OP_SRLI | OP_SRAI | OP_SLLIW | OP_SRLIW | OP_SRAIW | OP_BCLRI | OP_BEXTI | OP_BINVI
+        | OP_BSETI | OP_RORI | OP_RORIW | OP_SLLIUW => {
+            let (rd, rs1, imm) = unpack_i(i);
+            vec![Register(rd), Register(rs1), Immediate(i64::from(imm))]
+        }
+        OP_CSRRW | OP_CSRRS | OP_CSRRC => {
+            let (rd, rs1, csr) = unpack_i(i);
+            vec![Register(rd), Csr(csr as u16), Register(rs1)]
+        }
+        OP_CSRRWI | OP_CSRRSI | OP_CSRRCI => {
+            let (rd, uimm, csr) = unpack_i(i);
+            vec![Register(rd), Csr(csr as u16), Immediate(i64::from(uimm))]
+        }
+        OP_LR_W | OP_LR_D => {
+            let (rd, rs1, _) = unpack_r(i);
+            vec![Register(rd), Address(rs1)]
+        }
+        OP_SC_W | OP_AMOSWAP_W | OP_AMOADD_W | OP_AMOXOR_W | OP_AMOAND_W | OP_AMOOR_W
+        | OP_AMOMIN_W | OP_AMOMAX_W | OP_AMOMINU_W | OP_AMOMAXU_W | OP_SC_D | OP_AMOSWAP_D
+        | OP_AMOADD_D | OP_AMOXOR_D | OP_AMOAND_D | OP_AMOOR_D | OP_AMOMIN_D | OP_AMOMAX_D
+        | OP_AMOMINU_D | OP_AMOMAXU_D => {
+            let (rd, rs1, rs2) = unpack_r(i);
+            vec![Register(rd), Register(rs2), Address(rs1)]
+        }
+        OP_CLZ | OP_CTZ | OP_CPOP | OP_CLZW | OP_CTZW | OP_CPOPW | OP_SEXTB | OP_SEXTH
+        | OP_ZEXTH | OP_ORCB | OP_REV8 => {
+            let (rd, rs1, _) = unpack_r(i);
+            vec![Register(rd), Register(rs1)]
+        }
+        OP_FADD_S | OP_FSUB_S | OP_FMUL_S | OP_FDIV_S | OP_FADD_D | OP_FSUB_D | OP_FMUL_D
+        | OP_FDIV_D => {
+            let (rd, rs1, rs2, rm) = unpack_r4(i);
+            let operands = vec![FloatRegister(rd), FloatRegister(rs1), FloatRegister(rs2)];
+            with_rounding_mode(operands, rm)
+        }
+        OP_FSQRT_S | OP_FSQRT_D | OP_FCVT_S_D | OP_FCVT_D_S => {
+            let (rd, rs1, _, rm) = unpack_r4(i);
+            with_rounding_mode(vec![FloatRegister(rd), FloatRegister(rs1)], rm)
+        }
+        OP_FCVT_W_S | OP_FCVT_WU_S | OP_FCVT_L_S | OP_FCVT_LU_S | OP_FCVT_W_D | OP_FCVT_WU_D
+        | OP_FCVT_L_D | OP_FCVT_LU_D => {
+            let (rd, rs1, _, rm) = unpack_r4(i);
+            with_rounding_mode(vec![Register(rd), FloatRegister(rs1)], rm)
+        }
+        OP_FCVT_S_W | OP_FCVT_S_WU | OP_FCVT_S_L | OP_FCVT_S_LU | OP_FCVT_D_W | OP_FCVT_D_WU
+        | OP_FCVT_D_L | OP_FCVT_D_LU => {
+            let (rd, rs1, _, rm) = unpack_r4(i);
+            with_rounding_mode(vec![FloatRegister(rd), Register(rs1)], rm)
+        }
+        OP_FSGNJ_S | OP_FSGNJN_S | OP_FSGNJX_S | OP_FMIN_S | OP_FMAX_S | OP_FSGNJ_D
+        | OP_FSGNJN_D | OP_FSGNJX_D | OP_FMIN_D | OP_FMAX_D => {
+            let (rd, rs1, rs2) = unpack_r(i);
+            vec![FloatRegister(rd), FloatRegister(rs1), FloatRegister(rs2)]
+        }
+        OP_FEQ_S | OP_FLT_S | OP_FLE_S | OP_FEQ_D | OP_FLT_D | OP_FLE_D => {
+            let (rd, rs1, rs2) = unpack_r(i);
+            vec![Register(rd), FloatRegister(rs1), FloatRegister(rs2)]
+        }
+        OP_FMV_X_W | OP_FMV_X_D | OP_FCLASS_S | OP_FCLASS_D => {
+            let (rd, rs1, _) = unpack_r(i);
+            vec![Register(rd), FloatRegister(rs1)]
+        }
+        OP_FMV_W_X | OP_FMV_D_X => {
+            let (rd, rs1, _) = unpack_r(i);
+            vec![FloatRegister(rd), Register(rs1)]
+        }
+        OP_FMADD_S | OP_FMSUB_S | OP_FNMSUB_S | OP_FNMADD_S | OP_FMADD_D | OP_FMSUB_D
+        | OP_FNMSUB_D | OP_FNMADD_D => {
+            let (rd, rs1, rs2, rs3, rm) = unpack_r5(i);
+            let operands = vec![
+                FloatRegister(rd),
+                FloatRegister(rs1),
+                FloatRegister(rs2),
+                FloatRegister(rs3),
+            ];
+            with_rounding_mode(operands, rm)
+        }
+        OP_VSETVLI => {
+            let (rd, rs1, zimm) = unpack_i(i);
+            vec![Register(rd), Register(rs1), VectorType(zimm)]
+        }
+        OP_VSETIVLI => {
+            let (rd, uimm, zimm) = unpack_i(i);
+            vec![Register(rd), Immediate(i64::from(uimm)), VectorType(zimm)]
+        }
+        OP_VLE8_V | OP_VLE16_V | OP_VLE32_V | OP_VLE64_V | OP_VSE8_V | OP_VSE16_V | OP_VSE32_V
+        | OP_VSE64_V => {
+            let (vd, rs1, _, vm) = unpack_r4(i);
+            let mut operands = vec![VectorRegister(vd), Address(rs1)];
+            if vm == 0 {
+                operands.push(VectorMask);
+            }
+            operands
+        }
+        OP_VSETVL => {
+            let (rd, rs1, rs2) = unpack_r(i);
+            vec![Register(rd), Register(rs1), Register(rs2)]
+        }
+        // The remaining vector instructions are arithmetic ones.
+        _ if op & 0xff == OP_VSETVLI & 0xff => vector_operands(op, name, i),
+        OP_WIDE_MUL | OP_WIDE_MULU | OP_WIDE_MULSU | OP_WIDE_DIV | OP_WIDE_DIVU | OP_SBB
+        | OP_ADCS | OP_SBBS => {
+            let (rd, rs1, rs2, rs3) = unpack_r4(i);
+            vec![Register(rd), Register(rs1), Register(rs2), Register(rs3)]
+        }
+        OP_ADD3A | OP_ADD3B | OP_ADD3C => {
+            let (rd, rs1, rs2, rs3, rs4) = unpack_r5(i);
+            vec![
+                Register(rd),
+                Register(rs1),
+                Register(rs2),
+                Register(rs3),
+                Register(rs4),
+            ]
+        }
+        // Integer register-register instructions.
+        _ => {
+            let (rd, rs1, rs2) = unpack_r(i);
+            vec![Register(rd), Register(rs1), Register(rs2)]
+        }
+    }
+}
+
+// Splits an instruction into mnemonic and operands, None if its opcode is
+// unknown.
+pub fn disassemble_instruction(i: Instruction) -> Option<DisassembledInstruction> {
+    let op = opcode(i);
+    // Fast path opcodes ignore op2.
+    let op = if op as u8 as u16 >= MINIMAL_OPCODE {
+        op & 0xff
+    } else {
+        op
+    };
+    let name = try_instruction_opcode_name(op)?;
+    Some(DisassembledInstruction {
+        opcode: op,
+        mnemonic: mnemonic(op, name),
+        operands: operands(op, name, i),
+    })
+}
+
+// Renders an instruction as assembly text, e.g. "add a0, a1, a2". Unknown
+// opcodes are rendered as ".insn" followed by the raw value.
+pub fn disassemble(i: Instruction) -> String {
+    match disassemble_instruction(i) {
+        Some(instruction) => instruction.to_string(),
+        None => format!(".insn 0x{:016x}", i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{pack_i, pack_r, pack_r4, pack_r5, pack_s, pack_u};
+    use crate::instructions::*;
+    use crate::registers::{A0, A1, A2, RA, SP, T0};
+
+    #[test]
+    fn test_disassemble_integer() {
+        let (a0, a1, a2) = (A0 as u8, A1 as u8, A2 as u8);
+        assert_eq!(disassemble(pack_r(OP_ADD, a0, a1, a2)), "add a0, a1, a2");
+        assert_eq!(disassemble(pack_i(OP_ADDI, a0, a1, -1)), "addi a0, a1, -1");
+        assert_eq!(
+            disassemble(pack_i(OP_LD_VERSION1, a0, SP as u8, 8)),
+            "ld a0, 8(sp)"
+        );
+        assert_eq!(
+            disassemble(pack_i(OP_JALR_VERSION0, RA as u8, T0 as u8, 0)),
+            "jalr ra, 0(t0)"
+        );
+        assert_eq!(
+            disassemble(pack_s(OP_SD, SP as u8, a0, -8)),
+            "sd a0, -8(sp)"
+        );
+        assert_eq!(disassemble(pack_s(OP_BNE, a0, 0, -12)), "bne a0, zero, -12");
+        assert_eq!(disassemble(pack_u(OP_LUI, a0, 0x12000)), "lui a0, 18");
+        assert_eq!(disassemble(pack_u(OP_JAL, RA as u8, -16)), "jal ra, -16");
+        assert_eq!(disassemble(pack_r(OP_ECALL, 0, 0, 0)), "ecall");
+        assert_eq!(
+            disassemble(pack_r(OP_FENCE, 0, 0b0011, 0b0001)),
+            "fence rw, w"
+        );
+        assert_eq!(disassemble(pack_r(OP_FENCEI, 0, 0, 0)), "fence.i");
+        assert_eq!(disassemble(pack_r(OP_SEXTB, a0, a1, 4)), "sext.b a0, a1");
+        assert_eq!(
+            disassemble(pack_r(OP_ADDUW, a0, a1, a2)),
+            "add.uw a0, a1, a2"
+        );
+        assert_eq!(
+            disassemble(pack_r(OP_AMOADD_W, a0, a1, a2)),
+            "amoadd.w a0, a2, (a1)"
+        );
+        assert_eq!(disassemble(pack_r(OP_LR_D, a0, a1, 0)), "lr.d a0, (a1)");
+        assert_eq!(
+            disassemble(pack_i(OP_CSRRS, a0, 0, 0xc00)),
+            "csrrs a0, 0xc00, zero"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_WIDE_MUL, a0, a1, a2, T0 as u8)),
+            "wide_mul a0, a1, a2, t0"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_float_and_vector() {
+        let (a0, a1) = (A0 as u8, A1 as u8);
+        assert_eq!(
+            disassemble(pack_r4(OP_FADD_D, 10, 11, 12, DYNAMIC_ROUNDING_MODE)),
+            "fadd.d fa0, fa1, fa2"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_FCVT_W_S, a0, 10, 0, 1)),
+            "fcvt.w.s a0, fa0, rtz"
+        );
+        assert_eq!(
+            disassemble(pack_i(OP_FLW, 0, SP as u8, 4)),
+            "flw ft0, 4(sp)"
+        );
+        assert_eq!(
+            disassemble(pack_r5(OP_FMADD_S, 0, 1, 2, 3, DYNAMIC_ROUNDING_MODE)),
+            "fmadd.s ft0, ft1, ft2, ft3"
+        );
+        // e32, m1, tail and mask agnostic.
+        assert_eq!(
+            disassemble(pack_i(OP_VSETVLI, a0, a1, 0b1101_0000)),
+            "vsetvli a0, a1, e32, m1, ta, ma"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VADD_VV, 1, 2, 3, 1)),
+            "vadd.vv v1, v2, v3"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VADD_VX, 1, 2, a0, 0)),
+            "vadd.vx v1, v2, a0, v0.t"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VADD_VI, 1, 2, 0x1f, 1)),
+            "vadd.vi v1, v2, -1"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VSLL_VI, 1, 2, 0x1f, 1)),
+            "vsll.vi v1, v2, 31"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VMACC_VV, 1, 2, 3, 1)),
+            "vmacc.vv v1, v3, v2"
+        );
+        assert_eq!(
+            disassemble(pack_r4(OP_VLE8_V, 1, a0, 0, 1)),
+            "vle8.v v1, (a0)"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_structure() {
+        let a0 = A0 as u8;
+        let instruction = disassemble_instruction(pack_s(OP_SW, SP as u8, a0, 12)).unwrap();
+        assert_eq!(instruction.opcode, OP_SW);
+        assert_eq!(instruction.mnemonic, "sw");
+        assert_eq!(
+            instruction.operands,
+            vec![
+                Operand::Register(a0),
+                Operand::Memory {
+                    base: SP as u8,
+                    offset: 12
+                }
+            ]
+        );
+        assert_eq!(disassemble_instruction(0x0f0f), None);
+        assert_eq!(disassemble(0x0f0f), ".insn 0x0000000000000f0f");
+    }
+}
@@ -0,0 +1,675 @@
+// Stable schema of the internal Instruction layout described in
+// instructions.rs, for tools producing or consuming decoded instruction
+// streams outside of CKB VM.
+//
+// The layout is identified by INSTRUCTION_SCHEMA_VERSION, which is bumped
+// whenever the position of a field or the meaning of an opcode changes.
+// Tools persisting decoded instructions should store the version along with
+// them and refuse to load a mismatching one.
+use crate::instructions::{self as insts, Instruction, InstructionOpcode};
+
+pub const INSTRUCTION_SCHEMA_VERSION: u32 = 1;
+
+// Bit offsets of each field.
+pub const OP_SHIFT: u32 = 0;
+pub const RD_SHIFT: u32 = 8;
+pub const OP2_SHIFT: u32 = 16;
+pub const FLAGS_SHIFT: u32 = 24;
+pub const RS1_SHIFT: u32 = 32;
+pub const RS2_SHIFT: u32 = 40;
+pub const RS3_SHIFT: u32 = 48;
+pub const RS4_SHIFT: u32 = 56;
+// I-type and S-type immediates take 24 bits starting from RS2_SHIFT, U-type
+// immediates take 32 bits starting from RS1_SHIFT. Both are sign extended.
+pub const IS_IMMEDIATE_SHIFT: u32 = 40;
+pub const U_IMMEDIATE_SHIFT: u32 = 32;
+
+fn pack_opcode(op: InstructionOpcode) -> Instruction {
+    (u64::from(op) >> 8 << OP2_SHIFT) | u64::from(op as u8)
+}
+
+pub fn opcode(i: Instruction) -> InstructionOpcode {
+    ((((i >> OP2_SHIFT) & 0xff) << 8) | (i & 0xff)) as InstructionOpcode
+}
+
+// Length in bytes of the original RISC-V instruction, 0 if not set.
+pub fn length(i: Instruction) -> u8 {
+    (((i >> FLAGS_SHIFT) & 0x0f) << 1) as u8
+}
+
+pub fn with_length(i: Instruction, length: u8) -> Instruction {
+    debug_assert!(length % 2 == 0 && length <= 30);
+    (i & !(0x0f << FLAGS_SHIFT)) | (u64::from(length & 0x1f) >> 1 << FLAGS_SHIFT)
+}
+
+fn field(i: Instruction, shift: u32) -> u8 {
+    (i >> shift) as u8
+}
+
+pub fn pack_r(op: InstructionOpcode, rd: u8, rs1: u8, rs2: u8) -> Instruction {
+    pack_opcode(op)
+        | u64::from(rd) << RD_SHIFT
+        | u64::from(rs1) << RS1_SHIFT
+        | u64::from(rs2) << RS2_SHIFT
+}
+
+// Returns (rd, rs1, rs2).
+pub fn unpack_r(i: Instruction) -> (u8, u8, u8) {
+    (field(i, RD_SHIFT), field(i, RS1_SHIFT), field(i, RS2_SHIFT))
+}
+
+pub fn pack_r4(op: InstructionOpcode, rd: u8, rs1: u8, rs2: u8, rs3: u8) -> Instruction {
+    pack_r(op, rd, rs1, rs2) | u64::from(rs3) << RS3_SHIFT
+}
+
+// Returns (rd, rs1, rs2, rs3).
+pub fn unpack_r4(i: Instruction) -> (u8, u8, u8, u8) {
+    let (rd, rs1, rs2) = unpack_r(i);
+    (rd, rs1, rs2, field(i, RS3_SHIFT))
+}
+
+pub fn pack_r5(op: InstructionOpcode, rd: u8, rs1: u8, rs2: u8, rs3: u8, rs4: u8) -> Instruction {
+    pack_r4(op, rd, rs1, rs2, rs3) | u64::from(rs4) << RS4_SHIFT
+}
+
+// Returns (rd, rs1, rs2, rs3, rs4).
+pub fn unpack_r5(i: Instruction) -> (u8, u8, u8, u8, u8) {
+    let (rd, rs1, rs2, rs3) = unpack_r4(i);
+    (rd, rs1, rs2, rs3, field(i, RS4_SHIFT))
+}
+
+// Only the lower 24 bits of `imm` are kept.
+pub fn pack_i(op: InstructionOpcode, rd: u8, rs1: u8, imm: i32) -> Instruction {
+    pack_opcode(op)
+        | u64::from(rd) << RD_SHIFT
+        | u64::from(rs1) << RS1_SHIFT
+        | u64::from(imm as u32) << IS_IMMEDIATE_SHIFT
+}
+
+// Returns (rd, rs1, imm).
+pub fn unpack_i(i: Instruction) -> (u8, u8, i32) {
+    (
+        field(i, RD_SHIFT),
+        field(i, RS1_SHIFT),
+        ((i as i64) >> IS_IMMEDIATE_SHIFT) as i32,
+    )
+}
+
+// S-type stores rs2 where other types store rd. Only the lower 24 bits of
+// `imm` are kept.
+pub fn pack_s(op: InstructionOpcode, rs1: u8, rs2: u8, imm: i32) -> Instruction {
+    pack_i(op, rs2, rs1, imm)
+}
+
+// Returns (rs1, rs2, imm).
+pub fn unpack_s(i: Instruction) -> (u8, u8, i32) {
+    let (rs2, rs1, imm) = unpack_i(i);
+    (rs1, rs2, imm)
+}
+
+// B-type shares the layout of S-type, `imm` is the branch offset.
+pub fn pack_b(op: InstructionOpcode, rs1: u8, rs2: u8, imm: i32) -> Instruction {
+    pack_s(op, rs1, rs2, imm)
+}
+
+// Returns (rs1, rs2, imm).
+pub fn unpack_b(i: Instruction) -> (u8, u8, i32) {
+    unpack_s(i)
+}
+
+// `imm` is the value loaded into rd by LUI, or added to pc by AUIPC, its
+// lower 12 bits are zero for instructions decoded from RISC-V code.
+pub fn pack_u(op: InstructionOpcode, rd: u8, imm: i32) -> Instruction {
+    pack_opcode(op) | u64::from(rd) << RD_SHIFT | u64::from(imm as u32) << U_IMMEDIATE_SHIFT
+}
+
+// Returns (rd, imm).
+pub fn unpack_u(i: Instruction) -> (u8, i32) {
+    (field(i, RD_SHIFT), ((i as i64) >> U_IMMEDIATE_SHIFT) as i32)
+}
+
+// J-type shares the layout of U-type, `imm` is the jump offset.
+pub fn pack_j(op: InstructionOpcode, rd: u8, imm: i32) -> Instruction {
+    pack_u(op, rd, imm)
+}
+
+// Returns (rd, imm).
+pub fn unpack_j(i: Instruction) -> (u8, i32) {
+    unpack_u(i)
+}
+
+// Converts an internal instruction back into its 32-bit RISC-V encoding.
+// Compressed instructions are encoded as their 32-bit equivalent, and the
+// aq/rl bits of atomic instructions, which CKB VM ignores, are cleared.
+// None is returned for VM specific instructions(macro-op fusion and trace
+// markers), and for operands not representable in RISC-V, e.g. a register
+// index above 31 or an out of range immediate.
+//
+// Raw encodings are converted into internal instructions by the decoders in
+// ckb-vm, so the two form a round trip for all RV64IMAFDB instructions, and
+// the vector instructions of ISA_V.
+pub fn to_riscv(i: Instruction) -> Option<u32> {
+    use insts::*;
+    match opcode(i) {
+        OP_LUI => encode_u(i, 0b_0110111),
+        OP_AUIPC => encode_u(i, 0b_0010111),
+        OP_JAL => encode_j(i),
+        OP_JALR_VERSION0 | OP_JALR_VERSION1 => encode_i(i, 0b_1100111, 0b_000),
+        OP_BEQ => encode_b(i, 0b_000),
+        OP_BNE => encode_b(i, 0b_001),
+        OP_BLT => encode_b(i, 0b_100),
+        OP_BGE => encode_b(i, 0b_101),
+        OP_BLTU => encode_b(i, 0b_110),
+        OP_BGEU => encode_b(i, 0b_111),
+        OP_LB_VERSION0 | OP_LB_VERSION1 => encode_i(i, 0b_0000011, 0b_000),
+        OP_LH_VERSION0 | OP_LH_VERSION1 => encode_i(i, 0b_0000011, 0b_001),
+        OP_LW_VERSION0 | OP_LW_VERSION1 => encode_i(i, 0b_0000011, 0b_010),
+        OP_LD_VERSION0 | OP_LD_VERSION1 => encode_i(i, 0b_0000011, 0b_011),
+        OP_LBU_VERSION0 | OP_LBU_VERSION1 => encode_i(i, 0b_0000011, 0b_100),
+        OP_LHU_VERSION0 | OP_LHU_VERSION1 => encode_i(i, 0b_0000011, 0b_101),
+        OP_LWU_VERSION0 | OP_LWU_VERSION1 => encode_i(i, 0b_0000011, 0b_110),
+        OP_SB => encode_s(i, 0b_0100011, 0b_000),
+        OP_SH => encode_s(i, 0b_0100011, 0b_001),
+        OP_SW => encode_s(i, 0b_0100011, 0b_010),
+        OP_SD => encode_s(i, 0b_0100011, 0b_011),
+        OP_ADDI => encode_i(i, 0b_0010011, 0b_000),
+        OP_SLTI => encode_i(i, 0b_0010011, 0b_010),
+        OP_SLTIU => encode_i(i, 0b_0010011, 0b_011),
+        OP_XORI => encode_i(i, 0b_0010011, 0b_100),
+        OP_ORI => encode_i(i, 0b_0010011, 0b_110),
+        OP_ANDI => encode_i(i, 0b_0010011, 0b_111),
+        OP_ADDIW => encode_i(i, 0b_0011011, 0b_000),
+        OP_SLLI => encode_shift(i, 0b_0010011, 0b_001, 0b_000000, 6),
+        OP_SRLI => encode_shift(i, 0b_0010011, 0b_101, 0b_000000, 6),
+        OP_SRAI => encode_shift(i, 0b_0010011, 0b_101, 0b_010000, 6),
+        OP_SLLIW => encode_shift(i, 0b_0011011, 0b_001, 0b_0000000, 5),
+        OP_SRLIW => encode_shift(i, 0b_0011011, 0b_101, 0b_0000000, 5),
+        OP_SRAIW => encode_shift(i, 0b_0011011, 0b_101, 0b_0100000, 5),
+        OP_ADD => encode_r(i, 0b_0110011, 0b_000, 0b_0000000),
+        OP_SUB => encode_r(i, 0b_0110011, 0b_000, 0b_0100000),
+        OP_SLL => encode_r(i, 0b_0110011, 0b_001, 0b_0000000),
+        OP_SLT => encode_r(i, 0b_0110011, 0b_010, 0b_0000000),
+        OP_SLTU => encode_r(i, 0b_0110011, 0b_011, 0b_0000000),
+        OP_XOR => encode_r(i, 0b_0110011, 0b_100, 0b_0000000),
+        OP_SRL => encode_r(i, 0b_0110011, 0b_101, 0b_0000000),
+        OP_SRA => encode_r(i, 0b_0110011, 0b_101, 0b_0100000),
+        OP_OR => encode_r(i, 0b_0110011, 0b_110, 0b_0000000),
+        OP_AND => encode_r(i, 0b_0110011, 0b_111, 0b_0000000),
+        OP_ADDW => encode_r(i, 0b_0111011, 0b_000, 0b_0000000),
+        OP_SUBW => encode_r(i, 0b_0111011, 0b_000, 0b_0100000),
+        OP_SLLW => encode_r(i, 0b_0111011, 0b_001, 0b_0000000),
+        OP_SRLW => encode_r(i, 0b_0111011, 0b_101, 0b_0000000),
+        OP_SRAW => encode_r(i, 0b_0111011, 0b_101, 0b_0100000),
+        OP_FENCE => {
+            let (fm, pred, succ) = unpack_r(i);
+            if fm > 0xf || pred > 0xf || succ > 0xf {
+                return None;
+            }
+            Some(u32::from(fm) << 28 | u32::from(pred) << 24 | u32::from(succ) << 20 | 0b_0001111)
+        }
+        OP_FENCEI => Some(0b_0000_0000_0000_00000_001_00000_0001111),
+        OP_ECALL => Some(0b_000000000000_00000_000_00000_1110011),
+        OP_EBREAK => Some(0b_000000000001_00000_000_00000_1110011),
+        OP_MRET => Some(0b_001100000010_00000_000_00000_1110011),
+        OP_CSRRW => encode_csr(i, 0b_001),
+        OP_CSRRS => encode_csr(i, 0b_010),
+        OP_CSRRC => encode_csr(i, 0b_011),
+        OP_CSRRWI => encode_csr(i, 0b_101),
+        OP_CSRRSI => encode_csr(i, 0b_110),
+        OP_CSRRCI => encode_csr(i, 0b_111),
+        // M
+        OP_MUL => encode_r(i, 0b_0110011, 0b_000, 0b_0000001),
+        OP_MULH => encode_r(i, 0b_0110011, 0b_001, 0b_0000001),
+        OP_MULHSU => encode_r(i, 0b_0110011, 0b_010, 0b_0000001),
+        OP_MULHU => encode_r(i, 0b_0110011, 0b_011, 0b_0000001),
+        OP_DIV => encode_r(i, 0b_0110011, 0b_100, 0b_0000001),
+        OP_DIVU => encode_r(i, 0b_0110011, 0b_101, 0b_0000001),
+        OP_REM => encode_r(i, 0b_0110011, 0b_110, 0b_0000001),
+        OP_REMU => encode_r(i, 0b_0110011, 0b_111, 0b_0000001),
+        OP_MULW => encode_r(i, 0b_0111011, 0b_000, 0b_0000001),
+        OP_DIVW => encode_r(i, 0b_0111011, 0b_100, 0b_0000001),
+        OP_DIVUW => encode_r(i, 0b_0111011, 0b_101, 0b_0000001),
+        OP_REMW => encode_r(i, 0b_0111011, 0b_110, 0b_0000001),
+        OP_REMUW => encode_r(i, 0b_0111011, 0b_111, 0b_0000001),
+        // A
+        OP_LR_W => encode_amo(i, 0b_010, 0b_00010),
+        OP_SC_W => encode_amo(i, 0b_010, 0b_00011),
+        OP_AMOSWAP_W => encode_amo(i, 0b_010, 0b_00001),
+        OP_AMOADD_W => encode_amo(i, 0b_010, 0b_00000),
+        OP_AMOXOR_W => encode_amo(i, 0b_010, 0b_00100),
+        OP_AMOAND_W => encode_amo(i, 0b_010, 0b_01100),
+        OP_AMOOR_W => encode_amo(i, 0b_010, 0b_01000),
+        OP_AMOMIN_W => encode_amo(i, 0b_010, 0b_10000),
+        OP_AMOMAX_W => encode_amo(i, 0b_010, 0b_10100),
+        OP_AMOMINU_W => encode_amo(i, 0b_010, 0b_11000),
+        OP_AMOMAXU_W => encode_amo(i, 0b_010, 0b_11100),
+        OP_LR_D => encode_amo(i, 0b_011, 0b_00010),
+        OP_SC_D => encode_amo(i, 0b_011, 0b_00011),
+        OP_AMOSWAP_D => encode_amo(i, 0b_011, 0b_00001),
+        OP_AMOADD_D => encode_amo(i, 0b_011, 0b_00000),
+        OP_AMOXOR_D => encode_amo(i, 0b_011, 0b_00100),
+        OP_AMOAND_D => encode_amo(i, 0b_011, 0b_01100),
+        OP_AMOOR_D => encode_amo(i, 0b_011, 0b_01000),
+        OP_AMOMIN_D => encode_amo(i, 0b_011, 0b_10000),
+        OP_AMOMAX_D => encode_amo(i, 0b_011, 0b_10100),
+        OP_AMOMINU_D => encode_amo(i, 0b_011, 0b_11000),
+        OP_AMOMAXU_D => encode_amo(i, 0b_011, 0b_11100),
+        // B
+        OP_ADDUW => encode_r(i, 0b_0111011, 0b_000, 0b_0000100),
+        OP_ANDN => encode_r(i, 0b_0110011, 0b_111, 0b_0100000),
+        OP_ORN => encode_r(i, 0b_0110011, 0b_110, 0b_0100000),
+        OP_XNOR => encode_r(i, 0b_0110011, 0b_100, 0b_0100000),
+        OP_BCLR => encode_r(i, 0b_0110011, 0b_001, 0b_0100100),
+        OP_BEXT => encode_r(i, 0b_0110011, 0b_101, 0b_0100100),
+        OP_BINV => encode_r(i, 0b_0110011, 0b_001, 0b_0110100),
+        OP_BSET => encode_r(i, 0b_0110011, 0b_001, 0b_0010100),
+        OP_BCLRI => encode_shift(i, 0b_0010011, 0b_001, 0b_010010, 6),
+        OP_BEXTI => encode_shift(i, 0b_0010011, 0b_101, 0b_010010, 6),
+        OP_BINVI => encode_shift(i, 0b_0010011, 0b_001, 0b_011010, 6),
+        OP_BSETI => encode_shift(i, 0b_0010011, 0b_001, 0b_001010, 6),
+        OP_CLMUL => encode_r(i, 0b_0110011, 0b_001, 0b_0000101),
+        OP_CLMULH => encode_r(i, 0b_0110011, 0b_011, 0b_0000101),
+        OP_CLMULR => encode_r(i, 0b_0110011, 0b_010, 0b_0000101),
+        OP_MIN => encode_r(i, 0b_0110011, 0b_100, 0b_0000101),
+        OP_MINU => encode_r(i, 0b_0110011, 0b_101, 0b_0000101),
+        OP_MAX => encode_r(i, 0b_0110011, 0b_110, 0b_0000101),
+        OP_MAXU => encode_r(i, 0b_0110011, 0b_111, 0b_0000101),
+        OP_ROL => encode_r(i, 0b_0110011, 0b_001, 0b_0110000),
+        OP_ROLW => encode_r(i, 0b_0111011, 0b_001, 0b_0110000),
+        OP_ROR => encode_r(i, 0b_0110011, 0b_101, 0b_0110000),
+        OP_RORW => encode_r(i, 0b_0111011, 0b_101, 0b_0110000),
+        OP_RORI => encode_shift(i, 0b_0010011, 0b_101, 0b_011000, 6),
+        OP_RORIW => encode_shift(i, 0b_0011011, 0b_101, 0b_0110000, 5),
+        OP_SH1ADD => encode_r(i, 0b_0110011, 0b_010, 0b_0010000),
+        OP_SH2ADD => encode_r(i, 0b_0110011, 0b_100, 0b_0010000),
+        OP_SH3ADD => encode_r(i, 0b_0110011, 0b_110, 0b_0010000),
+        OP_SH1ADDUW => encode_r(i, 0b_0111011, 0b_010, 0b_0010000),
+        OP_SH2ADDUW => encode_r(i, 0b_0111011, 0b_100, 0b_0010000),
+        OP_SH3ADDUW => encode_r(i, 0b_0111011, 0b_110, 0b_0010000),
+        OP_SLLIUW => encode_shift(i, 0b_0011011, 0b_001, 0b_000010, 6),
+        // Unary instructions keep the rs2 field of the raw encoding, which
+        // selects the operation.
+        OP_CLZ | OP_CTZ | OP_CPOP | OP_SEXTB | OP_SEXTH => {
+            encode_r(i, 0b_0010011, 0b_001, 0b_0110000)
+        }
+        OP_CLZW | OP_CTZW | OP_CPOPW => encode_r(i, 0b_0011011, 0b_001, 0b_0110000),
+        OP_ORCB => encode_r(i, 0b_0010011, 0b_101, 0b_0010100),
+        OP_REV8 => encode_r(i, 0b_0010011, 0b_101, 0b_0110101),
+        OP_ZEXTH => encode_r(i, 0b_0111011, 0b_100, 0b_0000100),
+        // F
+        OP_FLW => encode_i(i, 0b_0000111, 0b_010),
+        OP_FSW => encode_s(i, 0b_0100111, 0b_010),
+        OP_FADD_S => encode_fp(i, 0b_00000, 0),
+        OP_FSUB_S => encode_fp(i, 0b_00001, 0),
+        OP_FMUL_S => encode_fp(i, 0b_00010, 0),
+        OP_FDIV_S => encode_fp(i, 0b_00011, 0),
+        OP_FSQRT_S => encode_fp(i, 0b_01011, 0),
+        OP_FSGNJ_S => encode_r(i, 0b_1010011, 0b_000, 0b_0010000),
+        OP_FSGNJN_S => encode_r(i, 0b_1010011, 0b_001, 0b_0010000),
+        OP_FSGNJX_S => encode_r(i, 0b_1010011, 0b_010, 0b_0010000),
+        OP_FMIN_S => encode_r(i, 0b_1010011, 0b_000, 0b_0010100),
+        OP_FMAX_S => encode_r(i, 0b_1010011, 0b_001, 0b_0010100),
+        OP_FEQ_S => encode_r(i, 0b_1010011, 0b_010, 0b_1010000),
+        OP_FLT_S => encode_r(i, 0b_1010011, 0b_001, 0b_1010000),
+        OP_FLE_S => encode_r(i, 0b_1010011, 0b_000, 0b_1010000),
+        OP_FMV_X_W => encode_r(i, 0b_1010011, 0b_000, 0b_1110000),
+        OP_FCLASS_S => encode_r(i, 0b_1010011, 0b_001, 0b_1110000),
+        OP_FMV_W_X => encode_r(i, 0b_1010011, 0b_000, 0b_1111000),
+        OP_FCVT_W_S | OP_FCVT_WU_S | OP_FCVT_L_S | OP_FCVT_LU_S => encode_fp(i, 0b_11000, 0),
+        OP_FCVT_S_W | OP_FCVT_S_WU | OP_FCVT_S_L | OP_FCVT_S_LU => encode_fp(i, 0b_11010, 0),
+        OP_FMADD_S => encode_fma(i, 0b_1000011, 0),
+        OP_FMSUB_S => encode_fma(i, 0b_1000111, 0),
+        OP_FNMSUB_S => encode_fma(i, 0b_1001011, 0),
+        OP_FNMADD_S => encode_fma(i, 0b_1001111, 0),
+        // D
+        OP_FLD => encode_i(i, 0b_0000111, 0b_011),
+        OP_FSD => encode_s(i, 0b_0100111, 0b_011),
+        OP_FADD_D => encode_fp(i, 0b_00000, 1),
+        OP_FSUB_D => encode_fp(i, 0b_00001, 1),
+        OP_FMUL_D => encode_fp(i, 0b_00010, 1),
+        OP_FDIV_D => encode_fp(i, 0b_00011, 1),
+        OP_FSQRT_D => encode_fp(i, 0b_01011, 1),
+        OP_FSGNJ_D => encode_r(i, 0b_1010011, 0b_000, 0b_0010001),
+        OP_FSGNJN_D => encode_r(i, 0b_1010011, 0b_001, 0b_0010001),
+        OP_FSGNJX_D => encode_r(i, 0b_1010011, 0b_010, 0b_0010001),
+        OP_FMIN_D => encode_r(i, 0b_1010011, 0b_000, 0b_0010101),
+        OP_FMAX_D => encode_r(i, 0b_1010011, 0b_001, 0b_0010101),
+        OP_FEQ_D => encode_r(i, 0b_1010011, 0b_010, 0b_1010001),
+        OP_FLT_D => encode_r(i, 0b_1010011, 0b_001, 0b_1010001),
+        OP_FLE_D => encode_r(i, 0b_1010011, 0b_000, 0b_1010001),
+        OP_FMV_X_D => encode_r(i, 0b_1010011, 0b_000, 0b_1110001),
+        OP_FCLASS_D => encode_r(i, 0b_1010011, 0b_001, 0b_1110001),
+        OP_FMV_D_X => encode_r(i, 0b_1010011, 0b_000, 0b_1111001),
+        OP_FCVT_W_D | OP_FCVT_WU_D | OP_FCVT_L_D | OP_FCVT_LU_D => encode_fp(i, 0b_11000, 1),
+        OP_FCVT_D_W | OP_FCVT_D_WU | OP_FCVT_D_L | OP_FCVT_D_LU => encode_fp(i, 0b_11010, 1),
+        OP_FMADD_D => encode_fma(i, 0b_1000011, 1),
+        OP_FMSUB_D => encode_fma(i, 0b_1000111, 1),
+        OP_FNMSUB_D => encode_fma(i, 0b_1001011, 1),
+        OP_FNMADD_D => encode_fma(i, 0b_1001111, 1),
+        OP_FCVT_S_D => encode_fp(i, 0b_01000, 0),
+        OP_FCVT_D_S => encode_fp(i, 0b_01000, 1),
+        // V
+        OP_VSETVLI => encode_vsetvli(i),
+        OP_VSETIVLI => encode_vsetivli(i),
+        OP_VSETVL => encode_r(i, 0b_1010111, 0b_111, 0b_1000000),
+        OP_VLE8_V => encode_vmem(i, 0b_0000111, 0b_000),
+        OP_VLE16_V => encode_vmem(i, 0b_0000111, 0b_101),
+        OP_VLE32_V => encode_vmem(i, 0b_0000111, 0b_110),
+        OP_VLE64_V => encode_vmem(i, 0b_0000111, 0b_111),
+        OP_VSE8_V => encode_vmem(i, 0b_0100111, 0b_000),
+        OP_VSE16_V => encode_vmem(i, 0b_0100111, 0b_101),
+        OP_VSE32_V => encode_vmem(i, 0b_0100111, 0b_110),
+        OP_VSE64_V => encode_vmem(i, 0b_0100111, 0b_111),
+        OP_VADD_VV => encode_v(i, 0b_000000, 0b_000),
+        OP_VADD_VX => encode_v(i, 0b_000000, 0b_100),
+        OP_VADD_VI => encode_v(i, 0b_000000, 0b_011),
+        OP_VSUB_VV => encode_v(i, 0b_000010, 0b_000),
+        OP_VSUB_VX => encode_v(i, 0b_000010, 0b_100),
+        OP_VRSUB_VX => encode_v(i, 0b_000011, 0b_100),
+        OP_VRSUB_VI => encode_v(i, 0b_000011, 0b_011),
+        OP_VMINU_VV => encode_v(i, 0b_000100, 0b_000),
+        OP_VMINU_VX => encode_v(i, 0b_000100, 0b_100),
+        OP_VMIN_VV => encode_v(i, 0b_000101, 0b_000),
+        OP_VMIN_VX => encode_v(i, 0b_000101, 0b_100),
+        OP_VMAXU_VV => encode_v(i, 0b_000110, 0b_000),
+        OP_VMAXU_VX => encode_v(i, 0b_000110, 0b_100),
+        OP_VMAX_VV => encode_v(i, 0b_000111, 0b_000),
+        OP_VMAX_VX => encode_v(i, 0b_000111, 0b_100),
+        OP_VAND_VV => encode_v(i, 0b_001001, 0b_000),
+        OP_VAND_VX => encode_v(i, 0b_001001, 0b_100),
+        OP_VAND_VI => encode_v(i, 0b_001001, 0b_011),
+        OP_VOR_VV => encode_v(i, 0b_001010, 0b_000),
+        OP_VOR_VX => encode_v(i, 0b_001010, 0b_100),
+        OP_VOR_VI => encode_v(i, 0b_001010, 0b_011),
+        OP_VXOR_VV => encode_v(i, 0b_001011, 0b_000),
+        OP_VXOR_VX => encode_v(i, 0b_001011, 0b_100),
+        OP_VXOR_VI => encode_v(i, 0b_001011, 0b_011),
+        OP_VMSEQ_VV => encode_v(i, 0b_011000, 0b_000),
+        OP_VMSEQ_VX => encode_v(i, 0b_011000, 0b_100),
+        OP_VMSEQ_VI => encode_v(i, 0b_011000, 0b_011),
+        OP_VMSNE_VV => encode_v(i, 0b_011001, 0b_000),
+        OP_VMSNE_VX => encode_v(i, 0b_011001, 0b_100),
+        OP_VMSNE_VI => encode_v(i, 0b_011001, 0b_011),
+        OP_VMSLTU_VV => encode_v(i, 0b_011010, 0b_000),
+        OP_VMSLTU_VX => encode_v(i, 0b_011010, 0b_100),
+        OP_VMSLT_VV => encode_v(i, 0b_011011, 0b_000),
+        OP_VMSLT_VX => encode_v(i, 0b_011011, 0b_100),
+        OP_VMSLEU_VV => encode_v(i, 0b_011100, 0b_000),
+        OP_VMSLEU_VX => encode_v(i, 0b_011100, 0b_100),
+        OP_VMSLEU_VI => encode_v(i, 0b_011100, 0b_011),
+        OP_VMSLE_VV => encode_v(i, 0b_011101, 0b_000),
+        OP_VMSLE_VX => encode_v(i, 0b_011101, 0b_100),
+        OP_VMSLE_VI => encode_v(i, 0b_011101, 0b_011),
+        OP_VMSGTU_VX => encode_v(i, 0b_011110, 0b_100),
+        OP_VMSGTU_VI => encode_v(i, 0b_011110, 0b_011),
+        OP_VMSGT_VX => encode_v(i, 0b_011111, 0b_100),
+        OP_VMSGT_VI => encode_v(i, 0b_011111, 0b_011),
+        OP_VSLL_VV => encode_v(i, 0b_100101, 0b_000),
+        OP_VSLL_VX => encode_v(i, 0b_100101, 0b_100),
+        OP_VSLL_VI => encode_v(i, 0b_100101, 0b_011),
+        OP_VSRL_VV => encode_v(i, 0b_101000, 0b_000),
+        OP_VSRL_VX => encode_v(i, 0b_101000, 0b_100),
+        OP_VSRL_VI => encode_v(i, 0b_101000, 0b_011),
+        OP_VSRA_VV => encode_v(i, 0b_101001, 0b_000),
+        OP_VSRA_VX => encode_v(i, 0b_101001, 0b_100),
+        OP_VSRA_VI => encode_v(i, 0b_101001, 0b_011),
+        OP_VMERGE_VVM => encode_v(i, 0b_010111, 0b_000),
+        OP_VMERGE_VXM => encode_v(i, 0b_010111, 0b_100),
+        OP_VMERGE_VIM => encode_v(i, 0b_010111, 0b_011),
+        OP_VMV_V_V => encode_v(i, 0b_010111, 0b_000),
+        OP_VMV_V_X => encode_v(i, 0b_010111, 0b_100),
+        OP_VMV_V_I => encode_v(i, 0b_010111, 0b_011),
+        OP_VREDSUM_VS => encode_v(i, 0b_000000, 0b_010),
+        OP_VMV_X_S => encode_v(i, 0b_010000, 0b_010),
+        OP_VMV_S_X => encode_v(i, 0b_010000, 0b_110),
+        OP_VDIVU_VV => encode_v(i, 0b_100000, 0b_010),
+        OP_VDIVU_VX => encode_v(i, 0b_100000, 0b_110),
+        OP_VDIV_VV => encode_v(i, 0b_100001, 0b_010),
+        OP_VDIV_VX => encode_v(i, 0b_100001, 0b_110),
+        OP_VREMU_VV => encode_v(i, 0b_100010, 0b_010),
+        OP_VREMU_VX => encode_v(i, 0b_100010, 0b_110),
+        OP_VREM_VV => encode_v(i, 0b_100011, 0b_010),
+        OP_VREM_VX => encode_v(i, 0b_100011, 0b_110),
+        OP_VMULHU_VV => encode_v(i, 0b_100100, 0b_010),
+        OP_VMULHU_VX => encode_v(i, 0b_100100, 0b_110),
+        OP_VMUL_VV => encode_v(i, 0b_100101, 0b_010),
+        OP_VMUL_VX => encode_v(i, 0b_100101, 0b_110),
+        OP_VMULHSU_VV => encode_v(i, 0b_100110, 0b_010),
+        OP_VMULHSU_VX => encode_v(i, 0b_100110, 0b_110),
+        OP_VMULH_VV => encode_v(i, 0b_100111, 0b_010),
+        OP_VMULH_VX => encode_v(i, 0b_100111, 0b_110),
+        OP_VMADD_VV => encode_v(i, 0b_101001, 0b_010),
+        OP_VMADD_VX => encode_v(i, 0b_101001, 0b_110),
+        OP_VNMSUB_VV => encode_v(i, 0b_101011, 0b_010),
+        OP_VNMSUB_VX => encode_v(i, 0b_101011, 0b_110),
+        OP_VMACC_VV => encode_v(i, 0b_101101, 0b_010),
+        OP_VMACC_VX => encode_v(i, 0b_101101, 0b_110),
+        OP_VNMSAC_VV => encode_v(i, 0b_101111, 0b_010),
+        OP_VNMSAC_VX => encode_v(i, 0b_101111, 0b_110),
+        _ => None,
+    }
+}
+
+fn register(index: u8) -> Option<u32> {
+    if index < 32 {
+        Some(u32::from(index))
+    } else {
+        None
+    }
+}
+
+fn fits_signed(imm: i32, bits: u32) -> bool {
+    let bound = 1i32 << (bits - 1);
+    (-bound..bound).contains(&imm)
+}
+
+fn encode_r(i: Instruction, opcode: u32, funct3: u32, funct7: u32) -> Option<u32> {
+    let (rd, rs1, rs2) = unpack_r(i);
+    Some(
+        funct7 << 25
+            | register(rs2)? << 20
+            | register(rs1)? << 15
+            | funct3 << 12
+            | register(rd)? << 7
+            | opcode,
+    )
+}
+
+// Floating point instructions with a rounding mode hold it in rs3. FSQRT and
+// FCVT keep the rs2 field of the raw encoding, which selects the operation.
+fn encode_fp(i: Instruction, funct5: u32, fmt: u32) -> Option<u32> {
+    let (rd, rs1, rs2, rm) = unpack_r4(i);
+    if rm > 0b111 {
+        return None;
+    }
+    Some(
+        funct5 << 27
+            | fmt << 25
+            | register(rs2)? << 20
+            | register(rs1)? << 15
+            | u32::from(rm) << 12
+            | register(rd)? << 7
+            | 0b_1010011,
+    )
+}
+
+// Fused multiply-add instructions hold their rounding mode in rs4.
+fn encode_fma(i: Instruction, opcode: u32, fmt: u32) -> Option<u32> {
+    let (rd, rs1, rs2, rs3, rm) = unpack_r5(i);
+    if rm > 0b111 {
+        return None;
+    }
+    Some(
+        register(rs3)? << 27
+            | fmt << 25
+            | register(rs2)? << 20
+            | register(rs1)? << 15
+            | u32::from(rm) << 12
+            | register(rd)? << 7
+            | opcode,
+    )
+}
+
+// Vector arithmetic instructions are packed as R4type(vd, vs2, src, vm), src
+// being vs1, rs1 or the raw 5-bit immediate depending on funct3.
+fn encode_v(i: Instruction, funct6: u32, funct3: u32) -> Option<u32> {
+    let (vd, vs2, src, vm) = unpack_r4(i);
+    if vm > 1 {
+        return None;
+    }
+    Some(
+        funct6 << 26
+            | u32::from(vm) << 25
+            | register(vs2)? << 20
+            | register(src)? << 15
+            | funct3 << 12
+            | register(vd)? << 7
+            | 0b_1010111,
+    )
+}
+
+// Unit-stride vector loads and stores are packed as R4type(vd, rs1, 0, vm).
+fn encode_vmem(i: Instruction, opcode: u32, width: u32) -> Option<u32> {
+    let (vd, rs1, _, vm) = unpack_r4(i);
+    if vm > 1 {
+        return None;
+    }
+    Some(u32::from(vm) << 25 | register(rs1)? << 15 | width << 12 | register(vd)? << 7 | opcode)
+}
+
+// VSETVLI holds zimm[10:0] as an I-type immediate.
+fn encode_vsetvli(i: Instruction) -> Option<u32> {
+    let (rd, rs1, zimm) = unpack_i(i);
+    if !(0..0x800).contains(&zimm) {
+        return None;
+    }
+    Some(
+        (zimm as u32) << 20 | register(rs1)? << 15 | 0b_111 << 12 | register(rd)? << 7 | 0b_1010111,
+    )
+}
+
+// VSETIVLI holds the AVL immediate in rs1, and zimm[9:0] as an I-type
+// immediate.
+fn encode_vsetivli(i: Instruction) -> Option<u32> {
+    let (rd, uimm, zimm) = unpack_i(i);
+    if !(0..0x400).contains(&zimm) {
+        return None;
+    }
+    Some(
+        0b_11 << 30
+            | (zimm as u32) << 20
+            | register(uimm)? << 15
+            | 0b_111 << 12
+            | register(rd)? << 7
+            | 0b_1010111,
+    )
+}
+
+fn encode_amo(i: Instruction, funct3: u32, funct5: u32) -> Option<u32> {
+    encode_r(i, 0b_0101111, funct3, funct5 << 2)
+}
+
+// CSR instructions are I-type, with the unsigned CSR number as immediate.
+// The immediate variants hold a 5-bit unsigned immediate in rs1.
+fn encode_csr(i: Instruction, funct3: u32) -> Option<u32> {
+    let (rd, rs1, csr) = unpack_i(i);
+    if !(0..=0xfff).contains(&csr) {
+        return None;
+    }
+    Some((csr as u32) << 20 | register(rs1)? << 15 | funct3 << 12 | register(rd)? << 7 | 0b_1110011)
+}
+
+fn encode_i(i: Instruction, opcode: u32, funct3: u32) -> Option<u32> {
+    let (rd, rs1, imm) = unpack_i(i);
+    if !fits_signed(imm, 12) {
+        return None;
+    }
+    Some(
+        (imm as u32 & 0xfff) << 20
+            | register(rs1)? << 15
+            | funct3 << 12
+            | register(rd)? << 7
+            | opcode,
+    )
+}
+
+// Shift instructions store the shift amount as an I-type immediate, `top`
+// holds the bits above the shift amount.
+fn encode_shift(
+    i: Instruction,
+    opcode: u32,
+    funct3: u32,
+    top: u32,
+    shamt_bits: u32,
+) -> Option<u32> {
+    let (rd, rs1, shamt) = unpack_i(i);
+    if !(0..1 << shamt_bits).contains(&shamt) {
+        return None;
+    }
+    Some(
+        top << (20 + shamt_bits)
+            | (shamt as u32) << 20
+            | register(rs1)? << 15
+            | funct3 << 12
+            | register(rd)? << 7
+            | opcode,
+    )
+}
+
+fn encode_s(i: Instruction, opcode: u32, funct3: u32) -> Option<u32> {
+    let (rs1, rs2, imm) = unpack_s(i);
+    if !fits_signed(imm, 12) {
+        return None;
+    }
+    let imm = imm as u32;
+    Some(
+        ((imm >> 5) & 0x7f) << 25
+            | register(rs2)? << 20
+            | register(rs1)? << 15
+            | funct3 << 12
+            | (imm & 0x1f) << 7
+            | opcode,
+    )
+}
+
+fn encode_b(i: Instruction, funct3: u32) -> Option<u32> {
+    let (rs1, rs2, imm) = unpack_b(i);
+    if !fits_signed(imm, 13) || imm & 1 != 0 {
+        return None;
+    }
+    let imm = imm as u32;
+    Some(
+        ((imm >> 12) & 1) << 31
+            | ((imm >> 5) & 0x3f) << 25
+            | register(rs2)? << 20
+            | register(rs1)? << 15
+            | funct3 << 12
+            | ((imm >> 1) & 0xf) << 8
+            | ((imm >> 11) & 1) << 7
+            | 0b_1100011,
+    )
+}
+
+fn encode_u(i: Instruction, opcode: u32) -> Option<u32> {
+    let (rd, imm) = unpack_u(i);
+    if imm & 0xfff != 0 {
+        return None;
+    }
+    Some(imm as u32 | register(rd)? << 7 | opcode)
+}
+
+fn encode_j(i: Instruction) -> Option<u32> {
+    let (rd, imm) = unpack_j(i);
+    if !fits_signed(imm, 21) || imm & 1 != 0 {
+        return None;
+    }
+    let imm = imm as u32;
+    Some(
+        ((imm >> 20) & 1) << 31
+            | ((imm >> 1) & 0x3ff) << 21
+            | ((imm >> 11) & 1) << 20
+            | ((imm >> 12) & 0xff) << 12
+            | register(rd)? << 7
+            | 0b_1101111,
+    )
+}
@@ -32,6 +32,9 @@
 // path, at this time the value of op2 is ignored.
 // When the op value is 0x00-0x0f, op and op2 are combined to express a
 // second-level instruction under slow path.
+//
+// The layout is versioned by encoding::INSTRUCTION_SCHEMA_VERSION, see the
+// encoding module for helpers packing and unpacking each type.
 pub type Instruction = u64;
 
 pub type InstructionOpcode = u16;
@@ -197,6 +200,179 @@ pub const OP_ADD3C: InstructionOpcode = 0xa9;
 pub const OP_CUSTOM_LOAD_UIMM: InstructionOpcode = 0xaa;
 pub const OP_CUSTOM_LOAD_IMM: InstructionOpcode = 0xab;
 pub const OP_CUSTOM_TRACE_END: InstructionOpcode = 0xac;
+// Slow path instructions, see the description of +op+ and +op2+ above. The
+// ASM backend hands them back to the Rust interpreter. op 0x01 groups the
+// privileged instructions enabled by ISA_PRIV.
+pub const OP_CSRRW: InstructionOpcode = 0x0101;
+pub const OP_CSRRS: InstructionOpcode = 0x0201;
+pub const OP_CSRRC: InstructionOpcode = 0x0301;
+pub const OP_CSRRWI: InstructionOpcode = 0x0401;
+pub const OP_CSRRSI: InstructionOpcode = 0x0501;
+pub const OP_CSRRCI: InstructionOpcode = 0x0601;
+pub const OP_MRET: InstructionOpcode = 0x0701;
+// op 0x02 groups the single precision instructions enabled by ISA_F, op 0x03
+// the double precision ones enabled by ISA_D. Instructions of both groups
+// with the same op2 only differ in precision.
+pub const OP_FLW: InstructionOpcode = 0x0102;
+pub const OP_FSW: InstructionOpcode = 0x0202;
+pub const OP_FADD_S: InstructionOpcode = 0x0302;
+pub const OP_FSUB_S: InstructionOpcode = 0x0402;
+pub const OP_FMUL_S: InstructionOpcode = 0x0502;
+pub const OP_FDIV_S: InstructionOpcode = 0x0602;
+pub const OP_FSQRT_S: InstructionOpcode = 0x0702;
+pub const OP_FSGNJ_S: InstructionOpcode = 0x0802;
+pub const OP_FSGNJN_S: InstructionOpcode = 0x0902;
+pub const OP_FSGNJX_S: InstructionOpcode = 0x0a02;
+pub const OP_FMIN_S: InstructionOpcode = 0x0b02;
+pub const OP_FMAX_S: InstructionOpcode = 0x0c02;
+pub const OP_FCVT_W_S: InstructionOpcode = 0x0d02;
+pub const OP_FCVT_WU_S: InstructionOpcode = 0x0e02;
+pub const OP_FCVT_L_S: InstructionOpcode = 0x0f02;
+pub const OP_FCVT_LU_S: InstructionOpcode = 0x1002;
+pub const OP_FMV_X_W: InstructionOpcode = 0x1102;
+pub const OP_FEQ_S: InstructionOpcode = 0x1202;
+pub const OP_FLT_S: InstructionOpcode = 0x1302;
+pub const OP_FLE_S: InstructionOpcode = 0x1402;
+pub const OP_FCLASS_S: InstructionOpcode = 0x1502;
+pub const OP_FCVT_S_W: InstructionOpcode = 0x1602;
+pub const OP_FCVT_S_WU: InstructionOpcode = 0x1702;
+pub const OP_FCVT_S_L: InstructionOpcode = 0x1802;
+pub const OP_FCVT_S_LU: InstructionOpcode = 0x1902;
+pub const OP_FMV_W_X: InstructionOpcode = 0x1a02;
+pub const OP_FMADD_S: InstructionOpcode = 0x1b02;
+pub const OP_FMSUB_S: InstructionOpcode = 0x1c02;
+pub const OP_FNMSUB_S: InstructionOpcode = 0x1d02;
+pub const OP_FNMADD_S: InstructionOpcode = 0x1e02;
+pub const OP_FLD: InstructionOpcode = 0x0103;
+pub const OP_FSD: InstructionOpcode = 0x0203;
+pub const OP_FADD_D: InstructionOpcode = 0x0303;
+pub const OP_FSUB_D: InstructionOpcode = 0x0403;
+pub const OP_FMUL_D: InstructionOpcode = 0x0503;
+pub const OP_FDIV_D: InstructionOpcode = 0x0603;
+pub const OP_FSQRT_D: InstructionOpcode = 0x0703;
+pub const OP_FSGNJ_D: InstructionOpcode = 0x0803;
+pub const OP_FSGNJN_D: InstructionOpcode = 0x0903;
+pub const OP_FSGNJX_D: InstructionOpcode = 0x0a03;
+pub const OP_FMIN_D: InstructionOpcode = 0x0b03;
+pub const OP_FMAX_D: InstructionOpcode = 0x0c03;
+pub const OP_FCVT_W_D: InstructionOpcode = 0x0d03;
+pub const OP_FCVT_WU_D: InstructionOpcode = 0x0e03;
+pub const OP_FCVT_L_D: InstructionOpcode = 0x0f03;
+pub const OP_FCVT_LU_D: InstructionOpcode = 0x1003;
+pub const OP_FMV_X_D: InstructionOpcode = 0x1103;
+pub const OP_FEQ_D: InstructionOpcode = 0x1203;
+pub const OP_FLT_D: InstructionOpcode = 0x1303;
+pub const OP_FLE_D: InstructionOpcode = 0x1403;
+pub const OP_FCLASS_D: InstructionOpcode = 0x1503;
+pub const OP_FCVT_D_W: InstructionOpcode = 0x1603;
+pub const OP_FCVT_D_WU: InstructionOpcode = 0x1703;
+pub const OP_FCVT_D_L: InstructionOpcode = 0x1803;
+pub const OP_FCVT_D_LU: InstructionOpcode = 0x1903;
+pub const OP_FMV_D_X: InstructionOpcode = 0x1a03;
+pub const OP_FMADD_D: InstructionOpcode = 0x1b03;
+pub const OP_FMSUB_D: InstructionOpcode = 0x1c03;
+pub const OP_FNMSUB_D: InstructionOpcode = 0x1d03;
+pub const OP_FNMADD_D: InstructionOpcode = 0x1e03;
+pub const OP_FCVT_S_D: InstructionOpcode = 0x1f03;
+pub const OP_FCVT_D_S: InstructionOpcode = 0x2003;
+// op 0x04 groups the vector instructions enabled by ISA_V.
+pub const OP_VSETVLI: InstructionOpcode = 0x0104;
+pub const OP_VSETIVLI: InstructionOpcode = 0x0204;
+pub const OP_VSETVL: InstructionOpcode = 0x0304;
+pub const OP_VLE8_V: InstructionOpcode = 0x0404;
+pub const OP_VLE16_V: InstructionOpcode = 0x0504;
+pub const OP_VLE32_V: InstructionOpcode = 0x0604;
+pub const OP_VLE64_V: InstructionOpcode = 0x0704;
+pub const OP_VSE8_V: InstructionOpcode = 0x0804;
+pub const OP_VSE16_V: InstructionOpcode = 0x0904;
+pub const OP_VSE32_V: InstructionOpcode = 0x0a04;
+pub const OP_VSE64_V: InstructionOpcode = 0x0b04;
+pub const OP_VADD_VV: InstructionOpcode = 0x0c04;
+pub const OP_VADD_VX: InstructionOpcode = 0x0d04;
+pub const OP_VADD_VI: InstructionOpcode = 0x0e04;
+pub const OP_VSUB_VV: InstructionOpcode = 0x0f04;
+pub const OP_VSUB_VX: InstructionOpcode = 0x1004;
+pub const OP_VRSUB_VX: InstructionOpcode = 0x1104;
+pub const OP_VRSUB_VI: InstructionOpcode = 0x1204;
+pub const OP_VMINU_VV: InstructionOpcode = 0x1304;
+pub const OP_VMINU_VX: InstructionOpcode = 0x1404;
+pub const OP_VMIN_VV: InstructionOpcode = 0x1504;
+pub const OP_VMIN_VX: InstructionOpcode = 0x1604;
+pub const OP_VMAXU_VV: InstructionOpcode = 0x1704;
+pub const OP_VMAXU_VX: InstructionOpcode = 0x1804;
+pub const OP_VMAX_VV: InstructionOpcode = 0x1904;
+pub const OP_VMAX_VX: InstructionOpcode = 0x1a04;
+pub const OP_VAND_VV: InstructionOpcode = 0x1b04;
+pub const OP_VAND_VX: InstructionOpcode = 0x1c04;
+pub const OP_VAND_VI: InstructionOpcode = 0x1d04;
+pub const OP_VOR_VV: InstructionOpcode = 0x1e04;
+pub const OP_VOR_VX: InstructionOpcode = 0x1f04;
+pub const OP_VOR_VI: InstructionOpcode = 0x2004;
+pub const OP_VXOR_VV: InstructionOpcode = 0x2104;
+pub const OP_VXOR_VX: InstructionOpcode = 0x2204;
+pub const OP_VXOR_VI: InstructionOpcode = 0x2304;
+pub const OP_VMSEQ_VV: InstructionOpcode = 0x2404;
+pub const OP_VMSEQ_VX: InstructionOpcode = 0x2504;
+pub const OP_VMSEQ_VI: InstructionOpcode = 0x2604;
+pub const OP_VMSNE_VV: InstructionOpcode = 0x2704;
+pub const OP_VMSNE_VX: InstructionOpcode = 0x2804;
+pub const OP_VMSNE_VI: InstructionOpcode = 0x2904;
+pub const OP_VMSLTU_VV: InstructionOpcode = 0x2a04;
+pub const OP_VMSLTU_VX: InstructionOpcode = 0x2b04;
+pub const OP_VMSLT_VV: InstructionOpcode = 0x2c04;
+pub const OP_VMSLT_VX: InstructionOpcode = 0x2d04;
+pub const OP_VMSLEU_VV: InstructionOpcode = 0x2e04;
+pub const OP_VMSLEU_VX: InstructionOpcode = 0x2f04;
+pub const OP_VMSLEU_VI: InstructionOpcode = 0x3004;
+pub const OP_VMSLE_VV: InstructionOpcode = 0x3104;
+pub const OP_VMSLE_VX: InstructionOpcode = 0x3204;
+pub const OP_VMSLE_VI: InstructionOpcode = 0x3304;
+pub const OP_VMSGTU_VX: InstructionOpcode = 0x3404;
+pub const OP_VMSGTU_VI: InstructionOpcode = 0x3504;
+pub const OP_VMSGT_VX: InstructionOpcode = 0x3604;
+pub const OP_VMSGT_VI: InstructionOpcode = 0x3704;
+pub const OP_VSLL_VV: InstructionOpcode = 0x3804;
+pub const OP_VSLL_VX: InstructionOpcode = 0x3904;
+pub const OP_VSLL_VI: InstructionOpcode = 0x3a04;
+pub const OP_VSRL_VV: InstructionOpcode = 0x3b04;
+pub const OP_VSRL_VX: InstructionOpcode = 0x3c04;
+pub const OP_VSRL_VI: InstructionOpcode = 0x3d04;
+pub const OP_VSRA_VV: InstructionOpcode = 0x3e04;
+pub const OP_VSRA_VX: InstructionOpcode = 0x3f04;
+pub const OP_VSRA_VI: InstructionOpcode = 0x4004;
+pub const OP_VMERGE_VVM: InstructionOpcode = 0x4104;
+pub const OP_VMERGE_VXM: InstructionOpcode = 0x4204;
+pub const OP_VMERGE_VIM: InstructionOpcode = 0x4304;
+pub const OP_VMV_V_V: InstructionOpcode = 0x4404;
+pub const OP_VMV_V_X: InstructionOpcode = 0x4504;
+pub const OP_VMV_V_I: InstructionOpcode = 0x4604;
+pub const OP_VREDSUM_VS: InstructionOpcode = 0x4704;
+pub const OP_VMV_X_S: InstructionOpcode = 0x4804;
+pub const OP_VMV_S_X: InstructionOpcode = 0x4904;
+pub const OP_VDIVU_VV: InstructionOpcode = 0x4a04;
+pub const OP_VDIVU_VX: InstructionOpcode = 0x4b04;
+pub const OP_VDIV_VV: InstructionOpcode = 0x4c04;
+pub const OP_VDIV_VX: InstructionOpcode = 0x4d04;
+pub const OP_VREMU_VV: InstructionOpcode = 0x4e04;
+pub const OP_VREMU_VX: InstructionOpcode = 0x4f04;
+pub const OP_VREM_VV: InstructionOpcode = 0x5004;
+pub const OP_VREM_VX: InstructionOpcode = 0x5104;
+pub const OP_VMULHU_VV: InstructionOpcode = 0x5204;
+pub const OP_VMULHU_VX: InstructionOpcode = 0x5304;
+pub const OP_VMUL_VV: InstructionOpcode = 0x5404;
+pub const OP_VMUL_VX: InstructionOpcode = 0x5504;
+pub const OP_VMULHSU_VV: InstructionOpcode = 0x5604;
+pub const OP_VMULHSU_VX: InstructionOpcode = 0x5704;
+pub const OP_VMULH_VV: InstructionOpcode = 0x5804;
+pub const OP_VMULH_VX: InstructionOpcode = 0x5904;
+pub const OP_VMADD_VV: InstructionOpcode = 0x5a04;
+pub const OP_VMADD_VX: InstructionOpcode = 0x5b04;
+pub const OP_VNMSUB_VV: InstructionOpcode = 0x5c04;
+pub const OP_VNMSUB_VX: InstructionOpcode = 0x5d04;
+pub const OP_VMACC_VV: InstructionOpcode = 0x5e04;
+pub const OP_VMACC_VX: InstructionOpcode = 0x5f04;
+pub const OP_VNMSAC_VV: InstructionOpcode = 0x6004;
+pub const OP_VNMSAC_VX: InstructionOpcode = 0x6104;
 
 pub const MINIMAL_OPCODE: InstructionOpcode = OP_UNLOADED;
 pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CUSTOM_TRACE_END;
@@ -362,5 +538,183 @@ pub const INSTRUCTION_OPCODE_NAMES: [&str; (MAXIMUM_OPCODE - MINIMAL_OPCODE + 1)
 ];
 
 pub fn instruction_opcode_name(i: InstructionOpcode) -> &'static str {
-    INSTRUCTION_OPCODE_NAMES[(i - MINIMAL_OPCODE) as usize]
+    try_instruction_opcode_name(i).expect("unknown instruction opcode")
+}
+
+// Returns the name of an opcode, None if the value isn't a valid opcode.
+pub fn try_instruction_opcode_name(i: InstructionOpcode) -> Option<&'static str> {
+    let name = match i {
+        OP_CSRRW => "CSRRW",
+        OP_CSRRS => "CSRRS",
+        OP_CSRRC => "CSRRC",
+        OP_CSRRWI => "CSRRWI",
+        OP_CSRRSI => "CSRRSI",
+        OP_CSRRCI => "CSRRCI",
+        OP_MRET => "MRET",
+        OP_FLW => "FLW",
+        OP_FSW => "FSW",
+        OP_FADD_S => "FADD_S",
+        OP_FSUB_S => "FSUB_S",
+        OP_FMUL_S => "FMUL_S",
+        OP_FDIV_S => "FDIV_S",
+        OP_FSQRT_S => "FSQRT_S",
+        OP_FSGNJ_S => "FSGNJ_S",
+        OP_FSGNJN_S => "FSGNJN_S",
+        OP_FSGNJX_S => "FSGNJX_S",
+        OP_FMIN_S => "FMIN_S",
+        OP_FMAX_S => "FMAX_S",
+        OP_FCVT_W_S => "FCVT_W_S",
+        OP_FCVT_WU_S => "FCVT_WU_S",
+        OP_FCVT_L_S => "FCVT_L_S",
+        OP_FCVT_LU_S => "FCVT_LU_S",
+        OP_FMV_X_W => "FMV_X_W",
+        OP_FEQ_S => "FEQ_S",
+        OP_FLT_S => "FLT_S",
+        OP_FLE_S => "FLE_S",
+        OP_FCLASS_S => "FCLASS_S",
+        OP_FCVT_S_W => "FCVT_S_W",
+        OP_FCVT_S_WU => "FCVT_S_WU",
+        OP_FCVT_S_L => "FCVT_S_L",
+        OP_FCVT_S_LU => "FCVT_S_LU",
+        OP_FMV_W_X => "FMV_W_X",
+        OP_FMADD_S => "FMADD_S",
+        OP_FMSUB_S => "FMSUB_S",
+        OP_FNMSUB_S => "FNMSUB_S",
+        OP_FNMADD_S => "FNMADD_S",
+        OP_FLD => "FLD",
+        OP_FSD => "FSD",
+        OP_FADD_D => "FADD_D",
+        OP_FSUB_D => "FSUB_D",
+        OP_FMUL_D => "FMUL_D",
+        OP_FDIV_D => "FDIV_D",
+        OP_FSQRT_D => "FSQRT_D",
+        OP_FSGNJ_D => "FSGNJ_D",
+        OP_FSGNJN_D => "FSGNJN_D",
+        OP_FSGNJX_D => "FSGNJX_D",
+        OP_FMIN_D => "FMIN_D",
+        OP_FMAX_D => "FMAX_D",
+        OP_FCVT_W_D => "FCVT_W_D",
+        OP_FCVT_WU_D => "FCVT_WU_D",
+        OP_FCVT_L_D => "FCVT_L_D",
+        OP_FCVT_LU_D => "FCVT_LU_D",
+        OP_FMV_X_D => "FMV_X_D",
+        OP_FEQ_D => "FEQ_D",
+        OP_FLT_D => "FLT_D",
+        OP_FLE_D => "FLE_D",
+        OP_FCLASS_D => "FCLASS_D",
+        OP_FCVT_D_W => "FCVT_D_W",
+        OP_FCVT_D_WU => "FCVT_D_WU",
+        OP_FCVT_D_L => "FCVT_D_L",
+        OP_FCVT_D_LU => "FCVT_D_LU",
+        OP_FMV_D_X => "FMV_D_X",
+        OP_FMADD_D => "FMADD_D",
+        OP_FMSUB_D => "FMSUB_D",
+        OP_FNMSUB_D => "FNMSUB_D",
+        OP_FNMADD_D => "FNMADD_D",
+        OP_FCVT_S_D => "FCVT_S_D",
+        OP_FCVT_D_S => "FCVT_D_S",
+        OP_VSETVLI => "VSETVLI",
+        OP_VSETIVLI => "VSETIVLI",
+        OP_VSETVL => "VSETVL",
+        OP_VLE8_V => "VLE8_V",
+        OP_VLE16_V => "VLE16_V",
+        OP_VLE32_V => "VLE32_V",
+        OP_VLE64_V => "VLE64_V",
+        OP_VSE8_V => "VSE8_V",
+        OP_VSE16_V => "VSE16_V",
+        OP_VSE32_V => "VSE32_V",
+        OP_VSE64_V => "VSE64_V",
+        OP_VADD_VV => "VADD_VV",
+        OP_VADD_VX => "VADD_VX",
+        OP_VADD_VI => "VADD_VI",
+        OP_VSUB_VV => "VSUB_VV",
+        OP_VSUB_VX => "VSUB_VX",
+        OP_VRSUB_VX => "VRSUB_VX",
+        OP_VRSUB_VI => "VRSUB_VI",
+        OP_VMINU_VV => "VMINU_VV",
+        OP_VMINU_VX => "VMINU_VX",
+        OP_VMIN_VV => "VMIN_VV",
+        OP_VMIN_VX => "VMIN_VX",
+        OP_VMAXU_VV => "VMAXU_VV",
+        OP_VMAXU_VX => "VMAXU_VX",
+        OP_VMAX_VV => "VMAX_VV",
+        OP_VMAX_VX => "VMAX_VX",
+        OP_VAND_VV => "VAND_VV",
+        OP_VAND_VX => "VAND_VX",
+        OP_VAND_VI => "VAND_VI",
+        OP_VOR_VV => "VOR_VV",
+        OP_VOR_VX => "VOR_VX",
+        OP_VOR_VI => "VOR_VI",
+        OP_VXOR_VV => "VXOR_VV",
+        OP_VXOR_VX => "VXOR_VX",
+        OP_VXOR_VI => "VXOR_VI",
+        OP_VMSEQ_VV => "VMSEQ_VV",
+        OP_VMSEQ_VX => "VMSEQ_VX",
+        OP_VMSEQ_VI => "VMSEQ_VI",
+        OP_VMSNE_VV => "VMSNE_VV",
+        OP_VMSNE_VX => "VMSNE_VX",
+        OP_VMSNE_VI => "VMSNE_VI",
+        OP_VMSLTU_VV => "VMSLTU_VV",
+        OP_VMSLTU_VX => "VMSLTU_VX",
+        OP_VMSLT_VV => "VMSLT_VV",
+        OP_VMSLT_VX => "VMSLT_VX",
+        OP_VMSLEU_VV => "VMSLEU_VV",
+        OP_VMSLEU_VX => "VMSLEU_VX",
+        OP_VMSLEU_VI => "VMSLEU_VI",
+        OP_VMSLE_VV => "VMSLE_VV",
+        OP_VMSLE_VX => "VMSLE_VX",
+        OP_VMSLE_VI => "VMSLE_VI",
+        OP_VMSGTU_VX => "VMSGTU_VX",
+        OP_VMSGTU_VI => "VMSGTU_VI",
+        OP_VMSGT_VX => "VMSGT_VX",
+        OP_VMSGT_VI => "VMSGT_VI",
+        OP_VSLL_VV => "VSLL_VV",
+        OP_VSLL_VX => "VSLL_VX",
+        OP_VSLL_VI => "VSLL_VI",
+        OP_VSRL_VV => "VSRL_VV",
+        OP_VSRL_VX => "VSRL_VX",
+        OP_VSRL_VI => "VSRL_VI",
+        OP_VSRA_VV => "VSRA_VV",
+        OP_VSRA_VX => "VSRA_VX",
+        OP_VSRA_VI => "VSRA_VI",
+        OP_VMERGE_VVM => "VMERGE_VVM",
+        OP_VMERGE_VXM => "VMERGE_VXM",
+        OP_VMERGE_VIM => "VMERGE_VIM",
+        OP_VMV_V_V => "VMV_V_V",
+        OP_VMV_V_X => "VMV_V_X",
+        OP_VMV_V_I => "VMV_V_I",
+        OP_VREDSUM_VS => "VREDSUM_VS",
+        OP_VMV_X_S => "VMV_X_S",
+        OP_VMV_S_X => "VMV_S_X",
+        OP_VDIVU_VV => "VDIVU_VV",
+        OP_VDIVU_VX => "VDIVU_VX",
+        OP_VDIV_VV => "VDIV_VV",
+        OP_VDIV_VX => "VDIV_VX",
+        OP_VREMU_VV => "VREMU_VV",
+        OP_VREMU_VX => "VREMU_VX",
+        OP_VREM_VV => "VREM_VV",
+        OP_VREM_VX => "VREM_VX",
+        OP_VMULHU_VV => "VMULHU_VV",
+        OP_VMULHU_VX => "VMULHU_VX",
+        OP_VMUL_VV => "VMUL_VV",
+        OP_VMUL_VX => "VMUL_VX",
+        OP_VMULHSU_VV => "VMULHSU_VV",
+        OP_VMULHSU_VX => "VMULHSU_VX",
+        OP_VMULH_VV => "VMULH_VV",
+        OP_VMULH_VX => "VMULH_VX",
+        OP_VMADD_VV => "VMADD_VV",
+        OP_VMADD_VX => "VMADD_VX",
+        OP_VNMSUB_VV => "VNMSUB_VV",
+        OP_VNMSUB_VX => "VNMSUB_VX",
+        OP_VMACC_VV => "VMACC_VV",
+        OP_VMACC_VX => "VMACC_VX",
+        OP_VNMSAC_VV => "VNMSAC_VV",
+        OP_VNMSAC_VX => "VNMSAC_VX",
+        _ => {
+            return INSTRUCTION_OPCODE_NAMES
+                .get(usize::from(i.wrapping_sub(MINIMAL_OPCODE)))
+                .copied()
+        }
+    };
+    Some(name)
 }
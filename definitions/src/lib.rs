@@ -1,4 +1,6 @@
 pub mod asm;
+pub mod disassembler;
+pub mod encoding;
 pub mod instructions;
 pub mod memory;
 pub mod registers;
@@ -17,7 +19,13 @@ pub const MEMORY_FRAMESIZE: usize = 1 << MEMORY_FRAME_SHIFTS;
 pub const MEMORY_FRAMES: usize = RISCV_MAX_MEMORY / MEMORY_FRAMESIZE;
 pub const MEMORY_FRAME_PAGE_SHIFTS: usize = MEMORY_FRAME_SHIFTS - RISCV_PAGE_SHIFTS;
 
-pub const ISA_IMC: u8 = 0b0000_0000;
-pub const ISA_B: u8 = 0b0000_0001;
-pub const ISA_MOP: u8 = 0b0000_0010;
-pub const ISA_A: u8 = 0b0000_0100;
+// ISA masks are 64 bits wide, leaving room for extensions after ISA_ZICSR.
+pub const ISA_IMC: u64 = 0b0000_0000;
+pub const ISA_B: u64 = 0b0000_0001;
+pub const ISA_MOP: u64 = 0b0000_0010;
+pub const ISA_A: u64 = 0b0000_0100;
+pub const ISA_PRIV: u64 = 0b0000_1000;
+pub const ISA_F: u64 = 0b0001_0000;
+pub const ISA_D: u64 = 0b0010_0000;
+pub const ISA_V: u64 = 0b0100_0000;
+pub const ISA_ZICSR: u64 = 0b1000_0000;
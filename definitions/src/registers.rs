@@ -45,3 +45,96 @@ pub const REGISTER_ABI_NAMES: [&str; 32] = [
     "s8", "s9", "s10", "s11",
     "t3", "t4", "t5", "t6",
 ];
+
+// Alternative names accepted when parsing, s0 is also known as fp.
+const REGISTER_ALIASES: [(&str, usize); 1] = [("fp", FP)];
+
+// Returns the ABI name of a register index.
+pub fn register_name(index: usize) -> Option<&'static str> {
+    REGISTER_ABI_NAMES.get(index).copied()
+}
+
+// Returns the register index for an ABI name(a0, sp, t3, fp), or an
+// architectural name(x0 - x31).
+pub fn register_index(name: &str) -> Option<usize> {
+    if let Some(index) = REGISTER_ABI_NAMES.iter().position(|n| *n == name) {
+        return Some(index);
+    }
+    if let Some((_, index)) = REGISTER_ALIASES.iter().find(|(n, _)| *n == name) {
+        return Some(*index);
+    }
+    let number = name.strip_prefix('x')?;
+    // Reject forms like "x01" or "x+1" which are accepted by parse.
+    if number.is_empty() || (number.len() > 1 && number.starts_with('0')) {
+        return None;
+    }
+    if !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number
+        .parse::<usize>()
+        .ok()
+        .filter(|i| *i < REGISTER_ABI_NAMES.len())
+}
+
+// A register index which can be parsed from and displayed as its ABI name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RegisterName(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRegisterError(pub String);
+
+impl std::fmt::Display for ParseRegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown register: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseRegisterError {}
+
+impl std::str::FromStr for RegisterName {
+    type Err = ParseRegisterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        register_index(&s.to_ascii_lowercase())
+            .map(RegisterName)
+            .ok_or_else(|| ParseRegisterError(s.to_string()))
+    }
+}
+
+impl std::fmt::Display for RegisterName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match register_name(self.0) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "x{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_name_and_index() {
+        for (i, name) in REGISTER_ABI_NAMES.iter().enumerate() {
+            assert_eq!(register_index(name), Some(i));
+            assert_eq!(register_name(i), Some(*name));
+            assert_eq!(register_index(&format!("x{}", i)), Some(i));
+        }
+        assert_eq!(register_index("fp"), Some(S0));
+        assert_eq!(register_index("x32"), None);
+        assert_eq!(register_index("x01"), None);
+        assert_eq!(register_index("x"), None);
+        assert_eq!(register_index("a8"), None);
+        assert_eq!(register_name(32), None);
+    }
+
+    #[test]
+    fn test_register_name_parse() {
+        assert_eq!("A0".parse::<RegisterName>(), Ok(RegisterName(A0)));
+        assert_eq!("sp".parse::<RegisterName>(), Ok(RegisterName(SP)));
+        assert!("foo".parse::<RegisterName>().is_err());
+        assert_eq!(RegisterName(T3).to_string(), "t3");
+    }
+}
@@ -0,0 +1,60 @@
+#ifndef CKB_VM_H_
+#define CKB_VM_H_
+
+/*
+ * C ABI of CKB-VM, available when the crate is built with the `capi` feature.
+ * See src/capi.rs for the documentation of each function.
+ */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CKB_VM_OK 0
+#define CKB_VM_ERROR_INVALID_ARGUMENT -1
+#define CKB_VM_ERROR_CYCLES_EXCEEDED -2
+#define CKB_VM_ERROR_ELF -3
+#define CKB_VM_ERROR_MEMORY -4
+#define CKB_VM_ERROR_INVALID_INSTRUCTION -5
+#define CKB_VM_ERROR_INVALID_ECALL -6
+#define CKB_VM_ERROR_SYSCALL -7
+#define CKB_VM_ERROR_OTHER -8
+#define CKB_VM_ERROR_PANIC -9
+
+typedef struct CkbVmMachine CkbVmMachine;
+typedef struct CkbVmCore CkbVmCore;
+
+typedef int (*CkbVmSyscallCallback)(void *data, CkbVmCore *core);
+
+CkbVmMachine *ckb_vm_machine_new(uint64_t isa, uint32_t version,
+                                 uint64_t max_cycles, size_t memory_size);
+void ckb_vm_machine_free(CkbVmMachine *machine);
+const char *ckb_vm_machine_last_error(const CkbVmMachine *machine);
+int ckb_vm_machine_add_syscall(CkbVmMachine *machine,
+                               CkbVmSyscallCallback callback, void *data);
+int ckb_vm_machine_load_program(CkbVmMachine *machine, const uint8_t *program,
+                                size_t program_length, size_t argc,
+                                const uint8_t *const *argv,
+                                const size_t *argv_lengths);
+int ckb_vm_machine_run(CkbVmMachine *machine, int8_t *exit_code);
+int ckb_vm_machine_resume(CkbVmMachine *machine, uint64_t additional_cycles,
+                          int8_t *exit_code);
+CkbVmCore *ckb_vm_machine_core(CkbVmMachine *machine);
+
+uint64_t ckb_vm_core_cycles(const CkbVmCore *core);
+uint64_t ckb_vm_core_pc(const CkbVmCore *core);
+int ckb_vm_core_register(const CkbVmCore *core, size_t index, uint64_t *value);
+int ckb_vm_core_set_register(CkbVmCore *core, size_t index, uint64_t value);
+int ckb_vm_core_load_bytes(CkbVmCore *core, uint64_t addr, uint8_t *buffer,
+                           size_t length);
+int ckb_vm_core_store_bytes(CkbVmCore *core, uint64_t addr,
+                            const uint8_t *buffer, size_t length);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CKB_VM_H_ */
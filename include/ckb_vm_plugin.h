@@ -0,0 +1,55 @@
+#ifndef CKB_VM_PLUGIN_H_
+#define CKB_VM_PLUGIN_H_
+
+/*
+ * Plugin ABI of CKB-VM, for syscall handlers built as shared libraries and
+ * loaded at runtime. See src/syscalls/plugin.rs for the documentation.
+ */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CKB_VM_PLUGIN_ABI_VERSION 1
+
+#define CKB_VM_PLUGIN_OK 0
+#define CKB_VM_PLUGIN_ERROR_INVALID_ARGUMENT -1
+#define CKB_VM_PLUGIN_ERROR_CYCLES_EXCEEDED -2
+#define CKB_VM_PLUGIN_ERROR_MEMORY -4
+
+typedef struct CkbVmPluginHost {
+  uint32_t abi_version;
+  uint64_t (*reg)(void *machine, size_t index);
+  int (*set_register)(void *machine, size_t index, uint64_t value);
+  uint64_t (*pc)(void *machine);
+  uint64_t (*cycles)(void *machine);
+  uint64_t (*max_cycles)(void *machine);
+  int (*add_cycles)(void *machine, uint64_t cycles);
+  int (*load_bytes)(void *machine, uint64_t addr, uint8_t *buffer,
+                    size_t length);
+  int (*store_bytes)(void *machine, uint64_t addr, const uint8_t *buffer,
+                     size_t length);
+} CkbVmPluginHost;
+
+typedef struct CkbVmPluginDescriptor {
+  uint32_t abi_version;
+  const char *name;
+  void *(*create)(const uint8_t *config, size_t config_length);
+  void (*destroy)(void *instance);
+  /* May be NULL. */
+  int (*initialize)(void *instance, const CkbVmPluginHost *host,
+                    void *machine);
+  int (*ecall)(void *instance, const CkbVmPluginHost *host, void *machine);
+} CkbVmPluginDescriptor;
+
+/* Exported by every plugin library. */
+const CkbVmPluginDescriptor *ckb_vm_plugin_v1(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CKB_VM_PLUGIN_H_ */
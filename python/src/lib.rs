@@ -0,0 +1,248 @@
+// Python bindings for the CKB-VM interpreter.
+//
+// The exported `Machine` class wraps a TraceMachine running on top of
+// DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>. Syscalls can be
+// implemented in Python: a syscall handler is a callable receiving a `Core`
+// object, which provides access to registers, memory and cycles, and returns
+// True when the syscall has been processed. The `Core` object is only valid
+// during the invocation of the handler.
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use ckb_vm::{
+    cost_model::estimate_cycles, Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder,
+    Error, Memory, SparseMemory, SupportMachine, Syscalls, TraceMachine, WXorXMemory,
+    RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+type Inner = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+create_exception!(ckb_vm, VMError, PyException);
+
+fn to_py_err(error: Error) -> PyErr {
+    VMError::new_err(error.to_string())
+}
+
+fn set_register(inner: &mut Inner, index: usize, value: u64) -> PyResult<()> {
+    if index >= RISCV_GENERAL_REGISTER_NUMBER {
+        return Err(PyIndexError::new_err("register index out of range"));
+    }
+    inner.set_register(index, value);
+    Ok(())
+}
+
+fn load_bytes(py: Python, inner: &mut Inner, addr: u64, size: u64) -> PyResult<PyObject> {
+    let data = inner
+        .memory_mut()
+        .load_bytes(addr, size)
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &data).into())
+}
+
+fn store_bytes(inner: &mut Inner, addr: u64, data: &[u8]) -> PyResult<()> {
+    inner
+        .memory_mut()
+        .store_bytes(addr, data)
+        .map_err(to_py_err)
+}
+
+/// Access to the core machine from inside a syscall handler.
+#[pyclass(unsendable)]
+pub struct Core {
+    inner: *mut Inner,
+}
+
+impl Core {
+    fn inner(&mut self) -> PyResult<&mut Inner> {
+        // Safety: the pointer is only set for the duration of a syscall
+        // handler invocation, during which the machine is borrowed by us.
+        unsafe { self.inner.as_mut() }
+            .ok_or_else(|| PyValueError::new_err("core is only valid inside a syscall handler"))
+    }
+}
+
+#[pymethods]
+impl Core {
+    #[getter]
+    fn pc(&mut self) -> PyResult<u64> {
+        Ok(*self.inner()?.pc())
+    }
+
+    #[getter]
+    fn cycles(&mut self) -> PyResult<u64> {
+        Ok(self.inner()?.cycles())
+    }
+
+    fn add_cycles(&mut self, cycles: u64) -> PyResult<()> {
+        self.inner()?.add_cycles(cycles).map_err(to_py_err)
+    }
+
+    fn register(&mut self, index: usize) -> PyResult<u64> {
+        self.inner()?
+            .registers()
+            .get(index)
+            .copied()
+            .ok_or_else(|| PyIndexError::new_err("register index out of range"))
+    }
+
+    fn set_register(&mut self, index: usize, value: u64) -> PyResult<()> {
+        set_register(self.inner()?, index, value)
+    }
+
+    fn load_bytes(&mut self, py: Python, addr: u64, size: u64) -> PyResult<PyObject> {
+        load_bytes(py, self.inner()?, addr, size)
+    }
+
+    fn store_bytes(&mut self, addr: u64, data: &[u8]) -> PyResult<()> {
+        store_bytes(self.inner()?, addr, data)
+    }
+}
+
+struct PythonSyscalls {
+    handlers: Arc<Mutex<Vec<PyObject>>>,
+}
+
+impl Syscalls<Inner> for PythonSyscalls {
+    fn initialize(&mut self, _machine: &mut Inner) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Inner) -> Result<bool, Error> {
+        let handlers = self
+            .handlers
+            .lock()
+            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        Python::with_gil(|py| {
+            for handler in handlers.iter() {
+                let core = Py::new(
+                    py,
+                    Core {
+                        inner: machine as *mut Inner,
+                    },
+                )
+                .map_err(|e| Error::External(e.to_string()))?;
+                let result = handler.call1(py, (core.clone_ref(py),));
+                // Invalidate the core so a handler cannot keep using it.
+                core.borrow_mut(py).inner = ptr::null_mut();
+                let processed = result
+                    .and_then(|r| r.is_true(py))
+                    .map_err(|e| Error::External(e.to_string()))?;
+                if processed {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// A CKB-VM machine using the interpreter backend.
+#[pyclass(unsendable)]
+pub struct Machine {
+    machine: TraceMachine<Inner>,
+    syscalls: Arc<Mutex<Vec<PyObject>>>,
+}
+
+#[pymethods]
+impl Machine {
+    #[new]
+    #[pyo3(signature = (isa, version, max_cycles, memory_size = RISCV_MAX_MEMORY))]
+    fn new(isa: u64, version: u32, max_cycles: u64, memory_size: usize) -> Self {
+        let syscalls = Arc::new(Mutex::new(Vec::new()));
+        let core = Inner::new_with_memory(isa, version, max_cycles, memory_size);
+        let machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core)
+                .instruction_cycle_func(Box::new(estimate_cycles))
+                .syscall(Box::new(PythonSyscalls {
+                    handlers: Arc::clone(&syscalls),
+                }))
+                .build(),
+        );
+        Self { machine, syscalls }
+    }
+
+    /// Registers a syscall handler, handlers are tried in registration order.
+    fn add_syscall(&mut self, handler: PyObject) -> PyResult<()> {
+        self.syscalls
+            .lock()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?
+            .push(handler);
+        Ok(())
+    }
+
+    #[pyo3(signature = (program, args = Vec::new()))]
+    fn load_program(&mut self, program: &[u8], args: Vec<Vec<u8>>) -> PyResult<u64> {
+        let args: Vec<Bytes> = args.into_iter().map(Bytes::from).collect();
+        self.machine
+            .load_program(&Bytes::copy_from_slice(program), &args)
+            .map_err(to_py_err)
+    }
+
+    fn run(&mut self) -> PyResult<i8> {
+        self.machine.run().map_err(to_py_err)
+    }
+
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.machine.machine.inner_mut().set_max_cycles(max_cycles);
+    }
+
+    #[getter]
+    fn exit_code(&self) -> i8 {
+        self.machine.machine.exit_code()
+    }
+
+    #[getter]
+    fn pc(&self) -> u64 {
+        *self.machine.pc()
+    }
+
+    #[getter]
+    fn cycles(&self) -> u64 {
+        self.machine.machine.cycles()
+    }
+
+    #[getter]
+    fn registers(&self) -> Vec<u64> {
+        self.machine.registers().to_vec()
+    }
+
+    fn register(&self, index: usize) -> PyResult<u64> {
+        self.machine
+            .registers()
+            .get(index)
+            .copied()
+            .ok_or_else(|| PyIndexError::new_err("register index out of range"))
+    }
+
+    fn set_register(&mut self, index: usize, value: u64) -> PyResult<()> {
+        set_register(self.machine.machine.inner_mut(), index, value)
+    }
+
+    fn load_bytes(&mut self, py: Python, addr: u64, size: u64) -> PyResult<PyObject> {
+        load_bytes(py, self.machine.machine.inner_mut(), addr, size)
+    }
+
+    fn store_bytes(&mut self, addr: u64, data: &[u8]) -> PyResult<()> {
+        store_bytes(self.machine.machine.inner_mut(), addr, data)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "ckb_vm")]
+fn ckb_vm_python(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("VMError", py.get_type::<VMError>())?;
+    m.add("ISA_IMC", ckb_vm::ISA_IMC)?;
+    m.add("ISA_B", ckb_vm::ISA_B)?;
+    m.add("ISA_MOP", ckb_vm::ISA_MOP)?;
+    m.add("ISA_A", ckb_vm::ISA_A)?;
+    m.add("VERSION0", ckb_vm::machine::VERSION0)?;
+    m.add("VERSION1", ckb_vm::machine::VERSION1)?;
+    m.add("VERSION2", ckb_vm::machine::VERSION2)?;
+    m.add_class::<Machine>()?;
+    m.add_class::<Core>()?;
+    Ok(())
+}
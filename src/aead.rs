@@ -0,0 +1,84 @@
+// ChaCha20-Poly1305 authenticated encryption as specified in RFC 8439, used
+// to seal snapshots.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+pub(crate) use super::chacha::{KEY_LENGTH, NONCE_LENGTH};
+
+// Encrypts `plaintext`, and returns the ciphertext followed by the tag
+// authenticating it together with `aad`.
+pub(crate) fn seal(
+    key: &[u8; KEY_LENGTH],
+    nonce: &[u8; NONCE_LENGTH],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("snapshot too large to seal")
+}
+
+// Reverses seal, returns None when the data or `aad` were altered or the key
+// is wrong.
+pub(crate) fn open(
+    key: &[u8; KEY_LENGTH],
+    nonce: &[u8; NONCE_LENGTH],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // RFC 8439 section 2.8.2.
+    #[test]
+    fn test_rfc8439_aead() {
+        let mut key = [0u8; KEY_LENGTH];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = 0x80 + i as u8;
+        }
+        let nonce = [
+            0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let expected = hex(
+            "d31a8d34648e60db7b86afbc53ef7ec2 a4aded51296e08fea9e2b5a736ee62d6
+             3dbea45e8ca9671282fafb69da92728b 1a71de0a9e060b2905d6a5b67ecd3b36
+             92ddbd7f2d778b8c9803aee328091b58 fab324e4fad675945585808b4831d7bc
+             3ff4def08e4b7a9de576d26586cec64b 6116
+             1ae10b594f09e26a7e902ecbd0600691",
+        );
+
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(sealed, expected);
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), &plaintext[..]);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered), None);
+        assert_eq!(open(&key, &nonce, &aad[1..], &sealed), None);
+        assert_eq!(open(&key, &nonce, &aad, &sealed[..15]), None);
+    }
+}
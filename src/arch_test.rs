@@ -0,0 +1,119 @@
+// Harness for the RISC-V architectural tests(riscv-arch-test), as run by
+// RISCOF. Each test binary stores its results in a signature region delimited
+// by the begin_signature and end_signature symbols, which is compared against
+// the signature produced by a reference model.
+//
+// Test binaries must be built with a CKB VM model, that is, linked inside the
+// VM memory, and with RVMODEL_HALT invoking the exit syscall(93):
+//
+// let result = run_arch_test::<u64>(&program, ISA_IMC | ISA_A | ISA_B, VERSION2, u64::MAX)?;
+// let expected = Signature::parse(&fs::read_to_string("add-01.reference_output")?)?;
+// assert!(result.signature.mismatches(&expected).is_empty());
+use crate::{
+    machine::{call::find_symbol, DefaultCoreMachine, DefaultMachineBuilder},
+    memory::{sparse::SparseMemory, Memory},
+    Bytes, CoreMachine, Error, Register, SupportMachine,
+};
+use std::fmt;
+
+pub const BEGIN_SIGNATURE_SYMBOL: &str = "begin_signature";
+pub const END_SIGNATURE_SYMBOL: &str = "end_signature";
+
+// A signature made of 32-bit words, in the format used by RISCOF: one word
+// per line, as 8 lowercase hex digits.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Signature(pub Vec<u32>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureMismatch {
+    // Offset in bytes from the start of the signature region.
+    pub offset: usize,
+    // None when one of the signatures is shorter.
+    pub actual: Option<u32>,
+    pub expected: Option<u32>,
+}
+
+impl Signature {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                u32::from_str_radix(line, 16)
+                    .map_err(|_| Error::Unexpected(format!("Invalid signature line: {}", line)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Signature)
+    }
+
+    pub fn mismatches(&self, expected: &Signature) -> Vec<SignatureMismatch> {
+        let len = self.0.len().max(expected.0.len());
+        (0..len)
+            .map(|i| (i, self.0.get(i).copied(), expected.0.get(i).copied()))
+            .filter(|(_, actual, expected)| actual != expected)
+            .map(|(i, actual, expected)| SignatureMismatch {
+                offset: i * 4,
+                actual,
+                expected,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for word in &self.0 {
+            writeln!(f, "{:08x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchTestResult {
+    pub exit_code: i8,
+    pub cycles: u64,
+    pub signature: Signature,
+}
+
+// Returns the start and end address of the signature region.
+pub fn signature_region(program: &Bytes) -> Result<(u64, u64), Error> {
+    let symbol = |name: &str| {
+        find_symbol(program, name)?
+            .ok_or_else(|| Error::Unexpected(format!("Missing symbol: {}", name)))
+    };
+    let begin = symbol(BEGIN_SIGNATURE_SYMBOL)?;
+    let end = symbol(END_SIGNATURE_SYMBOL)?;
+    if end < begin || (end - begin) % 4 != 0 {
+        return Err(Error::Unexpected(format!(
+            "Invalid signature region: 0x{:x} - 0x{:x}",
+            begin, end
+        )));
+    }
+    Ok((begin, end))
+}
+
+// Runs a test binary until it exits, then extracts its signature. Tests
+// exercise self-modifying code, hence W^X is not enforced.
+pub fn run_arch_test<R: Register>(
+    program: &Bytes,
+    isa: u64,
+    version: u32,
+    max_cycles: u64,
+) -> Result<ArchTestResult, Error> {
+    let (begin, end) = signature_region(program)?;
+    let core_machine = DefaultCoreMachine::<R, SparseMemory<R>>::new(isa, version, max_cycles);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(program, &[])?;
+    let exit_code = machine.run()?;
+    let bytes = machine.memory_mut().load_bytes(begin, end - begin)?;
+    let signature = bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    Ok(ArchTestResult {
+        exit_code,
+        cycles: machine.cycles(),
+        signature: Signature(signature),
+    })
+}
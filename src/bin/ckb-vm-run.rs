@@ -0,0 +1,282 @@
+// Runs a RISC-V ELF program, the reference harness for trying programs
+// outside of a node. Build with `cargo build --features cli`.
+use ckb_vm::chrome_trace::{ChromeTracer, TraceClock};
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::machine::abort::extract_abort_message;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::callgrind::CallgrindProfiler;
+use ckb_vm::machine::instrumented::InstrumentedMachine;
+use ckb_vm::machine::program::ProgramInfo;
+use ckb_vm::machine::symbols::Symbols;
+use ckb_vm::machine::{DefaultMachine, DefaultMachineBuilder, VERSION0, VERSION1, VERSION2};
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, Error, Memory, Register, SparseMemory, SupportMachine, Syscalls,
+    TraceMachine, WXorXMemory, ISA_A, ISA_B, ISA_D, ISA_F, ISA_IMC, ISA_MOP, ISA_ZICSR,
+};
+use std::fs::{self, File};
+use std::process::exit;
+
+const USAGE: &str = "Usage: ckb-vm-run [options] <program> [args...]
+
+Runs an ELF program, passing the program path and args as argv, and exits
+with its exit code, or 255 when the VM fails.
+
+Options:
+    --backend <name>     interpreter, trace (default) or asm
+    --version <n>        VM version, 0, 1 or 2 (default)
+    --isa <list>         extensions on top of IMC, comma separated among b,
+                         mop, a, f, d, zicsr and v (default b,mop,a), or none,
+                         v needs a build with the rvv feature
+    --max-cycles <n>     stop with an error past n cycles
+    --trace <file>       write a chrome://tracing timeline of the run
+    --profile <file>     write a callgrind profile, interpreter backend only
+    --dump <file>        write the machine state after the run, - for stdout
+    -h, --help           print this help
+
+Syscall 2177 prints the NUL terminated string at a0.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Interpreter,
+    Trace,
+    Asm,
+}
+
+struct Options {
+    backend: Backend,
+    version: u32,
+    isa: u64,
+    max_cycles: u64,
+    trace: Option<String>,
+    profile: Option<String>,
+    dump: Option<String>,
+    program: String,
+    args: Vec<String>,
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        backend: Backend::Trace,
+        version: VERSION2,
+        isa: ISA_IMC | ISA_B | ISA_MOP | ISA_A,
+        max_cycles: u64::MAX,
+        trace: None,
+        profile: None,
+        dump: None,
+        program: String::new(),
+        args: vec![],
+    };
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            options.program = arg;
+            options.args = args.collect();
+            break;
+        }
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            exit(0);
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} requires a value", arg))?;
+        match arg.as_str() {
+            "--backend" => {
+                options.backend = match value.as_str() {
+                    "interpreter" => Backend::Interpreter,
+                    "trace" => Backend::Trace,
+                    "asm" => Backend::Asm,
+                    _ => return Err(format!("unknown backend {}", value)),
+                }
+            }
+            "--version" => {
+                options.version = match value.as_str() {
+                    "0" => VERSION0,
+                    "1" => VERSION1,
+                    "2" => VERSION2,
+                    _ => return Err(format!("unknown version {}", value)),
+                }
+            }
+            "--isa" => {
+                options.isa = ISA_IMC;
+                for extension in value.split(',') {
+                    options.isa |= match extension {
+                        "none" => ISA_IMC,
+                        "b" => ISA_B,
+                        "mop" => ISA_MOP,
+                        "a" => ISA_A,
+                        "f" => ISA_F,
+                        "d" => ISA_F | ISA_D,
+                        "zicsr" => ISA_ZICSR,
+                        #[cfg(feature = "rvv")]
+                        "v" => ckb_vm::ISA_V,
+                        _ => return Err(format!("unknown extension {}", extension)),
+                    };
+                }
+            }
+            "--max-cycles" => {
+                options.max_cycles = value
+                    .parse()
+                    .map_err(|_| format!("invalid max cycles {}", value))?
+            }
+            "--trace" => options.trace = Some(value),
+            "--profile" => options.profile = Some(value),
+            "--dump" => options.dump = Some(value),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if options.program.is_empty() {
+        return Err(String::from("missing program"));
+    }
+    if options.profile.is_some() && options.backend != Backend::Interpreter {
+        return Err(String::from("--profile requires the interpreter backend"));
+    }
+    if cfg!(not(has_asm)) && options.backend == Backend::Asm {
+        return Err(String::from(
+            "the asm backend is not available on this platform",
+        ));
+    }
+    Ok(options)
+}
+
+struct DebugSyscall;
+
+impl<Mac: SupportMachine> Syscalls<Mac> for DebugSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 2177 {
+            return Ok(false);
+        }
+        let mut addr = machine.registers()[A0].to_u64();
+        let mut buffer = Vec::new();
+        loop {
+            let byte = machine
+                .memory_mut()
+                .load8(&Mac::REG::from_u64(addr))?
+                .to_u8();
+            if byte == 0 {
+                break;
+            }
+            buffer.push(byte);
+            addr += 1;
+        }
+        println!("{}", String::from_utf8_lossy(&buffer));
+        Ok(true)
+    }
+}
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+fn builder<Inner: SupportMachine>(
+    core: Inner,
+    tracer: &Option<ChromeTracer>,
+) -> DefaultMachineBuilder<Inner> {
+    let mut builder = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(estimate_cycles))
+        .syscall(Box::new(DebugSyscall));
+    if let Some(tracer) = tracer {
+        builder = builder.observer(Box::new(tracer.clone()));
+    }
+    builder
+}
+
+// Prints the outcome of the run and writes the requested outputs, which are
+// most useful when the run failed.
+fn report<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    result: Result<i8, Error>,
+    options: &Options,
+    tracer: &Option<ChromeTracer>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    eprintln!("exit={:?} cycles={}", result, machine.cycles());
+    if !matches!(result, Ok(0) | Err(Error::CyclesExceeded)) {
+        if let Some(abort) = extract_abort_message(machine) {
+            eprintln!("guest {:?}: {}", abort.kind, abort.message);
+        }
+        if let Some(build_id) = machine.program_info().and_then(ProgramInfo::build_id_hex) {
+            eprintln!("build-id={}", build_id);
+        }
+    }
+    if let Some(path) = &options.dump {
+        let dump = machine.dump(&[])?;
+        if path == "-" {
+            print!("{}", dump);
+        } else {
+            fs::write(path, dump)?;
+        }
+    }
+    if let (Some(path), Some(tracer)) = (&options.trace, tracer) {
+        fs::write(path, tracer.to_json())?;
+    }
+    Ok(i32::from(result?))
+}
+
+fn run(options: &Options) -> Result<i32, Box<dyn std::error::Error>> {
+    let program: Bytes = fs::read(&options.program)?.into();
+    let args: Vec<Bytes> = std::iter::once(&options.program)
+        .chain(options.args.iter())
+        .map(|arg| Bytes::from(arg.clone().into_bytes()))
+        .collect();
+    let tracer = options
+        .trace
+        .as_ref()
+        .map(|_| ChromeTracer::new(TraceClock::WallClock));
+    let core = || Core::new(options.isa, options.version, options.max_cycles);
+    match options.backend {
+        Backend::Interpreter => {
+            if let Some(path) = &options.profile {
+                let profiler = CallgrindProfiler::new(Symbols::from_elf(&program)?);
+                let mut machine =
+                    InstrumentedMachine::new(builder(core(), &tracer).build(), profiler);
+                machine.load_program(&program, &args)?;
+                let result = machine.run();
+                let cycles = machine.machine.cycles();
+                machine.handler.finish(cycles);
+                machine.handler.write(&mut File::create(path)?)?;
+                report(&mut machine.machine, result, options, &tracer)
+            } else {
+                let mut machine = builder(core(), &tracer).build();
+                machine.load_program(&program, &args)?;
+                let result = machine.run();
+                report(&mut machine, result, options, &tracer)
+            }
+        }
+        Backend::Trace => {
+            let mut machine = TraceMachine::new(builder(core(), &tracer).build());
+            machine.load_program(&program, &args)?;
+            let result = machine.run();
+            report(&mut machine.machine, result, options, &tracer)
+        }
+        #[cfg(has_asm)]
+        Backend::Asm => {
+            let core = AsmCoreMachine::new(options.isa, options.version, options.max_cycles);
+            let mut machine = AsmMachine::new(builder(core, &tracer).build());
+            machine.load_program(&program, &args)?;
+            let result = machine.run();
+            report(&mut machine.machine, result, options, &tracer)
+        }
+        #[cfg(not(has_asm))]
+        Backend::Asm => unreachable!(),
+    }
+}
+
+fn main() {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(exit_code) => exit(exit_code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(255);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+// C ABI for embedding CKB-VM from non-Rust hosts.
+//
+// The exported machine is a TraceMachine running on top of
+// DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>, which is the most
+// common configuration used in CKB. All functions are prefixed with `ckb_vm_`,
+// return CKB_VM_OK(0) on success and a negative error code on failure. The
+// description of the last error can be fetched via ckb_vm_machine_last_error.
+// A panic never unwinds into the host, it is reported as CKB_VM_ERROR_PANIC,
+// or a null pointer or 0 by the functions not returning an error code.
+//
+// Syscalls are implemented via callbacks. Inside a callback, the host only
+// gets access to the core machine(registers, memory, cycles), this is the
+// same restriction applied to Rust Syscalls implementations.
+use std::any::Any;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    cost_model::estimate_cycles, machine::trace::TraceMachine, Bytes, CoreMachine,
+    DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory, SupportMachine,
+    Syscalls, WXorXMemory, RISCV_GENERAL_REGISTER_NUMBER,
+};
+
+pub type CkbVmCore = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+pub const CKB_VM_OK: c_int = 0;
+pub const CKB_VM_ERROR_INVALID_ARGUMENT: c_int = -1;
+pub const CKB_VM_ERROR_CYCLES_EXCEEDED: c_int = -2;
+pub const CKB_VM_ERROR_ELF: c_int = -3;
+pub const CKB_VM_ERROR_MEMORY: c_int = -4;
+pub const CKB_VM_ERROR_INVALID_INSTRUCTION: c_int = -5;
+pub const CKB_VM_ERROR_INVALID_ECALL: c_int = -6;
+pub const CKB_VM_ERROR_SYSCALL: c_int = -7;
+pub const CKB_VM_ERROR_OTHER: c_int = -8;
+pub const CKB_VM_ERROR_PANIC: c_int = -9;
+
+// Syscall callback. `core` is only valid during the invocation. Returns 1 if
+// the syscall has been processed, 0 if the next syscall handler should be
+// tried, and any negative value to abort execution with CKB_VM_ERROR_SYSCALL.
+pub type CkbVmSyscallCallback = extern "C" fn(data: *mut c_void, core: *mut CkbVmCore) -> c_int;
+
+struct SyscallEntry {
+    callback: CkbVmSyscallCallback,
+    data: *mut c_void,
+}
+
+// The host is responsible for making the callback data usable from the thread
+// running the machine.
+unsafe impl Send for SyscallEntry {}
+
+struct CallbackSyscalls {
+    entries: Arc<Mutex<Vec<SyscallEntry>>>,
+}
+
+impl Syscalls<CkbVmCore> for CallbackSyscalls {
+    fn initialize(&mut self, _machine: &mut CkbVmCore) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut CkbVmCore) -> Result<bool, Error> {
+        let entries = self
+            .entries
+            .lock()
+            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        for entry in entries.iter() {
+            let r = (entry.callback)(entry.data, machine as *mut CkbVmCore);
+            if r < 0 {
+                return Err(Error::External(format!("syscall callback returns {}", r)));
+            }
+            if r > 0 {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+pub struct CkbVmMachine {
+    machine: TraceMachine<CkbVmCore>,
+    syscalls: Arc<Mutex<Vec<SyscallEntry>>>,
+    last_error: CString,
+}
+
+impl CkbVmMachine {
+    fn record(&mut self, result: Result<(), Error>) -> c_int {
+        match result {
+            Ok(()) => CKB_VM_OK,
+            Err(e) => {
+                let code = error_code(&e);
+                self.last_error = CString::new(e.to_string()).unwrap_or_default();
+                code
+            }
+        }
+    }
+
+    // Runs `f` on the machine, recording its error or panic.
+    fn call<F>(&mut self, f: F) -> c_int
+    where
+        F: FnOnce(&mut TraceMachine<CkbVmCore>) -> Result<(), Error>,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut self.machine))) {
+            Ok(result) => self.record(result),
+            Err(payload) => {
+                self.last_error = CString::new(panic_message(payload.as_ref())).unwrap_or_default();
+                CKB_VM_ERROR_PANIC
+            }
+        }
+    }
+}
+
+// Unwinding out of an extern "C" function is undefined behavior, so every
+// entry point runs its body here, returning `panicked` on a panic.
+fn guard<T, F: FnOnce() -> T>(panicked: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(panicked)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panic: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panic: {}", message)
+    } else {
+        "panic".to_string()
+    }
+}
+
+pub fn error_code(error: &Error) -> c_int {
+    match error.root() {
+        Error::CyclesExceeded | Error::CyclesOverflow => CKB_VM_ERROR_CYCLES_EXCEEDED,
+        Error::ElfBits
+        | Error::ElfParseError(_)
+        | Error::ElfSegmentUnreadable
+        | Error::ElfSegmentWritableAndExecutable
+        | Error::ElfSegmentAddrOrSizeError => CKB_VM_ERROR_ELF,
+        Error::MemOutOfBound
+        | Error::MemOutOfStack
+        | Error::MemPageUnalignedAccess
+        | Error::MemUnalignedAccess
+        | Error::MemWriteOnExecutablePage
+        | Error::MemWriteOnFreezedPage
+        | Error::MemWriteLimitExceeded
+        | Error::MemQuotaExceeded => CKB_VM_ERROR_MEMORY,
+        Error::InvalidInstruction { .. }
+        | Error::InvalidOp(_)
+        | Error::DeniedInstruction { .. } => CKB_VM_ERROR_INVALID_INSTRUCTION,
+        Error::InvalidEcall(_) => CKB_VM_ERROR_INVALID_ECALL,
+        Error::External(_) => CKB_VM_ERROR_SYSCALL,
+        _ => CKB_VM_ERROR_OTHER,
+    }
+}
+
+/// Creates a new machine, the returned pointer must be released with
+/// ckb_vm_machine_free.
+#[no_mangle]
+pub extern "C" fn ckb_vm_machine_new(
+    isa: u64,
+    version: u32,
+    max_cycles: u64,
+    memory_size: usize,
+) -> *mut CkbVmMachine {
+    guard(ptr::null_mut(), || {
+        let syscalls = Arc::new(Mutex::new(Vec::new()));
+        let core = CkbVmCore::new_with_memory(isa, version, max_cycles, memory_size);
+        let machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core)
+                .instruction_cycle_func(Box::new(estimate_cycles))
+                .syscall(Box::new(CallbackSyscalls {
+                    entries: Arc::clone(&syscalls),
+                }))
+                .build(),
+        );
+        Box::into_raw(Box::new(CkbVmMachine {
+            machine,
+            syscalls,
+            last_error: CString::default(),
+        }))
+    })
+}
+
+/// # Safety
+///
+/// `machine` must be created by ckb_vm_machine_new, and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_free(machine: *mut CkbVmMachine) {
+    guard((), || {
+        if !machine.is_null() {
+            drop(Box::from_raw(machine));
+        }
+    })
+}
+
+/// Returns the description of the last error, the string stays valid until
+/// the next failed call on the same machine.
+///
+/// # Safety
+///
+/// `machine` must be a valid machine pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_last_error(machine: *const CkbVmMachine) -> *const c_char {
+    guard(ptr::null(), || match machine.as_ref() {
+        Some(machine) => machine.last_error.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Registers a syscall callback, callbacks are tried in registration order.
+///
+/// # Safety
+///
+/// `machine` must be a valid machine pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_add_syscall(
+    machine: *mut CkbVmMachine,
+    callback: CkbVmSyscallCallback,
+    data: *mut c_void,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let machine = match machine.as_mut() {
+            Some(machine) => machine,
+            None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        let result = machine
+            .syscalls
+            .lock()
+            .map(|mut entries| entries.push(SyscallEntry { callback, data }))
+            .map_err(|e| Error::Unexpected(e.to_string()));
+        machine.record(result)
+    })
+}
+
+/// Loads an ELF program together with its arguments. `argv` points to `argc`
+/// buffers, the length of each is provided in `argv_lengths`.
+///
+/// # Safety
+///
+/// All pointers must be valid for the provided lengths.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_load_program(
+    machine: *mut CkbVmMachine,
+    program: *const u8,
+    program_length: usize,
+    argc: usize,
+    argv: *const *const u8,
+    argv_lengths: *const usize,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let machine = match machine.as_mut() {
+            Some(machine) => machine,
+            None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        if program.is_null() || (argc > 0 && (argv.is_null() || argv_lengths.is_null())) {
+            return CKB_VM_ERROR_INVALID_ARGUMENT;
+        }
+        let program = Bytes::copy_from_slice(slice::from_raw_parts(program, program_length));
+        let mut args = Vec::with_capacity(argc);
+        for i in 0..argc {
+            let arg = *argv.add(i);
+            let length = *argv_lengths.add(i);
+            if length == 0 {
+                args.push(Bytes::new());
+                continue;
+            }
+            if arg.is_null() {
+                return CKB_VM_ERROR_INVALID_ARGUMENT;
+            }
+            args.push(Bytes::copy_from_slice(slice::from_raw_parts(arg, length)));
+        }
+        machine.call(|m| m.load_program(&program, &args).map(|_| ()))
+    })
+}
+
+/// Runs the machine until it exits, exit code is written to `exit_code`.
+/// When CKB_VM_ERROR_CYCLES_EXCEEDED is returned, the machine can be resumed
+/// via ckb_vm_machine_resume.
+///
+/// # Safety
+///
+/// `machine` and `exit_code` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_run(
+    machine: *mut CkbVmMachine,
+    exit_code: *mut i8,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let machine = match machine.as_mut() {
+            Some(machine) => machine,
+            None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        machine.call(|m| {
+            m.run().map(|code| {
+                if !exit_code.is_null() {
+                    *exit_code = code;
+                }
+            })
+        })
+    })
+}
+
+/// Raises max cycles by `additional_cycles` and continues a machine stopped
+/// by CKB_VM_ERROR_CYCLES_EXCEEDED.
+///
+/// # Safety
+///
+/// `machine` and `exit_code` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_resume(
+    machine: *mut CkbVmMachine,
+    additional_cycles: u64,
+    exit_code: *mut i8,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let m = match machine.as_mut() {
+            Some(machine) => machine,
+            None => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        let core = m.machine.machine.inner_mut();
+        let max_cycles = core.max_cycles().saturating_add(additional_cycles);
+        core.set_max_cycles(max_cycles);
+        ckb_vm_machine_run(machine, exit_code)
+    })
+}
+
+/// Returns the core of a machine, which can be used with the ckb_vm_core_*
+/// functions below. The core shares the lifetime of the machine.
+///
+/// # Safety
+///
+/// `machine` must be a valid machine pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_machine_core(machine: *mut CkbVmMachine) -> *mut CkbVmCore {
+    guard(ptr::null_mut(), || match machine.as_mut() {
+        Some(machine) => machine.machine.machine.inner_mut() as *mut CkbVmCore,
+        None => ptr::null_mut(),
+    })
+}
+
+/// # Safety
+///
+/// `core` must be a valid core pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_cycles(core: *const CkbVmCore) -> u64 {
+    guard(0, || core.as_ref().map(|c| c.cycles()).unwrap_or(0))
+}
+
+/// # Safety
+///
+/// `core` must be a valid core pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_pc(core: *const CkbVmCore) -> u64 {
+    guard(0, || core.as_ref().map(|c| *c.pc()).unwrap_or(0))
+}
+
+/// # Safety
+///
+/// `core` and `value` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_register(
+    core: *const CkbVmCore,
+    index: usize,
+    value: *mut u64,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || match core.as_ref() {
+        Some(core) if index < RISCV_GENERAL_REGISTER_NUMBER && !value.is_null() => {
+            *value = core.registers()[index];
+            CKB_VM_OK
+        }
+        _ => CKB_VM_ERROR_INVALID_ARGUMENT,
+    })
+}
+
+/// # Safety
+///
+/// `core` must be a valid core pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_set_register(
+    core: *mut CkbVmCore,
+    index: usize,
+    value: u64,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || match core.as_mut() {
+        Some(core) if index < RISCV_GENERAL_REGISTER_NUMBER => {
+            core.set_register(index, value);
+            CKB_VM_OK
+        }
+        _ => CKB_VM_ERROR_INVALID_ARGUMENT,
+    })
+}
+
+/// Copies `length` bytes at guest address `addr` into `buffer`.
+///
+/// # Safety
+///
+/// `core` must be a valid core pointer, `buffer` must hold `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_load_bytes(
+    core: *mut CkbVmCore,
+    addr: u64,
+    buffer: *mut u8,
+    length: usize,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let core = match core.as_mut() {
+            Some(core) if !buffer.is_null() || length == 0 => core,
+            _ => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        match core.memory_mut().load_bytes(addr, length as u64) {
+            Ok(data) => {
+                if length > 0 {
+                    slice::from_raw_parts_mut(buffer, length).copy_from_slice(&data);
+                }
+                CKB_VM_OK
+            }
+            Err(e) => error_code(&e),
+        }
+    })
+}
+
+/// Copies `length` bytes from `buffer` into guest address `addr`.
+///
+/// # Safety
+///
+/// `core` must be a valid core pointer, `buffer` must hold `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ckb_vm_core_store_bytes(
+    core: *mut CkbVmCore,
+    addr: u64,
+    buffer: *const u8,
+    length: usize,
+) -> c_int {
+    guard(CKB_VM_ERROR_PANIC, || {
+        let core = match core.as_mut() {
+            Some(core) if !buffer.is_null() || length == 0 => core,
+            _ => return CKB_VM_ERROR_INVALID_ARGUMENT,
+        };
+        if length == 0 {
+            return CKB_VM_OK;
+        }
+        match core
+            .memory_mut()
+            .store_bytes(addr, slice::from_raw_parts(buffer, length))
+        {
+            Ok(()) => CKB_VM_OK,
+            Err(e) => error_code(&e),
+        }
+    })
+}
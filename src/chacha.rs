@@ -0,0 +1,55 @@
+// The ChaCha20 block function as specified in RFC 8439, shared by the
+// snapshot encryption and the random syscall.
+
+pub(crate) const KEY_LENGTH: usize = 32;
+pub(crate) const NONCE_LENGTH: usize = 12;
+
+pub(crate) fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+pub(crate) fn chacha20_block(
+    key: &[u8; KEY_LENGTH],
+    counter: u32,
+    nonce: &[u8; NONCE_LENGTH],
+) -> [u8; 64] {
+    let mut initial = [0u32; 16];
+    initial[0] = 0x6170_7865;
+    initial[1] = 0x3320_646e;
+    initial[2] = 0x7962_2d32;
+    initial[3] = 0x6b20_6574;
+    for (word, chunk) in initial[4..12].iter_mut().zip(key.chunks(4)) {
+        *word = le32(chunk);
+    }
+    initial[12] = counter;
+    for (word, chunk) in initial[13..16].iter_mut().zip(nonce.chunks(4)) {
+        *word = le32(chunk);
+    }
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    let mut block = [0u8; 64];
+    for (i, chunk) in block.chunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&state[i].wrapping_add(initial[i]).to_le_bytes());
+    }
+    block
+}
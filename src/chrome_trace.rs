@@ -0,0 +1,276 @@
+use crate::observer::{Event, EventObserver};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Timestamps used to lay out the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceClock {
+    // Microseconds since the tracer was created.
+    WallClock,
+    // Cycles of the machine, shown as microseconds. Timelines are then the
+    // same on every run.
+    Cycles,
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    name: &'static str,
+    // Chrome trace event phase: B(egin), E(nd) or i(nstant).
+    phase: char,
+    wall: f64,
+    cycles: u64,
+    // (name, JSON value)
+    args: Vec<(&'static str, String)>,
+}
+
+#[derive(Default)]
+struct Timeline {
+    records: Vec<Record>,
+    // Code of the syscall in progress, closed when a run fails inside it.
+    syscall: Option<u64>,
+    running: bool,
+}
+
+// ChromeTracer is an EventObserver recording machine events as a timeline
+// in the chrome://tracing JSON format, also read by Perfetto. Loading, runs
+// and syscalls become spans, decoded traces, permission changes and the
+// outcome of runs become instant events, all carrying the cycles and the
+// wall clock time they happened at.
+//
+// The tracer is a handle, the clone given to the machine shares its
+// timeline:
+//
+// let tracer = ChromeTracer::new(TraceClock::WallClock);
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .observer(Box::new(tracer.clone()))
+//     .build();
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// std::fs::write("trace.json", tracer.to_json())?;
+#[derive(Clone)]
+pub struct ChromeTracer {
+    clock: TraceClock,
+    start: Instant,
+    timeline: Arc<Mutex<Timeline>>,
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn hex(value: u64) -> String {
+    json_string(&format!("0x{:x}", value))
+}
+
+impl ChromeTracer {
+    pub fn new(clock: TraceClock) -> Self {
+        Self {
+            clock,
+            start: Instant::now(),
+            timeline: Arc::new(Mutex::new(Timeline::default())),
+        }
+    }
+
+    // Number of recorded trace events.
+    pub fn len(&self) -> usize {
+        self.timeline.lock().map(|t| t.records.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut timeline) = self.timeline.lock() {
+            *timeline = Timeline::default();
+        }
+    }
+
+    // Returns the timeline as a chrome://tracing JSON object.
+    pub fn to_json(&self) -> String {
+        let timeline = match self.timeline.lock() {
+            Ok(timeline) => timeline,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut out = String::from("{\"traceEvents\":[");
+        for (i, record) in timeline.records.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let ts = match self.clock {
+                TraceClock::WallClock => format!("{:.3}", record.wall),
+                TraceClock::Cycles => record.cycles.to_string(),
+            };
+            let _ = write!(
+                out,
+                "{{\"name\":{},\"cat\":\"vm\",\"ph\":\"{}\",\"ts\":{},\"pid\":1,\"tid\":1",
+                json_string(record.name),
+                record.phase,
+                ts
+            );
+            if record.phase == 'i' {
+                out.push_str(",\"s\":\"t\"");
+            }
+            let _ = write!(
+                out,
+                ",\"args\":{{\"cycles\":{},\"wall_us\":{:.3}",
+                record.cycles, record.wall
+            );
+            for (name, value) in &record.args {
+                let _ = write!(out, ",{}:{}", json_string(name), value);
+            }
+            out.push_str("}}");
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+impl Timeline {
+    fn push(
+        &mut self,
+        name: &'static str,
+        phase: char,
+        wall: f64,
+        cycles: u64,
+        args: Vec<(&'static str, String)>,
+    ) {
+        self.records.push(Record {
+            name,
+            phase,
+            wall,
+            cycles,
+            args,
+        });
+    }
+
+    // Closes the spans of a run which just stopped.
+    fn end_run(&mut self, wall: f64, cycles: u64) {
+        if let Some(code) = self.syscall.take() {
+            self.push(
+                "syscall",
+                'E',
+                wall,
+                cycles,
+                vec![("code", code.to_string())],
+            );
+        }
+        if self.running {
+            self.running = false;
+            self.push("run", 'E', wall, cycles, vec![]);
+        }
+    }
+}
+
+impl EventObserver for ChromeTracer {
+    fn on_event(&mut self, event: &Event) {
+        self.on_event_at(event, 0);
+    }
+
+    fn on_event_at(&mut self, event: &Event, cycles: u64) {
+        let wall = self.start.elapsed().as_secs_f64() * 1e6;
+        let mut timeline = match self.timeline.lock() {
+            Ok(timeline) => timeline,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match event {
+            Event::LoadStarted => timeline.push("load", 'B', wall, cycles, vec![]),
+            Event::ProgramLoaded { entry, bytes } => timeline.push(
+                "load",
+                'E',
+                wall,
+                cycles,
+                vec![("entry", hex(*entry)), ("bytes", bytes.to_string())],
+            ),
+            Event::TraceCompiled {
+                address,
+                length,
+                instructions,
+            } => timeline.push(
+                "decode",
+                'i',
+                wall,
+                cycles,
+                vec![
+                    ("address", hex(*address)),
+                    ("length", length.to_string()),
+                    ("instructions", instructions.to_string()),
+                ],
+            ),
+            Event::SyscallEntered { code } => {
+                timeline.syscall = Some(*code);
+                timeline.push(
+                    "syscall",
+                    'B',
+                    wall,
+                    cycles,
+                    vec![("code", code.to_string())],
+                );
+            }
+            Event::SyscallExited { code } => {
+                timeline.syscall = None;
+                timeline.push(
+                    "syscall",
+                    'E',
+                    wall,
+                    cycles,
+                    vec![("code", code.to_string())],
+                );
+            }
+            Event::MemoryPermissionChanged { page, old, new } => timeline.push(
+                "permission",
+                'i',
+                wall,
+                cycles,
+                vec![
+                    ("page", page.to_string()),
+                    ("old", old.to_string()),
+                    ("new", new.to_string()),
+                ],
+            ),
+            Event::RunStarted { .. } => {
+                timeline.running = true;
+                timeline.push("run", 'B', wall, cycles, vec![]);
+            }
+            Event::Suspended { .. } => {
+                timeline.end_run(wall, cycles);
+                timeline.push("suspended", 'i', wall, cycles, vec![]);
+            }
+            Event::Terminated { exit_code, .. } => {
+                timeline.end_run(wall, cycles);
+                timeline.push(
+                    "terminated",
+                    'i',
+                    wall,
+                    cycles,
+                    vec![("exit_code", exit_code.to_string())],
+                );
+            }
+            Event::Failed { error } => {
+                timeline.end_run(wall, cycles);
+                timeline.push(
+                    "failed",
+                    'i',
+                    wall,
+                    cycles,
+                    vec![("error", json_string(&error.to_string()))],
+                );
+            }
+        }
+    }
+}
@@ -1,32 +1,56 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+
+use bytes::Bytes;
 use ckb_vm_definitions::instructions::{self as insts};
 use ckb_vm_definitions::registers::{RA, ZERO};
 
 use crate::instructions::{
-    a, b, extract_opcode, i, instruction_length, m, rvc, set_instruction_length_n, Instruction,
-    InstructionFactory, Itype, R4type, R5type, Register, Rtype, Utype,
+    a, b, extract_opcode, i, instruction_length, instruction_opcode_name, m, privileged, rvc, rvf,
+    set_instruction_length_n, tagged::TaggedInstruction, Instruction, InstructionFactory, Itype,
+    LongInstructionFactory, R4type, R5type, Register, Rtype, Utype,
 };
 use crate::machine::VERSION2;
 use crate::memory::Memory;
-use crate::{Error, ISA_A, ISA_B, ISA_MOP, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use crate::{Error, ISA_A, ISA_B, ISA_D, ISA_F, ISA_MOP, ISA_PRIV, ISA_ZICSR, RISCV_PAGESIZE};
 
 const RISCV_PAGESIZE_MASK: u64 = RISCV_PAGESIZE as u64 - 1;
 const INSTRUCTION_CACHE_SIZE: usize = 4096;
+// Key of empty instruction cache entries, an address past the end of any
+// memory.
+const EMPTY_CACHE_KEY: u64 = u64::MAX;
 
 pub struct Decoder {
     factories: Vec<InstructionFactory>,
+    // Factories of 16-bit instructions, they are skipped for 32-bit ones.
+    compressed_factories: Vec<InstructionFactory>,
+    // Factories of 48-bit and 64-bit instructions.
+    long_factories: Vec<LongInstructionFactory>,
+    // False when the program is known to contain no RVC instructions.
+    compressed: bool,
     mop: bool,
     version: u32,
+    // Opcodes rejected with Error::DeniedInstruction.
+    denied_opcodes: HashSet<insts::InstructionOpcode>,
     // use a cache of instructions to avoid decoding the same instruction twice, pc is the key and the instruction is the value
     instructions_cache: [(u64, u64); INSTRUCTION_CACHE_SIZE],
+    // Instructions decoded ahead of time, looked up on cache misses.
+    predecoded: Option<Arc<PredecodedCode>>,
 }
 
 impl Decoder {
     pub fn new(mop: bool, version: u32) -> Decoder {
         Decoder {
             factories: vec![],
+            compressed_factories: vec![],
+            long_factories: vec![],
+            compressed: true,
             mop,
             version,
-            instructions_cache: [(RISCV_MAX_MEMORY as u64, 0); INSTRUCTION_CACHE_SIZE],
+            denied_opcodes: HashSet::new(),
+            instructions_cache: [(EMPTY_CACHE_KEY, 0); INSTRUCTION_CACHE_SIZE],
+            predecoded: None,
         }
     }
 
@@ -34,9 +58,140 @@ impl Decoder {
         self.factories.push(factory);
     }
 
+    // Adds a factory which only decodes 16-bit instructions, e.g. RVC.
+    pub fn add_compressed_instruction_factory(&mut self, factory: InstructionFactory) {
+        self.compressed_factories.push(factory);
+    }
+
+    // Adds a factory of 48-bit or 64-bit instructions, e.g. custom extensions
+    // using the long encodings of the RISC-V spec.
+    pub fn add_long_instruction_factory(&mut self, factory: LongInstructionFactory) {
+        self.long_factories.push(factory);
+    }
+
+    // Tells the decoder whether the program may contain RVC instructions,
+    // e.g. from the EF_RISCV_RVC flag of its ELF header. Without them, PCs
+    // are 4-byte aligned and instructions are loaded with a single 32-bit
+    // load. This is only a hint, a compressed instruction found anyway is
+    // still decoded through the regular path.
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    // Uses instructions decoded ahead of time, see PredecodedCode. They are
+    // dropped when the instructions cache is reset.
+    pub fn set_predecoded(&mut self, predecoded: Arc<PredecodedCode>) {
+        self.predecoded = Some(predecoded);
+    }
+
+    // Rejects instructions whose opcode is in `opcodes`, including fused ones
+    // produced by macro-op fusion, e.g. all atomics:
+    //
+    // decoder.set_denied_opcodes(insts::OP_LR_W..=insts::OP_AMOMAXU_D);
+    pub fn set_denied_opcodes<I: IntoIterator<Item = insts::InstructionOpcode>>(
+        &mut self,
+        opcodes: I,
+    ) {
+        self.denied_opcodes = opcodes.into_iter().collect();
+        self.instructions_cache = [(EMPTY_CACHE_KEY, 0); INSTRUCTION_CACHE_SIZE];
+    }
+
+    fn check_denied(&self, instruction: Instruction, pc: u64) -> Result<Instruction, Error> {
+        let opcode = extract_opcode(instruction);
+        if self.denied_opcodes.contains(&opcode) {
+            return Err(Error::DeniedInstruction { pc, opcode });
+        }
+        Ok(instruction)
+    }
+
+    // Returns a decoder with the same factories and an empty cache.
+    fn fork(&self) -> Decoder {
+        Decoder {
+            factories: self.factories.clone(),
+            compressed_factories: self.compressed_factories.clone(),
+            long_factories: self.long_factories.clone(),
+            compressed: self.compressed,
+            mop: false,
+            version: self.version,
+            denied_opcodes: self.denied_opcodes.clone(),
+            instructions_cache: [(EMPTY_CACHE_KEY, 0); INSTRUCTION_CACHE_SIZE],
+            predecoded: None,
+        }
+    }
+
+    // Decodes instruction bits with the registered factories.
+    fn decode_instruction_bits(
+        &self,
+        instruction_bits: u32,
+        pc: u64,
+    ) -> Result<Instruction, Error> {
+        // RVC instructions never have 0b11 as their lowest bits.
+        let compressed_factories = if instruction_bits & 0x3 != 0x3 {
+            &self.compressed_factories[..]
+        } else {
+            &[]
+        };
+        for factory in compressed_factories.iter().chain(self.factories.iter()) {
+            if let Some(instruction) = factory(instruction_bits, self.version) {
+                return self.check_denied(instruction, pc);
+            }
+        }
+        Err(Error::InvalidInstruction {
+            pc,
+            instruction: instruction_bits,
+        })
+    }
+
+    // Decodes the `length` bytes long instruction bits with the long
+    // factories. Invalid instructions are reported with their lowest 32 bits.
+    fn decode_long_instruction_bits(
+        &self,
+        instruction_bits: u64,
+        length: u8,
+        pc: u64,
+    ) -> Result<Instruction, Error> {
+        for factory in &self.long_factories {
+            if let Some(instruction) = factory(instruction_bits, length, self.version) {
+                return self.check_denied(set_instruction_length_n(instruction, length), pc);
+            }
+        }
+        Err(Error::InvalidInstruction {
+            pc,
+            instruction: instruction_bits as u32,
+        })
+    }
+
+    // Loads the rest of a long instruction whose lowest 32 bits are
+    // `instruction_bits` and decodes it. The remaining parcels are loaded
+    // separately, so instructions may cross page boundaries.
+    fn decode_long<M: Memory>(
+        &self,
+        memory: &mut M,
+        pc: u64,
+        instruction_bits: u32,
+    ) -> Result<Instruction, Error> {
+        let length = match encoding_length(instruction_bits as u16) {
+            Some(length) if length > 4 => length,
+            _ => return self.decode_instruction_bits(instruction_bits, pc),
+        };
+        // Nothing could decode it, so don't fetch parcels that may not exist.
+        if self.long_factories.is_empty() {
+            return Err(Error::InvalidInstruction {
+                pc,
+                instruction: instruction_bits,
+            });
+        }
+        let mut bits = u64::from(instruction_bits);
+        for offset in (4..length).step_by(2) {
+            let parcel = memory.execute_load16(pc.wrapping_add(u64::from(offset)))?;
+            bits |= u64::from(parcel) << (offset * 8);
+        }
+        self.decode_long_instruction_bits(bits, length, pc)
+    }
+
     // This method is used to decode instruction raw bits from memory pointed
-    // by current PC. Right now we support 32-bit instructions and RVC compressed
-    // instructions. In future version we might add support for longer instructions.
+    // by current PC. It loads 32-bit instructions and RVC compressed
+    // instructions, and the lowest 32 bits of longer ones, see decode_long.
     //
     // This decode method actually leverages a trick from little endian encoding:
     // the format for a full 32 bit RISC-V instruction is as follows:
@@ -69,6 +224,15 @@ impl Decoder {
     // RVC instruction into a 32-bit instruction, the meaning of the instruction stays
     // unchanged in the cast conversion.
     fn decode_bits<M: Memory>(&self, memory: &mut M, pc: u64) -> Result<u32, Error> {
+        // without RVC instructions, an aligned PC never points to the last 2
+        // bytes of a page, and the loaded bits are expected to be a full
+        // 32-bit instruction
+        if !self.compressed && pc & 0x3 == 0 {
+            let instruction_bits = memory.execute_load32(pc)?;
+            if instruction_bits & 0x3 == 0x3 {
+                return Ok(instruction_bits);
+            }
+        }
         // when the address is not the last 2 bytes of an executable page,
         // use a faster path to load instruction bits
         if pc & RISCV_PAGESIZE_MASK < RISCV_PAGESIZE_MASK - 1 {
@@ -87,8 +251,9 @@ impl Decoder {
     }
 
     pub fn decode_raw<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
-        // since we are using RISCV_MAX_MEMORY as the default key in the instruction cache, have to check out of bound error first
-        if pc as usize >= RISCV_MAX_MEMORY {
+        // Bounds are checked by the memory when fetching, except for the key
+        // marking empty cache entries, which no fetch could succeed at.
+        if pc == EMPTY_CACHE_KEY {
             return Err(Error::MemOutOfBound);
         }
         let instruction_cache_key = {
@@ -107,17 +272,20 @@ impl Decoder {
         if cached_instruction.0 == pc {
             return Ok(cached_instruction.1);
         }
-        let instruction_bits = self.decode_bits(memory, pc)?;
-        for factory in &self.factories {
-            if let Some(instruction) = factory(instruction_bits, self.version) {
-                self.instructions_cache[instruction_cache_key] = (pc, instruction);
-                return Ok(instruction);
-            }
+        if let Some(instruction) = self.predecoded.as_ref().and_then(|p| p.get(pc)) {
+            // Predecoded code may come from a decoder with other denied opcodes.
+            let instruction = self.check_denied(instruction, pc)?;
+            self.instructions_cache[instruction_cache_key] = (pc, instruction);
+            return Ok(instruction);
         }
-        Err(Error::InvalidInstruction {
-            pc,
-            instruction: instruction_bits,
-        })
+        let instruction_bits = self.decode_bits(memory, pc)?;
+        let instruction = if instruction_bits & 0x1f == 0x1f {
+            self.decode_long(memory, pc, instruction_bits)?
+        } else {
+            self.decode_instruction_bits(instruction_bits, pc)?
+        };
+        self.instructions_cache[instruction_cache_key] = (pc, instruction);
+        Ok(instruction)
     }
 
     // Macro-Operation Fusion (also Macro-Op Fusion, MOP Fusion, or Macrofusion) is a hardware optimization technique found
@@ -848,22 +1016,173 @@ impl Decoder {
         }
     }
 
+    // Decodes a single instruction from raw bytes, no instruction cache or
+    // macro-op fusion is involved.
+    pub fn decode_bytes(&self, bytes: &[u8], pc: u64) -> Result<Instruction, Error> {
+        if bytes.len() < 2 {
+            return Err(Error::MemOutOfBound);
+        }
+        let mut instruction_bits = u32::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+        if instruction_bits & 0x3 == 0x3 {
+            if bytes.len() < 4 {
+                return Err(Error::MemOutOfBound);
+            }
+            instruction_bits |= u32::from(u16::from_le_bytes([bytes[2], bytes[3]])) << 16;
+        }
+        match encoding_length(instruction_bits as u16) {
+            Some(length) if length > 4 && self.long_factories.is_empty() => {
+                Err(Error::InvalidInstruction {
+                    pc,
+                    instruction: instruction_bits,
+                })
+            }
+            Some(length) if length > 4 => {
+                let encoding = bytes.get(..length as usize).ok_or(Error::MemOutOfBound)?;
+                let mut bits = [0u8; 8];
+                bits[..encoding.len()].copy_from_slice(encoding);
+                self.decode_long_instruction_bits(u64::from_le_bytes(bits), length, pc)
+            }
+            _ => self.decode_instruction_bits(instruction_bits, pc),
+        }
+    }
+
     pub fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
         if self.mop {
-            self.decode_mop(memory, pc)
+            let instruction = self.decode_mop(memory, pc)?;
+            self.check_denied(instruction, pc)
         } else {
             self.decode_raw(memory, pc)
         }
     }
 
     pub fn reset_instructions_cache(&mut self) {
-        self.instructions_cache = [(RISCV_MAX_MEMORY as u64, 0); INSTRUCTION_CACHE_SIZE];
+        self.instructions_cache = [(EMPTY_CACHE_KEY, 0); INSTRUCTION_CACHE_SIZE];
+        self.predecoded = None;
+    }
+}
+
+// Instructions of a program decoded ahead of time at every 2-byte aligned
+// address of its executable segments, so the first execution of each
+// instruction doesn't have to load and decode it. The code is assumed not to
+// change after decoding.
+#[derive(Default)]
+pub struct PredecodedCode {
+    // Start address and instructions of each range, 0 where the bytes at an
+    // address are not a valid instruction.
+    ranges: Vec<(u64, Vec<Instruction>)>,
+}
+
+impl PredecodedCode {
+    // Decodes `ranges` of (address, code) with the factories of `decoder`,
+    // each range is split between `workers` threads.
+    pub fn decode(
+        decoder: &Decoder,
+        ranges: &[(u64, Bytes)],
+        workers: usize,
+    ) -> Result<Self, Error> {
+        let workers = workers.max(1);
+        let mut decoded = Vec::with_capacity(ranges.len());
+        for (start, code) in ranges {
+            let count = (code.len() + 1) / 2;
+            let chunk = (count + workers - 1) / workers;
+            let handles: Vec<_> = (0..count)
+                .step_by(chunk.max(1))
+                .map(|first| {
+                    let decoder = decoder.fork();
+                    let code = code.clone();
+                    let start = *start;
+                    let last = (first + chunk).min(count);
+                    thread::spawn(move || {
+                        (first..last)
+                            .map(|index| {
+                                let offset = index * 2;
+                                decoder
+                                    .decode_bytes(&code[offset..], start + offset as u64)
+                                    .unwrap_or(0)
+                            })
+                            .collect::<Vec<Instruction>>()
+                    })
+                })
+                .collect();
+            let mut instructions = Vec::with_capacity(count);
+            for handle in handles {
+                let chunk = handle.join().map_err(|_| {
+                    Error::Unexpected(String::from("A predecoding worker panicked"))
+                })?;
+                instructions.extend(chunk);
+            }
+            decoded.push((*start, instructions));
+        }
+        Ok(Self { ranges: decoded })
+    }
+
+    // Builds the code from (start address, instructions) ranges, as returned
+    // by ranges.
+    pub fn from_ranges(ranges: Vec<(u64, Vec<Instruction>)>) -> Self {
+        Self { ranges }
+    }
+
+    pub fn ranges(&self) -> &[(u64, Vec<Instruction>)] {
+        &self.ranges
+    }
+
+    // Returns the instruction decoded at `pc`, if any.
+    pub fn get(&self, pc: u64) -> Option<Instruction> {
+        if pc & 1 != 0 {
+            return None;
+        }
+        for (start, instructions) in &self.ranges {
+            if pc >= *start {
+                if let Some(&instruction) = instructions.get(((pc - start) / 2) as usize) {
+                    if instruction != 0 {
+                        return Some(instruction);
+                    }
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // Number of valid instructions decoded.
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(_, instructions)| instructions.iter().filter(|i| **i != 0).count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Returns the length in bytes of the instruction starting with the 16 bits
+// `parcel`, following the length encoding of the RISC-V spec:
+//
+// xxxxxxxxxxxxxxaa (aa != 11)        16-bit
+// xxxxxxxxxxxbbb11 (bbb != 111)      32-bit
+// xxxxxxxxxx011111                   48-bit
+// xxxxxxxxx0111111                   64-bit
+//
+// None for 80-bit and longer encodings, which are not supported.
+pub fn encoding_length(parcel: u16) -> Option<u8> {
+    if parcel & 0x3 != 0x3 {
+        Some(2)
+    } else if parcel & 0x1c != 0x1c {
+        Some(4)
+    } else if parcel & 0x3f == 0x1f {
+        Some(6)
+    } else if parcel & 0x7f == 0x3f {
+        Some(8)
+    } else {
+        None
     }
 }
 
-pub fn build_decoder<R: Register>(isa: u8, version: u32) -> Decoder {
+pub fn build_decoder<R: Register>(isa: u64, version: u32) -> Decoder {
     let mut decoder = Decoder::new(isa & ISA_MOP != 0, version);
-    decoder.add_instruction_factory(rvc::factory::<R>);
+    decoder.add_compressed_instruction_factory(rvc::factory::<R>);
     decoder.add_instruction_factory(i::factory::<R>);
     decoder.add_instruction_factory(m::factory::<R>);
     if isa & ISA_B != 0 {
@@ -872,5 +1191,169 @@ pub fn build_decoder<R: Register>(isa: u8, version: u32) -> Decoder {
     if isa & ISA_A != 0 {
         decoder.add_instruction_factory(a::factory::<R>);
     }
+    if isa & ISA_PRIV != 0 {
+        decoder.add_instruction_factory(privileged::factory::<R>);
+    } else if isa & ISA_ZICSR != 0 {
+        decoder.add_instruction_factory(privileged::zicsr_factory::<R>);
+    }
+    if isa & ISA_F != 0 {
+        decoder.add_instruction_factory(rvf::factory::<R>);
+    }
+    if isa & ISA_D != 0 {
+        decoder.add_instruction_factory(rvf::factory_d::<R>);
+    }
+    #[cfg(feature = "rvv")]
+    if isa & crate::ISA_V != 0 {
+        decoder.add_instruction_factory(crate::instructions::rvv::factory::<R>);
+    }
     decoder
 }
+
+// Operands of a decoded instruction. Immediates are sign extended, and
+// branch/jump offsets are relative to the PC of the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    R {
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+    },
+    I {
+        rd: usize,
+        rs1: usize,
+        imm: i32,
+    },
+    S {
+        rs1: usize,
+        rs2: usize,
+        imm: i32,
+    },
+    U {
+        rd: usize,
+        imm: i32,
+    },
+    R4 {
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        rs3: usize,
+    },
+    R5 {
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        rs3: usize,
+        rs4: usize,
+    },
+}
+
+// A decoded instruction in a structured form. Unlike the packed Instruction
+// type used internally, whose layout is only stable within a
+// INSTRUCTION_SCHEMA_VERSION, fields here follow semver: existing fields are never changed or removed in a minor
+// release. Opcode values are the OP_* constants in ckb_vm_definitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub pc: u64,
+    pub opcode: insts::InstructionOpcode,
+    pub name: &'static str,
+    // Length in bytes, 2 for RVC instructions and 4 otherwise.
+    pub length: u8,
+    pub operands: Operands,
+}
+
+impl DecodedInstruction {
+    pub fn from_instruction(pc: u64, instruction: Instruction) -> Result<Self, Error> {
+        let operands = match TaggedInstruction::try_from(instruction)? {
+            TaggedInstruction::Rtype(i) => Operands::R {
+                rd: i.rd(),
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+            },
+            TaggedInstruction::Itype(i) => Operands::I {
+                rd: i.rd(),
+                rs1: i.rs1(),
+                imm: i.immediate_s(),
+            },
+            TaggedInstruction::Stype(i) => Operands::S {
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+                imm: i.immediate_s(),
+            },
+            TaggedInstruction::Utype(i) => Operands::U {
+                rd: i.rd(),
+                imm: i.immediate_s(),
+            },
+            TaggedInstruction::R4type(i) => Operands::R4 {
+                rd: i.rd(),
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+                rs3: i.rs3(),
+            },
+            TaggedInstruction::R5type(i) => Operands::R5 {
+                rd: i.rd(),
+                rs1: i.rs1(),
+                rs2: i.rs2(),
+                rs3: i.rs3(),
+                rs4: i.rs4(),
+            },
+        };
+        let opcode = extract_opcode(instruction);
+        Ok(DecodedInstruction {
+            pc,
+            opcode,
+            name: instruction_opcode_name(opcode),
+            length: instruction_length(instruction),
+            operands,
+        })
+    }
+}
+
+// InstructionDecoder is the stable entry point for tools analyzing RISC-V
+// code, it decodes raw bytes rather than reading from a machine's memory.
+// Macro-op fusion is an internal optimization of the VM, hence ISA_MOP is
+// ignored here, each decoded instruction maps to exactly one RISC-V
+// instruction.
+//
+// let decoder = InstructionDecoder::new::<u64>(ISA_IMC | ISA_B, VERSION2);
+// let instruction = decoder.decode(&code[offset..], pc)?;
+pub struct InstructionDecoder {
+    decoder: Decoder,
+}
+
+impl InstructionDecoder {
+    pub fn new<R: Register>(isa: u64, version: u32) -> Self {
+        Self {
+            decoder: build_decoder::<R>(isa & !ISA_MOP, version),
+        }
+    }
+
+    // Decodes the instruction at the start of `bytes`, `pc` is the address of
+    // the first byte. Only the bytes needed by the instruction are consumed,
+    // MemOutOfBound is returned if `bytes` is too short.
+    pub fn decode(&self, bytes: &[u8], pc: u64) -> Result<DecodedInstruction, Error> {
+        let instruction = self.decoder.decode_bytes(bytes, pc)?;
+        DecodedInstruction::from_instruction(pc, instruction)
+    }
+
+    // Decodes all instructions in `bytes` sequentially, stopping at the first
+    // error.
+    pub fn decode_all<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        pc: u64,
+    ) -> impl Iterator<Item = Result<DecodedInstruction, Error>> + 'a {
+        let mut offset = 0;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || offset >= bytes.len() {
+                return None;
+            }
+            let result = self.decode(&bytes[offset..], pc + offset as u64);
+            match &result {
+                Ok(i) => offset += i.length as usize,
+                Err(_) => failed = true,
+            }
+            Some(result)
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use super::{load_part, store_part, IRQ_MACHINE_SOFTWARE, IRQ_MACHINE_TIMER};
+use crate::memory::mmio::MmioDevice;
+use crate::Error;
+
+// Usual base address of the CLINT on RISC-V platforms.
+pub const CLINT_BASE: u64 = 0x0200_0000;
+pub const CLINT_SIZE: u64 = 0x1_0000;
+// Offsets of the registers in the window, with the SiFive layout.
+pub const CLINT_MSIP: u64 = 0x0;
+pub const CLINT_MTIMECMP: u64 = 0x4000;
+pub const CLINT_MTIME: u64 = 0xbff8;
+
+// Core local interruptor of a single hart. mtime is derived from the cycles
+// consumed by the machine, so timer interrupts are deterministic. Devices are
+// advanced at basic block boundaries, mtime seen by the guest and timer
+// interrupts may hence lag behind by up to a basic block.
+pub struct Clint {
+    cycles_per_tick: u64,
+    // Ticks elapsed according to the cycles of the machine.
+    ticks: u64,
+    // Set by guest writes to mtime.
+    mtime_offset: u64,
+    mtimecmp: u64,
+    msip: u64,
+}
+
+impl Clint {
+    // mtime is incremented every `cycles_per_tick` cycles.
+    pub fn new(cycles_per_tick: u64) -> Self {
+        Self {
+            cycles_per_tick: cycles_per_tick.max(1),
+            ticks: 0,
+            mtime_offset: 0,
+            mtimecmp: u64::MAX,
+            msip: 0,
+        }
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.ticks.wrapping_add(self.mtime_offset)
+    }
+
+    pub fn mtimecmp(&self) -> u64 {
+        self.mtimecmp
+    }
+
+    // Returns the register holding `offset` together with the offset in it,
+    // reserved offsets yield None.
+    fn register(offset: u64, size: u8) -> Result<Option<(u64, u64)>, Error> {
+        let (start, width) = if offset < CLINT_MSIP + 4 {
+            (CLINT_MSIP, 4)
+        } else if (CLINT_MTIMECMP..CLINT_MTIMECMP + 8).contains(&offset) {
+            (CLINT_MTIMECMP, 8)
+        } else if (CLINT_MTIME..CLINT_MTIME + 8).contains(&offset) {
+            (CLINT_MTIME, 8)
+        } else {
+            return Ok(None);
+        };
+        if offset - start + u64::from(size) > width {
+            return Err(Error::MemUnalignedAccess);
+        }
+        Ok(Some((start, offset - start)))
+    }
+}
+
+impl MmioDevice for Clint {
+    fn size(&self) -> u64 {
+        CLINT_SIZE
+    }
+
+    fn load(&mut self, offset: u64, size: u8) -> Result<u64, Error> {
+        let value = match Self::register(offset, size)? {
+            Some((CLINT_MSIP, part)) => load_part(self.msip, part, size),
+            Some((CLINT_MTIMECMP, part)) => load_part(self.mtimecmp, part, size),
+            Some((_, part)) => load_part(self.mtime(), part, size),
+            None => 0,
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, size: u8, value: u64) -> Result<(), Error> {
+        match Self::register(offset, size)? {
+            Some((CLINT_MSIP, part)) => {
+                store_part(&mut self.msip, part, size, value);
+                // Only the lowest bit of msip is writable.
+                self.msip &= 1;
+            }
+            Some((CLINT_MTIMECMP, part)) => store_part(&mut self.mtimecmp, part, size, value),
+            Some((_, part)) => {
+                let mut mtime = self.mtime();
+                store_part(&mut mtime, part, size, value);
+                self.mtime_offset = mtime.wrapping_sub(self.ticks);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.ticks = cycles / self.cycles_per_tick;
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        let mut pending = 0;
+        if self.msip != 0 {
+            pending |= 1 << IRQ_MACHINE_SOFTWARE;
+        }
+        if self.mtime() >= self.mtimecmp {
+            pending |= 1 << IRQ_MACHINE_TIMER;
+        }
+        pending
+    }
+}
@@ -0,0 +1,51 @@
+// Memory mapped devices of RISC-V platforms, attached to the guest through
+// MmioMemory. Their interrupts are handed to the TrapHandler of the machine.
+pub mod clint;
+pub mod plic;
+
+// Interrupt numbers, which are also the bits of pending interrupt masks.
+pub const IRQ_MACHINE_SOFTWARE: u64 = 3;
+pub const IRQ_MACHINE_TIMER: u64 = 7;
+pub const IRQ_MACHINE_EXTERNAL: u64 = 11;
+
+// Returns the interrupt to take among the `pending` ones, in the priority
+// order of the privileged spec: external, software, then timer interrupts.
+// Other interrupts come after them, lowest number first.
+pub fn highest_priority_interrupt(pending: u64) -> Option<u64> {
+    for irq in [
+        IRQ_MACHINE_EXTERNAL,
+        IRQ_MACHINE_SOFTWARE,
+        IRQ_MACHINE_TIMER,
+    ] {
+        if pending & (1 << irq) != 0 {
+            return Some(irq);
+        }
+    }
+    if pending == 0 {
+        None
+    } else {
+        Some(u64::from(pending.trailing_zeros()))
+    }
+}
+
+// Reads `size` bytes at byte `offset` of a 64-bit device register.
+pub(crate) fn load_part(register: u64, offset: u64, size: u8) -> u64 {
+    let value = register >> (offset * 8);
+    if size >= 8 {
+        value
+    } else {
+        value & ((1 << (u64::from(size) * 8)) - 1)
+    }
+}
+
+// Writes the lowest `size` bytes of `value` at byte `offset` of a 64-bit
+// device register.
+pub(crate) fn store_part(register: &mut u64, offset: u64, size: u8, value: u64) {
+    let mask = if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (u64::from(size) * 8)) - 1
+    };
+    let shift = offset * 8;
+    *register = (*register & !(mask << shift)) | ((value & mask) << shift);
+}
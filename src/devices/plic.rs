@@ -0,0 +1,160 @@
+use super::{load_part, store_part, IRQ_MACHINE_EXTERNAL};
+use crate::memory::mmio::{MmioDevice, MAX_INTERRUPT_SOURCE};
+use crate::Error;
+
+// Usual base address of the PLIC on RISC-V platforms.
+pub const PLIC_BASE: u64 = 0x0c00_0000;
+pub const PLIC_SIZE: u64 = 0x400_0000;
+// Offsets of the registers in the window, with the SiFive layout. Only the
+// machine mode context of hart 0 is modeled.
+pub const PLIC_PRIORITY: u64 = 0x0;
+pub const PLIC_PENDING: u64 = 0x1000;
+pub const PLIC_ENABLE: u64 = 0x2000;
+pub const PLIC_THRESHOLD: u64 = 0x20_0000;
+pub const PLIC_CLAIM: u64 = 0x20_0004;
+
+// Platform level interrupt controller, aggregating the interrupt lines of
+// devices attached with MmioMemory::attach_source into the machine external
+// interrupt. Lines are level triggered: a source becomes pending while its
+// line is high, unless it was claimed and not completed yet.
+pub struct Plic {
+    sources: u32,
+    // Indexed by source, source 0 does not exist.
+    priorities: Vec<u32>,
+    pending: u64,
+    enabled: u64,
+    // Claimed sources whose handling is not completed yet.
+    claimed: u64,
+    threshold: u32,
+}
+
+impl Plic {
+    // Creates a PLIC with sources 1 to `sources`, at most
+    // MAX_INTERRUPT_SOURCE.
+    pub fn new(sources: u32) -> Self {
+        let sources = sources.min(MAX_INTERRUPT_SOURCE);
+        Self {
+            sources,
+            priorities: vec![0; sources as usize + 1],
+            pending: 0,
+            enabled: 0,
+            claimed: 0,
+            threshold: 0,
+        }
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    // Returns the pending and enabled source with the highest priority above
+    // the threshold, the lowest source number wins ties.
+    fn best_source(&self) -> Option<u32> {
+        let mut best: Option<(u32, u32)> = None;
+        for source in 1..=self.sources {
+            let bit = 1 << source;
+            let priority = self.priorities[source as usize];
+            if self.pending & self.enabled & bit != 0
+                && priority > self.threshold
+                && best.map_or(true, |(_, p)| priority > p)
+            {
+                best = Some((source, priority));
+            }
+        }
+        best.map(|(source, _)| source)
+    }
+
+    fn claim(&mut self) -> u32 {
+        match self.best_source() {
+            Some(source) => {
+                self.pending &= !(1 << source);
+                self.claimed |= 1 << source;
+                source
+            }
+            None => 0,
+        }
+    }
+
+    // Reads the 32-bit register at `word`, the offset aligned down to 4.
+    fn load_word(&mut self, word: u64) -> u32 {
+        match word {
+            w if w < PLIC_PENDING => self.priorities.get((w / 4) as usize).copied().unwrap_or(0),
+            w if (PLIC_PENDING..PLIC_PENDING + 8).contains(&w) => {
+                (self.pending >> ((w - PLIC_PENDING) * 8)) as u32
+            }
+            w if (PLIC_ENABLE..PLIC_ENABLE + 8).contains(&w) => {
+                (self.enabled >> ((w - PLIC_ENABLE) * 8)) as u32
+            }
+            PLIC_THRESHOLD => self.threshold,
+            PLIC_CLAIM => self.claim(),
+            _ => 0,
+        }
+    }
+
+    fn store_word(&mut self, word: u64, value: u32) {
+        // Bits of existing sources.
+        let sources = (u64::MAX >> (63 - self.sources)) & !1;
+        match word {
+            w if w < PLIC_PENDING => {
+                if let Some(priority) = self.priorities.get_mut((w / 4) as usize) {
+                    if w != 0 {
+                        *priority = value;
+                    }
+                }
+            }
+            w if (PLIC_ENABLE..PLIC_ENABLE + 8).contains(&w) => {
+                let mut enabled = self.enabled;
+                store_part(&mut enabled, w - PLIC_ENABLE, 4, u64::from(value));
+                self.enabled = enabled & sources;
+            }
+            PLIC_THRESHOLD => self.threshold = value,
+            PLIC_CLAIM if value <= self.sources => self.claimed &= !(1 << value),
+            _ => (),
+        }
+    }
+
+    fn word(offset: u64, size: u8) -> Result<(u64, u64), Error> {
+        let word = offset & !3;
+        if offset - word + u64::from(size) > 4 {
+            return Err(Error::MemUnalignedAccess);
+        }
+        Ok((word, offset - word))
+    }
+}
+
+impl MmioDevice for Plic {
+    fn size(&self) -> u64 {
+        PLIC_SIZE
+    }
+
+    fn load(&mut self, offset: u64, size: u8) -> Result<u64, Error> {
+        let (word, part) = Self::word(offset, size)?;
+        Ok(load_part(u64::from(self.load_word(word)), part, size))
+    }
+
+    fn store(&mut self, offset: u64, size: u8, value: u64) -> Result<(), Error> {
+        let (word, part) = Self::word(offset, size)?;
+        // Claim reads have side effects, partial writes only merge with
+        // plain registers.
+        let mut current = if word == PLIC_CLAIM {
+            0
+        } else {
+            u64::from(self.load_word(word))
+        };
+        store_part(&mut current, part, size, value);
+        self.store_word(word, current as u32);
+        Ok(())
+    }
+
+    fn route_interrupts(&mut self, lines: u64) {
+        self.pending |= lines & !self.claimed & !1;
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        if self.best_source().is_some() {
+            1 << IRQ_MACHINE_EXTERNAL
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use bytes::Bytes;
+use scroll::Pread;
+
+use crate::machine::elf_adaptor::{self, ProgramHeader, PT_LOAD};
+use crate::machine::{SupportMachine, VERSION1};
+use crate::memory::lazy::{PageContent, PageFaultHandler};
+use crate::memory::{round_page_down, round_page_up, Memory};
+use crate::{Error, Register, RISCV_PAGESIZE};
+
+// Largest ELF header, the one of ELF64.
+const ELF_HEADER_LENGTH: u64 = 64;
+
+// ElfLoader loads programs from a seekable reader, e.g. a file, instead of a
+// Bytes holding the whole program. Only the ELF header and the program
+// headers are read upfront, the PT_LOAD segments are read when they are
+// mapped, so section headers, symbols and debug information stay on disk.
+// Programs are parsed and mapped like load_elf does for the machine
+// version, so the same program loads the same way:
+//
+// let mut loader = ElfLoader::new(File::open(path)?, version, 64)?;
+// loader.load(&mut machine, true)?;
+// machine.initialize_stack(&args, stack_start, stack_size)?;
+//
+// Segments can also be mapped on demand: an ElfLoader is a PageFaultHandler
+// populating the pages of a LazyMemory from the reader on first access,
+// other pages read as zero:
+//
+// machine.update_pc(loader.entry());
+// machine.commit_pc();
+// machine.memory_mut().set_fault_handler(Box::new(loader));
+//
+// Like load_elf, ElfLoader bypasses the load policy of DefaultMachine.
+pub struct ElfLoader<R> {
+    reader: R,
+    version: u32,
+    entry: u64,
+    // Size of the program.
+    length: u64,
+    program_headers: Vec<ProgramHeader>,
+}
+
+impl<R: Read + Seek> ElfLoader<R> {
+    // Reads the headers of a program for machines of `version` with `bits`
+    // wide registers.
+    pub fn new(mut reader: R, version: u32, bits: u8) -> Result<Self, Error> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        let header = read_at(&mut reader, 0, ELF_HEADER_LENGTH.min(length))?;
+        // We did not use Elf::parse here to avoid triggering potential bugs
+        // in goblin, see load_elf.
+        let (entry, phoff, phnum, phentsize, is_big) = if version < VERSION1 {
+            use goblin_v023::elf::Header;
+            let header = header.pread::<Header>(0)?;
+            let container = header.container().map_err(|_e| Error::ElfBits)?;
+            (
+                header.e_entry,
+                header.e_phoff,
+                header.e_phnum,
+                header.e_phentsize,
+                container.is_big(),
+            )
+        } else {
+            use goblin_v040::elf::Header;
+            let header = header.pread::<Header>(0)?;
+            let container = header.container().map_err(|_e| Error::ElfBits)?;
+            (
+                header.e_entry,
+                header.e_phoff,
+                header.e_phnum,
+                header.e_phentsize,
+                container.is_big(),
+            )
+        };
+        if bits != if is_big { 64 } else { 32 } {
+            return Err(Error::ElfBits);
+        }
+        let table_length = u64::from(phnum) * u64::from(phentsize);
+        if phoff
+            .checked_add(table_length)
+            .map_or(true, |end| end > length)
+        {
+            return Err(Error::ElfParseError(String::from(
+                "program headers are out of the file",
+            )));
+        }
+        // Program headers are parsed from a copy of the header followed by
+        // the table, for goblin to find the context of the program.
+        let mut program = header;
+        let table_offset = program.len();
+        program.extend_from_slice(&read_at(&mut reader, phoff, table_length)?);
+        let program_headers = if version < VERSION1 {
+            use goblin_v023::container::Ctx;
+            use goblin_v023::elf::{program_header::ProgramHeader as GoblinProgramHeader, Header};
+            let header = program.pread::<Header>(0)?;
+            let container = header.container().map_err(|_e| Error::ElfBits)?;
+            let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
+            let ctx = Ctx::new(container, endianness);
+            GoblinProgramHeader::parse(&program, table_offset, phnum as usize, ctx)?
+                .iter()
+                .map(ProgramHeader::from_v0)
+                .collect()
+        } else {
+            use goblin_v040::container::Ctx;
+            use goblin_v040::elf::{program_header::ProgramHeader as GoblinProgramHeader, Header};
+            let header = program.pread::<Header>(0)?;
+            let container = header.container().map_err(|_e| Error::ElfBits)?;
+            let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
+            let ctx = Ctx::new(container, endianness);
+            GoblinProgramHeader::parse(&program, table_offset, phnum as usize, ctx)?
+                .iter()
+                .map(ProgramHeader::from_v1)
+                .collect()
+        };
+        Ok(Self {
+            reader,
+            version,
+            entry,
+            length,
+            program_headers,
+        })
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    // Returns (address, size) of the PT_LOAD segments.
+    pub fn load_segments(&self) -> Vec<(u64, u64)> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.p_type == PT_LOAD)
+            .map(|header| (header.p_vaddr, header.p_memsz))
+            .collect()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // Maps all PT_LOAD segments into the memory of `machine`, reading one
+    // segment at a time, and returns the number of bytes read like
+    // load_elf.
+    pub fn load<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        update_pc: bool,
+    ) -> Result<u64, Error> {
+        let mut bytes: u64 = 0;
+        for header in self.program_headers.iter().filter(|h| h.p_type == PT_LOAD) {
+            let aligned_start = round_page_down(header.p_vaddr);
+            let padding_start = header.p_vaddr.wrapping_sub(aligned_start);
+            let size = round_page_up(header.p_memsz.wrapping_add(padding_start));
+            let (slice_start, slice_end) = file_range(header, self.length)?;
+            let data = read_at(&mut self.reader, slice_start, slice_end - slice_start)?;
+            machine.memory_mut().init_pages(
+                aligned_start,
+                size,
+                elf_adaptor::convert_flags(header.p_flags, self.version < VERSION1)?,
+                Some(Bytes::from(data)),
+                padding_start,
+            )?;
+            if self.version < VERSION1 {
+                machine
+                    .memory_mut()
+                    .store_byte(aligned_start, padding_start, 0)?;
+            }
+            bytes = bytes.checked_add(slice_end - slice_start).ok_or_else(|| {
+                Error::Unexpected(String::from("The bytes count overflowed on loading elf"))
+            })?;
+        }
+        if update_pc {
+            machine.update_pc(M::REG::from_u64(self.entry));
+            machine.commit_pc();
+        }
+        Ok(bytes)
+    }
+
+    // Content of the page at `page_addr`. Where segments share a page, the
+    // flags of the last one apply, as when load overwrites them.
+    fn page(&mut self, page_addr: u64) -> Result<PageContent, Error> {
+        let page_end = page_addr.saturating_add(RISCV_PAGESIZE as u64);
+        let mut data = vec![];
+        let mut flags = 0;
+        for header in self.program_headers.iter().filter(|h| h.p_type == PT_LOAD) {
+            let aligned_start = round_page_down(header.p_vaddr);
+            let padding_start = header.p_vaddr.wrapping_sub(aligned_start);
+            let segment_end = aligned_start
+                .saturating_add(round_page_up(header.p_memsz.wrapping_add(padding_start)));
+            if page_addr < aligned_start || page_addr >= segment_end {
+                continue;
+            }
+            flags = elf_adaptor::convert_flags(header.p_flags, self.version < VERSION1)?;
+            // Bytes of the page read from the program.
+            let (slice_start, slice_end) = file_range(header, self.length)?;
+            let start = header.p_vaddr.max(page_addr);
+            let end = header
+                .p_vaddr
+                .saturating_add(slice_end - slice_start)
+                .min(page_end);
+            if start >= end {
+                continue;
+            }
+            let chunk = read_at(
+                &mut self.reader,
+                slice_start + (start - header.p_vaddr),
+                end - start,
+            )?;
+            let offset = (start - page_addr) as usize;
+            if data.len() < offset + chunk.len() {
+                data.resize(offset + chunk.len(), 0);
+            }
+            data[offset..offset + chunk.len()].copy_from_slice(&chunk);
+        }
+        Ok(PageContent {
+            flags,
+            data: Bytes::from(data),
+        })
+    }
+}
+
+impl<R: Read + Seek + Send + Sync> PageFaultHandler for ElfLoader<R> {
+    fn fault(&mut self, page_addr: u64) -> Result<Option<PageContent>, Error> {
+        self.page(page_addr).map(Some)
+    }
+}
+
+// Range of the program of `length` bytes holding the data of segment
+// `header`.
+fn file_range(header: &ProgramHeader, length: u64) -> Result<(u64, u64), Error> {
+    let slice_start = header.p_offset;
+    let slice_end = header.p_offset.wrapping_add(header.p_filesz);
+    if slice_start > slice_end || slice_end > length {
+        return Err(Error::ElfSegmentAddrOrSizeError);
+    }
+    Ok((slice_start, slice_end))
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,129 @@
+// Writes minimal ELF executables, for tests and tools generating code on the
+// fly. The produced file contains a single read-only executable segment with
+// the code, loaded at DEFAULT_LOAD_ADDRESS, optionally a PT_NOTE segment with
+// a build ID, and no section headers.
+use crate::{instructions::Register, Bytes};
+
+pub const DEFAULT_LOAD_ADDRESS: u64 = 0x10000;
+
+const EM_RISCV: u16 = 243;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_R_X: u32 = 0b101;
+const PF_R: u32 = 0b100;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// Returns an ELF for machines of register type R, whose entry point is the
+// first byte of `code`.
+pub fn minimal_elf<R: Register>(code: &[u8]) -> Bytes {
+    build_elf::<R>(code, None)
+}
+
+// Same as minimal_elf, with a GNU build ID note in a PT_NOTE segment, as
+// left by linkers in stripped programs.
+pub fn minimal_elf_with_build_id<R: Register>(code: &[u8], build_id: &[u8]) -> Bytes {
+    build_elf::<R>(code, Some(build_id))
+}
+
+fn build_elf<R: Register>(code: &[u8], build_id: Option<&[u8]>) -> Bytes {
+    let rv64 = R::BITS == 64;
+    let (header_size, program_header_size, section_header_size): (u16, u16, u16) =
+        if rv64 { (64, 56, 64) } else { (52, 32, 40) };
+    let program_headers: u16 = if build_id.is_some() { 2 } else { 1 };
+    let code_offset = u64::from(header_size + program_header_size * program_headers);
+    // The note follows the code, aligned to 4 bytes.
+    let note = build_id.map(|build_id| {
+        let mut note = vec![];
+        note.extend_from_slice(&4u32.to_le_bytes());
+        note.extend_from_slice(&(build_id.len() as u32).to_le_bytes());
+        note.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+        note.extend_from_slice(b"GNU\0");
+        note.extend_from_slice(build_id);
+        note.resize((note.len() + 3) & !3, 0);
+        note
+    });
+    let note_offset = (code_offset + code.len() as u64 + 3) & !3;
+    let file_size = match &note {
+        Some(note) => note_offset + note.len() as u64,
+        None => code_offset + code.len() as u64,
+    };
+    let entry = DEFAULT_LOAD_ADDRESS + code_offset;
+
+    let mut elf = Vec::with_capacity(file_size as usize);
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F']);
+    // Class, little endian, version 1, System V ABI
+    elf.extend_from_slice(&[if rv64 { 2 } else { 1 }, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&ET_EXEC.to_le_bytes());
+    elf.extend_from_slice(&EM_RISCV.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    let push_word = |elf: &mut Vec<u8>, value: u64| {
+        if rv64 {
+            elf.extend_from_slice(&value.to_le_bytes());
+        } else {
+            elf.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+    };
+    // Entry, program header offset, section header offset
+    push_word(&mut elf, entry);
+    push_word(&mut elf, u64::from(header_size));
+    push_word(&mut elf, 0);
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    for value in [
+        header_size,
+        program_header_size,
+        program_headers,
+        section_header_size,
+        0,
+        0,
+    ] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    elf.extend_from_slice(&PT_LOAD.to_le_bytes());
+    if rv64 {
+        elf.extend_from_slice(&PF_R_X.to_le_bytes());
+    }
+    // Offset, virtual address, physical address, file size, memory size
+    for value in [
+        0,
+        DEFAULT_LOAD_ADDRESS,
+        DEFAULT_LOAD_ADDRESS,
+        file_size,
+        file_size,
+    ] {
+        push_word(&mut elf, value);
+    }
+    if !rv64 {
+        elf.extend_from_slice(&PF_R_X.to_le_bytes());
+    }
+    push_word(&mut elf, 0x1000);
+
+    if let Some(note) = &note {
+        elf.extend_from_slice(&PT_NOTE.to_le_bytes());
+        if rv64 {
+            elf.extend_from_slice(&PF_R.to_le_bytes());
+        }
+        for value in [
+            note_offset,
+            DEFAULT_LOAD_ADDRESS + note_offset,
+            DEFAULT_LOAD_ADDRESS + note_offset,
+            note.len() as u64,
+            note.len() as u64,
+        ] {
+            push_word(&mut elf, value);
+        }
+        if !rv64 {
+            elf.extend_from_slice(&PF_R.to_le_bytes());
+        }
+        push_word(&mut elf, 4);
+    }
+
+    elf.extend_from_slice(code);
+    if let Some(note) = &note {
+        elf.resize(note_offset as usize, 0);
+        elf.extend_from_slice(note);
+    }
+    Bytes::from(elf)
+}
@@ -1,3 +1,5 @@
+use ckb_vm_definitions::instructions::{try_instruction_opcode_name, InstructionOpcode};
+
 #[derive(Debug, PartialEq, Clone, Eq, Display)]
 pub enum Error {
     #[display(fmt = "asm error: {}", "_0")]
@@ -6,6 +8,15 @@ pub enum Error {
     CyclesExceeded,
     #[display(fmt = "cycles error: overflow")]
     CyclesOverflow,
+    // An error of a failing instruction along with where it happened, see
+    // DefaultMachineBuilder::error_context.
+    #[display(fmt = "{} at {}", "error", "context")]
+    Context {
+        error: Box<Error>,
+        context: ErrorContext,
+    },
+    #[display(fmt = "denied instruction pc=0x{:x} opcode=0x{:x}", "pc", "opcode")]
+    DeniedInstruction { pc: u64, opcode: u16 },
     #[display(fmt = "elf error: bits")]
     ElfBits,
     #[display(fmt = "elf error: {}", "_0")]
@@ -20,6 +31,17 @@ pub enum Error {
     // used in this project.
     #[display(fmt = "external error: {}", "_0")]
     External(String),
+    #[display(
+        fmt = "infinite loop pc=0x{:x}-0x{:x} iterations={}",
+        "start",
+        "end",
+        "iterations"
+    )]
+    InfiniteLoop {
+        start: u64,
+        end: u64,
+        iterations: u64,
+    },
     #[display(fmt = "invalid syscall {}", "_0")]
     InvalidEcall(u64),
     #[display(
@@ -28,6 +50,8 @@ pub enum Error {
         "instruction"
     )]
     InvalidInstruction { pc: u64, instruction: u32 },
+    #[display(fmt = "invalid isa 0x{:x}", "_0")]
+    InvalidIsa(u64),
     #[display(fmt = "invalid operand {}", "_0")]
     InvalidOp(u16),
     #[display(fmt = "invalid version")]
@@ -43,18 +67,109 @@ pub enum Error {
     MemOutOfStack,
     #[display(fmt = "memory error: unaligned page access")]
     MemPageUnalignedAccess,
+    #[display(fmt = "memory error: unaligned access")]
+    MemUnalignedAccess,
     #[display(fmt = "memory error: write on executable page")]
     MemWriteOnExecutablePage,
     #[display(fmt = "memory error: write on freezed page")]
     MemWriteOnFreezedPage,
+    // More bytes written than allowed, see MeteredMemory.
+    #[display(fmt = "memory error: write limit exceeded")]
+    MemWriteLimitExceeded,
+    // More pages touched than allowed, see QuotaMemory.
+    #[display(fmt = "memory error: page quota exceeded")]
+    MemQuotaExceeded,
+    #[display(fmt = "program 0x{} is not allowed by the load policy", "_0")]
+    ProgramNotAllowed(String),
+    // Returned by a syscall which can't complete yet, see Syscalls::ecall.
+    #[display(fmt = "syscall {} should be retried", "_0")]
+    SyscallRetry(u64),
     #[display(fmt = "unexpected error")]
     Unexpected(String),
     #[display(fmt = "unimplemented")]
     Unimplemented,
+    // An access hit a watchpoint, see WatchpointMemory.
+    #[display(fmt = "watchpoint hit pc=0x{:x} addr=0x{:x}", "pc", "addr")]
+    Watchpoint { addr: u64, pc: u64 },
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    // Returns the error without its context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { error, .. } => error.root(),
+            _ => self,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    // Whether the error takes a context when an instruction fails with it.
+    // Errors which already tell where they happened don't, neither do the
+    // ones suspending a run, since callers resume on them.
+    pub fn takes_context(&self) -> bool {
+        !matches!(
+            self,
+            Error::Context { .. }
+                | Error::CyclesExceeded
+                | Error::DeniedInstruction { .. }
+                | Error::InfiniteLoop { .. }
+                | Error::InvalidInstruction { .. }
+                | Error::SyscallRetry(_)
+                | Error::Watchpoint { .. }
+        )
+    }
+
+    pub fn with_context(self, context: ErrorContext) -> Error {
+        if !self.takes_context() {
+            return self;
+        }
+        Error::Context {
+            error: Box::new(self),
+            context,
+        }
+    }
+}
+
+// Where an instruction failed.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct ErrorContext {
+    pub pc: u64,
+    pub opcode: InstructionOpcode,
+    // Memory address accessed by the instruction, if any.
+    pub address: Option<u64>,
+    // Cycles consumed by the run, including the failing instruction.
+    pub cycles: u64,
+}
+
+impl ErrorContext {
+    pub fn opcode_name(&self) -> &'static str {
+        try_instruction_opcode_name(self.opcode).unwrap_or("UNKNOWN")
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "pc=0x{:x} opcode={}",
+            self.pc,
+            self.opcode_name().to_lowercase()
+        )?;
+        if let Some(address) = self.address {
+            write!(f, " address=0x{:x}", address)?;
+        }
+        write!(f, " cycles={}", self.cycles)
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
         Error::IO {
@@ -64,12 +179,14 @@ impl From<std::io::Error> for Error {
     }
 }
 
+#[cfg(feature = "elf")]
 impl From<goblin_v023::error::Error> for Error {
     fn from(error: goblin_v023::error::Error) -> Self {
         Error::ElfParseError(error.to_string())
     }
 }
 
+#[cfg(feature = "elf")]
 impl From<goblin_v040::error::Error> for Error {
     fn from(error: goblin_v040::error::Error) -> Self {
         Error::ElfParseError(error.to_string())
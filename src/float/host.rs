@@ -0,0 +1,273 @@
+use super::{
+    soft, FloatBackend, Precision, RoundingMode, FLAG_DIVIDE_BY_ZERO, FLAG_INEXACT, FLAG_INVALID,
+    FLAG_OVERFLOW, FLAG_UNDERFLOW,
+};
+
+// HostFloat computes arithmetic with the host FPU. Rust only rounds to
+// nearest even, other rounding modes fall back to SoftFloat. Results are the
+// same as SoftFloat's, flags are derived from the inputs and the exact
+// residual of the operation, which differs from SoftFloat in rare cases:
+//
+// * inexact is approximate for fma, whose residual isn't representable and
+//   is missed for tiny operands;
+// * underflow can be missed for results rounded up to the smallest normal
+//   value.
+//
+// DiffFloat reports such cases.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostFloat;
+
+trait HostFp: Copy + PartialEq + PartialOrd {
+    const PRECISION: Precision;
+    const ZERO: Self;
+    const MIN_POSITIVE: Self;
+    // Power of two residuals of results below 1 / SCALE are computed at, so
+    // they don't underflow.
+    const SCALE: Self;
+
+    fn from_raw(bits: u64) -> Self;
+    fn to_raw(self) -> u64;
+    fn is_nan(self) -> bool;
+    fn is_finite(self) -> bool;
+    fn abs(self) -> Self;
+    fn neg(self) -> Self;
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn mul(self, other: Self) -> Self;
+    fn div(self, other: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+}
+
+macro_rules! host_fp {
+    ($t:ty, $precision:expr, $bits:ty, $scale:expr) => {
+        impl HostFp for $t {
+            const PRECISION: Precision = $precision;
+            const ZERO: Self = 0.0;
+            const MIN_POSITIVE: Self = <$t>::MIN_POSITIVE;
+            const SCALE: Self = (1u128 << $scale) as $t;
+
+            fn from_raw(bits: u64) -> Self {
+                <$t>::from_bits(bits as $bits)
+            }
+
+            fn to_raw(self) -> u64 {
+                u64::from(self.to_bits())
+            }
+
+            fn is_nan(self) -> bool {
+                <$t>::is_nan(self)
+            }
+
+            fn is_finite(self) -> bool {
+                <$t>::is_finite(self)
+            }
+
+            fn abs(self) -> Self {
+                <$t>::abs(self)
+            }
+
+            fn neg(self) -> Self {
+                -self
+            }
+
+            fn add(self, other: Self) -> Self {
+                self + other
+            }
+
+            fn sub(self, other: Self) -> Self {
+                self - other
+            }
+
+            fn mul(self, other: Self) -> Self {
+                self * other
+            }
+
+            fn div(self, other: Self) -> Self {
+                self / other
+            }
+
+            fn sqrt(self) -> Self {
+                <$t>::sqrt(self)
+            }
+
+            fn mul_add(self, a: Self, b: Self) -> Self {
+                <$t>::mul_add(self, a, b)
+            }
+        }
+    };
+}
+
+host_fp!(f32, Precision::Single, u32, 60);
+host_fp!(f64, Precision::Double, u64, 100);
+
+// Packs a host result, raising the flags the host FPU would have accrued.
+fn finish<F: HostFp>(operands: &[F], result: F, inexact: bool, flags: &mut u8) -> u64 {
+    let p = F::PRECISION;
+    if result.is_nan() {
+        let signaling = operands
+            .iter()
+            .any(|x| soft::is_signaling_nan(p, x.to_raw()));
+        if signaling || !operands.iter().any(|x| x.is_nan()) {
+            *flags |= FLAG_INVALID;
+        }
+        return p.canonical_nan();
+    }
+    if !result.is_finite() {
+        if operands.iter().all(|x| x.is_finite()) {
+            *flags |= FLAG_OVERFLOW | FLAG_INEXACT;
+        }
+    } else if inexact {
+        *flags |= FLAG_INEXACT;
+        if result.abs() < F::MIN_POSITIVE {
+            *flags |= FLAG_UNDERFLOW;
+        }
+    }
+    result.to_raw()
+}
+
+fn add<F: HostFp>(x: F, y: F, flags: &mut u8) -> u64 {
+    let sum = x.add(y);
+    // TwoSum, the rounding error of sum is exact.
+    let y_part = sum.sub(x);
+    let x_part = sum.sub(y_part);
+    let error = x.sub(x_part).add(y.sub(y_part));
+    finish(&[x, y], sum, sum.is_finite() && error != F::ZERO, flags)
+}
+
+fn mul<F: HostFp>(x: F, y: F, flags: &mut u8) -> u64 {
+    let product = x.mul(y);
+    let residual = if product.abs().mul(F::SCALE) < F::SCALE.div(F::SCALE) {
+        // The smaller operand is scaled, which can't overflow.
+        let (x, y) = if x.abs() < y.abs() { (x, y) } else { (y, x) };
+        x.mul(F::SCALE).mul_add(y, product.mul(F::SCALE).neg())
+    } else {
+        x.mul_add(y, product.neg())
+    };
+    let inexact = product.is_finite()
+        && (residual != F::ZERO || (product == F::ZERO && x != F::ZERO && y != F::ZERO));
+    finish(&[x, y], product, inexact, flags)
+}
+
+fn div<F: HostFp>(x: F, y: F, flags: &mut u8) -> u64 {
+    if y == F::ZERO && x.is_finite() && x != F::ZERO {
+        *flags |= FLAG_DIVIDE_BY_ZERO;
+        return x.div(y).to_raw();
+    }
+    let quotient = x.div(y);
+    let one = F::SCALE.div(F::SCALE);
+    let residual = if quotient.abs().mul(F::SCALE) < one {
+        quotient.mul(F::SCALE).neg().mul_add(y, x.mul(F::SCALE))
+    } else if x.abs().mul(F::SCALE) < one {
+        // y is then below 1.
+        quotient.neg().mul_add(y.mul(F::SCALE), x.mul(F::SCALE))
+    } else {
+        quotient.neg().mul_add(y, x)
+    };
+    let inexact = quotient.is_finite()
+        && y.is_finite()
+        && (residual != F::ZERO || (quotient == F::ZERO && x != F::ZERO));
+    finish(&[x, y], quotient, inexact, flags)
+}
+
+fn sqrt<F: HostFp>(x: F, flags: &mut u8) -> u64 {
+    let root = x.sqrt();
+    let residual = if x.mul(F::SCALE) < F::SCALE.div(F::SCALE) {
+        let root = root.mul(F::SCALE);
+        root.mul_add(root, x.mul(F::SCALE).mul(F::SCALE).neg())
+    } else {
+        root.mul_add(root, x.neg())
+    };
+    let inexact = root.is_finite() && x > F::ZERO && residual != F::ZERO;
+    finish(&[x], root, inexact, flags)
+}
+
+fn fma<F: HostFp>(x: F, y: F, z: F, flags: &mut u8) -> u64 {
+    // Raised even when the addend is a quiet NaN.
+    let inf_times_zero = |a: F, b: F| !a.is_nan() && !a.is_finite() && b == F::ZERO;
+    if inf_times_zero(x, y) || inf_times_zero(y, x) {
+        *flags |= FLAG_INVALID;
+    }
+    let result = x.mul_add(y, z);
+    // Exact when the product, the sum of the rounded product and z, and the
+    // final rounding all are.
+    let product = x.mul(y);
+    let product_error = x.mul_add(y, product.neg());
+    let sum = product.add(z);
+    let y_part = sum.sub(product);
+    let sum_error = product.sub(sum.sub(y_part)).add(z.sub(y_part));
+    let product_underflow = product == F::ZERO && x != F::ZERO && y != F::ZERO;
+    let inexact = result.is_finite()
+        && (product_error != F::ZERO || product_underflow || sum_error != F::ZERO || sum != result);
+    finish(&[x, y, z], result, inexact, flags)
+}
+
+impl FloatBackend for HostFloat {
+    fn add(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        match (p, rm) {
+            (Precision::Single, RoundingMode::NearestEven) => {
+                add(f32::from_raw(a), f32::from_raw(b), flags)
+            }
+            (Precision::Double, RoundingMode::NearestEven) => {
+                add(f64::from_raw(a), f64::from_raw(b), flags)
+            }
+            _ => soft::add(p, a, b, rm, flags),
+        }
+    }
+
+    fn sub(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.add(p, a, b ^ p.sign_bit(), rm, flags)
+    }
+
+    fn mul(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        match (p, rm) {
+            (Precision::Single, RoundingMode::NearestEven) => {
+                mul(f32::from_raw(a), f32::from_raw(b), flags)
+            }
+            (Precision::Double, RoundingMode::NearestEven) => {
+                mul(f64::from_raw(a), f64::from_raw(b), flags)
+            }
+            _ => soft::mul(p, a, b, rm, flags),
+        }
+    }
+
+    fn div(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        match (p, rm) {
+            (Precision::Single, RoundingMode::NearestEven) => {
+                div(f32::from_raw(a), f32::from_raw(b), flags)
+            }
+            (Precision::Double, RoundingMode::NearestEven) => {
+                div(f64::from_raw(a), f64::from_raw(b), flags)
+            }
+            _ => soft::div(p, a, b, rm, flags),
+        }
+    }
+
+    fn sqrt(&mut self, p: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        match (p, rm) {
+            (Precision::Single, RoundingMode::NearestEven) => sqrt(f32::from_raw(a), flags),
+            (Precision::Double, RoundingMode::NearestEven) => sqrt(f64::from_raw(a), flags),
+            _ => soft::sqrt(p, a, rm, flags),
+        }
+    }
+
+    fn fma(
+        &mut self,
+        p: Precision,
+        a: u64,
+        b: u64,
+        c: u64,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        match (p, rm) {
+            (Precision::Single, RoundingMode::NearestEven) => {
+                fma(f32::from_raw(a), f32::from_raw(b), f32::from_raw(c), flags)
+            }
+            (Precision::Double, RoundingMode::NearestEven) => {
+                fma(f64::from_raw(a), f64::from_raw(b), f64::from_raw(c), flags)
+            }
+            _ => soft::fma(p, a, b, c, rm, flags),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+// Floating point arithmetic for the F and D extensions. Values are passed as
+// raw bits, singles in the low 32 bits, and exceptions are accrued in
+// `flags` as in the fflags CSR.
+//
+// Two backends implement FloatBackend: SoftFloat, computing results with
+// integer arithmetic only so they are bit-exact on every host, which
+// consensus requires, and HostFloat, using the host FPU for speed when
+// analysing programs. DiffFloat runs both and records where they disagree.
+pub mod host;
+pub mod soft;
+
+pub use host::HostFloat;
+pub use soft::SoftFloat;
+
+// Exception flags, in fflags order.
+pub const FLAG_INEXACT: u8 = 1;
+pub const FLAG_UNDERFLOW: u8 = 2;
+pub const FLAG_OVERFLOW: u8 = 4;
+pub const FLAG_DIVIDE_BY_ZERO: u8 = 8;
+pub const FLAG_INVALID: u8 = 16;
+
+pub const CANONICAL_NAN_32: u64 = 0x7fc0_0000;
+pub const CANONICAL_NAN_64: u64 = 0x7ff8_0000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+impl Precision {
+    pub fn exponent_bits(self) -> u32 {
+        match self {
+            Precision::Single => 8,
+            Precision::Double => 11,
+        }
+    }
+
+    // Stored fraction bits, the implicit leading bit excluded.
+    pub fn fraction_bits(self) -> u32 {
+        match self {
+            Precision::Single => 23,
+            Precision::Double => 52,
+        }
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            Precision::Single => CANONICAL_NAN_32,
+            Precision::Double => CANONICAL_NAN_64,
+        }
+    }
+
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.exponent_bits() + self.fraction_bits())
+    }
+
+    pub(crate) fn mask(self) -> u64 {
+        match self {
+            Precision::Single => 0xffff_ffff,
+            Precision::Double => u64::MAX,
+        }
+    }
+}
+
+// Rounding modes, valued as in the rm field and the frm CSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven = 0,
+    TowardZero = 1,
+    Down = 2,
+    Up = 3,
+    NearestMaxMagnitude = 4,
+}
+
+impl RoundingMode {
+    // Returns None for reserved values and DYN, which instructions resolve
+    // to frm.
+    pub fn from_u8(rm: u8) -> Option<Self> {
+        match rm {
+            0 => Some(RoundingMode::NearestEven),
+            1 => Some(RoundingMode::TowardZero),
+            2 => Some(RoundingMode::Down),
+            3 => Some(RoundingMode::Up),
+            4 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+// Width and signedness of the integer of FCVT instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntFormat {
+    W,
+    Wu,
+    L,
+    Lu,
+}
+
+// Operations of the F and D extensions needing more than moving bits, named
+// after their instructions where std traits would clash. NaN results are
+// canonical, as RISC-V requires. Comparisons, min/max and
+// conversions are exact integer operations, they default to the SoftFloat
+// implementation.
+pub trait FloatBackend {
+    fn add(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64;
+    fn sub(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64;
+    fn mul(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64;
+    fn div(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64;
+    fn sqrt(&mut self, p: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64;
+    // a * b + c with a single rounding. The FMSUB, FNMSUB and FNMADD
+    // variants negate the inputs first, negating a NaN keeps it a NaN.
+    fn fma(
+        &mut self,
+        p: Precision,
+        a: u64,
+        b: u64,
+        c: u64,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64;
+
+    fn fmin(&mut self, p: Precision, a: u64, b: u64, flags: &mut u8) -> u64 {
+        soft::min_max(p, a, b, false, flags)
+    }
+
+    fn fmax(&mut self, p: Precision, a: u64, b: u64, flags: &mut u8) -> u64 {
+        soft::min_max(p, a, b, true, flags)
+    }
+
+    fn feq(&mut self, p: Precision, a: u64, b: u64, flags: &mut u8) -> bool {
+        soft::eq(p, a, b, flags)
+    }
+
+    fn flt(&mut self, p: Precision, a: u64, b: u64, flags: &mut u8) -> bool {
+        soft::lt(p, a, b, false, flags)
+    }
+
+    fn fle(&mut self, p: Precision, a: u64, b: u64, flags: &mut u8) -> bool {
+        soft::lt(p, a, b, true, flags)
+    }
+
+    // Result of FCLASS.
+    fn classify(&mut self, p: Precision, a: u64) -> u64 {
+        soft::classify(p, a)
+    }
+
+    // Converts to an integer, 32-bit results are sign extended.
+    fn float_to_int(
+        &mut self,
+        p: Precision,
+        a: u64,
+        format: IntFormat,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        soft::to_int(p, a, format, rm, flags)
+    }
+
+    fn int_to_float(
+        &mut self,
+        p: Precision,
+        value: u64,
+        format: IntFormat,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        soft::from_int(p, value, format, rm, flags)
+    }
+
+    // Converts between single and double precision.
+    fn convert(
+        &mut self,
+        from: Precision,
+        to: Precision,
+        a: u64,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        soft::convert(from, to, a, rm, flags)
+    }
+
+    // Operations on which backends disagreed since the last call, only
+    // DiffFloat records them.
+    fn take_mismatches(&mut self) -> Vec<FloatMismatch> {
+        vec![]
+    }
+}
+
+// Selects the backend of a machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatMode {
+    // Bit-exact, required for consensus.
+    Soft,
+    // Host FPU, for analysis only.
+    Host,
+    // Soft results, checked against the host, see DiffFloat.
+    Differential,
+}
+
+impl Default for FloatMode {
+    fn default() -> Self {
+        FloatMode::Soft
+    }
+}
+
+impl FloatMode {
+    pub fn backend(self) -> Box<dyn FloatBackend + Send + Sync> {
+        match self {
+            FloatMode::Soft => Box::new(SoftFloat),
+            FloatMode::Host => Box::new(HostFloat),
+            FloatMode::Differential => Box::new(DiffFloat::new(SoftFloat, HostFloat)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Sqrt,
+    Fma,
+}
+
+// An operation whose results or flags differ between two backends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloatMismatch {
+    pub op: FloatOp,
+    pub precision: Precision,
+    pub rm: RoundingMode,
+    pub operands: Vec<u64>,
+    // (result, flags) of the reference backend.
+    pub expected: (u64, u8),
+    pub actual: (u64, u8),
+}
+
+// DiffFloat runs every arithmetic operation on a reference backend and
+// another one, returns the reference results, and records the operations
+// whose results or flags differ. It finds where the host FPU can't stand in
+// for SoftFloat:
+//
+// let mut diff = DiffFloat::new(SoftFloat, HostFloat);
+// let sum = diff.add(Precision::Double, a, b, RoundingMode::NearestEven, &mut flags);
+// assert!(diff.take_mismatches().is_empty());
+pub struct DiffFloat<A, B> {
+    pub reference: A,
+    pub other: B,
+    mismatches: Vec<FloatMismatch>,
+}
+
+impl<A: FloatBackend, B: FloatBackend> DiffFloat<A, B> {
+    pub fn new(reference: A, other: B) -> Self {
+        Self {
+            reference,
+            other,
+            mismatches: vec![],
+        }
+    }
+
+    pub fn mismatches(&self) -> &[FloatMismatch] {
+        &self.mismatches
+    }
+
+    fn check<F: FnMut(&mut dyn FloatBackend, &mut u8) -> u64>(
+        &mut self,
+        op: FloatOp,
+        precision: Precision,
+        rm: RoundingMode,
+        operands: &[u64],
+        flags: &mut u8,
+        mut run: F,
+    ) -> u64 {
+        let mut expected_flags = 0;
+        let expected = run(&mut self.reference, &mut expected_flags);
+        let mut actual_flags = 0;
+        let actual = run(&mut self.other, &mut actual_flags);
+        if (expected, expected_flags) != (actual, actual_flags) {
+            self.mismatches.push(FloatMismatch {
+                op,
+                precision,
+                rm,
+                operands: operands.to_vec(),
+                expected: (expected, expected_flags),
+                actual: (actual, actual_flags),
+            });
+        }
+        *flags |= expected_flags;
+        expected
+    }
+}
+
+impl<A: FloatBackend, B: FloatBackend> FloatBackend for DiffFloat<A, B> {
+    fn add(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.check(FloatOp::Add, p, rm, &[a, b], flags, |f, flags| {
+            f.add(p, a, b, rm, flags)
+        })
+    }
+
+    fn sub(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.check(FloatOp::Sub, p, rm, &[a, b], flags, |f, flags| {
+            f.sub(p, a, b, rm, flags)
+        })
+    }
+
+    fn mul(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.check(FloatOp::Mul, p, rm, &[a, b], flags, |f, flags| {
+            f.mul(p, a, b, rm, flags)
+        })
+    }
+
+    fn div(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.check(FloatOp::Div, p, rm, &[a, b], flags, |f, flags| {
+            f.div(p, a, b, rm, flags)
+        })
+    }
+
+    fn sqrt(&mut self, p: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        self.check(FloatOp::Sqrt, p, rm, &[a], flags, |f, flags| {
+            f.sqrt(p, a, rm, flags)
+        })
+    }
+
+    fn fma(
+        &mut self,
+        p: Precision,
+        a: u64,
+        b: u64,
+        c: u64,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        self.check(FloatOp::Fma, p, rm, &[a, b, c], flags, |f, flags| {
+            f.fma(p, a, b, c, rm, flags)
+        })
+    }
+
+    fn take_mismatches(&mut self) -> Vec<FloatMismatch> {
+        std::mem::take(&mut self.mismatches)
+    }
+}
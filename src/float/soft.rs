@@ -0,0 +1,625 @@
+use super::{
+    FloatBackend, IntFormat, Precision, RoundingMode, FLAG_DIVIDE_BY_ZERO, FLAG_INEXACT,
+    FLAG_INVALID, FLAG_OVERFLOW, FLAG_UNDERFLOW,
+};
+
+// SoftFloat implements IEEE 754 arithmetic with integer operations only, so
+// results and flags are the same on every host. Tininess is detected after
+// rounding, and NaN results are canonical, as RISC-V specifies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SoftFloat;
+
+impl FloatBackend for SoftFloat {
+    fn add(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        add(p, a, b, rm, flags)
+    }
+
+    fn sub(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        sub(p, a, b, rm, flags)
+    }
+
+    fn mul(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        mul(p, a, b, rm, flags)
+    }
+
+    fn div(&mut self, p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        div(p, a, b, rm, flags)
+    }
+
+    fn sqrt(&mut self, p: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+        sqrt(p, a, rm, flags)
+    }
+
+    fn fma(
+        &mut self,
+        p: Precision,
+        a: u64,
+        b: u64,
+        c: u64,
+        rm: RoundingMode,
+        flags: &mut u8,
+    ) -> u64 {
+        fma(p, a, b, c, rm, flags)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Zero(bool),
+    Inf(bool),
+    // True for signaling NaNs.
+    Nan(bool),
+    // sig * 2^exp, sig is not 0.
+    Finite { sign: bool, exp: i32, sig: u128 },
+}
+
+use Value::{Finite, Inf, Nan, Zero};
+
+fn bias(p: Precision) -> i32 {
+    (1 << (p.exponent_bits() - 1)) - 1
+}
+
+// Biased exponent of infinities and NaNs.
+fn max_exponent(p: Precision) -> u64 {
+    (1 << p.exponent_bits()) - 1
+}
+
+fn fraction_mask(p: Precision) -> u64 {
+    (1 << p.fraction_bits()) - 1
+}
+
+fn sign_bits(p: Precision, sign: bool) -> u64 {
+    if sign {
+        p.sign_bit()
+    } else {
+        0
+    }
+}
+
+fn unpack(p: Precision, bits: u64) -> Value {
+    let f = p.fraction_bits();
+    let bits = bits & p.mask();
+    let sign = bits & p.sign_bit() != 0;
+    let biased = (bits >> f) & max_exponent(p);
+    let fraction = bits & fraction_mask(p);
+    if biased == max_exponent(p) {
+        if fraction == 0 {
+            Inf(sign)
+        } else {
+            Nan(fraction >> (f - 1) == 0)
+        }
+    } else if biased == 0 {
+        if fraction == 0 {
+            Zero(sign)
+        } else {
+            Finite {
+                sign,
+                exp: 1 - bias(p) - f as i32,
+                sig: u128::from(fraction),
+            }
+        }
+    } else {
+        Finite {
+            sign,
+            exp: biased as i32 - bias(p) - f as i32,
+            sig: u128::from(fraction | 1 << f),
+        }
+    }
+}
+
+pub(crate) fn is_signaling_nan(p: Precision, bits: u64) -> bool {
+    unpack(p, bits) == Nan(true)
+}
+
+fn zero(p: Precision, sign: bool) -> u64 {
+    sign_bits(p, sign)
+}
+
+fn inf(p: Precision, sign: bool) -> u64 {
+    sign_bits(p, sign) | max_exponent(p) << p.fraction_bits()
+}
+
+fn invalid(p: Precision, flags: &mut u8) -> u64 {
+    *flags |= FLAG_INVALID;
+    p.canonical_nan()
+}
+
+// Returns the canonical NaN if any operand is a NaN, raising invalid for
+// signaling ones.
+fn propagate_nan(p: Precision, operands: &[Value], flags: &mut u8) -> Option<u64> {
+    if operands.contains(&Nan(true)) {
+        *flags |= FLAG_INVALID;
+    }
+    if operands.iter().any(|v| matches!(v, Nan(_))) {
+        Some(p.canonical_nan())
+    } else {
+        None
+    }
+}
+
+fn sign_of(v: Value) -> bool {
+    match v {
+        Zero(sign) | Inf(sign) | Finite { sign, .. } => sign,
+        Nan(_) => false,
+    }
+}
+
+// Shifts `sig` right by `shift` bits and rounds the result to an integer.
+// `sticky` tells that there are nonzero bits below sig. Returns the rounded
+// value and whether it is inexact.
+fn shift_round(sig: u128, shift: i32, sticky: bool, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, sticky);
+    }
+    let (kept, rest, half) = match shift {
+        s if s < 128 => (sig >> s, sig & ((1 << s) - 1), 1 << (s - 1)),
+        128 => (0, sig, 1 << 127),
+        // Far below half of the last kept bit.
+        _ => (0, u128::from(sig != 0), u128::MAX),
+    };
+    let inexact = rest != 0 || sticky;
+    let above_half = rest > half || (rest == half && sticky);
+    let at_half = rest == half && !sticky;
+    let up = match rm {
+        RoundingMode::NearestEven => above_half || (at_half && kept & 1 == 1),
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => inexact && sign,
+        RoundingMode::Up => inexact && !sign,
+        RoundingMode::NearestMaxMagnitude => above_half || at_half,
+    };
+    (kept + u128::from(up), inexact)
+}
+
+fn overflow(p: Precision, sign: bool, rm: RoundingMode, flags: &mut u8) -> u64 {
+    *flags |= FLAG_OVERFLOW | FLAG_INEXACT;
+    let to_inf = match rm {
+        RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => sign,
+        RoundingMode::Up => !sign,
+    };
+    if to_inf {
+        inf(p, sign)
+    } else {
+        // The largest finite value.
+        sign_bits(p, sign) | (max_exponent(p) - 1) << p.fraction_bits() | fraction_mask(p)
+    }
+}
+
+// Rounds sig * 2^exp to precision `p`. `sticky` tells that the exact value
+// has nonzero bits below sig, sig must then have at least 3 more bits than
+// the precision so they only affect rounding.
+fn round_pack(
+    p: Precision,
+    sign: bool,
+    exp: i32,
+    sig: u128,
+    sticky: bool,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    if sig == 0 {
+        return zero(p, sign);
+    }
+    let f = p.fraction_bits() as i32;
+    let emin = 1 - bias(p);
+    // Exponent of the leading bit.
+    let mut top = exp + 127 - sig.leading_zeros() as i32;
+    let quantum = top.max(emin) - f;
+    let (mut kept, inexact) = shift_round(sig, quantum - exp, sticky, sign, rm);
+    if inexact {
+        *flags |= FLAG_INEXACT;
+    }
+    if top < emin {
+        // Tiny unless rounding with an unbounded exponent reaches the
+        // smallest normal value.
+        let tiny = top < emin - 1 || {
+            let (unbounded, _) = shift_round(sig, top - f - exp, sticky, sign, rm);
+            unbounded >> (f + 1) == 0
+        };
+        if tiny && inexact {
+            *flags |= FLAG_UNDERFLOW;
+        }
+        // A subnormal, or the smallest normal value when rounding carried
+        // into the exponent field.
+        return sign_bits(p, sign) | kept as u64;
+    }
+    if kept >> (f + 1) != 0 {
+        kept >>= 1;
+        top += 1;
+    }
+    let biased = (top + bias(p)) as u64;
+    if biased >= max_exponent(p) {
+        return overflow(p, sign, rm, flags);
+    }
+    sign_bits(p, sign) | biased << f | (kept as u64 & fraction_mask(p))
+}
+
+// Shifts sig left so its leading bit is at the implicit bit position.
+fn normalize(p: Precision, exp: i32, sig: u128) -> (i32, u128) {
+    let shift = p.fraction_bits() as i32 - (127 - sig.leading_zeros() as i32);
+    (exp - shift, sig << shift)
+}
+
+// Adds two nonzero finite values exactly, then rounds the sum.
+fn add_finite(
+    p: Precision,
+    a: (bool, i32, u128),
+    b: (bool, i32, u128),
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    let top = |(_, exp, sig): (bool, i32, u128)| exp + 127 - sig.leading_zeros() as i32;
+    let (a, b) = if top(b) > top(a) { (b, a) } else { (a, b) };
+    let (sign_a, exp_a, sig_a) = a;
+    let (sign_b, exp_b, sig_b) = b;
+    // The larger operand leads at bit 125, leaving room for a carry.
+    let shift_a = 125 - (127 - sig_a.leading_zeros() as i32);
+    let sig_a = sig_a << shift_a;
+    let exp = exp_a - shift_a;
+    let (sig_b, sticky) = match exp_b - exp {
+        d if d >= 0 => (sig_b << d, false),
+        d if d > -128 => (sig_b >> -d, sig_b & ((1 << -d) - 1) != 0),
+        _ => (0, true),
+    };
+    if sign_a == sign_b {
+        return round_pack(p, sign_a, exp, sig_a + sig_b, sticky, rm, flags);
+    }
+    if sticky {
+        // a - (b + e) with 0 < e < 1 is (a - b - 1) + (1 - e).
+        return round_pack(p, sign_a, exp, sig_a - sig_b - 1, true, rm, flags);
+    }
+    match sig_a.cmp(&sig_b) {
+        std::cmp::Ordering::Greater => round_pack(p, sign_a, exp, sig_a - sig_b, false, rm, flags),
+        std::cmp::Ordering::Less => round_pack(p, sign_b, exp, sig_b - sig_a, false, rm, flags),
+        std::cmp::Ordering::Equal => zero(p, rm == RoundingMode::Down),
+    }
+}
+
+pub fn add(p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if let Some(nan) = propagate_nan(p, &[x, y], flags) {
+        return nan;
+    }
+    match (x, y) {
+        (Inf(sa), Inf(sb)) if sa != sb => invalid(p, flags),
+        (Inf(sign), _) | (_, Inf(sign)) => inf(p, sign),
+        (Zero(sa), Zero(sb)) => zero(
+            p,
+            if sa == sb {
+                sa
+            } else {
+                rm == RoundingMode::Down
+            },
+        ),
+        (Zero(_), _) => b & p.mask(),
+        (_, Zero(_)) => a & p.mask(),
+        (
+            Finite {
+                sign: sa,
+                exp: ea,
+                sig: ma,
+            },
+            Finite {
+                sign: sb,
+                exp: eb,
+                sig: mb,
+            },
+        ) => add_finite(p, (sa, ea, ma), (sb, eb, mb), rm, flags),
+        _ => unreachable!(),
+    }
+}
+
+pub fn sub(p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    add(p, a, b ^ p.sign_bit(), rm, flags)
+}
+
+pub fn mul(p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if let Some(nan) = propagate_nan(p, &[x, y], flags) {
+        return nan;
+    }
+    let sign = sign_of(x) ^ sign_of(y);
+    match (x, y) {
+        (Inf(_), Zero(_)) | (Zero(_), Inf(_)) => invalid(p, flags),
+        (Inf(_), _) | (_, Inf(_)) => inf(p, sign),
+        (Zero(_), _) | (_, Zero(_)) => zero(p, sign),
+        (
+            Finite {
+                exp: ea, sig: ma, ..
+            },
+            Finite {
+                exp: eb, sig: mb, ..
+            },
+        ) => round_pack(p, sign, ea + eb, ma * mb, false, rm, flags),
+        _ => unreachable!(),
+    }
+}
+
+pub fn div(p: Precision, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if let Some(nan) = propagate_nan(p, &[x, y], flags) {
+        return nan;
+    }
+    let sign = sign_of(x) ^ sign_of(y);
+    match (x, y) {
+        (Inf(_), Inf(_)) | (Zero(_), Zero(_)) => invalid(p, flags),
+        (Inf(_), _) => inf(p, sign),
+        (_, Inf(_)) | (Zero(_), _) => zero(p, sign),
+        (_, Zero(_)) => {
+            *flags |= FLAG_DIVIDE_BY_ZERO;
+            inf(p, sign)
+        }
+        (
+            Finite {
+                exp: ea, sig: ma, ..
+            },
+            Finite {
+                exp: eb, sig: mb, ..
+            },
+        ) => {
+            let (ea, ma) = normalize(p, ea, ma);
+            let (eb, mb) = normalize(p, eb, mb);
+            // The quotient has at least fraction bits + 4 bits.
+            let shift = p.fraction_bits() as i32 + 4;
+            let dividend = ma << shift;
+            let quotient = dividend / mb;
+            let sticky = dividend % mb != 0;
+            round_pack(p, sign, ea - eb - shift, quotient, sticky, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Integer square root, rounded down.
+fn isqrt(n: u128) -> u128 {
+    let mut rest = n;
+    let mut root = 0u128;
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+pub fn sqrt(p: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let x = unpack(p, a);
+    if let Some(nan) = propagate_nan(p, &[x], flags) {
+        return nan;
+    }
+    match x {
+        Zero(sign) => zero(p, sign),
+        Inf(false) => inf(p, false),
+        Inf(true) | Finite { sign: true, .. } => invalid(p, flags),
+        Finite { exp, sig, .. } => {
+            let (mut exp, mut sig) = normalize(p, exp, sig);
+            if exp & 1 != 0 {
+                exp -= 1;
+                sig <<= 1;
+            }
+            // The root has at least fraction bits + 3 bits.
+            let shift = p.fraction_bits() as i32 / 2 + 4;
+            let square = sig << (2 * shift);
+            let root = isqrt(square);
+            let sticky = root * root != square;
+            round_pack(p, false, exp / 2 - shift, root, sticky, rm, flags)
+        }
+        Nan(_) => unreachable!(),
+    }
+}
+
+pub fn fma(p: Precision, a: u64, b: u64, c: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (x, y, z) = (unpack(p, a), unpack(p, b), unpack(p, c));
+    // Raised even when the addend is a quiet NaN.
+    if matches!((x, y), (Inf(_), Zero(_)) | (Zero(_), Inf(_))) {
+        *flags |= FLAG_INVALID;
+    }
+    if let Some(nan) = propagate_nan(p, &[x, y, z], flags) {
+        return nan;
+    }
+    let sign = sign_of(x) ^ sign_of(y);
+    match (x, y, z) {
+        (Inf(_), Zero(_), _) | (Zero(_), Inf(_), _) => p.canonical_nan(),
+        (Inf(_), _, Inf(sz)) | (_, Inf(_), Inf(sz)) if sz != sign => invalid(p, flags),
+        (Inf(_), _, _) | (_, Inf(_), _) => inf(p, sign),
+        (_, _, Inf(sz)) => inf(p, sz),
+        (Zero(_), _, Zero(sz)) | (_, Zero(_), Zero(sz)) => zero(
+            p,
+            if sz == sign {
+                sign
+            } else {
+                rm == RoundingMode::Down
+            },
+        ),
+        (Zero(_), _, _) | (_, Zero(_), _) => c & p.mask(),
+        (
+            Finite {
+                exp: ea, sig: ma, ..
+            },
+            Finite {
+                exp: eb, sig: mb, ..
+            },
+            Zero(_),
+        ) => round_pack(p, sign, ea + eb, ma * mb, false, rm, flags),
+        (
+            Finite {
+                exp: ea, sig: ma, ..
+            },
+            Finite {
+                exp: eb, sig: mb, ..
+            },
+            Finite {
+                sign: sc,
+                exp: ec,
+                sig: mc,
+            },
+        ) => add_finite(p, (sign, ea + eb, ma * mb), (sc, ec, mc), rm, flags),
+        _ => unreachable!(),
+    }
+}
+
+// Orders values, with -0 and +0 equal. NaNs are handled by callers.
+fn order_key(p: Precision, bits: u64) -> i128 {
+    let magnitude = i128::from(bits & p.mask() & !p.sign_bit());
+    if bits & p.sign_bit() != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+// FMIN and FMAX: a NaN operand yields the other one, and -0 is less than
+// +0.
+pub fn min_max(p: Precision, a: u64, b: u64, max: bool, flags: &mut u8) -> u64 {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if x == Nan(true) || y == Nan(true) {
+        *flags |= FLAG_INVALID;
+    }
+    match (x, y) {
+        (Nan(_), Nan(_)) => return p.canonical_nan(),
+        (Nan(_), _) => return b & p.mask(),
+        (_, Nan(_)) => return a & p.mask(),
+        _ => (),
+    }
+    let (ka, kb) = (order_key(p, a), order_key(p, b));
+    let pick_a = if ka == kb {
+        sign_of(x) != max
+    } else {
+        (ka < kb) != max
+    };
+    if pick_a {
+        a & p.mask()
+    } else {
+        b & p.mask()
+    }
+}
+
+// FEQ, only signaling NaNs raise invalid.
+pub fn eq(p: Precision, a: u64, b: u64, flags: &mut u8) -> bool {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if x == Nan(true) || y == Nan(true) {
+        *flags |= FLAG_INVALID;
+    }
+    if matches!(x, Nan(_)) || matches!(y, Nan(_)) {
+        return false;
+    }
+    order_key(p, a) == order_key(p, b)
+}
+
+// FLT, or FLE when `or_equal`, any NaN raises invalid.
+pub fn lt(p: Precision, a: u64, b: u64, or_equal: bool, flags: &mut u8) -> bool {
+    let (x, y) = (unpack(p, a), unpack(p, b));
+    if matches!(x, Nan(_)) || matches!(y, Nan(_)) {
+        *flags |= FLAG_INVALID;
+        return false;
+    }
+    let (ka, kb) = (order_key(p, a), order_key(p, b));
+    ka < kb || (or_equal && ka == kb)
+}
+
+pub fn classify(p: Precision, a: u64) -> u64 {
+    let bits = a & p.mask();
+    let biased = (bits >> p.fraction_bits()) & max_exponent(p);
+    let shift = match unpack(p, a) {
+        Inf(true) => 0,
+        Finite { sign: true, .. } if biased != 0 => 1,
+        Finite { sign: true, .. } => 2,
+        Zero(true) => 3,
+        Zero(false) => 4,
+        Finite { .. } if biased == 0 => 5,
+        Finite { .. } => 6,
+        Inf(false) => 7,
+        Nan(true) => 8,
+        Nan(false) => 9,
+    };
+    1 << shift
+}
+
+pub fn to_int(p: Precision, a: u64, format: IntFormat, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (bits, signed) = match format {
+        IntFormat::W => (32, true),
+        IntFormat::Wu => (32, false),
+        IntFormat::L => (64, true),
+        IntFormat::Lu => (64, false),
+    };
+    // Largest magnitudes of positive and negative results.
+    let (max, min) = if signed {
+        ((1u128 << (bits - 1)) - 1, 1u128 << (bits - 1))
+    } else {
+        ((1u128 << bits) - 1, 0)
+    };
+    let (sign, magnitude, inexact) = match unpack(p, a) {
+        Nan(_) => (false, u128::MAX, false),
+        Inf(sign) => (sign, u128::MAX, false),
+        Zero(_) => (false, 0, false),
+        Finite { sign, exp, sig } => {
+            if exp > 64 {
+                (sign, u128::MAX, false)
+            } else {
+                let (magnitude, inexact) = shift_round(sig, -exp, false, sign, rm);
+                (sign, magnitude, inexact)
+            }
+        }
+    };
+    let value = if !sign && magnitude > max {
+        *flags |= FLAG_INVALID;
+        max as i128
+    } else if sign && magnitude > min {
+        *flags |= FLAG_INVALID;
+        -(min as i128)
+    } else {
+        if inexact {
+            *flags |= FLAG_INEXACT;
+        }
+        if sign {
+            -(magnitude as i128)
+        } else {
+            magnitude as i128
+        }
+    };
+    // 32-bit results, unsigned ones included, are sign extended.
+    if bits == 32 {
+        value as u32 as i32 as i64 as u64
+    } else {
+        value as u64
+    }
+}
+
+pub fn from_int(
+    p: Precision,
+    value: u64,
+    format: IntFormat,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    let (sign, magnitude) = match format {
+        IntFormat::W => ((value as i32) < 0, u64::from((value as i32).unsigned_abs())),
+        IntFormat::Wu => (false, value & 0xffff_ffff),
+        IntFormat::L => ((value as i64) < 0, (value as i64).unsigned_abs()),
+        IntFormat::Lu => (false, value),
+    };
+    round_pack(p, sign, 0, u128::from(magnitude), false, rm, flags)
+}
+
+pub fn convert(from: Precision, to: Precision, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    match unpack(from, a) {
+        Nan(signaling) => {
+            if signaling {
+                *flags |= FLAG_INVALID;
+            }
+            to.canonical_nan()
+        }
+        Inf(sign) => inf(to, sign),
+        Zero(sign) => zero(to, sign),
+        Finite { sign, exp, sig } => round_pack(to, sign, exp, sig, false, rm, flags),
+    }
+}
@@ -0,0 +1,223 @@
+// Arbitrary implementations of machine states, so that fuzzers can start from
+// randomized but valid states instead of only randomized programs:
+//
+// fuzz_target!(|state: MachineState<u64>| {
+//     let mut machine = state.build::<SparseMemory<u64>>().unwrap();
+//     let _ = machine.run();
+// });
+use super::{
+    isa::{max_isa, Isa},
+    machine::{
+        config::{FromConfig, MachineConfig},
+        DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION0, VERSION1, VERSION2,
+    },
+    memory::{FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE},
+    CoreMachine, Error, Memory, Register, SupportMachine, MEMORY_FRAMESIZE,
+    RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
+};
+use arbitrary::{Arbitrary, Unstructured};
+use bytes::Bytes;
+use ckb_vm_definitions::registers::SP;
+use std::fmt;
+
+// Upper bound of pages in a MemoryImage, keeping inputs small.
+pub const MAX_IMAGE_PAGES: usize = 8;
+
+// Values around boundaries, which random integers rarely hit.
+const INTERESTING_VALUES: [u64; 10] = [
+    0,
+    1,
+    2,
+    0x7f,
+    0x8000_0000,
+    0xffff_ffff,
+    0x7fff_ffff_ffff_ffff,
+    0x8000_0000_0000_0000,
+    0xffff_ffff_ffff_fffe,
+    0xffff_ffff_ffff_ffff,
+];
+
+fn register_value<R: Register>(u: &mut Unstructured) -> arbitrary::Result<R> {
+    let value = if u.ratio(1, 4)? {
+        *u.choose(&INTERESTING_VALUES)?
+    } else {
+        u.arbitrary()?
+    };
+    Ok(R::from_u64(value))
+}
+
+// General purpose registers, with x0 always being zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterFile<R>(pub Vec<R>);
+
+impl<'a, R: Register> Arbitrary<'a> for RegisterFile<R> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut registers = vec![R::zero()];
+        for _ in 1..RISCV_GENERAL_REGISTER_NUMBER {
+            registers.push(register_value(u)?);
+        }
+        Ok(RegisterFile(registers))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryPage {
+    // Page aligned.
+    pub address: u64,
+    pub flags: u8,
+    // At most RISCV_PAGESIZE bytes, the rest of the page is zero.
+    pub data: Vec<u8>,
+}
+
+// A few initialized pages. Pages are distinct and lie within the memory
+// size the image was generated for.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryImage {
+    pub pages: Vec<MemoryPage>,
+}
+
+impl MemoryImage {
+    pub fn arbitrary_with_size(
+        u: &mut Unstructured,
+        memory_size: usize,
+    ) -> arbitrary::Result<Self> {
+        let total_pages = (memory_size / RISCV_PAGESIZE) as u64;
+        let count = u.int_in_range(0..=MAX_IMAGE_PAGES)?;
+        let mut pages: Vec<MemoryPage> = Vec::with_capacity(count);
+        for _ in 0..count {
+            if total_pages == 0 {
+                break;
+            }
+            let address = u.int_in_range(0..=total_pages - 1)? * RISCV_PAGESIZE as u64;
+            if pages.iter().any(|page| page.address == address) {
+                continue;
+            }
+            let flags = *u.choose(&[
+                FLAG_WRITABLE,
+                FLAG_EXECUTABLE,
+                FLAG_WRITABLE | FLAG_FREEZED,
+                FLAG_EXECUTABLE | FLAG_FREEZED,
+            ])?;
+            let length = u.int_in_range(0..=RISCV_PAGESIZE)?.min(u.len());
+            let data = u.bytes(length)?.to_vec();
+            pages.push(MemoryPage {
+                address,
+                flags,
+                data,
+            });
+        }
+        Ok(MemoryImage { pages })
+    }
+
+    pub fn apply<M: Memory>(&self, memory: &mut M) -> Result<(), Error> {
+        for page in &self.pages {
+            memory.init_pages(
+                page.address,
+                RISCV_PAGESIZE as u64,
+                page.flags,
+                Some(Bytes::copy_from_slice(&page.data)),
+                0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Arbitrary<'a> for MemoryImage {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Self::arbitrary_with_size(u, RISCV_MAX_MEMORY)
+    }
+}
+
+// Configurations accepted by the machine: the ISA is valid for the version,
+// and the memory size is a multiple of MEMORY_FRAMESIZE. Cycle functions and
+// metrics sinks are left unset.
+impl<'a> Arbitrary<'a> for MachineConfig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let version = *u.choose(&[VERSION0, VERSION1, VERSION2])?;
+        let mask = u.arbitrary::<u64>()? & max_isa(version).mask();
+        let isa = Isa::new(mask, version).unwrap_or(Isa::IMC);
+        let max_cycles = if u.ratio(1, 4)? {
+            u64::MAX
+        } else {
+            u.arbitrary()?
+        };
+        let frames = u.int_in_range(1..=RISCV_MAX_MEMORY / MEMORY_FRAMESIZE)?;
+        Ok(MachineConfig::new()
+            .isa(isa)
+            .version(version)
+            .max_cycles(max_cycles)
+            .memory_size(frames * MEMORY_FRAMESIZE))
+    }
+}
+
+// A complete machine state. SP points into memory, and PC points to an
+// executable page when the memory image has one.
+pub struct MachineState<R> {
+    pub config: MachineConfig,
+    pub registers: RegisterFile<R>,
+    pub pc: u64,
+    pub cycles: u64,
+    pub memory: MemoryImage,
+}
+
+impl<'a, R: Register> Arbitrary<'a> for MachineState<R> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let config: MachineConfig = u.arbitrary()?;
+        let memory = MemoryImage::arbitrary_with_size(u, config.memory_size)?;
+        let mut registers: RegisterFile<R> = u.arbitrary()?;
+        let memory_size = config.memory_size as u64;
+        registers.0[SP] = R::from_u64(u.int_in_range(1..=memory_size / 16)? * 16);
+        let executable: Vec<u64> = memory
+            .pages
+            .iter()
+            .filter(|page| page.flags & FLAG_EXECUTABLE != 0)
+            .map(|page| page.address)
+            .collect();
+        let pc = match u.choose(&executable) {
+            Ok(address) => address + u.int_in_range(0..=RISCV_PAGESIZE as u64 / 2 - 1)? * 2,
+            Err(_) => u.int_in_range(0..=memory_size / 2 - 1)? * 2,
+        };
+        let cycles = u.int_in_range(0..=config.max_cycles)?;
+        Ok(MachineState {
+            config,
+            registers,
+            pc,
+            cycles,
+            memory,
+        })
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for MachineState<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MachineState")
+            .field("isa", &self.config.isa)
+            .field("version", &self.config.version)
+            .field("max_cycles", &self.config.max_cycles)
+            .field("memory_size", &self.config.memory_size)
+            .field("registers", &self.registers)
+            .field("pc", &self.pc)
+            .field("cycles", &self.cycles)
+            .field("memory", &self.memory)
+            .finish()
+    }
+}
+
+impl<R: Register> MachineState<R> {
+    // Builds a machine in this state, ready to run from PC.
+    pub fn build<M: Memory<REG = R>>(
+        self,
+    ) -> Result<DefaultMachine<DefaultCoreMachine<R, M>>, Error> {
+        let core = DefaultCoreMachine::<R, M>::from_config(&self.config)?;
+        let mut machine = DefaultMachineBuilder::new(core).build();
+        self.memory.apply(machine.memory_mut())?;
+        for (index, value) in self.registers.0.into_iter().enumerate().skip(1) {
+            machine.set_register(index, value);
+        }
+        machine.update_pc(R::from_u64(self.pc));
+        machine.commit_pc();
+        machine.set_cycles(self.cycles);
+        Ok(machine)
+    }
+}
@@ -0,0 +1,467 @@
+// Random program generator for differential fuzzing. Generated programs are
+// valid by construction: control flow is structured(forward branches over
+// if-blocks and loops with bounded iteration counts), memory accesses stay in
+// a data region below the stack pointer, and every program exits with code 0.
+// They can be run under lockstep comparison against a reference model:
+//
+// let program = generate_program_from_seed::<u64>(seed, &GeneratorOptions::default());
+// machine.load_program(&program, &["fuzz".into()])?;
+// let divergence = run_lockstep(&mut machine, &mut spike, &LockstepOptions::default())?;
+//
+// Reproduce a failure by generating the program again with the same seed.
+use crate::{
+    elf_writer::minimal_elf,
+    instructions::{insts, Instruction, InstructionOpcode, Register},
+    Bytes, ISA_A, ISA_B, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, pack_s, pack_u, to_riscv};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+// Instruction classes to generate besides integer computations. The default
+// only generates straight-line code without memory accesses, which always
+// runs to the end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstructionOptions {
+    // Loads, stores, and atomic instructions when ISA_A is enabled.
+    pub memory: bool,
+    // Jumps and branches.
+    pub control_flow: bool,
+    // ECALL, EBREAK, FENCE and FENCE.I.
+    pub system: bool,
+}
+
+impl InstructionOptions {
+    pub fn all() -> Self {
+        Self {
+            memory: true,
+            control_flow: true,
+            system: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Format {
+    R,
+    I,
+    // Shift amount of the given bits.
+    Shift(u32),
+    // R-type with a fixed rs2 selecting the operation.
+    Unary(u8),
+    S,
+    B,
+    U,
+    J,
+    Fence,
+    Blank,
+}
+
+pub(crate) fn opcodes<R: Register>(
+    isa: u64,
+    options: InstructionOptions,
+) -> Vec<(InstructionOpcode, Format)> {
+    use insts::*;
+    let rv64 = R::BITS == 64;
+    let shift = Format::Shift(if rv64 { 6 } else { 5 });
+    let mut opcodes = vec![
+        (OP_ADD, Format::R),
+        (OP_SUB, Format::R),
+        (OP_SLL, Format::R),
+        (OP_SLT, Format::R),
+        (OP_SLTU, Format::R),
+        (OP_XOR, Format::R),
+        (OP_SRL, Format::R),
+        (OP_SRA, Format::R),
+        (OP_OR, Format::R),
+        (OP_AND, Format::R),
+        (OP_ADDI, Format::I),
+        (OP_SLTI, Format::I),
+        (OP_SLTIU, Format::I),
+        (OP_XORI, Format::I),
+        (OP_ORI, Format::I),
+        (OP_ANDI, Format::I),
+        (OP_SLLI, shift),
+        (OP_SRLI, shift),
+        (OP_SRAI, shift),
+        (OP_LUI, Format::U),
+        (OP_AUIPC, Format::U),
+        (OP_MUL, Format::R),
+        (OP_MULH, Format::R),
+        (OP_MULHSU, Format::R),
+        (OP_MULHU, Format::R),
+        (OP_DIV, Format::R),
+        (OP_DIVU, Format::R),
+        (OP_REM, Format::R),
+        (OP_REMU, Format::R),
+    ];
+    if rv64 {
+        opcodes.extend_from_slice(&[
+            (OP_ADDW, Format::R),
+            (OP_SUBW, Format::R),
+            (OP_SLLW, Format::R),
+            (OP_SRLW, Format::R),
+            (OP_SRAW, Format::R),
+            (OP_ADDIW, Format::I),
+            (OP_SLLIW, Format::Shift(5)),
+            (OP_SRLIW, Format::Shift(5)),
+            (OP_SRAIW, Format::Shift(5)),
+            (OP_MULW, Format::R),
+            (OP_DIVW, Format::R),
+            (OP_DIVUW, Format::R),
+            (OP_REMW, Format::R),
+            (OP_REMUW, Format::R),
+        ]);
+    }
+    if isa & ISA_B != 0 {
+        opcodes.extend_from_slice(&[
+            (OP_ANDN, Format::R),
+            (OP_ORN, Format::R),
+            (OP_XNOR, Format::R),
+            (OP_ROL, Format::R),
+            (OP_ROR, Format::R),
+            (OP_BINV, Format::R),
+            (OP_BSET, Format::R),
+            (OP_BCLR, Format::R),
+            (OP_BEXT, Format::R),
+            (OP_SH1ADD, Format::R),
+            (OP_SH2ADD, Format::R),
+            (OP_SH3ADD, Format::R),
+            (OP_CLMUL, Format::R),
+            (OP_CLMULH, Format::R),
+            (OP_CLMULR, Format::R),
+            (OP_MIN, Format::R),
+            (OP_MINU, Format::R),
+            (OP_MAX, Format::R),
+            (OP_MAXU, Format::R),
+            (OP_BCLRI, shift),
+            (OP_BEXTI, shift),
+            (OP_BINVI, shift),
+            (OP_BSETI, shift),
+            (OP_RORI, shift),
+            (OP_CLZ, Format::Unary(0b_00000)),
+            (OP_CTZ, Format::Unary(0b_00001)),
+            (OP_CPOP, Format::Unary(0b_00010)),
+            (OP_SEXTB, Format::Unary(0b_00100)),
+            (OP_SEXTH, Format::Unary(0b_00101)),
+            (OP_ORCB, Format::Unary(0b_00111)),
+            (OP_REV8, Format::Unary(0b_11000)),
+        ]);
+        if rv64 {
+            opcodes.extend_from_slice(&[
+                (OP_ADDUW, Format::R),
+                (OP_ROLW, Format::R),
+                (OP_RORW, Format::R),
+                (OP_SH1ADDUW, Format::R),
+                (OP_SH2ADDUW, Format::R),
+                (OP_SH3ADDUW, Format::R),
+                (OP_RORIW, Format::Shift(5)),
+                (OP_SLLIUW, Format::Shift(6)),
+                (OP_CLZW, Format::Unary(0b_00000)),
+                (OP_CTZW, Format::Unary(0b_00001)),
+                (OP_CPOPW, Format::Unary(0b_00010)),
+                (OP_ZEXTH, Format::Unary(0b_00000)),
+            ]);
+        }
+    }
+    if options.memory {
+        opcodes.extend_from_slice(&[
+            (OP_LB_VERSION1, Format::I),
+            (OP_LH_VERSION1, Format::I),
+            (OP_LW_VERSION1, Format::I),
+            (OP_LBU_VERSION1, Format::I),
+            (OP_LHU_VERSION1, Format::I),
+            (OP_SB, Format::S),
+            (OP_SH, Format::S),
+            (OP_SW, Format::S),
+        ]);
+        if rv64 {
+            opcodes.extend_from_slice(&[
+                (OP_LD_VERSION1, Format::I),
+                (OP_LWU_VERSION1, Format::I),
+                (OP_SD, Format::S),
+            ]);
+        }
+        if isa & ISA_A != 0 {
+            opcodes.extend_from_slice(&[
+                (OP_LR_W, Format::Unary(0)),
+                (OP_SC_W, Format::R),
+                (OP_AMOSWAP_W, Format::R),
+                (OP_AMOADD_W, Format::R),
+                (OP_AMOXOR_W, Format::R),
+                (OP_AMOAND_W, Format::R),
+                (OP_AMOOR_W, Format::R),
+                (OP_AMOMIN_W, Format::R),
+                (OP_AMOMAX_W, Format::R),
+                (OP_AMOMINU_W, Format::R),
+                (OP_AMOMAXU_W, Format::R),
+            ]);
+            if rv64 {
+                opcodes.extend_from_slice(&[
+                    (OP_LR_D, Format::Unary(0)),
+                    (OP_SC_D, Format::R),
+                    (OP_AMOSWAP_D, Format::R),
+                    (OP_AMOADD_D, Format::R),
+                    (OP_AMOXOR_D, Format::R),
+                    (OP_AMOAND_D, Format::R),
+                    (OP_AMOOR_D, Format::R),
+                    (OP_AMOMIN_D, Format::R),
+                    (OP_AMOMAX_D, Format::R),
+                    (OP_AMOMINU_D, Format::R),
+                    (OP_AMOMAXU_D, Format::R),
+                ]);
+            }
+        }
+    }
+    if options.control_flow {
+        opcodes.extend_from_slice(&[
+            (OP_JAL, Format::J),
+            (OP_JALR_VERSION1, Format::I),
+            (OP_BEQ, Format::B),
+            (OP_BNE, Format::B),
+            (OP_BLT, Format::B),
+            (OP_BGE, Format::B),
+            (OP_BLTU, Format::B),
+            (OP_BGEU, Format::B),
+        ]);
+    }
+    if options.system {
+        opcodes.extend_from_slice(&[
+            (OP_ECALL, Format::Blank),
+            (OP_EBREAK, Format::Blank),
+            (OP_FENCEI, Format::Blank),
+            (OP_FENCE, Format::Fence),
+        ]);
+    }
+    opcodes
+}
+
+pub(crate) fn encode(instruction: Instruction) -> u32 {
+    to_riscv(instruction).expect("generated instructions are encodable")
+}
+
+// Reserved registers: sp, the data region base and loop counters, one per
+// nesting level.
+const SP: u8 = 2;
+const DATA: u8 = 8;
+const LOOP_COUNTERS: [u8; 3] = [9, 18, 19];
+
+#[derive(Debug, Clone)]
+pub struct GeneratorOptions {
+    // ISA of the machine running the programs, extensions outside of the ISA
+    // are not generated.
+    pub isa: u64,
+    // Number of top level blocks.
+    pub blocks: usize,
+    // Maximum number of instructions in a straight-line block.
+    pub block_size: usize,
+    // Maximum nesting of if-blocks and loops, up to 3.
+    pub max_depth: usize,
+    pub max_iterations: u32,
+    // Size of the data region in bytes, a multiple of 8 up to 2048.
+    pub data_size: u32,
+    // Mixes compressed instructions into straight-line blocks.
+    pub compressed: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            isa: ISA_IMC | ISA_A | ISA_B,
+            blocks: 16,
+            block_size: 8,
+            max_depth: 2,
+            max_iterations: 8,
+            data_size: 256,
+            compressed: true,
+        }
+    }
+}
+
+fn push(code: &mut Vec<u8>, instruction: Instruction) {
+    code.extend_from_slice(&encode(instruction).to_le_bytes());
+}
+
+struct Generator<'a, G> {
+    rng: &'a mut G,
+    options: &'a GeneratorOptions,
+    rv64: bool,
+    computations: Vec<(InstructionOpcode, Format)>,
+    writable: Vec<u8>,
+    data_size: i32,
+}
+
+impl<'a, G: Rng> Generator<'a, G> {
+    fn register(&mut self) -> u8 {
+        self.rng.gen_range(0, 32)
+    }
+
+    fn writable_register(&mut self) -> u8 {
+        *self.writable.choose(self.rng).expect("writable registers")
+    }
+
+    fn computation(&mut self, code: &mut Vec<u8>) {
+        if self.options.compressed && self.rng.gen_ratio(1, 4) {
+            return self.compressed(code);
+        }
+        let (op, format) = *self.computations.choose(self.rng).expect("computations");
+        let rd = self.writable_register();
+        let rs1 = self.register();
+        let instruction = match format {
+            Format::R => pack_r(op, rd, rs1, self.register()),
+            Format::I => pack_i(op, rd, rs1, self.rng.gen_range(-2048, 2048)),
+            Format::Shift(bits) => pack_i(op, rd, rs1, self.rng.gen_range(0, 1 << bits)),
+            Format::Unary(rs2) => pack_r(op, rd, rs1, rs2),
+            Format::U => pack_u(op, rd, self.rng.gen::<i32>() & !0xfff),
+            _ => unreachable!("computations only use register and immediate formats"),
+        };
+        push(code, instruction);
+    }
+
+    // C.LI, C.ADDI, C.MV or C.ADD, all with a non-zero destination.
+    fn compressed(&mut self, code: &mut Vec<u8>) {
+        let rd = loop {
+            let rd = self.writable_register();
+            if rd != 0 {
+                break u16::from(rd);
+            }
+        };
+        let rs2 = u16::from(self.rng.gen_range(1u8, 32));
+        let imm = self.rng.gen_range(1u16, 64);
+        let instruction = match self.rng.gen_range(0, 4) {
+            0 => 0b_010 << 13 | (imm & 0x20) << 7 | rd << 7 | (imm & 0x1f) << 2 | 0b_01,
+            1 => (imm & 0x20) << 7 | rd << 7 | (imm & 0x1f) << 2 | 0b_01,
+            2 => 0b_1000 << 12 | rd << 7 | rs2 << 2 | 0b_10,
+            _ => 0b_1001 << 12 | rd << 7 | rs2 << 2 | 0b_10,
+        };
+        code.extend_from_slice(&instruction.to_le_bytes());
+    }
+
+    // A load, store or atomic instruction inside the data region.
+    fn memory(&mut self, code: &mut Vec<u8>) {
+        use insts::*;
+        let mut loads = vec![
+            (OP_LB_VERSION1, 1),
+            (OP_LH_VERSION1, 2),
+            (OP_LW_VERSION1, 4),
+            (OP_LBU_VERSION1, 1),
+            (OP_LHU_VERSION1, 2),
+        ];
+        let mut stores = vec![(OP_SB, 1), (OP_SH, 2), (OP_SW, 4)];
+        let mut atomics = vec![];
+        if self.rv64 {
+            loads.extend_from_slice(&[(OP_LD_VERSION1, 8), (OP_LWU_VERSION1, 4)]);
+            stores.push((OP_SD, 8));
+        }
+        if self.options.isa & ISA_A != 0 {
+            atomics.extend_from_slice(&[OP_AMOSWAP_W, OP_AMOADD_W, OP_AMOXOR_W, OP_AMOMAXU_W]);
+            if self.rv64 {
+                atomics.extend_from_slice(&[OP_AMOSWAP_D, OP_AMOAND_D, OP_AMOOR_D, OP_AMOMIN_D]);
+            }
+        }
+        let data_size = self.data_size;
+        let slots = |width: i32| data_size / width;
+        let instruction = match self.rng.gen_range(0, 3) {
+            0 => {
+                let (op, width) = *loads.choose(self.rng).expect("loads");
+                let offset = self.rng.gen_range(0, slots(width)) * width;
+                pack_i(op, self.writable_register(), DATA, offset)
+            }
+            1 => {
+                let (op, width) = *stores.choose(self.rng).expect("stores");
+                let offset = self.rng.gen_range(0, slots(width)) * width;
+                pack_s(op, DATA, self.register(), offset)
+            }
+            // Atomics take the address from rs1, hence the start of the data
+            // region, which is aligned.
+            _ => match atomics.choose(self.rng) {
+                Some(op) => pack_r(*op, self.writable_register(), DATA, self.register()),
+                None => pack_i(insts::OP_LBU_VERSION1, self.writable_register(), DATA, 0),
+            },
+        };
+        push(code, instruction);
+    }
+
+    fn block(&mut self, depth: usize, code: &mut Vec<u8>) {
+        let kinds = if depth < self.options.max_depth.min(LOOP_COUNTERS.len()) {
+            4
+        } else {
+            2
+        };
+        let size = self.rng.gen_range(1, self.options.block_size.max(1) + 1);
+        match self.rng.gen_range(0, kinds) {
+            0 => (0..size).for_each(|_| self.computation(code)),
+            1 => (0..size).for_each(|_| self.memory(code)),
+            2 => {
+                // Branches over the inner block, if it fits in the branch
+                // range.
+                let mut inner = vec![];
+                self.block(depth + 1, &mut inner);
+                let offset = inner.len() as i32 + 4;
+                if offset < 4096 {
+                    let op = *[
+                        insts::OP_BEQ,
+                        insts::OP_BNE,
+                        insts::OP_BLT,
+                        insts::OP_BGE,
+                        insts::OP_BLTU,
+                        insts::OP_BGEU,
+                    ]
+                    .choose(self.rng)
+                    .expect("branches");
+                    let (rs1, rs2) = (self.register(), self.register());
+                    push(code, pack_b(op, rs1, rs2, offset));
+                }
+                code.extend_from_slice(&inner);
+            }
+            _ => {
+                let counter = LOOP_COUNTERS[depth];
+                let iterations = self
+                    .rng
+                    .gen_range(1, self.options.max_iterations.max(1) + 1);
+                let mut body = vec![];
+                self.block(depth + 1, &mut body);
+                let offset = -(body.len() as i32 + 4);
+                if offset < -4096 {
+                    code.extend_from_slice(&body);
+                    return;
+                }
+                push(code, pack_i(insts::OP_ADDI, counter, 0, iterations as i32));
+                code.extend_from_slice(&body);
+                push(code, pack_i(insts::OP_ADDI, counter, counter, -1));
+                push(code, pack_b(insts::OP_BNE, counter, 0, offset));
+            }
+        }
+    }
+}
+
+pub fn generate_program<R: Register, G: Rng>(rng: &mut G, options: &GeneratorOptions) -> Bytes {
+    let reserved = [
+        SP,
+        DATA,
+        LOOP_COUNTERS[0],
+        LOOP_COUNTERS[1],
+        LOOP_COUNTERS[2],
+    ];
+    let data_size = options.data_size.clamp(8, 2048) as i32 & !7;
+    let mut generator = Generator {
+        rng,
+        options,
+        rv64: R::BITS == 64,
+        computations: opcodes::<R>(options.isa, InstructionOptions::default()),
+        writable: (0..32).filter(|r| !reserved.contains(r)).collect(),
+        data_size,
+    };
+    let mut code = vec![];
+    push(&mut code, pack_i(insts::OP_ADDI, DATA, SP, -data_size));
+    for _ in 0..options.blocks {
+        generator.block(0, &mut code);
+    }
+    push(&mut code, pack_i(insts::OP_ADDI, 10, 0, 0));
+    push(&mut code, pack_i(insts::OP_ADDI, 17, 0, 93));
+    push(&mut code, pack_r(insts::OP_ECALL, 0, 0, 0));
+    minimal_elf::<R>(&code)
+}
+
+pub fn generate_program_from_seed<R: Register>(seed: u64, options: &GeneratorOptions) -> Bytes {
+    generate_program::<R, _>(&mut StdRng::seed_from_u64(seed), options)
+}
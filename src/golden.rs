@@ -0,0 +1,262 @@
+// Golden-state regression tests: each program of a corpus is run under every
+// VM version, and its final state is compared against the state recorded in a
+// golden file. Any change in behavior, including cycles consumed, shows up as
+// a mismatch, which is either a bug or an intended change whose golden files
+// should be updated.
+//
+// let cases = vec![GoldenCase::new("simple64", fs::read("tests/programs/simple64")?.into())];
+// let mismatches = check_golden::<u64>(Path::new("tests/golden"), &cases, GoldenMode::from_env())?;
+// assert!(mismatches.is_empty(), "{:?}", mismatches);
+//
+// Run with CKB_VM_UPDATE_GOLDEN=1 to (re)write the golden files instead.
+use crate::{
+    cost_model::estimate_cycles,
+    machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION0, VERSION1, VERSION2},
+    memory::{flat::FlatMemory, wxorx::WXorXMemory, Memory},
+    Bytes, CoreMachine, Error, Register, SupportMachine, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+    RISCV_GENERAL_REGISTER_NUMBER,
+};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const UPDATE_GOLDEN_ENV: &str = "CKB_VM_UPDATE_GOLDEN";
+
+#[derive(Debug, Clone)]
+pub struct GoldenCase {
+    // Also the name of the golden file, without the .golden extension.
+    pub name: String,
+    pub program: Bytes,
+    pub args: Vec<Bytes>,
+    pub isa: u64,
+    pub versions: Vec<u32>,
+    pub max_cycles: u64,
+}
+
+impl GoldenCase {
+    // A case running with all extensions, under all VM versions.
+    pub fn new(name: &str, program: Bytes) -> Self {
+        Self {
+            name: name.to_string(),
+            args: vec![Bytes::from(name.to_string())],
+            program,
+            isa: ISA_IMC | ISA_A | ISA_B | ISA_MOP,
+            versions: vec![VERSION0, VERSION1, VERSION2],
+            max_cycles: u64::MAX,
+        }
+    }
+}
+
+// Final state of a program run under one VM version, with cycles measured by
+// cost_model::estimate_cycles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenState {
+    pub version: u32,
+    // "exit <code>", or the error stopping the program.
+    pub result: String,
+    pub cycles: u64,
+    pub pc: u64,
+    pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
+    // FNV-1a hash of the whole memory.
+    pub memory_hash: u64,
+}
+
+impl fmt::Display for GoldenState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version {}", self.version)?;
+        writeln!(f, "result {}", self.result)?;
+        writeln!(f, "cycles {}", self.cycles)?;
+        writeln!(f, "pc 0x{:x}", self.pc)?;
+        writeln!(f, "memory_hash 0x{:016x}", self.memory_hash)?;
+        for (i, value) in self.registers.iter().enumerate() {
+            writeln!(f, "x{} 0x{:x}", i, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl GoldenState {
+    // Parses states written by Display, separated by blank lines.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, Error> {
+        let invalid = |line: &str| Error::Unexpected(format!("Invalid golden line: {}", line));
+        let hex = |value: &str, line: &str| {
+            u64::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| invalid(line))
+        };
+        let mut states = vec![];
+        for block in text.split("\n\n").filter(|b| !b.trim().is_empty()) {
+            let mut state = GoldenState {
+                version: 0,
+                result: String::new(),
+                cycles: 0,
+                pc: 0,
+                registers: [0; RISCV_GENERAL_REGISTER_NUMBER],
+                memory_hash: 0,
+            };
+            for line in block.lines().filter(|l| !l.trim().is_empty()) {
+                let (key, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+                match key {
+                    "version" => state.version = value.parse().map_err(|_| invalid(line))?,
+                    "result" => state.result = value.to_string(),
+                    "cycles" => state.cycles = value.parse().map_err(|_| invalid(line))?,
+                    "pc" => state.pc = hex(value, line)?,
+                    "memory_hash" => state.memory_hash = hex(value, line)?,
+                    _ => {
+                        let index = key
+                            .strip_prefix('x')
+                            .and_then(|i| i.parse::<usize>().ok())
+                            .filter(|i| *i < RISCV_GENERAL_REGISTER_NUMBER)
+                            .ok_or_else(|| invalid(line))?;
+                        state.registers[index] = hex(value, line)?;
+                    }
+                }
+            }
+            states.push(state);
+        }
+        Ok(states)
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn run_golden_case<R: Register>(case: &GoldenCase, version: u32) -> Result<GoldenState, Error> {
+    let core_machine = DefaultCoreMachine::<R, WXorXMemory<FlatMemory<R>>>::new(
+        case.isa,
+        version,
+        case.max_cycles,
+    );
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(estimate_cycles))
+        .build();
+    // Errors raised by the program are part of its behavior, hence recorded
+    // rather than returned. So are errors loading it, as loading differs
+    // between versions too, e.g. VERSION0 rejects some segment layouts.
+    let result = match machine.load_program(&case.program, &case.args) {
+        Ok(_) => match machine.run() {
+            Ok(code) => format!("exit {}", code),
+            Err(e) => e.to_string(),
+        },
+        Err(e) => format!("load error: {}", e),
+    };
+    let mut registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
+    for (value, register) in registers.iter_mut().zip(machine.registers()) {
+        *value = register.to_u64();
+    }
+    let memory_size = machine.memory().memory_size() as u64;
+    let memory = machine.memory_mut().load_bytes(0, memory_size)?;
+    Ok(GoldenState {
+        version,
+        result,
+        cycles: machine.cycles(),
+        pc: machine.pc().to_u64(),
+        registers,
+        memory_hash: fnv1a(&memory),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenMode {
+    Check,
+    // Writes the current states as golden files.
+    Update,
+}
+
+impl GoldenMode {
+    // Update when CKB_VM_UPDATE_GOLDEN is set to a non-empty value.
+    pub fn from_env() -> Self {
+        match std::env::var(UPDATE_GOLDEN_ENV) {
+            Ok(value) if !value.is_empty() => GoldenMode::Update,
+            _ => GoldenMode::Check,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenMismatch {
+    pub case: String,
+    pub version: u32,
+    // None if the golden file has no state for this version.
+    pub expected: Option<GoldenState>,
+    pub actual: GoldenState,
+}
+
+impl fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} version {}: ", self.case, self.version)?;
+        let expected = match &self.expected {
+            Some(expected) => expected,
+            None => return write!(f, "no golden state"),
+        };
+        let actual = &self.actual;
+        let mut fields = vec![];
+        if expected.result != actual.result {
+            fields.push(format!("result {} != {}", actual.result, expected.result));
+        }
+        if expected.cycles != actual.cycles {
+            fields.push(format!("cycles {} != {}", actual.cycles, expected.cycles));
+        }
+        if expected.pc != actual.pc {
+            fields.push(format!("pc 0x{:x} != 0x{:x}", actual.pc, expected.pc));
+        }
+        for i in 0..RISCV_GENERAL_REGISTER_NUMBER {
+            if expected.registers[i] != actual.registers[i] {
+                fields.push(format!(
+                    "x{} 0x{:x} != 0x{:x}",
+                    i, actual.registers[i], expected.registers[i]
+                ));
+            }
+        }
+        if expected.memory_hash != actual.memory_hash {
+            fields.push(String::from("memory differs"));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+// Runs all cases, then either compares their states against the golden files
+// in `dir`, or rewrites the golden files. Mismatches are returned, a missing
+// golden file is an error in check mode.
+pub fn check_golden<R: Register>(
+    dir: &Path,
+    cases: &[GoldenCase],
+    mode: GoldenMode,
+) -> Result<Vec<GoldenMismatch>, Error> {
+    let mut mismatches = vec![];
+    for case in cases {
+        let path = dir.join(format!("{}.golden", case.name));
+        let states = case
+            .versions
+            .iter()
+            .map(|version| run_golden_case::<R>(case, *version))
+            .collect::<Result<Vec<_>, _>>()?;
+        if mode == GoldenMode::Update {
+            fs::create_dir_all(dir)?;
+            let text: Vec<String> = states.iter().map(|s| s.to_string()).collect();
+            fs::write(&path, text.join("\n"))?;
+            continue;
+        }
+        let text = fs::read_to_string(&path).map_err(|_| {
+            Error::Unexpected(format!(
+                "Missing golden file {}, run with {}=1 to create it",
+                path.display(),
+                UPDATE_GOLDEN_ENV
+            ))
+        })?;
+        let expected = GoldenState::parse_all(&text)?;
+        for actual in states {
+            let expected = expected.iter().find(|s| s.version == actual.version);
+            if expected != Some(&actual) {
+                mismatches.push(GoldenMismatch {
+                    case: case.name.clone(),
+                    version: actual.version,
+                    expected: expected.cloned(),
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
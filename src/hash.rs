@@ -0,0 +1,161 @@
+// BLAKE2b as specified in RFC 7693, with the parameters of CKB's default
+// hash: 32 byte digests personalized with "ckb-default-hash". Hashing a
+// program thus gives the data hash of the cell holding it on chain, which is
+// how programs are identified, see program_hash.
+
+pub const HASH_LENGTH: usize = 32;
+pub const CKB_HASH_PERSONALIZATION: &[u8; 16] = b"ckb-default-hash";
+
+const BLOCK_LENGTH: usize = 128;
+
+const IV: [u64; 8] = [
+    0x6a09_e667_f3bc_c908,
+    0xbb67_ae85_84ca_a73b,
+    0x3c6e_f372_fe94_f82b,
+    0xa54f_f53a_5f1d_36f1,
+    0x510e_527f_ade6_82d1,
+    0x9b05_688c_2b3e_6c1f,
+    0x1f83_d9ab_fb41_bd6b,
+    0x5be0_cd19_137e_2179,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+fn le64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+// Incremental BLAKE2b hasher, for data which isn't in one piece.
+#[derive(Clone)]
+pub struct Blake2b {
+    h: [u64; 8],
+    // Bytes hashed so far.
+    t: u128,
+    buffer: [u8; BLOCK_LENGTH],
+    buffered: usize,
+    length: usize,
+}
+
+impl Blake2b {
+    // Unkeyed hasher with `length` bytes digests, 1 to 64, and a 16 bytes
+    // personalization.
+    pub fn new(length: usize, personalization: &[u8; 16]) -> Self {
+        assert!(length > 0 && length <= 64);
+        let mut h = IV;
+        h[0] ^= 0x0101_0000 ^ length as u64;
+        h[6] ^= le64(&personalization[..8]);
+        h[7] ^= le64(&personalization[8..]);
+        Self {
+            h,
+            t: 0,
+            buffer: [0; BLOCK_LENGTH],
+            buffered: 0,
+            length,
+        }
+    }
+
+    // Hasher of CKB's default hash.
+    pub fn new_ckb() -> Self {
+        Self::new(HASH_LENGTH, CKB_HASH_PERSONALIZATION)
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The last block is only compressed in finalize, so a full
+            // buffer is flushed only once more data arrives.
+            if self.buffered == BLOCK_LENGTH {
+                self.t += BLOCK_LENGTH as u128;
+                let block = self.buffer;
+                self.compress(&block, false);
+                self.buffered = 0;
+            }
+            let bytes = (BLOCK_LENGTH - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + bytes].copy_from_slice(&data[..bytes]);
+            self.buffered += bytes;
+            data = &data[bytes..];
+        }
+    }
+
+    // Writes the digest to `out`, which must be as long as the digest.
+    pub fn finalize(mut self, out: &mut [u8]) {
+        assert_eq!(out.len(), self.length);
+        self.t += self.buffered as u128;
+        let mut block = [0u8; BLOCK_LENGTH];
+        block[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+        self.compress(&block, true);
+        let mut digest = [0u8; 64];
+        for (chunk, word) in digest.chunks_mut(8).zip(self.h.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        out.copy_from_slice(&digest[..self.length]);
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_LENGTH], last: bool) {
+        let mut m = [0u64; 16];
+        for (word, chunk) in m.iter_mut().zip(block.chunks(8)) {
+            *word = le64(chunk);
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.t as u64;
+        v[13] ^= (self.t >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+        for round in 0..12 {
+            let s = &SIGMA[round % 10];
+            mix(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            mix(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            mix(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            mix(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            mix(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            mix(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            mix(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            mix(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+        for (i, word) in self.h.iter_mut().enumerate() {
+            *word ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+// CKB's default hash of `data`.
+pub fn blake2b_256(data: &[u8]) -> [u8; HASH_LENGTH] {
+    let mut hasher = Blake2b::new_ckb();
+    hasher.update(data);
+    let mut hash = [0u8; HASH_LENGTH];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+// Fingerprint identifying a program binary, the CKB hash of its bytes.
+pub type ProgramHash = [u8; HASH_LENGTH];
+
+pub fn program_hash(program: &[u8]) -> ProgramHash {
+    blake2b_256(program)
+}
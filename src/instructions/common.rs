@@ -7,6 +7,33 @@ use super::{Error, RegisterIndex, SImmediate, UImmediate};
 
 // Other instruction set functions common with RVC
 
+// Writes rd with a value computed from rs1 and rs2, which are borrowed from
+// the register file in place rather than cloned out of it one by one.
+#[inline(always)]
+fn rtype<Mac: Machine>(
+    machine: &mut Mac,
+    rd: RegisterIndex,
+    rs1: RegisterIndex,
+    rs2: RegisterIndex,
+    op: impl FnOnce(&Mac::REG, &Mac::REG) -> Mac::REG,
+) {
+    let registers = machine.registers();
+    let value = op(&registers[rs1 as usize], &registers[rs2 as usize]);
+    update_register(machine, rd, value);
+}
+
+// Writes rd with a value computed from rs1, see rtype.
+#[inline(always)]
+fn itype<Mac: Machine>(
+    machine: &mut Mac,
+    rd: RegisterIndex,
+    rs1: RegisterIndex,
+    op: impl FnOnce(&Mac::REG) -> Mac::REG,
+) {
+    let value = op(&machine.registers()[rs1 as usize]);
+    update_register(machine, rd, value);
+}
+
 // ======================
 // #  ALU instructions  #
 // ======================
@@ -16,10 +43,7 @@ pub fn add<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = &machine.registers()[rs1 as usize];
-    let rs2_value = &machine.registers()[rs2 as usize];
-    let value = rs1_value.overflowing_add(rs2_value);
-    update_register(machine, rd, value);
+    rtype(machine, rd, rs1, rs2, |a, b| a.overflowing_add(b));
 }
 
 pub fn addw<Mac: Machine>(
@@ -28,10 +52,9 @@ pub fn addw<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = &machine.registers()[rs1 as usize];
-    let rs2_value = &machine.registers()[rs2 as usize];
-    let value = rs1_value.overflowing_add(rs2_value);
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    rtype(machine, rd, rs1, rs2, |a, b| {
+        a.overflowing_add(b).sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 pub fn sub<Mac: Machine>(
@@ -40,10 +63,7 @@ pub fn sub<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = &machine.registers()[rs1 as usize];
-    let rs2_value = &machine.registers()[rs2 as usize];
-    let value = rs1_value.overflowing_sub(rs2_value);
-    update_register(machine, rd, value);
+    rtype(machine, rd, rs1, rs2, |a, b| a.overflowing_sub(b));
 }
 
 pub fn subw<Mac: Machine>(
@@ -52,10 +72,9 @@ pub fn subw<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = &machine.registers()[rs1 as usize];
-    let rs2_value = &machine.registers()[rs2 as usize];
-    let value = rs1_value.overflowing_sub(rs2_value);
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    rtype(machine, rd, rs1, rs2, |a, b| {
+        a.overflowing_sub(b).sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 pub fn addi<Mac: Machine>(
@@ -64,8 +83,9 @@ pub fn addi<Mac: Machine>(
     rs1: RegisterIndex,
     imm: SImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| {
+        a.overflowing_add(&Mac::REG::from_i32(imm))
+    });
 }
 
 pub fn addiw<Mac: Machine>(
@@ -74,8 +94,10 @@ pub fn addiw<Mac: Machine>(
     rs1: RegisterIndex,
     imm: SImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    itype(machine, rd, rs1, |a| {
+        a.overflowing_add(&Mac::REG::from_i32(imm))
+            .sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 // =======================
@@ -201,8 +223,9 @@ pub fn sb<Mac: Machine>(
     rs2: RegisterIndex,
     imm: SImmediate,
 ) -> Result<(), Error> {
-    let address = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    let value = machine.registers()[rs2 as usize].clone();
+    let registers = machine.registers();
+    let address = registers[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
+    let value = registers[rs2 as usize].clone();
     machine.memory_mut().store8(&address, &value)?;
     Ok(())
 }
@@ -213,8 +236,9 @@ pub fn sh<Mac: Machine>(
     rs2: RegisterIndex,
     imm: SImmediate,
 ) -> Result<(), Error> {
-    let address = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    let value = machine.registers()[rs2 as usize].clone();
+    let registers = machine.registers();
+    let address = registers[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
+    let value = registers[rs2 as usize].clone();
     machine.memory_mut().store16(&address, &value)?;
     Ok(())
 }
@@ -225,8 +249,9 @@ pub fn sw<Mac: Machine>(
     rs2: RegisterIndex,
     imm: SImmediate,
 ) -> Result<(), Error> {
-    let address = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    let value = machine.registers()[rs2 as usize].clone();
+    let registers = machine.registers();
+    let address = registers[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
+    let value = registers[rs2 as usize].clone();
     machine.memory_mut().store32(&address, &value)?;
     Ok(())
 }
@@ -237,8 +262,9 @@ pub fn sd<Mac: Machine>(
     rs2: RegisterIndex,
     imm: SImmediate,
 ) -> Result<(), Error> {
-    let address = machine.registers()[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
-    let value = machine.registers()[rs2 as usize].clone();
+    let registers = machine.registers();
+    let address = registers[rs1 as usize].overflowing_add(&Mac::REG::from_i32(imm));
+    let value = registers[rs2 as usize].clone();
     machine.memory_mut().store64(&address, &value)?;
     Ok(())
 }
@@ -252,10 +278,7 @@ pub fn and<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = machine.registers()[rs1 as usize].clone();
-    let rs2_value = machine.registers()[rs2 as usize].clone();
-    let value = rs1_value & rs2_value;
-    update_register(machine, rd, value);
+    rtype(machine, rd, rs1, rs2, |a, b| a.bit_and(b));
 }
 
 pub fn xor<Mac: Machine>(
@@ -264,10 +287,7 @@ pub fn xor<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = machine.registers()[rs1 as usize].clone();
-    let rs2_value = machine.registers()[rs2 as usize].clone();
-    let value = rs1_value ^ rs2_value;
-    update_register(machine, rd, value);
+    rtype(machine, rd, rs1, rs2, |a, b| a.bit_xor(b));
 }
 
 pub fn or<Mac: Machine>(
@@ -276,10 +296,7 @@ pub fn or<Mac: Machine>(
     rs1: RegisterIndex,
     rs2: RegisterIndex,
 ) {
-    let rs1_value = machine.registers()[rs1 as usize].clone();
-    let rs2_value = machine.registers()[rs2 as usize].clone();
-    let value = rs1_value | rs2_value;
-    update_register(machine, rd, value);
+    rtype(machine, rd, rs1, rs2, |a, b| a.bit_or(b));
 }
 
 pub fn andi<Mac: Machine>(
@@ -288,8 +305,7 @@ pub fn andi<Mac: Machine>(
     rs1: RegisterIndex,
     imm: SImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() & Mac::REG::from_i32(imm);
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| a.bit_and(&Mac::REG::from_i32(imm)));
 }
 
 pub fn xori<Mac: Machine>(
@@ -298,8 +314,7 @@ pub fn xori<Mac: Machine>(
     rs1: RegisterIndex,
     imm: SImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() ^ Mac::REG::from_i32(imm);
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| a.bit_xor(&Mac::REG::from_i32(imm)));
 }
 
 pub fn ori<Mac: Machine>(
@@ -308,8 +323,7 @@ pub fn ori<Mac: Machine>(
     rs1: RegisterIndex,
     imm: SImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() | Mac::REG::from_i32(imm);
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| a.bit_or(&Mac::REG::from_i32(imm)));
 }
 
 pub fn slli<Mac: Machine>(
@@ -318,8 +332,9 @@ pub fn slli<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() << Mac::REG::from_u32(shamt);
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| {
+        a.logical_shl(&Mac::REG::from_u32(shamt))
+    });
 }
 
 pub fn srli<Mac: Machine>(
@@ -328,8 +343,9 @@ pub fn srli<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() >> Mac::REG::from_u32(shamt);
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| {
+        a.logical_shr(&Mac::REG::from_u32(shamt))
+    });
 }
 
 pub fn srai<Mac: Machine>(
@@ -338,8 +354,9 @@ pub fn srai<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].signed_shr(&Mac::REG::from_u32(shamt));
-    update_register(machine, rd, value);
+    itype(machine, rd, rs1, |a| {
+        a.signed_shr(&Mac::REG::from_u32(shamt))
+    });
 }
 
 pub fn slliw<Mac: Machine>(
@@ -348,8 +365,10 @@ pub fn slliw<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].clone() << Mac::REG::from_u32(shamt);
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    itype(machine, rd, rs1, |a| {
+        a.logical_shl(&Mac::REG::from_u32(shamt))
+            .sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 pub fn srliw<Mac: Machine>(
@@ -358,9 +377,11 @@ pub fn srliw<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize].zero_extend(&Mac::REG::from_u8(32))
-        >> Mac::REG::from_u32(shamt);
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    itype(machine, rd, rs1, |a| {
+        a.zero_extend(&Mac::REG::from_u8(32))
+            .logical_shr(&Mac::REG::from_u32(shamt))
+            .sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 pub fn sraiw<Mac: Machine>(
@@ -369,10 +390,11 @@ pub fn sraiw<Mac: Machine>(
     rs1: RegisterIndex,
     shamt: UImmediate,
 ) {
-    let value = machine.registers()[rs1 as usize]
-        .sign_extend(&Mac::REG::from_u8(32))
-        .signed_shr(&Mac::REG::from_u32(shamt));
-    update_register(machine, rd, value.sign_extend(&Mac::REG::from_u8(32)));
+    itype(machine, rd, rs1, |a| {
+        a.sign_extend(&Mac::REG::from_u8(32))
+            .signed_shr(&Mac::REG::from_u32(shamt))
+            .sign_extend(&Mac::REG::from_u8(32))
+    });
 }
 
 // =======================
@@ -8,7 +8,11 @@ pub mod ast;
 pub mod b;
 pub mod i;
 pub mod m;
+pub mod privileged;
 pub mod rvc;
+pub mod rvf;
+#[cfg(feature = "rvv")]
+pub mod rvv;
 pub mod tagged;
 
 pub use self::register::Register;
@@ -20,7 +24,7 @@ pub use ckb_vm_definitions::{
     registers::REGISTER_ABI_NAMES,
 };
 use core::fmt;
-pub use execute::{execute, execute_instruction};
+pub use execute::{execute, execute_instruction, HandlerTable, InstructionHandler};
 
 pub type RegisterIndex = usize;
 pub type SImmediate = i32;
@@ -33,6 +37,12 @@ pub fn extract_opcode(i: Instruction) -> InstructionOpcode {
 
 pub type InstructionFactory = fn(instruction_bits: u32, version: u32) -> Option<Instruction>;
 
+// Factory of 48-bit and 64-bit instructions, `instruction_bits` holds the
+// `length` bytes of the encoding, 6 or 8. The decoder sets the length of the
+// returned instruction.
+pub type LongInstructionFactory =
+    fn(instruction_bits: u64, length: u8, version: u32) -> Option<Instruction>;
+
 // Blank instructions need no register indices nor immediates, they only have opcode
 // and module bit set.
 pub fn blank_instruction(op: InstructionOpcode) -> Instruction {
@@ -409,6 +419,10 @@ pub fn is_slowpath_instruction(i: Instruction) -> bool {
     (i as u8 as u16) < MINIMAL_OPCODE
 }
 
+// Blocks are charged up front, see DefaultMachine::charge_block, so every
+// instruction observing cycles, ECALL, EBREAK and the CSR instructions reading
+// the counters, has to end its block. CSR instructions are slow path ones too,
+// they are listed anyway so that this does not hinge on opcode numbering.
 pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
     matches!(
         extract_opcode(i),
@@ -426,6 +440,13 @@ pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
             | insts::OP_JAL
             | insts::OP_FAR_JUMP_ABS
             | insts::OP_FAR_JUMP_REL
+            | insts::OP_CSRRW
+            | insts::OP_CSRRS
+            | insts::OP_CSRRC
+            | insts::OP_CSRRWI
+            | insts::OP_CSRRSI
+            | insts::OP_CSRRCI
+            | insts::OP_MRET
     ) | is_slowpath_instruction(i)
 }
 
@@ -446,6 +467,10 @@ pub fn set_instruction_length_n(i: u64, n: u8) -> u64 {
     i | ((n as u64 & 0x1f) >> 1 << 24)
 }
 
+// Longest encoding decoded, see decoder::encoding_length. Fused
+// instructions may be longer.
+pub const MAX_INSTRUCTION_LENGTH: u8 = 8;
+
 #[inline(always)]
 pub fn instruction_length(i: Instruction) -> u8 {
     (((i >> 24) & 0x0f) << 1) as u8
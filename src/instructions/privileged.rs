@@ -0,0 +1,118 @@
+use ckb_vm_definitions::encoding::to_riscv;
+use ckb_vm_definitions::instructions as insts;
+
+use super::utils::{funct3, opcode, rd, rs1, update_register};
+use super::{blank_instruction, set_instruction_length_4, Instruction, Itype, Register};
+use crate::machine::float::{CSR_FCSR, CSR_FFLAGS};
+use crate::machine::privileged::{raise_exception, CsrFile, CAUSE_ILLEGAL_INSTRUCTION};
+#[cfg(feature = "rvv")]
+use crate::machine::vector::VectorRegisters;
+use crate::machine::Machine;
+use crate::Error;
+
+// Decodes the privileged instructions enabled by ISA_PRIV: CSR accesses and
+// MRET, see machine::privileged.
+pub fn factory<R: Register>(instruction_bits: u32, version: u32) -> Option<Instruction> {
+    if instruction_bits == 0b_001100000010_00000_000_00000_1110011 {
+        return Some(set_instruction_length_4(blank_instruction(insts::OP_MRET)));
+    }
+    zicsr_factory::<R>(instruction_bits, version)
+}
+
+// Decodes the CSR accesses of Zicsr, enabled by ISA_ZICSR, see
+// machine::csr.
+pub fn zicsr_factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    if opcode(instruction_bits) != 0b_1110011 {
+        return None;
+    }
+    let op = match funct3(instruction_bits) {
+        0b_001 => insts::OP_CSRRW,
+        0b_010 => insts::OP_CSRRS,
+        0b_011 => insts::OP_CSRRC,
+        0b_101 => insts::OP_CSRRWI,
+        0b_110 => insts::OP_CSRRSI,
+        0b_111 => insts::OP_CSRRCI,
+        _ => return None,
+    };
+    let csr = instruction_bits >> 20;
+    Some(set_instruction_length_4(
+        Itype::new_u(op, rd(instruction_bits), rs1(instruction_bits), csr).0,
+    ))
+}
+
+pub(crate) fn illegal_instruction<Mac: Machine>(
+    machine: &mut Mac,
+    inst: Instruction,
+) -> Result<(), Error> {
+    let bits = to_riscv(inst).unwrap_or(0);
+    let error = Error::InvalidInstruction {
+        pc: machine.pc().to_u64(),
+        instruction: bits,
+    };
+    raise_exception(machine, CAUSE_ILLEGAL_INSTRUCTION, u64::from(bits), error)
+}
+
+// CSRs holding `csr`. The floating point and vector CSRs are available
+// without ISA_PRIV. Other CSRs missing here go to the CSR provider of the
+// machine.
+fn csr_file<Mac: Machine>(machine: &mut Mac, csr: u16) -> Option<&mut dyn CsrFile> {
+    if (CSR_FFLAGS..=CSR_FCSR).contains(&csr) && machine.float_registers().is_some() {
+        return machine.float_registers_mut().map(|f| f as &mut dyn CsrFile);
+    }
+    #[cfg(feature = "rvv")]
+    if VectorRegisters::is_csr(csr) && machine.vector_registers().is_some() {
+        return machine
+            .vector_registers_mut()
+            .map(|v| v as &mut dyn CsrFile);
+    }
+    machine.privileged_mut().map(|p| p as &mut dyn CsrFile)
+}
+
+// Executes CSRRW, CSRRS, CSRRC and their immediate variants, on the
+// privileged, floating point, vector or provided CSRs.
+pub fn execute_csr<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let i = Itype(inst);
+    let op = i.op();
+    let csr = i.immediate_u() as u16;
+    let operand = match op {
+        insts::OP_CSRRWI | insts::OP_CSRRSI | insts::OP_CSRRCI => i.rs1() as u64,
+        _ => machine.registers()[i.rs1()].to_u64(),
+    };
+    // CSRRS and CSRRC do not write the CSR when rs1 is zero.
+    let writes = matches!(op, insts::OP_CSRRW | insts::OP_CSRRWI) || i.rs1() != 0;
+    let update = |old: u64| match op {
+        insts::OP_CSRRW | insts::OP_CSRRWI => operand,
+        insts::OP_CSRRS | insts::OP_CSRRSI => old | operand,
+        _ => old & !operand,
+    };
+    let old = match csr_file(machine, csr) {
+        Some(file) if file.read_csr(csr).is_some() => match file.read_csr(csr) {
+            Some(old) if writes => file.write_csr(csr, update(old)).map(|_| old),
+            old => old,
+        },
+        _ => match machine.read_provided_csr(csr) {
+            Some(old) if writes => machine.write_provided_csr(csr, update(old)).map(|_| old),
+            old => old,
+        },
+    };
+    let old = match old {
+        Some(old) => old,
+        None => return illegal_instruction(machine, inst),
+    };
+    update_register(machine, i.rd(), Mac::REG::from_u64(old));
+    Ok(())
+}
+
+pub fn execute_mret<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let target = machine
+        .privileged_mut()
+        .ok_or(Error::InvalidOp(insts::OP_MRET))?
+        .mret();
+    match target {
+        Some(pc) => {
+            machine.update_pc(Mac::REG::from_u64(pc));
+            Ok(())
+        }
+        None => illegal_instruction(machine, inst),
+    }
+}
@@ -123,6 +123,29 @@ pub trait Register:
     fn ge_s(&self, other: &Self) -> Self {
         self.lt_s(other).logical_not()
     }
+
+    // Operators on borrowed values, so that instructions can compute with
+    // registers in place instead of cloning them out first. The defaults
+    // clone both operands, u32 and u64 override them with plain copies.
+    fn bit_and(&self, rhs: &Self) -> Self {
+        self.clone() & rhs.clone()
+    }
+
+    fn bit_or(&self, rhs: &Self) -> Self {
+        self.clone() | rhs.clone()
+    }
+
+    fn bit_xor(&self, rhs: &Self) -> Self {
+        self.clone() ^ rhs.clone()
+    }
+
+    fn logical_shl(&self, rhs: &Self) -> Self {
+        self.clone() << rhs.clone()
+    }
+
+    fn logical_shr(&self, rhs: &Self) -> Self {
+        self.clone() >> rhs.clone()
+    }
 }
 
 impl Register for u32 {
@@ -253,6 +276,26 @@ impl Register for u32 {
         (*self as i32).shr(*rhs) as u32
     }
 
+    fn bit_and(&self, rhs: &u32) -> u32 {
+        *self & *rhs
+    }
+
+    fn bit_or(&self, rhs: &u32) -> u32 {
+        *self | *rhs
+    }
+
+    fn bit_xor(&self, rhs: &u32) -> u32 {
+        *self ^ *rhs
+    }
+
+    fn logical_shl(&self, rhs: &u32) -> u32 {
+        *self << *rhs
+    }
+
+    fn logical_shr(&self, rhs: &u32) -> u32 {
+        *self >> *rhs
+    }
+
     fn zero_extend(&self, start_bit: &u32) -> u32 {
         let start_bit = min(*start_bit, 32);
         debug_assert!(start_bit > 0);
@@ -538,6 +581,26 @@ impl Register for u64 {
         (*self as i64).shr(*rhs) as u64
     }
 
+    fn bit_and(&self, rhs: &u64) -> u64 {
+        *self & *rhs
+    }
+
+    fn bit_or(&self, rhs: &u64) -> u64 {
+        *self | *rhs
+    }
+
+    fn bit_xor(&self, rhs: &u64) -> u64 {
+        *self ^ *rhs
+    }
+
+    fn logical_shl(&self, rhs: &u64) -> u64 {
+        *self << *rhs
+    }
+
+    fn logical_shr(&self, rhs: &u64) -> u64 {
+        *self >> *rhs
+    }
+
     fn zero_extend(&self, start_bit: &u64) -> u64 {
         let start_bit = min(*start_bit, 64);
         debug_assert!(start_bit > 0);
@@ -0,0 +1,389 @@
+use ckb_vm_definitions::instructions as insts;
+
+use super::utils::{funct3, funct7, itype_immediate, opcode, rd, rs1, rs2, stype_immediate};
+use super::utils::{update_register, x};
+use super::{
+    extract_opcode, privileged, set_instruction_length_4, Instruction, InstructionOpcode, Itype,
+    R4type, R5type, Register, Rtype, Stype,
+};
+use crate::float::{FloatBackend, IntFormat, Precision, RoundingMode, SoftFloat};
+use crate::machine::float::{FloatRegisters, CSR_FCSR, CSR_FFLAGS, RM_DYNAMIC};
+use crate::machine::Machine;
+use crate::memory::Memory;
+use crate::Error;
+
+// Instructions of the F and D extensions, enabled by ISA_F and ISA_D. Both
+// groups of opcodes share their op2, so an instruction is decoded into its
+// single precision opcode, then moved to the group of its precision.
+//
+// Operands are packed as follows, rm being the rounding mode field:
+// * FLW/FLD: Itype, FSW/FSD: Stype;
+// * Instructions taking a rounding mode: R4type(rd, rs1, rs2, rm), FSQRT
+//   and FCVT keep the raw rs2 field selecting the operation;
+// * FMADD, FMSUB, FNMSUB and FNMADD: R5type(rd, rs1, rs2, rs3, rm);
+// * Other instructions: Rtype.
+//
+// Arithmetic is computed by SoftFloat, so results are the same on every host.
+
+// Moves a single precision opcode to the group of precision `p`.
+fn group(op: InstructionOpcode, p: Precision) -> InstructionOpcode {
+    match p {
+        Precision::Single => op,
+        Precision::Double => op & 0xff00 | 0x03,
+    }
+}
+
+// Opcode of the single precision instruction with the same op2.
+fn single(op: InstructionOpcode) -> InstructionOpcode {
+    op & 0xff00 | 0x02
+}
+
+fn precision(op: InstructionOpcode) -> Precision {
+    if op & 0xff == 0x03 {
+        Precision::Double
+    } else {
+        Precision::Single
+    }
+}
+
+// Static rounding modes 5 and 6 are reserved.
+fn valid_rm(rm: u32) -> bool {
+    rm <= 4 || rm == u32::from(RM_DYNAMIC)
+}
+
+fn decode<R: Register>(instruction_bits: u32, p: Precision) -> Option<Instruction> {
+    if R::BITS != 64 {
+        return None;
+    }
+    let fmt = match p {
+        Precision::Single => 0b00,
+        Precision::Double => 0b01,
+    };
+    let width = match p {
+        Precision::Single => 0b010,
+        Precision::Double => 0b011,
+    };
+    let rm = funct3(instruction_bits);
+    let (rd, rs1, rs2) = (
+        rd(instruction_bits),
+        rs1(instruction_bits),
+        rs2(instruction_bits),
+    );
+    let inst = match opcode(instruction_bits) {
+        0b_0000111 if funct3(instruction_bits) == width => {
+            Itype::new_s(
+                group(insts::OP_FLW, p),
+                rd,
+                rs1,
+                itype_immediate(instruction_bits),
+            )
+            .0
+        }
+        0b_0100111 if funct3(instruction_bits) == width => {
+            Stype::new_s(
+                group(insts::OP_FSW, p),
+                stype_immediate(instruction_bits),
+                rs1,
+                rs2,
+            )
+            .0
+        }
+        op @ (0b_1000011 | 0b_1000111 | 0b_1001011 | 0b_1001111) => {
+            if x(instruction_bits, 25, 2, 0) != fmt || !valid_rm(rm) {
+                return None;
+            }
+            let op = match op {
+                0b_1000011 => insts::OP_FMADD_S,
+                0b_1000111 => insts::OP_FMSUB_S,
+                0b_1001011 => insts::OP_FNMSUB_S,
+                _ => insts::OP_FNMADD_S,
+            };
+            let rs3 = x(instruction_bits, 27, 5, 0) as usize;
+            R5type::new(group(op, p), rd, rs1, rs2, rs3, rm as usize).0
+        }
+        0b_1010011 => {
+            let funct7 = funct7(instruction_bits);
+            if funct7 & 0b11 != fmt {
+                return None;
+            }
+            let with_rm = |op| {
+                if valid_rm(rm) {
+                    Some(R4type::new(group(op, p), rd, rs1, rs2, rm as usize).0)
+                } else {
+                    None
+                }
+            };
+            let without_rm = |op| Some(Rtype::new(group(op, p), rd, rs1, rs2).0);
+            match (funct7 >> 2, rm, rs2) {
+                (0b_00000, _, _) => with_rm(insts::OP_FADD_S),
+                (0b_00001, _, _) => with_rm(insts::OP_FSUB_S),
+                (0b_00010, _, _) => with_rm(insts::OP_FMUL_S),
+                (0b_00011, _, _) => with_rm(insts::OP_FDIV_S),
+                (0b_01011, _, 0) => with_rm(insts::OP_FSQRT_S),
+                (0b_00100, 0b_000, _) => without_rm(insts::OP_FSGNJ_S),
+                (0b_00100, 0b_001, _) => without_rm(insts::OP_FSGNJN_S),
+                (0b_00100, 0b_010, _) => without_rm(insts::OP_FSGNJX_S),
+                (0b_00101, 0b_000, _) => without_rm(insts::OP_FMIN_S),
+                (0b_00101, 0b_001, _) => without_rm(insts::OP_FMAX_S),
+                (0b_10100, 0b_010, _) => without_rm(insts::OP_FEQ_S),
+                (0b_10100, 0b_001, _) => without_rm(insts::OP_FLT_S),
+                (0b_10100, 0b_000, _) => without_rm(insts::OP_FLE_S),
+                (0b_11100, 0b_000, 0) => without_rm(insts::OP_FMV_X_W),
+                (0b_11100, 0b_001, 0) => without_rm(insts::OP_FCLASS_S),
+                (0b_11110, 0b_000, 0) => without_rm(insts::OP_FMV_W_X),
+                (0b_11000, _, 0) => with_rm(insts::OP_FCVT_W_S),
+                (0b_11000, _, 1) => with_rm(insts::OP_FCVT_WU_S),
+                (0b_11000, _, 2) => with_rm(insts::OP_FCVT_L_S),
+                (0b_11000, _, 3) => with_rm(insts::OP_FCVT_LU_S),
+                (0b_11010, _, 0) => with_rm(insts::OP_FCVT_S_W),
+                (0b_11010, _, 1) => with_rm(insts::OP_FCVT_S_WU),
+                (0b_11010, _, 2) => with_rm(insts::OP_FCVT_S_L),
+                (0b_11010, _, 3) => with_rm(insts::OP_FCVT_S_LU),
+                _ => None,
+            }?
+        }
+        _ => return None,
+    };
+    Some(set_instruction_length_4(inst))
+}
+
+// Decodes the F extension, and accesses to the floating point CSRs.
+pub fn factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    if opcode(instruction_bits) == 0b_1110011 {
+        return csr_factory::<R>(instruction_bits);
+    }
+    decode::<R>(instruction_bits, Precision::Single)
+}
+
+// Decodes the D extension, including FCVT.S.D and FCVT.D.S.
+pub fn factory_d<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    if opcode(instruction_bits) == 0b_1010011
+        && funct7(instruction_bits) >> 2 == 0b_01000
+        && valid_rm(funct3(instruction_bits))
+    {
+        let op = match (funct7(instruction_bits) & 0b11, rs2(instruction_bits)) {
+            (0b00, 1) => insts::OP_FCVT_S_D,
+            (0b01, 0) => insts::OP_FCVT_D_S,
+            _ => return None,
+        };
+        return Some(set_instruction_length_4(
+            R4type::new(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+                funct3(instruction_bits) as usize,
+            )
+            .0,
+        ));
+    }
+    decode::<R>(instruction_bits, Precision::Double)
+}
+
+// fflags, frm and fcsr are decoded as privileged CSR accesses, which
+// execute_csr routes to the floating point registers.
+fn csr_factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    let csr = (instruction_bits >> 20) as u16;
+    if !(CSR_FFLAGS..=CSR_FCSR).contains(&csr) {
+        return None;
+    }
+    privileged::factory::<R>(instruction_bits, 0)
+}
+
+fn negate(p: Precision, value: u64) -> u64 {
+    value ^ p.sign_bit()
+}
+
+fn int_format(op: InstructionOpcode) -> IntFormat {
+    match single(op) {
+        insts::OP_FCVT_W_S | insts::OP_FCVT_S_W => IntFormat::W,
+        insts::OP_FCVT_WU_S | insts::OP_FCVT_S_WU => IntFormat::Wu,
+        insts::OP_FCVT_L_S | insts::OP_FCVT_S_L => IntFormat::L,
+        _ => IntFormat::Lu,
+    }
+}
+
+// Instructions are invalid when the machine has no floating point registers.
+fn float_registers<Mac: Machine>(
+    machine: &mut Mac,
+    op: InstructionOpcode,
+) -> Result<&mut FloatRegisters, Error> {
+    machine.float_registers_mut().ok_or(Error::InvalidOp(op))
+}
+
+// Resolves the rm field of an instruction, None when it is reserved.
+fn rounding_mode<Mac: Machine>(
+    machine: &mut Mac,
+    op: InstructionOpcode,
+    rm: usize,
+) -> Result<Option<RoundingMode>, Error> {
+    Ok(float_registers(machine, op)?.rounding_mode(rm as u8))
+}
+
+// Executes an instruction of the F and D extensions.
+pub fn execute<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let p = precision(op);
+    let mut backend = SoftFloat;
+    let mut flags = 0;
+    if op == insts::OP_FCVT_S_D || op == insts::OP_FCVT_D_S {
+        let i = R4type(inst);
+        let rm = match rounding_mode(machine, op, i.rs3())? {
+            Some(rm) => rm,
+            None => return privileged::illegal_instruction(machine, inst),
+        };
+        let (from, to) = if op == insts::OP_FCVT_S_D {
+            (Precision::Double, Precision::Single)
+        } else {
+            (Precision::Single, Precision::Double)
+        };
+        let float = float_registers(machine, op)?;
+        let result = backend.convert(from, to, float.value(from, i.rs1()), rm, &mut flags);
+        float.set_value(to, i.rd(), result);
+        float.raise(flags);
+        return Ok(());
+    }
+    match single(op) {
+        insts::OP_FLW => {
+            let i = Itype(inst);
+            let address =
+                machine.registers()[i.rs1()].overflowing_add(&Mac::REG::from_i32(i.immediate_s()));
+            let value = match p {
+                Precision::Single => machine.memory_mut().load32(&address)?,
+                Precision::Double => machine.memory_mut().load64(&address)?,
+            };
+            float_registers(machine, op)?.set_value(p, i.rd(), value.to_u64());
+        }
+        insts::OP_FSW => {
+            // Stores raw bits, singles are not unboxed.
+            let i = Stype(inst);
+            let address =
+                machine.registers()[i.rs1()].overflowing_add(&Mac::REG::from_i32(i.immediate_s()));
+            let value = Mac::REG::from_u64(float_registers(machine, op)?.register(i.rs2()));
+            match p {
+                Precision::Single => machine.memory_mut().store32(&address, &value)?,
+                Precision::Double => machine.memory_mut().store64(&address, &value)?,
+            }
+        }
+        insts::OP_FADD_S
+        | insts::OP_FSUB_S
+        | insts::OP_FMUL_S
+        | insts::OP_FDIV_S
+        | insts::OP_FSQRT_S => {
+            let i = R4type(inst);
+            let rm = match rounding_mode(machine, op, i.rs3())? {
+                Some(rm) => rm,
+                None => return privileged::illegal_instruction(machine, inst),
+            };
+            let float = float_registers(machine, op)?;
+            let (a, b) = (float.value(p, i.rs1()), float.value(p, i.rs2()));
+            let result = match single(op) {
+                insts::OP_FADD_S => backend.add(p, a, b, rm, &mut flags),
+                insts::OP_FSUB_S => backend.sub(p, a, b, rm, &mut flags),
+                insts::OP_FMUL_S => backend.mul(p, a, b, rm, &mut flags),
+                insts::OP_FDIV_S => backend.div(p, a, b, rm, &mut flags),
+                _ => backend.sqrt(p, a, rm, &mut flags),
+            };
+            float.set_value(p, i.rd(), result);
+        }
+        insts::OP_FMADD_S | insts::OP_FMSUB_S | insts::OP_FNMSUB_S | insts::OP_FNMADD_S => {
+            let i = R5type(inst);
+            let rm = match rounding_mode(machine, op, i.rs4())? {
+                Some(rm) => rm,
+                None => return privileged::illegal_instruction(machine, inst),
+            };
+            let float = float_registers(machine, op)?;
+            let (a, b, c) = (
+                float.value(p, i.rs1()),
+                float.value(p, i.rs2()),
+                float.value(p, i.rs3()),
+            );
+            // FMSUB computes a * b - c, FNMSUB -(a * b) + c, and FNMADD
+            // -(a * b) - c.
+            let (a, c) = match single(op) {
+                insts::OP_FMSUB_S => (a, negate(p, c)),
+                insts::OP_FNMSUB_S => (negate(p, a), c),
+                insts::OP_FNMADD_S => (negate(p, a), negate(p, c)),
+                _ => (a, c),
+            };
+            let result = backend.fma(p, a, b, c, rm, &mut flags);
+            float.set_value(p, i.rd(), result);
+        }
+        insts::OP_FSGNJ_S | insts::OP_FSGNJN_S | insts::OP_FSGNJX_S => {
+            let i = Rtype(inst);
+            let float = float_registers(machine, op)?;
+            let (a, b) = (float.value(p, i.rs1()), float.value(p, i.rs2()));
+            let sign = match single(op) {
+                insts::OP_FSGNJ_S => b,
+                insts::OP_FSGNJN_S => !b,
+                _ => a ^ b,
+            } & p.sign_bit();
+            float.set_value(p, i.rd(), a & !p.sign_bit() | sign);
+        }
+        insts::OP_FMIN_S | insts::OP_FMAX_S => {
+            let i = Rtype(inst);
+            let float = float_registers(machine, op)?;
+            let (a, b) = (float.value(p, i.rs1()), float.value(p, i.rs2()));
+            let result = if single(op) == insts::OP_FMIN_S {
+                backend.fmin(p, a, b, &mut flags)
+            } else {
+                backend.fmax(p, a, b, &mut flags)
+            };
+            float.set_value(p, i.rd(), result);
+        }
+        insts::OP_FEQ_S | insts::OP_FLT_S | insts::OP_FLE_S => {
+            let i = Rtype(inst);
+            let float = float_registers(machine, op)?;
+            let (a, b) = (float.value(p, i.rs1()), float.value(p, i.rs2()));
+            let result = match single(op) {
+                insts::OP_FEQ_S => backend.feq(p, a, b, &mut flags),
+                insts::OP_FLT_S => backend.flt(p, a, b, &mut flags),
+                _ => backend.fle(p, a, b, &mut flags),
+            };
+            update_register(machine, i.rd(), Mac::REG::from_u8(result as u8));
+        }
+        insts::OP_FCLASS_S => {
+            let i = Rtype(inst);
+            let a = float_registers(machine, op)?.value(p, i.rs1());
+            update_register(machine, i.rd(), Mac::REG::from_u64(backend.classify(p, a)));
+        }
+        insts::OP_FMV_X_W => {
+            // Moves raw bits, singles are sign extended from bit 31.
+            let i = Rtype(inst);
+            let bits = float_registers(machine, op)?.register(i.rs1());
+            let value = match p {
+                Precision::Single => bits as u32 as i32 as i64 as u64,
+                Precision::Double => bits,
+            };
+            update_register(machine, i.rd(), Mac::REG::from_u64(value));
+        }
+        insts::OP_FMV_W_X => {
+            let i = Rtype(inst);
+            let value = machine.registers()[i.rs1()].to_u64();
+            float_registers(machine, op)?.set_value(p, i.rd(), value);
+        }
+        insts::OP_FCVT_W_S | insts::OP_FCVT_WU_S | insts::OP_FCVT_L_S | insts::OP_FCVT_LU_S => {
+            let i = R4type(inst);
+            let rm = match rounding_mode(machine, op, i.rs3())? {
+                Some(rm) => rm,
+                None => return privileged::illegal_instruction(machine, inst),
+            };
+            let a = float_registers(machine, op)?.value(p, i.rs1());
+            let result = backend.float_to_int(p, a, int_format(op), rm, &mut flags);
+            update_register(machine, i.rd(), Mac::REG::from_u64(result));
+        }
+        insts::OP_FCVT_S_W | insts::OP_FCVT_S_WU | insts::OP_FCVT_S_L | insts::OP_FCVT_S_LU => {
+            let i = R4type(inst);
+            let rm = match rounding_mode(machine, op, i.rs3())? {
+                Some(rm) => rm,
+                None => return privileged::illegal_instruction(machine, inst),
+            };
+            let value = machine.registers()[i.rs1()].to_u64();
+            let result = backend.int_to_float(p, value, int_format(op), rm, &mut flags);
+            float_registers(machine, op)?.set_value(p, i.rd(), result);
+        }
+        _ => return Err(Error::InvalidOp(op)),
+    }
+    float_registers(machine, op)?.raise(flags);
+    Ok(())
+}
@@ -0,0 +1,527 @@
+use ckb_vm_definitions::instructions as insts;
+
+use super::utils::{funct3, opcode, rd, rs1, rs2, update_register, x};
+use super::{
+    extract_opcode, privileged, set_instruction_length_4, Instruction, InstructionOpcode, Itype,
+    R4type, Register, Rtype,
+};
+use crate::machine::vector::VectorRegisters;
+use crate::machine::Machine;
+use crate::memory::Memory;
+use crate::Error;
+
+// Instructions of the V extension enabled by ISA_V, limited to unit-stride
+// loads and stores, and the integer arithmetic instructions. Operands are
+// packed as follows, vm being the raw mask bit, 0 for masked instructions:
+// * VSETVLI: Itype(rd, rs1, zimm), VSETIVLI: Itype(rd, uimm, zimm);
+// * VSETVL: Rtype;
+// * Loads and stores: R4type(vd, rs1, 0, vm);
+// * Arithmetic: R4type(vd, vs2, src, vm), src being vs1, rs1 or the raw
+//   5-bit immediate.
+//
+// Tail and masked-off elements are left undisturbed.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Vector,
+    Scalar,
+    Immediate,
+}
+
+fn source(op: InstructionOpcode) -> Source {
+    match op {
+        insts::OP_VADD_VI
+        | insts::OP_VRSUB_VI
+        | insts::OP_VAND_VI
+        | insts::OP_VOR_VI
+        | insts::OP_VXOR_VI
+        | insts::OP_VMSEQ_VI
+        | insts::OP_VMSNE_VI
+        | insts::OP_VMSLEU_VI
+        | insts::OP_VMSLE_VI
+        | insts::OP_VMSGTU_VI
+        | insts::OP_VMSGT_VI
+        | insts::OP_VSLL_VI
+        | insts::OP_VSRL_VI
+        | insts::OP_VSRA_VI
+        | insts::OP_VMERGE_VIM
+        | insts::OP_VMV_V_I => Source::Immediate,
+        insts::OP_VADD_VX
+        | insts::OP_VSUB_VX
+        | insts::OP_VRSUB_VX
+        | insts::OP_VMINU_VX
+        | insts::OP_VMIN_VX
+        | insts::OP_VMAXU_VX
+        | insts::OP_VMAX_VX
+        | insts::OP_VAND_VX
+        | insts::OP_VOR_VX
+        | insts::OP_VXOR_VX
+        | insts::OP_VMSEQ_VX
+        | insts::OP_VMSNE_VX
+        | insts::OP_VMSLTU_VX
+        | insts::OP_VMSLT_VX
+        | insts::OP_VMSLEU_VX
+        | insts::OP_VMSLE_VX
+        | insts::OP_VMSGTU_VX
+        | insts::OP_VMSGT_VX
+        | insts::OP_VSLL_VX
+        | insts::OP_VSRL_VX
+        | insts::OP_VSRA_VX
+        | insts::OP_VMERGE_VXM
+        | insts::OP_VMV_V_X
+        | insts::OP_VMV_S_X
+        | insts::OP_VDIVU_VX
+        | insts::OP_VDIV_VX
+        | insts::OP_VREMU_VX
+        | insts::OP_VREM_VX
+        | insts::OP_VMULHU_VX
+        | insts::OP_VMUL_VX
+        | insts::OP_VMULHSU_VX
+        | insts::OP_VMULH_VX
+        | insts::OP_VMADD_VX
+        | insts::OP_VNMSUB_VX
+        | insts::OP_VMACC_VX
+        | insts::OP_VNMSAC_VX => Source::Scalar,
+        _ => Source::Vector,
+    }
+}
+
+fn is_compare(op: InstructionOpcode) -> bool {
+    (insts::OP_VMSEQ_VV..=insts::OP_VMSGT_VI).contains(&op)
+}
+
+fn is_shift(op: InstructionOpcode) -> bool {
+    (insts::OP_VSLL_VV..=insts::OP_VSRA_VI).contains(&op)
+}
+
+fn arithmetic_opcode(funct3: u32, funct6: u32, vm: u32) -> Option<InstructionOpcode> {
+    let op = match (funct3, funct6, vm) {
+        (0b_000, 0b_000000, _) => insts::OP_VADD_VV,
+        (0b_100, 0b_000000, _) => insts::OP_VADD_VX,
+        (0b_011, 0b_000000, _) => insts::OP_VADD_VI,
+        (0b_000, 0b_000010, _) => insts::OP_VSUB_VV,
+        (0b_100, 0b_000010, _) => insts::OP_VSUB_VX,
+        (0b_100, 0b_000011, _) => insts::OP_VRSUB_VX,
+        (0b_011, 0b_000011, _) => insts::OP_VRSUB_VI,
+        (0b_000, 0b_000100, _) => insts::OP_VMINU_VV,
+        (0b_100, 0b_000100, _) => insts::OP_VMINU_VX,
+        (0b_000, 0b_000101, _) => insts::OP_VMIN_VV,
+        (0b_100, 0b_000101, _) => insts::OP_VMIN_VX,
+        (0b_000, 0b_000110, _) => insts::OP_VMAXU_VV,
+        (0b_100, 0b_000110, _) => insts::OP_VMAXU_VX,
+        (0b_000, 0b_000111, _) => insts::OP_VMAX_VV,
+        (0b_100, 0b_000111, _) => insts::OP_VMAX_VX,
+        (0b_000, 0b_001001, _) => insts::OP_VAND_VV,
+        (0b_100, 0b_001001, _) => insts::OP_VAND_VX,
+        (0b_011, 0b_001001, _) => insts::OP_VAND_VI,
+        (0b_000, 0b_001010, _) => insts::OP_VOR_VV,
+        (0b_100, 0b_001010, _) => insts::OP_VOR_VX,
+        (0b_011, 0b_001010, _) => insts::OP_VOR_VI,
+        (0b_000, 0b_001011, _) => insts::OP_VXOR_VV,
+        (0b_100, 0b_001011, _) => insts::OP_VXOR_VX,
+        (0b_011, 0b_001011, _) => insts::OP_VXOR_VI,
+        (0b_000, 0b_011000, _) => insts::OP_VMSEQ_VV,
+        (0b_100, 0b_011000, _) => insts::OP_VMSEQ_VX,
+        (0b_011, 0b_011000, _) => insts::OP_VMSEQ_VI,
+        (0b_000, 0b_011001, _) => insts::OP_VMSNE_VV,
+        (0b_100, 0b_011001, _) => insts::OP_VMSNE_VX,
+        (0b_011, 0b_011001, _) => insts::OP_VMSNE_VI,
+        (0b_000, 0b_011010, _) => insts::OP_VMSLTU_VV,
+        (0b_100, 0b_011010, _) => insts::OP_VMSLTU_VX,
+        (0b_000, 0b_011011, _) => insts::OP_VMSLT_VV,
+        (0b_100, 0b_011011, _) => insts::OP_VMSLT_VX,
+        (0b_000, 0b_011100, _) => insts::OP_VMSLEU_VV,
+        (0b_100, 0b_011100, _) => insts::OP_VMSLEU_VX,
+        (0b_011, 0b_011100, _) => insts::OP_VMSLEU_VI,
+        (0b_000, 0b_011101, _) => insts::OP_VMSLE_VV,
+        (0b_100, 0b_011101, _) => insts::OP_VMSLE_VX,
+        (0b_011, 0b_011101, _) => insts::OP_VMSLE_VI,
+        (0b_100, 0b_011110, _) => insts::OP_VMSGTU_VX,
+        (0b_011, 0b_011110, _) => insts::OP_VMSGTU_VI,
+        (0b_100, 0b_011111, _) => insts::OP_VMSGT_VX,
+        (0b_011, 0b_011111, _) => insts::OP_VMSGT_VI,
+        (0b_000, 0b_100101, _) => insts::OP_VSLL_VV,
+        (0b_100, 0b_100101, _) => insts::OP_VSLL_VX,
+        (0b_011, 0b_100101, _) => insts::OP_VSLL_VI,
+        (0b_000, 0b_101000, _) => insts::OP_VSRL_VV,
+        (0b_100, 0b_101000, _) => insts::OP_VSRL_VX,
+        (0b_011, 0b_101000, _) => insts::OP_VSRL_VI,
+        (0b_000, 0b_101001, _) => insts::OP_VSRA_VV,
+        (0b_100, 0b_101001, _) => insts::OP_VSRA_VX,
+        (0b_011, 0b_101001, _) => insts::OP_VSRA_VI,
+        (0b_000, 0b_010111, 0) => insts::OP_VMERGE_VVM,
+        (0b_100, 0b_010111, 0) => insts::OP_VMERGE_VXM,
+        (0b_011, 0b_010111, 0) => insts::OP_VMERGE_VIM,
+        (0b_000, 0b_010111, 1) => insts::OP_VMV_V_V,
+        (0b_100, 0b_010111, 1) => insts::OP_VMV_V_X,
+        (0b_011, 0b_010111, 1) => insts::OP_VMV_V_I,
+        (0b_010, 0b_000000, _) => insts::OP_VREDSUM_VS,
+        (0b_010, 0b_010000, 1) => insts::OP_VMV_X_S,
+        (0b_110, 0b_010000, 1) => insts::OP_VMV_S_X,
+        (0b_010, 0b_100000, _) => insts::OP_VDIVU_VV,
+        (0b_110, 0b_100000, _) => insts::OP_VDIVU_VX,
+        (0b_010, 0b_100001, _) => insts::OP_VDIV_VV,
+        (0b_110, 0b_100001, _) => insts::OP_VDIV_VX,
+        (0b_010, 0b_100010, _) => insts::OP_VREMU_VV,
+        (0b_110, 0b_100010, _) => insts::OP_VREMU_VX,
+        (0b_010, 0b_100011, _) => insts::OP_VREM_VV,
+        (0b_110, 0b_100011, _) => insts::OP_VREM_VX,
+        (0b_010, 0b_100100, _) => insts::OP_VMULHU_VV,
+        (0b_110, 0b_100100, _) => insts::OP_VMULHU_VX,
+        (0b_010, 0b_100101, _) => insts::OP_VMUL_VV,
+        (0b_110, 0b_100101, _) => insts::OP_VMUL_VX,
+        (0b_010, 0b_100110, _) => insts::OP_VMULHSU_VV,
+        (0b_110, 0b_100110, _) => insts::OP_VMULHSU_VX,
+        (0b_010, 0b_100111, _) => insts::OP_VMULH_VV,
+        (0b_110, 0b_100111, _) => insts::OP_VMULH_VX,
+        (0b_010, 0b_101001, _) => insts::OP_VMADD_VV,
+        (0b_110, 0b_101001, _) => insts::OP_VMADD_VX,
+        (0b_010, 0b_101011, _) => insts::OP_VNMSUB_VV,
+        (0b_110, 0b_101011, _) => insts::OP_VNMSUB_VX,
+        (0b_010, 0b_101101, _) => insts::OP_VMACC_VV,
+        (0b_110, 0b_101101, _) => insts::OP_VMACC_VX,
+        (0b_010, 0b_101111, _) => insts::OP_VNMSAC_VV,
+        (0b_110, 0b_101111, _) => insts::OP_VNMSAC_VX,
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Decodes the V extension, and accesses to the vector CSRs.
+pub fn factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    if R::BITS != 64 {
+        return None;
+    }
+    let (rd, rs1, rs2) = (
+        rd(instruction_bits),
+        rs1(instruction_bits),
+        rs2(instruction_bits),
+    );
+    let vm = x(instruction_bits, 25, 1, 0);
+    let inst = match (opcode(instruction_bits), funct3(instruction_bits)) {
+        (0b_1110011, _) => return csr_factory::<R>(instruction_bits),
+        (0b_1010111, 0b_111) => match instruction_bits >> 30 {
+            0b_00 | 0b_01 => {
+                Itype::new_u(insts::OP_VSETVLI, rd, rs1, x(instruction_bits, 20, 11, 0)).0
+            }
+            0b_11 => Itype::new_u(insts::OP_VSETIVLI, rd, rs1, x(instruction_bits, 20, 10, 0)).0,
+            _ if instruction_bits >> 25 == 0b_1000000 => {
+                Rtype::new(insts::OP_VSETVL, rd, rs1, rs2).0
+            }
+            _ => return None,
+        },
+        (0b_1010111, funct3) => {
+            let op = arithmetic_opcode(funct3, instruction_bits >> 26, vm)?;
+            // Unused operand fields must be zero.
+            let unused = match op {
+                insts::OP_VMV_V_V | insts::OP_VMV_V_X | insts::OP_VMV_V_I | insts::OP_VMV_S_X => {
+                    rs2
+                }
+                insts::OP_VMV_X_S => rs1,
+                _ => 0,
+            };
+            if unused != 0 {
+                return None;
+            }
+            R4type::new(op, rd, rs2, rs1, vm as usize).0
+        }
+        (opcode @ (0b_0000111 | 0b_0100111), width) => {
+            // Unit-stride only: nf, mew, mop and lumop or sumop are zero.
+            if instruction_bits >> 26 != 0 || rs2 != 0 {
+                return None;
+            }
+            let op = match (opcode, width) {
+                (0b_0000111, 0b_000) => insts::OP_VLE8_V,
+                (0b_0000111, 0b_101) => insts::OP_VLE16_V,
+                (0b_0000111, 0b_110) => insts::OP_VLE32_V,
+                (0b_0000111, 0b_111) => insts::OP_VLE64_V,
+                (0b_0100111, 0b_000) => insts::OP_VSE8_V,
+                (0b_0100111, 0b_101) => insts::OP_VSE16_V,
+                (0b_0100111, 0b_110) => insts::OP_VSE32_V,
+                (0b_0100111, 0b_111) => insts::OP_VSE64_V,
+                _ => return None,
+            };
+            R4type::new(op, rd, rs1, 0, vm as usize).0
+        }
+        _ => return None,
+    };
+    Some(set_instruction_length_4(inst))
+}
+
+// The vector CSRs are decoded as privileged CSR accesses, which execute_csr
+// routes to the vector registers.
+fn csr_factory<R: Register>(instruction_bits: u32) -> Option<Instruction> {
+    if !VectorRegisters::is_csr((instruction_bits >> 20) as u16) {
+        return None;
+    }
+    privileged::factory::<R>(instruction_bits, 0)
+}
+
+// Instructions are invalid when the machine has no vector registers.
+fn vector_registers<Mac: Machine>(
+    machine: &mut Mac,
+    op: InstructionOpcode,
+) -> Result<&mut VectorRegisters, Error> {
+    machine.vector_registers_mut().ok_or(Error::InvalidOp(op))
+}
+
+// Register groups of more than one register must be aligned to their size.
+fn aligned(register: usize, lmul_log2: i32) -> bool {
+    lmul_log2 <= 0 || register % (1 << lmul_log2) == 0
+}
+
+fn sign_extend(value: u64, sew: u64) -> i64 {
+    let shift = 64 - sew * 8;
+    ((value << shift) as i64) >> shift
+}
+
+// Computes an element of `sew` bytes from a (vs2), b (vs1, rs1 or the
+// immediate) and d (the destination element, used by the multiply-add
+// instructions). Operands are zero extended, compares return 0 or 1.
+fn compute(op: InstructionOpcode, a: u64, b: u64, d: u64, sew: u64) -> u64 {
+    let bits = sew * 8;
+    let (sa, sb) = (sign_extend(a, sew), sign_extend(b, sew));
+    let shift = b & (bits - 1);
+    match op {
+        insts::OP_VADD_VV | insts::OP_VADD_VX | insts::OP_VADD_VI => a.wrapping_add(b),
+        insts::OP_VSUB_VV | insts::OP_VSUB_VX => a.wrapping_sub(b),
+        insts::OP_VRSUB_VX | insts::OP_VRSUB_VI => b.wrapping_sub(a),
+        insts::OP_VMINU_VV | insts::OP_VMINU_VX => a.min(b),
+        insts::OP_VMIN_VV | insts::OP_VMIN_VX => sa.min(sb) as u64,
+        insts::OP_VMAXU_VV | insts::OP_VMAXU_VX => a.max(b),
+        insts::OP_VMAX_VV | insts::OP_VMAX_VX => sa.max(sb) as u64,
+        insts::OP_VAND_VV | insts::OP_VAND_VX | insts::OP_VAND_VI => a & b,
+        insts::OP_VOR_VV | insts::OP_VOR_VX | insts::OP_VOR_VI => a | b,
+        insts::OP_VXOR_VV | insts::OP_VXOR_VX | insts::OP_VXOR_VI => a ^ b,
+        insts::OP_VMSEQ_VV | insts::OP_VMSEQ_VX | insts::OP_VMSEQ_VI => (a == b) as u64,
+        insts::OP_VMSNE_VV | insts::OP_VMSNE_VX | insts::OP_VMSNE_VI => (a != b) as u64,
+        insts::OP_VMSLTU_VV | insts::OP_VMSLTU_VX => (a < b) as u64,
+        insts::OP_VMSLT_VV | insts::OP_VMSLT_VX => (sa < sb) as u64,
+        insts::OP_VMSLEU_VV | insts::OP_VMSLEU_VX | insts::OP_VMSLEU_VI => (a <= b) as u64,
+        insts::OP_VMSLE_VV | insts::OP_VMSLE_VX | insts::OP_VMSLE_VI => (sa <= sb) as u64,
+        insts::OP_VMSGTU_VX | insts::OP_VMSGTU_VI => (a > b) as u64,
+        insts::OP_VMSGT_VX | insts::OP_VMSGT_VI => (sa > sb) as u64,
+        insts::OP_VSLL_VV | insts::OP_VSLL_VX | insts::OP_VSLL_VI => a << shift,
+        insts::OP_VSRL_VV | insts::OP_VSRL_VX | insts::OP_VSRL_VI => a >> shift,
+        insts::OP_VSRA_VV | insts::OP_VSRA_VX | insts::OP_VSRA_VI => (sa >> shift) as u64,
+        // Division by zero and overflow follow the M extension.
+        insts::OP_VDIVU_VV | insts::OP_VDIVU_VX => a.checked_div(b).unwrap_or(u64::MAX),
+        insts::OP_VDIV_VV | insts::OP_VDIV_VX => {
+            if sb == 0 {
+                u64::MAX
+            } else {
+                sa.wrapping_div(sb) as u64
+            }
+        }
+        insts::OP_VREMU_VV | insts::OP_VREMU_VX => a.checked_rem(b).unwrap_or(a),
+        insts::OP_VREM_VV | insts::OP_VREM_VX => {
+            if sb == 0 {
+                a
+            } else {
+                sa.wrapping_rem(sb) as u64
+            }
+        }
+        insts::OP_VMUL_VV | insts::OP_VMUL_VX => a.wrapping_mul(b),
+        insts::OP_VMULHU_VV | insts::OP_VMULHU_VX => ((a as u128 * b as u128) >> bits) as u64,
+        insts::OP_VMULH_VV | insts::OP_VMULH_VX => ((sa as i128 * sb as i128) >> bits) as u64,
+        insts::OP_VMULHSU_VV | insts::OP_VMULHSU_VX => ((sa as i128 * b as i128) >> bits) as u64,
+        insts::OP_VMACC_VV | insts::OP_VMACC_VX => b.wrapping_mul(a).wrapping_add(d),
+        insts::OP_VNMSAC_VV | insts::OP_VNMSAC_VX => d.wrapping_sub(b.wrapping_mul(a)),
+        insts::OP_VMADD_VV | insts::OP_VMADD_VX => b.wrapping_mul(d).wrapping_add(a),
+        insts::OP_VNMSUB_VV | insts::OP_VNMSUB_VX => a.wrapping_sub(b.wrapping_mul(d)),
+        // VMERGE and VMV.V.
+        _ => b,
+    }
+}
+
+// Executes an instruction of the V extension.
+pub fn execute<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    match op {
+        insts::OP_VSETVLI | insts::OP_VSETIVLI | insts::OP_VSETVL => execute_vset(machine, inst),
+        insts::OP_VLE8_V..=insts::OP_VSE64_V => execute_memory(machine, inst),
+        _ => execute_arithmetic(machine, inst),
+    }
+}
+
+fn execute_vset<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let (rd, rs1, vtype) = if op == insts::OP_VSETVL {
+        let i = Rtype(inst);
+        (i.rd(), i.rs1(), machine.registers()[i.rs2()].to_u64())
+    } else {
+        let i = Itype(inst);
+        (i.rd(), i.rs1(), u64::from(i.immediate_u()))
+    };
+    // With rs1 and rd both x0, vl is kept.
+    let avl = match (op, rs1, rd) {
+        (insts::OP_VSETIVLI, _, _) => Some(rs1 as u64),
+        (_, 0, 0) => None,
+        (_, 0, _) => Some(u64::MAX),
+        _ => Some(machine.registers()[rs1].to_u64()),
+    };
+    let vector = vector_registers(machine, op)?;
+    let avl = avl.unwrap_or_else(|| vector.vl());
+    let vl = vector.set_vtype(vtype, avl);
+    vector.set_vstart(0);
+    update_register(machine, rd, Mac::REG::from_u64(vl));
+    Ok(())
+}
+
+fn execute_memory<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = R4type(inst);
+    let (vd, masked) = (i.rd(), i.rs3() == 0);
+    let load = op <= insts::OP_VLE64_V;
+    let eew: u64 = match op {
+        insts::OP_VLE8_V | insts::OP_VSE8_V => 1,
+        insts::OP_VLE16_V | insts::OP_VSE16_V => 2,
+        insts::OP_VLE32_V | insts::OP_VSE32_V => 4,
+        _ => 8,
+    };
+    let base = machine.registers()[i.rs1()].to_u64();
+    let vector = vector_registers(machine, op)?;
+    // The register group holds vl elements of EEW, its size EMUL is
+    // EEW / SEW * LMUL.
+    let emul_log2 =
+        eew.trailing_zeros() as i32 - vector.sew().trailing_zeros() as i32 + vector.lmul_log2();
+    let legal = !vector.vill()
+        && (-3..=3).contains(&emul_log2)
+        && aligned(vd, emul_log2)
+        && !(load && masked && vd == 0);
+    if !legal {
+        return privileged::illegal_instruction(machine, inst);
+    }
+    let (vstart, vl) = (vector.vstart(), vector.vl());
+    for index in vstart..vl {
+        if masked && !vector_registers(machine, op)?.mask(0, index) {
+            continue;
+        }
+        let address = Mac::REG::from_u64(base.wrapping_add(index * eew));
+        let result = if load {
+            let memory = machine.memory_mut();
+            match eew {
+                1 => memory.load8(&address),
+                2 => memory.load16(&address),
+                4 => memory.load32(&address),
+                _ => memory.load64(&address),
+            }
+            .map(|value| Some(value.to_u64()))
+        } else {
+            let value = vector_registers(machine, op)?.element(vd, index, eew);
+            let value = Mac::REG::from_u64(value);
+            let memory = machine.memory_mut();
+            match eew {
+                1 => memory.store8(&address, &value),
+                2 => memory.store16(&address, &value),
+                4 => memory.store32(&address, &value),
+                _ => memory.store64(&address, &value),
+            }
+            .map(|_| None)
+        };
+        let vector = vector_registers(machine, op)?;
+        match result {
+            Ok(Some(value)) => vector.set_element(vd, index, eew, value),
+            Ok(None) => (),
+            // A faulting access leaves vstart at its element.
+            Err(e) => {
+                vector.set_vstart(index);
+                return Err(e);
+            }
+        }
+    }
+    vector_registers(machine, op)?.set_vstart(0);
+    Ok(())
+}
+
+fn execute_arithmetic<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let op = extract_opcode(inst);
+    let i = R4type(inst);
+    let (vd, vs2, src, masked) = (i.rd(), i.rs1(), i.rs2(), i.rs3() == 0);
+    let source = source(op);
+    let scalar = machine.registers()[src].to_u64();
+    let vector = vector_registers(machine, op)?;
+    let (sew, lmul_log2) = (vector.sew(), vector.lmul_log2());
+    let legal = !vector.vill()
+        && match op {
+            // Scalar operands are single registers.
+            insts::OP_VMV_X_S | insts::OP_VMV_S_X => true,
+            insts::OP_VREDSUM_VS => aligned(vs2, lmul_log2) && vector.vstart() == 0,
+            // Masks are single registers.
+            _ if is_compare(op) => {
+                aligned(vs2, lmul_log2) && (source != Source::Vector || aligned(src, lmul_log2))
+            }
+            _ => {
+                aligned(vd, lmul_log2)
+                    && aligned(vs2, lmul_log2)
+                    && (source != Source::Vector || aligned(src, lmul_log2))
+                    && !(masked && vd == 0)
+            }
+        };
+    if !legal {
+        return privileged::illegal_instruction(machine, inst);
+    }
+    let vector = vector_registers(machine, op)?;
+    let (vstart, vl) = (vector.vstart(), vector.vl());
+    let sew_mask = u64::MAX >> (64 - sew * 8);
+    match op {
+        insts::OP_VMV_X_S => {
+            let value = sign_extend(vector.element(vs2, 0, sew), sew) as u64;
+            vector.set_vstart(0);
+            update_register(machine, vd, Mac::REG::from_u64(value));
+            return Ok(());
+        }
+        insts::OP_VMV_S_X => {
+            if vstart < vl {
+                vector.set_element(vd, 0, sew, scalar);
+            }
+        }
+        insts::OP_VREDSUM_VS => {
+            if vl > 0 {
+                let sum = (0..vl)
+                    .filter(|index| !masked || vector.mask(0, *index))
+                    .fold(vector.element(src, 0, sew), |sum, index| {
+                        sum.wrapping_add(vector.element(vs2, index, sew))
+                    });
+                vector.set_element(vd, 0, sew, sum);
+            }
+        }
+        _ => {
+            // Immediates are sign extended, except the shift amounts.
+            let b = match source {
+                Source::Scalar => scalar,
+                Source::Immediate if is_shift(op) => src as u64,
+                Source::Immediate => ((src as i64) << 59 >> 59) as u64,
+                Source::Vector => 0,
+            } & sew_mask;
+            let merge = matches!(
+                op,
+                insts::OP_VMERGE_VVM | insts::OP_VMERGE_VXM | insts::OP_VMERGE_VIM
+            );
+            for index in vstart..vl {
+                if masked && !vector.mask(0, index) {
+                    // VMERGE takes vs2 where the mask is clear.
+                    if merge {
+                        let a = vector.element(vs2, index, sew);
+                        vector.set_element(vd, index, sew, a);
+                    }
+                    continue;
+                }
+                let a = vector.element(vs2, index, sew);
+                let b = match source {
+                    Source::Vector => vector.element(src, index, sew),
+                    _ => b,
+                };
+                let d = vector.element(vd, index, sew);
+                let result = compute(op, a, b, d, sew);
+                if is_compare(op) {
+                    vector.set_mask(vd, index, result != 0);
+                } else {
+                    vector.set_element(vd, index, sew, result);
+                }
+            }
+        }
+    }
+    vector.set_vstart(0);
+    Ok(())
+}
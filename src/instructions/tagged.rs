@@ -244,6 +244,172 @@ impl TryFrom<Instruction> for TaggedInstruction {
             insts::OP_ADD3C => R5type(i).into(),
             insts::OP_CUSTOM_LOAD_UIMM => Utype(i).into(),
             insts::OP_CUSTOM_LOAD_IMM => Utype(i).into(),
+            insts::OP_CSRRW => Itype(i).into(),
+            insts::OP_CSRRS => Itype(i).into(),
+            insts::OP_CSRRC => Itype(i).into(),
+            insts::OP_CSRRWI => Itype(i).into(),
+            insts::OP_CSRRSI => Itype(i).into(),
+            insts::OP_CSRRCI => Itype(i).into(),
+            insts::OP_MRET => Rtype(i).into(),
+            insts::OP_FLW => Itype(i).into(),
+            insts::OP_FSW => Stype(i).into(),
+            insts::OP_FADD_S => R4type(i).into(),
+            insts::OP_FSUB_S => R4type(i).into(),
+            insts::OP_FMUL_S => R4type(i).into(),
+            insts::OP_FDIV_S => R4type(i).into(),
+            insts::OP_FSQRT_S => R4type(i).into(),
+            insts::OP_FSGNJ_S => Rtype(i).into(),
+            insts::OP_FSGNJN_S => Rtype(i).into(),
+            insts::OP_FSGNJX_S => Rtype(i).into(),
+            insts::OP_FMIN_S => Rtype(i).into(),
+            insts::OP_FMAX_S => Rtype(i).into(),
+            insts::OP_FCVT_W_S => R4type(i).into(),
+            insts::OP_FCVT_WU_S => R4type(i).into(),
+            insts::OP_FCVT_L_S => R4type(i).into(),
+            insts::OP_FCVT_LU_S => R4type(i).into(),
+            insts::OP_FMV_X_W => Rtype(i).into(),
+            insts::OP_FEQ_S => Rtype(i).into(),
+            insts::OP_FLT_S => Rtype(i).into(),
+            insts::OP_FLE_S => Rtype(i).into(),
+            insts::OP_FCLASS_S => Rtype(i).into(),
+            insts::OP_FCVT_S_W => R4type(i).into(),
+            insts::OP_FCVT_S_WU => R4type(i).into(),
+            insts::OP_FCVT_S_L => R4type(i).into(),
+            insts::OP_FCVT_S_LU => R4type(i).into(),
+            insts::OP_FMV_W_X => Rtype(i).into(),
+            insts::OP_FMADD_S => R5type(i).into(),
+            insts::OP_FMSUB_S => R5type(i).into(),
+            insts::OP_FNMSUB_S => R5type(i).into(),
+            insts::OP_FNMADD_S => R5type(i).into(),
+            insts::OP_FLD => Itype(i).into(),
+            insts::OP_FSD => Stype(i).into(),
+            insts::OP_FADD_D => R4type(i).into(),
+            insts::OP_FSUB_D => R4type(i).into(),
+            insts::OP_FMUL_D => R4type(i).into(),
+            insts::OP_FDIV_D => R4type(i).into(),
+            insts::OP_FSQRT_D => R4type(i).into(),
+            insts::OP_FSGNJ_D => Rtype(i).into(),
+            insts::OP_FSGNJN_D => Rtype(i).into(),
+            insts::OP_FSGNJX_D => Rtype(i).into(),
+            insts::OP_FMIN_D => Rtype(i).into(),
+            insts::OP_FMAX_D => Rtype(i).into(),
+            insts::OP_FCVT_W_D => R4type(i).into(),
+            insts::OP_FCVT_WU_D => R4type(i).into(),
+            insts::OP_FCVT_L_D => R4type(i).into(),
+            insts::OP_FCVT_LU_D => R4type(i).into(),
+            insts::OP_FMV_X_D => Rtype(i).into(),
+            insts::OP_FEQ_D => Rtype(i).into(),
+            insts::OP_FLT_D => Rtype(i).into(),
+            insts::OP_FLE_D => Rtype(i).into(),
+            insts::OP_FCLASS_D => Rtype(i).into(),
+            insts::OP_FCVT_D_W => R4type(i).into(),
+            insts::OP_FCVT_D_WU => R4type(i).into(),
+            insts::OP_FCVT_D_L => R4type(i).into(),
+            insts::OP_FCVT_D_LU => R4type(i).into(),
+            insts::OP_FMV_D_X => Rtype(i).into(),
+            insts::OP_FMADD_D => R5type(i).into(),
+            insts::OP_FMSUB_D => R5type(i).into(),
+            insts::OP_FNMSUB_D => R5type(i).into(),
+            insts::OP_FNMADD_D => R5type(i).into(),
+            insts::OP_FCVT_S_D => R4type(i).into(),
+            insts::OP_FCVT_D_S => R4type(i).into(),
+            insts::OP_VSETVLI => Itype(i).into(),
+            insts::OP_VSETIVLI => Itype(i).into(),
+            insts::OP_VSETVL => Rtype(i).into(),
+            insts::OP_VLE8_V => R4type(i).into(),
+            insts::OP_VLE16_V => R4type(i).into(),
+            insts::OP_VLE32_V => R4type(i).into(),
+            insts::OP_VLE64_V => R4type(i).into(),
+            insts::OP_VSE8_V => R4type(i).into(),
+            insts::OP_VSE16_V => R4type(i).into(),
+            insts::OP_VSE32_V => R4type(i).into(),
+            insts::OP_VSE64_V => R4type(i).into(),
+            insts::OP_VADD_VV => R4type(i).into(),
+            insts::OP_VADD_VX => R4type(i).into(),
+            insts::OP_VADD_VI => R4type(i).into(),
+            insts::OP_VSUB_VV => R4type(i).into(),
+            insts::OP_VSUB_VX => R4type(i).into(),
+            insts::OP_VRSUB_VX => R4type(i).into(),
+            insts::OP_VRSUB_VI => R4type(i).into(),
+            insts::OP_VMINU_VV => R4type(i).into(),
+            insts::OP_VMINU_VX => R4type(i).into(),
+            insts::OP_VMIN_VV => R4type(i).into(),
+            insts::OP_VMIN_VX => R4type(i).into(),
+            insts::OP_VMAXU_VV => R4type(i).into(),
+            insts::OP_VMAXU_VX => R4type(i).into(),
+            insts::OP_VMAX_VV => R4type(i).into(),
+            insts::OP_VMAX_VX => R4type(i).into(),
+            insts::OP_VAND_VV => R4type(i).into(),
+            insts::OP_VAND_VX => R4type(i).into(),
+            insts::OP_VAND_VI => R4type(i).into(),
+            insts::OP_VOR_VV => R4type(i).into(),
+            insts::OP_VOR_VX => R4type(i).into(),
+            insts::OP_VOR_VI => R4type(i).into(),
+            insts::OP_VXOR_VV => R4type(i).into(),
+            insts::OP_VXOR_VX => R4type(i).into(),
+            insts::OP_VXOR_VI => R4type(i).into(),
+            insts::OP_VMSEQ_VV => R4type(i).into(),
+            insts::OP_VMSEQ_VX => R4type(i).into(),
+            insts::OP_VMSEQ_VI => R4type(i).into(),
+            insts::OP_VMSNE_VV => R4type(i).into(),
+            insts::OP_VMSNE_VX => R4type(i).into(),
+            insts::OP_VMSNE_VI => R4type(i).into(),
+            insts::OP_VMSLTU_VV => R4type(i).into(),
+            insts::OP_VMSLTU_VX => R4type(i).into(),
+            insts::OP_VMSLT_VV => R4type(i).into(),
+            insts::OP_VMSLT_VX => R4type(i).into(),
+            insts::OP_VMSLEU_VV => R4type(i).into(),
+            insts::OP_VMSLEU_VX => R4type(i).into(),
+            insts::OP_VMSLEU_VI => R4type(i).into(),
+            insts::OP_VMSLE_VV => R4type(i).into(),
+            insts::OP_VMSLE_VX => R4type(i).into(),
+            insts::OP_VMSLE_VI => R4type(i).into(),
+            insts::OP_VMSGTU_VX => R4type(i).into(),
+            insts::OP_VMSGTU_VI => R4type(i).into(),
+            insts::OP_VMSGT_VX => R4type(i).into(),
+            insts::OP_VMSGT_VI => R4type(i).into(),
+            insts::OP_VSLL_VV => R4type(i).into(),
+            insts::OP_VSLL_VX => R4type(i).into(),
+            insts::OP_VSLL_VI => R4type(i).into(),
+            insts::OP_VSRL_VV => R4type(i).into(),
+            insts::OP_VSRL_VX => R4type(i).into(),
+            insts::OP_VSRL_VI => R4type(i).into(),
+            insts::OP_VSRA_VV => R4type(i).into(),
+            insts::OP_VSRA_VX => R4type(i).into(),
+            insts::OP_VSRA_VI => R4type(i).into(),
+            insts::OP_VMERGE_VVM => R4type(i).into(),
+            insts::OP_VMERGE_VXM => R4type(i).into(),
+            insts::OP_VMERGE_VIM => R4type(i).into(),
+            insts::OP_VMV_V_V => R4type(i).into(),
+            insts::OP_VMV_V_X => R4type(i).into(),
+            insts::OP_VMV_V_I => R4type(i).into(),
+            insts::OP_VREDSUM_VS => R4type(i).into(),
+            insts::OP_VMV_X_S => R4type(i).into(),
+            insts::OP_VMV_S_X => R4type(i).into(),
+            insts::OP_VDIVU_VV => R4type(i).into(),
+            insts::OP_VDIVU_VX => R4type(i).into(),
+            insts::OP_VDIV_VV => R4type(i).into(),
+            insts::OP_VDIV_VX => R4type(i).into(),
+            insts::OP_VREMU_VV => R4type(i).into(),
+            insts::OP_VREMU_VX => R4type(i).into(),
+            insts::OP_VREM_VV => R4type(i).into(),
+            insts::OP_VREM_VX => R4type(i).into(),
+            insts::OP_VMULHU_VV => R4type(i).into(),
+            insts::OP_VMULHU_VX => R4type(i).into(),
+            insts::OP_VMUL_VV => R4type(i).into(),
+            insts::OP_VMUL_VX => R4type(i).into(),
+            insts::OP_VMULHSU_VV => R4type(i).into(),
+            insts::OP_VMULHSU_VX => R4type(i).into(),
+            insts::OP_VMULH_VV => R4type(i).into(),
+            insts::OP_VMULH_VX => R4type(i).into(),
+            insts::OP_VMADD_VV => R4type(i).into(),
+            insts::OP_VMADD_VX => R4type(i).into(),
+            insts::OP_VNMSUB_VV => R4type(i).into(),
+            insts::OP_VNMSUB_VX => R4type(i).into(),
+            insts::OP_VMACC_VV => R4type(i).into(),
+            insts::OP_VMACC_VX => R4type(i).into(),
+            insts::OP_VNMSAC_VV => R4type(i).into(),
+            insts::OP_VNMSAC_VX => R4type(i).into(),
             _ => return Err(Error::InvalidOp(op)),
         };
         Ok(tagged_inst)
@@ -0,0 +1,252 @@
+use crate::machine::{VERSION1, VERSION2};
+use crate::{Error, ISA_A, ISA_B, ISA_D, ISA_F, ISA_IMC, ISA_MOP, ISA_PRIV, ISA_V, ISA_ZICSR};
+
+// Isa is a validated set of instruction set extensions. Raw masks (ISA_IMC,
+// ISA_B, ...) are still accepted everywhere, but they perform no checks at
+// all. Isa validates its 64-bit wide mask against the machine version:
+//
+// * Unknown bits are rejected;
+// * Each extension is only available starting from a certain version;
+// * An extension might depend on other extensions.
+//
+// New extensions should be added to the EXTENSIONS table below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Isa(u64);
+
+pub struct Extension {
+    pub name: &'static str,
+    pub mask: u64,
+    // The first version supporting this extension.
+    pub min_version: u32,
+    // Extensions that must be enabled together with this one.
+    pub requires: u64,
+}
+
+pub const EXTENSIONS: [Extension; 8] = [
+    Extension {
+        name: "B",
+        mask: ISA_B,
+        min_version: VERSION1,
+        requires: 0,
+    },
+    Extension {
+        name: "MOP",
+        mask: ISA_MOP,
+        min_version: VERSION1,
+        requires: 0,
+    },
+    Extension {
+        name: "A",
+        mask: ISA_A,
+        min_version: VERSION2,
+        requires: 0,
+    },
+    Extension {
+        name: "PRIV",
+        mask: ISA_PRIV,
+        min_version: VERSION2,
+        requires: 0,
+    },
+    Extension {
+        name: "F",
+        mask: ISA_F,
+        min_version: VERSION2,
+        requires: 0,
+    },
+    Extension {
+        name: "D",
+        mask: ISA_D,
+        min_version: VERSION2,
+        requires: ISA_F,
+    },
+    Extension {
+        name: "V",
+        mask: ISA_V,
+        min_version: VERSION2,
+        requires: 0,
+    },
+    Extension {
+        name: "ZICSR",
+        mask: ISA_ZICSR,
+        min_version: VERSION2,
+        requires: 0,
+    },
+];
+
+// Whether this build supports `extension`, V needs the rvv feature.
+pub fn compiled(extension: &Extension) -> bool {
+    cfg!(feature = "rvv") || extension.mask != ISA_V
+}
+
+impl Isa {
+    pub const IMC: Isa = Isa(ISA_IMC);
+
+    // Validates `mask` against `version`.
+    pub fn new(mask: u64, version: u32) -> Result<Self, Error> {
+        let known = EXTENSIONS
+            .iter()
+            .filter(|e| compiled(e))
+            .fold(0, |acc, e| acc | e.mask);
+        if mask & !known != 0 {
+            return Err(Error::InvalidIsa(mask & !known));
+        }
+        for extension in EXTENSIONS.iter().filter(|e| mask & e.mask != 0) {
+            if version < extension.min_version {
+                return Err(Error::InvalidIsa(extension.mask));
+            }
+            if mask & extension.requires != extension.requires {
+                return Err(Error::InvalidIsa(extension.requires & !mask));
+            }
+        }
+        Ok(Isa(mask))
+    }
+
+    pub fn builder() -> IsaBuilder {
+        IsaBuilder::default()
+    }
+
+    pub fn mask(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, mask: u64) -> bool {
+        self.0 & mask == mask
+    }
+
+    // Names of the enabled extensions, in the order of EXTENSIONS.
+    pub fn extension_names(self) -> Vec<&'static str> {
+        EXTENSIONS
+            .iter()
+            .filter(|e| self.0 & e.mask != 0)
+            .map(|e| e.name)
+            .collect()
+    }
+}
+
+// Raw masks are converted without validation, since VERSION0 machines in the
+// wild might use any combination of them.
+impl From<u64> for Isa {
+    fn from(mask: u64) -> Self {
+        Isa(mask)
+    }
+}
+
+// Conversions from and to the 8-bit masks ISA constants used to be, which
+// have no room left for new extensions.
+impl Isa {
+    #[deprecated(note = "ISA masks are 64 bits wide now, use Isa::from")]
+    pub fn from_legacy(mask: u8) -> Self {
+        Isa(u64::from(mask))
+    }
+
+    #[deprecated(note = "ISA masks are 64 bits wide now, use Isa::mask")]
+    pub fn to_legacy(self) -> Result<u8, Error> {
+        u8::try_from(self.0).map_err(|_| Error::InvalidIsa(self.0 & !0xff))
+    }
+}
+
+#[derive(Default)]
+pub struct IsaBuilder {
+    mask: u64,
+}
+
+impl IsaBuilder {
+    pub fn b(self) -> Self {
+        self.extension(ISA_B)
+    }
+
+    pub fn mop(self) -> Self {
+        self.extension(ISA_MOP)
+    }
+
+    pub fn a(self) -> Self {
+        self.extension(ISA_A)
+    }
+
+    pub fn privileged(self) -> Self {
+        self.extension(ISA_PRIV)
+    }
+
+    pub fn f(self) -> Self {
+        self.extension(ISA_F)
+    }
+
+    pub fn d(self) -> Self {
+        self.extension(ISA_D)
+    }
+
+    pub fn v(self) -> Self {
+        self.extension(ISA_V)
+    }
+
+    pub fn zicsr(self) -> Self {
+        self.extension(ISA_ZICSR)
+    }
+
+    pub fn extension(mut self, mask: u64) -> Self {
+        self.mask |= mask;
+        self
+    }
+
+    pub fn build(self, version: u32) -> Result<Isa, Error> {
+        Isa::new(self.mask, version)
+    }
+}
+
+// Returns the largest Isa supported by `version`.
+pub fn max_isa(version: u32) -> Isa {
+    let mask = EXTENSIONS
+        .iter()
+        .filter(|e| version >= e.min_version && compiled(e))
+        .fold(0, |acc, e| acc | e.mask);
+    Isa(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::VERSION0;
+
+    #[test]
+    fn test_isa_validation() {
+        assert_eq!(Isa::new(0, VERSION0), Ok(Isa::IMC));
+        assert_eq!(
+            Isa::builder()
+                .b()
+                .mop()
+                .a()
+                .build(VERSION2)
+                .map(|i| i.mask()),
+            Ok(ISA_B | ISA_MOP | ISA_A)
+        );
+        assert_eq!(
+            Isa::builder().mop().build(VERSION0),
+            Err(Error::InvalidIsa(ISA_MOP))
+        );
+        assert_eq!(
+            Isa::builder().a().build(VERSION1),
+            Err(Error::InvalidIsa(ISA_A))
+        );
+        assert_eq!(Isa::new(1 << 40, VERSION2), Err(Error::InvalidIsa(1 << 40)));
+    }
+
+    #[test]
+    fn test_isa_extensions_do_not_overlap() {
+        let mut mask = 0;
+        for e in EXTENSIONS.iter() {
+            assert_eq!(mask & e.mask, 0);
+            mask |= e.mask;
+        }
+        assert_eq!(max_isa(VERSION2).mask() | ISA_V, mask);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_isa_legacy_conversion() {
+        let isa = Isa::from_legacy((ISA_B | ISA_MOP) as u8);
+        assert_eq!(isa, Isa::from(ISA_B | ISA_MOP));
+        assert_eq!(isa.to_legacy(), Ok((ISA_B | ISA_MOP) as u8));
+        assert_eq!(isa.extension_names(), vec!["B", "MOP"]);
+        assert_eq!(Isa(1 << 8).to_legacy(), Err(Error::InvalidIsa(1 << 8)));
+    }
+}
@@ -1,15 +1,42 @@
 #[macro_use]
 extern crate derive_more;
 
+#[cfg(feature = "snapshot")]
+mod aead;
+#[cfg(feature = "elf")]
+pub mod arch_test;
 pub mod bits;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod chacha;
+pub mod chrome_trace;
 pub mod cost_model;
 pub mod debugger;
 pub mod decoder;
+pub mod devices;
+#[cfg(feature = "elf")]
+pub mod elf;
+pub mod elf_writer;
 pub mod error;
+pub mod float;
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
+pub mod generator;
+#[cfg(feature = "elf")]
+pub mod golden;
+pub mod hash;
 pub mod instructions;
+pub mod isa;
 pub mod machine;
 pub mod memory;
+pub mod metrics;
+#[cfg(all(feature = "elf", feature = "trace"))]
+pub mod microbench;
+pub mod observer;
+#[cfg(feature = "snapshot")]
 pub mod snapshot;
+#[cfg(feature = "proptest")]
+pub mod strategies;
 pub mod syscalls;
 
 pub use bytes;
@@ -18,23 +45,30 @@ pub use ckb_vm_definitions;
 pub use crate::{
     debugger::Debugger,
     instructions::{Instruction, Register},
+    isa::Isa,
     machine::{
-        trace::TraceMachine, CoreMachine, DefaultCoreMachine, DefaultMachine,
+        config::MachineConfig, CoreMachine, DefaultCoreMachine, DefaultMachine,
         DefaultMachineBuilder, InstructionCycleFunc, Machine, SupportMachine,
     },
     memory::{flat::FlatMemory, sparse::SparseMemory, wxorx::WXorXMemory, Memory},
+    metrics::MetricsSink,
+    observer::{Event, EventObserver},
     syscalls::Syscalls,
 };
 pub use bytes::Bytes;
 
+#[cfg(feature = "trace")]
+pub use crate::machine::trace::TraceMachine;
+
 pub use ckb_vm_definitions::{
-    registers, DEFAULT_STACK_SIZE, ISA_A, ISA_B, ISA_IMC, ISA_MOP, MEMORY_FRAMES, MEMORY_FRAMESIZE,
-    MEMORY_FRAME_SHIFTS, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGES,
-    RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    registers, DEFAULT_STACK_SIZE, ISA_A, ISA_B, ISA_D, ISA_F, ISA_IMC, ISA_MOP, ISA_PRIV, ISA_V,
+    ISA_ZICSR, MEMORY_FRAMES, MEMORY_FRAMESIZE, MEMORY_FRAME_SHIFTS, RISCV_GENERAL_REGISTER_NUMBER,
+    RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
 };
 
 pub use error::Error;
 
+#[cfg(all(feature = "elf", feature = "trace"))]
 pub fn run<R: Register, M: Memory<REG = R>>(
     program: &Bytes,
     args: &[Bytes],
@@ -0,0 +1,145 @@
+use super::{
+    super::{
+        instructions::Register,
+        memory::Memory,
+        registers::{A0, A7, SP},
+    },
+    CoreMachine,
+};
+
+// Longest message extracted, longer strings are truncated.
+pub const MAX_ABORT_MESSAGE_LENGTH: u64 = 1024;
+// Number of bytes above SP searched for messages and pointers to them.
+pub const ABORT_STACK_SCAN_LENGTH: u64 = 1024;
+// Shortest string considered a message.
+const MIN_MESSAGE_LENGTH: usize = 4;
+
+// Runtime convention a message was recognized by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortKind {
+    // Rust panic messages: "panicked at src/main.rs:1:2: ...".
+    RustPanic,
+    // C assertions, from newlib's __assert_func or glibc's __assert_fail:
+    // "assertion "x" failed: file ...", "... Assertion `x' failed.".
+    Assertion,
+    // Messages mentioning an abort, e.g. "abort() called".
+    Abort,
+}
+
+// Message left by a guest runtime on abnormal termination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbortMessage {
+    pub kind: AbortKind,
+    // Guest address the message was found at.
+    pub address: u64,
+    pub message: String,
+}
+
+// Looks for the message explaining why a program stopped, since exit codes
+// alone hide the actual failure. Runtimes usually format it right before
+// exiting, so it is searched for:
+//
+// * behind pointers held by a0 to a7, as C strings, or as (pointer, length)
+//   pairs in consecutive registers like Rust's &str;
+// * behind pointers saved in the first ABORT_STACK_SCAN_LENGTH bytes above
+//   SP;
+// * inline in those bytes, for messages formatted into stack buffers.
+//
+// Only strings matching one of the conventions of AbortKind are reported,
+// any readable string would otherwise qualify. This is a best effort
+// heuristic meant for diagnostics, never for deciding a program's outcome.
+pub fn extract_abort_message<Mac: CoreMachine>(machine: &mut Mac) -> Option<AbortMessage> {
+    let registers: Vec<u64> = machine.registers()[A0..=A7]
+        .iter()
+        .map(|r| r.to_u64())
+        .collect();
+    for address in &registers {
+        if let Some(message) = c_string_message(machine.memory_mut(), *address) {
+            return Some(message);
+        }
+    }
+    for pair in registers.windows(2) {
+        if let Some(message) = slice_message(machine.memory_mut(), pair[0], pair[1]) {
+            return Some(message);
+        }
+    }
+
+    let sp = machine.registers()[SP].to_u64();
+    let memory_size = machine.memory().memory_size() as u64;
+    let length = ABORT_STACK_SCAN_LENGTH.min(memory_size.saturating_sub(sp));
+    let stack = machine.memory_mut().load_bytes(sp, length).ok()?;
+    let word_size = (<Mac::REG as Register>::BITS / 8) as usize;
+    for word in stack.chunks_exact(word_size) {
+        let mut bytes = [0u8; 8];
+        bytes[..word_size].copy_from_slice(word);
+        let address = u64::from_le_bytes(bytes);
+        if let Some(message) = c_string_message(machine.memory_mut(), address) {
+            return Some(message);
+        }
+    }
+    inline_message(&stack, sp)
+}
+
+fn c_string_message<M: Memory>(memory: &mut M, address: u64) -> Option<AbortMessage> {
+    if address == 0 || address >= memory.memory_size() as u64 {
+        return None;
+    }
+    let data = match memory.load_c_string(address, MAX_ABORT_MESSAGE_LENGTH) {
+        Ok(data) => data,
+        // Strings longer than the limit are truncated.
+        Err(_) => memory.load_bytes(address, MAX_ABORT_MESSAGE_LENGTH).ok()?,
+    };
+    recognize(&data, address)
+}
+
+fn slice_message<M: Memory>(memory: &mut M, address: u64, length: u64) -> Option<AbortMessage> {
+    if address == 0 || length < MIN_MESSAGE_LENGTH as u64 || length > MAX_ABORT_MESSAGE_LENGTH {
+        return None;
+    }
+    let data = memory.load_bytes(address, length).ok()?;
+    recognize(&data, address)
+}
+
+// Finds recognized messages stored in `data`, which starts at `address`.
+fn inline_message(data: &[u8], address: u64) -> Option<AbortMessage> {
+    let mut start = 0;
+    while start < data.len() {
+        let end = data[start..]
+            .iter()
+            .position(|b| !is_text(*b))
+            .map(|position| start + position)
+            .unwrap_or_else(|| data.len());
+        if let Some(message) = recognize(&data[start..end], address + start as u64) {
+            return Some(message);
+        }
+        start = end + 1;
+    }
+    None
+}
+
+fn is_text(byte: u8) -> bool {
+    byte.is_ascii_graphic() || byte == b' ' || byte == b'\n' || byte == b'\t' || byte == b'\r'
+}
+
+fn recognize(data: &[u8], address: u64) -> Option<AbortMessage> {
+    let text = std::str::from_utf8(data).ok()?.trim_end();
+    if text.len() < MIN_MESSAGE_LENGTH || text.chars().any(|c| c.is_control() && !c.is_whitespace())
+    {
+        return None;
+    }
+    let lowercase = text.to_lowercase();
+    let kind = if lowercase.contains("panicked at") {
+        AbortKind::RustPanic
+    } else if lowercase.contains("assertion") && lowercase.contains("failed") {
+        AbortKind::Assertion
+    } else if lowercase.contains("abort") {
+        AbortKind::Abort
+    } else {
+        return None;
+    };
+    Some(AbortMessage {
+        kind,
+        address,
+        message: text.to_string(),
+    })
+}
@@ -0,0 +1,153 @@
+use super::super::{instructions::Instruction, Error};
+#[cfg(feature = "trace")]
+use super::trace::Trace;
+
+// The number of trace items to keep by default, see TraceCacheConfig.
+pub(crate) const TRACE_SIZE: usize = 8192;
+
+// Trace to evict from a full set of the trace cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEviction {
+    // The trace formed first.
+    Fifo,
+    // The least recently run trace.
+    Lru,
+}
+
+// Geometry of the trace cache of TraceMachine, see
+// DefaultMachineBuilder::trace_cache. The cache holds `slots` traces in sets
+// of `ways`, and a trace can only be kept in the set its address maps to.
+// The default is a direct mapped cache of 8192 traces, programs with more
+// hot code than that thrash it and run faster with a larger or associative
+// cache:
+//
+// TraceCacheConfig {
+//     slots: 32768,
+//     ways: 4,
+//     eviction: TraceEviction::Lru,
+// }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceCacheConfig {
+    // A power of two, at most 65536.
+    pub slots: usize,
+    // A power of two, at most slots.
+    pub ways: usize,
+    pub eviction: TraceEviction,
+}
+
+impl Default for TraceCacheConfig {
+    fn default() -> Self {
+        Self {
+            slots: TRACE_SIZE,
+            ways: 1,
+            eviction: TraceEviction::Fifo,
+        }
+    }
+}
+
+impl TraceCacheConfig {
+    pub fn sets(&self) -> usize {
+        self.slots / self.ways
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.slots.is_power_of_two()
+            || !self.ways.is_power_of_two()
+            || self.ways > self.slots
+            || self.slots > 1 << 16
+        {
+            return Err(Error::Unexpected(format!(
+                "Invalid trace cache: {:?}",
+                self
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Buffers for decoded instructions and traces of a machine. They are kept
+// across runs and only reset, not freed, between executions, so a verifier
+// running many programs in a row can hand the arena of a finished machine to
+// the next one instead of allocating these buffers for every machine:
+//
+// let arena = machine.take_arena();
+// let machine = DefaultMachineBuilder::new(core).arena(arena).build();
+#[derive(Default)]
+pub struct Arena {
+    // Basic block decoded by the interpreter.
+    pub(crate) instructions: Vec<Instruction>,
+    // Trace cache of TraceMachine.
+    #[cfg(feature = "trace")]
+    pub(crate) traces: Vec<Trace>,
+    // Position in `traces` of the trace cached for each slot, traces are
+    // moved around by relayout_traces.
+    #[cfg(feature = "trace")]
+    pub(crate) trace_positions: Vec<u16>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Forgets all decoded instructions and traces, keeping the memory.
+    pub fn reset(&mut self) {
+        self.instructions.clear();
+        #[cfg(feature = "trace")]
+        for trace in self.traces.iter_mut() {
+            *trace = Trace::default();
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    // Allocates a trace cache of `slots` traces, this is a no-op once it is
+    // allocated with this size.
+    pub(crate) fn reserve_traces(&mut self, slots: usize) {
+        if self.trace_positions.len() != slots {
+            self.traces.clear();
+            self.traces.resize_with(slots, Trace::default);
+            self.trace_positions = (0..slots).map(|position| position as u16).collect();
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    // Position in `traces` of the trace cached for `slot`.
+    #[inline(always)]
+    pub(crate) fn trace_position(&self, slot: usize) -> usize {
+        usize::from(self.trace_positions[slot])
+    }
+
+    #[cfg(feature = "trace")]
+    // Moves traces so that they are ordered by the number of times they ran,
+    // the hottest ones are then packed together at the front of the cache
+    // instead of being scattered by address, whatever the geometry of the
+    // cache. Counts are halved afterwards so the layout follows the program
+    // when its hot spots move.
+    pub(crate) fn relayout_traces(&mut self) {
+        let mut order: Vec<usize> = (0..self.traces.len()).collect();
+        order.sort_by_key(|position| std::cmp::Reverse(self.traces[*position].executions));
+        let mut new_positions = vec![0u16; self.traces.len()];
+        let mut traces = Vec::with_capacity(self.traces.len());
+        for (new_position, old_position) in order.into_iter().enumerate() {
+            new_positions[old_position] = new_position as u16;
+            let mut trace = std::mem::take(&mut self.traces[old_position]);
+            trace.executions /= 2;
+            traces.push(trace);
+        }
+        self.traces = traces;
+        for position in self.trace_positions.iter_mut() {
+            *position = new_positions[usize::from(*position)];
+        }
+    }
+
+    // Bytes allocated by this arena.
+    pub fn capacity(&self) -> usize {
+        let instructions = self.instructions.capacity() * std::mem::size_of::<Instruction>();
+        #[cfg(feature = "trace")]
+        return instructions
+            + self.traces.capacity() * std::mem::size_of::<Trace>()
+            + self.trace_positions.capacity() * std::mem::size_of::<u16>();
+        #[cfg(not(feature = "trace"))]
+        instructions
+    }
+}
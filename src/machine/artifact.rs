@@ -0,0 +1,183 @@
+use super::super::decoder::PredecodedCode;
+use super::super::hash::{blake2b_256, ProgramHash, HASH_LENGTH};
+use super::super::Error;
+use std::fs;
+use std::path::PathBuf;
+
+const MAGIC: &[u8; 8] = b"CKBVMART";
+// Bumped whenever the encoding or the meaning of decoded instructions
+// changes, artifacts of other formats are ignored.
+pub const ARTIFACT_FORMAT_VERSION: u32 = 2;
+
+// Identifies what a decoded artifact was built from, artifacts are only
+// reused for the exact same program, VM version, ISA and load offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArtifactKey {
+    pub program_hash: ProgramHash,
+    pub version: u32,
+    pub isa: u64,
+    // Offset the program was loaded at, see AddressSpaceLayout::load_base.
+    pub offset: u64,
+}
+
+impl ArtifactKey {
+    // Name of the key usable as a file name.
+    pub fn name(&self) -> String {
+        let hash: String = self
+            .program_hash
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!(
+            "{}-v{}-isa{:02x}-{:x}",
+            hash, self.version, self.isa, self.offset
+        )
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.program_hash);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.isa.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+    }
+}
+
+// Encodes predecoded instructions as an artifact:
+//
+// * magic "CKBVMART" and the format version, as u32;
+// * the key: program hash, version as u32, ISA as u64, load offset as u64;
+// * the number of ranges as u64, then for each range its start address,
+//   its number of instructions as u64, and the instructions as u64;
+// * the CKB hash of everything before it, to detect corruption.
+//
+// Integers are little endian.
+pub fn encode_artifact(code: &PredecodedCode, key: &ArtifactKey) -> Vec<u8> {
+    let instructions: usize = code.ranges().iter().map(|(_, i)| i.len()).sum();
+    let mut out = Vec::with_capacity(96 + code.ranges().len() * 16 + instructions * 8);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&ARTIFACT_FORMAT_VERSION.to_le_bytes());
+    key.encode(&mut out);
+    out.extend_from_slice(&(code.ranges().len() as u64).to_le_bytes());
+    for (start, instructions) in code.ranges() {
+        out.extend_from_slice(&start.to_le_bytes());
+        out.extend_from_slice(&(instructions.len() as u64).to_le_bytes());
+        for instruction in instructions {
+            out.extend_from_slice(&instruction.to_le_bytes());
+        }
+    }
+    let checksum = blake2b_256(&out);
+    out.extend_from_slice(&checksum);
+    out
+}
+
+pub(crate) fn invalid(reason: &str) -> Error {
+    Error::Unexpected(format!("Invalid artifact: {}", reason))
+}
+
+// Decodes an artifact produced by encode_artifact, checking that it is
+// intact and was built for `key`.
+pub fn decode_artifact(data: &[u8], key: &ArtifactKey) -> Result<PredecodedCode, Error> {
+    if data.len() < MAGIC.len() + 4 + HASH_LENGTH {
+        return Err(invalid("truncated"));
+    }
+    let (body, checksum) = data.split_at(data.len() - HASH_LENGTH);
+    if blake2b_256(body) != checksum {
+        return Err(invalid("checksum mismatch"));
+    }
+    let mut reader = Reader(body);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("bad magic"));
+    }
+    if reader.u32()? != ARTIFACT_FORMAT_VERSION {
+        return Err(invalid("unsupported format version"));
+    }
+    let mut expected = vec![];
+    key.encode(&mut expected);
+    if reader.take(expected.len())? != &expected[..] {
+        return Err(invalid("built for another program or machine"));
+    }
+    let count = reader.u64()?;
+    let mut ranges = vec![];
+    for _ in 0..count {
+        let start = reader.u64()?;
+        let length = reader.u64()?;
+        if length > (reader.0.len() / 8) as u64 {
+            return Err(invalid("truncated"));
+        }
+        let instructions = (0..length)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>, _>>()?;
+        ranges.push((start, instructions));
+    }
+    if !reader.0.is_empty() {
+        return Err(invalid("trailing data"));
+    }
+    Ok(PredecodedCode::from_ranges(ranges))
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if length > self.0.len() {
+            return Err(invalid("truncated"));
+        }
+        let (head, tail) = self.0.split_at(length);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+// Storage of decoded artifacts, see DefaultMachineBuilder::artifact_cache.
+// Failures are not fatal: a missing or invalid artifact makes the machine
+// decode the program again and store a fresh artifact.
+pub trait ArtifactCache: Send + Sync {
+    fn load(&self, key: &ArtifactKey) -> Option<Vec<u8>>;
+    fn store(&self, key: &ArtifactKey, artifact: &[u8]) -> Result<(), Error>;
+}
+
+// Keeps artifacts as files in a directory, named after their key.
+pub struct DirectoryArtifactCache {
+    directory: PathBuf,
+}
+
+impl DirectoryArtifactCache {
+    // Creates the directory if needed.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<Self, Error> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    pub fn path(&self, key: &ArtifactKey) -> PathBuf {
+        self.directory.join(format!("{}.artifact", key.name()))
+    }
+}
+
+impl ArtifactCache for DirectoryArtifactCache {
+    fn load(&self, key: &ArtifactKey) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    // Writes to a temporary file renamed over the artifact, so concurrent
+    // readers never see a partial artifact.
+    fn store(&self, key: &ArtifactKey, artifact: &[u8]) -> Result<(), Error> {
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, artifact)?;
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+}
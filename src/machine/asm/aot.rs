@@ -0,0 +1,743 @@
+use super::AsmMachine;
+use crate::{
+    decoder::{Decoder, PredecodedCode},
+    hash::{blake2b_256, HASH_LENGTH},
+    instructions::{
+        extract_opcode, instruction_length, insts, is_basic_block_end_instruction, Instruction,
+        Itype, Rtype, Stype, Utype,
+    },
+    machine::artifact::{invalid, ArtifactKey, Reader},
+    CoreMachine, Error, SupportMachine,
+};
+use ckb_vm_definitions::{asm::AsmCoreMachine, RISCV_GENERAL_REGISTER_NUMBER};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"CKBVMAOT";
+// Bumped whenever the encoding or the generated code changes.
+pub const AOT_FORMAT_VERSION: u32 = 2;
+
+// AsmCoreMachine is repr(C) and starts with the registers, then pc.
+const PC_OFFSET: u32 = 8 * RISCV_GENERAL_REGISTER_NUMBER as u32;
+
+// The generated code only uses rax, rcx and rdx, which are caller saved, and
+// addresses the machine through rdi, its only argument.
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RDI: u8 = 7;
+const REX_W: u8 = 0x48;
+
+// Opcodes of `op rax, rcx`.
+const ADD: u8 = 0x01;
+const OR: u8 = 0x09;
+const AND: u8 = 0x21;
+const SUB: u8 = 0x29;
+const XOR: u8 = 0x31;
+const CMP: u8 = 0x39;
+// Extensions of the immediate ALU opcodes and of the shift opcodes.
+const ADD_IMM: u8 = 0;
+const OR_IMM: u8 = 1;
+const AND_IMM: u8 = 4;
+const XOR_IMM: u8 = 6;
+const CMP_IMM: u8 = 7;
+const SHL: u8 = 4;
+const SHR: u8 = 5;
+const SAR: u8 = 7;
+// Condition codes of setcc and cmovcc.
+const BELOW: u8 = 0x2;
+const ABOVE_OR_EQUAL: u8 = 0x3;
+const EQUAL: u8 = 0x4;
+const NOT_EQUAL: u8 = 0x5;
+const LESS: u8 = 0xc;
+const GREATER_OR_EQUAL: u8 = 0xd;
+
+struct Emitter(Vec<u8>);
+
+impl Emitter {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn rex(&mut self, wide: bool) {
+        if wide {
+            self.0.push(REX_W);
+        }
+    }
+
+    // mov host, x[register]
+    fn load(&mut self, host: u8, register: usize) {
+        if register == 0 {
+            // xor host, host
+            self.bytes(&[0x31, 0xc0 | host << 3 | host]);
+        } else {
+            self.bytes(&[REX_W, 0x8b, 0x80 | host << 3 | RDI]);
+            self.bytes(&(8 * register as u32).to_le_bytes());
+        }
+    }
+
+    // mov [rdi + offset], rax
+    fn store(&mut self, offset: u32) {
+        self.bytes(&[REX_W, 0x89, 0x80 | RAX << 3 | RDI]);
+        self.bytes(&offset.to_le_bytes());
+    }
+
+    // Writes rax to x[rd], writes to x0 are dropped.
+    fn store_register(&mut self, rd: usize) {
+        if rd != 0 {
+            self.store(8 * rd as u32);
+        }
+    }
+
+    fn mov_imm(&mut self, host: u8, value: u64) {
+        if value as i64 == i64::from(value as i32) {
+            // mov host, imm32 sign extended
+            self.bytes(&[REX_W, 0xc7, 0xc0 | host]);
+            self.bytes(&(value as u32).to_le_bytes());
+        } else {
+            // movabs host, imm64
+            self.bytes(&[REX_W, 0xb8 | host]);
+            self.bytes(&value.to_le_bytes());
+        }
+    }
+
+    // op rax, rcx
+    fn alu(&mut self, opcode: u8, wide: bool) {
+        self.rex(wide);
+        self.bytes(&[opcode, 0xc0 | RCX << 3 | RAX]);
+    }
+
+    // op rax, imm32
+    fn alu_imm(&mut self, extension: u8, imm: i32, wide: bool) {
+        self.rex(wide);
+        self.bytes(&[0x81, 0xc0 | extension << 3 | RAX]);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    // imul rax, rcx
+    fn mul(&mut self, wide: bool) {
+        self.rex(wide);
+        self.bytes(&[0x0f, 0xaf, 0xc0 | RAX << 3 | RCX]);
+    }
+
+    // Shifts rax by an immediate, or by cl. Like RISC-V, x86 only uses the
+    // low 6 bits of the amount, or 5 bits on 32 bits.
+    fn shift(&mut self, extension: u8, amount: Option<i32>, wide: bool) {
+        self.rex(wide);
+        match amount {
+            Some(amount) => self.bytes(&[0xc1, 0xc0 | extension << 3 | RAX, amount as u8]),
+            None => self.bytes(&[0xd3, 0xc0 | extension << 3 | RAX]),
+        }
+    }
+
+    // movsxd rax, eax
+    fn sign_extend(&mut self) {
+        self.bytes(&[REX_W, 0x63, 0xc0 | RAX << 3 | RAX]);
+    }
+
+    // setcc al, then movzx eax, al
+    fn set(&mut self, condition: u8) {
+        self.bytes(&[0x0f, 0x90 | condition, 0xc0, 0x0f, 0xb6, 0xc0]);
+    }
+
+    // cmovcc rax, rdx
+    fn cmov(&mut self, condition: u8) {
+        self.bytes(&[REX_W, 0x0f, 0x40 | condition, 0xc0 | RAX << 3 | RDX]);
+    }
+
+    // Sets pc to rax and returns.
+    fn exit(&mut self) {
+        self.store(PC_OFFSET);
+        self.0.push(0xc3);
+    }
+}
+
+fn register_op<F: FnOnce(&mut Emitter)>(e: &mut Emitter, i: Instruction, op: F) {
+    let i = Rtype(i);
+    e.load(RAX, i.rs1());
+    e.load(RCX, i.rs2());
+    op(e);
+    e.store_register(i.rd());
+}
+
+fn immediate_op<F: FnOnce(&mut Emitter, i32)>(e: &mut Emitter, i: Instruction, op: F) {
+    let i = Itype(i);
+    e.load(RAX, i.rs1());
+    op(e, i.immediate_s());
+    e.store_register(i.rd());
+}
+
+fn branch(e: &mut Emitter, i: Instruction, pc: u64, next_pc: u64, condition: u8) {
+    let i = Stype(i);
+    e.load(RAX, i.rs1());
+    e.load(RCX, i.rs2());
+    e.alu(CMP, true);
+    // mov leaves the flags alone.
+    e.mov_imm(RAX, next_pc);
+    e.mov_imm(RDX, pc.wrapping_add(i.immediate_s() as u64));
+    e.cmov(condition);
+    e.exit();
+}
+
+// Emits the native code of `i` at `pc`. Returns None, without emitting
+// anything, for instructions left to the interpreter, otherwise whether the
+// instruction ends the block by setting pc itself.
+fn lower(e: &mut Emitter, i: Instruction, pc: u64) -> Option<bool> {
+    let next_pc = pc.wrapping_add(u64::from(instruction_length(i)));
+    match extract_opcode(i) {
+        insts::OP_ADD => register_op(e, i, |e| e.alu(ADD, true)),
+        insts::OP_SUB => register_op(e, i, |e| e.alu(SUB, true)),
+        insts::OP_AND => register_op(e, i, |e| e.alu(AND, true)),
+        insts::OP_OR => register_op(e, i, |e| e.alu(OR, true)),
+        insts::OP_XOR => register_op(e, i, |e| e.alu(XOR, true)),
+        insts::OP_SLL => register_op(e, i, |e| e.shift(SHL, None, true)),
+        insts::OP_SRL => register_op(e, i, |e| e.shift(SHR, None, true)),
+        insts::OP_SRA => register_op(e, i, |e| e.shift(SAR, None, true)),
+        insts::OP_MUL => register_op(e, i, |e| e.mul(true)),
+        insts::OP_SLT => register_op(e, i, |e| {
+            e.alu(CMP, true);
+            e.set(LESS);
+        }),
+        insts::OP_SLTU => register_op(e, i, |e| {
+            e.alu(CMP, true);
+            e.set(BELOW);
+        }),
+        insts::OP_ADDW => register_op(e, i, |e| {
+            e.alu(ADD, false);
+            e.sign_extend();
+        }),
+        insts::OP_SUBW => register_op(e, i, |e| {
+            e.alu(SUB, false);
+            e.sign_extend();
+        }),
+        insts::OP_SLLW => register_op(e, i, |e| {
+            e.shift(SHL, None, false);
+            e.sign_extend();
+        }),
+        insts::OP_SRLW => register_op(e, i, |e| {
+            e.shift(SHR, None, false);
+            e.sign_extend();
+        }),
+        insts::OP_SRAW => register_op(e, i, |e| {
+            e.shift(SAR, None, false);
+            e.sign_extend();
+        }),
+        insts::OP_MULW => register_op(e, i, |e| {
+            e.mul(false);
+            e.sign_extend();
+        }),
+        insts::OP_ADDI => immediate_op(e, i, |e, imm| e.alu_imm(ADD_IMM, imm, true)),
+        insts::OP_ANDI => immediate_op(e, i, |e, imm| e.alu_imm(AND_IMM, imm, true)),
+        insts::OP_ORI => immediate_op(e, i, |e, imm| e.alu_imm(OR_IMM, imm, true)),
+        insts::OP_XORI => immediate_op(e, i, |e, imm| e.alu_imm(XOR_IMM, imm, true)),
+        insts::OP_SLLI => immediate_op(e, i, |e, imm| e.shift(SHL, Some(imm & 0x3f), true)),
+        insts::OP_SRLI => immediate_op(e, i, |e, imm| e.shift(SHR, Some(imm & 0x3f), true)),
+        insts::OP_SRAI => immediate_op(e, i, |e, imm| e.shift(SAR, Some(imm & 0x3f), true)),
+        insts::OP_SLTI => immediate_op(e, i, |e, imm| {
+            e.alu_imm(CMP_IMM, imm, true);
+            e.set(LESS);
+        }),
+        insts::OP_SLTIU => immediate_op(e, i, |e, imm| {
+            e.alu_imm(CMP_IMM, imm, true);
+            e.set(BELOW);
+        }),
+        insts::OP_ADDIW => immediate_op(e, i, |e, imm| {
+            e.alu_imm(ADD_IMM, imm, false);
+            e.sign_extend();
+        }),
+        insts::OP_SLLIW => immediate_op(e, i, |e, imm| {
+            e.shift(SHL, Some(imm & 0x1f), false);
+            e.sign_extend();
+        }),
+        insts::OP_SRLIW => immediate_op(e, i, |e, imm| {
+            e.shift(SHR, Some(imm & 0x1f), false);
+            e.sign_extend();
+        }),
+        insts::OP_SRAIW => immediate_op(e, i, |e, imm| {
+            e.shift(SAR, Some(imm & 0x1f), false);
+            e.sign_extend();
+        }),
+        insts::OP_LUI => {
+            let i = Utype(i);
+            e.mov_imm(RAX, i.immediate_s() as u64);
+            e.store_register(i.rd());
+        }
+        insts::OP_AUIPC => {
+            let i = Utype(i);
+            e.mov_imm(RAX, pc.wrapping_add(i.immediate_s() as u64));
+            e.store_register(i.rd());
+        }
+        insts::OP_JAL => {
+            let i = Utype(i);
+            if i.rd() != 0 {
+                e.mov_imm(RAX, next_pc);
+                e.store_register(i.rd());
+            }
+            e.mov_imm(RAX, pc.wrapping_add(i.immediate_s() as u64));
+            e.exit();
+            return Some(true);
+        }
+        insts::OP_BEQ => branch(e, i, pc, next_pc, EQUAL),
+        insts::OP_BNE => branch(e, i, pc, next_pc, NOT_EQUAL),
+        insts::OP_BLT => branch(e, i, pc, next_pc, LESS),
+        insts::OP_BGE => branch(e, i, pc, next_pc, GREATER_OR_EQUAL),
+        insts::OP_BLTU => branch(e, i, pc, next_pc, BELOW),
+        insts::OP_BGEU => branch(e, i, pc, next_pc, ABOVE_OR_EQUAL),
+        _ => return None,
+    }
+    Some(is_branch(i))
+}
+
+fn is_branch(i: Instruction) -> bool {
+    matches!(
+        extract_opcode(i),
+        insts::OP_BEQ
+            | insts::OP_BNE
+            | insts::OP_BLT
+            | insts::OP_BGE
+            | insts::OP_BLTU
+            | insts::OP_BGEU
+    )
+}
+
+// Emits the block at `address` running the leading `instructions` that can
+// be lowered, and returns them.
+fn emit_block<I>(e: &mut Emitter, address: u64, instructions: I) -> Vec<Instruction>
+where
+    I: Iterator<Item = Instruction>,
+{
+    let mut lowered = vec![];
+    let mut pc = address;
+    for i in instructions {
+        match lower(e, i, pc) {
+            Some(true) => {
+                lowered.push(i);
+                return lowered;
+            }
+            Some(false) => {
+                lowered.push(i);
+                pc = pc.wrapping_add(u64::from(instruction_length(i)));
+            }
+            None => break,
+        }
+    }
+    e.mov_imm(RAX, pc);
+    e.exit();
+    lowered
+}
+
+// Native code mapped read only and executable.
+struct ExecutableBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The buffer owns its mapping, which is never written once created.
+unsafe impl Send for ExecutableBuffer {}
+unsafe impl Sync for ExecutableBuffer {}
+
+impl ExecutableBuffer {
+    fn new(code: &[u8]) -> Result<Self, Error> {
+        if code.is_empty() {
+            return Ok(Self {
+                ptr: NonNull::dangling(),
+                len: 0,
+            });
+        }
+        unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                code.len(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            std::ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+            if libc::mprotect(ptr, code.len(), libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                let error = std::io::Error::last_os_error();
+                libc::munmap(ptr, code.len());
+                return Err(error.into());
+            }
+            Ok(Self {
+                ptr: NonNull::new_unchecked(ptr as *mut u8),
+                len: code.len(),
+            })
+        }
+    }
+}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+type NativeBlock = unsafe extern "sysv64" fn(*mut AsmCoreMachine);
+
+pub struct AotBlock {
+    pub address: u64,
+    // The instructions run by the native code, the interpreter takes over
+    // after them unless the last one is a jump.
+    pub instructions: Vec<Instruction>,
+    offset: usize,
+}
+
+// Native x86-64 code compiled ahead of time from the decoded instructions of
+// a program, see AsmMachine::set_aot_code. Straight line integer code and
+// jumps are compiled, one block for each address a basic block can start at,
+// while memory accesses, system calls and the other instructions are left to
+// the interpreter. The code can be shared between machines behind an Arc, and
+// serialized to skip compiling when the program runs again:
+//
+// let code = AotCode::compile(machine.machine.predecoded().unwrap(), key)?;
+// cache.store(&key, &code.serialize())?;
+// ...
+// let code = Arc::new(AotCode::deserialize(&cache.load(&key).unwrap(), &key)?);
+// machine.set_aot_code(Arc::clone(&code))?;
+pub struct AotCode {
+    key: ArtifactKey,
+    blocks: Vec<AotBlock>,
+    // Index of the block at each address.
+    entries: HashMap<u64, usize>,
+    code: Vec<u8>,
+    buffer: ExecutableBuffer,
+}
+
+impl AotCode {
+    // Compiles the instructions of a program, decoded as it is in memory,
+    // see DefaultMachineBuilder::predecode. `key` identifies the program the
+    // code runs.
+    pub fn compile(predecoded: &PredecodedCode, key: ArtifactKey) -> Result<Self, Error> {
+        let mut e = Emitter(vec![]);
+        let mut scratch = Emitter(vec![]);
+        let mut blocks = vec![];
+        for (start, instructions) in predecoded.ranges() {
+            let end = start + 2 * instructions.len() as u64;
+            // Instructions decoded at `address`, following each other.
+            let sequence = |address: u64| {
+                let mut index = ((address - start) / 2) as usize;
+                std::iter::from_fn(move || {
+                    let i = *instructions.get(index)?;
+                    if i == 0 {
+                        return None;
+                    }
+                    index += usize::from(instruction_length(i) / 2);
+                    Some(i)
+                })
+            };
+            // Blocks start at the range, at jump targets, and after any
+            // instruction ending a block, or left to the interpreter.
+            let mut starts = BTreeSet::new();
+            starts.insert(*start);
+            let mut address = *start;
+            while address < end {
+                let i = instructions[((address - start) / 2) as usize];
+                let length = if i == 0 { 2 } else { instruction_length(i) };
+                let next = address + u64::from(length);
+                scratch.0.clear();
+                if i == 0
+                    || is_basic_block_end_instruction(i)
+                    || lower(&mut scratch, i, address).is_none()
+                {
+                    starts.insert(next);
+                }
+                match extract_opcode(i) {
+                    insts::OP_JAL => {
+                        starts.insert(address.wrapping_add(Utype(i).immediate_s() as u64));
+                    }
+                    _ if is_branch(i) => {
+                        starts.insert(address.wrapping_add(Stype(i).immediate_s() as u64));
+                    }
+                    _ => (),
+                }
+                address = next;
+            }
+            for address in starts.range(*start..end) {
+                if address % 2 != 0 {
+                    continue;
+                }
+                let offset = e.0.len();
+                let instructions = emit_block(&mut e, *address, sequence(*address));
+                if instructions.is_empty() {
+                    e.0.truncate(offset);
+                } else {
+                    blocks.push(AotBlock {
+                        address: *address,
+                        instructions,
+                        offset,
+                    });
+                }
+            }
+        }
+        Self::build(key, blocks, e.0)
+    }
+
+    fn build(key: ArtifactKey, blocks: Vec<AotBlock>, code: Vec<u8>) -> Result<Self, Error> {
+        let entries = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.address, index))
+            .collect();
+        let buffer = ExecutableBuffer::new(&code)?;
+        Ok(Self {
+            key,
+            blocks,
+            entries,
+            code,
+            buffer,
+        })
+    }
+
+    pub fn key(&self) -> &ArtifactKey {
+        &self.key
+    }
+
+    pub fn blocks(&self) -> &[AotBlock] {
+        &self.blocks
+    }
+
+    // The generated native code.
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    // Encodes the code like an artifact:
+    //
+    // * magic "CKBVMAOT" and the format version, as u32;
+    // * the key, see encode_artifact;
+    // * the number of blocks as u64, then for each block its address, its
+    //   number of instructions as u64, and the instructions as u64;
+    // * the native code, prefixed by its length as u64;
+    // * the CKB hash of everything before it, to detect corruption.
+    //
+    // Integers are little endian.
+    pub fn serialize(&self) -> Vec<u8> {
+        let instructions: usize = self.blocks.iter().map(|b| b.instructions.len()).sum();
+        let mut out =
+            Vec::with_capacity(96 + self.blocks.len() * 16 + instructions * 8 + self.code.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&AOT_FORMAT_VERSION.to_le_bytes());
+        self.key.encode(&mut out);
+        out.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+        for block in &self.blocks {
+            out.extend_from_slice(&block.address.to_le_bytes());
+            out.extend_from_slice(&(block.instructions.len() as u64).to_le_bytes());
+            for instruction in &block.instructions {
+                out.extend_from_slice(&instruction.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&(self.code.len() as u64).to_le_bytes());
+        out.extend_from_slice(&self.code);
+        let checksum = blake2b_256(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    // Decodes code serialized for `key`. Native code is never run as loaded:
+    // it has to be exactly what compiling the serialized instructions emits,
+    // so a forged buffer cannot run arbitrary code on the host.
+    pub fn deserialize(data: &[u8], key: &ArtifactKey) -> Result<Self, Error> {
+        if data.len() < MAGIC.len() + 4 + HASH_LENGTH {
+            return Err(invalid("truncated"));
+        }
+        let (body, checksum) = data.split_at(data.len() - HASH_LENGTH);
+        if blake2b_256(body) != checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        let mut reader = Reader(body);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        if reader.u32()? != AOT_FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+        let mut expected = vec![];
+        key.encode(&mut expected);
+        if reader.take(expected.len())? != &expected[..] {
+            return Err(invalid("built for another program or machine"));
+        }
+        let count = reader.u64()?;
+        let mut e = Emitter(vec![]);
+        let mut blocks = vec![];
+        for _ in 0..count {
+            let address = reader.u64()?;
+            let length = reader.u64()?;
+            if length > (reader.0.len() / 8) as u64 {
+                return Err(invalid("truncated"));
+            }
+            let instructions = (0..length)
+                .map(|_| reader.u64())
+                .collect::<Result<Vec<_>, _>>()?;
+            let offset = e.0.len();
+            if instructions.is_empty()
+                || emit_block(&mut e, address, instructions.iter().copied()) != instructions
+            {
+                return Err(invalid("block cannot be compiled"));
+            }
+            blocks.push(AotBlock {
+                address,
+                instructions,
+                offset,
+            });
+        }
+        let length = reader.u64()?;
+        if length > reader.0.len() as u64 {
+            return Err(invalid("truncated"));
+        }
+        if reader.take(length as usize)? != &e.0[..] {
+            return Err(invalid("native code mismatch"));
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid("trailing data"));
+        }
+        Self::build(*key, blocks, e.0)
+    }
+
+    // Writes a line for each block, with the address and the length of its
+    // native code and its guest address, in the format of the perf maps
+    // perf and other host profilers read to name JIT code:
+    //
+    // 7f3a2c001000 2a guest_100b0
+    pub fn write_perf_map<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let base = self.buffer.ptr.as_ptr() as usize;
+        for (index, block) in self.blocks.iter().enumerate() {
+            // Blocks are emitted one after the other.
+            let end = self
+                .blocks
+                .get(index + 1)
+                .map_or(self.code.len(), |next| next.offset);
+            writeln!(
+                writer,
+                "{:x} {:x} guest_{:x}",
+                base + block.offset,
+                end - block.offset,
+                block.address
+            )?;
+        }
+        Ok(())
+    }
+
+    // Returns the index of the block at `pc`, if any.
+    pub(crate) fn entry(&self, pc: u64) -> Option<usize> {
+        self.entries.get(&pc).copied()
+    }
+
+    // Runs the block at `index`, leaving pc at the next instruction to run.
+    pub(crate) fn run_block(&self, index: usize, machine: &mut AsmCoreMachine) {
+        // The generated code only reads and writes the registers and pc of
+        // the machine.
+        unsafe {
+            let entry = self.buffer.ptr.as_ptr().add(self.blocks[index].offset);
+            let block: NativeBlock = std::mem::transmute(entry);
+            block(machine);
+        }
+    }
+}
+
+// The perf map of the current process, where perf looks for the names of
+// JIT code.
+pub fn perf_map_path() -> PathBuf {
+    PathBuf::from(format!("/tmp/perf-{}.map", std::process::id()))
+}
+
+impl AsmMachine {
+    // Appends the perf map of the code passed to set_aot_code to `path`,
+    // usually perf_map_path(), so that host profilers name native blocks
+    // after the guest code they run. Disabled by default.
+    pub fn set_perf_map(&mut self, path: Option<PathBuf>) {
+        self.perf_map = path;
+    }
+
+    // Runs the program with `code`, compiled for it ahead of time, which has
+    // to match the ISA and version of the machine. Cycles of each block are
+    // charged before it runs, like traces of the assembly interpreter.
+    pub fn set_aot_code(&mut self, code: Arc<AotCode>) -> Result<(), Error> {
+        if code.key().isa != self.machine.isa() || code.key().version != self.machine.version() {
+            return Err(invalid("built for another program or machine"));
+        }
+        if let Some(path) = &self.perf_map {
+            let mut map = vec![];
+            code.write_perf_map(&mut map)?;
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&map)?;
+        }
+        let cycles = code
+            .blocks()
+            .iter()
+            .map(|block| {
+                block
+                    .instructions
+                    .iter()
+                    .map(|i| self.machine.instruction_cycle_func()(*i))
+                    .sum::<u64>()
+            })
+            .collect();
+        self.aot = Some((code, cycles));
+        Ok(())
+    }
+
+    // Runs the native code, and the interpreter between its blocks, until the
+    // machine stops or its code is invalidated, e.g. by loading another
+    // program. The assembly interpreter then runs the rest without it.
+    pub(super) fn run_aot(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
+        let (code, cycles) = match &self.aot {
+            Some((code, cycles)) => (Arc::clone(code), cycles.clone()),
+            None => return Ok(()),
+        };
+        // Set when the program was loaded.
+        if self.machine.reset_signal() {
+            decoder.reset_instructions_cache();
+        }
+        while self.machine.running() {
+            if self.machine.reset_signal() {
+                decoder.reset_instructions_cache();
+                self.aot = None;
+                return Ok(());
+            }
+            self.machine.apply_cycles_budget();
+            match code.entry(*self.machine.pc()) {
+                Some(index) => {
+                    self.machine.add_cycles(cycles[index])?;
+                    code.run_block(index, self.machine.inner_mut());
+                }
+                None => {
+                    if let Err(e) = self.machine.step_instruction(decoder) {
+                        self.machine.handle_invalid_instruction(e)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aot_pc_offset() {
+        let machine = AsmCoreMachine::new(0, 0, 0);
+        let base = &*machine as *const AsmCoreMachine as usize;
+        assert_eq!(
+            std::ptr::addr_of!(machine.pc) as usize - base,
+            PC_OFFSET as usize
+        );
+        assert_eq!(std::ptr::addr_of!(machine.registers) as usize, base);
+    }
+}
@@ -43,18 +43,18 @@
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_CHAOS_MODE 296
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_CHAOS_SEED 300
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LOAD_RESERVATION_ADDRESS 304
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_SIZE 320
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES_SIZE 328
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS_SIZE 336
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_READ_FRAME 344
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_WRITE_PAGE 352
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS 360
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY 2426232
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_TRACES 1400
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES 1384
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_SIZE 336
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES_SIZE 344
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS_SIZE 352
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_READ_FRAME 360
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_WRITE_PAGE 368
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS 376
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY 2426248
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_TRACES 1416
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES 1400
 
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_H 2424832
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_L 1400
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_L 1416
 
 #define CKB_VM_ASM_OP_UNLOADED 16
 #define CKB_VM_ASM_OP_ADD 17
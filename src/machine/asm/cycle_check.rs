@@ -0,0 +1,185 @@
+use super::{ckb_vm_x64_execute, AsmMachine};
+use crate::{
+    decoder::{build_decoder, Decoder},
+    instructions::execute,
+    machine::{Machine, VERSION0},
+    CoreMachine, DefaultMachine, Error, Register, SupportMachine, ISA_MOP,
+};
+use ckb_vm_definitions::asm::{
+    calculate_slot, Trace, RET_CYCLES_OVERFLOW, RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK,
+    RET_ECALL, RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, RET_SLOWPATH,
+};
+use std::fmt::{self, Display};
+
+// State of a backend at the end of a basic block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEnd {
+    pub pc: u64,
+    pub cycles: u64,
+    // Set once the program exited.
+    pub exit_code: Option<i8>,
+    // Set when the block failed.
+    pub error: Option<Error>,
+}
+
+impl Display for BlockEnd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pc=0x{:x} cycles={}", self.pc, self.cycles)?;
+        if let Some(exit_code) = self.exit_code {
+            write!(f, " exit={}", exit_code)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " error={:?}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// The first basic block after which the interpreter and the ASM backend
+// disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleMismatch {
+    // Index of the block, starting from 0.
+    pub block: u64,
+    // Address of the first instruction in the block.
+    pub address: u64,
+    // Instructions in the block.
+    pub instructions: usize,
+    pub interpreter: BlockEnd,
+    pub asm: BlockEnd,
+}
+
+impl Display for CycleMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block {} at 0x{:x} ({} instructions): interpreter {}, asm {}",
+            self.block, self.address, self.instructions, self.interpreter, self.asm
+        )
+    }
+}
+
+fn block_end<Inner: SupportMachine>(
+    machine: &DefaultMachine<Inner>,
+    error: Option<Error>,
+) -> BlockEnd {
+    BlockEnd {
+        pc: machine.pc().to_u64(),
+        cycles: machine.cycles(),
+        exit_code: if machine.running() {
+            None
+        } else {
+            Some(machine.exit_code())
+        },
+        error,
+    }
+}
+
+impl AsmMachine {
+    // Runs the basic block at PC on its own, returning the number of
+    // instructions in it and the error the block failed with, if any. Max
+    // cycles are lowered so that the assembly code stops before entering the
+    // next block, which is why every block has to cost at least one cycle.
+    fn run_block(&mut self, decoder: &mut Decoder) -> Result<(usize, Option<Error>), Error> {
+        let pc = *self.machine.pc();
+        let slot = calculate_slot(pc);
+        let (trace, instructions) = match self.build_trace(decoder, pc) {
+            Ok(trace) => trace,
+            Err(e) => return Ok((1, Some(e))),
+        };
+        if trace.cycles == 0 {
+            return Err(Error::Unexpected(format!(
+                "block at 0x{:x} costs no cycles",
+                pc
+            )));
+        }
+        let max_cycles = self.machine.max_cycles();
+        let start_cycles = self.machine.cycles();
+        self.machine
+            .set_max_cycles(start_cycles.saturating_add(trace.cycles).min(max_cycles));
+        self.machine.inner_mut().traces[slot] = trace;
+        let result = unsafe { ckb_vm_x64_execute(&mut **self.machine.inner_mut()) };
+        self.machine.set_max_cycles(max_cycles);
+        self.machine.inner_mut().traces[slot] = Trace::default();
+        // Cycles of a block are charged up front, unchanged cycles mean the
+        // block itself did not run.
+        let entered = self.machine.cycles() != start_cycles;
+        let error = match result {
+            RET_DECODE_TRACE | RET_DYNAMIC_JUMP => None,
+            RET_MAX_CYCLES_EXCEEDED if entered => None,
+            RET_ECALL => self.machine.ecall().err(),
+            RET_EBREAK => self.machine.ebreak().err(),
+            RET_MAX_CYCLES_EXCEEDED => Some(Error::CyclesExceeded),
+            RET_CYCLES_OVERFLOW => Some(Error::CyclesOverflow),
+            RET_OUT_OF_BOUND => Some(Error::MemOutOfBound),
+            RET_INVALID_PERMISSION => Some(Error::MemWriteOnExecutablePage),
+            RET_SLOWPATH => {
+                let pc = *self.machine.pc();
+                decoder
+                    .decode(self.machine.memory_mut(), pc)
+                    .and_then(|instruction| execute(instruction, &mut self.machine))
+                    .err()
+            }
+            _ => Some(Error::Asm(result)),
+        };
+        Ok((instructions, error))
+    }
+}
+
+// Runs a program on the interpreter and the ASM backend side by side, and
+// compares PC, cycles and exit status at every basic block boundary instead
+// of only at exit, so that a metering difference is pinned to the block
+// causing it before it turns into a consensus bug.
+//
+// Both machines must have the same program loaded, the same syscalls, and
+// the same instruction cycle function, which must not charge zero cycles for
+// a whole block. Blocks are the traces the ASM backend builds, for each of
+// them the interpreter executes the same number of instructions. Returns the
+// first mismatch, or None when both machines exit the same way. When both
+// fail with the same error, that error is returned without comparing cycles,
+// since the ASM backend charges a block before running it.
+pub fn check_cycles<Inner: SupportMachine>(
+    interpreter: &mut DefaultMachine<Inner>,
+    asm: &mut AsmMachine,
+) -> Result<Option<CycleMismatch>, Error> {
+    if asm.machine.isa() & ISA_MOP != 0 && asm.machine.version() == VERSION0 {
+        return Err(Error::InvalidVersion);
+    }
+    let mut decoder = build_decoder::<u64>(asm.machine.isa(), asm.machine.version());
+    let mut interpreter_decoder =
+        build_decoder::<Inner::REG>(interpreter.isa(), interpreter.version());
+    interpreter.set_running(true);
+    asm.machine.set_running(true);
+    let mut block = 0;
+    while asm.machine.running() {
+        let address = *asm.machine.pc();
+        let (instructions, asm_error) = asm.run_block(&mut decoder)?;
+        let mut interpreter_error = None;
+        let mut executed = 0;
+        while interpreter.running() && executed < instructions {
+            if let Err(e) = interpreter.step_instruction(&mut interpreter_decoder) {
+                interpreter_error = Some(e);
+                break;
+            }
+            executed += 1;
+        }
+        if let (Some(expected), Some(actual)) = (&interpreter_error, &asm_error) {
+            if expected == actual {
+                return Err(actual.clone());
+            }
+        }
+        let interpreter_end = block_end(interpreter, interpreter_error);
+        let asm_end = block_end(&asm.machine, asm_error);
+        if interpreter_end != asm_end {
+            return Ok(Some(CycleMismatch {
+                block,
+                address,
+                instructions,
+                interpreter: interpreter_end,
+                asm: asm_end,
+            }));
+        }
+        block += 1;
+    }
+    Ok(None)
+}
@@ -1,3 +1,7 @@
+#[cfg(all(unix, target_arch = "x86_64"))]
+pub mod aot;
+pub mod cycle_check;
+
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::Bytes;
 pub use ckb_vm_definitions::asm::AsmCoreMachine;
@@ -15,20 +19,40 @@ use rand::{prelude::RngCore, SeedableRng};
 use std::os::raw::c_uchar;
 
 use crate::{
-    decoder::{build_decoder, Decoder},
+    decoder::Decoder,
     instructions::{
-        blank_instruction, execute_instruction, extract_opcode, instruction_length,
+        blank_instruction, execute, extract_opcode, instruction_length,
         is_basic_block_end_instruction,
     },
-    machine::VERSION0,
+    machine::{
+        config::{FromConfig, MachineConfig},
+        VERSION0,
+    },
     memory::{
-        fill_page_data, get_page_indices, memset, round_page_down, round_page_up, FLAG_DIRTY,
-        FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT,
+        advise_huge_pages, fill_memory, fill_page_data, get_page_indices, memset, round_page_down,
+        round_page_up, FLAG_DIRTY, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT,
     },
+    observer::Event,
     CoreMachine, DefaultMachine, Error, Machine, Memory, SupportMachine, MEMORY_FRAME_SHIFTS,
     RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE,
 };
 
+impl FromConfig for Box<AsmCoreMachine> {
+    fn from_config(config: &MachineConfig) -> Result<Self, Error> {
+        let mut machine = AsmCoreMachine::new_with_memory(
+            config.checked_isa()?.mask(),
+            config.version,
+            config.max_cycles,
+            config.memory_size,
+        );
+        if config.huge_pages {
+            machine.use_huge_pages();
+        }
+        fill_memory(&mut machine, config.memory_fill)?;
+        Ok(machine)
+    }
+}
+
 impl CoreMachine for Box<AsmCoreMachine> {
     type REG = u64;
     type MEM = Self;
@@ -61,7 +85,7 @@ impl CoreMachine for Box<AsmCoreMachine> {
         self.registers[idx] = value;
     }
 
-    fn isa(&self) -> u8 {
+    fn isa(&self) -> u64 {
         self.isa
     }
 
@@ -322,6 +346,30 @@ impl Memory for Box<AsmCoreMachine> {
         ))
     }
 
+    fn use_huge_pages(&mut self) -> bool {
+        let memory_size = self.memory_size as usize;
+        advise_huge_pages(&mut self.memory[..memory_size])
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let page_indices = get_page_indices(src, size)?;
+        for page in page_indices.0..=page_indices.1 {
+            check_memory(self, page);
+        }
+        let page_indices = get_page_indices(dst, size)?;
+        for page in page_indices.0..=page_indices.1 {
+            check_permission(self, page, FLAG_WRITABLE)?;
+            check_memory(self, page);
+            self.set_flag(page, FLAG_DIRTY)?;
+        }
+        self.memory
+            .copy_within(src as usize..(src + size) as usize, dst as usize);
+        Ok(())
+    }
+
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
         check_memory_executable(self, addr, 2)?;
         Ok(LittleEndian::read_u16(
@@ -422,6 +470,10 @@ impl SupportMachine for Box<AsmCoreMachine> {
         self.max_cycles
     }
 
+    fn set_max_cycles(&mut self, cycles: u64) {
+        self.max_cycles = cycles;
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
         self.pc = 0;
@@ -442,6 +494,13 @@ impl SupportMachine for Box<AsmCoreMachine> {
         ret
     }
 
+    fn invalidate_code(&mut self) {
+        for trace in self.traces.iter_mut() {
+            *trace = Trace::default();
+        }
+        self.reset_signal = 1;
+    }
+
     fn running(&self) -> bool {
         self.running == 1
     }
@@ -465,67 +524,87 @@ extern "C" {
 
 pub struct AsmMachine {
     pub machine: DefaultMachine<Box<AsmCoreMachine>>,
+    // Native code of the program with the cycles of each of its blocks, see
+    // set_aot_code.
+    #[cfg(all(unix, target_arch = "x86_64"))]
+    aot: Option<(std::sync::Arc<aot::AotCode>, Vec<u64>)>,
+    // See set_perf_map.
+    #[cfg(all(unix, target_arch = "x86_64"))]
+    perf_map: Option<std::path::PathBuf>,
 }
 
 impl AsmMachine {
     pub fn new(machine: DefaultMachine<Box<AsmCoreMachine>>) -> Self {
-        Self { machine }
+        Self {
+            machine,
+            #[cfg(all(unix, target_arch = "x86_64"))]
+            aot: None,
+            #[cfg(all(unix, target_arch = "x86_64"))]
+            perf_map: None,
+        }
     }
 
     pub fn set_max_cycles(&mut self, cycles: u64) {
         self.machine.inner.max_cycles = cycles;
     }
 
+    #[cfg(feature = "elf")]
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
         self.machine.load_program(program, args)
     }
 
+    // See DefaultMachine::reload_program.
+    #[cfg(feature = "elf")]
+    pub fn reload_program(&mut self, program: &Bytes) -> Result<u64, Error> {
+        self.machine.reload_program(program)
+    }
+
     pub fn run(&mut self) -> Result<i8, Error> {
+        let start_cycles = self.machine.start_run();
+        let result = self.run_inner();
+        self.machine.report_metrics(start_cycles, &result);
+        result
+    }
+
+    fn run_inner(&mut self) -> Result<i8, Error> {
         if self.machine.isa() & ISA_MOP != 0 && self.machine.version() == VERSION0 {
             return Err(Error::InvalidVersion);
         }
-        let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        let mut decoder = self.machine.build_decoder();
         self.machine.set_running(true);
+        #[cfg(all(unix, target_arch = "x86_64"))]
+        self.run_aot(&mut decoder)?;
         while self.machine.running() {
             if self.machine.reset_signal() {
                 decoder.reset_instructions_cache();
             }
+            self.machine.apply_cycles_budget();
             let result = unsafe { ckb_vm_x64_execute(&mut **self.machine.inner_mut()) };
             match result {
                 RET_DECODE_TRACE => {
+                    // Trace cache hits happen inside assembly code and are
+                    // not visible here, only misses can be reported.
+                    if let Some(metrics) = &mut self.machine.metrics {
+                        metrics.trace_cache_miss();
+                    }
                     let pc = *self.machine.pc();
                     let slot = calculate_slot(pc);
-                    let mut trace = Trace::default();
-                    let mut current_pc = pc;
-                    let mut i = 0;
-                    while i < TRACE_ITEM_LENGTH {
-                        let instruction = decoder.decode(self.machine.memory_mut(), current_pc)?;
-                        let end_instruction = is_basic_block_end_instruction(instruction);
-                        current_pc += u64::from(instruction_length(instruction));
-                        trace.instructions[i] = instruction;
-                        trace.cycles += self.machine.instruction_cycle_func()(instruction);
-                        let opcode = extract_opcode(instruction);
-                        // Here we are calculating the absolute address used in direct threading
-                        // from label offsets.
-                        trace.thread[i] = unsafe {
-                            u64::from(
-                                *(ckb_vm_asm_labels as *const u32).offset(opcode as u8 as isize),
-                            ) + (ckb_vm_asm_labels as *const u32 as u64)
-                        };
-                        i += 1;
-                        if end_instruction {
-                            break;
+                    let (trace, instructions) = match self.build_trace(&mut decoder, pc) {
+                        Ok(built) => built,
+                        Err(e) => {
+                            self.machine.handle_invalid_instruction(e)?;
+                            continue;
                         }
-                    }
-                    trace.instructions[i] = blank_instruction(OP_CUSTOM_TRACE_END);
-                    trace.thread[i] = unsafe {
-                        u64::from(
-                            *(ckb_vm_asm_labels as *const u32).offset(OP_CUSTOM_TRACE_END as isize),
-                        ) + (ckb_vm_asm_labels as *const u32 as u64)
                     };
-                    trace.address = pc;
-                    trace.length = (current_pc - pc) as u8;
+                    let length = u64::from(trace.length);
                     self.machine.inner_mut().traces[slot] = trace;
+                    if self.machine.has_observer() {
+                        self.machine.notify(&Event::TraceCompiled {
+                            address: pc,
+                            length,
+                            instructions,
+                        });
+                    }
                 }
                 RET_ECALL => self.machine.ecall()?,
                 RET_EBREAK => self.machine.ebreak()?,
@@ -535,9 +614,9 @@ impl AsmMachine {
                 RET_OUT_OF_BOUND => return Err(Error::MemOutOfBound),
                 RET_INVALID_PERMISSION => return Err(Error::MemWriteOnExecutablePage),
                 RET_SLOWPATH => {
-                    let pc = *self.machine.pc() - 4;
+                    let pc = *self.machine.pc();
                     let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
-                    execute_instruction(instruction, &mut self.machine)?;
+                    execute(instruction, &mut self.machine)?;
                 }
                 _ => return Err(Error::Asm(result)),
             }
@@ -545,6 +624,54 @@ impl AsmMachine {
         Ok(self.machine.exit_code())
     }
 
+    // Decodes the basic block starting at `pc` into a trace, returning it
+    // together with the number of instructions it holds. A trace only holds
+    // decoded instructions and the addresses of their handlers in the
+    // assembly interpreter, no native code is generated for it, so host
+    // profilers attribute its time to those handlers. Code compiled ahead of
+    // time is named after the guest code instead, see
+    // AsmMachine::set_perf_map.
+    fn build_trace(&mut self, decoder: &mut Decoder, pc: u64) -> Result<(Trace, usize), Error> {
+        let mut trace = Trace::default();
+        let mut current_pc = pc;
+        let mut i = 0;
+        while i < TRACE_ITEM_LENGTH {
+            let instruction = match decoder.decode(self.machine.memory_mut(), current_pc) {
+                Ok(instruction) => instruction,
+                // See TraceMachine::lookup_trace.
+                Err(Error::InvalidInstruction { .. })
+                    if i > 0 && self.machine.handles_invalid_instructions() =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            };
+            let end_instruction = is_basic_block_end_instruction(instruction);
+            current_pc += u64::from(instruction_length(instruction));
+            trace.instructions[i] = instruction;
+            trace.cycles += self.machine.instruction_cycle_func()(instruction);
+            let opcode = extract_opcode(instruction);
+            // Here we are calculating the absolute address used in direct threading
+            // from label offsets.
+            trace.thread[i] = unsafe {
+                u64::from(*(ckb_vm_asm_labels as *const u32).offset(opcode as u8 as isize))
+                    + (ckb_vm_asm_labels as *const u32 as u64)
+            };
+            i += 1;
+            if end_instruction {
+                break;
+            }
+        }
+        trace.instructions[i] = blank_instruction(OP_CUSTOM_TRACE_END);
+        trace.thread[i] = unsafe {
+            u64::from(*(ckb_vm_asm_labels as *const u32).offset(OP_CUSTOM_TRACE_END as isize))
+                + (ckb_vm_asm_labels as *const u32 as u64)
+        };
+        trace.address = pc;
+        trace.length = (current_pc - pc) as u8;
+        Ok((trace, i))
+    }
+
     pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
         // Decode only one instruction into a trace
         let pc = *self.machine.pc();
@@ -577,9 +704,9 @@ impl AsmMachine {
             RET_OUT_OF_BOUND => return Err(Error::MemOutOfBound),
             RET_INVALID_PERMISSION => return Err(Error::MemWriteOnExecutablePage),
             RET_SLOWPATH => {
-                let pc = *self.machine.pc() - 4;
+                let pc = *self.machine.pc();
                 let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
-                execute_instruction(instruction, &mut self.machine)?;
+                execute(instruction, &mut self.machine)?;
             }
             _ => return Err(Error::Asm(result)),
         }
@@ -591,9 +718,11 @@ impl AsmMachine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instructions::MAX_INSTRUCTION_LENGTH;
 
     #[test]
     fn test_asm_constant_rules() {
-        assert!(TRACE_ITEM_LENGTH * 4 < 256);
+        // Trace lengths are stored in a byte.
+        assert!(TRACE_ITEM_LENGTH * (MAX_INSTRUCTION_LENGTH as usize) < 256);
     }
 }
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bytes::Bytes;
+
+use super::{
+    super::Error,
+    arena::Arena,
+    config::{FromConfig, MachineConfig},
+    report::{ExecutionReport, ExitReason},
+    DefaultMachine, DefaultMachineBuilder, InstructionCycleFunc, SupportMachine,
+};
+
+// A program to run by run_batch. `config` is handed to the setup function of
+// the batch, which uses it to pick the syscalls and other options of the
+// machine running this job.
+pub struct BatchJob<C> {
+    pub program: Bytes,
+    pub args: Vec<Bytes>,
+    pub max_cycles: u64,
+    pub config: C,
+}
+
+impl<C> BatchJob<C> {
+    pub fn new(program: Bytes, args: Vec<Bytes>, max_cycles: u64, config: C) -> Self {
+        Self {
+            program,
+            args,
+            max_cycles,
+            config,
+        }
+    }
+}
+
+// Jobs not taken by a worker yet, with their index in the batch.
+type JobQueue<C> = Arc<Mutex<VecDeque<(usize, BatchJob<C>)>>>;
+// Outcome of each job, None until a worker ran it.
+type JobOutcomes = Arc<Mutex<Vec<Option<Result<ExecutionReport, Error>>>>>;
+
+// Outcome of every job of a batch, in the order the jobs were given. A job
+// fails with an error when its program can't be loaded, the outcome of the
+// run itself is in the report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResults {
+    pub jobs: Vec<Result<ExecutionReport, Error>>,
+}
+
+impl BatchResults {
+    // Cycles consumed by all jobs.
+    pub fn total_cycles(&self) -> u64 {
+        self.jobs
+            .iter()
+            .filter_map(|job| job.as_ref().ok())
+            .fold(0u64, |total, report| total.saturating_add(report.cycles))
+    }
+
+    // Jobs whose program exited with code 0.
+    pub fn succeeded(&self) -> usize {
+        self.jobs
+            .iter()
+            .filter(|job| matches!(job, Ok(report) if report.exit_reason == ExitReason::Exit(0)))
+            .count()
+    }
+
+    // Jobs which failed to load, exited with a non zero code, ran out of
+    // cycles or stopped with an error.
+    pub fn failed(&self) -> usize {
+        self.jobs.len() - self.succeeded()
+    }
+}
+
+// Runs `jobs` on `workers` threads. Each worker creates one core machine from
+// `config` and resets it between jobs, together with the buffers of its
+// Arena, so machines are pooled instead of being allocated per job. The
+// instruction cycle function of `config` applies to all jobs, while `setup`
+// configures the rest of each machine, typically its syscalls. Metrics sinks
+// belong to a single machine, the one of `config` is not used:
+//
+// let results = run_batch::<DefaultCoreMachine<u64, SparseMemory<u64>>, _, _>(
+//     config,
+//     4,
+//     jobs,
+//     |builder, config: &Flags| builder.syscall(Box::new(Debug::new(config.verbose))),
+// )?;
+// println!("{} / {} jobs passed", results.succeeded(), results.jobs.len());
+//
+// An error is only returned when no core machine can be created from
+// `config`, a worker panicking fails the jobs it had not finished.
+pub fn run_batch<Inner, C, F>(
+    mut config: MachineConfig,
+    workers: usize,
+    jobs: Vec<BatchJob<C>>,
+    setup: F,
+) -> Result<BatchResults, Error>
+where
+    Inner: SupportMachine + FromConfig + 'static,
+    C: Send + Sync + 'static,
+    F: Fn(DefaultMachineBuilder<Inner>, &C) -> DefaultMachineBuilder<Inner> + Send + Sync + 'static,
+{
+    // Reports config errors up front instead of failing every job.
+    Inner::from_config(&config)?;
+    let count = jobs.len();
+    let workers = workers.max(1).min(count.max(1));
+    let queue: JobQueue<C> = Arc::new(Mutex::new(jobs.into_iter().enumerate().collect()));
+    let results: JobOutcomes = Arc::new(Mutex::new(vec![None; count]));
+    let cycle_func = config
+        .instruction_cycle_func
+        .take()
+        .map(Arc::<InstructionCycleFunc>::from);
+    let config = Arc::new(config);
+    let setup = Arc::new(setup);
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let results = Arc::clone(&results);
+            let cycle_func = cycle_func.as_ref().map(Arc::clone);
+            let config = Arc::clone(&config);
+            let setup = Arc::clone(&setup);
+            thread::spawn(move || {
+                let mut core: Option<Inner> = None;
+                let mut arena = Arena::new();
+                loop {
+                    let next = queue.lock().expect("batch queue poisoned").pop_front();
+                    let (index, job) = match next {
+                        Some(next) => next,
+                        None => return,
+                    };
+                    let inner = match core.take() {
+                        Some(mut inner) => {
+                            inner.reset(job.max_cycles);
+                            Ok(inner)
+                        }
+                        None => Inner::from_config(&config).map(|mut inner| {
+                            inner.set_max_cycles(job.max_cycles);
+                            inner
+                        }),
+                    };
+                    let result = inner.and_then(|inner| {
+                        let mut builder =
+                            DefaultMachineBuilder::new(inner).arena(std::mem::take(&mut arena));
+                        if let Some(func) = &cycle_func {
+                            let func = Arc::clone(func);
+                            builder = builder.instruction_cycle_func(Box::new(move |i| func(i)));
+                        }
+                        let mut machine = setup(builder, &job.config).build();
+                        let result = run_job(&mut machine, &job);
+                        arena = machine.take_arena();
+                        core = Some(machine.take_inner());
+                        result
+                    });
+                    results.lock().expect("batch results poisoned")[index] = Some(result);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        // Jobs of a panicking worker are left without a result, the others
+        // keep taking jobs from the queue.
+        let _ = handle.join();
+    }
+    // A panicking worker never holds the lock, the results collected so far
+    // are valid either way.
+    let results = std::mem::take(&mut *results.lock().unwrap_or_else(|e| e.into_inner()));
+    let jobs = results
+        .into_iter()
+        .map(|result| {
+            result
+                .unwrap_or_else(|| Err(Error::Unexpected(String::from("A batch worker panicked"))))
+        })
+        .collect();
+    Ok(BatchResults { jobs })
+}
+
+fn run_job<Inner: SupportMachine, C>(
+    machine: &mut DefaultMachine<Inner>,
+    job: &BatchJob<C>,
+) -> Result<ExecutionReport, Error> {
+    machine.load_program(&job.program, &job.args)?;
+    Ok(machine.run_with_report(false))
+}
@@ -0,0 +1,54 @@
+use super::SupportMachine;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// CyclesBudget allows another thread to adjust max cycles of a running
+// machine, e.g. an interactive environment topping up the budget while a
+// program runs. Adjustments are accumulated atomically, and picked up by the
+// machine at the next safe point: between instructions for DefaultMachine,
+// between traces for TraceMachine, and each time AsmMachine returns to Rust.
+//
+// let budget = CyclesBudget::new();
+// let machine = DefaultMachineBuilder::new(core).cycles_budget(budget.clone()).build();
+// std::thread::spawn(move || budget.add(1_000_000));
+#[derive(Clone, Default)]
+pub struct CyclesBudget {
+    added: Arc<AtomicU64>,
+    reduced: Arc<AtomicU64>,
+}
+
+impl CyclesBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Increases max cycles by `cycles`.
+    pub fn add(&self, cycles: u64) {
+        saturating_fetch_add(&self.added, cycles);
+    }
+
+    // Decreases max cycles by `cycles`, a machine whose consumed cycles end
+    // up above the new max cycles stops with CyclesExceeded.
+    pub fn reduce(&self, cycles: u64) {
+        saturating_fetch_add(&self.reduced, cycles);
+    }
+
+    // Applies pending adjustments to `machine`.
+    pub(crate) fn apply<Mac: SupportMachine>(&self, machine: &mut Mac) {
+        let added = self.added.swap(0, Ordering::AcqRel);
+        let reduced = self.reduced.swap(0, Ordering::AcqRel);
+        if added != 0 || reduced != 0 {
+            let max_cycles = machine
+                .max_cycles()
+                .saturating_add(added)
+                .saturating_sub(reduced);
+            machine.set_max_cycles(max_cycles);
+        }
+    }
+}
+
+fn saturating_fetch_add(value: &AtomicU64, delta: u64) {
+    let _ = value.fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| {
+        Some(v.saturating_add(delta))
+    });
+}
@@ -0,0 +1,232 @@
+use super::{
+    super::{
+        instructions::{instruction_length, Instruction},
+        Error,
+    },
+    instrumented::{InstrumentHandler, MemoryAccess},
+    symbols::Symbols,
+    CoreMachine,
+};
+
+// Geometry of a simulated cache. Sizes are in bytes, and all values must be
+// powers of two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub size: u64,
+    pub line_size: u64,
+    pub ways: u64,
+}
+
+impl CacheConfig {
+    pub fn new(size: u64, line_size: u64, ways: u64) -> Self {
+        Self {
+            size,
+            line_size,
+            ways,
+        }
+    }
+
+    fn sets(&self) -> Result<u64, Error> {
+        let valid = self.size.is_power_of_two()
+            && self.line_size.is_power_of_two()
+            && self.ways.is_power_of_two()
+            && self.line_size.saturating_mul(self.ways) <= self.size;
+        if !valid {
+            return Err(Error::Unexpected(format!(
+                "Invalid cache config {:?}",
+                self
+            )));
+        }
+        Ok(self.size / self.line_size / self.ways)
+    }
+}
+
+impl Default for CacheConfig {
+    // 32KB, 64 byte lines, 8 ways, a common L1 geometry.
+    fn default() -> Self {
+        Self::new(32 * 1024, 64, 8)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            return 0.0;
+        }
+        self.misses as f64 / self.accesses() as f64
+    }
+
+    fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+// A set associative cache with LRU replacement. Only tags are tracked, the
+// data itself always comes from the machine memory.
+pub struct Cache {
+    config: CacheConfig,
+    sets: u64,
+    // Tags of each set, most recently used first.
+    tags: Vec<Vec<u64>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Result<Self, Error> {
+        let sets = config.sets()?;
+        Ok(Self {
+            config,
+            sets,
+            tags: vec![Vec::with_capacity(config.ways as usize); sets as usize],
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn config(&self) -> CacheConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Accesses `size` bytes at `address`, and returns whether all the lines
+    // touched were cached. An access crossing lines counts once.
+    pub fn access(&mut self, address: u64, size: u64) -> bool {
+        let first = address / self.config.line_size;
+        let last = address.saturating_add(size.max(1) - 1) / self.config.line_size;
+        let mut hit = true;
+        for line in first..=last {
+            hit &= self.access_line(line);
+        }
+        self.stats.record(hit);
+        hit
+    }
+
+    fn access_line(&mut self, line: u64) -> bool {
+        let set = &mut self.tags[(line % self.sets) as usize];
+        let tag = line / self.sets;
+        match set.iter().position(|t| *t == tag) {
+            Some(position) => {
+                set[..=position].rotate_right(1);
+                true
+            }
+            None => {
+                if set.len() as u64 == self.config.ways {
+                    set.pop();
+                }
+                set.insert(0, tag);
+                false
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for set in &mut self.tags {
+            set.clear();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionCacheStats {
+    pub name: String,
+    pub icache: CacheStats,
+    pub dcache: CacheStats,
+}
+
+// Name of the function collecting accesses from code without symbols.
+pub const UNKNOWN_FUNCTION: &str = "[unknown]";
+
+// CacheSimulator runs an I-cache and a D-cache alongside an
+// InstrumentedMachine, and attributes hits and misses to the guest function
+// executing the access. It estimates how guest code would behave on real
+// hardware, the cycles charged by the machine are not affected:
+//
+// let symbols = Symbols::from_elf(&program)?;
+// let simulator = CacheSimulator::new(CacheConfig::default(), CacheConfig::default(), symbols)?;
+// let mut machine = InstrumentedMachine::new(DefaultMachineBuilder::new(core).build(), simulator);
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// for stats in machine.handler.function_stats() { ... }
+pub struct CacheSimulator {
+    icache: Cache,
+    dcache: Cache,
+    symbols: Symbols,
+    // Indexed by symbol index, the last entry is UNKNOWN_FUNCTION.
+    functions: Vec<(CacheStats, CacheStats)>,
+    // Function of the last instruction, which memory accesses belong to.
+    current: usize,
+}
+
+impl CacheSimulator {
+    pub fn new(icache: CacheConfig, dcache: CacheConfig, symbols: Symbols) -> Result<Self, Error> {
+        let functions = vec![(CacheStats::default(), CacheStats::default()); symbols.len() + 1];
+        Ok(Self {
+            icache: Cache::new(icache)?,
+            dcache: Cache::new(dcache)?,
+            current: symbols.len(),
+            symbols,
+            functions,
+        })
+    }
+
+    pub fn icache(&self) -> CacheStats {
+        self.icache.stats()
+    }
+
+    pub fn dcache(&self) -> CacheStats {
+        self.dcache.stats()
+    }
+
+    // Returns the statistics of functions which executed at least one
+    // instruction, sorted by misses in descending order.
+    pub fn function_stats(&self) -> Vec<FunctionCacheStats> {
+        let mut stats: Vec<_> = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, (icache, _))| icache.accesses() > 0)
+            .map(|(index, (icache, dcache))| FunctionCacheStats {
+                name: if index < self.symbols.len() {
+                    self.symbols.name(index).to_string()
+                } else {
+                    UNKNOWN_FUNCTION.to_string()
+                },
+                icache: *icache,
+                dcache: *dcache,
+            })
+            .collect();
+        stats.sort_by_key(|s| std::cmp::Reverse(s.icache.misses + s.dcache.misses));
+        stats
+    }
+}
+
+impl<M: CoreMachine> InstrumentHandler<M> for CacheSimulator {
+    fn instruction(&mut self, _machine: &M, pc: u64, instruction: Instruction) {
+        self.current = self.symbols.index(pc).unwrap_or(self.symbols.len());
+        let hit = self
+            .icache
+            .access(pc, u64::from(instruction_length(instruction)));
+        self.functions[self.current].0.record(hit);
+    }
+
+    fn memory(&mut self, _machine: &M, access: &MemoryAccess) {
+        let hit = self.dcache.access(access.address, u64::from(access.size));
+        self.functions[self.current].1.record(hit);
+    }
+}
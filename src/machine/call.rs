@@ -0,0 +1,244 @@
+#[cfg(feature = "trace")]
+use super::trace::TraceMachine;
+use super::{
+    super::{
+        registers::{A0, A1, RA, SP},
+        Error, Memory, Register,
+    },
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+#[cfg(feature = "elf")]
+use bytes::Bytes;
+
+// Calling a guest function directly makes unit testing of individual guest
+// functions trivial:
+//
+// let address = find_symbol(&program, "add")?.unwrap();
+// let sum: u64 = machine.call(address, &mut [1.into(), 2.into()])?;
+//
+// Arguments are passed following the RISC-V calling convention: each argument
+// takes one of a0 - a7. Buffers are copied to the guest stack, their addresses
+// are passed to the function, and mutable buffers are copied back once the
+// function returns. Arguments passed on stack are not supported.
+pub enum CallArg<'a> {
+    Int(u64),
+    Buffer(&'a [u8]),
+    BufferMut(&'a mut [u8]),
+}
+
+impl<'a> From<u64> for CallArg<'a> {
+    fn from(value: u64) -> Self {
+        CallArg::Int(value)
+    }
+}
+
+impl<'a> From<&'a [u8]> for CallArg<'a> {
+    fn from(value: &'a [u8]) -> Self {
+        CallArg::Buffer(value)
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for CallArg<'a> {
+    fn from(value: &'a mut [u8]) -> Self {
+        CallArg::BufferMut(value)
+    }
+}
+
+// The function returns to this address, which is never executed since
+// run_until stops right after the jump. A function jumping to address 0 by
+// itself is treated as returned as well.
+pub const CALL_RETURN_ADDRESS: u64 = 0;
+
+const MAX_CALL_ARGS: usize = 8;
+
+// Converts a0 and a1 into the return value of a guest function. Values wider
+// than a register are returned in a0(low bits) and a1(high bits).
+pub trait ReturnValue: Sized {
+    fn from_registers(a0: u64, a1: u64, bits: u8) -> Self;
+}
+
+impl ReturnValue for () {
+    fn from_registers(_a0: u64, _a1: u64, _bits: u8) -> Self {}
+}
+
+impl ReturnValue for bool {
+    fn from_registers(a0: u64, _a1: u64, _bits: u8) -> Self {
+        a0 & 0xff != 0
+    }
+}
+
+macro_rules! impl_return_value {
+    ($($t:ty),*) => {
+        $(
+            impl ReturnValue for $t {
+                fn from_registers(a0: u64, a1: u64, bits: u8) -> Self {
+                    if bits == 32 {
+                        (a0 & 0xffff_ffff | a1 << 32) as $t
+                    } else {
+                        a0 as $t
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_return_value!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+impl ReturnValue for u128 {
+    fn from_registers(a0: u64, a1: u64, bits: u8) -> Self {
+        if bits == 32 {
+            // Only 64 bits fit in a0 and a1 on 32-bit machines.
+            u128::from(u64::from_registers(a0, a1, bits))
+        } else {
+            u128::from(a0) | u128::from(a1) << 64
+        }
+    }
+}
+
+impl ReturnValue for i128 {
+    fn from_registers(a0: u64, a1: u64, bits: u8) -> Self {
+        if bits == 32 {
+            i128::from(i64::from_registers(a0, a1, bits))
+        } else {
+            u128::from_registers(a0, a1, bits) as i128
+        }
+    }
+}
+
+// Registers and buffers to restore once the call returns.
+struct CallFrame {
+    pc: u64,
+    sp: u64,
+    ra: u64,
+    buffers: Vec<Option<u64>>,
+}
+
+fn setup_call<Mac: CoreMachine>(
+    machine: &mut Mac,
+    address: u64,
+    args: &[CallArg],
+) -> Result<CallFrame, Error> {
+    if args.len() > MAX_CALL_ARGS {
+        return Err(Error::Unexpected(format!(
+            "Too many call arguments: {}, at most {} are supported",
+            args.len(),
+            MAX_CALL_ARGS
+        )));
+    }
+    let frame_pc = machine.pc().to_u64();
+    let frame_sp = machine.registers()[SP].to_u64();
+    let frame_ra = machine.registers()[RA].to_u64();
+    let mut sp = frame_sp;
+    let mut buffers = Vec::with_capacity(args.len());
+    for (i, arg) in args.iter().enumerate() {
+        let (value, buffer) = match arg {
+            CallArg::Int(value) => (*value, None),
+            CallArg::Buffer(data) => (push_buffer(machine, &mut sp, data)?, None),
+            CallArg::BufferMut(data) => {
+                let address = push_buffer(machine, &mut sp, data)?;
+                (address, Some(address))
+            }
+        };
+        machine.set_register(A0 + i, Mac::REG::from_u64(value));
+        buffers.push(buffer);
+    }
+    machine.set_register(SP, Mac::REG::from_u64(sp));
+    machine.set_register(RA, Mac::REG::from_u64(CALL_RETURN_ADDRESS));
+    machine.update_pc(Mac::REG::from_u64(address));
+    machine.commit_pc();
+    Ok(CallFrame {
+        pc: frame_pc,
+        sp: frame_sp,
+        ra: frame_ra,
+        buffers,
+    })
+}
+
+// Copies data below sp, keeping sp aligned to 16 bytes as required by the
+// calling convention.
+fn push_buffer<Mac: CoreMachine>(
+    machine: &mut Mac,
+    sp: &mut u64,
+    data: &[u8],
+) -> Result<u64, Error> {
+    let address = sp
+        .checked_sub(data.len() as u64)
+        .ok_or(Error::MemOutOfStack)?
+        & !15;
+    machine.memory_mut().store_bytes(address, data)?;
+    *sp = address;
+    Ok(address)
+}
+
+fn finish_call<Mac: CoreMachine, T: ReturnValue>(
+    machine: &mut Mac,
+    frame: CallFrame,
+    args: &mut [CallArg],
+) -> Result<T, Error> {
+    let value = T::from_registers(
+        machine.registers()[A0].to_u64(),
+        machine.registers()[A1].to_u64(),
+        Mac::REG::BITS,
+    );
+    for (arg, buffer) in args.iter_mut().zip(frame.buffers) {
+        if let (CallArg::BufferMut(data), Some(address)) = (arg, buffer) {
+            let bytes = machine
+                .memory_mut()
+                .load_bytes(address, data.len() as u64)?;
+            data.copy_from_slice(&bytes);
+        }
+    }
+    machine.set_register(SP, Mac::REG::from_u64(frame.sp));
+    machine.set_register(RA, Mac::REG::from_u64(frame.ra));
+    machine.update_pc(Mac::REG::from_u64(frame.pc));
+    machine.commit_pc();
+    Ok(value)
+}
+
+// A call ends with an exit code when the guest function invokes the exit
+// syscall instead of returning.
+fn call_exited(exit_code: i8) -> Error {
+    Error::Unexpected(format!(
+        "Guest function exited with code {} before returning",
+        exit_code
+    ))
+}
+
+impl<Inner: SupportMachine> DefaultMachine<Inner> {
+    // Calls the guest function at `address` and runs until it returns. The
+    // program must already be loaded, since the stack set up by load_program
+    // is reused. PC, SP and RA are restored after the call, other registers
+    // are left as the function sets them.
+    pub fn call<T: ReturnValue>(&mut self, address: u64, args: &mut [CallArg]) -> Result<T, Error> {
+        let frame = setup_call(self, address, args)?;
+        if let Some(exit_code) = self.run_until(CALL_RETURN_ADDRESS)? {
+            return Err(call_exited(exit_code));
+        }
+        finish_call(self, frame, args)
+    }
+}
+
+#[cfg(feature = "trace")]
+impl<Inner: SupportMachine> TraceMachine<Inner> {
+    // See DefaultMachine::call.
+    pub fn call<T: ReturnValue>(&mut self, address: u64, args: &mut [CallArg]) -> Result<T, Error> {
+        let frame = setup_call(self, address, args)?;
+        if let Some(exit_code) = self.run_until(CALL_RETURN_ADDRESS)? {
+            return Err(call_exited(exit_code));
+        }
+        finish_call(self, frame, args)
+    }
+}
+
+// Finds the address of a symbol in the symbol table of an ELF program.
+// Stripped programs have no symbol table, in which case None is returned.
+#[cfg(feature = "elf")]
+pub fn find_symbol(program: &Bytes, name: &str) -> Result<Option<u64>, Error> {
+    let elf = goblin_v040::elf::Elf::parse(program)?;
+    Ok(elf
+        .syms
+        .iter()
+        .find(|sym| sym.st_value != 0 && elf.strtab.get_at(sym.st_name) == Some(name))
+        .map(|sym| sym.st_value))
+}
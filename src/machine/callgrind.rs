@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use super::{
+    super::{
+        instructions::{extract_opcode, insts, Instruction, Itype, Utype},
+        registers::RA,
+        Error,
+    },
+    cache::UNKNOWN_FUNCTION,
+    instrumented::InstrumentHandler,
+    symbols::Symbols,
+    SupportMachine,
+};
+
+#[derive(Default)]
+struct FunctionProfile {
+    // Self cycles by instruction address.
+    costs: BTreeMap<u64, u64>,
+    // (call site, callee) => (calls, callee entry, inclusive cycles).
+    calls: BTreeMap<(u64, usize), (u64, u64, u64)>,
+}
+
+struct Frame {
+    caller: usize,
+    site: u64,
+    callee: usize,
+    entry: u64,
+    start_cycles: u64,
+}
+
+enum Pending {
+    Call(u64, usize, u64),
+    Return,
+}
+
+// CallgrindProfiler records the cycles of each guest instruction and the
+// calls between guest functions, then writes them in the callgrind format
+// read by KCachegrind and callgrind_annotate:
+//
+// let profiler = CallgrindProfiler::new(Symbols::from_elf(&program)?);
+// let mut machine = InstrumentedMachine::new(DefaultMachineBuilder::new(core).build(), profiler);
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// let cycles = machine.cycles();
+// machine.handler.finish(cycles);
+// machine.handler.write(&mut File::create("callgrind.out")?)?;
+//
+// Functions come from Symbols, costs are positioned by instruction address
+// since source lines are not resolved. Calls are JAL/JALR linking to ra, and
+// returns are JALR to ra without link.
+pub struct CallgrindProfiler {
+    symbols: Symbols,
+    functions: BTreeMap<usize, FunctionProfile>,
+    stack: Vec<Frame>,
+    pending: Option<Pending>,
+    // Address, function and cycles before the last instruction, whose cost
+    // is known once the next one starts.
+    last: Option<(u64, usize, u64)>,
+}
+
+impl CallgrindProfiler {
+    pub fn new(symbols: Symbols) -> Self {
+        Self {
+            symbols,
+            functions: BTreeMap::new(),
+            stack: vec![],
+            pending: None,
+            last: None,
+        }
+    }
+
+    fn function(&self, pc: u64) -> usize {
+        self.symbols.index(pc).unwrap_or(self.symbols.len())
+    }
+
+    fn name(&self, function: usize) -> &str {
+        if function < self.symbols.len() {
+            self.symbols.name(function)
+        } else {
+            UNKNOWN_FUNCTION
+        }
+    }
+
+    fn retire(&mut self, cycles: u64) {
+        if let Some((pc, function, start)) = self.last.take() {
+            *self
+                .functions
+                .entry(function)
+                .or_default()
+                .costs
+                .entry(pc)
+                .or_default() += cycles.saturating_sub(start);
+        }
+    }
+
+    fn finish_call(&mut self, frame: Frame, cycles: u64) {
+        let call = self
+            .functions
+            .entry(frame.caller)
+            .or_default()
+            .calls
+            .entry((frame.site, frame.callee))
+            .or_insert((0, frame.entry, 0));
+        call.0 += 1;
+        call.2 += cycles.saturating_sub(frame.start_cycles);
+    }
+
+    // Charges the last instruction and the calls still running with the
+    // cycles of the machine once it stopped.
+    pub fn finish(&mut self, cycles: u64) {
+        self.retire(cycles);
+        self.pending = None;
+        while let Some(frame) = self.stack.pop() {
+            self.finish_call(frame, cycles);
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let total: u64 = self
+            .functions
+            .values()
+            .flat_map(|profile| profile.costs.values())
+            .sum();
+        writeln!(writer, "# callgrind format")?;
+        writeln!(writer, "version: 1")?;
+        writeln!(writer, "creator: ckb-vm")?;
+        writeln!(writer, "positions: instr")?;
+        writeln!(writer, "events: Cycles")?;
+        writeln!(writer, "summary: {}", total)?;
+        for (function, profile) in &self.functions {
+            writeln!(writer)?;
+            writeln!(writer, "fn={}", self.name(*function))?;
+            for (pc, cycles) in &profile.costs {
+                writeln!(writer, "0x{:x} {}", pc, cycles)?;
+            }
+            for ((site, callee), (calls, entry, cycles)) in &profile.calls {
+                writeln!(writer, "cfn={}", self.name(*callee))?;
+                writeln!(writer, "calls={} 0x{:x}", calls, entry)?;
+                writeln!(writer, "0x{:x} {}", site, cycles)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<M: SupportMachine> InstrumentHandler<M> for CallgrindProfiler {
+    fn instruction(&mut self, machine: &M, pc: u64, instruction: Instruction) {
+        let cycles = machine.cycles();
+        self.retire(cycles);
+        let function = self.function(pc);
+        match self.pending.take() {
+            Some(Pending::Call(site, caller, start_cycles)) => self.stack.push(Frame {
+                caller,
+                site,
+                callee: function,
+                entry: pc,
+                start_cycles,
+            }),
+            Some(Pending::Return) => {
+                if let Some(frame) = self.stack.pop() {
+                    self.finish_call(frame, cycles);
+                }
+            }
+            None => (),
+        }
+        let op = extract_opcode(instruction);
+        let link = match op {
+            insts::OP_JAL => Some(Utype(instruction).rd()),
+            insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+                let i = Itype(instruction);
+                if i.rd() == 0 && i.rs1() == RA {
+                    self.pending = Some(Pending::Return);
+                }
+                Some(i.rd())
+            }
+            insts::OP_FAR_JUMP_REL | insts::OP_FAR_JUMP_ABS => Some(RA),
+            _ => None,
+        };
+        if link == Some(RA) {
+            self.pending = Some(Pending::Call(pc, function, cycles));
+        }
+        self.last = Some((pc, function, cycles));
+    }
+}
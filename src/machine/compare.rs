@@ -0,0 +1,133 @@
+use super::{
+    super::{
+        instructions::Register,
+        memory::{hexdump, Memory},
+        registers::register_name,
+        Error, RISCV_PAGESIZE,
+    },
+    SupportMachine,
+};
+use std::cmp::min;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Pc(u64, u64),
+    Register(usize, u64, u64),
+    Cycles(u64, u64),
+    MemorySize(usize, usize),
+    // A differing page, with the address of the first differing byte and the
+    // content of both machines starting from that byte, up to 16 bytes.
+    Memory(u64, Vec<u8>, Vec<u8>),
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Pc(left, right) => write!(f, "pc: 0x{:x} != 0x{:x}", left, right),
+            Difference::Register(index, left, right) => write!(
+                f,
+                "{}: 0x{:x} != 0x{:x}",
+                register_name(*index).unwrap_or("?"),
+                left,
+                right
+            ),
+            Difference::Cycles(left, right) => write!(f, "cycles: {} != {}", left, right),
+            Difference::MemorySize(left, right) => {
+                write!(f, "memory size: {} != {}", left, right)
+            }
+            Difference::Memory(address, left, right) => write!(
+                f,
+                "memory at 0x{:x}:\n< {}> {}",
+                address,
+                hexdump(*address, left),
+                hexdump(*address, right).trim_end()
+            ),
+        }
+    }
+}
+
+// All differences found between two machines, an empty StateDiff means the
+// machines are in the same state.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDiff(pub Vec<Difference>);
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for difference in &self.0 {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+// Compares PC, registers, cycles and the whole memory of two machines. This
+// is useful to compare different backends(e.g. interpreter vs ASM), or
+// different versions running the same program. Memory is compared page by
+// page, each differing page is reported once.
+pub fn compare_machines<A: SupportMachine, B: SupportMachine>(
+    left: &mut A,
+    right: &mut B,
+) -> Result<StateDiff, Error> {
+    let mut differences = Vec::new();
+    if left.pc().to_u64() != right.pc().to_u64() {
+        differences.push(Difference::Pc(left.pc().to_u64(), right.pc().to_u64()));
+    }
+    for (index, (l, r)) in left
+        .registers()
+        .iter()
+        .zip(right.registers().iter())
+        .enumerate()
+    {
+        if l.to_u64() != r.to_u64() {
+            differences.push(Difference::Register(index, l.to_u64(), r.to_u64()));
+        }
+    }
+    if left.cycles() != right.cycles() {
+        differences.push(Difference::Cycles(left.cycles(), right.cycles()));
+    }
+    let left_size = left.memory().memory_size();
+    let right_size = right.memory().memory_size();
+    if left_size != right_size {
+        differences.push(Difference::MemorySize(left_size, right_size));
+    }
+    let size = min(left_size, right_size) as u64;
+    let mut address = 0;
+    while address < size {
+        let length = min(RISCV_PAGESIZE as u64, size - address);
+        let l = left.memory_mut().load_bytes(address, length)?;
+        let r = right.memory_mut().load_bytes(address, length)?;
+        if let Some(offset) = l.iter().zip(r.iter()).position(|(a, b)| a != b) {
+            let end = min(offset + 16, l.len());
+            differences.push(Difference::Memory(
+                address + offset as u64,
+                l[offset..end].to_vec(),
+                r[offset..end].to_vec(),
+            ));
+        }
+        address += length;
+    }
+    Ok(StateDiff(differences))
+}
+
+// Asserts that two machines are in the same state, a readable diff of the
+// registers, cycles and memory is printed otherwise.
+#[macro_export]
+macro_rules! assert_machines_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match $crate::machine::compare::compare_machines(&mut $left, &mut $right) {
+            Ok(diff) => {
+                if !diff.is_empty() {
+                    panic!("machines are not equal (left: <, right: >):\n{}", diff);
+                }
+            }
+            Err(e) => panic!("failed to compare machines: {}", e),
+        }
+    };
+}
@@ -0,0 +1,116 @@
+use super::{
+    super::{isa::Isa, memory::MemoryFill, metrics::MetricsSink, Error, RISCV_MAX_MEMORY},
+    DefaultMachineBuilder, InstructionCycleFunc, VERSION2,
+};
+
+// MachineConfig collects all parameters needed to build a machine. Compared
+// to positional constructor arguments, new options can be added here without
+// breaking downstream code, since every option comes with a default value.
+//
+// let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::from_config(&config)?;
+// let machine = DefaultMachineBuilder::new_with_config(config)?.build();
+pub struct MachineConfig {
+    pub isa: Isa,
+    pub version: u32,
+    pub max_cycles: u64,
+    pub memory_size: usize,
+    pub memory_fill: MemoryFill,
+    // Backs memory with huge pages when the host supports it, falling back to
+    // regular pages otherwise. Only flat memories support it.
+    pub huge_pages: bool,
+    pub instruction_cycle_func: Option<Box<InstructionCycleFunc>>,
+    pub metrics: Option<Box<dyn MetricsSink>>,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            isa: Isa::IMC,
+            version: VERSION2,
+            max_cycles: u64::MAX,
+            memory_size: RISCV_MAX_MEMORY,
+            memory_fill: MemoryFill::Zero,
+            huge_pages: false,
+            instruction_cycle_func: None,
+            metrics: None,
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn max_cycles(mut self, max_cycles: u64) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    pub fn memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    pub fn memory_fill(mut self, memory_fill: MemoryFill) -> Self {
+        self.memory_fill = memory_fill;
+        self
+    }
+
+    pub fn huge_pages(mut self, huge_pages: bool) -> Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    pub fn instruction_cycle_func(mut self, func: Box<InstructionCycleFunc>) -> Self {
+        self.instruction_cycle_func = Some(func);
+        self
+    }
+
+    pub fn metrics_sink(mut self, metrics: Box<dyn MetricsSink>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Validates the ISA against the version, core machines are created with
+    // its mask.
+    pub fn checked_isa(&self) -> Result<Isa, Error> {
+        Isa::new(self.isa.mask(), self.version)
+    }
+
+    #[deprecated(note = "ISA masks are 64 bits wide now, use checked_isa")]
+    #[allow(deprecated)]
+    pub fn legacy_isa(&self) -> Result<u8, Error> {
+        self.checked_isa()?.to_legacy()
+    }
+}
+
+// Core machines which can be created from a MachineConfig.
+pub trait FromConfig: Sized {
+    fn from_config(config: &MachineConfig) -> Result<Self, Error>;
+}
+
+impl<Inner: FromConfig> DefaultMachineBuilder<Inner> {
+    // Creates the core machine from `config`, and applies the remaining
+    // options to the builder.
+    pub fn new_with_config(config: MachineConfig) -> Result<Self, Error> {
+        let mut builder = Self::new(Inner::from_config(&config)?);
+        if let Some(func) = config.instruction_cycle_func {
+            builder = builder.instruction_cycle_func(func);
+        }
+        if let Some(metrics) = config.metrics {
+            builder = builder.metrics_sink(metrics);
+        }
+        Ok(builder)
+    }
+}
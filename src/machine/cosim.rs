@@ -0,0 +1,362 @@
+use super::{
+    super::{
+        decoder::{build_decoder, Decoder},
+        instructions::{extract_opcode, instruction_length, Instruction, Register},
+        memory::Memory,
+        registers::register_name,
+        Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER,
+    },
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use ckb_vm_definitions::instructions as insts;
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStderr, Command, Stdio};
+
+// An instruction retired by the reference model, together with the integer
+// registers it wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitRecord {
+    // Privilege level the instruction ran in, when reported.
+    pub privilege: Option<u8>,
+    pub pc: u64,
+    pub instruction: u32,
+    pub writes: Vec<(usize, u64)>,
+}
+
+// A simulator used as reference in lockstep mode, yielding one record per
+// retired instruction. None means the reference stopped.
+pub trait ReferenceModel {
+    fn next_commit(&mut self) -> Result<Option<CommitRecord>, Error>;
+}
+
+// Prerecorded commits, e.g. to replay a log captured earlier.
+impl ReferenceModel for VecDeque<CommitRecord> {
+    fn next_commit(&mut self) -> Result<Option<CommitRecord>, Error> {
+        Ok(self.pop_front())
+    }
+}
+
+// Parses a line printed by `spike -l --log-commits`:
+//
+// core   0: 3 0x0000000080000004 (0x00a00513) x10 0x000000000000000a
+//
+// Other lines, such as disassembly printed without --log-commits, yield None.
+// Writes to floating point registers, CSRs and memory are ignored.
+pub fn parse_spike_commit(line: &str) -> Option<CommitRecord> {
+    let rest = line.trim().strip_prefix("core")?;
+    let (_, rest) = rest.split_once(':')?;
+    let mut tokens = rest.split_whitespace().peekable();
+    let privilege = if tokens.peek()?.starts_with("0x") {
+        None
+    } else {
+        Some(tokens.next()?.parse().ok()?)
+    };
+    let pc = parse_hex(tokens.next()?)?;
+    let instruction = tokens
+        .next()?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+        .and_then(parse_hex)?;
+    let mut writes = Vec::new();
+    while let Some(token) = tokens.next() {
+        if token == "mem" {
+            // The address, optionally followed by the stored value.
+            tokens.next()?;
+            if tokens.peek().map_or(false, |t| t.starts_with("0x")) {
+                tokens.next();
+            }
+        } else if let Some(index) = token.strip_prefix('x') {
+            let index = index.parse().ok()?;
+            writes.push((index, parse_hex(tokens.next()?)?));
+        } else if token.starts_with('f') || token.starts_with('c') || token.starts_with('v') {
+            parse_hex(tokens.next()?)?;
+        } else {
+            return None;
+        }
+    }
+    Some(CommitRecord {
+        privilege,
+        pc,
+        instruction: instruction as u32,
+        writes,
+    })
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+// Reads commits from a Spike log. When `user_only` is set, instructions
+// running above user mode(e.g. the proxy kernel handling a syscall) are
+// skipped.
+pub struct SpikeCommitLog<B> {
+    reader: B,
+    user_only: bool,
+}
+
+impl<B: BufRead> SpikeCommitLog<B> {
+    pub fn new(reader: B, user_only: bool) -> Self {
+        Self { reader, user_only }
+    }
+}
+
+impl<B: BufRead> ReferenceModel for SpikeCommitLog<B> {
+    fn next_commit(&mut self) -> Result<Option<CommitRecord>, Error> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if let Some(record) = parse_spike_commit(&line) {
+                if !self.user_only || record.privilege.unwrap_or(0) == 0 {
+                    return Ok(Some(record));
+                }
+            }
+        }
+    }
+}
+
+// A Spike subprocess, whose commit log is read as it runs. The process is
+// killed when dropped.
+//
+// let mut command = Command::new("spike");
+// command.args(["--isa=rv64imac", "pk", "program"]);
+// let mut spike = Spike::spawn(command, true)?;
+// let divergence = run_lockstep(&mut machine, &mut spike, &LockstepOptions::default())?;
+pub struct Spike {
+    child: Child,
+    log: SpikeCommitLog<BufReader<ChildStderr>>,
+}
+
+impl Spike {
+    pub fn spawn(mut command: Command, user_only: bool) -> Result<Self, Error> {
+        let mut child = command
+            .arg("-l")
+            .arg("--log-commits")
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| Error::Unexpected(String::from("Spike stderr is unavailable")))?;
+        Ok(Self {
+            child,
+            log: SpikeCommitLog::new(BufReader::new(stderr), user_only),
+        })
+    }
+}
+
+impl ReferenceModel for Spike {
+    fn next_commit(&mut self) -> Result<Option<CommitRecord>, Error> {
+        self.log.next_commit()
+    }
+}
+
+impl Drop for Spike {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Another VM used as reference, e.g. to compare memory implementations or
+// VM versions on generated programs. The reference machine must have the
+// program loaded already.
+pub struct MachineReference<Inner> {
+    pub machine: DefaultMachine<Inner>,
+    decoder: Decoder,
+}
+
+impl<Inner: SupportMachine> MachineReference<Inner> {
+    pub fn new(mut machine: DefaultMachine<Inner>) -> Self {
+        let decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+        machine.set_running(true);
+        Self { machine, decoder }
+    }
+}
+
+impl<Inner: SupportMachine> ReferenceModel for MachineReference<Inner> {
+    fn next_commit(&mut self) -> Result<Option<CommitRecord>, Error> {
+        if !self.machine.running() {
+            return Ok(None);
+        }
+        let pc = self.machine.pc().to_u64();
+        let before: Vec<u64> = self
+            .machine
+            .registers()
+            .iter()
+            .map(|r| r.to_u64())
+            .collect();
+        let instruction = self.machine.step_instruction(&mut self.decoder)?;
+        let writes = (1..RISCV_GENERAL_REGISTER_NUMBER)
+            .map(|i| (i, self.machine.registers()[i].to_u64()))
+            .filter(|(i, value)| before[*i] != *value)
+            .collect();
+        Ok(Some(CommitRecord {
+            privilege: None,
+            pc,
+            instruction: raw_instruction(&mut self.machine, pc, instruction)?,
+            writes,
+        }))
+    }
+}
+
+// Loads the raw encoding of a decoded instruction from memory.
+pub(crate) fn raw_instruction<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    pc: u64,
+    instruction: Instruction,
+) -> Result<u32, Error> {
+    let length = instruction_length(instruction) as u64;
+    let bytes = machine.memory_mut().load_bytes(pc, length)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0u32, |acc, byte| (acc << 8) | u32::from(*byte)))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LockstepOptions {
+    // Added to PCs of this VM before comparing, for references loading
+    // programs at a different base, e.g. Spike's default of 0x80000000.
+    pub pc_offset: u64,
+    // Stops after this many instructions, 0 means no limit.
+    pub max_steps: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    Pc {
+        expected: u64,
+        actual: u64,
+    },
+    Instruction {
+        expected: u32,
+        actual: u32,
+    },
+    // A register written by either side, `expected` is None if the reference
+    // did not write it.
+    Register {
+        index: usize,
+        expected: Option<u64>,
+        actual: u64,
+    },
+    ReferenceStopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // Index of the diverging instruction, starting from 0.
+    pub step: u64,
+    // PC of the diverging instruction in this VM.
+    pub pc: u64,
+    pub kind: DivergenceKind,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {} at 0x{:x}: ", self.step, self.pc)?;
+        match &self.kind {
+            DivergenceKind::Pc { expected, actual } => {
+                write!(f, "pc 0x{:x} != 0x{:x}", actual, expected)
+            }
+            DivergenceKind::Instruction { expected, actual } => {
+                write!(f, "instruction 0x{:x} != 0x{:x}", actual, expected)
+            }
+            DivergenceKind::Register {
+                index,
+                expected,
+                actual,
+            } => {
+                let name = register_name(*index).unwrap_or("?");
+                match expected {
+                    Some(expected) => write!(f, "{} 0x{:x} != 0x{:x}", name, actual, expected),
+                    None => write!(f, "{} written with 0x{:x} by vm only", name, actual),
+                }
+            }
+            DivergenceKind::ReferenceStopped => write!(f, "reference stopped"),
+        }
+    }
+}
+
+// Runs a loaded program one instruction at a time alongside `reference`,
+// comparing PC, the raw instruction and written registers after each one.
+// Returns the first divergence, or None if the program exits(or max_steps
+// is reached) without diverging.
+//
+// Macro-op fusion is disabled so each step retires exactly one RISC-V
+// instruction. Reference commits before the entry point of the program, such
+// as a boot ROM, are skipped. Registers changed by syscalls are not compared,
+// since syscalls are implemented differently by each environment.
+pub fn run_lockstep<Inner: SupportMachine, M: ReferenceModel>(
+    machine: &mut DefaultMachine<Inner>,
+    reference: &mut M,
+    options: &LockstepOptions,
+) -> Result<Option<Divergence>, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    let entry = machine.pc().to_u64().wrapping_add(options.pc_offset);
+    let mut record = loop {
+        match reference.next_commit()? {
+            Some(record) if record.pc == entry => break Some(record),
+            Some(_) => continue,
+            None => break None,
+        }
+    };
+    let mut step = 0;
+    machine.set_running(true);
+    while machine.running() && (options.max_steps == 0 || step < options.max_steps) {
+        let pc = machine.pc().to_u64();
+        let divergence = |kind| Some(Divergence { step, pc, kind });
+        let expected = match record.take() {
+            Some(record) => record,
+            None => match reference.next_commit()? {
+                Some(record) => record,
+                None => return Ok(divergence(DivergenceKind::ReferenceStopped)),
+            },
+        };
+        let actual_pc = pc.wrapping_add(options.pc_offset);
+        if expected.pc != actual_pc {
+            return Ok(divergence(DivergenceKind::Pc {
+                expected: expected.pc,
+                actual: actual_pc,
+            }));
+        }
+        let before: Vec<u64> = machine.registers().iter().map(|r| r.to_u64()).collect();
+        let instruction = machine.step_instruction(&mut decoder)?;
+        let raw = raw_instruction(machine, pc, instruction)?;
+        if expected.instruction != raw {
+            return Ok(divergence(DivergenceKind::Instruction {
+                expected: expected.instruction,
+                actual: raw,
+            }));
+        }
+        if extract_opcode(instruction) != insts::OP_ECALL {
+            for (index, before) in before.iter().enumerate().skip(1) {
+                let actual = machine.registers()[index].to_u64();
+                let written = expected
+                    .writes
+                    .iter()
+                    .rev()
+                    .find(|(i, _)| *i == index)
+                    .map(|(_, value)| *value);
+                let diverged = match written {
+                    Some(value) => value != actual,
+                    None => *before != actual,
+                };
+                if diverged {
+                    return Ok(divergence(DivergenceKind::Register {
+                        index,
+                        expected: written,
+                        actual,
+                    }));
+                }
+            }
+        }
+        step += 1;
+    }
+    Ok(None)
+}
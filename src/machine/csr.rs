@@ -0,0 +1,65 @@
+use super::SupportMachine;
+use crate::instructions::Register;
+
+// Unprivileged counters, read only. The high halves only exist on RV32.
+pub const CSR_CYCLE: u16 = 0xc00;
+pub const CSR_TIME: u16 = 0xc01;
+pub const CSR_INSTRET: u16 = 0xc02;
+pub const CSR_CYCLEH: u16 = 0xc80;
+pub const CSR_TIMEH: u16 = 0xc81;
+pub const CSR_INSTRETH: u16 = 0xc82;
+
+// Counters of the machine running a CSR instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counters {
+    // Cycles including the CSR instruction.
+    pub cycles: u64,
+    // Instructions retired before the CSR instruction.
+    pub instret: u64,
+}
+
+// CsrProvider handles the Zicsr instructions, enabled by ISA_ZICSR, on CSRs
+// outside of the privileged, floating point and vector ones. Like
+// CsrFile, both methods return None for CSRs which do not exist, and
+// writes also fail on read only CSRs. The instruction then raises an
+// illegal instruction exception.
+//
+// Without a provider, DefaultMachine uses CounterCsrs. Providers adding
+// their own CSRs can delegate to it for the counters:
+//
+// impl<Mac: SupportMachine> CsrProvider<Mac> for HartCsrs {
+//     fn read_csr(&mut self, machine: &mut Mac, counters: Counters, csr: u16) -> Option<u64> {
+//         match csr {
+//             CSR_HART_STATE => Some(self.state),
+//             _ => CounterCsrs.read_csr(machine, counters, csr),
+//         }
+//     }
+//     ...
+// }
+pub trait CsrProvider<Mac: SupportMachine>: Send + Sync {
+    fn read_csr(&mut self, machine: &mut Mac, counters: Counters, csr: u16) -> Option<u64>;
+    fn write_csr(&mut self, machine: &mut Mac, csr: u16, value: u64) -> Option<()>;
+}
+
+// Provides cycle, time and instret, along with their high halves on RV32.
+// CKB VM has no clock, since runs must be reproducible, so time counts
+// cycles as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CounterCsrs;
+
+impl<Mac: SupportMachine> CsrProvider<Mac> for CounterCsrs {
+    fn read_csr(&mut self, _machine: &mut Mac, counters: Counters, csr: u16) -> Option<u64> {
+        let rv32 = Mac::REG::BITS == 32;
+        match csr {
+            CSR_CYCLE | CSR_TIME => Some(counters.cycles),
+            CSR_INSTRET => Some(counters.instret),
+            CSR_CYCLEH | CSR_TIMEH if rv32 => Some(counters.cycles >> 32),
+            CSR_INSTRETH if rv32 => Some(counters.instret >> 32),
+            _ => None,
+        }
+    }
+
+    fn write_csr(&mut self, _machine: &mut Mac, _csr: u16, _value: u64) -> Option<()> {
+        None
+    }
+}
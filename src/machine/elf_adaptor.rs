@@ -2,11 +2,293 @@
 // same internal structure.
 use crate::memory::{FLAG_EXECUTABLE, FLAG_FREEZED};
 use crate::Error;
+use bytes::Bytes;
 
 // Even for different versions of goblin, their values must be consistent.
-pub use goblin_v023::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+pub use goblin_v023::elf::header::ET_DYN;
+pub use goblin_v023::elf::program_header::{PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD};
 pub use goblin_v023::elf::section_header::SHF_EXECINSTR;
 
+// Bit of e_flags in the ELF header set when the program contains RVC
+// instructions.
+pub const EF_RISCV_RVC: u32 = 0x0001;
+
+// Returns whether the ELF header of `program` declares RVC instructions,
+// programs whose header can't be read are assumed to contain them.
+pub fn uses_compressed_instructions(program: &[u8]) -> bool {
+    // Offset of e_flags, depending on the ELF class
+    let offset = match program.get(4) {
+        Some(1) => 0x24,
+        Some(2) => 0x30,
+        _ => return true,
+    };
+    match program.get(offset..offset + 4) {
+        Some(flags) => {
+            u32::from_le_bytes([flags[0], flags[1], flags[2], flags[3]]) & EF_RISCV_RVC != 0
+        }
+        None => true,
+    }
+}
+
+// Returns (address, size) of the executable PT_LOAD segments of `program`.
+pub fn executable_segments(program: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+    segments(program, PF_X)
+}
+
+// Returns (address, size) of all PT_LOAD segments of `program`.
+pub fn load_segments(program: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+    segments(program, 0)
+}
+
+// PT_LOAD segments having all of `flags`.
+fn segments(program: &[u8], flags: u32) -> Result<Vec<(u64, u64)>, Error> {
+    Ok(load_program_headers(program, flags)?
+        .iter()
+        .map(|header| (header.p_vaddr, header.p_memsz))
+        .collect())
+}
+
+// Returns the PT_LOAD program headers of `program` having all of `flags`.
+pub fn load_program_headers(program: &[u8], flags: u32) -> Result<Vec<ProgramHeader>, Error> {
+    Ok(program_headers(program)?
+        .into_iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_flags & flags == flags)
+        .collect())
+}
+
+fn program_headers(program: &[u8]) -> Result<Vec<ProgramHeader>, Error> {
+    use goblin_v040::container::Ctx;
+    use goblin_v040::elf::{program_header::ProgramHeader as GoblinProgramHeader, Header};
+    use scroll::Pread;
+    let header = program.pread::<Header>(0)?;
+    let container = header.container().map_err(|_e| Error::ElfBits)?;
+    let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
+    let ctx = Ctx::new(container, endianness);
+    let program_headers = GoblinProgramHeader::parse(
+        program,
+        header.e_phoff as usize,
+        header.e_phnum as usize,
+        ctx,
+    )?;
+    Ok(program_headers.iter().map(ProgramHeader::from_v1).collect())
+}
+
+// Returns whether `program` is a position independent executable, which
+// toolchains build by default. Programs whose header can't be read aren't.
+pub fn is_position_independent(program: &[u8]) -> bool {
+    match program.get(16..18) {
+        Some(e_type) => u16::from_le_bytes([e_type[0], e_type[1]]) == ET_DYN,
+        None => false,
+    }
+}
+
+// Tags of the dynamic section entries locating the relocations.
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+
+pub const R_RISCV_NONE: u64 = 0;
+pub const R_RISCV_RELATIVE: u64 = 3;
+
+// Returns (address, addend) of the R_RISCV_RELATIVE relocations of
+// `program`, found through its PT_DYNAMIC segment. Each one stores the load
+// bias plus the addend in the word at the address. Static position
+// independent executables only need these, programs with relocations
+// against symbols are rejected since there is no dynamic linker.
+pub fn relative_relocations(program: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+    let word = word_size(program)?;
+    let headers = program_headers(program)?;
+    let dynamic = match headers.iter().find(|header| header.p_type == PT_DYNAMIC) {
+        Some(dynamic) => dynamic,
+        None => return Ok(vec![]),
+    };
+    let (mut rela, mut rela_size, mut rela_entry) = (None, 0, 3 * word);
+    let end = dynamic.p_offset.saturating_add(dynamic.p_filesz);
+    let mut offset = dynamic.p_offset;
+    while offset.saturating_add(2 * word) <= end {
+        let value = read_word(program, offset + word, word)?;
+        match read_word(program, offset, word)? {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry = value,
+            _ => (),
+        }
+        offset += 2 * word;
+    }
+    let rela = match rela {
+        Some(rela) => rela,
+        None => return Ok(vec![]),
+    };
+    if rela_entry < 3 * word {
+        return Err(Error::ElfParseError(format!(
+            "invalid relocation entry size {}",
+            rela_entry
+        )));
+    }
+    let start = file_offset(&headers, rela, rela_size)?;
+    let mut relocations = vec![];
+    for index in 0..rela_size / rela_entry {
+        let entry = start + index * rela_entry;
+        let info = read_word(program, entry + word, word)?;
+        let kind = if word == 8 {
+            info & 0xffff_ffff
+        } else {
+            info & 0xff
+        };
+        match kind {
+            R_RISCV_NONE => (),
+            R_RISCV_RELATIVE => relocations.push((
+                read_word(program, entry, word)?,
+                read_word(program, entry + 2 * word, word)?,
+            )),
+            _ => {
+                return Err(Error::ElfParseError(format!(
+                    "unsupported relocation type {}",
+                    kind
+                )))
+            }
+        }
+    }
+    Ok(relocations)
+}
+
+// Returns a copy of `program` with the relative relocations applied for a
+// load bias of `bias`, or `program` itself when it has none. Relocating
+// the file rather than the memory keeps the read only segments frozen.
+pub fn relocate(program: &Bytes, bias: u64) -> Result<Bytes, Error> {
+    let relocations = relative_relocations(program)?;
+    if relocations.is_empty() {
+        return Ok(program.clone());
+    }
+    let word = word_size(program)?;
+    let headers = program_headers(program)?;
+    let mut relocated = program.to_vec();
+    for (address, addend) in relocations {
+        let offset = file_offset(&headers, address, word)? as usize;
+        let value = addend.wrapping_add(bias).to_le_bytes();
+        relocated
+            .get_mut(offset..offset + word as usize)
+            .ok_or(Error::ElfSegmentAddrOrSizeError)?
+            .copy_from_slice(&value[..word as usize]);
+    }
+    Ok(Bytes::from(relocated))
+}
+
+// Size of the words of `program`, depending on the ELF class.
+fn word_size(program: &[u8]) -> Result<u64, Error> {
+    match program.get(4) {
+        Some(1) => Ok(4),
+        Some(2) => Ok(8),
+        _ => Err(Error::ElfBits),
+    }
+}
+
+fn read_word(program: &[u8], offset: u64, word: u64) -> Result<u64, Error> {
+    let bytes = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| program.get(offset..offset.checked_add(word as usize)?))
+        .ok_or(Error::ElfSegmentAddrOrSizeError)?;
+    let mut value = [0u8; 8];
+    value[..bytes.len()].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(value))
+}
+
+// Offset in the file of the `size` bytes at `address`, which must be
+// loaded from the file by a PT_LOAD segment.
+fn file_offset(headers: &[ProgramHeader], address: u64, size: u64) -> Result<u64, Error> {
+    headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .find(|header| {
+            address >= header.p_vaddr
+                && address.checked_add(size).map_or(false, |end| {
+                    end <= header.p_vaddr.saturating_add(header.p_filesz)
+                })
+        })
+        .map(|header| header.p_offset + (address - header.p_vaddr))
+        .ok_or(Error::ElfSegmentAddrOrSizeError)
+}
+
+// Type of the GNU note holding the build ID.
+pub const NT_GNU_BUILD_ID: u32 = 3;
+
+// Returns the build ID identifying `program`, which debuggers use to find
+// its debug symbols. It is read from the .note.gnu.build-id section, or from
+// the PT_NOTE segments of programs whose section headers were stripped.
+// None is returned for programs built without one.
+pub fn build_id(program: &[u8]) -> Option<Vec<u8>> {
+    use goblin_v040::container::Ctx;
+    use goblin_v040::elf::program_header::{ProgramHeader as GoblinProgramHeader, PT_NOTE};
+    use goblin_v040::elf::section_header::{SectionHeader as GoblinSectionHeader, SHT_NOTE};
+    use goblin_v040::elf::Header;
+    use scroll::Pread;
+    let header = program.pread::<Header>(0).ok()?;
+    let container = header.container().ok()?;
+    let endianness = header.endianness().ok()?;
+    let ctx = Ctx::new(container, endianness);
+    let sections = GoblinSectionHeader::parse(
+        program,
+        header.e_shoff as usize,
+        header.e_shnum as usize,
+        ctx,
+    )
+    .unwrap_or_default();
+    let segments = GoblinProgramHeader::parse(
+        program,
+        header.e_phoff as usize,
+        header.e_phnum as usize,
+        ctx,
+    )
+    .unwrap_or_default();
+    sections
+        .iter()
+        .filter(|section| section.sh_type == SHT_NOTE)
+        .map(|section| (section.sh_offset, section.sh_size))
+        .chain(
+            segments
+                .iter()
+                .filter(|segment| segment.p_type == PT_NOTE)
+                .map(|segment| (segment.p_offset, segment.p_filesz)),
+        )
+        .filter_map(|(offset, size)| {
+            let end = usize::try_from(offset.checked_add(size)?).ok()?;
+            program.get(offset as usize..end)
+        })
+        .find_map(gnu_build_id)
+}
+
+// Finds the GNU build ID note among `notes`. Each note is its name size,
+// descriptor size and type as u32, followed by its name and descriptor,
+// both padded to 4 bytes.
+fn gnu_build_id(mut notes: &[u8]) -> Option<Vec<u8>> {
+    let u32_at = |data: &[u8], offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    while notes.len() >= 12 {
+        let name_size = u32_at(notes, 0) as usize;
+        let descriptor_size = u32_at(notes, 4) as usize;
+        let note_type = u32_at(notes, 8);
+        let name_end = 12usize.checked_add(name_size)?;
+        let descriptor_start = name_end.checked_add(3)? & !3;
+        let descriptor_end = descriptor_start.checked_add(descriptor_size)?;
+        let name = notes.get(12..name_end)?;
+        let descriptor = notes.get(descriptor_start..descriptor_end)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(descriptor.to_vec());
+        }
+        let next = descriptor_end.checked_add(3)? & !3;
+        notes = notes.get(next.min(notes.len())..)?;
+    }
+    None
+}
+
 /// Converts goblin's ELF flags into RISC-V flags
 pub fn convert_flags(p_flags: u32, allow_freeze_writable: bool) -> Result<u8, Error> {
     let readable = p_flags & PF_R != 0;
@@ -0,0 +1,106 @@
+use super::privileged::CsrFile;
+use crate::float::{Precision, RoundingMode};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Floating point CSRs, accessible without ISA_PRIV.
+pub const CSR_FFLAGS: u16 = 0x001;
+pub const CSR_FRM: u16 = 0x002;
+pub const CSR_FCSR: u16 = 0x003;
+
+const FFLAGS_MASK: u8 = 0b1_1111;
+const FRM_MASK: u8 = 0b111;
+// Value of the rm field selecting the rounding mode in frm.
+pub const RM_DYNAMIC: u8 = 0b111;
+
+const NAN_BOX: u64 = 0xffff_ffff_0000_0000;
+
+// Floating point register file and fcsr of the F and D extensions, held by
+// DefaultCoreMachine when ISA_F is enabled. Registers are 64 bits wide,
+// single precision values are NaN-boxed: stored with all upper 32 bits set,
+// and read as the canonical NaN otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FloatRegisters {
+    registers: [u64; 32],
+    fflags: u8,
+    frm: u8,
+}
+
+impl FloatRegisters {
+    // Raw bits of register `index`.
+    pub fn register(&self, index: usize) -> u64 {
+        self.registers[index % 32]
+    }
+
+    pub fn set_register(&mut self, index: usize, value: u64) {
+        self.registers[index % 32] = value;
+    }
+
+    // Value of register `index` in precision `p`, singles are unboxed.
+    pub fn value(&self, p: Precision, index: usize) -> u64 {
+        let bits = self.register(index);
+        match p {
+            Precision::Single if bits & NAN_BOX != NAN_BOX => p.canonical_nan(),
+            Precision::Single => bits & p.mask(),
+            Precision::Double => bits,
+        }
+    }
+
+    // Sets register `index` to a value in precision `p`, singles are boxed.
+    pub fn set_value(&mut self, p: Precision, index: usize, value: u64) {
+        let bits = match p {
+            Precision::Single => NAN_BOX | (value & p.mask()),
+            Precision::Double => value,
+        };
+        self.set_register(index, bits);
+    }
+
+    pub fn fflags(&self) -> u8 {
+        self.fflags
+    }
+
+    // Accrues exception flags raised by an instruction.
+    pub fn raise(&mut self, flags: u8) {
+        self.fflags |= flags & FFLAGS_MASK;
+    }
+
+    pub fn frm(&self) -> u8 {
+        self.frm
+    }
+
+    // Resolves the rm field of an instruction, None for reserved modes,
+    // including a reserved frm selected by DYN.
+    pub fn rounding_mode(&self, rm: u8) -> Option<RoundingMode> {
+        if rm == RM_DYNAMIC {
+            RoundingMode::from_u8(self.frm)
+        } else {
+            RoundingMode::from_u8(rm)
+        }
+    }
+}
+
+// Returns None for CSRs other than fflags, frm and fcsr.
+impl CsrFile for FloatRegisters {
+    fn read_csr(&self, csr: u16) -> Option<u64> {
+        match csr {
+            CSR_FFLAGS => Some(u64::from(self.fflags)),
+            CSR_FRM => Some(u64::from(self.frm)),
+            CSR_FCSR => Some(u64::from(self.frm << 5 | self.fflags)),
+            _ => None,
+        }
+    }
+
+    fn write_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        match csr {
+            CSR_FFLAGS => self.fflags = value as u8 & FFLAGS_MASK,
+            CSR_FRM => self.frm = value as u8 & FRM_MASK,
+            CSR_FCSR => {
+                self.fflags = value as u8 & FFLAGS_MASK;
+                self.frm = (value >> 5) as u8 & FRM_MASK;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,379 @@
+use super::{
+    super::{
+        decoder::Decoder,
+        instructions::{extract_opcode, insts, Register},
+        memory::Memory,
+        Error,
+    },
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Signals reported in stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// GDB numbers x0-x31 as registers 0-31, followed by pc.
+const PC_REGISTER: usize = 32;
+
+// Instructions executed between two checks for an interrupt from GDB.
+const INTERRUPT_CHECK_INTERVAL: u64 = 4096;
+
+// Largest packet accepted, advertised in qSupported.
+const PACKET_SIZE: usize = 0x4000;
+
+// A stub of the GDB remote serial protocol serving a DefaultMachine, so
+// programs can be debugged with riscv64-unknown-elf-gdb:
+//
+// let listener = TcpListener::bind("127.0.0.1:9999")?;
+// let exit_code = GdbStub::new(&mut machine).serve(&listener)?;
+//
+// then `target remote :9999` in GDB. Registers and memory can be read and
+// written, and the program can be stepped, continued and interrupted.
+// Breakpoints are kept by the stub instead of being written to memory, so
+// they also work on pages which are not writable. EBREAK instructions of the
+// program stop it with SIGTRAP, and errors with SIGILL or SIGSEGV, leaving
+// the machine at the failing instruction.
+pub struct GdbStub<'a, Inner> {
+    machine: &'a mut DefaultMachine<Inner>,
+    decoder: Decoder,
+    breakpoints: HashSet<u64>,
+    exit_code: Option<i8>,
+}
+
+impl<'a, Inner: SupportMachine> GdbStub<'a, Inner> {
+    // The machine should already have its program loaded.
+    pub fn new(machine: &'a mut DefaultMachine<Inner>) -> Self {
+        let decoder = machine.build_decoder();
+        Self {
+            machine,
+            decoder,
+            breakpoints: HashSet::new(),
+            exit_code: None,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u64> {
+        &self.breakpoints
+    }
+
+    // Accepts a single connection from `listener` and serves it, see
+    // serve_connection.
+    pub fn serve(&mut self, listener: &TcpListener) -> Result<Option<i8>, Error> {
+        let (stream, _) = listener.accept()?;
+        self.serve_connection(stream)
+    }
+
+    // Serves GDB until it detaches, kills the program or closes the
+    // connection. Returns the exit code if the program exited.
+    pub fn serve_connection(&mut self, stream: TcpStream) -> Result<Option<i8>, Error> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream,
+            last_reply: Vec::new(),
+        };
+        while let Some(packet) = connection.read_packet()? {
+            let reply = match packet.first() {
+                Some(b'D') => {
+                    connection.send(b"OK")?;
+                    break;
+                }
+                Some(b'k') => break,
+                Some(b'c') | Some(b's') => {
+                    if let Some(address) = parse_hex(&packet[1..]) {
+                        self.machine.update_pc(Inner::REG::from_u64(address));
+                        self.machine.commit_pc();
+                    }
+                    self.resume(&mut connection, packet[0] == b's')?
+                }
+                _ => self.handle(&packet),
+            };
+            connection.send(reply.as_bytes())?;
+        }
+        Ok(self.exit_code)
+    }
+
+    // Handles a packet which does not resume the program.
+    fn handle(&mut self, packet: &[u8]) -> String {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return String::new(),
+        };
+        let reply = match command {
+            b'?' => Some(self.stop_reply(SIGTRAP)),
+            b'g' => Some(
+                (0..=PC_REGISTER)
+                    .map(|index| self.read_register(index).unwrap_or_default())
+                    .collect(),
+            ),
+            b'G' => self.write_registers(args),
+            b'p' => parse_hex(args).and_then(|index| self.read_register(index as usize)),
+            b'P' => {
+                let (index, value) = split(args, b'=');
+                parse_hex(index).and_then(|index| self.write_register(index as usize, value))
+            }
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'Z' | b'z' => {
+                let mut fields = args.split(|b| *b == b',');
+                let kind = fields.next();
+                let address = fields.next().and_then(parse_hex);
+                match (kind, address) {
+                    // Software and hardware breakpoints are handled alike.
+                    (Some(b"0"), Some(address)) | (Some(b"1"), Some(address)) => {
+                        if command == b'Z' {
+                            self.breakpoints.insert(address);
+                        } else {
+                            self.breakpoints.remove(&address);
+                        }
+                        Some("OK".to_string())
+                    }
+                    // Watchpoints are not supported.
+                    _ => return String::new(),
+                }
+            }
+            b'H' | b'T' => Some("OK".to_string()),
+            b'q' => return query(args),
+            _ => return String::new(),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    // Runs the program until it stops, and returns the stop reply.
+    fn resume(&mut self, connection: &mut Connection, single_step: bool) -> Result<String, Error> {
+        if self.exit_code.is_some() {
+            return Ok(self.stop_reply(SIGTRAP));
+        }
+        self.machine.set_running(true);
+        let mut executed = 0u64;
+        loop {
+            if self.machine.reset_signal() {
+                self.decoder.reset_instructions_cache();
+            }
+            let opcode = match self.machine.step_instruction(&mut self.decoder) {
+                Ok(instruction) => extract_opcode(instruction),
+                Err(e) => return Ok(format!("S{:02x}", signal(&e))),
+            };
+            if !self.machine.running() {
+                self.exit_code = Some(self.machine.exit_code());
+                return Ok(self.stop_reply(SIGTRAP));
+            }
+            let pc = self.machine.pc().to_u64();
+            if single_step || opcode == insts::OP_EBREAK || self.breakpoints.contains(&pc) {
+                return Ok(self.stop_reply(SIGTRAP));
+            }
+            executed += 1;
+            if executed % INTERRUPT_CHECK_INTERVAL == 0 && connection.interrupted()? {
+                return Ok(self.stop_reply(SIGINT));
+            }
+        }
+    }
+
+    fn stop_reply(&self, signal: u8) -> String {
+        match self.exit_code {
+            Some(code) => format!("W{:02x}", code as u8),
+            None => format!("S{:02x}", signal),
+        }
+    }
+
+    // Registers are sent as little endian hex strings of XLEN bits.
+    fn read_register(&self, index: usize) -> Option<String> {
+        let value = match index {
+            0..=31 => self.machine.registers()[index].to_u64(),
+            PC_REGISTER => self.machine.pc().to_u64(),
+            _ => return None,
+        };
+        let bytes = value.to_le_bytes();
+        Some(to_hex(&bytes[..Inner::REG::BITS as usize / 8]))
+    }
+
+    fn write_register(&mut self, index: usize, hex: &[u8]) -> Option<String> {
+        let mut bytes = [0u8; 8];
+        let value = from_hex(hex)?;
+        if value.len() != Inner::REG::BITS as usize / 8 {
+            return None;
+        }
+        bytes[..value.len()].copy_from_slice(&value);
+        let value = Inner::REG::from_u64(u64::from_le_bytes(bytes));
+        match index {
+            // Writes to x0 are ignored.
+            0 => (),
+            1..=31 => self.machine.set_register(index, value),
+            PC_REGISTER => {
+                self.machine.update_pc(value);
+                self.machine.commit_pc();
+            }
+            _ => return None,
+        }
+        Some("OK".to_string())
+    }
+
+    fn write_registers(&mut self, hex: &[u8]) -> Option<String> {
+        let width = Inner::REG::BITS as usize / 4;
+        for (index, value) in hex.chunks(width).enumerate().take(PC_REGISTER + 1) {
+            self.write_register(index, value)?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&mut self, args: &[u8]) -> Option<String> {
+        let (address, length) = split(args, b',');
+        let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+        let length = length.min(PACKET_SIZE as u64 / 2);
+        let bytes = self.machine.memory_mut().load_bytes(address, length).ok()?;
+        Some(to_hex(&bytes))
+    }
+
+    fn write_memory(&mut self, args: &[u8]) -> Option<String> {
+        let (range, data) = split(args, b':');
+        let (address, length) = split(range, b',');
+        let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+        let data = from_hex(data)?;
+        if data.len() as u64 != length {
+            return None;
+        }
+        self.machine.memory_mut().store_bytes(address, &data).ok()?;
+        // Cached instructions might have been overwritten.
+        self.machine.invalidate_code();
+        Some("OK".to_string())
+    }
+}
+
+// Replies to general queries, empty for unsupported ones.
+fn query(args: &[u8]) -> String {
+    let name = split(args, b':').0;
+    match name {
+        b"Supported" => format!("PacketSize={:x}", PACKET_SIZE),
+        // There is a single thread.
+        b"C" => "QC1".to_string(),
+        b"fThreadInfo" => "m1".to_string(),
+        b"sThreadInfo" => "l".to_string(),
+        b"Attached" => "1".to_string(),
+        b"Symbol" => "OK".to_string(),
+        _ => String::new(),
+    }
+}
+
+fn signal(error: &Error) -> u8 {
+    match error.root() {
+        Error::InvalidInstruction { .. }
+        | Error::InvalidOp(_)
+        | Error::DeniedInstruction { .. } => SIGILL,
+        Error::MemOutOfBound
+        | Error::MemOutOfStack
+        | Error::MemPageUnalignedAccess
+        | Error::MemUnalignedAccess
+        | Error::MemWriteOnExecutablePage
+        | Error::MemWriteOnFreezedPage => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+// Splits `data` at the first `separator`, the second half is empty if there
+// is none.
+fn split(data: &[u8], separator: u8) -> (&[u8], &[u8]) {
+    match data.iter().position(|b| *b == separator) {
+        Some(index) => (&data[..index], &data[index + 1..]),
+        None => (data, &[]),
+    }
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(data).ok()?;
+    u64::from_str_radix(text, 16).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+// Framing of the remote serial protocol: packets are sent as
+// `$data#checksum`, and acknowledged with `+`, or `-` to ask for a resend.
+struct Connection {
+    stream: TcpStream,
+    last_reply: Vec<u8>,
+}
+
+impl Connection {
+    // Returns None when the connection is closed.
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let mut byte = [0u8];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(b'-') => {
+                    let reply = self.last_reply.clone();
+                    self.stream.write_all(&reply)?;
+                    continue;
+                }
+                // Acks, and interrupts received while the program is stopped.
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut sum = [0u8; 2];
+            self.stream.read_exact(&mut sum)?;
+            if parse_hex(&sum) == Some(u64::from(checksum(&data))) && data.len() <= PACKET_SIZE {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(data));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.last_reply = packet;
+        Ok(())
+    }
+
+    // Checks without blocking whether GDB sent an interrupt, i.e. Ctrl-C. A
+    // closed connection also stops the program.
+    fn interrupted(&mut self) -> Result<bool, Error> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use super::{
+    super::{
+        decoder::Decoder,
+        instructions::{execute, extract_opcode, Instruction, Itype, Register, Rtype, Stype},
+        Error, ISA_MOP,
+    },
+    float::FloatRegisters,
+    privileged::Privileged,
+    CoreMachine, DefaultMachine, Machine, SupportMachine, VERSION0,
+};
+#[cfg(any(feature = "elf", feature = "pprof"))]
+use bytes::Bytes;
+use ckb_vm_definitions::{instructions as insts, registers::A7};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccessKind {
+    Load,
+    Store,
+    // LR/SC and AMO instructions, which might both read and write memory.
+    Atomic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u64,
+    pub size: u8,
+    pub kind: MemoryAccessKind,
+}
+
+// Returns the memory access performed by `instruction` given the current
+// register values, or None if the instruction does not access memory.
+pub fn memory_access<R: Register>(
+    instruction: Instruction,
+    registers: &[R],
+) -> Option<MemoryAccess> {
+    let (kind, size) = match extract_opcode(instruction) {
+        insts::OP_LB_VERSION0
+        | insts::OP_LB_VERSION1
+        | insts::OP_LBU_VERSION0
+        | insts::OP_LBU_VERSION1 => (MemoryAccessKind::Load, 1),
+        insts::OP_LH_VERSION0
+        | insts::OP_LH_VERSION1
+        | insts::OP_LHU_VERSION0
+        | insts::OP_LHU_VERSION1 => (MemoryAccessKind::Load, 2),
+        insts::OP_LW_VERSION0
+        | insts::OP_LW_VERSION1
+        | insts::OP_LWU_VERSION0
+        | insts::OP_LWU_VERSION1 => (MemoryAccessKind::Load, 4),
+        insts::OP_LD_VERSION0 | insts::OP_LD_VERSION1 => (MemoryAccessKind::Load, 8),
+        insts::OP_SB => (MemoryAccessKind::Store, 1),
+        insts::OP_SH => (MemoryAccessKind::Store, 2),
+        insts::OP_SW => (MemoryAccessKind::Store, 4),
+        insts::OP_SD => (MemoryAccessKind::Store, 8),
+        insts::OP_LR_W..=insts::OP_AMOMAXU_W => (MemoryAccessKind::Atomic, 4),
+        insts::OP_LR_D..=insts::OP_AMOMAXU_D => (MemoryAccessKind::Atomic, 8),
+        _ => return None,
+    };
+    let address = match kind {
+        MemoryAccessKind::Load => {
+            let i = Itype(instruction);
+            registers[i.rs1()].overflowing_add(&R::from_i32(i.immediate_s()))
+        }
+        MemoryAccessKind::Store => {
+            let i = Stype(instruction);
+            registers[i.rs1()].overflowing_add(&R::from_i32(i.immediate_s()))
+        }
+        MemoryAccessKind::Atomic => registers[Rtype(instruction).rs1()].clone(),
+    };
+    Some(MemoryAccess {
+        address: address.to_u64(),
+        size,
+        kind,
+    })
+}
+
+// InstrumentHandler receives events from an InstrumentedMachine. All hooks are
+// invoked before the event takes place, and come with an empty default
+// implementation.
+pub trait InstrumentHandler<M: CoreMachine> {
+    fn instruction(&mut self, _machine: &M, _pc: u64, _instruction: Instruction) {}
+    fn memory(&mut self, _machine: &M, _access: &MemoryAccess) {}
+    fn syscall(&mut self, _machine: &M, _code: u64) {}
+}
+
+// InstrumentedMachine layers instrumentation over an existing machine without
+// modifying it. All machine traits are forwarded to the inner machine, while
+// the handler is notified of each executed instruction, memory access and
+// syscall.
+//
+// let mut machine = InstrumentedMachine::new(DefaultMachineBuilder::new(core).build(), handler);
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+pub struct InstrumentedMachine<M, H> {
+    pub machine: M,
+    pub handler: H,
+}
+
+impl<M, H> InstrumentedMachine<M, H> {
+    pub fn new(machine: M, handler: H) -> Self {
+        Self { machine, handler }
+    }
+
+    pub fn into_inner(self) -> (M, H) {
+        (self.machine, self.handler)
+    }
+}
+
+impl<M: CoreMachine, H> CoreMachine for InstrumentedMachine<M, H> {
+    type REG = M::REG;
+    type MEM = M::MEM;
+
+    fn pc(&self) -> &Self::REG {
+        self.machine.pc()
+    }
+
+    fn update_pc(&mut self, pc: Self::REG) {
+        self.machine.update_pc(pc)
+    }
+
+    fn commit_pc(&mut self) {
+        self.machine.commit_pc()
+    }
+
+    fn memory(&self) -> &Self::MEM {
+        self.machine.memory()
+    }
+
+    fn memory_mut(&mut self) -> &mut Self::MEM {
+        self.machine.memory_mut()
+    }
+
+    fn registers(&self) -> &[Self::REG] {
+        self.machine.registers()
+    }
+
+    fn set_register(&mut self, idx: usize, value: Self::REG) {
+        self.machine.set_register(idx, value)
+    }
+
+    fn version(&self) -> u32 {
+        self.machine.version()
+    }
+
+    fn isa(&self) -> u64 {
+        self.machine.isa()
+    }
+
+    fn float_registers(&self) -> Option<&FloatRegisters> {
+        self.machine.float_registers()
+    }
+
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        self.machine.float_registers_mut()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers(&self) -> Option<&super::vector::VectorRegisters> {
+        self.machine.vector_registers()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers_mut(&mut self) -> Option<&mut super::vector::VectorRegisters> {
+        self.machine.vector_registers_mut()
+    }
+}
+
+impl<M: Machine, H: InstrumentHandler<M>> Machine for InstrumentedMachine<M, H> {
+    fn ecall(&mut self) -> Result<(), Error> {
+        let code = self.machine.registers()[A7].to_u64();
+        self.handler.syscall(&self.machine, code);
+        self.machine.ecall()
+    }
+
+    fn ebreak(&mut self) -> Result<(), Error> {
+        self.machine.ebreak()
+    }
+
+    fn privileged_mut(&mut self) -> Option<&mut Privileged> {
+        self.machine.privileged_mut()
+    }
+
+    fn read_provided_csr(&mut self, csr: u16) -> Option<u64> {
+        self.machine.read_provided_csr(csr)
+    }
+
+    fn write_provided_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        self.machine.write_provided_csr(csr, value)
+    }
+}
+
+impl<M: SupportMachine, H> SupportMachine for InstrumentedMachine<M, H> {
+    fn cycles(&self) -> u64 {
+        self.machine.cycles()
+    }
+
+    fn set_cycles(&mut self, cycles: u64) {
+        self.machine.set_cycles(cycles)
+    }
+
+    fn max_cycles(&self) -> u64 {
+        self.machine.max_cycles()
+    }
+
+    fn set_max_cycles(&mut self, cycles: u64) {
+        self.machine.set_max_cycles(cycles)
+    }
+
+    fn running(&self) -> bool {
+        self.machine.running()
+    }
+
+    fn set_running(&mut self, running: bool) {
+        self.machine.set_running(running)
+    }
+
+    fn reset(&mut self, max_cycles: u64) {
+        self.machine.reset(max_cycles)
+    }
+
+    fn reset_signal(&mut self) -> bool {
+        self.machine.reset_signal()
+    }
+
+    fn invalidate_code(&mut self) {
+        self.machine.invalidate_code()
+    }
+
+    #[cfg(feature = "elf")]
+    fn load_elf(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
+        self.machine.load_elf(program, update_pc)
+    }
+
+    #[cfg(feature = "elf")]
+    fn load_elf_with_offset(
+        &mut self,
+        program: &Bytes,
+        update_pc: bool,
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.machine
+            .load_elf_with_offset(program, update_pc, offset)
+    }
+
+    #[cfg(feature = "pprof")]
+    fn code(&self) -> &Bytes {
+        self.machine.code()
+    }
+}
+
+impl<Inner: SupportMachine, H: InstrumentHandler<DefaultMachine<Inner>>>
+    InstrumentedMachine<DefaultMachine<Inner>, H>
+{
+    #[cfg(feature = "elf")]
+    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        self.machine.load_program(program, args)
+    }
+
+    // Same as DefaultMachine::run, with the handler invoked for each event.
+    pub fn run(&mut self) -> Result<i8, Error> {
+        let start_cycles = self.machine.start_run();
+        let result = self.run_inner();
+        self.machine.report_metrics(start_cycles, &result);
+        result
+    }
+
+    fn run_inner(&mut self) -> Result<i8, Error> {
+        if self.isa() & ISA_MOP != 0 && self.version() == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let mut decoder = self.machine.build_decoder();
+        self.set_running(true);
+        while self.running() {
+            if self.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            self.machine.apply_cycles_budget();
+            self.step(&mut decoder)?;
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
+        let pc = self.machine.pc().to_u64();
+        let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
+        self.handler.instruction(&self.machine, pc, instruction);
+        if let Some(access) = memory_access(instruction, self.machine.registers()) {
+            self.handler.memory(&self.machine, &access);
+        }
+        let cycles = self.machine.instruction_cycle_func()(instruction);
+        self.machine.add_cycles(cycles)?;
+        execute(instruction, self)
+    }
+}
@@ -0,0 +1,28 @@
+// Layout of the guest address space set up by DefaultMachine::load_program.
+// Fields left to None keep the default layout:
+//
+// let layout = AddressSpaceLayout {
+//     stack_size: Some(2 << 20),
+//     ..Default::default()
+// };
+// let machine = DefaultMachineBuilder::new(core).layout(layout).build();
+//
+// Once a program is loaded, DefaultMachine::layout returns the layout in
+// use, with all defaults resolved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddressSpaceLayout {
+    // Lowest address of the stack, which grows down from stack_start +
+    // stack_size. Defaults to the last stack_size bytes of memory.
+    pub stack_start: Option<u64>,
+    // Defaults to a quarter of the memory size.
+    pub stack_size: Option<u64>,
+    // Page aligned address the lowest PT_LOAD segment is moved to, along with
+    // the other segments and the entry point. Only position independent
+    // programs can be moved, by default segments stay at their link
+    // addresses. The R_RISCV_RELATIVE relocations of ET_DYN programs are
+    // applied once a load base is set, even when it is their link address.
+    pub load_base: Option<u64>,
+    // Start of the memory left to the guest heap, for syscalls managing it.
+    // Defaults to the page after the highest loaded segment.
+    pub heap_start: Option<u64>,
+}
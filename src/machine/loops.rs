@@ -0,0 +1,132 @@
+use super::super::{
+    instructions::{extract_opcode, Instruction, Register},
+    Error,
+};
+use ckb_vm_definitions::instructions as insts;
+use std::collections::HashSet;
+
+// Register states remembered per window, a window is restarted once this is
+// exceeded, e.g. for a long counting loop.
+const MAX_STATES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopDetectorOptions {
+    // Instructions a loop has to spin without progress before it is flagged.
+    pub window: u64,
+    // Distinct PCs allowed in a loop.
+    pub max_pcs: usize,
+}
+
+impl Default for LoopDetectorOptions {
+    fn default() -> Self {
+        Self {
+            window: 1 << 20,
+            max_pcs: 64,
+        }
+    }
+}
+
+// LoopDetector flags executions stuck in a small set of PCs without making
+// progress: the loop writes no memory, makes no syscall, and its registers
+// repeat a previous state each time it passes the head of the loop. Since
+// the VM is deterministic, such a loop never terminates. Execution then stops
+// with Error::InfiniteLoop instead of running until cycles are exhausted.
+//
+// let machine = DefaultMachineBuilder::new(core)
+//     .loop_detector(LoopDetectorOptions::default())
+//     .build();
+pub struct LoopDetector {
+    options: LoopDetectorOptions,
+    anchor: Option<u64>,
+    pcs: HashSet<u64>,
+    states: HashSet<Vec<u64>>,
+    repeating: bool,
+    instructions: u64,
+    iterations: u64,
+}
+
+impl LoopDetector {
+    pub fn new(options: LoopDetectorOptions) -> Self {
+        Self {
+            options,
+            anchor: None,
+            pcs: HashSet::new(),
+            states: HashSet::new(),
+            repeating: false,
+            instructions: 0,
+            iterations: 0,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.anchor = None;
+        self.pcs.clear();
+        self.states.clear();
+        self.repeating = false;
+        self.instructions = 0;
+        self.iterations = 0;
+    }
+
+    // Observes an instruction executed at `pc`, `registers` are the values
+    // after it.
+    pub fn retire<R: Register>(
+        &mut self,
+        pc: u64,
+        instruction: Instruction,
+        registers: &[R],
+    ) -> Result<(), Error> {
+        let progress = matches!(
+            extract_opcode(instruction),
+            insts::OP_SB
+                | insts::OP_SH
+                | insts::OP_SW
+                | insts::OP_SD
+                | insts::OP_SC_W
+                | insts::OP_SC_D
+                | insts::OP_AMOSWAP_W..=insts::OP_AMOMAXU_W
+                | insts::OP_AMOSWAP_D..=insts::OP_AMOMAXU_D
+                | insts::OP_ECALL
+                | insts::OP_EBREAK
+        );
+        if progress || (self.pcs.len() >= self.options.max_pcs && !self.pcs.contains(&pc)) {
+            self.restart();
+            return Ok(());
+        }
+        // The first revisited PC is taken as the head of the loop, PCs seen
+        // before it are dropped.
+        let anchor = match self.anchor {
+            Some(anchor) => anchor,
+            None if self.pcs.contains(&pc) => {
+                self.pcs.clear();
+                self.anchor = Some(pc);
+                pc
+            }
+            None => {
+                self.pcs.insert(pc);
+                return Ok(());
+            }
+        };
+        self.pcs.insert(pc);
+        self.instructions += 1;
+        if pc != anchor {
+            return Ok(());
+        }
+        self.iterations += 1;
+        if !self.repeating {
+            if self.states.len() >= MAX_STATES {
+                self.restart();
+                return Ok(());
+            }
+            let state = registers.iter().map(|r| r.to_u64()).collect();
+            self.repeating = !self.states.insert(state);
+        }
+        if self.repeating && self.instructions >= self.options.window {
+            return Err(Error::InfiniteLoop {
+                start: self.pcs.iter().copied().min().unwrap_or(pc),
+                end: self.pcs.iter().copied().max().unwrap_or(pc),
+                iterations: self.iterations,
+            });
+        }
+        Ok(())
+    }
+}
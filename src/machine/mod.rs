@@ -1,22 +1,102 @@
+pub mod abort;
+pub mod arena;
+pub mod artifact;
 #[cfg(has_asm)]
 pub mod asm;
+#[cfg(feature = "elf")]
+pub mod batch;
+pub mod budget;
+pub mod cache;
+pub mod call;
+pub mod callgrind;
+pub mod compare;
+pub mod config;
+pub mod cosim;
+pub mod csr;
+#[cfg(feature = "elf")]
 pub mod elf_adaptor;
+pub mod float;
+pub mod gdbstub;
+pub mod instrumented;
+pub mod layout;
+pub mod loops;
+pub mod policy;
+pub mod privileged;
+pub mod profiler;
+pub mod program;
+pub mod provenance;
+pub mod qemu;
+pub mod recorder;
+pub mod report;
+pub mod rvfi;
+pub mod scheduler;
+pub mod symbolic;
+pub mod symbols;
+pub mod taint;
+pub mod template;
+pub mod timing;
+#[cfg(feature = "trace")]
 pub mod trace;
-
+pub mod trace_diff;
+// The vector register file is always built so snapshots have one layout,
+// executing ISA_V still requires the rvv feature.
+pub mod vector;
+#[cfg(feature = "elf")]
+pub mod versions;
+
+use std::collections::HashMap;
 use std::fmt::{self, Display};
+use std::sync::Arc;
 
 use bytes::Bytes;
+#[cfg(feature = "elf")]
 use scroll::Pread;
 
 use super::debugger::Debugger;
-use super::decoder::{build_decoder, Decoder};
-use super::instructions::{execute, Instruction, Register};
-use super::memory::{round_page_down, round_page_up, Memory};
-use super::syscalls::Syscalls;
+use super::decoder::{build_decoder, Decoder, PredecodedCode};
+use super::devices::highest_priority_interrupt;
+use super::error::ErrorContext;
+use super::instructions::{
+    blank_instruction, execute, extract_opcode, instruction_length, insts,
+    is_basic_block_end_instruction, set_instruction_length_4, Instruction, InstructionOpcode,
+    LongInstructionFactory, Register,
+};
+use super::isa::Isa;
+use super::memory::{fill_memory, hexdump, Memory, MemoryFill};
+#[cfg(feature = "elf")]
+use super::memory::{
+    round_page_down, round_page_up, FLAG_EXECUTABLE, FLAG_FREEZED, RISCV_PAGE_SHIFTS,
+};
+use super::metrics::{report_run, MetricsSink};
+use super::observer::{run_event, Event, EventObserver};
+use super::syscalls::{
+    insert_syscalls, InvalidInstructionAction, Syscalls, SyscallsInfo, TrapAction, TrapHandler,
+    DEFAULT_SYSCALL_PRIORITY,
+};
 use super::{
     registers::{A0, A7, REGISTER_ABI_NAMES, SP},
-    Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    Error, ISA_D, ISA_F, ISA_MOP, ISA_PRIV, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    RISCV_PAGESIZE,
+};
+use arena::{Arena, TraceCacheConfig};
+use artifact::ArtifactCache;
+use budget::CyclesBudget;
+use config::{FromConfig, MachineConfig};
+use csr::{CounterCsrs, Counters, CsrProvider};
+use float::FloatRegisters;
+use instrumented::memory_access;
+use layout::AddressSpaceLayout;
+use loops::{LoopDetector, LoopDetectorOptions};
+use policy::LoadPolicy;
+use privileged::{
+    enter_guest_trap, interrupt_cause, memory_fault, raise_exception, PrivilegeMode, Privileged,
+    CAUSE_BREAKPOINT, CAUSE_ILLEGAL_INSTRUCTION, CAUSE_USER_ECALL,
 };
+use profiler::{Profiler, ProfilerOptions};
+use program::ProgramInfo;
+use report::{ExecutionReport, ReportCollector, SyscallStats};
+use template::LoadedProgram;
+use timing::TimingModel;
 
 // Version 0 is the initial launched CKB VM, it is used in CKB Lina mainnet
 pub const VERSION0: u32 = 0;
@@ -29,6 +109,10 @@ pub const VERSION0: u32 = 0;
 pub const VERSION1: u32 = 1;
 pub const VERSION2: u32 = 2;
 
+// Maximum number of instructions the interpreter decodes ahead as a basic
+// block.
+const MAX_BLOCK_LENGTH: usize = 64;
+
 /// This is the core part of RISC-V that only deals with data part, it
 /// is extracted from Machine so we can handle lifetime logic in dynamic
 /// syscall support.
@@ -47,7 +131,29 @@ pub trait CoreMachine {
     // Current running machine version, used to support compatible behavior
     // in case of bug fixes.
     fn version(&self) -> u32;
-    fn isa(&self) -> u8;
+    fn isa(&self) -> u64;
+
+    // Floating point registers, None when ISA_F is not enabled. Wrappers
+    // must forward them to the inner machine.
+    fn float_registers(&self) -> Option<&FloatRegisters> {
+        None
+    }
+
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        None
+    }
+
+    // Vector registers and CSRs, None when ISA_V is not enabled. Wrappers
+    // must forward them to the inner machine.
+    #[cfg(feature = "rvv")]
+    fn vector_registers(&self) -> Option<&vector::VectorRegisters> {
+        None
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers_mut(&mut self) -> Option<&mut vector::VectorRegisters> {
+        None
+    }
 }
 
 /// This is the core trait describing a full RISC-V machine. Instruction
@@ -55,6 +161,22 @@ pub trait CoreMachine {
 pub trait Machine: CoreMachine {
     fn ecall(&mut self) -> Result<(), Error>;
     fn ebreak(&mut self) -> Result<(), Error>;
+
+    // Privileged state used by privileged instructions, None when ISA_PRIV
+    // is not enabled. Wrappers must forward it to the inner machine.
+    fn privileged_mut(&mut self) -> Option<&mut Privileged> {
+        None
+    }
+
+    // CSRs outside of the privileged, floating point and vector ones, see
+    // CsrProvider. Wrappers must forward them to the inner machine.
+    fn read_provided_csr(&mut self, _csr: u16) -> Option<u64> {
+        None
+    }
+
+    fn write_provided_csr(&mut self, _csr: u16, _value: u64) -> Option<()> {
+        None
+    }
 }
 
 /// This traits extend on top of CoreMachine by adding additional support
@@ -68,6 +190,7 @@ pub trait SupportMachine: CoreMachine {
     fn cycles(&self) -> u64;
     fn set_cycles(&mut self, cycles: u64);
     fn max_cycles(&self) -> u64;
+    fn set_max_cycles(&mut self, cycles: u64);
 
     fn running(&self) -> bool;
     fn set_running(&mut self, running: bool);
@@ -75,6 +198,9 @@ pub trait SupportMachine: CoreMachine {
     // Erase all the states of the virtual machine.
     fn reset(&mut self, max_cycles: u64);
     fn reset_signal(&mut self) -> bool;
+    // Drops instructions cached from memory after the code changed, and
+    // raises the reset signal so the run loops drop theirs.
+    fn invalidate_code(&mut self);
 
     fn add_cycles(&mut self, cycles: u64) -> Result<(), Error> {
         let new_cycles = self
@@ -97,7 +223,21 @@ pub trait SupportMachine: CoreMachine {
         Ok(())
     }
 
+    #[cfg(feature = "elf")]
     fn load_elf_inner(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
+        self.load_elf_with_offset(program, update_pc, 0)
+    }
+
+    // Same as load_elf_inner, with all segments and the entry point moved up
+    // by `offset` bytes, wrapping around. Only position independent programs
+    // still run once moved, see AddressSpaceLayout::load_base.
+    #[cfg(feature = "elf")]
+    fn load_elf_with_offset(
+        &mut self,
+        program: &Bytes,
+        update_pc: bool,
+        offset: u64,
+    ) -> Result<u64, Error> {
         let version = self.version();
         // We did not use Elf::parse here to avoid triggering potential bugs in goblin.
         // * https://github.com/nervosnetwork/ckb-vm/issues/143
@@ -146,8 +286,9 @@ pub trait SupportMachine: CoreMachine {
         let mut bytes: u64 = 0;
         for program_header in program_headers {
             if program_header.p_type == elf_adaptor::PT_LOAD {
-                let aligned_start = round_page_down(program_header.p_vaddr);
-                let padding_start = program_header.p_vaddr.wrapping_sub(aligned_start);
+                let vaddr = program_header.p_vaddr.wrapping_add(offset);
+                let aligned_start = round_page_down(vaddr);
+                let padding_start = vaddr.wrapping_sub(aligned_start);
                 let size = round_page_up(program_header.p_memsz.wrapping_add(padding_start));
                 let slice_start = program_header.p_offset;
                 let slice_end = program_header
@@ -173,12 +314,13 @@ pub trait SupportMachine: CoreMachine {
             }
         }
         if update_pc {
-            self.update_pc(Self::REG::from_u64(e_entry));
+            self.update_pc(Self::REG::from_u64(e_entry.wrapping_add(offset)));
             self.commit_pc();
         }
         Ok(bytes)
     }
 
+    #[cfg(feature = "elf")]
     fn load_elf(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
         // Allows to override load_elf by writing the real function body in load_elf_inner.
         //
@@ -280,7 +422,7 @@ pub trait SupportMachine: CoreMachine {
     fn code(&self) -> &Bytes;
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct DefaultCoreMachine<R, M> {
     registers: [R; RISCV_GENERAL_REGISTER_NUMBER],
     pc: R,
@@ -290,8 +432,12 @@ pub struct DefaultCoreMachine<R, M> {
     cycles: u64,
     max_cycles: u64,
     running: bool,
-    isa: u8,
+    isa: u64,
     version: u32,
+    memory_fill: MemoryFill,
+    float_registers: FloatRegisters,
+    #[cfg(feature = "rvv")]
+    vector_registers: vector::VectorRegisters,
     #[cfg(feature = "pprof")]
     code: Bytes,
 }
@@ -327,13 +473,47 @@ impl<R: Register, M: Memory<REG = R>> CoreMachine for DefaultCoreMachine<R, M> {
         self.registers[idx] = value;
     }
 
-    fn isa(&self) -> u8 {
+    fn isa(&self) -> u64 {
         self.isa
     }
 
     fn version(&self) -> u32 {
         self.version
     }
+
+    fn float_registers(&self) -> Option<&FloatRegisters> {
+        if self.isa & (ISA_F | ISA_D) != 0 {
+            Some(&self.float_registers)
+        } else {
+            None
+        }
+    }
+
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        if self.isa & (ISA_F | ISA_D) != 0 {
+            Some(&mut self.float_registers)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers(&self) -> Option<&vector::VectorRegisters> {
+        if self.isa & crate::ISA_V != 0 {
+            Some(&self.vector_registers)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers_mut(&mut self) -> Option<&mut vector::VectorRegisters> {
+        if self.isa & crate::ISA_V != 0 {
+            Some(&mut self.vector_registers)
+        } else {
+            None
+        }
+    }
 }
 
 impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M> {
@@ -349,10 +529,21 @@ impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M
         self.max_cycles
     }
 
+    fn set_max_cycles(&mut self, cycles: u64) {
+        self.max_cycles = cycles;
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.registers = Default::default();
         self.pc = Default::default();
+        self.float_registers = Default::default();
+        #[cfg(feature = "rvv")]
+        {
+            self.vector_registers = Default::default();
+        }
         self.memory = M::new_with_memory(self.memory().memory_size());
+        // Fresh memory is writable, filling it can't fail.
+        let _ = fill_memory(&mut self.memory, self.memory_fill);
         self.cycles = 0;
         self.max_cycles = max_cycles;
         self.reset_signal = true;
@@ -365,6 +556,10 @@ impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M
         ret
     }
 
+    fn invalidate_code(&mut self) {
+        self.reset_signal = true;
+    }
+
     fn running(&self) -> bool {
         self.running
     }
@@ -373,6 +568,7 @@ impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M
         self.running = running;
     }
 
+    #[cfg(feature = "elf")]
     fn load_elf(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
         #[cfg(feature = "pprof")]
         {
@@ -388,11 +584,11 @@ impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M
 }
 
 impl<R: Register, M: Memory> DefaultCoreMachine<R, M> {
-    pub fn new(isa: u8, version: u32, max_cycles: u64) -> Self {
+    pub fn new(isa: u64, version: u32, max_cycles: u64) -> Self {
         Self::new_with_memory(isa, version, max_cycles, RISCV_MAX_MEMORY)
     }
 
-    pub fn new_with_memory(isa: u8, version: u32, max_cycles: u64, memory_size: usize) -> Self {
+    pub fn new_with_memory(isa: u64, version: u32, max_cycles: u64, memory_size: usize) -> Self {
         Self {
             registers: Default::default(),
             pc: Default::default(),
@@ -404,18 +600,52 @@ impl<R: Register, M: Memory> DefaultCoreMachine<R, M> {
             running: Default::default(),
             isa,
             version,
+            memory_fill: MemoryFill::Zero,
+            float_registers: Default::default(),
+            #[cfg(feature = "rvv")]
+            vector_registers: Default::default(),
             #[cfg(feature = "pprof")]
             code: Default::default(),
         }
     }
 
-    pub fn set_max_cycles(&mut self, cycles: u64) {
-        self.max_cycles = cycles;
+    // Same as new_with_memory, but the ISA is validated against version first.
+    pub fn new_with_isa(
+        isa: Isa,
+        version: u32,
+        max_cycles: u64,
+        memory_size: usize,
+    ) -> Result<Self, Error> {
+        let isa = Isa::new(isa.mask(), version)?.mask();
+        Ok(Self::new_with_memory(isa, version, max_cycles, memory_size))
     }
 
     pub fn take_memory(self) -> M {
         self.memory
     }
+
+    // Fills memory with `fill`, which is applied again whenever the machine
+    // is reset. Call it before loading programs.
+    pub fn set_memory_fill(&mut self, fill: MemoryFill) -> Result<(), Error> {
+        self.memory_fill = fill;
+        fill_memory(&mut self.memory, fill)
+    }
+}
+
+impl<R: Register, M: Memory<REG = R>> FromConfig for DefaultCoreMachine<R, M> {
+    fn from_config(config: &MachineConfig) -> Result<Self, Error> {
+        let mut machine = Self::new_with_memory(
+            config.checked_isa()?.mask(),
+            config.version,
+            config.max_cycles,
+            config.memory_size,
+        );
+        if config.huge_pages {
+            machine.memory_mut().use_huge_pages();
+        }
+        machine.set_memory_fill(config.memory_fill)?;
+        Ok(machine)
+    }
 }
 
 pub type InstructionCycleFunc = dyn Fn(Instruction) -> u64 + Send + Sync;
@@ -429,7 +659,42 @@ pub struct DefaultMachine<Inner> {
     // we can change to static dispatch.
     instruction_cycle_func: Box<InstructionCycleFunc>,
     debugger: Option<Box<dyn Debugger<Inner>>>,
-    syscalls: Vec<Box<dyn Syscalls<Inner>>>,
+    // Sorted by priority in descending order.
+    syscalls: Vec<(i32, Box<dyn Syscalls<Inner>>)>,
+    trap_handler: Option<Box<dyn TrapHandler<Inner>>>,
+    metrics: Option<Box<dyn MetricsSink>>,
+    cycles_budget: Option<CyclesBudget>,
+    observer: Option<Box<dyn EventObserver>>,
+    loop_detector: Option<LoopDetector>,
+    pub(crate) arena: Arena,
+    pub(crate) trace_cache: TraceCacheConfig,
+    // False when the loaded program declares no RVC instructions, see
+    // Decoder::set_compressed.
+    compressed: bool,
+    // Number of threads decoding the program after it is loaded, 0 to
+    // decode instructions when they are first executed.
+    predecode_workers: usize,
+    predecoded: Option<Arc<PredecodedCode>>,
+    artifact_cache: Option<Box<dyn ArtifactCache>>,
+    load_policy: Option<Box<dyn LoadPolicy>>,
+    privileged: Privileged,
+    guest_memory_faults: bool,
+    error_context: bool,
+    layout: AddressSpaceLayout,
+    // Layout of the loaded program, with defaults resolved.
+    loaded_layout: Option<AddressSpaceLayout>,
+    denied_opcodes: Vec<InstructionOpcode>,
+    long_instruction_factories: Vec<LongInstructionFactory>,
+    // Executable segments of the loaded program, as (address, size).
+    code_segments: Vec<(u64, u64)>,
+    program_info: Option<ProgramInfo>,
+    // Only collected during run_with_report.
+    syscall_stats: Option<HashMap<u64, SyscallStats>>,
+    timing_model: Option<Box<dyn TimingModel>>,
+    profiler: Option<Profiler>,
+    csr_provider: Option<Box<dyn CsrProvider<Inner>>>,
+    // Instructions retired by the interpreter loops, read by the instret CSR.
+    instret: u64,
     exit_code: i8,
 }
 
@@ -465,13 +730,31 @@ impl<Inner: CoreMachine> CoreMachine for DefaultMachine<Inner> {
         self.inner.set_register(idx, value)
     }
 
-    fn isa(&self) -> u8 {
+    fn isa(&self) -> u64 {
         self.inner.isa()
     }
 
     fn version(&self) -> u32 {
         self.inner.version()
     }
+
+    fn float_registers(&self) -> Option<&FloatRegisters> {
+        self.inner.float_registers()
+    }
+
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        self.inner.float_registers_mut()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers(&self) -> Option<&vector::VectorRegisters> {
+        self.inner.vector_registers()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers_mut(&mut self) -> Option<&mut vector::VectorRegisters> {
+        self.inner.vector_registers_mut()
+    }
 }
 
 impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
@@ -487,14 +770,24 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
         self.inner.max_cycles()
     }
 
+    fn set_max_cycles(&mut self, cycles: u64) {
+        self.inner.set_max_cycles(cycles)
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.inner_mut().reset(max_cycles);
+        self.privileged = Privileged::default();
+        self.instret = 0;
     }
 
     fn reset_signal(&mut self) -> bool {
         self.inner_mut().reset_signal()
     }
 
+    fn invalidate_code(&mut self) {
+        self.inner_mut().invalidate_code();
+    }
+
     fn running(&self) -> bool {
         self.inner.running()
     }
@@ -511,6 +804,99 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
 
 impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
     fn ecall(&mut self) -> Result<(), Error> {
+        self.memory_mut().trap();
+        if self.trap_to_guest(CAUSE_USER_ECALL, 0) {
+            return Ok(());
+        }
+        let pc = self.pc().clone();
+        let code = self.registers()[A7].to_u64();
+        let start_cycles = self.cycles();
+        let result = if self.observer.is_none() {
+            self.ecall_inner()
+        } else {
+            self.notify(&Event::SyscallEntered { code });
+            let watch = self.watch_page_flags()?;
+            let result = self.ecall_inner();
+            self.notify_permission_changes(watch)?;
+            if result.is_ok() {
+                self.notify(&Event::SyscallExited { code });
+            }
+            result
+        };
+        if let Err(Error::SyscallRetry(_)) = result {
+            // Puts the ECALL back as the next instruction to run, and gives
+            // back its cycles which will be charged again.
+            self.update_pc(pc);
+            let cycles = self.instruction_cycle_func()(set_instruction_length_4(
+                blank_instruction(insts::OP_ECALL),
+            ));
+            self.set_cycles(self.cycles().saturating_sub(cycles));
+        } else {
+            let cycles = self.cycles().saturating_sub(start_cycles);
+            if let Some(stats) = &mut self.syscall_stats {
+                let stats = stats.entry(code).or_default();
+                stats.calls += 1;
+                stats.cycles += cycles;
+            }
+        }
+        result
+    }
+
+    fn ebreak(&mut self) -> Result<(), Error> {
+        self.memory_mut().trap();
+        let pc = self.pc().to_u64();
+        if self.trap_to_guest(CAUSE_BREAKPOINT, pc) {
+            return Ok(());
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.ebreak(&mut self.inner)
+        } else if let Some(handler) = &mut self.trap_handler {
+            let action = handler.ebreak(&mut self.inner);
+            self.apply_trap_action(action)
+        } else {
+            // Unlike ecall, the default behavior of an EBREAK operation is
+            // a dummy one.
+            Ok(())
+        }
+    }
+
+    fn privileged_mut(&mut self) -> Option<&mut Privileged> {
+        if self.isa() & ISA_PRIV != 0 {
+            Some(&mut self.privileged)
+        } else {
+            None
+        }
+    }
+
+    fn read_provided_csr(&mut self, csr: u16) -> Option<u64> {
+        let counters = Counters {
+            cycles: self.inner.cycles(),
+            instret: self.instret,
+        };
+        match &mut self.csr_provider {
+            Some(provider) => provider.read_csr(&mut self.inner, counters, csr),
+            None => CounterCsrs.read_csr(&mut self.inner, counters, csr),
+        }
+    }
+
+    fn write_provided_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        match &mut self.csr_provider {
+            Some(provider) => provider.write_csr(&mut self.inner, csr, value),
+            None => CounterCsrs.write_csr(&mut self.inner, csr, value),
+        }
+    }
+}
+
+// Page flags before a syscall or a load, see watch_page_flags.
+enum FlagWatch {
+    // The memory records the flags of the pages it changes.
+    Tracked,
+    // Flags of all pages.
+    Snapshot(Vec<u8>),
+}
+
+impl<Inner: SupportMachine> DefaultMachine<Inner> {
+    fn ecall_inner(&mut self) -> Result<(), Error> {
         let code = self.registers()[A7].to_u64();
         match code {
             93 => {
@@ -520,7 +906,7 @@ impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
                 Ok(())
             }
             _ => {
-                for syscall in &mut self.syscalls {
+                for (_, syscall) in &mut self.syscalls {
                     let processed = syscall.ecall(&mut self.inner)?;
                     if processed {
                         if self.cycles() > self.max_cycles() {
@@ -529,20 +915,130 @@ impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
                         return Ok(());
                     }
                 }
+                if let Some(handler) = &mut self.trap_handler {
+                    let action = handler.ecall(&mut self.inner);
+                    return self.apply_trap_action(action);
+                }
                 Err(Error::InvalidEcall(code))
             }
         }
     }
 
-    fn ebreak(&mut self) -> Result<(), Error> {
-        if let Some(debugger) = &mut self.debugger {
-            debugger.ebreak(&mut self.inner)
-        } else {
-            // Unlike ecall, the default behavior of an EBREAK operation is
-            // a dummy one.
-            Ok(())
+    pub(crate) fn notify(&mut self, event: &Event) {
+        let cycles = self.inner.cycles();
+        if let Some(observer) = &mut self.observer {
+            observer.on_event_at(event, cycles);
+        }
+    }
+
+    pub(crate) fn has_observer(&self) -> bool {
+        self.observer.is_some()
+    }
+
+    // Starts watching page flags to report the permission changes of a
+    // syscall or a load. Memories recording their flag changes are asked
+    // for them afterwards, the others have the flags of all their pages
+    // compared.
+    fn watch_page_flags(&mut self) -> Result<FlagWatch, Error> {
+        if self.memory_mut().track_flag_changes(true) {
+            return Ok(FlagWatch::Tracked);
         }
+        let pages = (self.memory().memory_size() / RISCV_PAGESIZE) as u64;
+        let flags = (0..pages)
+            .map(|page| self.memory_mut().fetch_flag(page))
+            .collect::<Result<_, _>>()?;
+        Ok(FlagWatch::Snapshot(flags))
     }
+
+    fn notify_permission_changes(&mut self, watch: FlagWatch) -> Result<(), Error> {
+        let changes = match watch {
+            FlagWatch::Tracked => {
+                let changes = self.memory_mut().take_flag_changes();
+                self.memory_mut().track_flag_changes(false);
+                changes
+            }
+            FlagWatch::Snapshot(flags) => flags
+                .into_iter()
+                .enumerate()
+                .map(|(page, flags)| (page as u64, flags))
+                .collect(),
+        };
+        for (page, old) in changes {
+            let new = self.memory_mut().fetch_flag(page)?;
+            if new != old {
+                self.notify(&Event::MemoryPermissionChanged { page, old, new });
+            }
+        }
+        Ok(())
+    }
+
+    // Advances memory mapped devices to the current cycles, and hands their
+    // pending interrupt with the highest priority to the trap handler.
+    // Returns whether the handler was called. Without a trap handler,
+    // interrupts are ignored. Once the guest installed a trap vector, see
+    // Privileged, interrupts go to the guest instead. Only the interpreter
+    // loops of DefaultMachine and TraceMachine poll interrupts.
+    pub(crate) fn poll_interrupts(&mut self) -> Result<bool, Error> {
+        let cycles = self.cycles();
+        self.memory_mut().tick(cycles);
+        let pending = self.memory().pending_interrupts();
+        let pc = self.pc().to_u64();
+        if let Some(state) = self.privileged_mut().filter(|s| s.trap_installed()) {
+            state.set_pending_interrupts(pending);
+            let irq = match state.interrupt_to_take() {
+                Some(irq) => irq,
+                None => return Ok(false),
+            };
+            enter_guest_trap(self, interrupt_cause(irq, Inner::REG::BITS), pc, 0);
+            self.commit_pc();
+            return Ok(true);
+        }
+        let irq = match highest_priority_interrupt(pending) {
+            Some(irq) => irq,
+            None => return Ok(false),
+        };
+        match &mut self.trap_handler {
+            Some(handler) => {
+                self.inner.memory_mut().trap();
+                let action = handler.interrupt(&mut self.inner, irq);
+                self.apply_trap_action(action)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn apply_trap_action(&mut self, action: TrapAction) -> Result<(), Error> {
+        match action {
+            TrapAction::Continue => Ok(()),
+            TrapAction::Trap(e) => Err(e),
+            TrapAction::Terminate(exit_code) => {
+                self.exit_code = exit_code;
+                self.set_running(false);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn dispatch_order<Inner: SupportMachine>(
+    syscalls: &[(i32, Box<dyn Syscalls<Inner>>)],
+) -> Vec<SyscallsInfo> {
+    syscalls
+        .iter()
+        .map(|(priority, syscall)| SyscallsInfo {
+            name: syscall.name(),
+            priority: *priority,
+        })
+        .collect()
+}
+
+// Iterates over all general purpose registers of a machine together with
+// their ABI names.
+pub fn named_registers<Mac: CoreMachine>(
+    machine: &Mac,
+) -> impl Iterator<Item = (&'static str, &Mac::REG)> {
+    REGISTER_ABI_NAMES.iter().copied().zip(machine.registers())
 }
 
 impl<Inner: CoreMachine> Display for DefaultMachine<Inner> {
@@ -561,18 +1057,116 @@ impl<Inner: CoreMachine> Display for DefaultMachine<Inner> {
 }
 
 impl<Inner: SupportMachine> DefaultMachine<Inner> {
+    #[cfg(feature = "elf")]
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
-        let elf_bytes = self.load_elf(program, true)?;
-        for syscall in &mut self.syscalls {
+        self.check_load_policy(program)?;
+        let watch = if self.has_observer() {
+            self.notify(&Event::LoadStarted);
+            Some(self.watch_page_flags()?)
+        } else {
+            None
+        };
+        let result = self.load_program_inner(program, args);
+        if let Some(watch) = watch {
+            self.notify_permission_changes(watch)?;
+            if let Ok(bytes) = result {
+                let entry = self.pc().to_u64();
+                self.notify(&Event::ProgramLoaded { entry, bytes });
+            }
+        }
+        result
+    }
+
+    fn check_load_policy(&self, program: &[u8]) -> Result<(), Error> {
+        if let Some(policy) = &self.load_policy {
+            let hash = super::hash::program_hash(program);
+            if !policy.allows(&hash, program) {
+                return Err(policy::not_allowed(&hash));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "elf")]
+    fn load_program_inner(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        // VERSION0 programs are loaded with an older goblin, failing to read
+        // the segments again only leaves the defaults of the layout unknown.
+        let segments = elf_adaptor::load_segments(program);
+        let offset = self.load_offset(&segments)?;
+        // Position independent executables are only relocated when a load
+        // base is configured, others load as they always did.
+        let relocated;
+        let program =
+            if self.layout.load_base.is_some() && elf_adaptor::is_position_independent(program) {
+                relocated = elf_adaptor::relocate(program, offset)?;
+                &relocated
+            } else {
+                program
+            };
+        let elf_bytes = if offset == 0 {
+            self.load_elf(program, true)?
+        } else {
+            self.load_elf_with_offset(program, true, offset)?
+        };
+        self.compressed = elf_adaptor::uses_compressed_instructions(program);
+        self.code_segments = elf_adaptor::executable_segments(program)
+            .map(|segments| {
+                segments
+                    .into_iter()
+                    .map(|(address, size)| (address.wrapping_add(offset), size))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.predecoded = None;
+        if self.predecode_workers > 0 {
+            self.predecoded = Some(Arc::new(self.predecode_cached(program, offset)?));
+        }
+        for (_, syscall) in &mut self.syscalls {
             syscall.initialize(&mut self.inner)?;
         }
         if let Some(debugger) = &mut self.debugger {
             debugger.initialize(&mut self.inner)?;
         }
-        let memory_size = self.memory().memory_size();
-        let stack_size = memory_size / 4;
-        let stack_bytes =
-            self.initialize_stack(args, (memory_size - stack_size) as u64, stack_size as u64)?;
+        let memory_size = self.memory().memory_size() as u64;
+        let stack_size = self.layout.stack_size.unwrap_or(memory_size / 4);
+        let stack_start = match self.layout.stack_start {
+            Some(start) => start,
+            None => memory_size
+                .checked_sub(stack_size)
+                .ok_or(Error::MemOutOfStack)?,
+        };
+        if stack_start.checked_add(stack_size).is_none() {
+            return Err(Error::MemOutOfStack);
+        }
+        let stack_bytes = self.initialize_stack(args, stack_start, stack_size)?;
+        let heap_start = self.layout.heap_start.or_else(|| {
+            segments.as_ref().ok().and_then(|segments| {
+                segments
+                    .iter()
+                    .map(|(address, size)| {
+                        round_page_up(address.wrapping_add(offset).saturating_add(*size))
+                    })
+                    .max()
+            })
+        });
+        let load_base = segments.as_ref().ok().and_then(|segments| {
+            segments
+                .iter()
+                .map(|(address, _)| round_page_down(address.wrapping_add(offset)))
+                .min()
+        });
+        self.loaded_layout = Some(AddressSpaceLayout {
+            stack_start: Some(stack_start),
+            stack_size: Some(stack_size),
+            load_base: self.layout.load_base.or(load_base),
+            heap_start,
+        });
+        self.program_info = Some(ProgramInfo {
+            entry: self.pc().to_u64(),
+            size: program.len() as u64,
+            build_id: elf_adaptor::build_id(program),
+            load_bias: offset,
+        });
         // Make sure SP is 16 byte aligned
         if self.inner.version() >= VERSION1 {
             debug_assert!(self.registers()[SP].to_u64() % 16 == 0);
@@ -585,6 +1179,106 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         Ok(bytes)
     }
 
+    // Offset added to the addresses of `segments` to load them at the
+    // configured load base.
+    #[cfg(feature = "elf")]
+    fn load_offset(&self, segments: &Result<Vec<(u64, u64)>, Error>) -> Result<u64, Error> {
+        match self.layout.load_base {
+            Some(base) => {
+                let lowest = segments
+                    .as_ref()
+                    .map_err(Clone::clone)?
+                    .iter()
+                    .map(|(address, _)| round_page_down(*address))
+                    .min()
+                    .ok_or(Error::ElfSegmentAddrOrSizeError)?;
+                if base != round_page_down(base) {
+                    return Err(Error::Unexpected(format!(
+                        "Load base 0x{:x} is not page aligned",
+                        base
+                    )));
+                }
+                Ok(base.wrapping_sub(lowest))
+            }
+            None => Ok(0),
+        }
+    }
+
+    // Replaces the code of the loaded program with the one of `program`, a
+    // new build of it, while the machine is paused. Only the executable
+    // segments are loaded, data, heap and stack keep their content, as do
+    // registers and PC. Pages of the old code are zeroed first, and those
+    // not covered by the new code are left writable. The data segments of
+    // `program` are expected at the same addresses as before, so the new
+    // code finds its data in place. Returns the bytes of code loaded.
+    #[cfg(feature = "elf")]
+    pub fn reload_program(&mut self, program: &Bytes) -> Result<u64, Error> {
+        self.check_load_policy(program)?;
+        let watch = if self.has_observer() {
+            Some(self.watch_page_flags()?)
+        } else {
+            None
+        };
+        let result = self.reload_program_inner(program);
+        if let Some(watch) = watch {
+            self.notify_permission_changes(watch)?;
+        }
+        result
+    }
+
+    #[cfg(feature = "elf")]
+    fn reload_program_inner(&mut self, program: &Bytes) -> Result<u64, Error> {
+        let headers = elf_adaptor::load_program_headers(program, elf_adaptor::PF_X)?;
+        let offset = self.load_offset(&elf_adaptor::load_segments(program))?;
+        for (address, size) in std::mem::take(&mut self.code_segments) {
+            let start = round_page_down(address);
+            let end = round_page_up(address.saturating_add(size));
+            for page in (start >> RISCV_PAGE_SHIFTS)..(end >> RISCV_PAGE_SHIFTS) {
+                self.memory_mut()
+                    .clear_flag(page, FLAG_EXECUTABLE | FLAG_FREEZED)?;
+            }
+            self.memory_mut().store_byte(start, end - start, 0)?;
+        }
+        let mut bytes: u64 = 0;
+        for header in &headers {
+            let vaddr = header.p_vaddr.wrapping_add(offset);
+            let aligned_start = round_page_down(vaddr);
+            let padding_start = vaddr - aligned_start;
+            let size = round_page_up(header.p_memsz.wrapping_add(padding_start));
+            let slice_end = header.p_offset.wrapping_add(header.p_filesz);
+            if header.p_offset > slice_end || slice_end > program.len() as u64 {
+                return Err(Error::ElfSegmentAddrOrSizeError);
+            }
+            // Data pages of the old program may be in the way, and frozen.
+            for page in
+                (aligned_start >> RISCV_PAGE_SHIFTS)..((aligned_start + size) >> RISCV_PAGE_SHIFTS)
+            {
+                self.memory_mut().clear_flag(page, FLAG_FREEZED)?;
+            }
+            let flags = elf_adaptor::convert_flags(header.p_flags, self.version() < VERSION1)?;
+            self.memory_mut().init_pages(
+                aligned_start,
+                size,
+                flags,
+                Some(program.slice(header.p_offset as usize..slice_end as usize)),
+                padding_start,
+            )?;
+            bytes = bytes.saturating_add(header.p_filesz);
+            self.code_segments.push((vaddr, header.p_memsz));
+        }
+        self.compressed = elf_adaptor::uses_compressed_instructions(program);
+        if let Some(info) = &mut self.program_info {
+            info.size = program.len() as u64;
+            info.build_id = elf_adaptor::build_id(program);
+        }
+        self.predecoded = None;
+        if self.predecode_workers > 0 {
+            self.predecoded = Some(Arc::new(self.predecode_cached(program, offset)?));
+        }
+        self.invalidate_code();
+        Ok(bytes)
+    }
+
     pub fn take_inner(self) -> Inner {
         self.inner
     }
@@ -593,6 +1287,11 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         self.exit_code
     }
 
+    // Instructions retired by the interpreter loops, see CSR_INSTRET.
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
     pub fn instruction_cycle_func(&self) -> &InstructionCycleFunc {
         &self.instruction_cycle_func
     }
@@ -601,34 +1300,525 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         &mut self.inner
     }
 
+    // Returns a human readable dump of the machine state: pc, cycles, all
+    // registers with ABI names, the build ID of the program, and a hexdump
+    // of each `(address, size)` range in `memory_ranges`. This is intended
+    // for bug reports and panic logs.
+    pub fn dump(&mut self, memory_ranges: &[(u64, u64)]) -> Result<String, Error> {
+        let mut s = format!("{}", self);
+        s.push_str(&format!(
+            "cycles: {} / {}\n",
+            self.cycles(),
+            self.max_cycles()
+        ));
+        if let Some(build_id) = self.program_info().and_then(ProgramInfo::build_id_hex) {
+            s.push_str(&format!("build-id: {}\n", build_id));
+        }
+        for (addr, size) in memory_ranges {
+            let data = self.memory_mut().load_bytes(*addr, *size)?;
+            s.push_str(&format!("memory 0x{:x} - 0x{:x}:\n", addr, addr + size));
+            s.push_str(&hexdump(*addr, &data));
+        }
+        Ok(s)
+    }
+
+    // Returns registered syscall modules in the order they are dispatched.
+    pub fn dispatch_order(&self) -> Vec<SyscallsInfo> {
+        dispatch_order(&self.syscalls)
+    }
+
+    // Takes the buffers of this machine, so they can be reused by the next
+    // machine. This machine allocates new buffers if it runs again.
+    pub fn take_arena(&mut self) -> Arena {
+        std::mem::take(&mut self.arena)
+    }
+
+    // State of the loaded program kept outside of the core, see
+    // MachineTemplate.
+    pub(crate) fn loaded_program(&self) -> LoadedProgram {
+        LoadedProgram {
+            compressed: self.compressed,
+            predecoded: self.predecoded.as_ref().map(Arc::clone),
+            loaded_layout: self.loaded_layout,
+            code_segments: self.code_segments.clone(),
+            program_info: self.program_info.clone(),
+            privileged: self.privileged.clone(),
+        }
+    }
+
+    // Takes over a program loaded by another machine, whose core this
+    // machine was built with. Syscalls and the debugger are initialized as
+    // if the program was loaded by this machine.
+    pub(crate) fn restore_loaded_program(&mut self, program: &LoadedProgram) -> Result<(), Error> {
+        self.compressed = program.compressed;
+        self.predecoded = program.predecoded.as_ref().map(Arc::clone);
+        self.loaded_layout = program.loaded_layout;
+        self.code_segments = program.code_segments.clone();
+        self.program_info = program.program_info.clone();
+        self.privileged = program.privileged.clone();
+        for (_, syscall) in &mut self.syscalls {
+            syscall.initialize(&mut self.inner)?;
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.initialize(&mut self.inner)?;
+        }
+        self.invalidate_code();
+        Ok(())
+    }
+
+    // Builds the decoder used by the run loops for the loaded program.
+    pub(crate) fn build_decoder(&self) -> Decoder {
+        let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+        decoder.set_compressed(self.compressed);
+        decoder.set_denied_opcodes(self.denied_opcodes.iter().copied());
+        for factory in &self.long_instruction_factories {
+            decoder.add_long_instruction_factory(*factory);
+        }
+        if let Some(predecoded) = &self.predecoded {
+            decoder.set_predecoded(Arc::clone(predecoded));
+        }
+        decoder
+    }
+
+    // Returns what is known about the loaded program, None before a program
+    // is loaded with load_program.
+    pub fn program_info(&self) -> Option<&ProgramInfo> {
+        self.program_info.as_ref()
+    }
+
+    // Difference between the loaded addresses of the program and its link
+    // addresses, to add to the addresses of its symbols, see
+    // Symbols::relocated. 0 unless the program was moved to a load base.
+    pub fn load_bias(&self) -> u64 {
+        self.program_info
+            .as_ref()
+            .map_or(0, |program_info| program_info.load_bias)
+    }
+
+    // Returns the layout of the loaded program, or the configured layout
+    // before a program is loaded.
+    pub fn layout(&self) -> AddressSpaceLayout {
+        self.loaded_layout.unwrap_or(self.layout)
+    }
+
+    // Returns the privileged state, None when ISA_PRIV is not enabled.
+    pub fn privileged(&self) -> Option<&Privileged> {
+        if self.isa() & ISA_PRIV != 0 {
+            Some(&self.privileged)
+        } else {
+            None
+        }
+    }
+
+    // Takes exception `cause` in the guest, for ECALL and EBREAK executed in
+    // user mode once the guest installed a trap vector. Returns false when
+    // the trap keeps its host behavior.
+    fn trap_to_guest(&mut self, cause: u64, tval: u64) -> bool {
+        let pc = self.pc().to_u64();
+        match self.privileged() {
+            Some(state) if state.mode() == PrivilegeMode::User => {
+                enter_guest_trap(self, cause, pc, tval)
+            }
+            _ => false,
+        }
+    }
+
+    // Takes the exception of `instruction` failing with `error` in the guest,
+    // see DefaultMachineBuilder::guest_memory_faults. PC already points after
+    // the failed instruction. Returns false when `error` goes to the host.
+    pub(crate) fn trap_memory_fault(&mut self, instruction: Instruction, error: &Error) -> bool {
+        if !self.guest_memory_faults {
+            return false;
+        }
+        let (cause, tval) = match memory_fault(instruction, self.registers(), error) {
+            Some(fault) => fault,
+            None => return false,
+        };
+        let pc = self
+            .pc()
+            .to_u64()
+            .wrapping_sub(u64::from(instruction_length(instruction)));
+        if !enter_guest_trap(self, cause, pc, tval) {
+            return false;
+        }
+        self.commit_pc();
+        true
+    }
+
+    // Attaches where `instruction` at `pc` failed to `error`: watchpoint hits
+    // get the PC, other errors a context, see
+    // DefaultMachineBuilder::error_context. Faulting instructions do not
+    // write rd, so registers still give the accessed address.
+    pub(crate) fn error_with_context(
+        &self,
+        error: Error,
+        pc: u64,
+        instruction: Instruction,
+    ) -> Error {
+        if let Error::Watchpoint { addr, .. } = error {
+            return Error::Watchpoint { addr, pc };
+        }
+        if !self.error_context {
+            return error;
+        }
+        let address = memory_access(instruction, self.registers()).map(|access| access.address);
+        error.with_context(ErrorContext {
+            pc,
+            opcode: extract_opcode(instruction),
+            address,
+            cycles: self.cycles(),
+        })
+    }
+
+    // Whether instructions failing to decode go to the trap handler, the run
+    // loops then end blocks before them.
+    pub(crate) fn handles_invalid_instructions(&self) -> bool {
+        self.trap_handler.is_some()
+    }
+
+    // Hands the instruction at PC which failed to decode with `error` to the
+    // trap handler, see TrapHandler::invalid_instruction. Returns the error
+    // which stops execution, if any.
+    pub(crate) fn handle_invalid_instruction(&mut self, error: Error) -> Result<(), Error> {
+        let (pc, instruction) = match error {
+            Error::InvalidInstruction { pc, instruction } if pc == self.pc().to_u64() => {
+                (pc, instruction)
+            }
+            _ => return Err(error),
+        };
+        let action = match &mut self.trap_handler {
+            Some(handler) => handler.invalid_instruction(&mut self.inner, pc, instruction),
+            None => return Err(error),
+        };
+        let target = match action {
+            InvalidInstructionAction::Trap(e) => return Err(e),
+            InvalidInstructionAction::Skip(length) => pc.wrapping_add(u64::from(length)),
+            InvalidInstructionAction::Jump(target) => target,
+            InvalidInstructionAction::GuestTrap => {
+                raise_exception(
+                    self,
+                    CAUSE_ILLEGAL_INSTRUCTION,
+                    u64::from(instruction),
+                    error,
+                )?;
+                self.commit_pc();
+                return Ok(());
+            }
+        };
+        self.update_pc(Inner::REG::from_u64(target));
+        self.commit_pc();
+        Ok(())
+    }
+
+    // Returns the instructions decoded ahead of time for the loaded program.
+    pub fn predecoded(&self) -> Option<&PredecodedCode> {
+        self.predecoded.as_deref()
+    }
+
+    // Decodes the executable segments of the loaded program, as they are in
+    // memory, with a pool of predecode_workers threads.
+    #[cfg(feature = "elf")]
+    fn predecode(&mut self, program: &Bytes, offset: u64) -> Result<PredecodedCode, Error> {
+        let memory_size = self.memory().memory_size() as u64;
+        let mut ranges = vec![];
+        for (start, size) in elf_adaptor::executable_segments(program)? {
+            let start = start.wrapping_add(offset);
+            let end = start.saturating_add(size).min(memory_size);
+            if start < end {
+                ranges.push((start, self.memory_mut().load_bytes(start, end - start)?));
+            }
+        }
+        let decoder = self.build_decoder();
+        PredecodedCode::decode(&decoder, &ranges, self.predecode_workers)
+    }
+
+    // Same as predecode, going through the artifact cache when there is one.
+    #[cfg(feature = "elf")]
+    fn predecode_cached(&mut self, program: &Bytes, offset: u64) -> Result<PredecodedCode, Error> {
+        let key = match &self.artifact_cache {
+            Some(_) => artifact::ArtifactKey {
+                program_hash: super::hash::program_hash(program),
+                version: self.version(),
+                isa: self.isa(),
+                offset,
+            },
+            None => return self.predecode(program, offset),
+        };
+        let cached = self
+            .artifact_cache
+            .as_ref()
+            .and_then(|cache| cache.load(&key))
+            .and_then(|data| artifact::decode_artifact(&data, &key).ok());
+        if let Some(code) = cached {
+            return Ok(code);
+        }
+        let code = self.predecode(program, offset)?;
+        if let Some(cache) = &self.artifact_cache {
+            // The artifact is only an optimization, the run goes on without it.
+            let _ = cache.store(&key, &artifact::encode_artifact(&code, &key));
+        }
+        Ok(code)
+    }
+
+    pub fn metrics_sink(&mut self) -> Option<&mut (dyn MetricsSink + 'static)> {
+        self.metrics.as_deref_mut()
+    }
+
+    pub fn timing_model(&mut self) -> Option<&mut (dyn TimingModel + 'static)> {
+        self.timing_model.as_deref_mut()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    // Tells the profiler, if any, that the block of `code` at `address` is
+    // about to run.
+    pub(crate) fn profile_block(&mut self, address: u64, code: &[Instruction]) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter_block(address, code);
+        }
+    }
+
+    // Tells the profiler, if any, that `instruction` at `pc` retired.
+    pub(crate) fn profile_retire(&mut self, pc: u64, instruction: Instruction) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.retire(pc, instruction, (self.instruction_cycle_func)(instruction));
+        }
+    }
+
+    // Applies pending adjustments from the cycles budget, this is called by
+    // the run loops at each safe point.
+    pub(crate) fn apply_cycles_budget(&mut self) {
+        if let Some(budget) = &self.cycles_budget {
+            budget.apply(&mut self.inner);
+        }
+    }
+
+    // Notifies the start of a run, and returns the cycles it starts at, see
+    // report_metrics.
+    pub(crate) fn start_run(&mut self) -> u64 {
+        let cycles = self.cycles();
+        if self.has_observer() {
+            self.notify(&Event::RunStarted { cycles });
+        }
+        cycles
+    }
+
+    // Reports the result of a run started at `start_cycles` to the metrics
+    // sink if there is one.
+    pub(crate) fn report_metrics(&mut self, start_cycles: u64, result: &Result<i8, Error>) {
+        let cycles = self.cycles().saturating_sub(start_cycles);
+        if let Some(metrics) = &mut self.metrics {
+            report_run(metrics.as_mut(), cycles, result);
+        }
+        if self.has_observer() {
+            let event = run_event(self.cycles(), result);
+            self.notify(&event);
+        }
+    }
+
     // This is the most naive way of running the VM, it only decodes each
     // instruction and run it, no optimization is performed here. It might
     // not be practical in production, but it serves as a baseline and
     // reference implementation
     pub fn run(&mut self) -> Result<i8, Error> {
+        let start_cycles = self.start_run();
+        let result = self.run_inner(|_, _| true);
+        self.report_metrics(start_cycles, &result);
+        result
+    }
+
+    // Same as run, but returns a report with statistics of this run. Per
+    // opcode statistics are only collected when `opcode_stats` is true.
+    pub fn run_with_report(&mut self, opcode_stats: bool) -> ExecutionReport {
+        let start_cycles = self.start_run();
+        let mut collector = ReportCollector::new(self, start_cycles, opcode_stats);
+        let result = self.run_inner(|i, pc| {
+            collector.retire(i, pc);
+            true
+        });
+        self.report_metrics(start_cycles, &result);
+        collector.finish(result, self)
+    }
+
+    // Runs the machine until PC reaches `address`, or the program exits.
+    // Ok(None) is returned in the former case, the machine is left in a
+    // resumable state, calling run or run_until again continues the execution.
+    // At least one instruction is executed before PC is checked.
+    pub fn run_until(&mut self, address: u64) -> Result<Option<i8>, Error> {
+        let start_cycles = self.start_run();
+        let mut reached = false;
+        let result = self.run_inner(|_, pc| {
+            reached = pc == address;
+            !reached
+        });
+        self.report_metrics(start_cycles, &result);
+        let exit_code = result?;
+        if reached {
+            Ok(None)
+        } else {
+            Ok(Some(exit_code))
+        }
+    }
+
+    // `on_retire` is called with each executed instruction and the PC after
+    // it, execution is paused when false is returned. run passes a closure
+    // always returning true so there is no cost. Instructions are decoded and
+    // charged a basic block at a time, with the same resulting cycles as
+    // charging them one by one.
+    fn run_inner<F: FnMut(Instruction, u64) -> bool>(&mut self, on_retire: F) -> Result<i8, Error> {
         if self.isa() & ISA_MOP != 0 && self.version() == VERSION0 {
             return Err(Error::InvalidVersion);
         }
-        let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+        let mut decoder = self.build_decoder();
+        // The block buffer is taken out of the arena while running, so that
+        // it can be borrowed along with the machine.
+        let mut block = std::mem::take(&mut self.arena.instructions);
+        block.reserve(MAX_BLOCK_LENGTH);
+        let result = self.run_blocks(&mut decoder, &mut block, on_retire);
+        self.arena.instructions = block;
+        result
+    }
+
+    fn run_blocks<F: FnMut(Instruction, u64) -> bool>(
+        &mut self,
+        decoder: &mut Decoder,
+        block: &mut Vec<Instruction>,
+        mut on_retire: F,
+    ) -> Result<i8, Error> {
         self.set_running(true);
         while self.running() {
             if self.reset_signal() {
                 decoder.reset_instructions_cache();
             }
-            self.step(&mut decoder)?;
+            self.apply_cycles_budget();
+            let (block_cycles, decode_error) = self.decode_block(decoder, block);
+            let charged = self.charge_block(block_cycles);
+            let mut trapped = false;
+            let address = self.pc().to_u64();
+            self.profile_block(address, block);
+            for (index, instruction) in block.iter().enumerate() {
+                let pc = self.pc().to_u64();
+                if !charged {
+                    let cycles = self.instruction_cycle_func()(*instruction);
+                    self.add_cycles(cycles)
+                        .map_err(|e| self.error_with_context(e, pc, *instruction))?;
+                }
+                let result =
+                    execute(*instruction, self).and_then(|_| match &mut self.loop_detector {
+                        Some(detector) => detector.retire(pc, *instruction, self.inner.registers()),
+                        None => Ok(()),
+                    });
+                if let Err(e) = result {
+                    if charged {
+                        self.refund_block(&block[index + 1..]);
+                    }
+                    if !self.trap_memory_fault(*instruction, &e) {
+                        return Err(self.error_with_context(e, pc, *instruction));
+                    }
+                    trapped = true;
+                    break;
+                }
+                self.instret = self.instret.wrapping_add(1);
+                self.profile_retire(pc, *instruction);
+                if !on_retire(*instruction, self.pc().to_u64()) {
+                    if charged {
+                        self.refund_block(&block[index + 1..]);
+                    }
+                    return Ok(self.exit_code());
+                }
+            }
+            // The rest of the block is skipped by a trap, so is its decoding
+            // error.
+            if let Some(e) = decode_error.filter(|_| !trapped) {
+                self.handle_invalid_instruction(e)?;
+            }
+            if self.running() {
+                self.poll_interrupts()?;
+            }
         }
         Ok(self.exit_code())
     }
 
+    // Decodes the basic block at PC into `block`, and returns its cycles. A
+    // decoding error is returned along with the instructions before it, and
+    // is raised once these instructions ran.
+    fn decode_block(
+        &mut self,
+        decoder: &mut Decoder,
+        block: &mut Vec<Instruction>,
+    ) -> (u64, Option<Error>) {
+        block.clear();
+        let mut pc = self.pc().to_u64();
+        let mut cycles: u64 = 0;
+        while block.len() < MAX_BLOCK_LENGTH {
+            let instruction = match decoder.decode(self.memory_mut(), pc) {
+                Ok(instruction) => instruction,
+                Err(e) => return (cycles, Some(e)),
+            };
+            block.push(instruction);
+            cycles = cycles.saturating_add(self.instruction_cycle_func()(instruction));
+            if is_basic_block_end_instruction(instruction) {
+                break;
+            }
+            pc = pc.wrapping_add(u64::from(instruction_length(instruction)));
+        }
+        (cycles, None)
+    }
+
+    // Charges the cycles of a whole basic block up front, so that the
+    // instructions in it run without checking cycles one by one. Returns
+    // false without charging anything when the block does not fit in the
+    // remaining cycles, the block is then charged per instruction, so the
+    // instruction exceeding max cycles is still the one reported. Since a
+    // basic block ends at the first ECALL, EBREAK or CSR instruction,
+    // syscalls and reads of the cycle CSR see the same cycles either way:
+    // those of every instruction up to and including themselves.
+    pub(crate) fn charge_block(&mut self, cycles: u64) -> bool {
+        match self.cycles().checked_add(cycles) {
+            Some(cycles) if cycles <= self.max_cycles() => {
+                self.set_cycles(cycles);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Refunds the cycles of the instructions of a charged block which did not
+    // run, when the block stopped early on an error or a pause.
+    pub(crate) fn refund_block(&mut self, instructions: &[Instruction]) {
+        let cycle_func = self.instruction_cycle_func();
+        let cycles = instructions
+            .iter()
+            .fold(0u64, |sum, i| sum.saturating_add(cycle_func(*i)));
+        self.set_cycles(self.cycles().saturating_sub(cycles));
+    }
+
     pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
+        self.step_instruction(decoder).map(|_| ())
+    }
+
+    // Executes one instruction, and returns the executed instruction.
+    fn step_instruction(&mut self, decoder: &mut Decoder) -> Result<Instruction, Error> {
         let instruction = {
             let pc = self.pc().to_u64();
             let memory = self.memory_mut();
             decoder.decode(memory, pc)?
         };
+        let pc = self.pc().to_u64();
         let cycles = self.instruction_cycle_func()(instruction);
-        self.add_cycles(cycles)?;
-        execute(instruction, self)
+        self.add_cycles(cycles)
+            .map_err(|e| self.error_with_context(e, pc, instruction))?;
+        self.profile_block(pc, &[instruction]);
+        execute(instruction, self).map_err(|e| self.error_with_context(e, pc, instruction))?;
+        self.instret = self.instret.wrapping_add(1);
+        self.profile_retire(pc, instruction);
+        Ok(instruction)
     }
 }
 
@@ -636,7 +1826,26 @@ pub struct DefaultMachineBuilder<Inner> {
     inner: Inner,
     instruction_cycle_func: Box<InstructionCycleFunc>,
     debugger: Option<Box<dyn Debugger<Inner>>>,
-    syscalls: Vec<Box<dyn Syscalls<Inner>>>,
+    // Sorted by priority in descending order.
+    syscalls: Vec<(i32, Box<dyn Syscalls<Inner>>)>,
+    trap_handler: Option<Box<dyn TrapHandler<Inner>>>,
+    metrics: Option<Box<dyn MetricsSink>>,
+    cycles_budget: Option<CyclesBudget>,
+    observer: Option<Box<dyn EventObserver>>,
+    loop_detector: Option<LoopDetector>,
+    arena: Option<Arena>,
+    trace_cache: TraceCacheConfig,
+    predecode_workers: usize,
+    artifact_cache: Option<Box<dyn ArtifactCache>>,
+    load_policy: Option<Box<dyn LoadPolicy>>,
+    guest_memory_faults: bool,
+    error_context: bool,
+    layout: AddressSpaceLayout,
+    denied_opcodes: Vec<InstructionOpcode>,
+    long_instruction_factories: Vec<LongInstructionFactory>,
+    timing_model: Option<Box<dyn TimingModel>>,
+    profiler: Option<Profiler>,
+    csr_provider: Option<Box<dyn CsrProvider<Inner>>>,
 }
 
 impl<Inner> DefaultMachineBuilder<Inner> {
@@ -646,6 +1855,24 @@ impl<Inner> DefaultMachineBuilder<Inner> {
             instruction_cycle_func: Box::new(|_| 0),
             debugger: None,
             syscalls: vec![],
+            trap_handler: None,
+            metrics: None,
+            cycles_budget: None,
+            observer: None,
+            loop_detector: None,
+            arena: None,
+            trace_cache: TraceCacheConfig::default(),
+            predecode_workers: 0,
+            artifact_cache: None,
+            load_policy: None,
+            guest_memory_faults: false,
+            error_context: false,
+            layout: AddressSpaceLayout::default(),
+            denied_opcodes: vec![],
+            long_instruction_factories: vec![],
+            timing_model: None,
+            profiler: None,
+            csr_provider: None,
         }
     }
 
@@ -657,8 +1884,18 @@ impl<Inner> DefaultMachineBuilder<Inner> {
         self
     }
 
-    pub fn syscall(mut self, syscall: Box<dyn Syscalls<Inner>>) -> Self {
-        self.syscalls.push(syscall);
+    pub fn syscall(self, syscall: Box<dyn Syscalls<Inner>>) -> Self {
+        self.syscall_with_priority(DEFAULT_SYSCALL_PRIORITY, syscall)
+    }
+
+    // Syscall modules with higher priority are dispatched first, so a module
+    // can override the syscalls handled by modules with a lower priority.
+    pub fn syscall_with_priority(
+        mut self,
+        priority: i32,
+        syscall: Box<dyn Syscalls<Inner>>,
+    ) -> Self {
+        insert_syscalls(&mut self.syscalls, priority, syscall);
         self
     }
 
@@ -667,13 +1904,177 @@ impl<Inner> DefaultMachineBuilder<Inner> {
         self
     }
 
+    pub fn trap_handler(mut self, trap_handler: Box<dyn TrapHandler<Inner>>) -> Self {
+        self.trap_handler = Some(trap_handler);
+        self
+    }
+
+    pub fn metrics_sink(mut self, metrics: Box<dyn MetricsSink>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn cycles_budget(mut self, cycles_budget: CyclesBudget) -> Self {
+        self.cycles_budget = Some(cycles_budget);
+        self
+    }
+
+    pub fn observer(mut self, observer: Box<dyn EventObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    // Stops programs stuck in a loop without progress, see LoopDetector.
+    // Only the interpreter loops of DefaultMachine check for loops.
+    pub fn loop_detector(mut self, options: LoopDetectorOptions) -> Self {
+        self.loop_detector = Some(LoopDetector::new(options));
+        self
+    }
+
+    // Reuses the buffers of a previous machine, see Arena.
+    pub fn arena(mut self, arena: Arena) -> Self {
+        self.arena = Some(arena);
+        self
+    }
+
+    // Sizes the trace cache of TraceMachine, see TraceCacheConfig.
+    pub fn trace_cache(mut self, config: TraceCacheConfig) -> Self {
+        self.trace_cache = config;
+        self
+    }
+
+    // Decodes all executable segments with `workers` threads right after the
+    // program is loaded, so running it doesn't stall on decoding. The
+    // program must not modify its code after loading.
+    pub fn predecode(mut self, workers: usize) -> Self {
+        self.predecode_workers = workers;
+        self
+    }
+
+    // Reuses the instructions predecoded by earlier machines for the same
+    // program, stored in `cache`. Only used together with predecode, an
+    // artifact failing validation is replaced by a freshly decoded one.
+    pub fn artifact_cache(mut self, cache: Box<dyn ArtifactCache>) -> Self {
+        self.artifact_cache = Some(cache);
+        self
+    }
+
+    // Checks programs against `policy` before loading them, see LoadPolicy.
+    pub fn load_policy(mut self, policy: Box<dyn LoadPolicy>) -> Self {
+        self.load_policy = Some(policy);
+        self
+    }
+
+    // Estimates hardware cycles in run_with_report, see TimingModel.
+    pub fn timing_model(mut self, timing_model: Box<dyn TimingModel>) -> Self {
+        self.timing_model = Some(timing_model);
+        self
+    }
+
+    // Counts executions per opcode, PC region and basic block in the
+    // interpreter loops, see Profiler.
+    pub fn profiler(mut self, options: ProfilerOptions) -> Self {
+        self.profiler = Some(Profiler::new(options));
+        self
+    }
+
+    // Handles Zicsr accesses to CSRs the machine doesn't implement itself,
+    // instead of CounterCsrs. Requires ISA_ZICSR.
+    pub fn csr_provider(mut self, csr_provider: Box<dyn CsrProvider<Inner>>) -> Self {
+        self.csr_provider = Some(csr_provider);
+        self
+    }
+
+    // Delivers memory faults of loads, stores and AMOs to the guest as
+    // access fault or misaligned exceptions, instead of returning the memory
+    // error. Requires ISA_PRIV, and only applies once the guest installed a
+    // trap vector, see Privileged.
+    pub fn guest_memory_faults(mut self, enabled: bool) -> Self {
+        self.guest_memory_faults = enabled;
+        self
+    }
+
+    // Wraps errors of failing instructions in Error::Context, telling the PC,
+    // opcode, accessed address and cycles of the failure. Off by default, so
+    // errors keep comparing equal to their bare variants. Error::root returns
+    // the wrapped error. Only applies to the interpreter loops.
+    pub fn error_context(mut self, enabled: bool) -> Self {
+        self.error_context = enabled;
+        self
+    }
+
+    pub fn layout(mut self, layout: AddressSpaceLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    // Makes the decoder reject instructions with any of `opcodes` with
+    // Error::DeniedInstruction, before they execute. Fused opcodes of
+    // ISA_MOP can be denied as well, e.g. all atomics and ADC:
+    //
+    // builder
+    //     .deny_opcodes(insts::OP_LR_W..=insts::OP_AMOMAXU_D)
+    //     .deny_opcodes([insts::OP_ADC])
+    pub fn deny_opcodes<I: IntoIterator<Item = InstructionOpcode>>(mut self, opcodes: I) -> Self {
+        self.denied_opcodes.extend(opcodes);
+        self
+    }
+
+    // Decodes 48-bit and 64-bit instructions with `factory`, see
+    // Decoder::add_long_instruction_factory. Without a factory, long
+    // encodings are invalid instructions.
+    pub fn long_instruction_factory(mut self, factory: LongInstructionFactory) -> Self {
+        self.long_instruction_factories.push(factory);
+        self
+    }
+
     pub fn build(self) -> DefaultMachine<Inner> {
+        let arena = match self.arena {
+            Some(mut arena) => {
+                arena.reset();
+                arena
+            }
+            None => Arena::new(),
+        };
         DefaultMachine {
             inner: self.inner,
             instruction_cycle_func: self.instruction_cycle_func,
             debugger: self.debugger,
             syscalls: self.syscalls,
+            trap_handler: self.trap_handler,
+            metrics: self.metrics,
+            cycles_budget: self.cycles_budget,
+            observer: self.observer,
+            loop_detector: self.loop_detector,
+            arena,
+            trace_cache: self.trace_cache,
+            compressed: true,
+            predecode_workers: self.predecode_workers,
+            predecoded: None,
+            artifact_cache: self.artifact_cache,
+            load_policy: self.load_policy,
+            privileged: Privileged::default(),
+            guest_memory_faults: self.guest_memory_faults,
+            error_context: self.error_context,
+            layout: self.layout,
+            loaded_layout: None,
+            denied_opcodes: self.denied_opcodes,
+            long_instruction_factories: self.long_instruction_factories,
+            code_segments: vec![],
+            program_info: None,
+            syscall_stats: None,
+            timing_model: self.timing_model,
+            profiler: self.profiler,
+            csr_provider: self.csr_provider,
+            instret: 0,
             exit_code: 0,
         }
     }
 }
+
+impl<Inner: SupportMachine> DefaultMachineBuilder<Inner> {
+    // Returns registered syscall modules in the order they are dispatched.
+    pub fn dispatch_order(&self) -> Vec<SyscallsInfo> {
+        dispatch_order(&self.syscalls)
+    }
+}
@@ -0,0 +1,84 @@
+use super::super::hash::ProgramHash;
+use super::super::Error;
+use std::collections::HashSet;
+
+// LoadPolicy decides which programs a machine may load, for environments
+// which only execute audited code. It is consulted by load_program and
+// reload_program with the fingerprint of the program, see program_hash,
+// before any memory is initialized. Closures taking the hash and the
+// program, and returning whether it may run, are policies too:
+//
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .load_policy(Box::new(AllowList::new(audited_hashes)))
+//     .build();
+// assert!(matches!(
+//     machine.load_program(&unknown_program, &args),
+//     Err(Error::ProgramNotAllowed(_))
+// ));
+//
+// Loading directly through load_elf bypasses the policy.
+pub trait LoadPolicy: Send + Sync {
+    fn allows(&self, hash: &ProgramHash, program: &[u8]) -> bool;
+}
+
+impl<F> LoadPolicy for F
+where
+    F: Fn(&ProgramHash, &[u8]) -> bool + Send + Sync,
+{
+    fn allows(&self, hash: &ProgramHash, program: &[u8]) -> bool {
+        self(hash, program)
+    }
+}
+
+// Only allows the listed programs.
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    hashes: HashSet<ProgramHash>,
+}
+
+impl AllowList {
+    pub fn new<I: IntoIterator<Item = ProgramHash>>(hashes: I) -> Self {
+        Self {
+            hashes: hashes.into_iter().collect(),
+        }
+    }
+
+    pub fn insert(&mut self, hash: ProgramHash) {
+        self.hashes.insert(hash);
+    }
+}
+
+impl LoadPolicy for AllowList {
+    fn allows(&self, hash: &ProgramHash, _program: &[u8]) -> bool {
+        self.hashes.contains(hash)
+    }
+}
+
+// Allows every program but the listed ones.
+#[derive(Debug, Clone, Default)]
+pub struct DenyList {
+    hashes: HashSet<ProgramHash>,
+}
+
+impl DenyList {
+    pub fn new<I: IntoIterator<Item = ProgramHash>>(hashes: I) -> Self {
+        Self {
+            hashes: hashes.into_iter().collect(),
+        }
+    }
+
+    pub fn insert(&mut self, hash: ProgramHash) {
+        self.hashes.insert(hash);
+    }
+}
+
+impl LoadPolicy for DenyList {
+    fn allows(&self, hash: &ProgramHash, _program: &[u8]) -> bool {
+        !self.hashes.contains(hash)
+    }
+}
+
+// Returns the error of a program rejected by a policy.
+pub(crate) fn not_allowed(hash: &ProgramHash) -> Error {
+    Error::ProgramNotAllowed(hash.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
@@ -0,0 +1,333 @@
+use super::instrumented::{memory_access, MemoryAccessKind};
+use super::Machine;
+use crate::devices::highest_priority_interrupt;
+use crate::instructions::{extract_opcode, insts, Instruction};
+use crate::{Error, Memory, Register};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Machine mode CSRs.
+pub const CSR_MSTATUS: u16 = 0x300;
+pub const CSR_MISA: u16 = 0x301;
+pub const CSR_MIE: u16 = 0x304;
+pub const CSR_MTVEC: u16 = 0x305;
+pub const CSR_MSCRATCH: u16 = 0x340;
+pub const CSR_MEPC: u16 = 0x341;
+pub const CSR_MCAUSE: u16 = 0x342;
+pub const CSR_MTVAL: u16 = 0x343;
+pub const CSR_MIP: u16 = 0x344;
+pub const CSR_MHARTID: u16 = 0xf14;
+
+// Fields of mstatus, other fields are hardwired to zero.
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_MPP_SHIFT: u64 = 11;
+pub const MSTATUS_MPP: u64 = 0b11 << MSTATUS_MPP_SHIFT;
+
+// Exception codes of mcause, interrupts also have the highest bit set.
+pub const CAUSE_ILLEGAL_INSTRUCTION: u64 = 2;
+pub const CAUSE_BREAKPOINT: u64 = 3;
+pub const CAUSE_LOAD_ADDRESS_MISALIGNED: u64 = 4;
+pub const CAUSE_LOAD_ACCESS_FAULT: u64 = 5;
+pub const CAUSE_STORE_ADDRESS_MISALIGNED: u64 = 6;
+pub const CAUSE_STORE_ACCESS_FAULT: u64 = 7;
+pub const CAUSE_USER_ECALL: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PrivilegeMode {
+    User = 0,
+    Machine = 3,
+}
+
+// Privileged state of a hart supporting machine and user modes, enabled by
+// ISA_PRIV. Machines start in machine mode. Traps are only taken by the
+// guest once it has installed a trap vector in mtvec, until then they keep
+// their usual host behavior: ECALL goes to syscalls, EBREAK to the debugger,
+// interrupts to the TrapHandler, and invalid CSR accesses are errors. ECALL
+// and EBREAK from machine mode always keep their host behavior, so a guest
+// kernel can still use syscalls. Memory faults may also be delivered to the
+// guest, see DefaultMachineBuilder::guest_memory_faults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Privileged {
+    mode: PrivilegeMode,
+    mstatus: u64,
+    mie: u64,
+    // Interrupts pending in memory mapped devices, read only for the guest.
+    mip: u64,
+    mtvec: u64,
+    mscratch: u64,
+    mepc: u64,
+    mcause: u64,
+    mtval: u64,
+}
+
+impl Default for Privileged {
+    fn default() -> Self {
+        Self {
+            mode: PrivilegeMode::Machine,
+            mstatus: 0,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+        }
+    }
+}
+
+impl Privileged {
+    pub fn mode(&self) -> PrivilegeMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PrivilegeMode) {
+        self.mode = mode;
+    }
+
+    // Mode followed by the CSRs, used to seal snapshots.
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn to_words(&self) -> [u64; 9] {
+        [
+            self.mode as u64,
+            self.mstatus,
+            self.mie,
+            self.mip,
+            self.mtvec,
+            self.mscratch,
+            self.mepc,
+            self.mcause,
+            self.mtval,
+        ]
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn from_words(words: [u64; 9]) -> Option<Self> {
+        let mode = match words[0] {
+            0 => PrivilegeMode::User,
+            3 => PrivilegeMode::Machine,
+            _ => return None,
+        };
+        Some(Self {
+            mode,
+            mstatus: words[1],
+            mie: words[2],
+            mip: words[3],
+            mtvec: words[4],
+            mscratch: words[5],
+            mepc: words[6],
+            mcause: words[7],
+            mtval: words[8],
+        })
+    }
+
+    // Whether the guest installed a trap vector.
+    pub fn trap_installed(&self) -> bool {
+        self.mtvec & !0b11 != 0
+    }
+
+    // Returns the value of `csr`, None if it does not exist or is not
+    // accessible from the current mode.
+    pub fn read_csr(&self, csr: u16) -> Option<u64> {
+        // Bits 9:8 of a CSR number hold the lowest mode allowed to access it.
+        if (csr >> 8) & 0b11 > self.mode as u16 {
+            return None;
+        }
+        let value = match csr {
+            CSR_MSTATUS => self.mstatus,
+            // An empty misa is allowed, the ISA is then found elsewhere.
+            CSR_MISA => 0,
+            CSR_MIE => self.mie,
+            CSR_MTVEC => self.mtvec,
+            CSR_MSCRATCH => self.mscratch,
+            CSR_MEPC => self.mepc,
+            CSR_MCAUSE => self.mcause,
+            CSR_MTVAL => self.mtval,
+            CSR_MIP => self.mip,
+            CSR_MHARTID => 0,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Writes `csr`, fields which are not writable keep their value. Returns
+    // None if the CSR is read only, see read_csr for other failures.
+    pub fn write_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        self.read_csr(csr)?;
+        // Bits 11:10 set means a read only CSR.
+        if (csr >> 10) & 0b11 == 0b11 {
+            return None;
+        }
+        match csr {
+            CSR_MSTATUS => {
+                let mut mstatus = value & (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+                // Only user and machine modes exist.
+                if (mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT != PrivilegeMode::Machine as u64 {
+                    mstatus &= !MSTATUS_MPP;
+                }
+                self.mstatus = mstatus;
+            }
+            CSR_MIE => self.mie = value,
+            CSR_MTVEC => {
+                // Direct and vectored modes only.
+                self.mtvec = if value & 0b11 > 1 {
+                    value & !0b11
+                } else {
+                    value
+                };
+            }
+            CSR_MSCRATCH => self.mscratch = value,
+            CSR_MEPC => self.mepc = value & !1,
+            CSR_MCAUSE => self.mcause = value,
+            CSR_MTVAL => self.mtval = value,
+            _ => (),
+        }
+        Some(())
+    }
+
+    // Updates the interrupts pending in devices, see Memory::pending_interrupts.
+    pub fn set_pending_interrupts(&mut self, pending: u64) {
+        self.mip = pending;
+    }
+
+    // Returns the interrupt to take before the next instruction, if any.
+    pub fn interrupt_to_take(&self) -> Option<u64> {
+        if self.mode == PrivilegeMode::Machine && self.mstatus & MSTATUS_MIE == 0 {
+            return None;
+        }
+        highest_priority_interrupt(self.mip & self.mie)
+    }
+
+    // Enters machine mode to handle trap `cause` at `pc`, and returns the
+    // address of the trap handler. `xlen` is the register width.
+    pub fn enter_trap(&mut self, cause: u64, pc: u64, tval: u64, xlen: u8) -> u64 {
+        let interrupt = cause >> (xlen - 1) != 0;
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = tval;
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+        self.mstatus |= (self.mode as u64) << MSTATUS_MPP_SHIFT;
+        self.mode = PrivilegeMode::Machine;
+        let base = self.mtvec & !0b11;
+        if interrupt && self.mtvec & 0b11 == 1 {
+            base.wrapping_add(4 * (cause & !(1 << (xlen - 1))))
+        } else {
+            base
+        }
+    }
+
+    // Returns from a machine mode trap handler, and returns the address to
+    // resume at. None in user mode.
+    pub fn mret(&mut self) -> Option<u64> {
+        if self.mode != PrivilegeMode::Machine {
+            return None;
+        }
+        self.mode = if self.mstatus & MSTATUS_MPP == 0 {
+            PrivilegeMode::User
+        } else {
+            PrivilegeMode::Machine
+        };
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus |= MSTATUS_MPIE;
+        Some(self.mepc)
+    }
+}
+
+// A set of CSRs accessed by the Zicsr instructions. Both methods return None
+// for CSRs which do not exist or may not be accessed, writes also fail on
+// read only CSRs.
+pub trait CsrFile {
+    fn read_csr(&self, csr: u16) -> Option<u64>;
+    fn write_csr(&mut self, csr: u16, value: u64) -> Option<()>;
+}
+
+impl CsrFile for Privileged {
+    fn read_csr(&self, csr: u16) -> Option<u64> {
+        Privileged::read_csr(self, csr)
+    }
+
+    fn write_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        Privileged::write_csr(self, csr, value)
+    }
+}
+
+// Returns mcause of interrupt `irq` for registers of `xlen` bits.
+pub fn interrupt_cause(irq: u64, xlen: u8) -> u64 {
+    (1 << (xlen - 1)) | irq
+}
+
+// Takes exception `cause` at the current instruction, if the guest installed
+// a trap vector. Otherwise `error` is returned.
+pub fn raise_exception<Mac: Machine>(
+    machine: &mut Mac,
+    cause: u64,
+    tval: u64,
+    error: Error,
+) -> Result<(), Error> {
+    let pc = machine.pc().to_u64();
+    if enter_guest_trap(machine, cause, pc, tval) {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+// Enters the trap vector installed by the guest for `cause` raised at `pc`,
+// the common path of all traps taken in the guest. Like ECALL and EBREAK,
+// traps drop the LR/SC reservation, see Memory::trap. Returns false when the
+// guest installed no trap vector.
+pub fn enter_guest_trap<Mac: Machine>(machine: &mut Mac, cause: u64, pc: u64, tval: u64) -> bool {
+    let target = match machine.privileged_mut() {
+        Some(state) if state.trap_installed() => state.enter_trap(cause, pc, tval, Mac::REG::BITS),
+        _ => return false,
+    };
+    machine.memory_mut().trap();
+    machine.update_pc(Mac::REG::from_u64(target));
+    true
+}
+
+// Returns mcause and mtval of the exception raised when memory instruction
+// `inst` fails with `error`, None if `error` is not a memory fault. Faulting
+// instructions do not write rd, except AMOs failing on the store, so
+// `registers` still hold the operands. AMOs report store faults.
+pub fn memory_fault<R: Register>(
+    inst: Instruction,
+    registers: &[R],
+    error: &Error,
+) -> Option<(u64, u64)> {
+    let misaligned = match error {
+        Error::MemUnalignedAccess => true,
+        Error::MemOutOfBound
+        | Error::MemPageUnalignedAccess
+        | Error::MemWriteOnExecutablePage
+        | Error::MemWriteOnFreezedPage => false,
+        _ => return None,
+    };
+    let access = memory_access(inst, registers)?;
+    // LR only loads.
+    let store = match access.kind {
+        MemoryAccessKind::Load => false,
+        MemoryAccessKind::Store => true,
+        MemoryAccessKind::Atomic => {
+            !matches!(extract_opcode(inst), insts::OP_LR_W | insts::OP_LR_D)
+        }
+    };
+    let cause = match (store, misaligned) {
+        (false, false) => CAUSE_LOAD_ACCESS_FAULT,
+        (false, true) => CAUSE_LOAD_ADDRESS_MISALIGNED,
+        (true, false) => CAUSE_STORE_ACCESS_FAULT,
+        (true, true) => CAUSE_STORE_ADDRESS_MISALIGNED,
+    };
+    Some((cause, access.address))
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use ckb_vm_definitions::disassembler::disassemble;
+
+use super::super::{
+    instructions::{
+        extract_opcode, instruction_length, instruction_opcode_name, Instruction, InstructionOpcode,
+    },
+    Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfilerOptions {
+    // Size of the PC regions executions are counted by, rounded up to a
+    // power of two.
+    pub region_size: u64,
+}
+
+impl Default for ProfilerOptions {
+    fn default() -> Self {
+        Self { region_size: 256 }
+    }
+}
+
+// A basic block as run by the interpreter loops, see Profiler::hot_blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotBlock {
+    pub address: u64,
+    // Times the block was entered.
+    pub executions: u64,
+    // Instructions retired and cycles charged in the block, over all
+    // executions. A block left early, e.g. on a trap, only counts what ran.
+    pub instructions: u64,
+    pub cycles: u64,
+    // (address, assembly) of each instruction of the block.
+    pub code: Vec<(u64, String)>,
+}
+
+#[derive(Default)]
+struct BlockProfile {
+    executions: u64,
+    instructions: u64,
+    cycles: u64,
+    code: Vec<Instruction>,
+}
+
+// Profiler counts the instructions run by a DefaultMachine per opcode, per
+// PC region and per basic block, to find the guest code worth optimizing
+// and the opcodes worth pricing carefully:
+//
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .profiler(ProfilerOptions::default())
+//     .build();
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// machine.profiler().unwrap().write(&mut std::io::stdout(), 10)?;
+//
+// Blocks are the ones the interpreter loops decode and charge at once,
+// ending at the first branch, jump or system instruction. Instructions run
+// one at a time by step are counted as blocks of their own. Cycles are the
+// ones of instruction_cycle_func, syscalls charging cycles are left out.
+// The asm machine runs without the profiler.
+pub struct Profiler {
+    region_shift: u32,
+    instructions: u64,
+    cycles: u64,
+    opcodes: HashMap<InstructionOpcode, (u64, u64)>,
+    regions: HashMap<u64, u64>,
+    blocks: HashMap<u64, BlockProfile>,
+    // Address of the block being run.
+    block: Option<u64>,
+}
+
+impl Profiler {
+    pub fn new(options: ProfilerOptions) -> Self {
+        Self {
+            region_shift: options
+                .region_size
+                .max(1)
+                .next_power_of_two()
+                .trailing_zeros(),
+            instructions: 0,
+            cycles: 0,
+            opcodes: HashMap::new(),
+            regions: HashMap::new(),
+            blocks: HashMap::new(),
+            block: None,
+        }
+    }
+
+    // Called by the run loops before running the block of `code` at
+    // `address`.
+    pub(crate) fn enter_block(&mut self, address: u64, code: &[Instruction]) {
+        let block = self.blocks.entry(address).or_default();
+        block.executions += 1;
+        // The code at an address changes when the program is reloaded.
+        if block.code != code {
+            block.code = code.to_vec();
+        }
+        self.block = Some(address);
+    }
+
+    // Called by the run loops after `instruction` at `pc` retired.
+    pub(crate) fn retire(&mut self, pc: u64, instruction: Instruction, cycles: u64) {
+        self.instructions += 1;
+        self.cycles = self.cycles.saturating_add(cycles);
+        let opcode = self
+            .opcodes
+            .entry(extract_opcode(instruction))
+            .or_insert((0, 0));
+        opcode.0 += 1;
+        opcode.1 = opcode.1.saturating_add(cycles);
+        *self.regions.entry(pc >> self.region_shift).or_insert(0) += 1;
+        if let Some(block) = self.block.and_then(|address| self.blocks.get_mut(&address)) {
+            block.instructions += 1;
+            block.cycles = block.cycles.saturating_add(cycles);
+        }
+    }
+
+    // Forgets all counts, e.g. to profile only part of a run.
+    pub fn clear(&mut self) {
+        self.instructions = 0;
+        self.cycles = 0;
+        self.opcodes.clear();
+        self.regions.clear();
+        self.blocks.clear();
+        self.block = None;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Returns (opcode, executions, cycles) by decreasing executions.
+    pub fn opcodes(&self) -> Vec<(InstructionOpcode, u64, u64)> {
+        let mut opcodes: Vec<_> = self
+            .opcodes
+            .iter()
+            .map(|(opcode, (executions, cycles))| (*opcode, *executions, *cycles))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        opcodes
+    }
+
+    // Returns (start, executions) of the PC regions by decreasing
+    // executions.
+    pub fn regions(&self) -> Vec<(u64, u64)> {
+        let mut regions: Vec<_> = self
+            .regions
+            .iter()
+            .map(|(region, executions)| (region << self.region_shift, *executions))
+            .collect();
+        regions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        regions
+    }
+
+    // Returns the `count` blocks taking the most cycles, hottest first.
+    pub fn hot_blocks(&self, count: usize) -> Vec<HotBlock> {
+        let mut blocks: Vec<_> = self.blocks.iter().collect();
+        blocks.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        blocks
+            .into_iter()
+            .take(count)
+            .map(|(address, block)| {
+                let mut pc = *address;
+                let code = block
+                    .code
+                    .iter()
+                    .map(|instruction| {
+                        let line = (pc, disassemble(*instruction));
+                        pc = pc.wrapping_add(u64::from(instruction_length(*instruction)));
+                        line
+                    })
+                    .collect();
+                HotBlock {
+                    address: *address,
+                    executions: block.executions,
+                    instructions: block.instructions,
+                    cycles: block.cycles,
+                    code,
+                }
+            })
+            .collect()
+    }
+
+    // Writes the `count` most run opcodes and regions, and the `count`
+    // hottest blocks with their code, as text.
+    pub fn write<W: Write>(&self, writer: &mut W, count: usize) -> Result<(), Error> {
+        let percent = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                part as f64 * 100.0 / total as f64
+            }
+        };
+        writeln!(
+            writer,
+            "instructions: {} cycles: {}",
+            self.instructions, self.cycles
+        )?;
+        writeln!(writer, "opcodes:")?;
+        for (opcode, executions, cycles) in self.opcodes().into_iter().take(count) {
+            writeln!(
+                writer,
+                "  {:<16} {:>12} {:>6.2}% {:>12} cycles",
+                instruction_opcode_name(opcode),
+                executions,
+                percent(executions, self.instructions),
+                cycles
+            )?;
+        }
+        writeln!(writer, "regions:")?;
+        for (start, executions) in self.regions().into_iter().take(count) {
+            writeln!(
+                writer,
+                "  0x{:<14x} {:>12} {:>6.2}%",
+                start,
+                executions,
+                percent(executions, self.instructions)
+            )?;
+        }
+        writeln!(writer, "blocks:")?;
+        for block in self.hot_blocks(count) {
+            writeln!(
+                writer,
+                "  0x{:x}: {} executions, {} instructions, {} cycles ({:.2}%)",
+                block.address,
+                block.executions,
+                block.instructions,
+                block.cycles,
+                percent(block.cycles, self.cycles)
+            )?;
+            for (pc, assembly) in &block.code {
+                writeln!(writer, "    0x{:x}: {}", pc, assembly)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+// Describes the program loaded in a machine. It is included in reports and
+// dumps, so logs from production nodes can be matched to the exact guest
+// binary, and to its debug symbols through the build ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramInfo {
+    // Entry point, moved with the program to its load base.
+    pub entry: u64,
+    // Size of the ELF file, in bytes.
+    pub size: u64,
+    // GNU build ID, see elf_adaptor::build_id. Replaced by reload_program.
+    pub build_id: Option<Vec<u8>>,
+    // Added to link addresses to get loaded addresses, see
+    // DefaultMachine::load_bias.
+    pub load_bias: u64,
+}
+
+impl ProgramInfo {
+    // Build ID as a lower case hex string, the form used by debuggers and
+    // symbol servers.
+    pub fn build_id_hex(&self) -> Option<String> {
+        self.build_id.as_ref().map(|id| build_id_hex(id))
+    }
+}
+
+pub fn build_id_hex(build_id: &[u8]) -> String {
+    build_id
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
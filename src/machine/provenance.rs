@@ -0,0 +1,125 @@
+use super::{
+    super::{
+        decoder::{build_decoder, DecodedInstruction},
+        instructions::Register,
+        registers::A0,
+        Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER,
+    },
+    instrumented::{memory_access, MemoryAccessKind},
+    rvfi::register_operands,
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use ckb_vm_definitions::instructions as insts;
+use std::collections::HashMap;
+
+// Granularity of memory provenance, in bytes. A store covering part of a
+// word is recorded as writing the whole word.
+pub const PROVENANCE_WORD_SIZE: u64 = 4;
+
+// Shadow state recording the PC of the instruction which last wrote each
+// register and each memory word, answering where a bad value came from once
+// execution stops, whether normally or with an error:
+//
+// let mut tracker = ProvenanceTracker::default();
+// let result = run_provenance(&mut machine, &mut tracker);
+// let writer = tracker.register_writer(A0);
+//
+// Syscalls are recorded as writing A0, which holds their result. Memory
+// written by syscalls, and by the ELF loader, has no recorded writer.
+#[derive(Debug, Clone, Default)]
+pub struct ProvenanceTracker {
+    registers: [Option<u64>; RISCV_GENERAL_REGISTER_NUMBER],
+    // Address of each word, as a multiple of PROVENANCE_WORD_SIZE, to the PC
+    // which last wrote it.
+    memory: HashMap<u64, u64>,
+}
+
+impl ProvenanceTracker {
+    // PC of the instruction which last wrote register `index`.
+    pub fn register_writer(&self, index: usize) -> Option<u64> {
+        self.registers[index]
+    }
+
+    // PC of the instruction which last wrote the word holding `address`.
+    pub fn memory_writer(&self, address: u64) -> Option<u64> {
+        self.memory
+            .get(&(address & !(PROVENANCE_WORD_SIZE - 1)))
+            .copied()
+    }
+
+    // Last writers of the words covering `size` bytes at `address`, as
+    // (word address, PC), in address order.
+    pub fn memory_writers(&self, address: u64, size: u64) -> Vec<(u64, u64)> {
+        words(address, size)
+            .filter_map(|word| self.memory.get(&word).map(|pc| (word, *pc)))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.registers = [None; RISCV_GENERAL_REGISTER_NUMBER];
+        self.memory.clear();
+    }
+
+    fn record_register(&mut self, index: usize, pc: u64) {
+        if index != 0 {
+            self.registers[index] = Some(pc);
+        }
+    }
+
+    fn record_memory(&mut self, address: u64, size: u64, pc: u64) {
+        for word in words(address, size) {
+            self.memory.insert(word, pc);
+        }
+    }
+}
+
+// Addresses of the words covering `size` bytes at `address`.
+fn words(address: u64, size: u64) -> impl Iterator<Item = u64> {
+    let first = address & !(PROVENANCE_WORD_SIZE - 1);
+    let count = match size {
+        0 => 0,
+        _ => {
+            let last = address.wrapping_add(size - 1) & !(PROVENANCE_WORD_SIZE - 1);
+            last.wrapping_sub(first) / PROVENANCE_WORD_SIZE + 1
+        }
+    };
+    (0..count).map(move |i| first.wrapping_add(i * PROVENANCE_WORD_SIZE))
+}
+
+// Runs a loaded program one instruction at a time, recording the writers of
+// registers and memory in `tracker`. Instructions are only recorded once
+// they executed successfully. Macro-op fusion is disabled so that each write
+// is attributed to the RISC-V instruction doing it.
+pub fn run_provenance<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    tracker: &mut ProvenanceTracker,
+) -> Result<i8, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    machine.set_running(true);
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let decoded = DecodedInstruction::from_instruction(pc, instruction)?;
+        let (rd, _, _) = register_operands(&decoded);
+        let access = memory_access(instruction, machine.registers());
+        machine.step_instruction(&mut decoder)?;
+        match decoded.opcode {
+            insts::OP_ECALL => tracker.record_register(A0, pc),
+            _ => tracker.record_register(rd, pc),
+        }
+        let stored = match (decoded.opcode, access) {
+            (_, None) => None,
+            (_, Some(access)) if access.kind == MemoryAccessKind::Load => None,
+            (insts::OP_LR_W, _) | (insts::OP_LR_D, _) => None,
+            // SC only stores when it succeeds, returning 0.
+            (insts::OP_SC_W, Some(access)) | (insts::OP_SC_D, Some(access)) => {
+                Some(access).filter(|_| machine.registers()[rd].to_u64() == 0)
+            }
+            (_, access) => access,
+        };
+        if let Some(access) = stored {
+            tracker.record_memory(access.address, u64::from(access.size), pc);
+        }
+    }
+    Ok(machine.exit_code())
+}
@@ -0,0 +1,236 @@
+use super::{
+    super::{
+        decoder::build_decoder, instructions::Register, registers::register_name, Error, ISA_MOP,
+        RISCV_GENERAL_REGISTER_NUMBER,
+    },
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+// A guest instruction printed by QEMU's in_asm log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuInstruction {
+    pub pc: u64,
+    pub instruction: u32,
+    pub disassembly: String,
+}
+
+// CPU state printed by QEMU's cpu log, before each translation block is
+// executed(or each instruction when running with one-insn-per-tb).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuState {
+    pub pc: u64,
+    pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QemuTrace {
+    // All translated instructions, indexed by pc for context reporting.
+    pub instructions: Vec<QemuInstruction>,
+    pub states: Vec<QemuState>,
+}
+
+impl QemuTrace {
+    // Parses the output of `qemu-riscv64 -d in_asm,cpu`. Lines not belonging
+    // to either log are ignored.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut trace = QemuTrace::default();
+        let mut state: Option<QemuState> = None;
+        for line in text.lines() {
+            let mut tokens = line.split_whitespace();
+            let first = match tokens.next() {
+                Some(token) => token,
+                None => continue,
+            };
+            if let Some(pc) = first.strip_prefix("0x").and_then(|s| s.strip_suffix(':')) {
+                // 0x00000000000100b0:  1141          addi            sp,sp,-16
+                let pc = parse_hex(pc, line)?;
+                let instruction = parse_hex(tokens.next().unwrap_or_default(), line)? as u32;
+                let disassembly = tokens.collect::<Vec<_>>().join(" ");
+                trace.instructions.push(QemuInstruction {
+                    pc,
+                    instruction,
+                    disassembly,
+                });
+            } else if first == "pc" {
+                if let Some(state) = state.take() {
+                    trace.states.push(state);
+                }
+                state = Some(QemuState {
+                    pc: parse_hex(tokens.next().unwrap_or_default(), line)?,
+                    registers: [0; RISCV_GENERAL_REGISTER_NUMBER],
+                });
+            } else if let Some(current) = state.as_mut() {
+                // x0/zero  0000000000000000 x1/ra    0000000000000000 ...
+                let mut name = Some(first);
+                while let Some(n) = name {
+                    let value = tokens.next();
+                    if let Some(index) = n
+                        .strip_prefix('x')
+                        .and_then(|s| s.split('/').next())
+                        .and_then(|s| s.parse::<usize>().ok())
+                    {
+                        if index < RISCV_GENERAL_REGISTER_NUMBER {
+                            current.registers[index] = parse_hex(value.unwrap_or_default(), line)?;
+                        }
+                    }
+                    name = tokens.next();
+                }
+            }
+        }
+        if let Some(state) = state {
+            trace.states.push(state);
+        }
+        Ok(trace)
+    }
+
+    fn instruction_at(&self, pc: u64) -> Option<&QemuInstruction> {
+        self.instructions.iter().rev().find(|i| i.pc == pc)
+    }
+}
+
+fn parse_hex(s: &str, line: &str) -> Result<u64, Error> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| Error::Unexpected(format!("Invalid QEMU log line: {}", line)))
+}
+
+#[derive(Debug, Clone)]
+pub struct QemuCompareOptions {
+    // Added to PCs of this VM before comparing.
+    pub pc_offset: u64,
+    // Registers excluded from comparison. QEMU user mode sets up its own
+    // stack, so SP and pointers into the stack usually differ.
+    pub ignored_registers: Vec<usize>,
+    // Maximum number of instructions executed between 2 states, which is
+    // the size limit of a QEMU translation block.
+    pub max_block_size: usize,
+    // Number of instructions executed before a divergence included in the
+    // report.
+    pub context: usize,
+}
+
+impl Default for QemuCompareOptions {
+    fn default() -> Self {
+        Self {
+            pc_offset: 0,
+            ignored_registers: vec![],
+            max_block_size: 512,
+            context: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QemuDivergenceKind {
+    // The VM did not reach the pc of the state within max_block_size
+    // instructions.
+    PcNotReached,
+    // The program exited in the VM before the state.
+    Exited(i8),
+    // (index, qemu, vm) of each differing register.
+    Registers(Vec<(usize, u64, u64)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuDivergence {
+    // Index of the diverging state in QemuTrace::states.
+    pub state: usize,
+    pub pc: u64,
+    pub kind: QemuDivergenceKind,
+    // The last instructions executed by the VM, disassembled by QEMU when
+    // available.
+    pub context: Vec<String>,
+}
+
+impl Display for QemuDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "state {} at 0x{:x}: ", self.state, self.pc)?;
+        match &self.kind {
+            QemuDivergenceKind::PcNotReached => writeln!(f, "pc not reached")?,
+            QemuDivergenceKind::Exited(code) => writeln!(f, "exited with {}", code)?,
+            QemuDivergenceKind::Registers(registers) => {
+                writeln!(f, "registers differ")?;
+                for (index, qemu, vm) in registers {
+                    writeln!(
+                        f,
+                        "  {}: qemu 0x{:x}, vm 0x{:x}",
+                        register_name(*index).unwrap_or("?"),
+                        qemu,
+                        vm
+                    )?;
+                }
+            }
+        }
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs a loaded program, checking the VM against each CPU state of a QEMU
+// trace: the VM runs until it reaches the pc of the next state, then all
+// registers are compared. The first divergence is returned, None if all
+// states match.
+//
+// let trace = QemuTrace::parse(&fs::read_to_string("qemu.log")?)?;
+// if let Some(divergence) = compare_qemu_trace(&mut machine, &trace, &options)? {
+//     println!("{}", divergence);
+// }
+pub fn compare_qemu_trace<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    trace: &QemuTrace,
+    options: &QemuCompareOptions,
+) -> Result<Option<QemuDivergence>, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    let mut history = VecDeque::with_capacity(options.context + 1);
+    machine.set_running(true);
+    for (index, state) in trace.states.iter().enumerate() {
+        let divergence = |kind, history: &VecDeque<u64>| {
+            let context = history
+                .iter()
+                .map(|pc| match trace.instruction_at(*pc) {
+                    Some(i) => format!("0x{:x}: {}", pc, i.disassembly),
+                    None => format!("0x{:x}", pc),
+                })
+                .collect();
+            Ok(Some(QemuDivergence {
+                state: index,
+                pc: state.pc,
+                kind,
+                context,
+            }))
+        };
+        let mut steps = 0;
+        // The VM is at the pc of the previous state, so at least one
+        // instruction is executed before the next comparison.
+        while (index > 0 && steps == 0)
+            || machine.pc().to_u64().wrapping_add(options.pc_offset) != state.pc
+        {
+            if !machine.running() {
+                return divergence(QemuDivergenceKind::Exited(machine.exit_code()), &history);
+            }
+            if steps >= options.max_block_size {
+                return divergence(QemuDivergenceKind::PcNotReached, &history);
+            }
+            if history.len() == options.context {
+                history.pop_front();
+            }
+            if options.context > 0 {
+                history.push_back(machine.pc().to_u64().wrapping_add(options.pc_offset));
+            }
+            machine.step_instruction(&mut decoder)?;
+            steps += 1;
+        }
+        let registers: Vec<_> = (1..RISCV_GENERAL_REGISTER_NUMBER)
+            .filter(|i| !options.ignored_registers.contains(i))
+            .map(|i| (i, state.registers[i], machine.registers()[i].to_u64()))
+            .filter(|(_, qemu, vm)| qemu != vm)
+            .collect();
+        if !registers.is_empty() {
+            return divergence(QemuDivergenceKind::Registers(registers), &history);
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,361 @@
+use super::{
+    super::{
+        decoder::{build_decoder, Decoder},
+        instructions::Register,
+        memory::{recording::RecordingMemory, Memory},
+        Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    },
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use std::fmt::{self, Display};
+use std::io::{ErrorKind, Read, Write};
+
+// Recorded runs start with the magic and the format version, followed by one
+// entry per executed instruction and a final Exit or Trap entry. Integers are
+// LEB128 encoded.
+pub const RECORD_MAGIC: &[u8; 8] = b"CKBVMREC";
+pub const RECORD_FORMAT_VERSION: u32 = 1;
+
+const TAG_STEP: u8 = 0;
+const TAG_EXIT: u8 = 1;
+const TAG_TRAP: u8 = 2;
+
+// One executed instruction: its PC, the cycles it consumed, the registers it
+// changed and the memory it wrote, syscalls included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepRecord {
+    pub pc: u64,
+    pub cycles: u64,
+    pub registers: Vec<(u8, u64)>,
+    pub stores: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordEntry {
+    Step(StepRecord),
+    Exit(i8),
+    // The instruction at pc failed, error is the Debug output of the error.
+    Trap { pc: u64, error: String },
+}
+
+impl RecordEntry {
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        match self {
+            RecordEntry::Step(step) => {
+                buffer.push(TAG_STEP);
+                write_varint(&mut buffer, step.pc);
+                write_varint(&mut buffer, step.cycles);
+                write_varint(&mut buffer, step.registers.len() as u64);
+                for (index, value) in &step.registers {
+                    buffer.push(*index);
+                    write_varint(&mut buffer, *value);
+                }
+                write_varint(&mut buffer, step.stores.len() as u64);
+                for (address, data) in &step.stores {
+                    write_varint(&mut buffer, *address);
+                    write_varint(&mut buffer, data.len() as u64);
+                    buffer.extend_from_slice(data);
+                }
+            }
+            RecordEntry::Exit(code) => {
+                buffer.push(TAG_EXIT);
+                buffer.push(*code as u8);
+            }
+            RecordEntry::Trap { pc, error } => {
+                buffer.push(TAG_TRAP);
+                write_varint(&mut buffer, *pc);
+                write_varint(&mut buffer, error.len() as u64);
+                buffer.extend_from_slice(error.as_bytes());
+            }
+        }
+        writer.write_all(&buffer)?;
+        Ok(())
+    }
+
+    // Reads the next entry, None at the end of the stream.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Option<Self>, Error> {
+        let mut tag = [0u8];
+        loop {
+            match reader.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let entry = match tag[0] {
+            TAG_STEP => {
+                let pc = read_varint(reader)?;
+                let cycles = read_varint(reader)?;
+                let count = read_length(reader, RISCV_GENERAL_REGISTER_NUMBER as u64)?;
+                let mut registers = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let index = read_byte(reader)?;
+                    if usize::from(index) >= RISCV_GENERAL_REGISTER_NUMBER {
+                        return Err(Error::Unexpected(format!(
+                            "Invalid register {} in record",
+                            index
+                        )));
+                    }
+                    registers.push((index, read_varint(reader)?));
+                }
+                let count = read_length(reader, RISCV_MAX_MEMORY as u64)?;
+                let mut stores = Vec::new();
+                for _ in 0..count {
+                    let address = read_varint(reader)?;
+                    let length = read_length(reader, RISCV_MAX_MEMORY as u64)?;
+                    let mut data = vec![0; length];
+                    reader.read_exact(&mut data)?;
+                    stores.push((address, data));
+                }
+                RecordEntry::Step(StepRecord {
+                    pc,
+                    cycles,
+                    registers,
+                    stores,
+                })
+            }
+            TAG_EXIT => RecordEntry::Exit(read_byte(reader)? as i8),
+            TAG_TRAP => {
+                let pc = read_varint(reader)?;
+                let length = read_length(reader, 4096)?;
+                let mut data = vec![0; length];
+                reader.read_exact(&mut data)?;
+                let error = String::from_utf8(data)
+                    .map_err(|_| Error::Unexpected("Invalid trap in record".to_string()))?;
+                RecordEntry::Trap { pc, error }
+            }
+            tag => {
+                return Err(Error::Unexpected(format!(
+                    "Invalid record entry tag {}",
+                    tag
+                )))
+            }
+        };
+        Ok(Some(entry))
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8, Error> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Unexpected("Invalid varint in record".to_string()))
+}
+
+fn read_length<R: Read>(reader: &mut R, limit: u64) -> Result<usize, Error> {
+    let length = read_varint(reader)?;
+    if length > limit {
+        return Err(Error::Unexpected(format!(
+            "Record length {} exceeds {}",
+            length, limit
+        )));
+    }
+    Ok(length as usize)
+}
+
+pub fn write_header<W: Write>(writer: &mut W) -> Result<(), Error> {
+    writer.write_all(RECORD_MAGIC)?;
+    writer.write_all(&RECORD_FORMAT_VERSION.to_le_bytes())?;
+    Ok(())
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(), Error> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != RECORD_MAGIC {
+        return Err(Error::Unexpected("Invalid record magic".to_string()));
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&header[8..]);
+    let version = u32::from_le_bytes(version);
+    if version != RECORD_FORMAT_VERSION {
+        return Err(Error::Unexpected(format!(
+            "Unsupported record format version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+fn register_values<Inner: SupportMachine>(machine: &DefaultMachine<Inner>) -> Vec<u64> {
+    machine.registers().iter().map(|r| r.to_u64()).collect()
+}
+
+// Executes one instruction, diffing the registers against `registers`, which
+// is updated to the values after the instruction.
+fn execute<Inner, M>(
+    machine: &mut DefaultMachine<Inner>,
+    decoder: &mut Decoder,
+    registers: &mut [u64],
+) -> Result<StepRecord, Error>
+where
+    Inner: SupportMachine<MEM = RecordingMemory<M>>,
+    M: Memory<REG = Inner::REG>,
+{
+    let pc = machine.pc().to_u64();
+    let cycles = machine.cycles();
+    machine.memory_mut().take_stores();
+    machine.step_instruction(decoder)?;
+    let mut record = StepRecord {
+        pc,
+        cycles: machine.cycles().wrapping_sub(cycles),
+        stores: machine.memory_mut().take_stores(),
+        ..Default::default()
+    };
+    for (index, value) in machine.registers().iter().enumerate() {
+        let value = value.to_u64();
+        if registers[index] != value {
+            registers[index] = value;
+            record.registers.push((index as u8, value));
+        }
+    }
+    Ok(record)
+}
+
+fn prepare<Inner, M>(machine: &mut DefaultMachine<Inner>) -> (Decoder, Vec<u64>)
+where
+    Inner: SupportMachine<MEM = RecordingMemory<M>>,
+    M: Memory<REG = Inner::REG>,
+{
+    // Macro-op fusion is disabled so that each step is one RISC-V instruction.
+    let decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    machine.memory_mut().set_recording(true);
+    machine.set_running(true);
+    (decoder, register_values(machine))
+}
+
+// Runs a loaded program one instruction at a time, writing the executed PCs,
+// register writebacks and memory stores to `writer`. Stores done while loading
+// the program are not recorded, the replaying machine loads it the same way.
+//
+// let mut file = BufWriter::new(File::create("run.rec")?);
+// record(&mut machine, &mut file)?;
+pub fn record<Inner, M, W>(machine: &mut DefaultMachine<Inner>, mut writer: W) -> Result<i8, Error>
+where
+    Inner: SupportMachine<MEM = RecordingMemory<M>>,
+    M: Memory<REG = Inner::REG>,
+    W: Write,
+{
+    write_header(&mut writer)?;
+    let (mut decoder, mut registers) = prepare(machine);
+    let result = loop {
+        if !machine.running() {
+            break Ok(machine.exit_code());
+        }
+        let pc = machine.pc().to_u64();
+        match execute(machine, &mut decoder, &mut registers) {
+            Ok(step) => RecordEntry::Step(step).write(&mut writer)?,
+            Err(e) => {
+                let error = format!("{:?}", e);
+                RecordEntry::Trap { pc, error }.write(&mut writer)?;
+                break Err(e);
+            }
+        }
+    };
+    if let Ok(code) = result {
+        RecordEntry::Exit(code).write(&mut writer)?;
+    }
+    machine.memory_mut().set_recording(false);
+    writer.flush()?;
+    result
+}
+
+// The first step where the replayed run differs from the recorded one.
+// Expected is None when the record ends before the run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: u64,
+    pub expected: Option<RecordEntry>,
+    pub actual: RecordEntry,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.expected {
+            Some(expected) => write!(
+                f,
+                "step {}: expected {:?}, got {:?}",
+                self.step, expected, self.actual
+            ),
+            None => write!(f, "step {}: record ended, got {:?}", self.step, self.actual),
+        }
+    }
+}
+
+// Re-executes a recorded run on a machine loaded with the same program and
+// arguments, checking every step against the record.
+pub struct ReplayMachine<Inner> {
+    pub machine: DefaultMachine<Inner>,
+}
+
+impl<Inner, M> ReplayMachine<Inner>
+where
+    Inner: SupportMachine<MEM = RecordingMemory<M>>,
+    M: Memory<REG = Inner::REG>,
+{
+    pub fn new(machine: DefaultMachine<Inner>) -> Self {
+        Self { machine }
+    }
+
+    // Returns the first divergence, or None when the run matches the record
+    // up to its final entry. Malformed records are reported as errors.
+    pub fn replay<R: Read>(&mut self, mut reader: R) -> Result<Option<Divergence>, Error> {
+        read_header(&mut reader)?;
+        let (mut decoder, mut registers) = prepare(&mut self.machine);
+        let mut step = 0;
+        let divergence = loop {
+            let expected = RecordEntry::read(&mut reader)?;
+            let actual = if self.machine.running() {
+                let pc = self.machine.pc().to_u64();
+                match execute(&mut self.machine, &mut decoder, &mut registers) {
+                    Ok(record) => RecordEntry::Step(record),
+                    Err(e) => RecordEntry::Trap {
+                        pc,
+                        error: format!("{:?}", e),
+                    },
+                }
+            } else {
+                RecordEntry::Exit(self.machine.exit_code())
+            };
+            if expected.as_ref() != Some(&actual) {
+                break Some(Divergence {
+                    step,
+                    expected,
+                    actual,
+                });
+            }
+            if !matches!(actual, RecordEntry::Step(_)) {
+                if RecordEntry::read(&mut reader)?.is_some() {
+                    return Err(Error::Unexpected(
+                        "Record continues after the end of the run".to_string(),
+                    ));
+                }
+                break None;
+            }
+            step += 1;
+        };
+        self.machine.memory_mut().set_recording(false);
+        Ok(divergence)
+    }
+}
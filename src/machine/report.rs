@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{
+    super::{
+        instructions::{
+            extract_opcode, instruction_opcode_name, Instruction, InstructionOpcode, Register,
+        },
+        memory::{Memory, FLAG_DIRTY},
+        metrics::error_kind,
+        Error, RISCV_PAGESIZE,
+    },
+    abort::{extract_abort_message, AbortMessage},
+    program::build_id_hex,
+    timing::TimingModel,
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+
+// Why a run stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitReason {
+    // The program exited normally with the exit code.
+    Exit(i8),
+    // Max cycles was reached, the machine can be resumed with more cycles.
+    CyclesExceeded,
+    // The program stopped because of an error.
+    Error(Error),
+}
+
+// ExecutionReport summarizes a single run of a machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionReport {
+    pub exit_reason: ExitReason,
+    // Cycles consumed by this run.
+    pub cycles: u64,
+    // Instructions successfully executed by this run. A fused instruction
+    // produced by MOP counts as one instruction.
+    pub instructions_retired: u64,
+    // Bytes of memory written so far(including program loading), measured in
+    // pages. Memory is never released in CKB-VM, so this is also the peak value.
+    pub peak_memory: u64,
+    // Number of retired instructions per opcode, only collected on request.
+    pub opcode_stats: Option<HashMap<InstructionOpcode, u64>>,
+    // Message left by the guest runtime when the program exited with a non
+    // zero code or an error, see extract_abort_message.
+    pub abort_message: Option<AbortMessage>,
+    // Build ID of the program, see ProgramInfo.
+    pub build_id: Option<Vec<u8>>,
+    // Size of the guest memory, in bytes.
+    pub memory_size: u64,
+    // Syscalls handled by the host during this run, by syscall number.
+    // Syscalls trapped to the guest are not included.
+    pub syscalls: HashMap<u64, SyscallStats>,
+    // Hardware cycles of this run estimated by the timing model, if the
+    // machine has one.
+    pub timing_cycles: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SyscallStats {
+    pub calls: u64,
+    // Cycles charged by the syscall implementations, on top of the cycles
+    // of the ECALL instructions.
+    pub cycles: u64,
+}
+
+impl ExecutionReport {
+    pub fn result(&self) -> Result<i8, Error> {
+        match &self.exit_reason {
+            ExitReason::Exit(code) => Ok(*code),
+            ExitReason::CyclesExceeded => Err(Error::CyclesExceeded),
+            ExitReason::Error(e) => Err(e.clone()),
+        }
+    }
+
+    // Converts the report to plain data, see Report.
+    pub fn to_report(&self) -> Report {
+        let exit = match &self.exit_reason {
+            ExitReason::Exit(code) => ReportExit::Exit { code: *code },
+            ExitReason::CyclesExceeded => ReportExit::CyclesExceeded,
+            ExitReason::Error(e) => ReportExit::Error {
+                kind: error_kind(e).to_string(),
+                message: e.to_string(),
+            },
+        };
+        Report {
+            exit,
+            cycles: self.cycles,
+            instructions_retired: self.instructions_retired,
+            opcode_stats: self.opcode_stats.as_ref().map(|stats| {
+                stats
+                    .iter()
+                    .map(|(opcode, count)| (instruction_opcode_name(*opcode).to_string(), *count))
+                    .collect()
+            }),
+            memory: MemoryStats {
+                memory_size: self.memory_size,
+                peak_memory: self.peak_memory,
+            },
+            syscalls: self
+                .syscalls
+                .iter()
+                .map(|(code, stats)| (*code, *stats))
+                .collect(),
+            abort_message: self
+                .abort_message
+                .as_ref()
+                .map(|abort| abort.message.clone()),
+            build_id: self.build_id.as_deref().map(build_id_hex),
+            timing_cycles: self.timing_cycles,
+        }
+    }
+}
+
+// Plain data version of ExecutionReport, meant to be ingested by CI
+// pipelines and analytics systems. With the serde feature, which the default
+// snapshot feature enables, it is serializable, e.g. as JSON with serde_json:
+//
+// let report = machine.run_with_report(true).to_report();
+// println!("{}", serde_json::to_string(&report)?);
+//
+// Maps are ordered, so serializing the same run always gives the same output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Report {
+    pub exit: ReportExit,
+    pub cycles: u64,
+    pub instructions_retired: u64,
+    // Retired instructions by opcode name, e.g. "ADDI".
+    pub opcode_stats: Option<BTreeMap<String, u64>>,
+    pub memory: MemoryStats,
+    // Syscalls handled by the host, by syscall number.
+    pub syscalls: BTreeMap<u64, SyscallStats>,
+    pub abort_message: Option<String>,
+    // Build ID as a lower case hex string.
+    pub build_id: Option<String>,
+    pub timing_cycles: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "reason", rename_all = "snake_case")
+)]
+pub enum ReportExit {
+    Exit {
+        code: i8,
+    },
+    CyclesExceeded,
+    Error {
+        // Stable label of the error, see metrics::error_kind.
+        kind: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemoryStats {
+    pub memory_size: u64,
+    // See ExecutionReport::peak_memory.
+    pub peak_memory: u64,
+}
+
+// Collects statistics while a machine is running.
+pub(crate) struct ReportCollector {
+    pub(crate) start_cycles: u64,
+    pub(crate) instructions_retired: u64,
+    pub(crate) opcode_stats: Option<HashMap<InstructionOpcode, u64>>,
+    // Taken from the machine for the run, with its cycles when the run
+    // started.
+    pub(crate) timing_model: Option<(Box<dyn TimingModel>, u64)>,
+    // PC of the next instruction to retire, only tracked for the timing
+    // model.
+    pub(crate) pc: u64,
+}
+
+impl ReportCollector {
+    // Also starts counting the syscalls of `machine`.
+    pub(crate) fn new<Inner: CoreMachine>(
+        machine: &mut DefaultMachine<Inner>,
+        start_cycles: u64,
+        opcode_stats: bool,
+    ) -> Self {
+        machine.syscall_stats = Some(HashMap::new());
+        Self {
+            timing_model: machine.timing_model.take().map(|model| {
+                let cycles = model.cycles();
+                (model, cycles)
+            }),
+            pc: machine.pc().to_u64(),
+            start_cycles,
+            instructions_retired: 0,
+            opcode_stats: if opcode_stats {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn retire(&mut self, instruction: Instruction, next_pc: u64) {
+        self.instructions_retired += 1;
+        if let Some(stats) = &mut self.opcode_stats {
+            *stats.entry(extract_opcode(instruction)).or_insert(0) += 1;
+        }
+        if let Some((model, _)) = &mut self.timing_model {
+            model.retire(instruction, self.pc, next_pc);
+            self.pc = next_pc;
+        }
+    }
+
+    pub(crate) fn finish<Inner: SupportMachine>(
+        self,
+        result: Result<i8, Error>,
+        machine: &mut DefaultMachine<Inner>,
+    ) -> ExecutionReport {
+        let exit_reason = match result {
+            Ok(code) => ExitReason::Exit(code),
+            Err(Error::CyclesExceeded) => ExitReason::CyclesExceeded,
+            Err(e) => ExitReason::Error(e),
+        };
+        let timing_cycles = self.timing_model.map(|(model, start_cycles)| {
+            let cycles = model.cycles().saturating_sub(start_cycles);
+            machine.timing_model = Some(model);
+            cycles
+        });
+        let abort_message = match exit_reason {
+            ExitReason::Exit(0) | ExitReason::CyclesExceeded => None,
+            _ => extract_abort_message(machine),
+        };
+        ExecutionReport {
+            exit_reason,
+            cycles: machine.cycles().saturating_sub(self.start_cycles),
+            instructions_retired: self.instructions_retired,
+            peak_memory: dirty_memory(machine.memory_mut()),
+            opcode_stats: self.opcode_stats,
+            abort_message,
+            build_id: machine
+                .program_info()
+                .and_then(|info| info.build_id.clone()),
+            memory_size: machine.memory().memory_size() as u64,
+            syscalls: machine.syscall_stats.take().unwrap_or_default(),
+            timing_cycles,
+        }
+    }
+}
+
+// Returns the size of all pages marked as dirty.
+pub fn dirty_memory<M: Memory>(memory: &mut M) -> u64 {
+    let pages = memory.memory_size() / RISCV_PAGESIZE;
+    let mut dirty = 0;
+    for page in 0..pages {
+        if let Ok(flag) = memory.fetch_flag(page as u64) {
+            if flag & FLAG_DIRTY != 0 {
+                dirty += RISCV_PAGESIZE as u64;
+            }
+        }
+    }
+    dirty
+}
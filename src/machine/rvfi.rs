@@ -0,0 +1,260 @@
+use super::{
+    super::{
+        decoder::{build_decoder, DecodedInstruction, Operands},
+        instructions::Register,
+        memory::Memory,
+        Error, ISA_MOP,
+    },
+    cosim::raw_instruction,
+    instrumented::{memory_access, MemoryAccessKind},
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use ckb_vm_definitions::instructions as insts;
+use std::fmt::{self, Display};
+use std::io::Write;
+
+// One retired instruction in the format of the RISC-V Formal Interface, as
+// consumed by riscv-formal and TestRIG(RVFI-DII). Registers and memory data
+// are zero extended to 64 bits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RvfiRecord {
+    pub order: u64,
+    pub insn: u64,
+    // Set when the instruction raised an error, the VM stops after it.
+    pub trap: bool,
+    // Set on the last instruction, i.e. the exit syscall.
+    pub halt: bool,
+    pub intr: bool,
+    // Programs run in user mode.
+    pub mode: u8,
+    // 1 for RV32, 2 for RV64.
+    pub ixl: u8,
+    pub rs1_addr: u8,
+    pub rs2_addr: u8,
+    pub rs1_rdata: u64,
+    pub rs2_rdata: u64,
+    pub rd_addr: u8,
+    pub rd_wdata: u64,
+    pub pc_rdata: u64,
+    pub pc_wdata: u64,
+    pub mem_addr: u64,
+    pub mem_rmask: u8,
+    pub mem_wmask: u8,
+    pub mem_rdata: u64,
+    pub mem_wdata: u64,
+}
+
+pub const RVFI_DII_PACKET_SIZE: usize = 88;
+
+impl RvfiRecord {
+    // Serializes the record as a RVFI-DII execution packet(version 1), the
+    // trace format shared by TestRIG and the Sail model.
+    pub fn to_dii_packet(&self) -> [u8; RVFI_DII_PACKET_SIZE] {
+        let mut packet = [0; RVFI_DII_PACKET_SIZE];
+        let words = [
+            self.order,
+            self.pc_rdata,
+            self.pc_wdata,
+            self.insn,
+            self.rs1_rdata,
+            self.rs2_rdata,
+            self.rd_wdata,
+            self.mem_addr,
+            self.mem_rdata,
+            self.mem_wdata,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            packet[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+        packet[80..].copy_from_slice(&[
+            self.mem_rmask,
+            self.mem_wmask,
+            self.rs1_addr,
+            self.rs2_addr,
+            self.rd_addr,
+            self.trap as u8,
+            self.halt as u8,
+            self.intr as u8,
+        ]);
+        packet
+    }
+}
+
+// One line of rvfi_* signals, named as in the RVFI specification.
+impl Display for RvfiRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "order={} insn=0x{:08x} trap={} halt={} intr={} mode={} ixl={} \
+             rs1_addr={} rs2_addr={} rs1_rdata=0x{:x} rs2_rdata=0x{:x} \
+             rd_addr={} rd_wdata=0x{:x} pc_rdata=0x{:x} pc_wdata=0x{:x} \
+             mem_addr=0x{:x} mem_rmask=0x{:x} mem_wmask=0x{:x} mem_rdata=0x{:x} mem_wdata=0x{:x}",
+            self.order,
+            self.insn,
+            self.trap as u8,
+            self.halt as u8,
+            self.intr as u8,
+            self.mode,
+            self.ixl,
+            self.rs1_addr,
+            self.rs2_addr,
+            self.rs1_rdata,
+            self.rs2_rdata,
+            self.rd_addr,
+            self.rd_wdata,
+            self.pc_rdata,
+            self.pc_wdata,
+            self.mem_addr,
+            self.mem_rmask,
+            self.mem_wmask,
+            self.mem_rdata,
+            self.mem_wdata,
+        )
+    }
+}
+
+// Register operands as RVFI sees them: (rd, rs1, rs2). Fields of the internal
+// format which are not registers, such as the operation selector of unary
+// instructions or FENCE arguments, are reported as x0.
+pub(crate) fn register_operands(decoded: &DecodedInstruction) -> (usize, usize, usize) {
+    use insts::*;
+    match decoded.opcode {
+        OP_FENCE | OP_FENCEI | OP_ECALL | OP_EBREAK => return (0, 0, 0),
+        OP_CLZ | OP_CTZ | OP_CPOP | OP_CLZW | OP_CTZW | OP_CPOPW | OP_SEXTB | OP_SEXTH
+        | OP_ZEXTH | OP_ORCB | OP_REV8 | OP_LR_W | OP_LR_D => {
+            if let Operands::R { rd, rs1, .. } = decoded.operands {
+                return (rd, rs1, 0);
+            }
+        }
+        _ => (),
+    }
+    match decoded.operands {
+        Operands::R { rd, rs1, rs2 } => (rd, rs1, rs2),
+        Operands::I { rd, rs1, .. } => (rd, rs1, 0),
+        Operands::S { rs1, rs2, .. } => (0, rs1, rs2),
+        Operands::U { rd, .. } => (rd, 0, 0),
+        Operands::R4 { rd, rs1, rs2, .. } | Operands::R5 { rd, rs1, rs2, .. } => (rd, rs1, rs2),
+    }
+}
+
+fn load_value<M: Memory>(memory: &mut M, address: u64, size: u8) -> Result<u64, Error> {
+    let bytes = memory.load_bytes(address, u64::from(size))?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte)))
+}
+
+// Runs a loaded program one instruction at a time, passing a RVFI record for
+// each retired instruction to `sink`. Macro-op fusion is disabled so that
+// records map to RISC-V instructions. When an instruction fails, a record
+// with trap set is emitted before the error is returned.
+//
+// let mut file = BufWriter::new(File::create("trace.rvfi")?);
+// run_rvfi(&mut machine, |record| Ok(file.write_all(&record.to_dii_packet())?))?;
+pub fn run_rvfi<Inner: SupportMachine, F: FnMut(&RvfiRecord) -> Result<(), Error>>(
+    machine: &mut DefaultMachine<Inner>,
+    mut sink: F,
+) -> Result<i8, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    let ixl = if Inner::REG::BITS == 64 { 2 } else { 1 };
+    let mut order = 0;
+    machine.set_running(true);
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let mut record = RvfiRecord {
+            order,
+            ixl,
+            pc_rdata: pc,
+            pc_wdata: pc,
+            ..Default::default()
+        };
+        let instruction = match decoder.decode(machine.memory_mut(), pc) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                record.trap = true;
+                sink(&record)?;
+                return Err(e);
+            }
+        };
+        let decoded = DecodedInstruction::from_instruction(pc, instruction)?;
+        let (rd, rs1, rs2) = register_operands(&decoded);
+        record.insn = u64::from(raw_instruction(machine, pc, instruction)?);
+        record.rs1_addr = rs1 as u8;
+        record.rs2_addr = rs2 as u8;
+        record.rs1_rdata = machine.registers()[rs1].to_u64();
+        record.rs2_rdata = machine.registers()[rs2].to_u64();
+        let access = memory_access(instruction, machine.registers());
+        // Atomic instructions read memory before writing it.
+        let atomic_rdata = match access {
+            Some(a) if a.kind == MemoryAccessKind::Atomic => {
+                load_value(machine.memory_mut(), a.address, a.size).ok()
+            }
+            _ => None,
+        };
+        if let Err(e) = machine.step_instruction(&mut decoder) {
+            record.trap = true;
+            sink(&record)?;
+            return Err(e);
+        }
+        record.rd_addr = rd as u8;
+        record.rd_wdata = machine.registers()[rd].to_u64();
+        record.pc_wdata = machine.pc().to_u64();
+        record.halt = !machine.running();
+        if let Some(access) = access {
+            let mask = ((1u16 << access.size) - 1) as u8;
+            let value = load_value(machine.memory_mut(), access.address, access.size)?;
+            record.mem_addr = access.address;
+            match access.kind {
+                MemoryAccessKind::Load => {
+                    record.mem_rmask = mask;
+                    record.mem_rdata = value;
+                }
+                MemoryAccessKind::Store => {
+                    record.mem_wmask = mask;
+                    record.mem_wdata = value;
+                }
+                MemoryAccessKind::Atomic => {
+                    record.mem_rmask = mask;
+                    record.mem_rdata = atomic_rdata.unwrap_or(value);
+                    // LR never writes, and neither does a failed SC.
+                    let written = match decoded.opcode {
+                        insts::OP_LR_W | insts::OP_LR_D => false,
+                        insts::OP_SC_W | insts::OP_SC_D => record.rd_wdata == 0,
+                        _ => true,
+                    };
+                    if written {
+                        record.mem_wmask = mask;
+                        record.mem_wdata = value;
+                    }
+                }
+            }
+        }
+        sink(&record)?;
+        order += 1;
+    }
+    Ok(machine.exit_code())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RvfiFormat {
+    // One line per instruction, see RvfiRecord's Display.
+    Text,
+    // Binary RVFI-DII execution packets.
+    DiiPacket,
+}
+
+// Same as run_rvfi, writing records to `writer` in the given format.
+pub fn write_rvfi<Inner: SupportMachine, W: Write>(
+    machine: &mut DefaultMachine<Inner>,
+    writer: &mut W,
+    format: RvfiFormat,
+) -> Result<i8, Error> {
+    run_rvfi(machine, |record| {
+        match format {
+            RvfiFormat::Text => writeln!(writer, "{}", record)?,
+            RvfiFormat::DiiPacket => writer.write_all(&record.to_dii_packet())?,
+        }
+        Ok(())
+    })
+}
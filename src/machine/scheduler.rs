@@ -0,0 +1,234 @@
+#[cfg(has_asm)]
+use super::asm::AsmMachine;
+#[cfg(feature = "trace")]
+use super::trace::TraceMachine;
+use super::{super::Error, DefaultMachine, SupportMachine};
+
+// A machine which can be run in slices by Scheduler. A slice lowers max
+// cycles to the end of the quantum, the machine then stops with
+// CyclesExceeded at an instruction boundary, and running it again resumes
+// the program.
+pub trait Schedulable {
+    fn run(&mut self) -> Result<i8, Error>;
+    fn cycles(&self) -> u64;
+    fn max_cycles(&self) -> u64;
+    fn set_max_cycles(&mut self, cycles: u64);
+}
+
+impl<Inner: SupportMachine> Schedulable for DefaultMachine<Inner> {
+    fn run(&mut self) -> Result<i8, Error> {
+        DefaultMachine::run(self)
+    }
+
+    fn cycles(&self) -> u64 {
+        SupportMachine::cycles(self)
+    }
+
+    fn max_cycles(&self) -> u64 {
+        SupportMachine::max_cycles(self)
+    }
+
+    fn set_max_cycles(&mut self, cycles: u64) {
+        SupportMachine::set_max_cycles(self, cycles)
+    }
+}
+
+#[cfg(feature = "trace")]
+impl<Inner: SupportMachine> Schedulable for TraceMachine<Inner> {
+    fn run(&mut self) -> Result<i8, Error> {
+        TraceMachine::run(self)
+    }
+
+    fn cycles(&self) -> u64 {
+        SupportMachine::cycles(&self.machine)
+    }
+
+    fn max_cycles(&self) -> u64 {
+        SupportMachine::max_cycles(&self.machine)
+    }
+
+    fn set_max_cycles(&mut self, cycles: u64) {
+        SupportMachine::set_max_cycles(&mut self.machine, cycles)
+    }
+}
+
+#[cfg(has_asm)]
+impl Schedulable for AsmMachine {
+    fn run(&mut self) -> Result<i8, Error> {
+        AsmMachine::run(self)
+    }
+
+    fn cycles(&self) -> u64 {
+        SupportMachine::cycles(&self.machine)
+    }
+
+    fn max_cycles(&self) -> u64 {
+        SupportMachine::max_cycles(&self.machine)
+    }
+
+    fn set_max_cycles(&mut self, cycles: u64) {
+        SupportMachine::set_max_cycles(&mut self.machine, cycles)
+    }
+}
+
+pub type VmId = usize;
+
+// Fairness accounting of a scheduled machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmStats {
+    // Cycles consumed while scheduled.
+    pub cycles: u64,
+    // Slices run, including the last one.
+    pub slices: u64,
+    // Slices which ended at the end of the quantum.
+    pub preemptions: u64,
+}
+
+// Virtual time advanced by a slice of one quantum at priority 1.
+const STRIDE: u128 = 1 << 20;
+
+struct Vm<M> {
+    machine: M,
+    priority: u32,
+    // Virtual time consumed, the machine with the lowest pass runs next.
+    pass: u128,
+    stats: VmStats,
+    result: Option<Result<i8, Error>>,
+}
+
+// Scheduler runs many machines on the current thread, interleaved in slices
+// of `quantum` cycles. Machines are picked by stride scheduling: each one
+// accumulates virtual time inversely proportional to its priority, and the
+// machine with the least virtual time runs next, ties going to the one added
+// first. A machine with priority 2 thus gets twice the cycles of one with
+// priority 1, and the interleaving only depends on the cycles consumed, so
+// it is the same on every run:
+//
+// let mut scheduler = Scheduler::new(100_000);
+// let a = scheduler.add(machine_a, 1)?;
+// let b = scheduler.add(machine_b, 2)?;
+// scheduler.run();
+// let exit_code = scheduler.result(a);
+//
+// Each machine still stops at its own max cycles, with CyclesExceeded. A
+// syscall charging cycles past the end of a slice fails and runs again in
+// the next one, as when resuming a machine stopped by max cycles.
+pub struct Scheduler<M> {
+    quantum: u64,
+    vms: Vec<Vm<M>>,
+}
+
+impl<M: Schedulable> Scheduler<M> {
+    pub fn new(quantum: u64) -> Self {
+        Self {
+            quantum: quantum.max(1),
+            vms: vec![],
+        }
+    }
+
+    pub fn quantum(&self) -> u64 {
+        self.quantum
+    }
+
+    // Adds a machine with a loaded program, it joins at the virtual time of
+    // the machines still running so it doesn't catch up on their past.
+    pub fn add(&mut self, machine: M, priority: u32) -> Result<VmId, Error> {
+        if priority == 0 {
+            return Err(Error::Unexpected(String::from(
+                "Scheduling priority must be positive",
+            )));
+        }
+        let pass = self.runnable().map(|vm| vm.pass).min().unwrap_or(0);
+        self.vms.push(Vm {
+            machine,
+            priority,
+            pass,
+            stats: VmStats::default(),
+            result: None,
+        });
+        Ok(self.vms.len() - 1)
+    }
+
+    fn runnable(&self) -> impl Iterator<Item = &Vm<M>> + '_ {
+        self.vms.iter().filter(|vm| vm.result.is_none())
+    }
+
+    // Number of machines which haven't finished.
+    pub fn pending(&self) -> usize {
+        self.runnable().count()
+    }
+
+    // Runs one slice of the next machine, and returns it, or None when all
+    // machines finished.
+    pub fn step(&mut self) -> Option<VmId> {
+        let id = self
+            .vms
+            .iter()
+            .enumerate()
+            .filter(|(_, vm)| vm.result.is_none())
+            .min_by_key(|(id, vm)| (vm.pass, *id))
+            .map(|(id, _)| id)?;
+        let quantum = self.quantum;
+        let vm = &mut self.vms[id];
+        let start = vm.machine.cycles();
+        let max_cycles = vm.machine.max_cycles();
+        let mut slice = quantum;
+        let result = loop {
+            let limit = start.saturating_add(slice).min(max_cycles);
+            vm.machine.set_max_cycles(limit);
+            let result = vm.machine.run();
+            vm.machine.set_max_cycles(max_cycles);
+            match result {
+                Err(Error::CyclesExceeded) if limit < max_cycles => {
+                    // An instruction costing more than the quantum would
+                    // never run, the slice is extended until it does.
+                    if vm.machine.cycles() == start {
+                        slice = slice.saturating_mul(2);
+                        continue;
+                    }
+                    break None;
+                }
+                result => break Some(result),
+            }
+        };
+        let consumed = vm.machine.cycles().saturating_sub(start);
+        vm.stats.cycles = vm.stats.cycles.saturating_add(consumed);
+        vm.stats.slices += 1;
+        if result.is_none() {
+            vm.stats.preemptions += 1;
+        }
+        // A slice always advances virtual time, even when it consumed no
+        // cycles.
+        vm.pass +=
+            u128::from(consumed.max(1)) * STRIDE / u128::from(quantum) / u128::from(vm.priority);
+        vm.result = result;
+        Some(id)
+    }
+
+    // Runs all machines until they finish.
+    pub fn run(&mut self) {
+        while self.step().is_some() {}
+    }
+
+    // Returns the exit code or error of a finished machine.
+    pub fn result(&self, id: VmId) -> Option<&Result<i8, Error>> {
+        self.vms.get(id).and_then(|vm| vm.result.as_ref())
+    }
+
+    pub fn stats(&self, id: VmId) -> Option<VmStats> {
+        self.vms.get(id).map(|vm| vm.stats)
+    }
+
+    pub fn machine(&self, id: VmId) -> Option<&M> {
+        self.vms.get(id).map(|vm| &vm.machine)
+    }
+
+    pub fn machine_mut(&mut self, id: VmId) -> Option<&mut M> {
+        self.vms.get_mut(id).map(|vm| &mut vm.machine)
+    }
+
+    // Returns the machines in the order they were added.
+    pub fn into_machines(self) -> Vec<M> {
+        self.vms.into_iter().map(|vm| vm.machine).collect()
+    }
+}
@@ -0,0 +1,448 @@
+use super::{
+    super::{
+        decoder::{build_decoder, DecodedInstruction, Operands},
+        instructions::Register,
+        memory::Memory,
+        Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER,
+    },
+    instrumented::{memory_access, MemoryAccessKind},
+    rvfi::register_operands,
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use ckb_vm_definitions::instructions::{self as insts, InstructionOpcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Andn,
+    Orn,
+    Xnor,
+    Rol,
+    Ror,
+    Min,
+    Minu,
+    Max,
+    Maxu,
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+    Clmul,
+    Clmulh,
+    Clmulr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Condition {
+    pub fn evaluate<R: Register>(self, left: &R, right: &R) -> bool {
+        let result = match self {
+            Condition::Eq => Register::eq(left, right),
+            Condition::Ne => Register::ne(left, right),
+            Condition::Lt => Register::lt_s(left, right),
+            Condition::Ge => Register::ge_s(left, right),
+            Condition::Ltu => Register::lt(left, right),
+            Condition::Geu => Register::ge(left, right),
+        };
+        result.to_u64() != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(usize),
+    Immediate(i32),
+}
+
+// Semantics of a RISC-V instruction, lifted from its decoded form. Immediates
+// are sign extended, and PC relative targets are resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    // rd = op(rs1, rs2). Word operations compute on the lower 32 bits and sign
+    // extend the result.
+    Binary {
+        op: BinaryOp,
+        word: bool,
+        rd: usize,
+        rs1: usize,
+        rs2: Operand,
+    },
+    // LUI and AUIPC.
+    LoadImmediate {
+        rd: usize,
+        value: u64,
+    },
+    // rd = memory[rs1 + offset], sign or zero extended.
+    Load {
+        rd: usize,
+        base: usize,
+        offset: i32,
+        size: u8,
+        signed: bool,
+    },
+    // memory[rs1 + offset] = rs2
+    Store {
+        base: usize,
+        offset: i32,
+        src: usize,
+        size: u8,
+    },
+    // LR, SC and AMO instructions on memory[rs1].
+    Atomic {
+        opcode: InstructionOpcode,
+        rd: usize,
+        address: usize,
+        src: usize,
+        size: u8,
+    },
+    Branch {
+        condition: Condition,
+        rs1: usize,
+        rs2: usize,
+        target: u64,
+    },
+    Jump {
+        rd: usize,
+        target: u64,
+    },
+    // rd = pc + length, pc = (rs1 + offset) & !1
+    JumpRegister {
+        rd: usize,
+        base: usize,
+        offset: i32,
+    },
+    Ecall,
+    Ebreak,
+    Fence,
+    // Instructions without a lifted form, e.g. unary bit manipulations. The
+    // operands are those read and written according to RVFI.
+    Other {
+        opcode: InstructionOpcode,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+    },
+}
+
+fn binary_op(opcode: InstructionOpcode) -> Option<(BinaryOp, bool)> {
+    use insts::*;
+    Some(match opcode {
+        OP_ADD | OP_ADDI => (BinaryOp::Add, false),
+        OP_SUB => (BinaryOp::Sub, false),
+        OP_SLL | OP_SLLI => (BinaryOp::Sll, false),
+        OP_SLT | OP_SLTI => (BinaryOp::Slt, false),
+        OP_SLTU | OP_SLTIU => (BinaryOp::Sltu, false),
+        OP_XOR | OP_XORI => (BinaryOp::Xor, false),
+        OP_SRL | OP_SRLI => (BinaryOp::Srl, false),
+        OP_SRA | OP_SRAI => (BinaryOp::Sra, false),
+        OP_OR | OP_ORI => (BinaryOp::Or, false),
+        OP_AND | OP_ANDI => (BinaryOp::And, false),
+        OP_MUL => (BinaryOp::Mul, false),
+        OP_MULH => (BinaryOp::Mulh, false),
+        OP_MULHSU => (BinaryOp::Mulhsu, false),
+        OP_MULHU => (BinaryOp::Mulhu, false),
+        OP_DIV => (BinaryOp::Div, false),
+        OP_DIVU => (BinaryOp::Divu, false),
+        OP_REM => (BinaryOp::Rem, false),
+        OP_REMU => (BinaryOp::Remu, false),
+        OP_ANDN => (BinaryOp::Andn, false),
+        OP_ORN => (BinaryOp::Orn, false),
+        OP_XNOR => (BinaryOp::Xnor, false),
+        OP_ROL => (BinaryOp::Rol, false),
+        OP_ROR | OP_RORI => (BinaryOp::Ror, false),
+        OP_MIN => (BinaryOp::Min, false),
+        OP_MINU => (BinaryOp::Minu, false),
+        OP_MAX => (BinaryOp::Max, false),
+        OP_MAXU => (BinaryOp::Maxu, false),
+        OP_SH1ADD => (BinaryOp::Sh1add, false),
+        OP_SH2ADD => (BinaryOp::Sh2add, false),
+        OP_SH3ADD => (BinaryOp::Sh3add, false),
+        OP_BCLR | OP_BCLRI => (BinaryOp::Bclr, false),
+        OP_BEXT | OP_BEXTI => (BinaryOp::Bext, false),
+        OP_BINV | OP_BINVI => (BinaryOp::Binv, false),
+        OP_BSET | OP_BSETI => (BinaryOp::Bset, false),
+        OP_CLMUL => (BinaryOp::Clmul, false),
+        OP_CLMULH => (BinaryOp::Clmulh, false),
+        OP_CLMULR => (BinaryOp::Clmulr, false),
+        OP_ADDW | OP_ADDIW => (BinaryOp::Add, true),
+        OP_SUBW => (BinaryOp::Sub, true),
+        OP_SLLW | OP_SLLIW => (BinaryOp::Sll, true),
+        OP_SRLW | OP_SRLIW => (BinaryOp::Srl, true),
+        OP_SRAW | OP_SRAIW => (BinaryOp::Sra, true),
+        OP_MULW => (BinaryOp::Mul, true),
+        OP_DIVW => (BinaryOp::Div, true),
+        OP_DIVUW => (BinaryOp::Divu, true),
+        OP_REMW => (BinaryOp::Rem, true),
+        OP_REMUW => (BinaryOp::Remu, true),
+        OP_ROLW => (BinaryOp::Rol, true),
+        OP_RORW | OP_RORIW => (BinaryOp::Ror, true),
+        _ => return None,
+    })
+}
+
+// Lifts a decoded instruction into its semantics, so that tools such as
+// symbolic execution engines do not need to reimplement RISC-V decoding.
+// Macro-op fusion instructions are not lifted, decode without ISA_MOP.
+pub fn lift(decoded: &DecodedInstruction) -> Operation {
+    use insts::*;
+    let pc = decoded.pc;
+    let (load_size, signed) = match decoded.opcode {
+        OP_LB_VERSION0 | OP_LB_VERSION1 => (1, true),
+        OP_LH_VERSION0 | OP_LH_VERSION1 => (2, true),
+        OP_LW_VERSION0 | OP_LW_VERSION1 => (4, true),
+        OP_LD_VERSION0 | OP_LD_VERSION1 => (8, true),
+        OP_LBU_VERSION0 | OP_LBU_VERSION1 => (1, false),
+        OP_LHU_VERSION0 | OP_LHU_VERSION1 => (2, false),
+        OP_LWU_VERSION0 | OP_LWU_VERSION1 => (4, false),
+        _ => (0, false),
+    };
+    let condition = match decoded.opcode {
+        OP_BEQ => Some(Condition::Eq),
+        OP_BNE => Some(Condition::Ne),
+        OP_BLT => Some(Condition::Lt),
+        OP_BGE => Some(Condition::Ge),
+        OP_BLTU => Some(Condition::Ltu),
+        OP_BGEU => Some(Condition::Geu),
+        _ => None,
+    };
+    match (decoded.opcode, decoded.operands) {
+        (OP_ECALL, _) => Operation::Ecall,
+        (OP_EBREAK, _) => Operation::Ebreak,
+        (OP_FENCE, _) | (OP_FENCEI, _) => Operation::Fence,
+        (OP_LUI, Operands::U { rd, imm }) => Operation::LoadImmediate {
+            rd,
+            value: i64::from(imm) as u64,
+        },
+        (OP_AUIPC, Operands::U { rd, imm }) => Operation::LoadImmediate {
+            rd,
+            value: pc.wrapping_add(i64::from(imm) as u64),
+        },
+        (OP_JAL, Operands::U { rd, imm }) => Operation::Jump {
+            rd,
+            target: pc.wrapping_add(i64::from(imm) as u64),
+        },
+        (OP_JALR_VERSION0, Operands::I { rd, rs1, imm })
+        | (OP_JALR_VERSION1, Operands::I { rd, rs1, imm }) => Operation::JumpRegister {
+            rd,
+            base: rs1,
+            offset: imm,
+        },
+        (_, Operands::I { rd, rs1, imm }) if load_size > 0 => Operation::Load {
+            rd,
+            base: rs1,
+            offset: imm,
+            size: load_size,
+            signed,
+        },
+        (OP_SB, Operands::S { rs1, rs2, imm })
+        | (OP_SH, Operands::S { rs1, rs2, imm })
+        | (OP_SW, Operands::S { rs1, rs2, imm })
+        | (OP_SD, Operands::S { rs1, rs2, imm }) => Operation::Store {
+            base: rs1,
+            offset: imm,
+            src: rs2,
+            size: match decoded.opcode {
+                OP_SB => 1,
+                OP_SH => 2,
+                OP_SW => 4,
+                _ => 8,
+            },
+        },
+        (OP_LR_W..=OP_AMOMAXU_D, Operands::R { rd, rs1, rs2 }) => Operation::Atomic {
+            opcode: decoded.opcode,
+            rd,
+            address: rs1,
+            src: rs2,
+            size: if decoded.opcode <= OP_AMOMAXU_W { 4 } else { 8 },
+        },
+        (_, Operands::S { rs1, rs2, imm }) if condition.is_some() => Operation::Branch {
+            condition: condition.unwrap(),
+            rs1,
+            rs2,
+            target: pc.wrapping_add(i64::from(imm) as u64),
+        },
+        (opcode, Operands::R { rd, rs1, rs2 }) if binary_op(opcode).is_some() => {
+            let (op, word) = binary_op(opcode).unwrap();
+            Operation::Binary {
+                op,
+                word,
+                rd,
+                rs1,
+                rs2: Operand::Register(rs2),
+            }
+        }
+        (opcode, Operands::I { rd, rs1, imm }) if binary_op(opcode).is_some() => {
+            let (op, word) = binary_op(opcode).unwrap();
+            Operation::Binary {
+                op,
+                word,
+                rd,
+                rs1,
+                rs2: Operand::Immediate(imm),
+            }
+        }
+        (opcode, _) => {
+            let (rd, rs1, rs2) = register_operands(decoded);
+            Operation::Other {
+                opcode,
+                rd,
+                rs1,
+                rs2,
+            }
+        }
+    }
+}
+
+// A conditional branch about to be executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchDecision {
+    pub pc: u64,
+    pub condition: Condition,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub target: u64,
+    // Direction taken with the concrete register values.
+    pub taken: bool,
+}
+
+// Hooks invoked by run_symbolic, with the machine in its state before the
+// instruction for operation, reads and branches, and after the instruction
+// for writes. All hooks come with an empty default implementation.
+pub trait SymbolicHooks<M> {
+    fn operation(&mut self, _machine: &M, _pc: u64, _operation: &Operation) {}
+    // Reads of x0 are not reported.
+    fn register_read(&mut self, _machine: &M, _index: usize, _value: u64) {}
+    fn register_write(&mut self, _machine: &M, _index: usize, _value: u64) {}
+    fn memory_read(&mut self, _machine: &M, _address: u64, _size: u8, _value: u64) {}
+    fn memory_write(&mut self, _machine: &M, _address: u64, _size: u8, _value: u64) {}
+    // Returns Some(taken) to force the direction of the branch, e.g. to
+    // explore the path not taken by the concrete execution.
+    fn branch(&mut self, _machine: &M, _branch: &BranchDecision) -> Option<bool> {
+        None
+    }
+}
+
+fn load_value<M: Memory>(memory: &mut M, address: u64, size: u8) -> Result<u64, Error> {
+    let bytes = memory.load_bytes(address, u64::from(size))?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte)))
+}
+
+// Runs a loaded program one instruction at a time, invoking `hooks` for each
+// lifted operation, register and memory access, and branch decision. Register
+// writes done by syscalls are reported, memory writes done by syscalls are
+// not.
+pub fn run_symbolic<Inner: SupportMachine, H: SymbolicHooks<DefaultMachine<Inner>>>(
+    machine: &mut DefaultMachine<Inner>,
+    hooks: &mut H,
+) -> Result<i8, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    machine.set_running(true);
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let decoded = DecodedInstruction::from_instruction(pc, instruction)?;
+        let operation = lift(&decoded);
+        hooks.operation(machine, pc, &operation);
+
+        let (rd, rs1, rs2) = register_operands(&decoded);
+        for index in [rs1, rs2] {
+            if index != 0 {
+                let value = machine.registers()[index].to_u64();
+                hooks.register_read(machine, index, value);
+            }
+        }
+        let access = memory_access(instruction, machine.registers());
+        if let Some(access) = access {
+            if access.kind != MemoryAccessKind::Store {
+                let value = load_value(machine.memory_mut(), access.address, access.size)?;
+                hooks.memory_read(machine, access.address, access.size, value);
+            }
+        }
+        let mut forced = None;
+        if let Operation::Branch {
+            condition,
+            rs1,
+            rs2,
+            target,
+        } = operation
+        {
+            let registers = machine.registers();
+            let taken = condition.evaluate(&registers[rs1], &registers[rs2]);
+            let decision = BranchDecision {
+                pc,
+                condition,
+                rs1,
+                rs2,
+                target,
+                taken,
+            };
+            forced = hooks
+                .branch(machine, &decision)
+                .filter(|t| *t != taken)
+                .map(|t| {
+                    if t {
+                        target
+                    } else {
+                        pc + u64::from(decoded.length)
+                    }
+                });
+        }
+        let before: Vec<u64> = machine.registers().iter().map(|r| r.to_u64()).collect();
+
+        machine.step_instruction(&mut decoder)?;
+
+        if let Some(next_pc) = forced {
+            machine.update_pc(Inner::REG::from_u64(next_pc));
+            machine.commit_pc();
+        }
+        if operation == Operation::Ecall {
+            for (index, value) in before.iter().enumerate().skip(1) {
+                let after = machine.registers()[index].to_u64();
+                if after != *value {
+                    hooks.register_write(machine, index, after);
+                }
+            }
+        } else if rd != 0 && rd < RISCV_GENERAL_REGISTER_NUMBER {
+            let value = machine.registers()[rd].to_u64();
+            hooks.register_write(machine, rd, value);
+        }
+        if let Some(access) = access {
+            if access.kind != MemoryAccessKind::Load {
+                let value = load_value(machine.memory_mut(), access.address, access.size)?;
+                hooks.memory_write(machine, access.address, access.size, value);
+            }
+        }
+    }
+    Ok(machine.exit_code())
+}
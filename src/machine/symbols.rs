@@ -0,0 +1,95 @@
+#[cfg(feature = "elf")]
+use super::super::Error;
+#[cfg(feature = "elf")]
+use bytes::Bytes;
+
+// Function symbols of a guest program, used to attribute profiles to guest
+// functions:
+//
+// let symbols = Symbols::from_elf(&program)?;
+// assert_eq!(symbols.lookup(pc), Some("main"));
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    // (start, end, name), sorted by start address.
+    functions: Vec<(u64, u64, String)>,
+}
+
+impl Symbols {
+    // Builds the symbols from (address, size, name) triples. Functions of
+    // size 0, e.g. from assembly, extend to the next function.
+    pub fn new(functions: Vec<(u64, u64, String)>) -> Self {
+        let mut functions: Vec<_> = functions
+            .into_iter()
+            .map(|(address, size, name)| (address, address.saturating_add(size), name))
+            .collect();
+        functions.sort_by_key(|(start, _, _)| *start);
+        functions.dedup_by_key(|(start, _, _)| *start);
+        let next_starts: Vec<u64> = functions
+            .iter()
+            .skip(1)
+            .map(|(start, _, _)| *start)
+            .chain(Some(u64::MAX))
+            .collect();
+        for (function, next_start) in functions.iter_mut().zip(next_starts) {
+            if function.0 == function.1 {
+                function.1 = next_start;
+            }
+        }
+        Self { functions }
+    }
+
+    // Reads the function symbols in the symbol table of an ELF program.
+    // Stripped programs have no symbols.
+    #[cfg(feature = "elf")]
+    pub fn from_elf(program: &Bytes) -> Result<Self, Error> {
+        let elf = goblin_v040::elf::Elf::parse(program)?;
+        let functions = elf
+            .syms
+            .iter()
+            .filter(|sym| sym.st_type() == goblin_v040::elf::sym::STT_FUNC && sym.st_value != 0)
+            .filter_map(|sym| {
+                let name = elf.strtab.get_at(sym.st_name)?;
+                Some((sym.st_value, sym.st_size, name.to_string()))
+            })
+            .collect();
+        Ok(Self::new(functions))
+    }
+
+    // Moves the functions by `bias`, for programs loaded away from their
+    // link addresses, see DefaultMachine::load_bias.
+    pub fn relocated(mut self, bias: u64) -> Self {
+        for (start, end, _) in &mut self.functions {
+            *start = start.wrapping_add(bias);
+            *end = end.wrapping_add(bias);
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    // Returns the index of the function holding `pc`, see name.
+    pub fn index(&self, pc: u64) -> Option<usize> {
+        let position = self.functions.partition_point(|(start, _, _)| *start <= pc);
+        let index = position.checked_sub(1)?;
+        if pc < self.functions[index].1 {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.functions[index].2
+    }
+
+    // Returns the name of the function holding `pc`.
+    pub fn lookup(&self, pc: u64) -> Option<&str> {
+        self.index(pc).map(|index| self.name(index))
+    }
+}
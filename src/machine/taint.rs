@@ -0,0 +1,235 @@
+use super::{
+    super::{
+        decoder::{build_decoder, DecodedInstruction},
+        instructions::Register,
+        memory::Memory,
+        registers::{A0, A1, A7},
+        Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER,
+    },
+    instrumented::{memory_access, MemoryAccessKind},
+    rvfi::register_operands,
+    CoreMachine, DefaultMachine, SupportMachine,
+};
+use ckb_vm_definitions::instructions as insts;
+use std::collections::HashMap;
+
+// A set of taint labels, one bit per label.
+pub type TaintLabels = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaintSink {
+    // A conditional branch comparing tainted registers.
+    Branch,
+    // JALR jumping to a tainted address.
+    IndirectJump,
+    // A tainted register among the syscall arguments A0 to A7, or the
+    // syscall number itself.
+    SyscallArgument(usize),
+}
+
+// Tainted data reaching a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintReport {
+    pub pc: u64,
+    pub sink: TaintSink,
+    pub labels: TaintLabels,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SyscallSource {
+    number: u64,
+    labels: TaintLabels,
+    buffer: bool,
+}
+
+// Shadow state tracking taint labels of registers and memory bytes. Labels
+// are introduced by sources, propagate through data flow(not through control
+// flow, nor through address computations), and are reported when they reach
+// a sink.
+//
+// let mut tracker = TaintTracker::default();
+// tracker.taint_syscall(2061, 0b1, true);
+// run_taint(&mut machine, &mut tracker)?;
+// for report in &tracker.reports { ... }
+#[derive(Debug, Clone, Default)]
+pub struct TaintTracker {
+    registers: [TaintLabels; RISCV_GENERAL_REGISTER_NUMBER],
+    memory: HashMap<u64, TaintLabels>,
+    syscalls: Vec<SyscallSource>,
+    pub reports: Vec<TaintReport>,
+}
+
+impl TaintTracker {
+    pub fn taint_register(&mut self, index: usize, labels: TaintLabels) {
+        if index != 0 {
+            self.registers[index] |= labels;
+        }
+    }
+
+    pub fn taint_memory(&mut self, address: u64, size: u64, labels: TaintLabels) {
+        for i in 0..size {
+            *self.memory.entry(address.wrapping_add(i)).or_insert(0) |= labels;
+        }
+    }
+
+    // Taints the result of a syscall. When `buffer` is set, the syscall is
+    // assumed to follow the CKB convention of loading data into a buffer
+    // pointed to by A0, with A1 pointing to the buffer length, and the loaded
+    // bytes are tainted. Otherwise the returned A0 is tainted.
+    pub fn taint_syscall(&mut self, number: u64, labels: TaintLabels, buffer: bool) {
+        self.syscalls.push(SyscallSource {
+            number,
+            labels,
+            buffer,
+        });
+    }
+
+    pub fn register_labels(&self, index: usize) -> TaintLabels {
+        self.registers[index]
+    }
+
+    pub fn memory_labels(&self, address: u64, size: u64) -> TaintLabels {
+        (0..size)
+            .filter_map(|i| self.memory.get(&address.wrapping_add(i)))
+            .fold(0, |acc, labels| acc | labels)
+    }
+
+    fn set_register(&mut self, index: usize, labels: TaintLabels) {
+        if index != 0 {
+            self.registers[index] = labels;
+        }
+    }
+
+    fn set_memory(&mut self, address: u64, size: u64, labels: TaintLabels) {
+        for i in 0..size {
+            let address = address.wrapping_add(i);
+            if labels == 0 {
+                self.memory.remove(&address);
+            } else {
+                self.memory.insert(address, labels);
+            }
+        }
+    }
+
+    fn report(&mut self, pc: u64, sink: TaintSink, labels: TaintLabels) {
+        if labels != 0 {
+            self.reports.push(TaintReport { pc, sink, labels });
+        }
+    }
+}
+
+fn load_u64<M: Memory>(memory: &mut M, address: u64) -> Option<u64> {
+    let bytes = memory.load_bytes(address, 8).ok()?;
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes);
+    Some(u64::from_le_bytes(value))
+}
+
+// Runs a loaded program one instruction at a time, propagating taint labels
+// in `tracker`. Macro-op fusion is disabled so propagation follows RISC-V
+// semantics.
+pub fn run_taint<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    tracker: &mut TaintTracker,
+) -> Result<i8, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa() & !ISA_MOP, machine.version());
+    machine.set_running(true);
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let decoded = DecodedInstruction::from_instruction(pc, instruction)?;
+        let (rd, rs1, rs2) = register_operands(&decoded);
+        let sources = tracker.registers[rs1] | tracker.registers[rs2];
+        let access = memory_access(instruction, machine.registers());
+        // Buffer and original length of a syscall loading data.
+        let mut syscall_buffer = None;
+        match decoded.opcode {
+            insts::OP_BEQ
+            | insts::OP_BNE
+            | insts::OP_BLT
+            | insts::OP_BGE
+            | insts::OP_BLTU
+            | insts::OP_BGEU => tracker.report(pc, TaintSink::Branch, sources),
+            insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+                tracker.report(pc, TaintSink::IndirectJump, tracker.registers[rs1])
+            }
+            insts::OP_ECALL => {
+                for index in A0..=A7 {
+                    let labels = tracker.registers[index];
+                    tracker.report(pc, TaintSink::SyscallArgument(index), labels);
+                }
+                let number = machine.registers()[A7].to_u64();
+                if tracker
+                    .syscalls
+                    .iter()
+                    .any(|s| s.number == number && s.buffer)
+                {
+                    let address = machine.registers()[A0].to_u64();
+                    let length_address = machine.registers()[A1].to_u64();
+                    if let Some(length) = load_u64(machine.memory_mut(), length_address) {
+                        syscall_buffer = Some((address, length_address, length));
+                    }
+                }
+            }
+            _ => (),
+        }
+        machine.step_instruction(&mut decoder)?;
+        match (decoded.opcode, access) {
+            (_, Some(access)) => {
+                let size = u64::from(access.size);
+                let memory = tracker.memory_labels(access.address, size);
+                match access.kind {
+                    MemoryAccessKind::Load => tracker.set_register(rd, memory),
+                    MemoryAccessKind::Store => {
+                        tracker.set_memory(access.address, size, tracker.registers[rs2])
+                    }
+                    MemoryAccessKind::Atomic => {
+                        let stored = match decoded.opcode {
+                            insts::OP_LR_W | insts::OP_LR_D => memory,
+                            insts::OP_SC_W
+                            | insts::OP_SC_D
+                            | insts::OP_AMOSWAP_W
+                            | insts::OP_AMOSWAP_D => tracker.registers[rs2],
+                            _ => memory | tracker.registers[rs2],
+                        };
+                        let loaded = match decoded.opcode {
+                            // SC returns a status code.
+                            insts::OP_SC_W | insts::OP_SC_D => 0,
+                            _ => memory,
+                        };
+                        tracker.set_memory(access.address, size, stored);
+                        tracker.set_register(rd, loaded);
+                    }
+                }
+            }
+            // Return addresses are not tainted.
+            (insts::OP_JAL, _) | (insts::OP_JALR_VERSION0, _) | (insts::OP_JALR_VERSION1, _) => {
+                tracker.set_register(rd, 0)
+            }
+            (insts::OP_ECALL, _) => {
+                // A0 holds the result of the syscall now.
+                tracker.set_register(A0, 0);
+                let number = machine.registers()[A7].to_u64();
+                for source in tracker.syscalls.clone() {
+                    if source.number != number {
+                        continue;
+                    }
+                    match syscall_buffer {
+                        Some((address, length_address, length)) if source.buffer => {
+                            // The syscall stores the full data length, of
+                            // which at most the buffer length is loaded.
+                            let loaded = load_u64(machine.memory_mut(), length_address)
+                                .unwrap_or(0)
+                                .min(length);
+                            tracker.taint_memory(address, loaded, source.labels);
+                        }
+                        _ if !source.buffer => tracker.taint_register(A0, source.labels),
+                        _ => (),
+                    }
+                }
+            }
+            _ => tracker.set_register(rd, sources),
+        }
+    }
+    Ok(machine.exit_code())
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use super::{
+    super::{decoder::PredecodedCode, Error},
+    layout::AddressSpaceLayout,
+    privileged::Privileged,
+    program::ProgramInfo,
+    DefaultMachine, DefaultMachineBuilder, SupportMachine,
+};
+
+// State DefaultMachine keeps about its loaded program, outside of the core.
+#[derive(Clone)]
+pub(crate) struct LoadedProgram {
+    pub(crate) compressed: bool,
+    pub(crate) predecoded: Option<Arc<PredecodedCode>>,
+    pub(crate) loaded_layout: Option<AddressSpaceLayout>,
+    pub(crate) code_segments: Vec<(u64, u64)>,
+    pub(crate) program_info: Option<ProgramInfo>,
+    pub(crate) privileged: Privileged,
+}
+
+// A machine frozen after its program was loaded, and possibly after some
+// setup code ran, which is instantiated into runnable machines without
+// parsing the ELF or initializing the stack again. Instances are copies of
+// the core of the template, so they are as cheap as cloning its memory:
+// with SharedMemory all pages are shared and copied on write.
+//
+// let mut machine = DefaultMachineBuilder::new(core).build();
+// machine.load_program(&program, &args)?;
+// let template = MachineTemplate::new(machine);
+// let mut instance = template.instantiate(|builder| {
+//     builder.syscall(Box::new(CustomSyscall::new()))
+// })?;
+// instance.set_max_cycles(max_cycles);
+// instance.run()?;
+//
+// Instances start with the cycles and max cycles of the template. Options
+// of the template machine aren't copied, each instance gets its own from
+// `instantiate`, syscalls are initialized there.
+pub struct MachineTemplate<Inner> {
+    inner: Inner,
+    program: LoadedProgram,
+}
+
+impl<Inner: SupportMachine + Clone> MachineTemplate<Inner> {
+    pub fn new(machine: DefaultMachine<Inner>) -> Self {
+        let program = machine.loaded_program();
+        Self {
+            inner: machine.take_inner(),
+            program,
+        }
+    }
+
+    pub fn inner(&self) -> &Inner {
+        &self.inner
+    }
+
+    // Core of the template, changes apply to instances created afterwards.
+    pub fn inner_mut(&mut self) -> &mut Inner {
+        &mut self.inner
+    }
+
+    pub fn program_info(&self) -> Option<&ProgramInfo> {
+        self.program.program_info.as_ref()
+    }
+
+    // Builds a machine running from the state of the template, `configure`
+    // sets the options of the machine, like with a DefaultMachineBuilder
+    // created by hand.
+    pub fn instantiate<F>(&self, configure: F) -> Result<DefaultMachine<Inner>, Error>
+    where
+        F: FnOnce(DefaultMachineBuilder<Inner>) -> DefaultMachineBuilder<Inner>,
+    {
+        let mut machine = configure(DefaultMachineBuilder::new(self.inner.clone())).build();
+        machine.restore_loaded_program(&self.program)?;
+        Ok(machine)
+    }
+}
+
+impl<Inner: SupportMachine + Clone> DefaultMachine<Inner> {
+    // Creates a child machine continuing from the current state of this
+    // one, e.g. to explore different paths from a common prefix of
+    // execution. The child gets a clone of the core, registers, cycles and
+    // retired instructions included, and writes of either machine are never
+    // seen by the other. Only SharedMemory makes this cheap: forking copies
+    // page references and pages written afterwards are copied on write,
+    // while other memories copy all of their contents on every fork. Like
+    // template instances, the child gets its options from `configure`:
+    //
+    // machine.run_until(branch)?;
+    // let mut child = machine.fork(|builder| builder.syscall(Box::new(syscalls)))?;
+    // child.set_register(A0, 1);
+    // child.run()?;
+    // machine.run()?;
+    pub fn fork<F>(&self, configure: F) -> Result<DefaultMachine<Inner>, Error>
+    where
+        F: FnOnce(DefaultMachineBuilder<Inner>) -> DefaultMachineBuilder<Inner>,
+    {
+        let mut child = configure(DefaultMachineBuilder::new(self.inner.clone())).build();
+        child.restore_loaded_program(&self.loaded_program())?;
+        child.exit_code = self.exit_code;
+        child.instret = self.instret;
+        Ok(child)
+    }
+}
@@ -0,0 +1,201 @@
+use super::super::{
+    decoder::DecodedInstruction,
+    instructions::{instruction_length, insts, Instruction},
+};
+use super::rvfi::register_operands;
+
+// Estimates the time real hardware would take to run guest code, as opposed
+// to the cycles charged by instruction_cycle_func, which are part of
+// consensus and must not change. A model is driven by run_with_report with
+// every retired instruction, and its estimate is reported next to the
+// consensus cycles:
+//
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .timing_model(Box::new(PipelineTimingModel::default()))
+//     .build();
+// let report = machine.run_with_report(false);
+// println!("{} cycles, {:?} on silicon", report.cycles, report.timing_cycles);
+pub trait TimingModel: Send + Sync {
+    // Called with each retired instruction, its PC and the PC of the next
+    // instruction, which tells whether branches were taken.
+    fn retire(&mut self, instruction: Instruction, pc: u64, next_pc: u64);
+
+    // Cycles estimated for all instructions retired so far.
+    fn cycles(&self) -> u64;
+}
+
+// Penalties of PipelineTimingModel, in cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    // Stall of an instruction using the result of the load right before it.
+    pub load_use_penalty: u64,
+    // Flush after a taken conditional branch. Branches are predicted not
+    // taken.
+    pub taken_branch_penalty: u64,
+    // Flush after JAL, whose target is known at decode.
+    pub jump_penalty: u64,
+    // Flush after JALR, whose target is only known at execute.
+    pub indirect_jump_penalty: u64,
+    // Latencies of multiplications and divisions, which are not pipelined.
+    pub mul_latency: u64,
+    pub div_latency: u64,
+    // Flush after ECALL and EBREAK, the cost of the environment is not
+    // modeled.
+    pub system_penalty: u64,
+}
+
+// A classic 5-stage in-order pipeline with full forwarding, retiring one
+// instruction per cycle unless it stalls.
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            load_use_penalty: 1,
+            taken_branch_penalty: 2,
+            jump_penalty: 1,
+            indirect_jump_penalty: 2,
+            mul_latency: 3,
+            div_latency: 34,
+            system_penalty: 4,
+        }
+    }
+}
+
+// Simple in-order pipeline model, see PipelineConfig for what it accounts
+// for. Caches are assumed to always hit.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineTimingModel {
+    config: PipelineConfig,
+    cycles: u64,
+    instructions: u64,
+    stalls: u64,
+    // Destination register of the previous instruction if it was a load.
+    pending_load: Option<usize>,
+}
+
+impl PipelineTimingModel {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // Cycles lost to stalls and flushes.
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    // Cycles per instruction so far.
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            self.cycles as f64 / self.instructions as f64
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+impl TimingModel for PipelineTimingModel {
+    fn retire(&mut self, instruction: Instruction, pc: u64, next_pc: u64) {
+        let decoded = match DecodedInstruction::from_instruction(pc, instruction) {
+            Ok(decoded) => decoded,
+            // Instructions of extensions the decoder doesn't describe take a
+            // cycle.
+            Err(_) => {
+                self.instructions += 1;
+                self.cycles += 1;
+                self.pending_load = None;
+                return;
+            }
+        };
+        let (rd, rs1, rs2) = register_operands(&decoded);
+        let config = &self.config;
+        let mut penalty = match self.pending_load {
+            Some(load) if load == rs1 || load == rs2 => config.load_use_penalty,
+            _ => 0,
+        };
+        let fallthrough = pc.wrapping_add(u64::from(instruction_length(instruction)));
+        penalty += match decoded.opcode {
+            insts::OP_BEQ
+            | insts::OP_BNE
+            | insts::OP_BLT
+            | insts::OP_BGE
+            | insts::OP_BLTU
+            | insts::OP_BGEU
+                if next_pc != fallthrough =>
+            {
+                config.taken_branch_penalty
+            }
+            insts::OP_JAL | insts::OP_FAR_JUMP_REL => config.jump_penalty,
+            insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 | insts::OP_FAR_JUMP_ABS => {
+                config.indirect_jump_penalty
+            }
+            insts::OP_MUL
+            | insts::OP_MULW
+            | insts::OP_MULH
+            | insts::OP_MULHU
+            | insts::OP_MULHSU
+            | insts::OP_WIDE_MUL
+            | insts::OP_WIDE_MULU
+            | insts::OP_WIDE_MULSU => config.mul_latency.saturating_sub(1),
+            insts::OP_DIV
+            | insts::OP_DIVW
+            | insts::OP_DIVU
+            | insts::OP_DIVUW
+            | insts::OP_REM
+            | insts::OP_REMW
+            | insts::OP_REMU
+            | insts::OP_REMUW
+            | insts::OP_WIDE_DIV
+            | insts::OP_WIDE_DIVU => config.div_latency.saturating_sub(1),
+            insts::OP_ECALL | insts::OP_EBREAK => config.system_penalty,
+            _ => 0,
+        };
+        self.pending_load = if is_load(decoded.opcode) && rd != 0 {
+            Some(rd)
+        } else {
+            None
+        };
+        self.instructions += 1;
+        self.stalls += penalty;
+        self.cycles += 1 + penalty;
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+fn is_load(opcode: insts::InstructionOpcode) -> bool {
+    matches!(
+        opcode,
+        insts::OP_LB_VERSION0
+            | insts::OP_LB_VERSION1
+            | insts::OP_LBU_VERSION0
+            | insts::OP_LBU_VERSION1
+            | insts::OP_LH_VERSION0
+            | insts::OP_LH_VERSION1
+            | insts::OP_LHU_VERSION0
+            | insts::OP_LHU_VERSION1
+            | insts::OP_LW_VERSION0
+            | insts::OP_LW_VERSION1
+            | insts::OP_LWU_VERSION0
+            | insts::OP_LWU_VERSION1
+            | insts::OP_LD_VERSION0
+            | insts::OP_LD_VERSION1
+            | insts::OP_LR_W
+            | insts::OP_LR_D
+    )
+}
@@ -1,41 +1,126 @@
 use super::{
     super::{
-        decoder::build_decoder,
+        decoder::{DecodedInstruction, Decoder},
         instructions::{
-            execute, instruction_length, is_basic_block_end_instruction, Instruction, Register,
+            execute, extract_opcode, instruction_length, insts, is_basic_block_end_instruction,
+            Instruction, Register,
         },
+        observer::Event,
         Error,
     },
+    arena::{TraceCacheConfig, TraceEviction},
+    float::FloatRegisters,
+    privileged::Privileged,
+    report::{ExecutionReport, ReportCollector},
     CoreMachine, DefaultMachine, Machine, SupportMachine,
 };
+#[cfg(feature = "elf")]
 use bytes::Bytes;
 
-// The number of trace items to keep
-const TRACE_SIZE: usize = 8192;
-// Quick bit-mask to truncate a value in trace size range
-const TRACE_MASK: usize = TRACE_SIZE - 1;
 // The maximum number of instructions to cache in a trace item
 const TRACE_ITEM_LENGTH: usize = 16;
 // Shifts to truncate a value so 2 traces has the minimal chance of sharing code.
 const TRACE_ADDRESS_SHIFTS: usize = 2;
+// Traces run between two relayouts of the trace cache, see
+// Arena::relayout_traces.
+const TRACE_RELAYOUT_INTERVAL: u64 = 1 << 16;
+
+// The target a JALR ending a trace jumped to last time, together with the
+// position of the trace found there. Evicting a trace, resetting the cache on
+// invalidate_code and relayouts all replace the trace at a position, which is
+// detected by comparing trace ids, so a stale target is never run.
+#[derive(Clone, Copy)]
+struct JalrCache {
+    target: u64,
+    slot: usize,
+    id: u64,
+}
 
 #[derive(Default)]
-struct Trace {
+pub(crate) struct Trace {
     address: u64,
     length: usize,
     instruction_count: u8,
     instructions: [Instruction; TRACE_ITEM_LENGTH],
+    // Cycles of all instructions, charged at once when the trace runs.
+    cycles: u64,
+    // Unique among traces of a machine, 0 for empty traces.
+    id: u64,
+    // Times this trace ran, halved at each relayout.
+    pub(crate) executions: u64,
+    // Value of the trace clock of the machine when this trace last ran.
+    last_used: u64,
+    jalr_cache: Option<JalrCache>,
 }
 
+impl Trace {
+    fn ends_with_jalr(&self) -> bool {
+        match self.instruction_count {
+            0 => false,
+            count => matches!(
+                extract_opcode(self.instructions[count as usize - 1]),
+                insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1
+            ),
+        }
+    }
+}
+
+// First slot of the set `addr` maps to.
 #[inline(always)]
-fn calculate_slot(addr: u64) -> usize {
-    (addr as usize >> TRACE_ADDRESS_SHIFTS) & TRACE_MASK
+fn calculate_set(addr: u64, config: &TraceCacheConfig) -> usize {
+    ((addr as usize >> TRACE_ADDRESS_SHIFTS) & (config.sets() - 1)) * config.ways
+}
+
+// Statistics of the trace cache of a TraceMachine, since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceStats {
+    // Traces found in the cache, including the ones found by the JALR cache.
+    pub hits: u64,
+    // Traces taken from the cache of the JALR ending the previous trace,
+    // without looking them up.
+    pub jalr_hits: u64,
+    // Traces decoded because they were not in the cache.
+    pub misses: u64,
+    // Misses which replaced another trace.
+    pub evictions: u64,
+}
+
+// A trace formed by TraceMachine, which is a basic block, or the first
+// TRACE_ITEM_LENGTH instructions of a longer basic block.
+pub struct TraceBlock<'a> {
+    pub address: u64,
+    // Length of the code covered by this trace in bytes.
+    pub length: usize,
+    pub instructions: &'a [Instruction],
+    // Cycles of all instructions in this trace, as computed by the
+    // instruction cycle function of the machine.
+    pub cycles: u64,
+}
+
+impl<'a> TraceBlock<'a> {
+    // Decodes the instructions in this trace into the stable structured form.
+    pub fn decoded(&self) -> Result<Vec<DecodedInstruction>, Error> {
+        let mut pc = self.address;
+        self.instructions
+            .iter()
+            .map(|i| {
+                let decoded = DecodedInstruction::from_instruction(pc, *i);
+                pc += u64::from(instruction_length(*i));
+                decoded
+            })
+            .collect()
+    }
 }
 
 pub struct TraceMachine<Inner> {
     pub machine: DefaultMachine<Inner>,
 
-    traces: Vec<Trace>,
+    last_trace_id: u64,
+    // Traces run since the last relayout of the trace cache.
+    traces_since_relayout: u64,
+    // Ticks each time a trace runs, see Trace::last_used.
+    trace_clock: u64,
+    stats: TraceStats,
 }
 
 impl<Inner: SupportMachine> CoreMachine for TraceMachine<Inner> {
@@ -70,13 +155,31 @@ impl<Inner: SupportMachine> CoreMachine for TraceMachine<Inner> {
         self.machine.set_register(idx, value)
     }
 
-    fn isa(&self) -> u8 {
+    fn isa(&self) -> u64 {
         self.machine.isa()
     }
 
     fn version(&self) -> u32 {
         self.machine.version()
     }
+
+    fn float_registers(&self) -> Option<&FloatRegisters> {
+        self.machine.float_registers()
+    }
+
+    fn float_registers_mut(&mut self) -> Option<&mut FloatRegisters> {
+        self.machine.float_registers_mut()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers(&self) -> Option<&super::vector::VectorRegisters> {
+        self.machine.vector_registers()
+    }
+
+    #[cfg(feature = "rvv")]
+    fn vector_registers_mut(&mut self) -> Option<&mut super::vector::VectorRegisters> {
+        self.machine.vector_registers_mut()
+    }
 }
 
 impl<Inner: SupportMachine> Machine for TraceMachine<Inner> {
@@ -87,59 +190,300 @@ impl<Inner: SupportMachine> Machine for TraceMachine<Inner> {
     fn ebreak(&mut self) -> Result<(), Error> {
         self.machine.ebreak()
     }
+
+    fn privileged_mut(&mut self) -> Option<&mut Privileged> {
+        self.machine.privileged_mut()
+    }
+
+    fn read_provided_csr(&mut self, csr: u16) -> Option<u64> {
+        self.machine.read_provided_csr(csr)
+    }
+
+    fn write_provided_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        self.machine.write_provided_csr(csr, value)
+    }
 }
 
 impl<Inner: SupportMachine> TraceMachine<Inner> {
     pub fn new(machine: DefaultMachine<Inner>) -> Self {
         Self {
             machine,
-            traces: vec![],
+            last_trace_id: 0,
+            traces_since_relayout: 0,
+            trace_clock: 0,
+            stats: TraceStats::default(),
         }
     }
 
+    #[cfg(feature = "elf")]
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
         self.machine.load_program(program, args)
     }
 
+    // See DefaultMachine::reload_program.
+    #[cfg(feature = "elf")]
+    pub fn reload_program(&mut self, program: &Bytes) -> Result<u64, Error> {
+        self.machine.reload_program(program)
+    }
+
     pub fn run(&mut self) -> Result<i8, Error> {
-        let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+        let start_cycles = self.machine.start_run();
+        let result = self.run_inner(|_, _| true);
+        self.machine.report_metrics(start_cycles, &result);
+        result
+    }
+
+    // Same as run, but returns a report with statistics of this run. Per
+    // opcode statistics are only collected when `opcode_stats` is true.
+    pub fn run_with_report(&mut self, opcode_stats: bool) -> ExecutionReport {
+        let start_cycles = self.machine.start_run();
+        let mut collector = ReportCollector::new(&mut self.machine, start_cycles, opcode_stats);
+        let result = self.run_inner(|i, pc| {
+            collector.retire(i, pc);
+            true
+        });
+        self.machine.report_metrics(start_cycles, &result);
+        collector.finish(result, &mut self.machine)
+    }
+
+    // See DefaultMachine::run_until.
+    pub fn run_until(&mut self, address: u64) -> Result<Option<i8>, Error> {
+        let start_cycles = self.machine.start_run();
+        let mut reached = false;
+        let result = self.run_inner(|_, pc| {
+            reached = pc == address;
+            !reached
+        });
+        self.machine.report_metrics(start_cycles, &result);
+        let exit_code = result?;
+        if reached {
+            Ok(None)
+        } else {
+            Ok(Some(exit_code))
+        }
+    }
+
+    // Returns the traces formed so far, sorted by address. Traces are formed
+    // lazily as the program executes, so only code that has been run is
+    // covered, and traces evicted from the cache are not included.
+    pub fn traces(&self) -> Vec<TraceBlock<'_>> {
+        let mut traces: Vec<TraceBlock> = self
+            .machine
+            .arena
+            .traces
+            .iter()
+            .filter(|t| t.instruction_count > 0)
+            .map(|t| TraceBlock {
+                address: t.address,
+                length: t.length,
+                instructions: &t.instructions[..t.instruction_count as usize],
+                cycles: t.cycles,
+            })
+            .collect();
+        traces.sort_by_key(|t| t.address);
+        traces
+    }
+
+    pub fn trace_stats(&self) -> TraceStats {
+        self.stats
+    }
+
+    // Returns the position of the trace starting at `pc`, decoding it first
+    // if it is not in the cache.
+    fn lookup_trace(&mut self, decoder: &mut Decoder, pc: u64) -> Result<usize, Error> {
+        let config = self.machine.trace_cache;
+        let first = calculate_set(pc, &config);
+        // Empty slots are filled first, then the slot with the lowest age
+        // is evicted.
+        let mut victim = (self.machine.arena.trace_position(first), u64::MAX);
+        for way in 0..config.ways {
+            let position = self.machine.arena.trace_position(first + way);
+            let trace = &self.machine.arena.traces[position];
+            if trace.instruction_count == 0 {
+                if victim.1 > 0 {
+                    victim = (position, 0);
+                }
+                continue;
+            }
+            if trace.address == pc {
+                self.stats.hits += 1;
+                if let Some(metrics) = &mut self.machine.metrics {
+                    metrics.trace_cache_hit();
+                }
+                return Ok(position);
+            }
+            let age = match config.eviction {
+                TraceEviction::Fifo => trace.id,
+                TraceEviction::Lru => trace.last_used,
+            };
+            if age < victim.1 {
+                victim = (position, age);
+            }
+        }
+        let slot = victim.0;
+        self.stats.misses += 1;
+        if self.machine.arena.traces[slot].instruction_count > 0 {
+            self.stats.evictions += 1;
+        }
+        if let Some(metrics) = &mut self.machine.metrics {
+            metrics.trace_cache_miss();
+        }
+        self.machine.arena.traces[slot] = Trace::default();
+        let mut current_pc = pc;
+        let mut i = 0;
+        while i < TRACE_ITEM_LENGTH {
+            let instruction = match decoder.decode(self.machine.memory_mut(), current_pc) {
+                Ok(instruction) => instruction,
+                // The trace ends before an instruction left to the trap
+                // handler, which gets it once the trace ran.
+                Err(Error::InvalidInstruction { .. })
+                    if i > 0 && self.machine.handles_invalid_instructions() =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            };
+            let end_instruction = is_basic_block_end_instruction(instruction);
+            current_pc += u64::from(instruction_length(instruction));
+            let cycles = self.machine.instruction_cycle_func()(instruction);
+            self.machine.arena.traces[slot].cycles = self.machine.arena.traces[slot]
+                .cycles
+                .saturating_add(cycles);
+            self.machine.arena.traces[slot].instructions[i] = instruction;
+            i += 1;
+            if end_instruction {
+                break;
+            }
+        }
+        self.last_trace_id += 1;
+        self.machine.arena.traces[slot].address = pc;
+        self.machine.arena.traces[slot].length = (current_pc - pc) as usize;
+        self.machine.arena.traces[slot].instruction_count = i as u8;
+        self.machine.arena.traces[slot].id = self.last_trace_id;
+        if self.machine.has_observer() {
+            self.machine.notify(&Event::TraceCompiled {
+                address: pc,
+                length: current_pc - pc,
+                instructions: i,
+            });
+        }
+        Ok(slot)
+    }
+
+    fn run_inner<F: FnMut(Instruction, u64) -> bool>(
+        &mut self,
+        mut on_retire: F,
+    ) -> Result<i8, Error> {
+        let mut decoder = self.machine.build_decoder();
         self.machine.set_running(true);
-        // For current trace size this is acceptable, however we might want
-        // to tweak the code here if we choose to use a larger trace size or
-        // larger trace item length.
-        self.traces.resize_with(TRACE_SIZE, Trace::default);
+        self.machine.trace_cache.validate()?;
+        self.machine
+            .arena
+            .reserve_traces(self.machine.trace_cache.slots);
+        // Slot and id of the previous trace when it ended with a JALR.
+        // Indirect calls and returns usually jump to the same target as last
+        // time, which is then taken from the cache of the JALR instead of
+        // looking it up.
+        let mut jalr_site: Option<(usize, u64)> = None;
         while self.machine.running() {
             if self.machine.reset_signal() {
                 decoder.reset_instructions_cache();
-                for i in self.traces.iter_mut() {
-                    *i = Trace::default()
-                }
+                self.machine.arena.reset();
+                jalr_site = None;
+            }
+            self.machine.apply_cycles_budget();
+            if self.traces_since_relayout >= TRACE_RELAYOUT_INTERVAL {
+                self.machine.arena.relayout_traces();
+                self.traces_since_relayout = 0;
+                jalr_site = None;
             }
             let pc = self.machine.pc().to_u64();
-            let slot = calculate_slot(pc);
-            if pc != self.traces[slot].address || self.traces[slot].instruction_count == 0 {
-                self.traces[slot] = Trace::default();
-                let mut current_pc = pc;
-                let mut i = 0;
-                while i < TRACE_ITEM_LENGTH {
-                    let instruction = decoder.decode(self.machine.memory_mut(), current_pc)?;
-                    let end_instruction = is_basic_block_end_instruction(instruction);
-                    current_pc += u64::from(instruction_length(instruction));
-                    self.traces[slot].instructions[i] = instruction;
-                    i += 1;
-                    if end_instruction {
-                        break;
+            let traces = &self.machine.arena.traces;
+            let site = jalr_site.filter(|(site, id)| traces[*site].id == *id);
+            let cached = site
+                .and_then(|(site, _)| traces[site].jalr_cache)
+                .filter(|cache| cache.target == pc && traces[cache.slot].id == cache.id);
+            let slot = match cached {
+                Some(cache) => {
+                    self.stats.hits += 1;
+                    self.stats.jalr_hits += 1;
+                    if let Some(metrics) = &mut self.machine.metrics {
+                        metrics.trace_cache_hit();
+                        metrics.jalr_cache_hit();
+                    }
+                    cache.slot
+                }
+                None => {
+                    let slot = match self.lookup_trace(&mut decoder, pc) {
+                        Ok(slot) => slot,
+                        Err(e) => {
+                            self.machine.handle_invalid_instruction(e)?;
+                            jalr_site = None;
+                            continue;
+                        }
+                    };
+                    // The lookup may have evicted the JALR site itself.
+                    let traces = &mut self.machine.arena.traces;
+                    if let Some((site, _)) = site.filter(|(site, id)| traces[*site].id == *id) {
+                        traces[site].jalr_cache = Some(JalrCache {
+                            target: pc,
+                            slot,
+                            id: traces[slot].id,
+                        });
                     }
+                    slot
                 }
-                self.traces[slot].address = pc;
-                self.traces[slot].length = (current_pc - pc) as usize;
-                self.traces[slot].instruction_count = i as u8;
+            };
+            self.machine.arena.traces[slot].executions += 1;
+            self.trace_clock += 1;
+            self.machine.arena.traces[slot].last_used = self.trace_clock;
+            self.traces_since_relayout += 1;
+            jalr_site = if self.machine.arena.traces[slot].ends_with_jalr() {
+                Some((slot, self.machine.arena.traces[slot].id))
+            } else {
+                None
+            };
+            // See DefaultMachine::charge_block.
+            let charged = self
+                .machine
+                .charge_block(self.machine.arena.traces[slot].cycles);
+            let count = self.machine.arena.traces[slot].instruction_count as usize;
+            if self.machine.profiler.is_some() {
+                let instructions = self.machine.arena.traces[slot].instructions;
+                self.machine.profile_block(pc, &instructions[..count]);
             }
-            for i in 0..self.traces[slot].instruction_count {
-                let i = self.traces[slot].instructions[i as usize];
-                let cycles = self.machine.instruction_cycle_func()(i);
-                self.machine.add_cycles(cycles)?;
-                execute(i, self)?;
+            for index in 0..count {
+                let i = self.machine.arena.traces[slot].instructions[index];
+                let pc = self.machine.pc().to_u64();
+                if !charged {
+                    let cycles = self.machine.instruction_cycle_func()(i);
+                    self.machine
+                        .add_cycles(cycles)
+                        .map_err(|e| self.machine.error_with_context(e, pc, i))?;
+                }
+                if let Err(e) = execute(i, self) {
+                    if charged {
+                        let instructions = self.machine.arena.traces[slot].instructions;
+                        self.machine.refund_block(&instructions[index + 1..count]);
+                    }
+                    if !self.machine.trap_memory_fault(i, &e) {
+                        return Err(self.machine.error_with_context(e, pc, i));
+                    }
+                    jalr_site = None;
+                    break;
+                }
+                self.machine.instret = self.machine.instret.wrapping_add(1);
+                self.machine.profile_retire(pc, i);
+                if !on_retire(i, self.machine.pc().to_u64()) {
+                    if charged {
+                        let instructions = self.machine.arena.traces[slot].instructions;
+                        self.machine.refund_block(&instructions[index + 1..count]);
+                    }
+                    return Ok(self.machine.exit_code());
+                }
+            }
+            if self.machine.running() && self.machine.poll_interrupts()? {
+                jalr_site = None;
             }
         }
         Ok(self.machine.exit_code())
@@ -148,13 +492,41 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
 
 #[cfg(test)]
 mod tests {
+    use super::super::arena::{Arena, TRACE_SIZE};
     use super::*;
 
     #[test]
     fn test_trace_constant_rules() {
         assert!(TRACE_SIZE.is_power_of_two());
-        assert_eq!(TRACE_MASK, TRACE_SIZE - 1);
+        assert_eq!(TraceCacheConfig::default().validate(), Ok(()));
         assert!(TRACE_ITEM_LENGTH.is_power_of_two());
         assert!(TRACE_ITEM_LENGTH <= 255);
+        // Positions of traces are stored as u16.
+        assert!(TRACE_SIZE <= 1 << 16);
+    }
+
+    #[test]
+    fn test_relayout_traces() {
+        let config = TraceCacheConfig::default();
+        let mut arena = Arena::default();
+        arena.reserve_traces(config.slots);
+        for (slot, executions) in [1, 5, 0, 3].iter().enumerate() {
+            let address = slot as u64 * 4;
+            assert_eq!(calculate_set(address, &config), slot);
+            arena.traces[slot].address = address;
+            arena.traces[slot].executions = *executions;
+        }
+        arena.relayout_traces();
+        // Hot traces are packed at the front of the direct mapped cache.
+        let addresses: Vec<u64> = arena.traces[..4].iter().map(|t| t.address).collect();
+        assert_eq!(addresses, vec![4, 12, 0, 8]);
+        let executions: Vec<u64> = arena.traces[..4].iter().map(|t| t.executions).collect();
+        assert_eq!(executions, vec![2, 1, 0, 0]);
+        // Each slot still leads to its trace.
+        for slot in 0..4 {
+            let position = arena.trace_position(slot);
+            assert_eq!(arena.traces[position].address, slot as u64 * 4);
+        }
+        assert_eq!(arena.trace_position(4), 4);
     }
 }
@@ -0,0 +1,227 @@
+use super::{
+    super::{registers::register_name, Error, RISCV_GENERAL_REGISTER_NUMBER},
+    cosim::{CommitRecord, ReferenceModel},
+    rvfi::RvfiRecord,
+};
+use std::fmt::{self, Display};
+
+// Only the destination register is known from RVFI records.
+impl From<&RvfiRecord> for CommitRecord {
+    fn from(record: &RvfiRecord) -> Self {
+        let writes = if record.rd_addr != 0 {
+            vec![(usize::from(record.rd_addr), record.rd_wdata)]
+        } else {
+            vec![]
+        };
+        CommitRecord {
+            privilege: Some(record.mode),
+            pc: record.pc_rdata,
+            instruction: record.insn as u32,
+            writes,
+        }
+    }
+}
+
+// Collects up to `limit` records from a reference model, 0 means no limit.
+// With a MachineReference this records the trace of a VM:
+//
+// let trace = record_trace(&mut MachineReference::new(machine), 0)?;
+pub fn record_trace<M: ReferenceModel>(
+    model: &mut M,
+    limit: usize,
+) -> Result<Vec<CommitRecord>, Error> {
+    let mut trace = Vec::new();
+    while limit == 0 || trace.len() < limit {
+        match model.next_commit()? {
+            Some(record) => trace.push(record),
+            None => break,
+        }
+    }
+    Ok(trace)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceMismatch {
+    Pc(u64, u64),
+    Instruction(u32, u32),
+    // Same instruction at the same PC, writing different registers or values.
+    Writes(Vec<(usize, u64)>, Vec<(usize, u64)>),
+    // One trace ended, with the lengths of both traces.
+    Length(usize, usize),
+}
+
+impl Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceMismatch::Pc(left, right) => write!(f, "pc 0x{:x} != 0x{:x}", left, right),
+            TraceMismatch::Instruction(left, right) => {
+                write!(f, "instruction 0x{:08x} != 0x{:08x}", left, right)
+            }
+            TraceMismatch::Writes(left, right) => {
+                write!(f, "writes {} != {}", writes(left), writes(right))
+            }
+            TraceMismatch::Length(left, right) => {
+                write!(f, "length {} != {}", left, right)
+            }
+        }
+    }
+}
+
+fn writes(writes: &[(usize, u64)]) -> String {
+    let writes: Vec<String> = writes
+        .iter()
+        .map(|(index, value)| format!("{}=0x{:x}", register_name(*index).unwrap_or("?"), value))
+        .collect();
+    format!("[{}]", writes.join(" "))
+}
+
+// A register whose last written value differs between both traces, None if
+// the trace never wrote it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDelta {
+    pub index: usize,
+    pub left: Option<u64>,
+    pub right: Option<u64>,
+}
+
+// Records at the same index of both traces, None past the end of a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignedRecords {
+    pub index: usize,
+    pub left: Option<CommitRecord>,
+    pub right: Option<CommitRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceDiff {
+    // Index of the first diverging record.
+    pub index: usize,
+    pub mismatch: TraceMismatch,
+    // Records around the divergence, aligned by index.
+    pub context: Vec<AlignedRecords>,
+    // Register values written up to and including the diverging record.
+    pub registers: Vec<RegisterDelta>,
+}
+
+fn record(record: &Option<CommitRecord>) -> String {
+    match record {
+        Some(r) => format!("0x{:x} 0x{:08x} {}", r.pc, r.instruction, writes(&r.writes)),
+        None => "-".to_string(),
+    }
+}
+
+impl Display for TraceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "record {}: {}", self.index, self.mismatch)?;
+        for aligned in &self.context {
+            let marker = if aligned.index == self.index {
+                '>'
+            } else {
+                ' '
+            };
+            writeln!(
+                f,
+                "{} {:>6} {} | {}",
+                marker,
+                aligned.index,
+                record(&aligned.left),
+                record(&aligned.right)
+            )?;
+        }
+        for delta in &self.registers {
+            let value = |v: Option<u64>| match v {
+                Some(v) => format!("0x{:x}", v),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{}: {} != {}",
+                register_name(delta.index).unwrap_or("?"),
+                value(delta.left),
+                value(delta.right)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mismatch(left: &CommitRecord, right: &CommitRecord) -> Option<TraceMismatch> {
+    if left.pc != right.pc {
+        return Some(TraceMismatch::Pc(left.pc, right.pc));
+    }
+    if left.instruction != right.instruction {
+        return Some(TraceMismatch::Instruction(
+            left.instruction,
+            right.instruction,
+        ));
+    }
+    let mut left_writes = left.writes.clone();
+    let mut right_writes = right.writes.clone();
+    left_writes.sort_unstable();
+    right_writes.sort_unstable();
+    if left_writes != right_writes {
+        return Some(TraceMismatch::Writes(left_writes, right_writes));
+    }
+    None
+}
+
+fn apply_writes(registers: &mut [Option<u64>], record: &CommitRecord) {
+    for (index, value) in &record.writes {
+        if let Some(register) = registers.get_mut(*index) {
+            *register = Some(*value);
+        }
+    }
+}
+
+// Compares two traces record by record, e.g. one recorded from this VM and
+// one imported from Spike or a RVFI log, and returns the first divergence,
+// or None if both traces are the same. PCs, raw instructions and register
+// writes are compared, privilege levels are not. `context` records before
+// and after the divergence are included in the diff.
+pub fn diff_traces(
+    left: &[CommitRecord],
+    right: &[CommitRecord],
+    context: usize,
+) -> Option<TraceDiff> {
+    let mut left_registers = [None; RISCV_GENERAL_REGISTER_NUMBER];
+    let mut right_registers = [None; RISCV_GENERAL_REGISTER_NUMBER];
+    let mut found = None;
+    for (index, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+        apply_writes(&mut left_registers, l);
+        apply_writes(&mut right_registers, r);
+        if let Some(mismatch) = mismatch(l, r) {
+            found = Some((index, mismatch));
+            break;
+        }
+    }
+    let (index, mismatch) = match found {
+        Some(found) => found,
+        None if left.len() != right.len() => {
+            let index = left.len().min(right.len());
+            (index, TraceMismatch::Length(left.len(), right.len()))
+        }
+        None => return None,
+    };
+    let end = (index + context + 1).min(left.len().max(right.len()));
+    let context = (index.saturating_sub(context)..end)
+        .map(|i| AlignedRecords {
+            index: i,
+            left: left.get(i).cloned(),
+            right: right.get(i).cloned(),
+        })
+        .collect();
+    let registers = (1..RISCV_GENERAL_REGISTER_NUMBER)
+        .filter(|i| left_registers[*i] != right_registers[*i])
+        .map(|i| RegisterDelta {
+            index: i,
+            left: left_registers[i],
+            right: right_registers[i],
+        })
+        .collect();
+    Some(TraceDiff {
+        index,
+        mismatch,
+        context,
+        registers,
+    })
+}
@@ -0,0 +1,212 @@
+use super::privileged::CsrFile;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Width of a vector register in bits, and the widest element.
+pub const VLEN: u64 = 128;
+pub const VLENB: u64 = VLEN / 8;
+pub const ELEN: u64 = 64;
+
+// Vector CSRs, accessible without ISA_PRIV. vl, vtype and vlenb are read
+// only, they are changed by VSETVLI, VSETIVLI and VSETVL.
+pub const CSR_VSTART: u16 = 0x008;
+pub const CSR_VXSAT: u16 = 0x009;
+pub const CSR_VXRM: u16 = 0x00a;
+pub const CSR_VCSR: u16 = 0x00f;
+pub const CSR_VL: u16 = 0xc20;
+pub const CSR_VTYPE: u16 = 0xc21;
+pub const CSR_VLENB: u16 = 0xc22;
+
+pub const VTYPE_VILL: u64 = 1 << 63;
+// vtype bits other than vill which are defined, vma, vta, vsew and vlmul.
+const VTYPE_FIELDS: u64 = 0xff;
+
+// Vector register file and vector CSRs of the V extension, held by
+// DefaultCoreMachine when ISA_V is enabled. Registers are VLEN bits wide,
+// elements are stored in little endian. Machines start with vill set, so
+// programs must configure vtype before running vector instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VectorRegisters {
+    registers: Vec<u8>,
+    vl: u64,
+    vtype: u64,
+    vstart: u64,
+    vxsat: u8,
+    vxrm: u8,
+}
+
+impl Default for VectorRegisters {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; 32 * VLENB as usize],
+            vl: 0,
+            vtype: VTYPE_VILL,
+            vstart: 0,
+            vxsat: 0,
+            vxrm: 0,
+        }
+    }
+}
+
+impl VectorRegisters {
+    pub fn is_csr(csr: u16) -> bool {
+        matches!(
+            csr,
+            CSR_VSTART | CSR_VXSAT | CSR_VXRM | CSR_VCSR | CSR_VL | CSR_VTYPE | CSR_VLENB
+        )
+    }
+
+    // Bytes of register `index`.
+    pub fn register(&self, index: usize) -> &[u8] {
+        let start = (index % 32) * VLENB as usize;
+        &self.registers[start..start + VLENB as usize]
+    }
+
+    pub fn register_mut(&mut self, index: usize) -> &mut [u8] {
+        let start = (index % 32) * VLENB as usize;
+        &mut self.registers[start..start + VLENB as usize]
+    }
+
+    pub fn vl(&self) -> u64 {
+        self.vl
+    }
+
+    pub fn vtype(&self) -> u64 {
+        self.vtype
+    }
+
+    pub fn vstart(&self) -> u64 {
+        self.vstart
+    }
+
+    pub fn set_vstart(&mut self, vstart: u64) {
+        self.vstart = vstart;
+    }
+
+    // Restores vtype and vl saved in a snapshot, they are otherwise only set
+    // by set_vtype.
+    pub(crate) fn restore_config(&mut self, vtype: u64, vl: u64) {
+        self.vtype = vtype;
+        self.vl = vl;
+    }
+
+    // Whether the register file has VLEN bits per register, which may not
+    // hold for deserialized state.
+    #[cfg(feature = "rvv")]
+    pub(crate) fn is_valid(&self) -> bool {
+        self.registers.len() == 32 * VLENB as usize
+    }
+
+    pub fn vill(&self) -> bool {
+        self.vtype & VTYPE_VILL != 0
+    }
+
+    // Selected element width in bytes.
+    pub fn sew(&self) -> u64 {
+        1 << (self.vtype >> 3 & 0b111)
+    }
+
+    // Log2 of the register group size, negative for fractional groups.
+    pub fn lmul_log2(&self) -> i32 {
+        lmul_log2(self.vtype).unwrap_or(0)
+    }
+
+    // Number of elements of the register group selected by vtype.
+    pub fn vlmax(&self) -> u64 {
+        vlmax(self.sew(), self.lmul_log2())
+    }
+
+    // Sets vtype, then vl to the number of elements to process given the
+    // application vector length `avl`, and returns vl. Unsupported vtype
+    // values set vill and clear vl.
+    pub fn set_vtype(&mut self, vtype: u64, avl: u64) -> u64 {
+        let sew_log2 = vtype >> 3 & 0b111;
+        // Fractional groups must still hold SEW <= LMUL * ELEN.
+        let valid = vtype & !VTYPE_FIELDS == 0
+            && 8 << sew_log2 <= ELEN
+            && lmul_log2(vtype).map_or(false, |lmul| 8 << sew_log2 <= ELEN >> (-lmul).max(0));
+        if !valid {
+            self.vtype = VTYPE_VILL;
+            self.vl = 0;
+            return 0;
+        }
+        self.vtype = vtype;
+        self.vl = avl.min(self.vlmax());
+        self.vl
+    }
+
+    // Reads element `index` of `width` bytes in the group starting at
+    // register `base`.
+    pub fn element(&self, base: usize, index: u64, width: u64) -> u64 {
+        let start = base * VLENB as usize + (index * width) as usize;
+        let mut bytes = [0u8; 8];
+        bytes[..width as usize].copy_from_slice(&self.registers[start..start + width as usize]);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn set_element(&mut self, base: usize, index: u64, width: u64, value: u64) {
+        let start = base * VLENB as usize + (index * width) as usize;
+        self.registers[start..start + width as usize]
+            .copy_from_slice(&value.to_le_bytes()[..width as usize]);
+    }
+
+    // Bit `index` of mask register `reg`.
+    pub fn mask(&self, reg: usize, index: u64) -> bool {
+        self.register(reg)[(index / 8) as usize] >> (index % 8) & 1 != 0
+    }
+
+    pub fn set_mask(&mut self, reg: usize, index: u64, value: bool) {
+        let byte = &mut self.register_mut(reg)[(index / 8) as usize];
+        if value {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+    }
+}
+
+impl CsrFile for VectorRegisters {
+    fn read_csr(&self, csr: u16) -> Option<u64> {
+        match csr {
+            CSR_VSTART => Some(self.vstart),
+            CSR_VXSAT => Some(u64::from(self.vxsat)),
+            CSR_VXRM => Some(u64::from(self.vxrm)),
+            CSR_VCSR => Some(u64::from(self.vxrm << 1 | self.vxsat)),
+            CSR_VL => Some(self.vl),
+            CSR_VTYPE => Some(self.vtype),
+            CSR_VLENB => Some(VLENB),
+            _ => None,
+        }
+    }
+
+    fn write_csr(&mut self, csr: u16, value: u64) -> Option<()> {
+        match csr {
+            CSR_VSTART => self.vstart = value & (VLEN - 1),
+            CSR_VXSAT => self.vxsat = value as u8 & 1,
+            CSR_VXRM => self.vxrm = value as u8 & 0b11,
+            CSR_VCSR => {
+                self.vxsat = value as u8 & 1;
+                self.vxrm = (value >> 1) as u8 & 0b11;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn lmul_log2(vtype: u64) -> Option<i32> {
+    match vtype & 0b111 {
+        4 => None,
+        lmul if lmul < 4 => Some(lmul as i32),
+        lmul => Some(lmul as i32 - 8),
+    }
+}
+
+pub(crate) fn vlmax(sew: u64, lmul_log2: i32) -> u64 {
+    if lmul_log2 >= 0 {
+        (VLENB << lmul_log2) / sew
+    } else {
+        (VLENB >> -lmul_log2) / sew
+    }
+}
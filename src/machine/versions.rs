@@ -0,0 +1,329 @@
+use super::{
+    super::{
+        cost_model::estimate_cycles,
+        decoder::build_decoder,
+        instructions::Register,
+        isa::Isa,
+        memory::{round_page_down, wxorx::WXorXMemory, Memory, FLAG_EXECUTABLE},
+        registers::register_name,
+        Error, ISA_IMC, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
+        RISCV_PAGE_SHIFTS,
+    },
+    compare::{compare_machines, Difference, StateDiff},
+    CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, SupportMachine,
+    VERSION0, VERSION1, VERSION2,
+};
+use bytes::Bytes;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone)]
+pub struct VersionCheckOptions {
+    // ISA extensions, which must be valid for all versions.
+    pub isa: u64,
+    // Versions to run, the first one is the baseline others are compared to.
+    pub versions: Vec<u32>,
+    // Stops after this many instructions, 0 means no limit.
+    pub max_steps: u64,
+}
+
+impl Default for VersionCheckOptions {
+    fn default() -> Self {
+        Self {
+            isa: ISA_IMC,
+            versions: vec![VERSION0, VERSION1, VERSION2],
+            max_steps: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionDivergenceKind {
+    // Loading the program succeeded in one version only, or failed
+    // differently.
+    Load {
+        baseline: Option<Error>,
+        actual: Option<Error>,
+    },
+    // The state differs right after loading the program.
+    Initial(StateDiff),
+    Pc {
+        baseline: u64,
+        actual: u64,
+    },
+    Register {
+        index: usize,
+        baseline: u64,
+        actual: u64,
+    },
+    // The instruction failed in one version only, or failed differently.
+    Error {
+        baseline: Option<Error>,
+        actual: Option<Error>,
+    },
+    // One version exited while the other kept running.
+    Exit {
+        baseline: Option<i8>,
+        actual: Option<i8>,
+    },
+    // Both versions exited, with different final states, e.g. memory.
+    State(StateDiff),
+}
+
+// The first point where a version behaves differently from the baseline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionDivergence {
+    pub baseline: u32,
+    pub version: u32,
+    // Index of the diverging instruction, starting from 0.
+    pub step: u64,
+    // PC of the diverging instruction in the baseline.
+    pub pc: u64,
+    pub kind: VersionDivergenceKind,
+}
+
+fn error_or_ok(error: &Option<Error>) -> String {
+    match error {
+        Some(e) => format!("{:?}", e),
+        None => "ok".to_string(),
+    }
+}
+
+fn exit_or_running(code: &Option<i8>) -> String {
+    match code {
+        Some(code) => format!("exit({})", code),
+        None => "running".to_string(),
+    }
+}
+
+impl Display for VersionDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "version {} vs {}, step {} at 0x{:x}: ",
+            self.version, self.baseline, self.step, self.pc
+        )?;
+        match &self.kind {
+            VersionDivergenceKind::Load { baseline, actual } => write!(
+                f,
+                "load {} != {}",
+                error_or_ok(actual),
+                error_or_ok(baseline)
+            ),
+            VersionDivergenceKind::Initial(diff) => write!(f, "initial state:\n{}", diff),
+            VersionDivergenceKind::Pc { baseline, actual } => {
+                write!(f, "pc 0x{:x} != 0x{:x}", actual, baseline)
+            }
+            VersionDivergenceKind::Register {
+                index,
+                baseline,
+                actual,
+            } => write!(
+                f,
+                "{} 0x{:x} != 0x{:x}",
+                register_name(*index).unwrap_or("?"),
+                actual,
+                baseline
+            ),
+            VersionDivergenceKind::Error { baseline, actual } => {
+                write!(f, "{} != {}", error_or_ok(actual), error_or_ok(baseline))
+            }
+            VersionDivergenceKind::Exit { baseline, actual } => write!(
+                f,
+                "{} != {}",
+                exit_or_running(actual),
+                exit_or_running(baseline)
+            ),
+            VersionDivergenceKind::State(diff) => write!(f, "final state:\n{}", diff),
+        }
+    }
+}
+
+type Machine<R, M> = DefaultMachine<DefaultCoreMachine<R, WXorXMemory<M>>>;
+
+fn build_machine<R: Register, M: Memory<REG = R>>(
+    isa: u64,
+    version: u32,
+    program: &Bytes,
+    args: &[Bytes],
+) -> Result<(Machine<R, M>, Option<Error>), Error> {
+    let core = DefaultCoreMachine::<R, WXorXMemory<M>>::new_with_isa(
+        Isa::from(isa),
+        version,
+        u64::MAX,
+        RISCV_MAX_MEMORY,
+    )?;
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(estimate_cycles))
+        .build();
+    let error = machine.load_program(program, args).err();
+    Ok((machine, error))
+}
+
+// Runs `baseline` and `machine` side by side, one instruction at a time,
+// returning the first divergence as (step, pc, kind). `initial` holds the
+// differences right after loading.
+fn run_pair<R: Register, M: Memory<REG = R>>(
+    baseline: &mut Machine<R, M>,
+    machine: &mut Machine<R, M>,
+    initial: &StateDiff,
+    max_steps: u64,
+) -> Result<Option<(u64, u64, VersionDivergenceKind)>, Error> {
+    let mut baseline_decoder = build_decoder::<R>(baseline.isa() & !ISA_MOP, baseline.version());
+    let mut decoder = build_decoder::<R>(machine.isa() & !ISA_MOP, machine.version());
+    baseline.set_running(true);
+    machine.set_running(true);
+    let mut step = 0;
+    while max_steps == 0 || step < max_steps {
+        let pc = baseline.pc().to_u64();
+        if machine.pc().to_u64() != pc {
+            let kind = VersionDivergenceKind::Pc {
+                baseline: pc,
+                actual: machine.pc().to_u64(),
+            };
+            return Ok(Some((step, pc, kind)));
+        }
+        let baseline_error = baseline.step_instruction(&mut baseline_decoder).err();
+        let error = machine.step_instruction(&mut decoder).err();
+        if baseline_error != error {
+            let kind = VersionDivergenceKind::Error {
+                baseline: baseline_error,
+                actual: error,
+            };
+            return Ok(Some((step, pc, kind)));
+        }
+        if baseline_error.is_some() {
+            // Both versions failed the same way.
+            return Ok(None);
+        }
+        for index in 1..RISCV_GENERAL_REGISTER_NUMBER {
+            let expected = baseline.registers()[index].to_u64();
+            let actual = machine.registers()[index].to_u64();
+            if expected != actual {
+                let kind = VersionDivergenceKind::Register {
+                    index,
+                    baseline: expected,
+                    actual,
+                };
+                return Ok(Some((step, pc, kind)));
+            }
+        }
+        let exit_code = |m: &Machine<R, M>| {
+            if m.running() {
+                None
+            } else {
+                Some(m.exit_code())
+            }
+        };
+        match (exit_code(baseline), exit_code(machine)) {
+            (None, None) => (),
+            (Some(_), Some(_)) => {
+                // Memory which differed the same way right after loading,
+                // e.g. the stack, is not a divergence of the execution.
+                let mut diff = compare_machines(baseline, machine)?;
+                diff.0.retain(|difference| !initial.0.contains(difference));
+                if diff.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some((step, pc, VersionDivergenceKind::State(diff))));
+            }
+            (expected, actual) => {
+                let kind = VersionDivergenceKind::Exit {
+                    baseline: expected,
+                    actual,
+                };
+                return Ok(Some((step, pc, kind)));
+            }
+        }
+        step += 1;
+    }
+    Ok(None)
+}
+
+// Compares the state right after loading, and copies PC, registers and
+// differing writable pages, e.g. the stack, from the baseline so that
+// execution starts from the same point.
+fn sync_initial_state<R: Register, M: Memory<REG = R>>(
+    baseline: &mut Machine<R, M>,
+    machine: &mut Machine<R, M>,
+) -> Result<StateDiff, Error> {
+    let diff = compare_machines(baseline, machine)?;
+    for difference in &diff.0 {
+        if let Difference::Memory(address, _, _) = difference {
+            let page = round_page_down(*address);
+            if machine.memory_mut().fetch_flag(page >> RISCV_PAGE_SHIFTS)? & FLAG_EXECUTABLE != 0 {
+                continue;
+            }
+            let bytes = baseline
+                .memory_mut()
+                .load_bytes(page, RISCV_PAGESIZE as u64)?;
+            machine.memory_mut().store_bytes(page, &bytes)?;
+        }
+    }
+    machine.update_pc(baseline.pc().clone());
+    machine.commit_pc();
+    for index in 1..RISCV_GENERAL_REGISTER_NUMBER {
+        machine.set_register(index, baseline.registers()[index].clone());
+    }
+    Ok(diff)
+}
+
+// Runs a program under each of `options.versions`, and reports where each
+// version diverges from the baseline(the first version). An empty result
+// means the program behaves the same under all versions, which is what
+// auditors want to certify before a hard fork activates a new version.
+//
+// Versions may set up the initial stack differently, e.g. VERSION1 aligns SP.
+// Such differences are reported as Initial, then PC, registers and differing
+// writable pages are copied from the baseline and execution is checked: instructions are run one at a
+// time without macro-op fusion, comparing PC, registers and errors after each
+// one, and the whole memory once both versions exit, ignoring memory which
+// differed in the same way after loading. At most one execution divergence,
+// the first one, is reported per version. Only the exit syscall is available
+// to the program.
+pub fn check_versions<R: Register, M: Memory<REG = R>>(
+    program: &Bytes,
+    args: &[Bytes],
+    options: &VersionCheckOptions,
+) -> Result<Vec<VersionDivergence>, Error> {
+    let mut divergences = Vec::new();
+    let baseline_version = match options.versions.first() {
+        Some(version) => *version,
+        None => return Ok(divergences),
+    };
+    for version in options.versions.iter().skip(1) {
+        let (mut baseline, baseline_error) =
+            build_machine::<R, M>(options.isa, baseline_version, program, args)?;
+        let (mut machine, error) = build_machine::<R, M>(options.isa, *version, program, args)?;
+        let pc = baseline.pc().to_u64();
+        let mut push = |step, pc, kind| {
+            divergences.push(VersionDivergence {
+                baseline: baseline_version,
+                version: *version,
+                step,
+                pc,
+                kind,
+            })
+        };
+        if baseline_error != error {
+            let kind = VersionDivergenceKind::Load {
+                baseline: baseline_error,
+                actual: error,
+            };
+            push(0, pc, kind);
+            continue;
+        }
+        if baseline_error.is_some() {
+            continue;
+        }
+        let initial = sync_initial_state(&mut baseline, &mut machine)?;
+        if !initial.is_empty() {
+            push(0, pc, VersionDivergenceKind::Initial(initial.clone()));
+        }
+        if let Some((step, pc, kind)) =
+            run_pair(&mut baseline, &mut machine, &initial, options.max_steps)?
+        {
+            push(step, pc, kind);
+        }
+    }
+    Ok(divergences)
+}
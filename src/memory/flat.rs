@@ -1,38 +1,75 @@
 use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
-use super::{fill_page_data, get_page_indices, memset, set_dirty, Memory};
+use super::{
+    advise_huge_pages, fill_page_data, get_page_indices, memset, set_dirty, zeroed::ZeroedBuffer,
+    FlagChanges, Memory,
+};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 use bytes::Bytes;
-use std::io::{Cursor, Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
-pub struct FlatMemory<R> {
-    data: Vec<u8>,
+// Storage of FlatMemory, created zero filled.
+pub trait FlatBuffer: DerefMut<Target = [u8]> {
+    fn zeroed(len: usize) -> Self;
+}
+
+impl FlatBuffer for Vec<u8> {
+    // vec![0; n] asks the allocator for zeroed memory instead of writing
+    // zeros, which system allocators usually serve with a fresh anonymous
+    // mapping at this size.
+    fn zeroed(len: usize) -> Self {
+        vec![0; len]
+    }
+}
+
+impl FlatBuffer for ZeroedBuffer {
+    fn zeroed(len: usize) -> Self {
+        ZeroedBuffer::new(len)
+    }
+}
+
+// FlatMemory mapped directly from the OS, see ZeroedBuffer. Creating one
+// is O(1) and pages are only backed by physical memory once touched. It
+// dereferences to the ZeroedBuffer where FlatMemory dereferences to a Vec.
+pub type MappedFlatMemory<R> = FlatMemory<R, ZeroedBuffer>;
+
+pub struct FlatMemory<R, B = Vec<u8>> {
+    data: B,
     flags: Vec<u8>,
+    flag_changes: FlagChanges,
     memory_size: usize,
     riscv_pages: usize,
     load_reservation_address: R,
     _inner: PhantomData<R>,
 }
 
-impl<R> Deref for FlatMemory<R> {
-    type Target = Vec<u8>;
+impl<R, B> Deref for FlatMemory<R, B> {
+    type Target = B;
 
     fn deref(&self) -> &Self::Target {
         &self.data
     }
 }
 
-impl<R> DerefMut for FlatMemory<R> {
-    fn deref_mut(&mut self) -> &mut Vec<u8> {
+impl<R, B> DerefMut for FlatMemory<R, B> {
+    fn deref_mut(&mut self) -> &mut B {
         &mut self.data
     }
 }
 
+impl<R, B> FlatMemory<R, B> {
+    fn check_range(&self, addr: u64, size: u64) -> Result<(), Error> {
+        if addr.checked_add(size).ok_or(Error::MemOutOfBound)? > self.memory_size as u64 {
+            return Err(Error::MemOutOfBound);
+        }
+        Ok(())
+    }
+}
+
 /// A flat chunk of memory used for RISC-V machine, it lacks all the permission
 /// checking logic.
-impl<R: Register> Memory for FlatMemory<R> {
+impl<R: Register, B: FlatBuffer> Memory for FlatMemory<R, B> {
     type REG = R;
 
     fn new() -> Self {
@@ -43,8 +80,9 @@ impl<R: Register> Memory for FlatMemory<R> {
         assert!(memory_size <= RISCV_MAX_MEMORY);
         assert!(memory_size % RISCV_PAGESIZE == 0);
         Self {
-            data: vec![0; memory_size as usize],
+            data: B::zeroed(memory_size),
             flags: vec![0; memory_size / RISCV_PAGESIZE],
+            flag_changes: FlagChanges::default(),
             memory_size,
             riscv_pages: memory_size / RISCV_PAGESIZE,
             load_reservation_address: R::from_u64(u64::MAX),
@@ -73,6 +111,8 @@ impl<R: Register> Memory for FlatMemory<R> {
 
     fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
         if page < self.riscv_pages as u64 {
+            let old = self.flags[page as usize];
+            self.flag_changes.record(page, old, old | flag);
             self.flags[page as usize] |= flag;
             Ok(())
         } else {
@@ -82,6 +122,8 @@ impl<R: Register> Memory for FlatMemory<R> {
 
     fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
         if page < self.riscv_pages as u64 {
+            let old = self.flags[page as usize];
+            self.flag_changes.record(page, old, old & !flag);
             self.flags[page as usize] &= !flag;
             Ok(())
         } else {
@@ -106,10 +148,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         if addr.checked_add(1).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
             return Err(Error::MemOutOfBound);
         }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
-        let v = reader.read_u8()?;
-        Ok(Self::REG::from_u8(v))
+        Ok(Self::REG::from_u8(self.data[addr as usize]))
     }
 
     fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
@@ -117,10 +156,8 @@ impl<R: Register> Memory for FlatMemory<R> {
         if addr.checked_add(2).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
             return Err(Error::MemOutOfBound);
         }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u16::<LittleEndian>()?;
+        let v = LittleEndian::read_u16(&self.data[addr as usize..addr as usize + 2]);
         Ok(Self::REG::from_u16(v))
     }
 
@@ -129,10 +166,8 @@ impl<R: Register> Memory for FlatMemory<R> {
         if addr.checked_add(4).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
             return Err(Error::MemOutOfBound);
         }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u32::<LittleEndian>()?;
+        let v = LittleEndian::read_u32(&self.data[addr as usize..addr as usize + 4]);
         Ok(Self::REG::from_u32(v))
     }
 
@@ -141,50 +176,49 @@ impl<R: Register> Memory for FlatMemory<R> {
         if addr.checked_add(8).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
             return Err(Error::MemOutOfBound);
         }
-        let mut reader = Cursor::new(&self.data);
-        reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
-        let v = reader.read_u64::<LittleEndian>()?;
+        let v = LittleEndian::read_u64(&self.data[addr as usize..addr as usize + 8]);
         Ok(Self::REG::from_u64(v))
     }
 
     fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 1)?;
+        let page_indices = get_page_indices(addr, 1)?;
         set_dirty(self, &page_indices)?;
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u8(value.to_u8())?;
+        self.data[addr as usize] = value.to_u8();
         Ok(())
     }
 
     fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 2)?;
+        let page_indices = get_page_indices(addr, 2)?;
         set_dirty(self, &page_indices)?;
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u16::<LittleEndian>(value.to_u16())?;
+        LittleEndian::write_u16(
+            &mut self.data[addr as usize..addr as usize + 2],
+            value.to_u16(),
+        );
         Ok(())
     }
 
     fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 4)?;
+        let page_indices = get_page_indices(addr, 4)?;
         set_dirty(self, &page_indices)?;
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u32::<LittleEndian>(value.to_u32())?;
+        LittleEndian::write_u32(
+            &mut self.data[addr as usize..addr as usize + 4],
+            value.to_u32(),
+        );
         Ok(())
     }
 
     fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 8)?;
+        let page_indices = get_page_indices(addr, 8)?;
         set_dirty(self, &page_indices)?;
-        let mut writer = Cursor::new(&mut self.data);
-        writer.seek(SeekFrom::Start(addr as u64))?;
-        writer.write_u64::<LittleEndian>(value.to_u64())?;
+        LittleEndian::write_u64(
+            &mut self.data[addr as usize..addr as usize + 8],
+            value.to_u64(),
+        );
         Ok(())
     }
 
@@ -193,7 +227,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr.to_u64(), size)?;
+        let page_indices = get_page_indices(addr, size)?;
         set_dirty(self, &page_indices)?;
         let slice = &mut self[addr as usize..(addr + size) as usize];
         slice.copy_from_slice(value);
@@ -204,7 +238,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr.to_u64(), size)?;
+        let page_indices = get_page_indices(addr, size)?;
         set_dirty(self, &page_indices)?;
         memset(&mut self[addr as usize..(addr + size) as usize], value);
         Ok(())
@@ -214,14 +248,38 @@ impl<R: Register> Memory for FlatMemory<R> {
         if size == 0 {
             return Ok(Bytes::new());
         }
-        if addr.checked_add(size).ok_or(Error::MemOutOfBound)? > self.memory_size() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        self.check_range(addr, size)?;
         Ok(Bytes::from(
             self[addr as usize..(addr + size) as usize].to_vec(),
         ))
     }
 
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        self.check_range(src, size)?;
+        let page_indices = get_page_indices(dst, size)?;
+        // Pages beyond memory_size are rejected by set_dirty.
+        set_dirty(self, &page_indices)?;
+        self.data
+            .copy_within(src as usize..(src + size) as usize, dst as usize);
+        Ok(())
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        advise_huge_pages(&mut self.data)
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.flag_changes.set_enabled(enabled);
+        true
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.flag_changes.take()
+    }
+
     fn lr(&self) -> &Self::REG {
         &self.load_reservation_address
     }
@@ -0,0 +1,266 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::{round_page_down, Memory};
+
+use bytes::Bytes;
+use std::collections::HashSet;
+
+// Content of a page supplied by a PageFaultHandler. Data shorter than a page
+// is zero padded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageContent {
+    pub flags: u8,
+    pub data: Bytes,
+}
+
+// Populates the pages of a LazyMemory on their first access, e.g. from a
+// file or a cell mapped into the guest. Closures taking the page address are
+// handlers too.
+pub trait PageFaultHandler: Send + Sync {
+    // Returns the content of the page at `page_addr`, or None to fail the
+    // access with MemOutOfBound.
+    fn fault(&mut self, page_addr: u64) -> Result<Option<PageContent>, Error>;
+}
+
+impl<F> PageFaultHandler for F
+where
+    F: FnMut(u64) -> Result<Option<PageContent>, Error> + Send + Sync,
+{
+    fn fault(&mut self, page_addr: u64) -> Result<Option<PageContent>, Error> {
+        self(page_addr)
+    }
+}
+
+// LazyMemory implements demand paging on top of another memory. Pages are
+// mapped once they are initialized by init_pages, written, or supplied by
+// the fault handler. Any access to an unmapped page, including loads, bulk
+// accesses of syscalls and instruction fetches, first asks the handler for
+// its content:
+//
+// let mut memory = LazyMemory::<SparseMemory<u64>>::new_with_memory(size);
+// memory.set_fault_handler(Box::new(|page_addr| {
+//     Ok(file.page(page_addr).map(|data| PageContent { flags: 0, data }))
+// }));
+//
+// Without a handler, LazyMemory behaves like the inner memory, so programs
+// can be loaded and their stack prepared before the handler is installed.
+pub struct LazyMemory<M: Memory> {
+    inner: M,
+    handler: Option<Box<dyn PageFaultHandler>>,
+    // Page numbers of the mapped pages.
+    mapped: HashSet<u64>,
+    faults: u64,
+}
+
+impl<M: Memory> LazyMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn set_fault_handler(&mut self, handler: Box<dyn PageFaultHandler>) {
+        self.handler = Some(handler);
+    }
+
+    pub fn is_mapped(&self, page_addr: u64) -> bool {
+        self.mapped.contains(&(page_addr >> RISCV_PAGE_SHIFTS))
+    }
+
+    // Number of pages populated by the handler.
+    pub fn faults(&self) -> u64 {
+        self.faults
+    }
+
+    // Maps the pages covering `size` bytes at `addr`, calling the handler for
+    // the unmapped ones. Without a handler, pages are mapped by stores only.
+    fn map(&mut self, addr: u64, size: u64, store: bool) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let end = addr.checked_add(size - 1).ok_or(Error::MemOutOfBound)?;
+        let mut page_addr = round_page_down(addr);
+        loop {
+            let page = page_addr >> RISCV_PAGE_SHIFTS;
+            if !self.mapped.contains(&page) {
+                match &mut self.handler {
+                    Some(handler) => {
+                        let content = handler.fault(page_addr)?.ok_or(Error::MemOutOfBound)?;
+                        if content.data.len() > RISCV_PAGESIZE {
+                            return Err(Error::MemOutOfBound);
+                        }
+                        self.inner.init_pages(
+                            page_addr,
+                            RISCV_PAGESIZE as u64,
+                            content.flags,
+                            Some(content.data),
+                            0,
+                        )?;
+                        self.faults += 1;
+                        self.mapped.insert(page);
+                    }
+                    None if store => {
+                        self.mapped.insert(page);
+                    }
+                    None => (),
+                }
+            }
+            if end - page_addr < RISCV_PAGESIZE as u64 {
+                return Ok(());
+            }
+            page_addr += RISCV_PAGESIZE as u64;
+        }
+    }
+}
+
+impl<M: Memory> Memory for LazyMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            handler: None,
+            mapped: HashSet::new(),
+            faults: 0,
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)?;
+        let end = addr.saturating_add(size);
+        let mut page_addr = round_page_down(addr);
+        while page_addr < end {
+            self.mapped.insert(page_addr >> RISCV_PAGE_SHIFTS);
+            page_addr += RISCV_PAGESIZE as u64;
+        }
+        Ok(())
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.map(addr, 2, false)?;
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.map(addr, 4, false)?;
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.map(addr.to_u64(), 1, false)?;
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.map(addr.to_u64(), 2, false)?;
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.map(addr.to_u64(), 4, false)?;
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.map(addr.to_u64(), 8, false)?;
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.map(addr.to_u64(), 1, true)?;
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.map(addr.to_u64(), 2, true)?;
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.map(addr.to_u64(), 4, true)?;
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.map(addr.to_u64(), 8, true)?;
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.map(addr, value.len() as u64, true)?;
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.map(addr, size, true)?;
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.map(addr, size, false)?;
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.map(src, size, false)?;
+        self.map(dst, size, true)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
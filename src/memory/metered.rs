@@ -0,0 +1,201 @@
+use super::super::{Error, RISCV_MAX_MEMORY};
+use super::Memory;
+
+use bytes::Bytes;
+
+// MeteredMemory counts the bytes written to the inner memory, and optionally
+// stops writes past a limit with MemWriteLimitExceeded, for environments
+// pricing state mutation apart from computation. Every store is counted,
+// from instructions as well as from syscalls, except the pages initialized
+// by the ELF loader. Loading a program also writes its arguments to the
+// stack, reset the meter after loading to only meter the execution:
+//
+// let mut core = DefaultCoreMachine::<u64, MeteredMemory<SparseMemory<u64>>>::new(
+//     isa, version, max_cycles,
+// );
+// let mut machine = DefaultMachineBuilder::new(core).build();
+// machine.load_program(&program, &args)?;
+// machine.memory_mut().reset();
+// machine.memory_mut().set_limit(Some(64 * 1024));
+// machine.run()?;
+// let written = machine.memory().written();
+//
+// A store going past the limit fails as a whole, nothing of it is written.
+// The asm machine writes memory without going through this wrapper.
+pub struct MeteredMemory<M: Memory> {
+    inner: M,
+    written: u64,
+    limit: Option<u64>,
+}
+
+impl<M: Memory> MeteredMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    // Bytes written since the memory was created or reset.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    // Limits the bytes written, None removes the limit.
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+
+    pub fn reset(&mut self) {
+        self.written = 0;
+    }
+
+    fn charge(&mut self, bytes: u64) -> Result<(), Error> {
+        let written = self.written.saturating_add(bytes);
+        if matches!(self.limit, Some(limit) if written > limit) {
+            return Err(Error::MemWriteLimitExceeded);
+        }
+        self.written = written;
+        Ok(())
+    }
+}
+
+impl<M: Memory> Memory for MeteredMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            written: 0,
+            limit: None,
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.charge(1)?;
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.charge(2)?;
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.charge(4)?;
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.charge(8)?;
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.charge(value.len() as u64)?;
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.charge(size)?;
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.charge(size)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
@@ -0,0 +1,330 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY};
+use super::Memory;
+
+use bytes::Bytes;
+
+// A device mapped into the guest address space, see MmioMemory.
+pub trait MmioDevice: Send + Sync {
+    // Size in bytes of the address window of the device.
+    fn size(&self) -> u64;
+    // Loads `size` bytes (1, 2, 4 or 8) at `offset` in the window.
+    fn load(&mut self, offset: u64, size: u8) -> Result<u64, Error>;
+    // Stores the lowest `size` bytes of `value` at `offset` in the window.
+    fn store(&mut self, offset: u64, size: u8, value: u64) -> Result<(), Error>;
+    // Advances the device to `cycles` consumed by the machine so far.
+    fn tick(&mut self, _cycles: u64) {}
+    // Interrupts raised by the device, as a bitmask indexed by interrupt
+    // number.
+    fn pending_interrupts(&self) -> u64 {
+        0
+    }
+    // Level of the interrupt line of the device, which is routed to the
+    // interrupt controller when the device is attached with a source number.
+    fn irq_line(&self) -> bool {
+        false
+    }
+    // Implemented by interrupt controllers, receives the levels of all
+    // interrupt lines after devices are advanced, bit n being source n.
+    fn route_interrupts(&mut self, _lines: u64) {}
+}
+
+// Highest interrupt source number, lines are passed around as a u64.
+pub const MAX_INTERRUPT_SOURCE: u32 = 63;
+
+struct MappedDevice {
+    base: u64,
+    // Interrupt source number of the device, if it is wired to the
+    // interrupt controller.
+    source: Option<u32>,
+    device: Box<dyn MmioDevice>,
+}
+
+// MmioMemory routes the loads and stores of RISC-V instructions falling into
+// the window of an attached device to the device, everything else goes to
+// the inner memory. Windows may lie beyond the memory size. Instruction
+// fetches and bulk accesses, e.g. from syscalls, never reach devices.
+pub struct MmioMemory<M: Memory> {
+    inner: M,
+    // Sorted by base address.
+    devices: Vec<MappedDevice>,
+}
+
+impl<M: Memory> MmioMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    // Maps `device` at `base`, its window must not overlap other devices.
+    pub fn attach(&mut self, base: u64, device: Box<dyn MmioDevice>) -> Result<(), Error> {
+        self.attach_device(base, None, device)
+    }
+
+    // Maps `device` at `base`, and wires its interrupt line to interrupt
+    // source `source` of the interrupt controller, see Plic.
+    pub fn attach_source(
+        &mut self,
+        base: u64,
+        source: u32,
+        device: Box<dyn MmioDevice>,
+    ) -> Result<(), Error> {
+        if source == 0 || source > MAX_INTERRUPT_SOURCE {
+            return Err(Error::Unexpected(format!(
+                "Invalid interrupt source {}",
+                source
+            )));
+        }
+        if self.devices.iter().any(|d| d.source == Some(source)) {
+            return Err(Error::Unexpected(format!(
+                "Interrupt source {} is already wired",
+                source
+            )));
+        }
+        self.attach_device(base, Some(source), device)
+    }
+
+    fn attach_device(
+        &mut self,
+        base: u64,
+        source: Option<u32>,
+        device: Box<dyn MmioDevice>,
+    ) -> Result<(), Error> {
+        let end = base
+            .checked_add(device.size())
+            .ok_or(Error::MemOutOfBound)?;
+        if self
+            .devices
+            .iter()
+            .any(|d| base < d.base.saturating_add(d.device.size()) && d.base < end)
+        {
+            return Err(Error::Unexpected(format!(
+                "MMIO device at 0x{:x} overlaps another device",
+                base
+            )));
+        }
+        let position = self
+            .devices
+            .iter()
+            .position(|d| d.base > base)
+            .unwrap_or(self.devices.len());
+        self.devices.insert(
+            position,
+            MappedDevice {
+                base,
+                source,
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn device_mut(&mut self, base: u64) -> Option<&mut (dyn MmioDevice + 'static)> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.base == base)
+            .map(|d| d.device.as_mut())
+    }
+
+    // Returns the index of the device whose window holds `addr`, and the
+    // offset of `addr` in it. An access crossing the end of a window is out
+    // of bound.
+    fn find(&self, addr: u64, size: u8) -> Result<Option<(usize, u64)>, Error> {
+        for (index, d) in self.devices.iter().enumerate() {
+            if addr >= d.base && addr - d.base < d.device.size() {
+                let offset = addr - d.base;
+                if offset + u64::from(size) > d.device.size() {
+                    return Err(Error::MemOutOfBound);
+                }
+                return Ok(Some((index, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn device_load(&mut self, addr: u64, size: u8) -> Result<Option<u64>, Error> {
+        match self.find(addr, size)? {
+            Some((index, offset)) => self.devices[index].device.load(offset, size).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn device_store(&mut self, addr: u64, size: u8, value: u64) -> Result<bool, Error> {
+        match self.find(addr, size)? {
+            Some((index, offset)) => {
+                self.devices[index].device.store(offset, size, value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<M: Memory> Memory for MmioMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            devices: vec![],
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        match self.device_load(addr.to_u64(), 1)? {
+            Some(value) => Ok(Self::REG::from_u8(value as u8)),
+            None => self.inner.load8(addr),
+        }
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        match self.device_load(addr.to_u64(), 2)? {
+            Some(value) => Ok(Self::REG::from_u16(value as u16)),
+            None => self.inner.load16(addr),
+        }
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        match self.device_load(addr.to_u64(), 4)? {
+            Some(value) => Ok(Self::REG::from_u32(value as u32)),
+            None => self.inner.load32(addr),
+        }
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        match self.device_load(addr.to_u64(), 8)? {
+            Some(value) => Ok(Self::REG::from_u64(value)),
+            None => self.inner.load64(addr),
+        }
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        if self.device_store(addr.to_u64(), 1, value.to_u64())? {
+            return Ok(());
+        }
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        if self.device_store(addr.to_u64(), 2, value.to_u64())? {
+            return Ok(());
+        }
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        if self.device_store(addr.to_u64(), 4, value.to_u64())? {
+            return Ok(());
+        }
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        if self.device_store(addr.to_u64(), 8, value.to_u64())? {
+            return Ok(());
+        }
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let mut lines = 0u64;
+        for d in &mut self.devices {
+            d.device.tick(cycles);
+            if let Some(source) = d.source {
+                if d.device.irq_line() {
+                    lines |= 1 << source;
+                }
+            }
+        }
+        for d in &mut self.devices {
+            d.device.route_interrupts(lines);
+        }
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.devices
+            .iter()
+            .fold(self.inner.pending_interrupts(), |pending, d| {
+                pending | d.device.pending_interrupts()
+            })
+    }
+}
@@ -3,12 +3,28 @@ use super::{
     Error, Register, RISCV_PAGESIZE,
 };
 use bytes::Bytes;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::cmp::min;
+use std::collections::BTreeMap;
+use std::mem::{align_of, size_of};
 use std::ptr;
+use std::slice;
 
 pub mod flat;
+pub mod lazy;
+pub mod metered;
+pub mod mmio;
+pub mod paged;
+pub mod quota;
+pub mod recording;
+pub mod region;
+pub mod reservation;
+pub mod shared;
 pub mod sparse;
+pub mod subpage;
+pub mod watchpoint;
 pub mod wxorx;
+pub mod zeroed;
 
 pub use ckb_vm_definitions::{
     memory::{FLAG_DIRTY, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT},
@@ -68,8 +84,162 @@ pub trait Memory {
     // Load reservation address for atomic extension.
     fn lr(&self) -> &Self::REG;
     fn set_lr(&mut self, value: &Self::REG);
+
+    // Called when the machine traps, i.e. on ECALL and EBREAK. Wrappers must
+    // forward it to the inner memory.
+    fn trap(&mut self) {}
+
+    // Advances memory mapped devices, see MmioMemory, to `cycles` consumed by
+    // the machine so far. Wrappers must forward it to the inner memory.
+    fn tick(&mut self, _cycles: u64) {}
+
+    // Returns the interrupts raised by memory mapped devices, as a bitmask
+    // indexed by interrupt number. Wrappers must forward it to the inner
+    // memory.
+    fn pending_interrupts(&self) -> u64 {
+        0
+    }
+
+    // Asks the host to back guest memory with huge pages, see
+    // advise_huge_pages. Returns false when the memory or the host does not
+    // support it, memory keeps working with regular pages then. Wrappers must
+    // forward it to the inner memory.
+    fn use_huge_pages(&mut self) -> bool {
+        false
+    }
+
+    // Starts or stops recording the flags pages have before they change, so
+    // that DefaultMachine reports the permission changes of a syscall or a
+    // load to its observer without scanning every page. Returns false when
+    // the memory does not record them. Wrappers must forward it to the inner
+    // memory.
+    fn track_flag_changes(&mut self, _enabled: bool) -> bool {
+        false
+    }
+
+    // Returns (page, previous flags) of the pages whose flags changed since
+    // tracking started or since the previous call, ordered by page. Wrappers
+    // must forward it to the inner memory.
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        Vec::new()
+    }
+
+    // Helpers below are meant for syscall implementations reading arguments
+    // from guest memory.
+
+    // Loads a NUL-terminated C string starting at `addr`, the NUL byte is not
+    // included. MemOutOfBound is returned if no NUL byte is found within
+    // `max_len` bytes.
+    fn load_c_string(&mut self, addr: u64, max_len: u64) -> Result<Bytes, Error> {
+        let mut buffer = Vec::new();
+        let mut current = addr;
+        while (buffer.len() as u64) < max_len {
+            // Read at most until the end of the current page, so we never touch
+            // pages after the string.
+            let page_end = round_page_down(current)
+                .checked_add(RISCV_PAGESIZE as u64)
+                .ok_or(Error::MemOutOfBound)?;
+            let size = min(page_end - current, max_len - buffer.len() as u64);
+            let chunk = self.load_bytes(current, size)?;
+            if let Some(position) = chunk.iter().position(|b| *b == 0) {
+                buffer.extend_from_slice(&chunk[..position]);
+                return Ok(Bytes::from(buffer));
+            }
+            buffer.extend_from_slice(&chunk);
+            current += size;
+        }
+        Err(Error::MemOutOfBound)
+    }
+
+    // Loads a slice prefixed by its length, the length is a register wide
+    // little endian integer. MemOutOfBound is returned if the length exceeds
+    // `max_len`.
+    fn load_length_prefixed(&mut self, addr: u64, max_len: u64) -> Result<Bytes, Error> {
+        let prefix_size = u64::from(Self::REG::BITS / 8);
+        let len = if prefix_size == 4 {
+            self.load32(&Self::REG::from_u64(addr))?.to_u64()
+        } else {
+            self.load64(&Self::REG::from_u64(addr))?.to_u64()
+        };
+        if len > max_len {
+            return Err(Error::MemOutOfBound);
+        }
+        let start = addr.checked_add(prefix_size).ok_or(Error::MemOutOfBound)?;
+        self.load_bytes(start, len)
+    }
+
+    // Loads a plain-old-data value, `addr` must be aligned to the alignment
+    // of T.
+    fn load_pod<T: Pod>(&mut self, addr: u64) -> Result<T, Error> {
+        if addr % align_of::<T>() as u64 != 0 {
+            return Err(Error::MemUnalignedAccess);
+        }
+        let bytes = self.load_bytes(addr, size_of::<T>() as u64)?;
+        if bytes.len() != size_of::<T>() {
+            return Err(Error::MemOutOfBound);
+        }
+        // Safety: the length is checked above, and Pod guarantees that any
+        // bit pattern is a valid T.
+        Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+
+    // Stores a plain-old-data value, `addr` must be aligned to the alignment
+    // of T.
+    fn store_pod<T: Pod>(&mut self, addr: u64, value: &T) -> Result<(), Error> {
+        if addr % align_of::<T>() as u64 != 0 {
+            return Err(Error::MemUnalignedAccess);
+        }
+        // Safety: Pod types have no padding, so every byte is initialized.
+        let bytes =
+            unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.store_bytes(addr, bytes)
+    }
+
+    // Copies `size` bytes from `src` to `dst`, e.g. for memcpy syscalls. The
+    // ranges may overlap, as with memmove. Flat memories override this with a
+    // single copy, avoiding the intermediate buffer.
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        let data = self.load_bytes(src, size)?;
+        self.store_bytes(dst, &data)
+    }
 }
 
+/// Plain-old-data types which can be copied from and to guest memory byte
+/// by byte. Guest memory is little endian, so are all RISC-V hosts we support
+/// for now.
+///
+/// # Safety
+///
+/// Values are built from guest bytes with an unaligned read and written back
+/// as their raw bytes, so implementors must guarantee that:
+///
+/// * the type contains no padding bytes, otherwise uninitialized host memory
+///   would be written to the guest;
+/// * every bit pattern of `size_of::<Self>()` bytes is a valid value, which
+///   rules out `bool`, `char`, enums and references;
+/// * the type holds no pointers or other host resources.
+///
+/// For structs this usually means `#[repr(C)]` with fields that are all
+/// `Pod` themselves and laid out without gaps:
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct Args { addr: u64, len: u64 }
+/// unsafe impl Pod for Args {}
+/// ```
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
 #[inline(always)]
 pub fn fill_page_data<M: Memory>(
     memory: &mut M,
@@ -97,13 +267,92 @@ pub fn fill_page_data<M: Memory>(
     Ok(())
 }
 
+// Initial content of guest memory. Consensus requires Zero, the other
+// patterns help flushing out guest code that depends on uninitialized memory.
+// Pages initialized from an ELF file are zero padded as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryFill {
+    Zero,
+    // Every byte set to the given value, e.g. 0xAA.
+    Byte(u8),
+    // Pseudo random bytes generated from the seed.
+    Random(u64),
+}
+
+impl Default for MemoryFill {
+    fn default() -> Self {
+        MemoryFill::Zero
+    }
+}
+
+// Fills the whole memory according to `fill`. Memory is assumed to be newly
+// created, i.e. zeroed and writable.
+pub fn fill_memory<M: Memory>(memory: &mut M, fill: MemoryFill) -> Result<(), Error> {
+    match fill {
+        MemoryFill::Zero => Ok(()),
+        MemoryFill::Byte(value) => memory.store_byte(0, memory.memory_size() as u64, value),
+        MemoryFill::Random(seed) => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut page = [0u8; RISCV_PAGESIZE];
+            for address in (0..memory.memory_size()).step_by(RISCV_PAGESIZE) {
+                rng.fill(&mut page[..]);
+                memory.store_bytes(address as u64, &page)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Size of transparent huge pages on x86_64 and aarch64 hosts.
+pub const HUGE_PAGE_SIZE: u64 = 2 << 20;
+
+// Asks the host to back the huge page aligned part of `data` with
+// transparent huge pages, which cuts TLB misses for guests touching lots of
+// memory. This should be called before `data` is touched. Only Linux
+// supports it, false is returned on other hosts, or when the kernel declines,
+// e.g. when transparent huge pages are disabled.
+pub fn advise_huge_pages(data: &mut [u8]) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let start = data.as_mut_ptr() as u64;
+        let aligned_start = roundup(start, HUGE_PAGE_SIZE);
+        let aligned_end = rounddown(start + data.len() as u64, HUGE_PAGE_SIZE);
+        if aligned_end <= aligned_start {
+            return false;
+        }
+        let result = unsafe {
+            libc::madvise(
+                aligned_start as *mut libc::c_void,
+                (aligned_end - aligned_start) as usize,
+                libc::MADV_HUGEPAGE,
+            )
+        };
+        result == 0
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = data;
+        false
+    }
+}
+
 // `size` should be none zero u64
 pub fn get_page_indices(addr: u64, size: u64) -> Result<(u64, u64), Error> {
+    get_page_indices_within(addr, size, RISCV_MAX_MEMORY as u64)
+}
+
+// Same as get_page_indices, bounded by `memory_size` instead, for wrappers
+// whose inner memory may span beyond RISCV_MAX_MEMORY.
+pub fn get_page_indices_within(
+    addr: u64,
+    size: u64,
+    memory_size: u64,
+) -> Result<(u64, u64), Error> {
     let (addr_end, overflowed) = addr.overflowing_add(size);
     if overflowed {
         return Err(Error::MemOutOfBound);
     }
-    if addr_end > RISCV_MAX_MEMORY as u64 {
+    if addr_end > memory_size {
         return Err(Error::MemOutOfBound);
     }
     let page = addr >> RISCV_PAGE_SHIFTS;
@@ -125,6 +374,33 @@ pub fn check_permission<M: Memory>(
     Ok(())
 }
 
+// Flags of pages before their first change, kept by memories implementing
+// Memory::track_flag_changes.
+#[derive(Clone, Default)]
+pub struct FlagChanges {
+    enabled: bool,
+    previous: BTreeMap<u64, u8>,
+}
+
+impl FlagChanges {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.previous.clear();
+    }
+
+    // Called before the flags of `page` change from `old` to `new`.
+    #[inline(always)]
+    pub fn record(&mut self, page: u64, old: u8, new: u8) {
+        if self.enabled && old != new {
+            self.previous.entry(page).or_insert(old);
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<(u64, u8)> {
+        std::mem::take(&mut self.previous).into_iter().collect()
+    }
+}
+
 pub fn set_dirty<M: Memory>(memory: &mut M, page_indices: &(u64, u64)) -> Result<(), Error> {
     for page in page_indices.0..=page_indices.1 {
         memory.set_flag(page, FLAG_DIRTY)?
@@ -132,6 +408,34 @@ pub fn set_dirty<M: Memory>(memory: &mut M, page_indices: &(u64, u64)) -> Result
     Ok(())
 }
 
+// Formats memory content the same way as `xxd`, `addr` is the address of the
+// first byte in `data`. Each line contains 16 bytes.
+pub fn hexdump(addr: u64, data: &[u8]) -> String {
+    let mut s = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        s.push_str(&format!("{:08x}:", addr + i as u64 * 16));
+        for j in 0..16 {
+            if j % 2 == 0 {
+                s.push(' ');
+            }
+            match chunk.get(j) {
+                Some(b) => s.push_str(&format!("{:02x}", b)),
+                None => s.push_str("  "),
+            }
+        }
+        s.push_str("  ");
+        for b in chunk {
+            if b.is_ascii_graphic() || *b == b' ' {
+                s.push(*b as char);
+            } else {
+                s.push('.');
+            }
+        }
+        s.push('\n');
+    }
+    s
+}
+
 // Keep this in a central place to allow for future optimization
 #[inline(always)]
 pub fn memset(slice: &mut [u8], value: u8) {
@@ -0,0 +1,230 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::{fill_page_data, memset, round_page_down, FlagChanges, Memory, Page, FLAG_DIRTY};
+
+use bytes::Bytes;
+use std::cmp::min;
+use std::collections::HashMap;
+
+// PagedMemory covers the whole 64-bit address space, pages are allocated
+// through a page table the first time they are written, and read as zeros
+// until then. It lets programs using high canonical addresses or large
+// mmap-style layouts run for analysis, with host memory growing only with
+// the pages actually touched. Like SparseMemory, it does no permission
+// checking.
+//
+// memory_size is nominal, it only sizes the default stack layout and
+// whole-memory tools like fill_memory or reports, which then cover the low
+// memory_size bytes. Use AddressSpaceLayout to place the stack elsewhere:
+//
+// let core = DefaultCoreMachine::<u64, PagedMemory<u64>>::new(isa, version, u64::MAX);
+// let layout = AddressSpaceLayout {
+//     stack_start: Some(0x7fff_ff00_0000),
+//     stack_size: Some(8 << 20),
+//     ..Default::default()
+// };
+//
+// WXorXMemory bounds checks against memory_size, so it can't guard pages
+// above it.
+pub struct PagedMemory<R> {
+    pages: HashMap<u64, Box<Page>>,
+    flags: HashMap<u64, u8>,
+    flag_changes: FlagChanges,
+    memory_size: usize,
+    load_reservation_address: R,
+}
+
+impl<R> PagedMemory<R> {
+    // Number of pages holding data.
+    pub fn allocated_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Returns the pages covering `size` bytes at `addr`, as (page address,
+    // offset in page, length) chunks.
+    fn chunks(addr: u64, size: u64) -> Result<impl Iterator<Item = (u64, usize, usize)>, Error> {
+        if size > 0 {
+            addr.checked_add(size - 1).ok_or(Error::MemOutOfBound)?;
+        }
+        let mut page_addr = round_page_down(addr);
+        let mut offset = addr - page_addr;
+        let mut remaining = size;
+        Ok(std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            let bytes = min(RISCV_PAGESIZE as u64 - offset, remaining);
+            let chunk = (page_addr, offset as usize, bytes as usize);
+            remaining -= bytes;
+            page_addr = page_addr.wrapping_add(RISCV_PAGESIZE as u64);
+            offset = 0;
+            Some(chunk)
+        }))
+    }
+
+    fn page_mut(&mut self, page_addr: u64) -> &mut Page {
+        let page = page_addr >> RISCV_PAGE_SHIFTS;
+        let flags = self.flags.entry(page).or_insert(0);
+        self.flag_changes.record(page, *flags, *flags | FLAG_DIRTY);
+        *flags |= FLAG_DIRTY;
+        self.pages
+            .entry(page)
+            .or_insert_with(|| Box::new([0; RISCV_PAGESIZE]))
+    }
+
+    fn read(&self, addr: u64, out: &mut [u8]) -> Result<(), Error> {
+        let mut written = 0;
+        for (page_addr, offset, bytes) in Self::chunks(addr, out.len() as u64)? {
+            let target = &mut out[written..written + bytes];
+            match self.pages.get(&(page_addr >> RISCV_PAGE_SHIFTS)) {
+                Some(page) => target.copy_from_slice(&page[offset..offset + bytes]),
+                None => memset(target, 0),
+            }
+            written += bytes;
+        }
+        Ok(())
+    }
+
+    fn load(&self, addr: u64, bytes: usize) -> Result<u64, Error> {
+        debug_assert!(bytes == 1 || bytes == 2 || bytes == 4 || bytes == 8);
+        let mut value = [0u8; 8];
+        self.read(addr, &mut value[..bytes])?;
+        // RISC-V is little-endian by specification
+        Ok(u64::from_le_bytes(value))
+    }
+}
+
+impl<R: Register> Memory for PagedMemory<R> {
+    type REG = R;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        assert!(memory_size % RISCV_PAGESIZE == 0);
+        Self {
+            pages: HashMap::new(),
+            flags: HashMap::new(),
+            flag_changes: FlagChanges::default(),
+            memory_size,
+            load_reservation_address: R::from_u64(u64::MAX),
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        _flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        fill_page_data(self, addr, size, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        Ok(self.flags.get(&page).copied().unwrap_or(0))
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let flags = self.flags.entry(page).or_insert(0);
+        self.flag_changes.record(page, *flags, *flags | flag);
+        *flags |= flag;
+        Ok(())
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        if let Some(flags) = self.flags.get_mut(&page) {
+            self.flag_changes.record(page, *flags, *flags & !flag);
+            *flags &= !flag;
+        }
+        Ok(())
+    }
+
+    fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 1)?;
+        Ok(Self::REG::from_u8(v as u8))
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 2)?;
+        Ok(Self::REG::from_u16(v as u16))
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 4)?;
+        Ok(Self::REG::from_u32(v as u32))
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 8)?;
+        Ok(Self::REG::from_u64(v))
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.load(addr, 2).map(|v| v as u16)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.load(addr, 4).map(|v| v as u32)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        let mut remaining = value;
+        for (page_addr, offset, bytes) in Self::chunks(addr, value.len() as u64)? {
+            self.page_mut(page_addr)[offset..offset + bytes].copy_from_slice(&remaining[..bytes]);
+            remaining = &remaining[bytes..];
+        }
+        Ok(())
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        for (page_addr, offset, bytes) in Self::chunks(addr, size)? {
+            memset(&mut self.page_mut(page_addr)[offset..offset + bytes], value);
+        }
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let mut out = vec![0; size as usize];
+        self.read(addr, &mut out)?;
+        Ok(Bytes::from(out))
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &[value.to_u8()])
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.flag_changes.set_enabled(enabled);
+        true
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.flag_changes.take()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        &self.load_reservation_address
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.load_reservation_address = value.clone();
+    }
+}
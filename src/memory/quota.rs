@@ -0,0 +1,227 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::Memory;
+
+use bytes::Bytes;
+
+// QuotaMemory bounds the pages a program actually touches, independently of
+// the size of its address space, so hosts can give every machine a large
+// address space while bounding the physical memory of each execution. A page
+// is touched by its first load, store or fetch, or when the ELF loader
+// initializes it, and stays touched. Accessing a new page past the quota
+// fails with MemQuotaExceeded, which is not delivered to the guest as a
+// memory fault:
+//
+// let mut core = DefaultCoreMachine::<u64, QuotaMemory<SparseMemory<u64>>>::new_with_memory(
+//     isa, version, max_cycles, 64 << 20,
+// );
+// core.memory_mut().set_quota(Some(256));
+// let mut machine = DefaultMachineBuilder::new(core).build();
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// let pages = machine.memory().touched_pages();
+//
+// An access spanning pages fails as a whole when any of them is past the
+// quota. The asm machine accesses memory without going through this wrapper.
+pub struct QuotaMemory<M: Memory> {
+    inner: M,
+    touched: Vec<bool>,
+    touched_pages: u64,
+    quota: Option<u64>,
+}
+
+impl<M: Memory> QuotaMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    // Number of pages touched since the memory was created.
+    pub fn touched_pages(&self) -> u64 {
+        self.touched_pages
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    // Limits the pages touched, None removes the limit. Pages already
+    // touched count against the new quota.
+    pub fn set_quota(&mut self, quota: Option<u64>) {
+        self.quota = quota;
+    }
+
+    fn touch(&mut self, addr: u64, size: u64) -> Result<(), Error> {
+        if size == 0 || self.touched.is_empty() {
+            return Ok(());
+        }
+        // Pages out of bound are left to the inner memory to reject.
+        let last_page = self.touched.len() as u64 - 1;
+        let first = addr >> RISCV_PAGE_SHIFTS;
+        if first > last_page {
+            return Ok(());
+        }
+        let last = (addr.saturating_add(size - 1) >> RISCV_PAGE_SHIFTS).min(last_page);
+        let pages = &mut self.touched[first as usize..=last as usize];
+        let new_pages = pages.iter().filter(|touched| !**touched).count() as u64;
+        if new_pages == 0 {
+            return Ok(());
+        }
+        let touched_pages = self.touched_pages + new_pages;
+        if matches!(self.quota, Some(quota) if touched_pages > quota) {
+            return Err(Error::MemQuotaExceeded);
+        }
+        for touched in pages.iter_mut() {
+            *touched = true;
+        }
+        self.touched_pages = touched_pages;
+        Ok(())
+    }
+}
+
+impl<M: Memory> Memory for QuotaMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            touched: vec![false; memory_size / RISCV_PAGESIZE],
+            touched_pages: 0,
+            quota: None,
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.touch(addr, size)?;
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.touch(addr, 2)?;
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.touch(addr, 4)?;
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.touch(addr.to_u64(), 1)?;
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.touch(addr.to_u64(), 2)?;
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.touch(addr.to_u64(), 4)?;
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.touch(addr.to_u64(), 8)?;
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.touch(addr.to_u64(), 1)?;
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.touch(addr.to_u64(), 2)?;
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.touch(addr.to_u64(), 4)?;
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.touch(addr.to_u64(), 8)?;
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.touch(addr, value.len() as u64)?;
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.touch(addr, size)?;
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.touch(addr, size)?;
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.touch(src, size)?;
+        self.touch(dst, size)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
@@ -0,0 +1,204 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY};
+use super::Memory;
+
+use bytes::Bytes;
+
+// RecordingMemory logs the bytes written to the inner memory while recording
+// is enabled, for the recorder of the machine module, see record. Stores are
+// logged as (address, bytes written), including the ones of syscalls and of
+// the ELF loader; failed stores are not logged.
+pub struct RecordingMemory<M: Memory> {
+    inner: M,
+    recording: bool,
+    stores: Vec<(u64, Vec<u8>)>,
+}
+
+impl<M: Memory> RecordingMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording
+    }
+
+    // Starts or stops logging stores, discarding stores logged so far.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+        self.stores.clear();
+    }
+
+    // Returns the stores logged since the last call.
+    pub fn take_stores(&mut self) -> Vec<(u64, Vec<u8>)> {
+        std::mem::take(&mut self.stores)
+    }
+
+    fn log(&mut self, addr: u64, value: &[u8]) {
+        if self.recording && !value.is_empty() {
+            self.stores.push((addr, value.to_vec()));
+        }
+    }
+
+    fn log_value(&mut self, addr: &M::REG, value: &M::REG, size: usize) {
+        if self.recording {
+            let bytes = value.to_u64().to_le_bytes();
+            self.log(addr.to_u64(), &bytes[..size]);
+        }
+    }
+}
+
+impl<M: Memory> Memory for RecordingMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            recording: false,
+            stores: vec![],
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)?;
+        if self.recording {
+            let data = self.inner.load_bytes(addr, size)?;
+            self.log(addr, &data);
+        }
+        Ok(())
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store8(addr, value)?;
+        self.log_value(addr, value, 1);
+        Ok(())
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store16(addr, value)?;
+        self.log_value(addr, value, 2);
+        Ok(())
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store32(addr, value)?;
+        self.log_value(addr, value, 4);
+        Ok(())
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store64(addr, value)?;
+        self.log_value(addr, value, 8);
+        Ok(())
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.inner.store_bytes(addr, value)?;
+        self.log(addr, value);
+        Ok(())
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.inner.store_byte(addr, size, value)?;
+        if self.recording {
+            self.log(addr, &vec![value; size as usize]);
+        }
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.inner.copy_bytes(dst, src, size)?;
+        if self.recording {
+            let data = self.inner.load_bytes(dst, size)?;
+            self.log(dst, &data);
+        }
+        Ok(())
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
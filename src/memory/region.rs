@@ -0,0 +1,295 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::Memory;
+
+use bytes::Bytes;
+
+struct Region<M> {
+    base: u64,
+    size: u64,
+    memory: M,
+}
+
+// RegionMemory builds an address space out of several disjoint regions, each
+// backed by its own inner memory, e.g. code at one base and a large data
+// window elsewhere. Accesses falling into holes between regions, or crossing
+// the end of a region, fail with MemOutOfBound:
+//
+// let mut memory = RegionMemory::<SparseMemory<u64>>::empty();
+// memory.add_region(0x1000_0000, 1 << 20)?;
+// memory.add_region(0x8000_0000, 4 << 20)?;
+//
+// memory_size is the end of the highest region, the default stack layout
+// ends there too, see AddressSpaceLayout to place it elsewhere.
+pub struct RegionMemory<M: Memory> {
+    // Sorted by base address.
+    regions: Vec<Region<M>>,
+    // Kept here since reservations may be in any region.
+    load_reservation_address: M::REG,
+}
+
+impl<M: Memory> RegionMemory<M> {
+    // Returns an address space without any region.
+    pub fn empty() -> Self {
+        Self {
+            regions: vec![],
+            load_reservation_address: M::REG::from_u64(u64::MAX),
+        }
+    }
+
+    // Maps `size` bytes at `base`, both page aligned, backed by a new inner
+    // memory. Regions must not overlap.
+    pub fn add_region(&mut self, base: u64, size: u64) -> Result<(), Error> {
+        let page_mask = RISCV_PAGESIZE as u64 - 1;
+        if base & page_mask != 0 || size & page_mask != 0 || size == 0 {
+            return Err(Error::Unexpected(format!(
+                "Region 0x{:x} of {} bytes is not page aligned",
+                base, size
+            )));
+        }
+        if size > RISCV_MAX_MEMORY as u64 {
+            return Err(Error::Unexpected(format!(
+                "Region 0x{:x} exceeds {} bytes",
+                base, RISCV_MAX_MEMORY
+            )));
+        }
+        let end = base.checked_add(size).ok_or(Error::MemOutOfBound)?;
+        if self
+            .regions
+            .iter()
+            .any(|r| base < r.base + r.size && r.base < end)
+        {
+            return Err(Error::Unexpected(format!(
+                "Region 0x{:x} overlaps another region",
+                base
+            )));
+        }
+        let position = self
+            .regions
+            .iter()
+            .position(|r| r.base > base)
+            .unwrap_or(self.regions.len());
+        self.regions.insert(
+            position,
+            Region {
+                base,
+                size,
+                memory: M::new_with_memory(size as usize),
+            },
+        );
+        Ok(())
+    }
+
+    // Returns (base, size) of each region, sorted by base address.
+    pub fn regions(&self) -> Vec<(u64, u64)> {
+        self.regions.iter().map(|r| (r.base, r.size)).collect()
+    }
+
+    pub fn region_mut(&mut self, base: u64) -> Option<&mut M> {
+        self.regions
+            .iter_mut()
+            .find(|r| r.base == base)
+            .map(|r| &mut r.memory)
+    }
+
+    // Returns the region holding the `size` bytes at `addr`, and the address
+    // in the inner memory.
+    fn find(&mut self, addr: u64, size: u64) -> Result<(&mut M, u64), Error> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|r| addr >= r.base && addr - r.base < r.size)
+            .ok_or(Error::MemOutOfBound)?;
+        let offset = addr - region.base;
+        if size > region.size - offset {
+            return Err(Error::MemOutOfBound);
+        }
+        Ok((&mut region.memory, offset))
+    }
+
+    fn find_page(&mut self, page: u64) -> Result<(&mut M, u64), Error> {
+        let addr = page
+            .checked_mul(RISCV_PAGESIZE as u64)
+            .ok_or(Error::MemOutOfBound)?;
+        let (memory, offset) = self.find(addr, RISCV_PAGESIZE as u64)?;
+        Ok((memory, offset >> RISCV_PAGE_SHIFTS))
+    }
+
+    fn find_reg(&mut self, addr: &M::REG, size: u64) -> Result<(&mut M, M::REG), Error> {
+        let (memory, offset) = self.find(addr.to_u64(), size)?;
+        Ok((memory, M::REG::from_u64(offset)))
+    }
+}
+
+impl<M: Memory> Memory for RegionMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    // A single region at address 0.
+    fn new_with_memory(memory_size: usize) -> Self {
+        let mut memory = Self::empty();
+        memory
+            .add_region(0, memory_size as u64)
+            .expect("invalid memory size");
+        memory
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        let (memory, offset) = self.find(addr, size)?;
+        memory.init_pages(offset, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        let (memory, page) = self.find_page(page)?;
+        memory.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let (memory, page) = self.find_page(page)?;
+        memory.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let (memory, page) = self.find_page(page)?;
+        memory.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.regions
+            .last()
+            .map(|r| (r.base + r.size) as usize)
+            .unwrap_or(0)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        let (memory, offset) = self.find(addr, size)?;
+        memory.store_byte(offset, size, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        let (memory, offset) = self.find(addr, value.len() as u64)?;
+        memory.store_bytes(offset, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let (memory, offset) = self.find(addr, size)?;
+        memory.load_bytes(offset, size)
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        let (memory, offset) = self.find(addr, 2)?;
+        memory.execute_load16(offset)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        let (memory, offset) = self.find(addr, 4)?;
+        memory.execute_load32(offset)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let (memory, offset) = self.find_reg(addr, 1)?;
+        memory.load8(&offset)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let (memory, offset) = self.find_reg(addr, 2)?;
+        memory.load16(&offset)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let (memory, offset) = self.find_reg(addr, 4)?;
+        memory.load32(&offset)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let (memory, offset) = self.find_reg(addr, 8)?;
+        memory.load64(&offset)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        let (memory, offset) = self.find_reg(addr, 1)?;
+        memory.store8(&offset, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        let (memory, offset) = self.find_reg(addr, 2)?;
+        memory.store16(&offset, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        let (memory, offset) = self.find_reg(addr, 4)?;
+        memory.store32(&offset, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        let (memory, offset) = self.find_reg(addr, 8)?;
+        memory.store64(&offset, value)
+    }
+
+    fn lr(&self) -> &Self::REG {
+        &self.load_reservation_address
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.load_reservation_address = value.clone();
+    }
+
+    fn trap(&mut self) {
+        for r in &mut self.regions {
+            r.memory.trap();
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for r in &mut self.regions {
+            r.memory.tick(cycles);
+        }
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.regions
+            .iter()
+            .fold(0, |pending, r| pending | r.memory.pending_interrupts())
+    }
+
+    // True only if all regions use huge pages, every region is asked anyway.
+    fn use_huge_pages(&mut self) -> bool {
+        let mut all = !self.regions.is_empty();
+        for region in &mut self.regions {
+            all &= region.memory.use_huge_pages();
+        }
+        all
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        let mut all = true;
+        for region in &mut self.regions {
+            all &= region.memory.track_flag_changes(enabled);
+        }
+        all
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        // Regions are sorted, so are the pages.
+        let mut changes = vec![];
+        for r in &mut self.regions {
+            let first = r.base >> RISCV_PAGE_SHIFTS;
+            changes.extend(
+                r.memory
+                    .take_flag_changes()
+                    .into_iter()
+                    .map(|(page, flags)| (first + page, flags)),
+            );
+        }
+        changes
+    }
+}
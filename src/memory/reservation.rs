@@ -0,0 +1,185 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY};
+use super::Memory;
+
+use bytes::Bytes;
+
+// Bytes covered by a reservation, starting from the LR address aligned down.
+pub const RESERVATION_GRANULE: u64 = 8;
+
+// StrictReservationMemory models the reservation set of LR/SC the way
+// hardware does. By default a reservation is only consumed by SC, with this
+// wrapper it is also invalidated by:
+// * stores to the reserved bytes, including AMOs and failed SCs;
+// * writes by other agents, i.e. syscalls writing guest memory;
+// * traps, i.e. ECALL, EBREAK, interrupts and exceptions taken in the guest.
+// This is useful for validating lock-free guest code, but it is not the
+// consensus semantics of CKB.
+pub struct StrictReservationMemory<M: Memory> {
+    inner: M,
+}
+
+impl<M: Memory> StrictReservationMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    // Returns true if a reservation is held. No reservation is u64::MAX
+    // truncated to the register width, see SC.
+    pub fn reserved(&self) -> bool {
+        self.inner.lr().to_u64() != M::REG::from_u64(u64::MAX).to_u64()
+    }
+
+    fn invalidate_overlapping(&mut self, addr: u64, size: u64) {
+        if !self.reserved() || size == 0 {
+            return;
+        }
+        let start = self.inner.lr().to_u64() & !(RESERVATION_GRANULE - 1);
+        let end = start.saturating_add(RESERVATION_GRANULE);
+        if addr < end && addr.saturating_add(size) > start {
+            self.invalidate();
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.set_lr(&M::REG::from_u64(u64::MAX));
+    }
+}
+
+impl<M: Memory> Memory for StrictReservationMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.invalidate_overlapping(addr, size);
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.invalidate_overlapping(addr.to_u64(), 1);
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.invalidate_overlapping(addr.to_u64(), 2);
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.invalidate_overlapping(addr.to_u64(), 4);
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.invalidate_overlapping(addr.to_u64(), 8);
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.invalidate_overlapping(addr, value.len() as u64);
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.invalidate_overlapping(addr, size);
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.invalidate_overlapping(dst, size);
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.invalidate();
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
@@ -0,0 +1,339 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::{
+    fill_page_data, memset, round_page_down, FlagChanges, Memory, Page, FLAG_DIRTY,
+    FLAG_EXECUTABLE, FLAG_FREEZED,
+};
+
+use bytes::Bytes;
+use std::cmp::min;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, Weak};
+
+static ZERO_PAGE: Page = [0; RISCV_PAGESIZE];
+
+// PagePool deduplicates the read-only pages of programs between memories.
+// Pages are found by content, so machines loading the same program end up
+// referencing the same host pages whichever way they loaded it. The pool
+// only keeps weak references, pages are released with the last memory
+// using them.
+#[derive(Clone, Default)]
+pub struct PagePool {
+    pages: Arc<Mutex<HashMap<u64, Vec<Weak<Page>>>>>,
+}
+
+impl PagePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of distinct pages currently shared through the pool.
+    pub fn len(&self) -> usize {
+        let mut pages = self.lock();
+        pages.retain(|_, candidates| {
+            candidates.retain(|page| page.strong_count() > 0);
+            !candidates.is_empty()
+        });
+        pages.values().map(|candidates| candidates.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Vec<Weak<Page>>>> {
+        match self.pages.lock() {
+            Ok(pages) => pages,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Returns the pooled page with the content of `page`, adding `page` to
+    // the pool when there is none.
+    fn intern(&self, page: &Arc<Page>) -> Arc<Page> {
+        let mut hasher = DefaultHasher::new();
+        page[..].hash(&mut hasher);
+        let mut pages = self.lock();
+        let candidates = pages.entry(hasher.finish()).or_default();
+        candidates.retain(|candidate| candidate.strong_count() > 0);
+        for candidate in candidates.iter() {
+            if let Some(candidate) = candidate.upgrade() {
+                if candidate[..] == page[..] {
+                    return candidate;
+                }
+            }
+        }
+        candidates.push(Arc::downgrade(page));
+        Arc::clone(page)
+    }
+}
+
+// SharedMemory is a sparse memory whose pages are reference counted, so
+// executable and read-only pages can be shared between the machines running
+// the same program instead of being copied into each of them. Pages are
+// copied on write, the first store to a shared page gives the machine its
+// own copy, so sharing is invisible to programs. Like SparseMemory, it does
+// no permission checking, wrap it in WXorXMemory for that.
+//
+// Memories join a pool before loading the program:
+//
+// let pool = PagePool::new();
+// for _ in 0..machines {
+//     let mut core = DefaultCoreMachine::<u64, WXorXMemory<SharedMemory<u64>>>::new(
+//         isa, version, max_cycles,
+//     );
+//     core.memory_mut().inner_mut().set_pool(pool.clone());
+//     let mut machine = DefaultMachineBuilder::new(core).build();
+//     machine.load_program(&program, &args)?;
+//     ...
+// }
+//
+// Resetting a machine recreates its memory, which leaves the pool. Cloning
+// a memory shares all of its pages, dirty or not, which makes copies of a
+// loaded machine cheap, see MachineTemplate.
+#[derive(Clone)]
+pub struct SharedMemory<R> {
+    pages: Vec<Option<Arc<Page>>>,
+    flags: Vec<u8>,
+    flag_changes: FlagChanges,
+    memory_size: usize,
+    pool: Option<PagePool>,
+    load_reservation_address: R,
+}
+
+impl<R> SharedMemory<R> {
+    // Pages initialized as executable or read-only from now on are shared
+    // through `pool`.
+    pub fn set_pool(&mut self, pool: PagePool) {
+        self.pool = Some(pool);
+    }
+
+    // Number of pages holding data.
+    pub fn allocated_pages(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+
+    // Number of pages also referenced by other memories.
+    pub fn shared_pages(&self) -> usize {
+        self.pages
+            .iter()
+            .flatten()
+            .filter(|page| Arc::strong_count(page) > 1)
+            .count()
+    }
+
+    // Returns the pages covering `size` bytes at `addr`, as (page index,
+    // offset in page, length) chunks.
+    fn chunks(
+        &self,
+        addr: u64,
+        size: u64,
+    ) -> Result<impl Iterator<Item = (usize, usize, usize)>, Error> {
+        let end = addr.checked_add(size).ok_or(Error::MemOutOfBound)?;
+        if end > self.memory_size as u64 {
+            return Err(Error::MemOutOfBound);
+        }
+        let mut page_addr = round_page_down(addr);
+        let mut offset = addr - page_addr;
+        let mut remaining = size;
+        Ok(std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            let bytes = min(RISCV_PAGESIZE as u64 - offset, remaining);
+            let chunk = (
+                (page_addr >> RISCV_PAGE_SHIFTS) as usize,
+                offset as usize,
+                bytes as usize,
+            );
+            remaining -= bytes;
+            page_addr += RISCV_PAGESIZE as u64;
+            offset = 0;
+            Some(chunk)
+        }))
+    }
+
+    // Returns a page to write to, copying it first when it is shared.
+    fn page_mut(&mut self, page: usize) -> &mut Page {
+        let old = self.flags[page];
+        self.flag_changes.record(page as u64, old, old | FLAG_DIRTY);
+        self.flags[page] |= FLAG_DIRTY;
+        Arc::make_mut(self.pages[page].get_or_insert_with(|| Arc::new([0; RISCV_PAGESIZE])))
+    }
+
+    fn read(&self, addr: u64, out: &mut [u8]) -> Result<(), Error> {
+        let mut written = 0;
+        for (page, offset, bytes) in self.chunks(addr, out.len() as u64)? {
+            let data = self.pages[page].as_deref().unwrap_or(&ZERO_PAGE);
+            out[written..written + bytes].copy_from_slice(&data[offset..offset + bytes]);
+            written += bytes;
+        }
+        Ok(())
+    }
+
+    fn load(&self, addr: u64, bytes: usize) -> Result<u64, Error> {
+        debug_assert!(bytes == 1 || bytes == 2 || bytes == 4 || bytes == 8);
+        let mut value = [0u8; 8];
+        self.read(addr, &mut value[..bytes])?;
+        // RISC-V is little-endian by specification
+        Ok(u64::from_le_bytes(value))
+    }
+}
+
+impl<R: Register> Memory for SharedMemory<R> {
+    type REG = R;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        assert!(memory_size <= RISCV_MAX_MEMORY);
+        assert!(memory_size % RISCV_PAGESIZE == 0);
+        Self {
+            pages: vec![None; memory_size / RISCV_PAGESIZE],
+            flags: vec![0; memory_size / RISCV_PAGESIZE],
+            flag_changes: FlagChanges::default(),
+            memory_size,
+            pool: None,
+            load_reservation_address: R::from_u64(u64::MAX),
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        fill_page_data(self, addr, size, source, offset_from_addr)?;
+        let pool = match &self.pool {
+            Some(pool) if flags & (FLAG_EXECUTABLE | FLAG_FREEZED) != 0 => pool,
+            _ => return Ok(()),
+        };
+        for (page, _, _) in self.chunks(addr, size)? {
+            let interned = self.pages[page].as_ref().map(|data| pool.intern(data));
+            if interned.is_some() {
+                self.pages[page] = interned;
+            }
+        }
+        Ok(())
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.flags
+            .get(page as usize)
+            .copied()
+            .ok_or(Error::MemOutOfBound)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let flags = self
+            .flags
+            .get_mut(page as usize)
+            .ok_or(Error::MemOutOfBound)?;
+        self.flag_changes.record(page, *flags, *flags | flag);
+        *flags |= flag;
+        Ok(())
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let flags = self
+            .flags
+            .get_mut(page as usize)
+            .ok_or(Error::MemOutOfBound)?;
+        self.flag_changes.record(page, *flags, *flags & !flag);
+        *flags &= !flag;
+        Ok(())
+    }
+
+    fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 1)?;
+        Ok(Self::REG::from_u8(v as u8))
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 2)?;
+        Ok(Self::REG::from_u16(v as u16))
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 4)?;
+        Ok(Self::REG::from_u32(v as u32))
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let v = self.load(addr.to_u64(), 8)?;
+        Ok(Self::REG::from_u64(v))
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.load(addr, 2).map(|v| v as u16)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.load(addr, 4).map(|v| v as u32)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        let mut remaining = value;
+        for (page, offset, bytes) in self.chunks(addr, value.len() as u64)? {
+            self.page_mut(page)[offset..offset + bytes].copy_from_slice(&remaining[..bytes]);
+            remaining = &remaining[bytes..];
+        }
+        Ok(())
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        for (page, offset, bytes) in self.chunks(addr, size)? {
+            memset(&mut self.page_mut(page)[offset..offset + bytes], value);
+        }
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let mut out = vec![0; size as usize];
+        self.read(addr, &mut out)?;
+        Ok(Bytes::from(out))
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &[value.to_u8()])
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u16().to_le_bytes())
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u32().to_le_bytes())
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.store_bytes(addr.to_u64(), &value.to_u64().to_le_bytes())
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.flag_changes.set_enabled(enabled);
+        true
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.flag_changes.take()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        &self.load_reservation_address
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.load_reservation_address = value.clone();
+    }
+}
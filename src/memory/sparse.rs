@@ -1,5 +1,5 @@
 use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
-use super::{fill_page_data, memset, round_page_down, Memory, Page, FLAG_DIRTY};
+use super::{fill_page_data, memset, round_page_down, FlagChanges, Memory, Page, FLAG_DIRTY};
 
 use bytes::Bytes;
 use std::cmp::min;
@@ -9,6 +9,7 @@ const INVALID_PAGE_INDEX: u16 = 0xFFFF;
 
 /// A sparse flat memory implementation, it allocates pages only when requested,
 /// but besides that, it does not permission checking.
+#[derive(Clone)]
 pub struct SparseMemory<R> {
     // Stores the indices of each page in pages data structure, if a page hasn't
     // been initialized, the corresponding position will be filled with
@@ -17,6 +18,7 @@ pub struct SparseMemory<R> {
     indices: Vec<u16>,
     pages: Vec<Page>,
     flags: Vec<u8>,
+    flag_changes: FlagChanges,
     memory_size: usize,
     riscv_pages: usize,
     load_reservation_address: R,
@@ -81,6 +83,7 @@ impl<R: Register> Memory for SparseMemory<R> {
             indices: vec![INVALID_PAGE_INDEX; memory_size / RISCV_PAGESIZE],
             pages: Vec::new(),
             flags: vec![0; memory_size / RISCV_PAGESIZE],
+            flag_changes: FlagChanges::default(),
             memory_size,
             riscv_pages: memory_size / RISCV_PAGESIZE,
             load_reservation_address: R::from_u64(u64::MAX),
@@ -109,6 +112,8 @@ impl<R: Register> Memory for SparseMemory<R> {
 
     fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
         if page < self.riscv_pages as u64 {
+            let old = self.flags[page as usize];
+            self.flag_changes.record(page, old, old | flag);
             self.flags[page as usize] |= flag;
             Ok(())
         } else {
@@ -118,6 +123,8 @@ impl<R: Register> Memory for SparseMemory<R> {
 
     fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
         if page < self.riscv_pages as u64 {
+            let old = self.flags[page as usize];
+            self.flag_changes.record(page, old, old & !flag);
             self.flags[page as usize] &= !flag;
             Ok(())
         } else {
@@ -265,6 +272,15 @@ impl<R: Register> Memory for SparseMemory<R> {
         )
     }
 
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.flag_changes.set_enabled(enabled);
+        true
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.flag_changes.take()
+    }
+
     fn lr(&self) -> &Self::REG {
         &self.load_reservation_address
     }
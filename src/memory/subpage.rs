@@ -0,0 +1,338 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::{Memory, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WXORX_BIT};
+
+use bytes::Bytes;
+
+// Default granularity of SubPageMemory, in bytes.
+pub const DEFAULT_LINE_SIZE: u64 = 64;
+// Smallest granularity, the size of a compressed instruction is not worth
+// tracking.
+pub const MIN_LINE_SIZE: u64 = 4;
+
+const LINE_FLAGS: u8 = FLAG_EXECUTABLE | FLAG_FREEZED;
+
+// SubPageMemory enforces W^X like WXorXMemory, but tracks the executable and
+// freezed flags per line of DEFAULT_LINE_SIZE bytes instead of per page. ELF
+// segments then only claim the lines they cover, so programs packing text
+// and rodata, or rodata and data, into the same page load and run, where
+// WXorXMemory fails with MemWriteOnFreezedPage or MemWriteOnExecutablePage:
+//
+// let core = DefaultCoreMachine::<u64, SubPageMemory<SparseMemory<u64>>>::new(
+//     isa, version, max_cycles,
+// );
+//
+// A segment claims the lines from its first byte to its last byte in the
+// file, writable segments also claim the rest of their last page for their
+// bss. Segments are loaded in ascending address order, the bytes between
+// segments sharing a page are left untouched. Page flags of the inner
+// memory hold the union of the flags of their lines, so the inner memory
+// must not check permissions itself. The asm machine checks page flags
+// only.
+pub struct SubPageMemory<M: Memory> {
+    inner: M,
+    line_shifts: u32,
+    lines: Vec<u8>,
+}
+
+impl<M: Memory> SubPageMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn line_size(&self) -> u64 {
+        1 << self.line_shifts
+    }
+
+    // Changes the granularity, a power of two between MIN_LINE_SIZE and the
+    // page size. Lines take the flags of their page, so this is best done
+    // before loading a program.
+    pub fn set_line_size(&mut self, line_size: u64) -> Result<(), Error> {
+        if !line_size.is_power_of_two()
+            || line_size < MIN_LINE_SIZE
+            || line_size > RISCV_PAGESIZE as u64
+        {
+            return Err(Error::Unexpected(format!(
+                "Invalid line size {}",
+                line_size
+            )));
+        }
+        let pages = self.inner.memory_size() / RISCV_PAGESIZE;
+        let lines_per_page = RISCV_PAGESIZE >> line_size.trailing_zeros();
+        let mut lines = Vec::with_capacity(pages * lines_per_page);
+        for page in 0..pages as u64 {
+            let flags = self.inner.fetch_flag(page)? & LINE_FLAGS;
+            lines.extend(std::iter::repeat(flags).take(lines_per_page));
+        }
+        self.line_shifts = line_size.trailing_zeros();
+        self.lines = lines;
+        Ok(())
+    }
+
+    // Executable and freezed flags of the line holding `addr`.
+    pub fn fetch_line_flag(&self, addr: u64) -> Result<u8, Error> {
+        self.lines
+            .get((addr >> self.line_shifts) as usize)
+            .copied()
+            .ok_or(Error::MemOutOfBound)
+    }
+
+    // Sets the flags of the lines covering `size` bytes at `addr`, for hosts
+    // protecting guest data more precisely than a page. Freezed lines can't
+    // be changed.
+    pub fn protect(&mut self, addr: u64, size: u64, flags: u8) -> Result<(), Error> {
+        let lines = self.line_range(addr, size)?;
+        if self.lines[lines.clone()]
+            .iter()
+            .any(|line| line & FLAG_FREEZED != 0)
+        {
+            return Err(Error::MemWriteOnFreezedPage);
+        }
+        for line in &mut self.lines[lines] {
+            *line = flags & LINE_FLAGS;
+        }
+        self.sync_page_flags(addr, size)
+    }
+
+    // Indices in `lines` of the lines covering `size` bytes at `addr`.
+    fn line_range(&self, addr: u64, size: u64) -> Result<std::ops::Range<usize>, Error> {
+        let end = addr.checked_add(size).ok_or(Error::MemOutOfBound)?;
+        if end > self.inner.memory_size() as u64 {
+            return Err(Error::MemOutOfBound);
+        }
+        let first = (addr >> self.line_shifts) as usize;
+        let last = ((end + self.line_size() - 1) >> self.line_shifts) as usize;
+        Ok(first..last)
+    }
+
+    fn check_lines(&self, addr: u64, size: u64, flag: u8) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let lines = self.line_range(addr, size)?;
+        if self.lines[lines]
+            .iter()
+            .any(|line| line & FLAG_WXORX_BIT != flag & FLAG_WXORX_BIT)
+        {
+            return Err(Error::MemWriteOnExecutablePage);
+        }
+        Ok(())
+    }
+
+    // Sets the flags of the pages covering `size` bytes at `addr` to the
+    // union of the flags of their lines.
+    fn sync_page_flags(&mut self, addr: u64, size: u64) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let lines_per_page = RISCV_PAGESIZE >> self.line_shifts;
+        let first = addr >> RISCV_PAGE_SHIFTS;
+        let last = (addr + size - 1) >> RISCV_PAGE_SHIFTS;
+        for page in first..=last {
+            let start = page as usize * lines_per_page;
+            let flags = self.lines[start..start + lines_per_page]
+                .iter()
+                .fold(0, |flags, line| flags | line);
+            self.inner.clear_flag(page, LINE_FLAGS & !flags)?;
+            self.inner.set_flag(page, flags)?;
+        }
+        Ok(())
+    }
+}
+
+impl<M: Memory> Memory for SubPageMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        let line_shifts = DEFAULT_LINE_SIZE.trailing_zeros();
+        Self {
+            inner: M::new_with_memory(memory_size),
+            line_shifts,
+            lines: vec![0; memory_size >> line_shifts],
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        if offset_from_addr > size {
+            return Err(Error::MemOutOfBound);
+        }
+        let start = addr
+            .checked_add(offset_from_addr)
+            .ok_or(Error::MemOutOfBound)?;
+        let end = addr.checked_add(size).ok_or(Error::MemOutOfBound)?;
+        let data_size = source
+            .as_ref()
+            .map_or(0, |source| source.len() as u64)
+            .min(size - offset_from_addr);
+        // Writable segments extend to the end of the range for their bss,
+        // other segments stop at their last byte.
+        let claimed_end = if flags & FLAG_FREEZED == 0 || data_size == 0 {
+            end
+        } else {
+            start + data_size
+        };
+        let lines = self.line_range(start, claimed_end - start)?;
+        if self.lines[lines.clone()]
+            .iter()
+            .any(|line| line & FLAG_FREEZED != 0)
+        {
+            return Err(Error::MemWriteOnFreezedPage);
+        }
+        for line in &mut self.lines[lines.clone()] {
+            *line = flags & LINE_FLAGS;
+        }
+        if let Some(source) = source {
+            self.inner
+                .store_bytes(start, &source[..data_size as usize])?;
+        }
+        // Zero fills the claimed lines past the data, up to the range end.
+        let zero_start = start + data_size;
+        let zero_end = end.min((lines.end as u64) << self.line_shifts);
+        if zero_end > zero_start {
+            self.inner
+                .store_byte(zero_start, zero_end - zero_start, 0)?;
+        }
+        self.sync_page_flags(start, claimed_end - start)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let lines_per_page = RISCV_PAGESIZE >> self.line_shifts;
+        let start = page as usize * lines_per_page;
+        let lines = self
+            .lines
+            .get_mut(start..start + lines_per_page)
+            .ok_or(Error::MemOutOfBound)?;
+        for line in lines {
+            *line |= flag & LINE_FLAGS;
+        }
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        let lines_per_page = RISCV_PAGESIZE >> self.line_shifts;
+        let start = page as usize * lines_per_page;
+        let lines = self
+            .lines
+            .get_mut(start..start + lines_per_page)
+            .ok_or(Error::MemOutOfBound)?;
+        for line in lines {
+            *line &= !flag;
+        }
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.check_lines(addr, 2, FLAG_EXECUTABLE)?;
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.check_lines(addr, 4, FLAG_EXECUTABLE)?;
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check_lines(addr.to_u64(), 1, 0)?;
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check_lines(addr.to_u64(), 2, 0)?;
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check_lines(addr.to_u64(), 4, 0)?;
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check_lines(addr.to_u64(), 8, 0)?;
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.check_lines(addr, value.len() as u64, 0)?;
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.check_lines(addr, size, 0)?;
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.check_lines(dst, size, 0)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
@@ -0,0 +1,315 @@
+use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use super::Memory;
+
+use bytes::Bytes;
+
+const WATCH_READ: u8 = 0b01;
+const WATCH_WRITE: u8 = 0b10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Both reads and writes.
+    Access,
+}
+
+impl WatchKind {
+    fn flags(self) -> u8 {
+        match self {
+            WatchKind::Read => WATCH_READ,
+            WatchKind::Write => WATCH_WRITE,
+            WatchKind::Access => WATCH_READ | WATCH_WRITE,
+        }
+    }
+}
+
+// Watches the bytes from start to end, end excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u64,
+    pub end: u64,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u64, size: u64, kind: WatchKind) -> Self {
+        Self {
+            start,
+            end: start.saturating_add(size),
+            kind,
+        }
+    }
+}
+
+// An access overlapping a watchpoint, reported before it happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub addr: u64,
+    pub size: u64,
+    pub write: bool,
+    pub watchpoint: Watchpoint,
+}
+
+// Called on each hit, returns whether the access goes on. Otherwise the
+// access fails with Error::Watchpoint.
+pub type WatchpointHandler = dyn FnMut(&WatchpointHit) -> bool + Send + Sync;
+
+// WatchpointMemory checks loads and stores, including the ones of syscalls,
+// against a set of watched ranges, to find which instruction corrupts a data
+// structure. Only pages overlapping a watchpoint are flagged, so accesses to
+// other pages cost a flag lookup. Without a handler, a hit stops the run
+// with Error::Watchpoint, which DefaultMachine fills with the PC of the
+// accessing instruction; the access is not performed:
+//
+// let mut core = DefaultCoreMachine::<u64, WatchpointMemory<SparseMemory<u64>>>::new(
+//     isa, version, max_cycles,
+// );
+// core.memory_mut().add_watchpoint(Watchpoint::new(table, 64, WatchKind::Write));
+// let mut machine = DefaultMachineBuilder::new(core).build();
+// machine.load_program(&program, &args)?;
+// if let Err(Error::Watchpoint { addr, pc }) = machine.run() { ... }
+//
+// Instruction fetches and the ELF loader are not checked. The asm machine
+// accesses memory without going through this wrapper.
+pub struct WatchpointMemory<M: Memory> {
+    inner: M,
+    watchpoints: Vec<Watchpoint>,
+    // WATCH_READ and WATCH_WRITE of the watchpoints overlapping each page.
+    page_flags: Vec<u8>,
+    handler: Option<Box<WatchpointHandler>>,
+}
+
+impl<M: Memory> WatchpointMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.flag_pages(&watchpoint);
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Returns whether the watchpoint existed.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        if self.watchpoints.len() == len {
+            return false;
+        }
+        for flags in self.page_flags.iter_mut() {
+            *flags = 0;
+        }
+        for watchpoint in self.watchpoints.clone() {
+            self.flag_pages(&watchpoint);
+        }
+        true
+    }
+
+    pub fn set_handler(&mut self, handler: Option<Box<WatchpointHandler>>) {
+        self.handler = handler;
+    }
+
+    // Pages from `addr` to `end` in bound, end excluded.
+    fn pages(&self, addr: u64, end: u64) -> std::ops::Range<usize> {
+        let pages = self.page_flags.len() as u64;
+        let first = (addr >> RISCV_PAGE_SHIFTS).min(pages);
+        let last = ((end.saturating_add(RISCV_PAGESIZE as u64 - 1)) >> RISCV_PAGE_SHIFTS)
+            .clamp(first, pages);
+        first as usize..last as usize
+    }
+
+    fn flag_pages(&mut self, watchpoint: &Watchpoint) {
+        let pages = self.pages(watchpoint.start, watchpoint.end);
+        for flags in &mut self.page_flags[pages] {
+            *flags |= watchpoint.kind.flags();
+        }
+    }
+
+    fn check(&mut self, addr: u64, size: u64, write: bool) -> Result<(), Error> {
+        if size == 0 || self.watchpoints.is_empty() {
+            return Ok(());
+        }
+        let flag = if write { WATCH_WRITE } else { WATCH_READ };
+        let end = addr.saturating_add(size);
+        if !self.page_flags[self.pages(addr, end)]
+            .iter()
+            .any(|flags| flags & flag != 0)
+        {
+            return Ok(());
+        }
+        let watchpoint = match self
+            .watchpoints
+            .iter()
+            .find(|w| w.kind.flags() & flag != 0 && w.start < end && addr < w.end)
+        {
+            Some(watchpoint) => *watchpoint,
+            None => return Ok(()),
+        };
+        let hit = WatchpointHit {
+            addr,
+            size,
+            write,
+            watchpoint,
+        };
+        let resume = match &mut self.handler {
+            Some(handler) => handler(&hit),
+            None => false,
+        };
+        if resume {
+            Ok(())
+        } else {
+            Err(Error::Watchpoint { addr, pc: 0 })
+        }
+    }
+}
+
+impl<M: Memory> Memory for WatchpointMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            watchpoints: vec![],
+            page_flags: vec![0; memory_size / RISCV_PAGESIZE],
+            handler: None,
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.check(addr.to_u64(), 1, false)?;
+        self.inner.load8(addr)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.check(addr.to_u64(), 2, false)?;
+        self.inner.load16(addr)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.check(addr.to_u64(), 4, false)?;
+        self.inner.load32(addr)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        self.check(addr.to_u64(), 8, false)?;
+        self.inner.load64(addr)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check(addr.to_u64(), 1, true)?;
+        self.inner.store8(addr, value)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check(addr.to_u64(), 2, true)?;
+        self.inner.store16(addr, value)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check(addr.to_u64(), 4, true)?;
+        self.inner.store32(addr, value)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.check(addr.to_u64(), 8, true)?;
+        self.inner.store64(addr, value)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.check(addr, value.len() as u64, true)?;
+        self.inner.store_bytes(addr, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.check(addr, size, true)?;
+        self.inner.store_byte(addr, size, value)
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.check(addr, size, false)?;
+        self.inner.load_bytes(addr, size)
+    }
+
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        self.check(src, size, false)?;
+        self.check(dst, size, true)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
+}
@@ -1,11 +1,12 @@
 use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
 use super::{
-    check_permission, get_page_indices, round_page_down, round_page_up, Memory, FLAG_EXECUTABLE,
-    FLAG_FREEZED, FLAG_WRITABLE,
+    check_permission, get_page_indices_within, round_page_down, round_page_up, Memory,
+    FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE,
 };
 
 use bytes::Bytes;
 
+#[derive(Clone)]
 pub struct WXorXMemory<M: Memory> {
     inner: M,
 }
@@ -14,6 +15,12 @@ impl<M: Memory> WXorXMemory<M> {
     pub fn inner_mut(&mut self) -> &mut M {
         &mut self.inner
     }
+
+    // Bounded by the inner memory, which may be a sparse address space such
+    // as RegionMemory.
+    fn page_indices(&self, addr: u64, size: u64) -> Result<(u64, u64), Error> {
+        get_page_indices_within(addr, size, self.memory_size() as u64)
+    }
 }
 
 impl<M: Memory> Memory for WXorXMemory<M> {
@@ -76,13 +83,13 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     }
 
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
-        let page_indices = get_page_indices(addr, 2)?;
+        let page_indices = self.page_indices(addr, 2)?;
         check_permission(self, &page_indices, FLAG_EXECUTABLE)?;
         self.inner.execute_load16(addr)
     }
 
     fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
-        let page_indices = get_page_indices(addr, 4)?;
+        let page_indices = self.page_indices(addr, 4)?;
         check_permission(self, &page_indices, FLAG_EXECUTABLE)?;
         self.inner.execute_load32(addr)
     }
@@ -104,25 +111,25 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     }
 
     fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 1)?;
+        let page_indices = self.page_indices(addr.to_u64(), 1)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store8(addr, value)
     }
 
     fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 2)?;
+        let page_indices = self.page_indices(addr.to_u64(), 2)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store16(addr, value)
     }
 
     fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 4)?;
+        let page_indices = self.page_indices(addr.to_u64(), 4)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store32(addr, value)
     }
 
     fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 8)?;
+        let page_indices = self.page_indices(addr.to_u64(), 8)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store64(addr, value)
     }
@@ -131,7 +138,7 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         if value.is_empty() {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, value.len() as u64)?;
+        let page_indices = self.page_indices(addr, value.len() as u64)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store_bytes(addr, value)
     }
@@ -140,7 +147,7 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, size)?;
+        let page_indices = self.page_indices(addr, size)?;
         check_permission(self, &page_indices, FLAG_WRITABLE)?;
         self.inner.store_byte(addr, size, value)
     }
@@ -150,6 +157,27 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         self.inner.load_bytes(addr, size)
     }
 
+    fn copy_bytes(&mut self, dst: u64, src: u64, size: u64) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let page_indices = self.page_indices(dst, size)?;
+        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        self.inner.copy_bytes(dst, src, size)
+    }
+
+    fn use_huge_pages(&mut self) -> bool {
+        self.inner.use_huge_pages()
+    }
+
+    fn track_flag_changes(&mut self, enabled: bool) -> bool {
+        self.inner.track_flag_changes(enabled)
+    }
+
+    fn take_flag_changes(&mut self) -> Vec<(u64, u8)> {
+        self.inner.take_flag_changes()
+    }
+
     fn lr(&self) -> &Self::REG {
         self.inner.lr()
     }
@@ -157,4 +185,16 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     fn set_lr(&mut self, value: &Self::REG) {
         self.inner.set_lr(value);
     }
+
+    fn trap(&mut self) {
+        self.inner.trap();
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.inner.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u64 {
+        self.inner.pending_interrupts()
+    }
 }
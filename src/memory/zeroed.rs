@@ -0,0 +1,97 @@
+use std::ops::{Deref, DerefMut};
+
+// Zero filled memory mapped directly from the OS with an anonymous mapping.
+// The kernel backs pages with physical memory only when they are first
+// touched, so allocating is O(1) whatever the size, and pages the program
+// never uses cost nothing. Hosts without mmap, or where the mapping fails,
+// fall back to a zeroed Vec.
+pub enum ZeroedBuffer {
+    #[cfg(unix)]
+    Mapped {
+        ptr: std::ptr::NonNull<u8>,
+        len: usize,
+    },
+    Heap(Vec<u8>),
+}
+
+// The buffer owns its mapping, just like a Vec owns its allocation.
+#[cfg(unix)]
+unsafe impl Send for ZeroedBuffer {}
+#[cfg(unix)]
+unsafe impl Sync for ZeroedBuffer {}
+
+impl ZeroedBuffer {
+    #[cfg(unix)]
+    pub fn new(len: usize) -> Self {
+        if len == 0 {
+            return ZeroedBuffer::Heap(Vec::new());
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        match std::ptr::NonNull::new(ptr as *mut u8) {
+            Some(ptr) if ptr.as_ptr() as *mut libc::c_void != libc::MAP_FAILED => {
+                ZeroedBuffer::Mapped { ptr, len }
+            }
+            _ => ZeroedBuffer::Heap(vec![0; len]),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(len: usize) -> Self {
+        ZeroedBuffer::Heap(vec![0; len])
+    }
+
+    // True if the buffer is an anonymous mapping.
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            #[cfg(unix)]
+            ZeroedBuffer::Mapped { .. } => true,
+            ZeroedBuffer::Heap(_) => false,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ZeroedBuffer {
+    fn drop(&mut self) {
+        if let ZeroedBuffer::Mapped { ptr, len } = self {
+            unsafe {
+                libc::munmap(ptr.as_ptr() as *mut libc::c_void, *len);
+            }
+        }
+    }
+}
+
+impl Deref for ZeroedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(unix)]
+            ZeroedBuffer::Mapped { ptr, len } => unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr(), *len)
+            },
+            ZeroedBuffer::Heap(data) => data,
+        }
+    }
+}
+
+impl DerefMut for ZeroedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            #[cfg(unix)]
+            ZeroedBuffer::Mapped { ptr, len } => unsafe {
+                std::slice::from_raw_parts_mut(ptr.as_ptr(), *len)
+            },
+            ZeroedBuffer::Heap(data) => data,
+        }
+    }
+}
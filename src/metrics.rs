@@ -0,0 +1,78 @@
+use crate::Error;
+
+// MetricsSink receives counters from a running machine so long-running hosts
+// (e.g. a CKB node) can forward them to their own monitoring system, such as
+// Prometheus, without patching VM internals.
+//
+// All methods come with an empty default implementation, a sink only needs to
+// override the counters it is interested in. The hooks are invoked on the VM
+// thread, implementations should keep them cheap.
+pub trait MetricsSink: Send + Sync {
+    // A run of the machine finished, `cycles` is the amount of cycles consumed
+    // by this particular run(not the accumulated cycles of the machine).
+    fn execution(&mut self, _cycles: u64) {}
+    // A decoded trace was found in the trace cache and reused.
+    fn trace_cache_hit(&mut self) {}
+    // A trace has to be decoded since it was not found in the trace cache.
+    fn trace_cache_miss(&mut self) {}
+    // A JALR jumped to the same target as last time, whose trace was taken
+    // from the cache of the JALR site. Also counted as a trace cache hit.
+    fn jalr_cache_hit(&mut self) {}
+    // A run stopped because max cycles was reached, the host may create a
+    // snapshot and resume the machine later with more cycles.
+    fn suspension(&mut self) {}
+    // A run stopped with an error, `kind` is a stable label suitable for
+    // using as a metric label, see `error_kind`.
+    fn error(&mut self, _kind: &'static str) {}
+}
+
+// Returns a short, stable label for each error variant. Unlike the Display
+// implementation, the returned value never contains dynamic data so it can be
+// safely used as a metric label.
+pub fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::Asm(_) => "asm",
+        Error::CyclesExceeded => "cycles_exceeded",
+        Error::CyclesOverflow => "cycles_overflow",
+        Error::Context { error, .. } => error_kind(error),
+        Error::DeniedInstruction { .. } => "denied_instruction",
+        Error::ElfBits => "elf_bits",
+        Error::ElfParseError(_) => "elf_parse_error",
+        Error::ElfSegmentUnreadable => "elf_segment_unreadable",
+        Error::ElfSegmentWritableAndExecutable => "elf_segment_writable_and_executable",
+        Error::ElfSegmentAddrOrSizeError => "elf_segment_addr_or_size_error",
+        Error::External(_) => "external",
+        Error::InfiniteLoop { .. } => "infinite_loop",
+        Error::InvalidEcall(_) => "invalid_ecall",
+        Error::InvalidInstruction { .. } => "invalid_instruction",
+        Error::InvalidIsa(_) => "invalid_isa",
+        Error::InvalidOp(_) => "invalid_op",
+        Error::InvalidVersion => "invalid_version",
+        Error::IO { .. } => "io",
+        Error::MemOutOfBound => "mem_out_of_bound",
+        Error::MemOutOfStack => "mem_out_of_stack",
+        Error::MemPageUnalignedAccess => "mem_page_unaligned_access",
+        Error::MemUnalignedAccess => "mem_unaligned_access",
+        Error::MemWriteOnExecutablePage => "mem_write_on_executable_page",
+        Error::MemWriteOnFreezedPage => "mem_write_on_freezed_page",
+        Error::MemWriteLimitExceeded => "mem_write_limit_exceeded",
+        Error::MemQuotaExceeded => "mem_quota_exceeded",
+        Error::ProgramNotAllowed(_) => "program_not_allowed",
+        Error::SyscallRetry(_) => "syscall_retry",
+        Error::Unexpected(_) => "unexpected",
+        Error::Unimplemented => "unimplemented",
+        Error::Watchpoint { .. } => "watchpoint",
+    }
+}
+
+// Reports the outcome of a single run to the sink. A run exceeding max cycles
+// or stopped by a syscall to retry is counted as a suspension rather than an
+// error.
+pub(crate) fn report_run(sink: &mut dyn MetricsSink, cycles: u64, result: &Result<i8, Error>) {
+    sink.execution(cycles);
+    match result {
+        Ok(_) => (),
+        Err(Error::CyclesExceeded) | Err(Error::SyscallRetry(_)) => sink.suspension(),
+        Err(e) => sink.error(error_kind(e)),
+    }
+}
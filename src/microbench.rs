@@ -0,0 +1,278 @@
+// Microbenchmarks measuring the throughput of each instruction class, to
+// check the cost model against real execution time. Each benchmark runs a
+// tight loop whose body repeats a single instruction:
+//
+//     li   t0, iterations
+// loop:
+//     <instruction> x unroll
+//     addi t0, t0, -1
+//     bnez t0, loop
+//
+// let options = MicrobenchOptions::default();
+// for result in run_microbenches(&options)? {
+//     println!("{}", result);
+// }
+//
+// A class whose nanoseconds per model cycle is well above the others is
+// undercharged by the cost model, and the other way around.
+#[cfg(has_asm)]
+use crate::machine::asm::{AsmCoreMachine, AsmMachine};
+use crate::{
+    cost_model::estimate_cycles,
+    elf_writer::minimal_elf,
+    instructions::{insts, Instruction},
+    machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION2},
+    memory::{sparse::SparseMemory, wxorx::WXorXMemory},
+    Bytes, Error, SupportMachine, TraceMachine, ISA_B, ISA_IMC, RISCV_MAX_MEMORY,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_j, pack_r, pack_s, pack_u, to_riscv};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const T0: u8 = 5;
+const T1: u8 = 6;
+const T2: u8 = 7;
+const T3: u8 = 28;
+const SP: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchClass {
+    // add t1, t2, t3
+    Alu,
+    // addi t1, t2, 1
+    Immediate,
+    // sll t1, t2, t3
+    Shift,
+    // mul t1, t2, t3
+    Multiply,
+    // mulh t1, t2, t3
+    MultiplyHigh,
+    // div t1, t2, t3
+    Divide,
+    // ld t1, 0(sp)
+    Load,
+    // sd t2, -8(sp)
+    Store,
+    // bne t2, t2, 8, never taken
+    Branch,
+    // beq zero, zero, 4, always taken
+    BranchTaken,
+    // jal zero, 4
+    Jump,
+    // cpop t1, t2
+    Bitmanip,
+}
+
+impl BenchClass {
+    pub const ALL: [BenchClass; 12] = [
+        BenchClass::Alu,
+        BenchClass::Immediate,
+        BenchClass::Shift,
+        BenchClass::Multiply,
+        BenchClass::MultiplyHigh,
+        BenchClass::Divide,
+        BenchClass::Load,
+        BenchClass::Store,
+        BenchClass::Branch,
+        BenchClass::BranchTaken,
+        BenchClass::Jump,
+        BenchClass::Bitmanip,
+    ];
+
+    pub fn instruction(self) -> Instruction {
+        match self {
+            BenchClass::Alu => pack_r(insts::OP_ADD, T1, T2, T3),
+            BenchClass::Immediate => pack_i(insts::OP_ADDI, T1, T2, 1),
+            BenchClass::Shift => pack_r(insts::OP_SLL, T1, T2, T3),
+            BenchClass::Multiply => pack_r(insts::OP_MUL, T1, T2, T3),
+            BenchClass::MultiplyHigh => pack_r(insts::OP_MULH, T1, T2, T3),
+            BenchClass::Divide => pack_r(insts::OP_DIV, T1, T2, T3),
+            BenchClass::Load => pack_i(insts::OP_LD_VERSION1, T1, SP, 0),
+            BenchClass::Store => pack_s(insts::OP_SD, SP, T2, -8),
+            BenchClass::Branch => pack_b(insts::OP_BNE, T2, T2, 8),
+            BenchClass::BranchTaken => pack_b(insts::OP_BEQ, 0, 0, 4),
+            BenchClass::Jump => pack_j(insts::OP_JAL, 0, 4),
+            // The rs2 field selects cpop among unary instructions.
+            BenchClass::Bitmanip => pack_r(insts::OP_CPOP, T1, T2, 0b_00010),
+        }
+    }
+}
+
+impl fmt::Display for BenchClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Trace,
+    #[cfg(has_asm)]
+    Asm,
+}
+
+#[derive(Debug, Clone)]
+pub struct MicrobenchOptions {
+    pub backend: Backend,
+    pub version: u32,
+    // Number of loop iterations, below 2^31.
+    pub iterations: u32,
+    // Copies of the measured instruction in the loop body.
+    pub unroll: usize,
+    // Cost model compared against real time.
+    pub cost_model: fn(Instruction) -> u64,
+}
+
+impl Default for MicrobenchOptions {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Interpreter,
+            version: VERSION2,
+            iterations: 100_000,
+            unroll: 16,
+            cost_model: estimate_cycles,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub class: BenchClass,
+    pub backend: Backend,
+    // Retired instructions, including the loop overhead.
+    pub instructions: u64,
+    // Cycles charged by the cost model.
+    pub cycles: u64,
+    // Time spent running the program, excluding loading.
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    // Real time per model cycle, comparable across classes.
+    pub fn nanos_per_cycle(&self) -> f64 {
+        self.elapsed.as_nanos() as f64 / self.cycles as f64
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:?}: {:.0} instructions/s, {} cycles in {:?}, {:.3} ns/cycle",
+            self.class,
+            self.backend,
+            self.instructions_per_second(),
+            self.cycles,
+            self.elapsed,
+            self.nanos_per_cycle()
+        )
+    }
+}
+
+fn encode(instruction: Instruction) -> Result<u32, Error> {
+    to_riscv(instruction)
+        .ok_or_else(|| Error::Unexpected(String::from("Benchmark instruction is not encodable")))
+}
+
+// Builds a loop running `body` for `iterations` times, then exiting with
+// code 0. Returns the program and the number of instructions it retires.
+pub fn loop_program(body: &[Instruction], iterations: u32) -> Result<(Bytes, u64), Error> {
+    if iterations == 0 || iterations > i32::MAX as u32 - 0x800 {
+        return Err(Error::Unexpected(format!(
+            "Invalid benchmark iterations: {}",
+            iterations
+        )));
+    }
+    let iterations = iterations as i32;
+    let upper = (iterations + 0x800) & !0xfff;
+    let mut code = vec![
+        pack_u(insts::OP_LUI, T0, upper),
+        pack_i(insts::OP_ADDI, T0, T0, iterations - upper),
+        // Operands: t2 = -1, t3 = 7
+        pack_i(insts::OP_ADDI, T2, 0, -1),
+        pack_i(insts::OP_ADDI, T3, 0, 7),
+    ];
+    let prologue = code.len();
+    code.extend_from_slice(body);
+    code.push(pack_i(insts::OP_ADDI, T0, T0, -1));
+    let offset = -4 * (body.len() as i32 + 1);
+    code.push(pack_b(insts::OP_BNE, T0, 0, offset));
+    code.push(pack_i(insts::OP_ADDI, 10, 0, 0));
+    code.push(pack_i(insts::OP_ADDI, 17, 0, 93));
+    code.push(pack_r(insts::OP_ECALL, 0, 0, 0));
+    let mut bytes = Vec::with_capacity(code.len() * 4);
+    for instruction in code {
+        bytes.extend_from_slice(&encode(instruction)?.to_le_bytes());
+    }
+    let retired = prologue as u64 + iterations as u64 * (body.len() as u64 + 2) + 3;
+    Ok((minimal_elf::<u64>(&bytes), retired))
+}
+
+pub fn run_microbench(
+    class: BenchClass,
+    options: &MicrobenchOptions,
+) -> Result<BenchResult, Error> {
+    let body = vec![class.instruction(); options.unroll];
+    let (program, instructions) = loop_program(&body, options.iterations)?;
+    let isa = ISA_IMC | ISA_B;
+    let args = [Bytes::from("microbench")];
+    let cost_model = options.cost_model;
+    let (elapsed, cycles) = match options.backend {
+        Backend::Interpreter | Backend::Trace => {
+            let core_machine =
+                DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new_with_memory(
+                    isa,
+                    options.version,
+                    u64::MAX,
+                    RISCV_MAX_MEMORY,
+                );
+            let machine = DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(cost_model))
+                .build();
+            if options.backend == Backend::Trace {
+                let mut machine = TraceMachine::new(machine);
+                machine.load_program(&program, &args)?;
+                let start = Instant::now();
+                machine.run()?;
+                (start.elapsed(), machine.machine.cycles())
+            } else {
+                let mut machine = machine;
+                machine.load_program(&program, &args)?;
+                let start = Instant::now();
+                machine.run()?;
+                (start.elapsed(), machine.cycles())
+            }
+        }
+        #[cfg(has_asm)]
+        Backend::Asm => {
+            let core_machine = AsmCoreMachine::new(isa, options.version, u64::MAX);
+            let machine = DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(cost_model))
+                .build();
+            let mut machine = AsmMachine::new(machine);
+            machine.load_program(&program, &args)?;
+            let start = Instant::now();
+            machine.run()?;
+            (start.elapsed(), machine.machine.cycles())
+        }
+    };
+    Ok(BenchResult {
+        class,
+        backend: options.backend,
+        instructions,
+        cycles,
+        elapsed,
+    })
+}
+
+pub fn run_microbenches(options: &MicrobenchOptions) -> Result<Vec<BenchResult>, Error> {
+    BenchClass::ALL
+        .iter()
+        .map(|class| run_microbench(*class, options))
+        .collect()
+}
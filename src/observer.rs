@@ -0,0 +1,78 @@
+use crate::Error;
+
+// Structured events emitted by a machine, see EventObserver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    // load_program started, ProgramLoaded follows unless loading fails.
+    LoadStarted,
+    // A program was loaded, `bytes` is the value returned by load_program.
+    ProgramLoaded {
+        entry: u64,
+        bytes: u64,
+    },
+    // A trace was decoded by TraceMachine or AsmMachine.
+    TraceCompiled {
+        address: u64,
+        length: u64,
+        instructions: usize,
+    },
+    SyscallEntered {
+        code: u64,
+    },
+    // Emitted only when the syscall succeeds, otherwise the run fails and
+    // Failed is emitted.
+    SyscallExited {
+        code: u64,
+    },
+    // The flags of a memory page changed, either when loading a program or
+    // during a syscall, failing ones included.
+    MemoryPermissionChanged {
+        page: u64,
+        old: u8,
+        new: u8,
+    },
+    // A run of the machine started, one of Suspended, Terminated or Failed
+    // ends it.
+    RunStarted {
+        cycles: u64,
+    },
+    // A run stopped because max cycles was reached, the machine can be
+    // resumed with more cycles, or because a syscall has to be retried.
+    Suspended {
+        cycles: u64,
+    },
+    // The program exited normally.
+    Terminated {
+        exit_code: i8,
+        cycles: u64,
+    },
+    // A run stopped with an error other than CyclesExceeded.
+    Failed {
+        error: Error,
+    },
+}
+
+// EventObserver subscribes to all VM events at once, so monitoring, auditing
+// and replay tools don't need to hook each subsystem separately. Events are
+// delivered synchronously on the VM thread.
+pub trait EventObserver: Send + Sync {
+    fn on_event(&mut self, event: &Event);
+
+    // Receives the cycles of the machine along with each event, for
+    // observers building timelines.
+    fn on_event_at(&mut self, event: &Event, _cycles: u64) {
+        self.on_event(event);
+    }
+}
+
+// Converts the result of a run into the corresponding event.
+pub(crate) fn run_event(cycles: u64, result: &Result<i8, Error>) -> Event {
+    match result {
+        Ok(exit_code) => Event::Terminated {
+            exit_code: *exit_code,
+            cycles,
+        },
+        Err(Error::CyclesExceeded) | Err(Error::SyscallRetry(_)) => Event::Suspended { cycles },
+        Err(e) => Event::Failed { error: e.clone() },
+    }
+}
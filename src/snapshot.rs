@@ -1,11 +1,18 @@
+use crate::aead::{self, KEY_LENGTH, NONCE_LENGTH};
 use crate::instructions::Register;
+use crate::machine::float::{FloatRegisters, CSR_FCSR};
+use crate::machine::privileged::{CsrFile, Privileged};
+use crate::machine::vector::{VectorRegisters, CSR_VCSR, CSR_VL, CSR_VSTART, CSR_VTYPE};
 use crate::memory::Memory;
 use crate::memory::FLAG_DIRTY;
 use crate::{
-    CoreMachine, Error, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGES, RISCV_PAGESIZE,
-    RISCV_PAGE_SHIFTS,
+    Error, Machine, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGES, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
 };
-use serde::{Deserialize, Serialize};
+use rand::Rng;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
 // Snapshot provides a mechanism for suspending and resuming a virtual machine.
 //
@@ -18,6 +25,10 @@ use serde::{Deserialize, Serialize};
 //   - machine.version
 //   - machine.pc
 //   - machine.registers
+//   - machine.privileged, when ISA_PRIV is enabled
+//   - machine.float_registers and fcsr, when ISA_F is enabled
+//   - machine.vector_registers, vtype, vl and the other vector CSRs, when
+//     ISA_V is enabled
 //
 // For memory, the situation becomes more complicated. Every memory page has
 // page flag where each page flag stores a optional FLAG_DIRTY. When this page
@@ -27,7 +38,7 @@ use serde::{Deserialize, Serialize};
 // as dirty are the pages that have been modified by the program. We only store
 // these pages in the snapshot.
 
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Default)]
 pub struct Snapshot {
     pub version: u32,
     pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
@@ -35,12 +46,174 @@ pub struct Snapshot {
     pub page_indices: Vec<u64>,
     pub page_flags: Vec<u8>,
     pub pages: Vec<Vec<u8>>,
+    // Privilege mode and CSRs, None when ISA_PRIV is not enabled.
+    pub privileged: Option<Privileged>,
+    // Floating point registers and fcsr, None when ISA_F is not enabled.
+    pub float_registers: Option<FloatRegisters>,
+    // Vector registers and CSRs, None when ISA_V is not enabled. Always part
+    // of the layout, builds without the rvv feature refuse to resume a
+    // snapshot carrying them.
+    pub vector_registers: Option<VectorRegisters>,
 }
 
-pub fn make_snapshot<T: CoreMachine>(machine: &mut T) -> Result<Snapshot, Error> {
+// Set in the serialized version of snapshots carrying privileged, floating
+// point or vector state, which then follows the pages. Other snapshots keep
+// the layout they had before these fields existed, so positional formats
+// such as bincode still decode snapshots persisted by older releases, and
+// older releases still decode snapshots of machines without the extensions.
+const SNAPSHOT_EXTENDED_LAYOUT: u32 = 1 << 31;
+
+const SNAPSHOT_FIELDS: &[&str] = &[
+    "version",
+    "registers",
+    "pc",
+    "page_indices",
+    "page_flags",
+    "pages",
+    "privileged",
+    "float_registers",
+    "vector_registers",
+];
+
+impl Snapshot {
+    fn extended(&self) -> bool {
+        self.privileged.is_some()
+            || self.float_registers.is_some()
+            || self.vector_registers.is_some()
+    }
+}
+
+impl Serialize for Snapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let extended = self.extended();
+        let (version, fields) = if extended {
+            (
+                self.version | SNAPSHOT_EXTENDED_LAYOUT,
+                SNAPSHOT_FIELDS.len(),
+            )
+        } else {
+            (self.version, 6)
+        };
+        let mut state = serializer.serialize_struct("Snapshot", fields)?;
+        state.serialize_field("version", &version)?;
+        state.serialize_field("registers", &self.registers)?;
+        state.serialize_field("pc", &self.pc)?;
+        state.serialize_field("page_indices", &self.page_indices)?;
+        state.serialize_field("page_flags", &self.page_flags)?;
+        state.serialize_field("pages", &self.pages)?;
+        if extended {
+            state.serialize_field("privileged", &self.privileged)?;
+            state.serialize_field("float_registers", &self.float_registers)?;
+            state.serialize_field("vector_registers", &self.vector_registers)?;
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Snapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "snake_case")]
+        enum Field {
+            Version,
+            Registers,
+            Pc,
+            PageIndices,
+            PageFlags,
+            Pages,
+            Privileged,
+            FloatRegisters,
+            VectorRegisters,
+        }
+
+        struct SnapshotVisitor;
+
+        impl<'de> Visitor<'de> for SnapshotVisitor {
+            type Value = Snapshot;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct Snapshot")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Snapshot, A::Error> {
+                fn next<'de, A: SeqAccess<'de>, T: Deserialize<'de>>(
+                    seq: &mut A,
+                    index: usize,
+                ) -> Result<T, A::Error> {
+                    seq.next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(index, &"struct Snapshot"))
+                }
+                let version: u32 = next(&mut seq, 0)?;
+                let mut snap = Snapshot {
+                    version: version & !SNAPSHOT_EXTENDED_LAYOUT,
+                    registers: next(&mut seq, 1)?,
+                    pc: next(&mut seq, 2)?,
+                    page_indices: next(&mut seq, 3)?,
+                    page_flags: next(&mut seq, 4)?,
+                    pages: next(&mut seq, 5)?,
+                    ..Default::default()
+                };
+                if version & SNAPSHOT_EXTENDED_LAYOUT != 0 {
+                    snap.privileged = next(&mut seq, 6)?;
+                    snap.float_registers = next(&mut seq, 7)?;
+                    snap.vector_registers = next(&mut seq, 8)?;
+                }
+                Ok(snap)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Snapshot, A::Error> {
+                let mut snap = Snapshot::default();
+                let mut seen = [false; 6];
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Version => {
+                            snap.version = map.next_value::<u32>()? & !SNAPSHOT_EXTENDED_LAYOUT;
+                            seen[0] = true;
+                        }
+                        Field::Registers => {
+                            snap.registers = map.next_value()?;
+                            seen[1] = true;
+                        }
+                        Field::Pc => {
+                            snap.pc = map.next_value()?;
+                            seen[2] = true;
+                        }
+                        Field::PageIndices => {
+                            snap.page_indices = map.next_value()?;
+                            seen[3] = true;
+                        }
+                        Field::PageFlags => {
+                            snap.page_flags = map.next_value()?;
+                            seen[4] = true;
+                        }
+                        Field::Pages => {
+                            snap.pages = map.next_value()?;
+                            seen[5] = true;
+                        }
+                        Field::Privileged => snap.privileged = map.next_value()?,
+                        Field::FloatRegisters => snap.float_registers = map.next_value()?,
+                        Field::VectorRegisters => snap.vector_registers = map.next_value()?,
+                    }
+                }
+                if let Some(missing) = seen.iter().position(|seen| !seen) {
+                    return Err(de::Error::missing_field(SNAPSHOT_FIELDS[missing]));
+                }
+                Ok(snap)
+            }
+        }
+
+        deserializer.deserialize_struct("Snapshot", SNAPSHOT_FIELDS, SnapshotVisitor)
+    }
+}
+
+pub fn make_snapshot<T: Machine>(machine: &mut T) -> Result<Snapshot, Error> {
     let mut snap = Snapshot {
         version: machine.version(),
         pc: machine.pc().to_u64(),
+        privileged: machine.privileged_mut().map(|state| state.clone()),
+        float_registers: machine.float_registers().cloned(),
+        #[cfg(feature = "rvv")]
+        vector_registers: machine.vector_registers().cloned(),
         ..Default::default()
     };
     for (i, v) in machine.registers().iter().enumerate() {
@@ -78,10 +251,52 @@ pub fn make_snapshot<T: CoreMachine>(machine: &mut T) -> Result<Snapshot, Error>
     Ok(snap)
 }
 
-pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<(), Error> {
+pub fn resume<T: Machine>(machine: &mut T, snapshot: &Snapshot) -> Result<(), Error> {
     if machine.version() != snapshot.version {
         return Err(Error::InvalidVersion);
     }
+    match (machine.privileged_mut(), &snapshot.privileged) {
+        (Some(state), Some(saved)) => *state = saved.clone(),
+        (Some(state), None) => *state = Privileged::default(),
+        (None, Some(_)) => {
+            return Err(Error::Unexpected(String::from(
+                "Snapshot has privileged state but ISA_PRIV is not enabled",
+            )))
+        }
+        (None, None) => {}
+    }
+    match (machine.float_registers_mut(), &snapshot.float_registers) {
+        (Some(registers), Some(saved)) => *registers = saved.clone(),
+        (Some(registers), None) => *registers = FloatRegisters::default(),
+        (None, Some(_)) => {
+            return Err(Error::Unexpected(String::from(
+                "Snapshot has floating point registers but ISA_F is not enabled",
+            )))
+        }
+        (None, None) => {}
+    }
+    #[cfg(feature = "rvv")]
+    match (machine.vector_registers_mut(), &snapshot.vector_registers) {
+        (Some(_), Some(saved)) if !saved.is_valid() => {
+            return Err(Error::Unexpected(String::from(
+                "Snapshot has vector registers of another length",
+            )))
+        }
+        (Some(registers), Some(saved)) => *registers = saved.clone(),
+        (Some(registers), None) => *registers = VectorRegisters::default(),
+        (None, Some(_)) => {
+            return Err(Error::Unexpected(String::from(
+                "Snapshot has vector registers but ISA_V is not enabled",
+            )))
+        }
+        (None, None) => {}
+    }
+    #[cfg(not(feature = "rvv"))]
+    if snapshot.vector_registers.is_some() {
+        return Err(Error::Unexpected(String::from(
+            "Snapshot has vector registers but rvv is not enabled",
+        )));
+    }
     for (i, v) in snapshot.registers.iter().enumerate() {
         machine.set_register(i, T::REG::from_u64(*v));
     }
@@ -98,3 +313,188 @@ pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<()
 
     Ok(())
 }
+
+// Key sealing snapshots, supplied and kept secret by the embedder.
+pub type SnapshotKey = [u8; KEY_LENGTH];
+
+// Domain separation of the authenticated data, bumped with the encoding.
+const SEALED_SNAPSHOT_TAG: &[u8] = b"ckb-vm sealed snapshot v4";
+
+// SealedSnapshot is a Snapshot encrypted and authenticated with
+// ChaCha20-Poly1305, so machine state holding sensitive script inputs can be
+// persisted to untrusted storage. Only the version is left in clear, opening
+// fails if anything was altered or the key is wrong:
+//
+// let sealed = machine.snapshot()?.seal(&key);
+// store(&sealed);
+// ...
+// machine.resume(&load()?.open(&key)?)?;
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SealedSnapshot {
+    pub version: u32,
+    pub nonce: [u8; NONCE_LENGTH],
+    // Encrypted snapshot followed by the authentication tag.
+    pub payload: Vec<u8>,
+}
+
+impl Snapshot {
+    // Seals the snapshot with a random nonce.
+    pub fn seal(&self, key: &SnapshotKey) -> SealedSnapshot {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill(&mut nonce[..]);
+        self.seal_with_nonce(key, nonce)
+    }
+
+    // Seals the snapshot with a nonce chosen by the caller, which must never
+    // be used twice with the same key.
+    pub fn seal_with_nonce(&self, key: &SnapshotKey, nonce: [u8; NONCE_LENGTH]) -> SealedSnapshot {
+        SealedSnapshot {
+            version: self.version,
+            nonce,
+            payload: aead::seal(key, &nonce, &sealed_aad(self.version), &self.encode()),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let pages: usize = self.pages.iter().map(|page| page.len()).sum();
+        let mut out = Vec::with_capacity(8 * 77 + self.pages.len() * 17 + pages);
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        for register in &self.registers {
+            out.extend_from_slice(&register.to_le_bytes());
+        }
+        match &self.privileged {
+            Some(state) => {
+                out.push(1);
+                for word in &state.to_words() {
+                    out.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            None => out.push(0),
+        }
+        match &self.float_registers {
+            Some(registers) => {
+                out.push(1);
+                for i in 0..32 {
+                    out.extend_from_slice(&registers.register(i).to_le_bytes());
+                }
+                let fcsr = registers.read_csr(CSR_FCSR).unwrap_or(0);
+                out.extend_from_slice(&fcsr.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        match &self.vector_registers {
+            Some(registers) => {
+                out.push(1);
+                for csr in &[CSR_VTYPE, CSR_VL, CSR_VSTART, CSR_VCSR] {
+                    let value = registers.read_csr(*csr).unwrap_or(0);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                for i in 0..32 {
+                    out.extend_from_slice(registers.register(i));
+                }
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&(self.page_indices.len() as u64).to_le_bytes());
+        for ((index, flag), page) in self
+            .page_indices
+            .iter()
+            .zip(self.page_flags.iter())
+            .zip(self.pages.iter())
+        {
+            out.extend_from_slice(&index.to_le_bytes());
+            out.push(*flag);
+            out.extend_from_slice(&(page.len() as u64).to_le_bytes());
+            out.extend_from_slice(page);
+        }
+        out
+    }
+
+    fn decode(version: u32, mut data: &[u8]) -> Result<Snapshot, Error> {
+        fn take<'a>(data: &mut &'a [u8], length: u64) -> Result<&'a [u8], Error> {
+            if length > data.len() as u64 {
+                return Err(Error::Unexpected(String::from(
+                    "Sealed snapshot is truncated",
+                )));
+            }
+            let (head, tail) = data.split_at(length as usize);
+            *data = tail;
+            Ok(head)
+        }
+        fn take_u64(data: &mut &[u8]) -> Result<u64, Error> {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(take(data, 8)?);
+            Ok(u64::from_le_bytes(bytes))
+        }
+        let mut snap = Snapshot {
+            version,
+            pc: take_u64(&mut data)?,
+            ..Default::default()
+        };
+        for register in snap.registers.iter_mut() {
+            *register = take_u64(&mut data)?;
+        }
+        if take(&mut data, 1)?[0] != 0 {
+            let mut words = [0u64; 9];
+            for word in words.iter_mut() {
+                *word = take_u64(&mut data)?;
+            }
+            snap.privileged = Some(Privileged::from_words(words).ok_or_else(|| {
+                Error::Unexpected(String::from("Sealed snapshot has an invalid mode"))
+            })?);
+        }
+        if take(&mut data, 1)?[0] != 0 {
+            let mut registers = FloatRegisters::default();
+            for i in 0..32 {
+                registers.set_register(i, take_u64(&mut data)?);
+            }
+            registers.write_csr(CSR_FCSR, take_u64(&mut data)?);
+            snap.float_registers = Some(registers);
+        }
+        if take(&mut data, 1)?[0] != 0 {
+            let mut registers = VectorRegisters::default();
+            let vtype = take_u64(&mut data)?;
+            let vl = take_u64(&mut data)?;
+            registers.restore_config(vtype, vl);
+            registers.write_csr(CSR_VSTART, take_u64(&mut data)?);
+            registers.write_csr(CSR_VCSR, take_u64(&mut data)?);
+            for i in 0..32 {
+                let length = registers.register(i).len() as u64;
+                registers
+                    .register_mut(i)
+                    .copy_from_slice(take(&mut data, length)?);
+            }
+            snap.vector_registers = Some(registers);
+        }
+        let count = take_u64(&mut data)?;
+        for _ in 0..count {
+            snap.page_indices.push(take_u64(&mut data)?);
+            snap.page_flags.push(take(&mut data, 1)?[0]);
+            let length = take_u64(&mut data)?;
+            snap.pages.push(take(&mut data, length)?.to_vec());
+        }
+        if !data.is_empty() {
+            return Err(Error::Unexpected(String::from(
+                "Sealed snapshot has trailing data",
+            )));
+        }
+        Ok(snap)
+    }
+}
+
+impl SealedSnapshot {
+    // Decrypts the snapshot, checking it wasn't tampered with.
+    pub fn open(&self, key: &SnapshotKey) -> Result<Snapshot, Error> {
+        let data = aead::open(key, &self.nonce, &sealed_aad(self.version), &self.payload)
+            .ok_or_else(|| {
+                Error::Unexpected(String::from("Sealed snapshot failed authentication"))
+            })?;
+        Snapshot::decode(self.version, &data)
+    }
+}
+
+fn sealed_aad(version: u32) -> Vec<u8> {
+    let mut aad = SEALED_SNAPSHOT_TAG.to_vec();
+    aad.extend_from_slice(&version.to_le_bytes());
+    aad
+}
@@ -0,0 +1,100 @@
+// Proptest strategies generating valid RISC-V code for property tests and
+// fuzzing, enabled by the proptest feature.
+//
+// proptest! {
+//     #[test]
+//     fn programs_exit(program in program::<u64>(ISA_IMC | ISA_B, InstructionOptions::default(), 1..64)) {
+//         ...
+//     }
+// }
+//
+// Instructions are generated in their internal form and encoded with
+// ckb_vm_definitions::encoding::to_riscv, hence every generated instruction
+// is accepted by the decoder of a machine with the same ISA. Raw encodings do
+// not depend on the VM version, so the generated code can be run with any
+// version.
+pub use crate::generator::InstructionOptions;
+
+use crate::elf_writer::minimal_elf;
+use crate::generator::{encode, opcodes, Format};
+use crate::instructions::{insts, InstructionOpcode, Register};
+use crate::Bytes;
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_j, pack_r, pack_s, pack_u};
+use proptest::collection::{vec, SizeRange};
+use proptest::prelude::*;
+use proptest::sample::select;
+
+fn operands(op: InstructionOpcode, format: Format) -> BoxedStrategy<u32> {
+    let reg = 0u8..32;
+    match format {
+        Format::R => (reg.clone(), reg.clone(), reg)
+            .prop_map(move |(rd, rs1, rs2)| encode(pack_r(op, rd, rs1, rs2)))
+            .boxed(),
+        Format::I => (reg.clone(), reg, -2048i32..2048)
+            .prop_map(move |(rd, rs1, imm)| encode(pack_i(op, rd, rs1, imm)))
+            .boxed(),
+        Format::Shift(bits) => (reg.clone(), reg, 0i32..(1 << bits))
+            .prop_map(move |(rd, rs1, shamt)| encode(pack_i(op, rd, rs1, shamt)))
+            .boxed(),
+        Format::Unary(rs2) => (reg.clone(), reg)
+            .prop_map(move |(rd, rs1)| encode(pack_r(op, rd, rs1, rs2)))
+            .boxed(),
+        Format::S => (reg.clone(), reg, -2048i32..2048)
+            .prop_map(move |(rs1, rs2, imm)| encode(pack_s(op, rs1, rs2, imm)))
+            .boxed(),
+        Format::B => (reg.clone(), reg, -2048i32..2048)
+            .prop_map(move |(rs1, rs2, imm)| encode(pack_b(op, rs1, rs2, imm * 2)))
+            .boxed(),
+        Format::U => (reg, any::<i32>())
+            .prop_map(move |(rd, imm)| encode(pack_u(op, rd, imm & !0xfff)))
+            .boxed(),
+        Format::J => (reg, -(1i32 << 19)..(1 << 19))
+            .prop_map(move |(rd, imm)| encode(pack_j(op, rd, imm * 2)))
+            .boxed(),
+        Format::Fence => (0u8..16, 0u8..16)
+            .prop_map(move |(pred, succ)| encode(pack_r(op, 0, pred, succ)))
+            .boxed(),
+        Format::Blank => Just(encode(pack_r(op, 0, 0, 0))).boxed(),
+    }
+}
+
+// Generates a single 32-bit instruction supported by machines with the
+// given ISA and register type.
+pub fn instruction<R: Register>(isa: u64, options: InstructionOptions) -> BoxedStrategy<u32> {
+    select(opcodes::<R>(isa, options))
+        .prop_flat_map(|(op, format)| operands(op, format))
+        .boxed()
+}
+
+pub fn instructions<R: Register>(
+    isa: u64,
+    options: InstructionOptions,
+    len: impl Into<SizeRange>,
+) -> BoxedStrategy<Vec<u32>> {
+    vec(instruction::<R>(isa, options), len).boxed()
+}
+
+// Generates an ELF running the generated instructions, then exiting with
+// code 0. With control flow or system instructions enabled, the program might
+// never reach the exit, so run it with a cycle limit.
+pub fn program<R: Register>(
+    isa: u64,
+    options: InstructionOptions,
+    len: impl Into<SizeRange>,
+) -> BoxedStrategy<Bytes> {
+    instructions::<R>(isa, options, len)
+        .prop_map(|instructions| {
+            let exit = [
+                pack_i(insts::OP_ADDI, 10, 0, 0),
+                pack_i(insts::OP_ADDI, 17, 0, 93),
+                pack_r(insts::OP_ECALL, 0, 0, 0),
+            ];
+            let code: Vec<u8> = instructions
+                .into_iter()
+                .chain(exit.iter().map(|i| encode(*i)))
+                .flat_map(|i| i.to_le_bytes())
+                .collect();
+            minimal_elf::<R>(&code)
+        })
+        .boxed()
+}
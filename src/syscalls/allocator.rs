@@ -0,0 +1,269 @@
+use super::super::{Error, Memory, Register};
+use super::Syscalls;
+use crate::machine::SupportMachine;
+use crate::registers::{A0, A1, A7};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+pub const MALLOC_SYSCALL_NUMBER: u64 = 1101;
+pub const FREE_SYSCALL_NUMBER: u64 = 1102;
+pub const REALLOC_SYSCALL_NUMBER: u64 = 1103;
+
+// Alignment of allocated blocks, enough for any RISC-V scalar type.
+pub const ALLOCATION_ALIGNMENT: u64 = 16;
+
+// Usage counters of a GuestAllocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    // Bytes currently allocated, rounded up to the alignment.
+    pub allocated: u64,
+    // Highest value of allocated.
+    pub peak: u64,
+    // Successful allocations, reallocations moving a block included.
+    pub allocations: u64,
+    pub frees: u64,
+    // Allocations which failed for lack of space.
+    pub failures: u64,
+}
+
+// First-fit allocator over an arena of guest memory. The state lives on the
+// host, blocks carry no headers in guest memory, so guest bugs can't corrupt
+// it and the host sees every live allocation.
+#[derive(Debug, Clone)]
+pub struct GuestAllocator {
+    start: u64,
+    end: u64,
+    // Free blocks by address, adjacent ones are merged.
+    free: BTreeMap<u64, u64>,
+    // Live blocks by address, with their rounded size.
+    live: BTreeMap<u64, u64>,
+    stats: AllocatorStats,
+}
+
+fn align_up(value: u64) -> Option<u64> {
+    value
+        .checked_add(ALLOCATION_ALIGNMENT - 1)
+        .map(|v| v & !(ALLOCATION_ALIGNMENT - 1))
+}
+
+impl GuestAllocator {
+    // Manages the `size` bytes at `start`, shrunk to aligned bounds.
+    pub fn new(start: u64, size: u64) -> Self {
+        let end = start.saturating_add(size) & !(ALLOCATION_ALIGNMENT - 1);
+        let start = align_up(start).unwrap_or(end).min(end);
+        let mut free = BTreeMap::new();
+        if end > start {
+            free.insert(start, end - start);
+        }
+        Self {
+            start,
+            end,
+            free,
+            live: BTreeMap::new(),
+            stats: AllocatorStats::default(),
+        }
+    }
+
+    // Bounds of the arena.
+    pub fn arena(&self) -> (u64, u64) {
+        (self.start, self.end)
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.stats
+    }
+
+    // Live allocations as (address, size) pairs, in address order.
+    pub fn allocations(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.live.iter().map(|(addr, size)| (*addr, *size))
+    }
+
+    // Returns the address of a block of at least `size` bytes, None when
+    // the arena has no room. Zero sized requests get a minimal block.
+    pub fn allocate(&mut self, size: u64) -> Option<u64> {
+        let size = match align_up(size.max(1)) {
+            Some(size) => size,
+            None => {
+                self.stats.failures += 1;
+                return None;
+            }
+        };
+        let found = self
+            .free
+            .iter()
+            .find(|(_, free_size)| **free_size >= size)
+            .map(|(addr, free_size)| (*addr, *free_size));
+        let (addr, free_size) = match found {
+            Some(block) => block,
+            None => {
+                self.stats.failures += 1;
+                return None;
+            }
+        };
+        self.free.remove(&addr);
+        if free_size > size {
+            self.free.insert(addr + size, free_size - size);
+        }
+        self.live.insert(addr, size);
+        self.stats.allocated += size;
+        self.stats.peak = self.stats.peak.max(self.stats.allocated);
+        self.stats.allocations += 1;
+        Some(addr)
+    }
+
+    // Releases a block, returning its size, or None if `addr` isn't the
+    // address of a live block.
+    pub fn free(&mut self, addr: u64) -> Option<u64> {
+        let size = self.live.remove(&addr)?;
+        self.stats.allocated -= size;
+        self.stats.frees += 1;
+        let (mut start, mut end) = (addr, addr + size);
+        let previous = self.free.range(..addr).next_back().map(|(a, s)| (*a, *s));
+        if let Some((prev_addr, prev_size)) = previous {
+            if prev_addr + prev_size == start {
+                self.free.remove(&prev_addr);
+                start = prev_addr;
+            }
+        }
+        if let Some(next_size) = self.free.remove(&end) {
+            end += next_size;
+        }
+        self.free.insert(start, end - start);
+        Some(size)
+    }
+
+    // Resizes a live block in place when it can, returns false otherwise.
+    fn resize_in_place(&mut self, addr: u64, size: u64) -> bool {
+        let size = match align_up(size.max(1)) {
+            Some(size) => size,
+            None => return false,
+        };
+        let current = self.live[&addr];
+        if size <= current {
+            if size < current {
+                self.live.insert(addr, size);
+                self.stats.allocated -= current - size;
+                // Returns the tail, merged with the following free block.
+                let mut tail = current - size;
+                if let Some(next_size) = self.free.remove(&(addr + current)) {
+                    tail += next_size;
+                }
+                self.free.insert(addr + size, tail);
+            }
+            return true;
+        }
+        let needed = size - current;
+        match self.free.get(&(addr + current)).copied() {
+            Some(next_size) if next_size >= needed => {
+                self.free.remove(&(addr + current));
+                if next_size > needed {
+                    self.free.insert(addr + size, next_size - needed);
+                }
+                self.live.insert(addr, size);
+                self.stats.allocated += needed;
+                self.stats.peak = self.stats.peak.max(self.stats.allocated);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// AllocatorSyscalls implements malloc, free and realloc on the host, over a
+// dedicated arena of guest memory, so small programs can skip linking an
+// allocator. Arguments and results follow the C functions:
+//
+// * malloc(a0 = size) returns the block address in a0, or 0;
+// * free(a0 = address) ignores 0;
+// * realloc(a0 = address, a1 = size) returns the new address in a0, or 0
+//   leaving the block untouched, moving the block copies its content.
+//
+// Freeing an address which isn't a live block stops the machine with an
+// error. The arena would usually be the heap, see
+// AddressSpaceLayout::heap_start:
+//
+// let syscalls = AllocatorSyscalls::new(heap_start, heap_size);
+// let allocator = syscalls.allocator();
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .syscall(Box::new(syscalls))
+//     .build();
+// machine.load_program(&program, &args)?;
+// machine.run()?;
+// let peak = allocator.lock().unwrap().stats().peak;
+//
+// The allocator is shared with the host, which can inspect live
+// allocations while the machine is paused or after it stopped.
+pub struct AllocatorSyscalls {
+    allocator: Arc<Mutex<GuestAllocator>>,
+}
+
+impl AllocatorSyscalls {
+    pub fn new(start: u64, size: u64) -> Self {
+        Self {
+            allocator: Arc::new(Mutex::new(GuestAllocator::new(start, size))),
+        }
+    }
+
+    pub fn allocator(&self) -> Arc<Mutex<GuestAllocator>> {
+        Arc::clone(&self.allocator)
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for AllocatorSyscalls {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let number = machine.registers()[A7].to_u64();
+        if number != MALLOC_SYSCALL_NUMBER
+            && number != FREE_SYSCALL_NUMBER
+            && number != REALLOC_SYSCALL_NUMBER
+        {
+            return Ok(false);
+        }
+        let mut allocator = self
+            .allocator
+            .lock()
+            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        let addr = machine.registers()[A0].to_u64();
+        let result = match number {
+            MALLOC_SYSCALL_NUMBER => allocator.allocate(addr).unwrap_or(0),
+            FREE_SYSCALL_NUMBER => {
+                if addr != 0 && allocator.free(addr).is_none() {
+                    return Err(Error::Unexpected(format!("Invalid free of 0x{:x}", addr)));
+                }
+                0
+            }
+            _ => {
+                let size = machine.registers()[A1].to_u64();
+                if addr == 0 {
+                    allocator.allocate(size).unwrap_or(0)
+                } else if !allocator.live.contains_key(&addr) {
+                    return Err(Error::Unexpected(format!(
+                        "Invalid realloc of 0x{:x}",
+                        addr
+                    )));
+                } else if size == 0 {
+                    allocator.free(addr);
+                    0
+                } else if allocator.resize_in_place(addr, size) {
+                    addr
+                } else {
+                    let old_size = allocator.live[&addr];
+                    match allocator.allocate(size) {
+                        Some(new_addr) => {
+                            let data = machine.memory_mut().load_bytes(addr, old_size)?;
+                            machine.memory_mut().store_bytes(new_addr, &data)?;
+                            allocator.free(addr);
+                            new_addr
+                        }
+                        None => 0,
+                    }
+                }
+            }
+        };
+        machine.set_register(A0, Mac::REG::from_u64(result));
+        Ok(true)
+    }
+}
@@ -1,10 +1,112 @@
 use super::Error;
 use crate::machine::SupportMachine;
+use crate::registers::A7;
+use crate::Register;
+
+pub mod allocator;
+pub mod plugin;
+pub mod random;
 
 pub trait Syscalls<Mac: SupportMachine>: Send + Sync {
     fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error>;
     // Returned bool means if the syscall has been processed, if
     // a module returns false, Machine would continue to leverage
     // the next syscall module to process.
+    //
+    // A syscall which can't complete yet, for example because its data is not
+    // available, returns Error::SyscallRetry before changing any state. The
+    // run stops with that error, with PC back on the ECALL and its cycles
+    // refunded, so running the machine again after the host remedied the
+    // situation executes the syscall once more.
     fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error>;
+    // Name used when inspecting the dispatch order, defaults to the type name.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+// Priority of syscall modules registered without an explicit one.
+pub const DEFAULT_SYSCALL_PRIORITY: i32 = 0;
+
+// Describes a registered syscall module, see DefaultMachine::dispatch_order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallsInfo {
+    pub name: &'static str,
+    pub priority: i32,
+}
+
+// Inserts a syscall module into `syscalls`, which is sorted by priority in
+// descending order. Modules with the same priority are dispatched in the
+// order of registration.
+pub(crate) fn insert_syscalls<T>(syscalls: &mut Vec<(i32, T)>, priority: i32, syscall: T) {
+    let position = syscalls
+        .iter()
+        .position(|(p, _)| *p < priority)
+        .unwrap_or(syscalls.len());
+    syscalls.insert(position, (priority, syscall));
+}
+
+// Decision made by a TrapHandler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrapAction {
+    // Resume execution at the next instruction.
+    Continue,
+    // Stop execution with the error.
+    Trap(Error),
+    // Stop execution normally with the exit code, the same as the exit syscall.
+    Terminate(i8),
+}
+
+// Decision made by a TrapHandler for an instruction no decoder factory
+// recognizes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidInstructionAction {
+    // Stop execution with the error.
+    Trap(Error),
+    // Resume execution `length` bytes after the instruction, once the handler
+    // emulated it, or to ignore it.
+    Skip(u8),
+    // Resume execution at the address, e.g. for an emulated jump.
+    Jump(u64),
+    // Raise an illegal instruction exception in the guest, see Privileged.
+    // Without a trap vector installed, execution stops with
+    // InvalidInstruction.
+    GuestTrap,
+}
+
+// TrapHandler is consulted for EBREAK when no debugger is installed, and for
+// ECALL when no registered syscall module claims the syscall. The default
+// implementation keeps the original behavior: EBREAK is a no-op, while an
+// unclaimed ECALL is an InvalidEcall error. It also receives interrupts
+// raised by memory mapped devices, see MmioMemory, which are ignored by
+// default, and instructions which fail to decode.
+pub trait TrapHandler<Mac: SupportMachine>: Send + Sync {
+    fn ebreak(&mut self, _machine: &mut Mac) -> TrapAction {
+        TrapAction::Continue
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> TrapAction {
+        TrapAction::Trap(Error::InvalidEcall(machine.registers()[A7].to_u64()))
+    }
+
+    // Called between basic blocks while interrupt `irq` is pending, see
+    // devices::highest_priority_interrupt. Interrupts are level triggered,
+    // the handler keeps being called until the guest or the handler clears
+    // the interrupt in its device.
+    fn interrupt(&mut self, _machine: &mut Mac, _irq: u64) -> TrapAction {
+        TrapAction::Continue
+    }
+
+    // Called by the run loops for the instruction at `pc` which fails to
+    // decode, with its encoding, the lowest 32 bits of long ones. The
+    // instruction costs no cycles, a handler emulating it may charge them
+    // with add_cycles.
+    fn invalid_instruction(
+        &mut self,
+        _machine: &mut Mac,
+        pc: u64,
+        instruction: u32,
+    ) -> InvalidInstructionAction {
+        InvalidInstructionAction::Trap(Error::InvalidInstruction { pc, instruction })
+    }
 }
@@ -0,0 +1,417 @@
+// Stable C ABI for syscall handlers compiled separately from the VM and
+// loaded at runtime, so node distributions and third parties can ship host
+// functions without recompiling the VM or the node. A plugin is a shared
+// library exporting CKB_VM_PLUGIN_ENTRY, which returns its descriptor:
+//
+// static const CkbVmPluginDescriptor descriptor = {
+//     CKB_VM_PLUGIN_ABI_VERSION, "hello", hello_create, hello_destroy, NULL, hello_ecall,
+// };
+// const CkbVmPluginDescriptor *ckb_vm_plugin_v1(void) { return &descriptor; }
+//
+// Hosts load the library once, each machine then gets its own instance of
+// the plugin:
+//
+// let library = PluginLibrary::open("libhello.so")?;
+// let machine = DefaultMachineBuilder::new(core)
+//     .syscall_plugin(&library, b"config")?
+//     .build();
+//
+// Plugins never see Rust types, they access the machine through the
+// CkbVmPluginHost table passed to every call, see include/ckb_vm_plugin.h.
+// The ABI version is bumped on any incompatible change, libraries built for
+// another version are rejected when loaded.
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::slice;
+use std::sync::Arc;
+
+use super::{Error, Syscalls};
+use crate::machine::{DefaultMachineBuilder, SupportMachine};
+use crate::{Memory, Register, RISCV_GENERAL_REGISTER_NUMBER};
+
+pub const CKB_VM_PLUGIN_ABI_VERSION: u32 = 1;
+// Symbol exported by plugin libraries.
+pub const CKB_VM_PLUGIN_ENTRY: &str = "ckb_vm_plugin_v1";
+
+// Return values of host functions, the same as the C ABI of the capi
+// module.
+pub const CKB_VM_PLUGIN_OK: c_int = 0;
+pub const CKB_VM_PLUGIN_ERROR_INVALID_ARGUMENT: c_int = -1;
+pub const CKB_VM_PLUGIN_ERROR_CYCLES_EXCEEDED: c_int = -2;
+pub const CKB_VM_PLUGIN_ERROR_MEMORY: c_int = -4;
+
+// Functions given to plugins to access the machine running the syscall.
+// `machine` is only valid during the call it is passed to.
+#[repr(C)]
+pub struct CkbVmPluginHost {
+    pub abi_version: u32,
+    pub reg: unsafe extern "C" fn(machine: *mut c_void, index: usize) -> u64,
+    pub set_register: unsafe extern "C" fn(machine: *mut c_void, index: usize, value: u64) -> c_int,
+    pub pc: unsafe extern "C" fn(machine: *mut c_void) -> u64,
+    pub cycles: unsafe extern "C" fn(machine: *mut c_void) -> u64,
+    pub max_cycles: unsafe extern "C" fn(machine: *mut c_void) -> u64,
+    pub add_cycles: unsafe extern "C" fn(machine: *mut c_void, cycles: u64) -> c_int,
+    pub load_bytes: unsafe extern "C" fn(
+        machine: *mut c_void,
+        addr: u64,
+        buffer: *mut u8,
+        length: usize,
+    ) -> c_int,
+    pub store_bytes: unsafe extern "C" fn(
+        machine: *mut c_void,
+        addr: u64,
+        buffer: *const u8,
+        length: usize,
+    ) -> c_int,
+}
+
+// Describes a plugin, returned by CKB_VM_PLUGIN_ENTRY. The descriptor and
+// the name must stay valid as long as the library is loaded.
+#[repr(C)]
+pub struct CkbVmPluginDescriptor {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    // Creates an instance for a machine from the configuration bytes given
+    // by the host. Returns NULL on failure.
+    pub create: unsafe extern "C" fn(config: *const u8, config_length: usize) -> *mut c_void,
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+    // Called when the program loaded, may be NULL.
+    pub initialize: Option<
+        unsafe extern "C" fn(
+            instance: *mut c_void,
+            host: *const CkbVmPluginHost,
+            machine: *mut c_void,
+        ) -> c_int,
+    >,
+    // Returns 1 if the syscall has been processed, 0 if the next syscall
+    // module should be tried, and any negative value to abort execution.
+    pub ecall: unsafe extern "C" fn(
+        instance: *mut c_void,
+        host: *const CkbVmPluginHost,
+        machine: *mut c_void,
+    ) -> c_int,
+}
+
+pub type CkbVmPluginEntry = unsafe extern "C" fn() -> *const CkbVmPluginDescriptor;
+
+// A plugin library, which stays loaded until the library and all instances
+// created from it are dropped.
+pub struct PluginLibrary {
+    handle: *mut c_void,
+    descriptor: &'static CkbVmPluginDescriptor,
+    name: String,
+}
+
+// Plugins must accept calls from any thread, which the ABI requires.
+unsafe impl Send for PluginLibrary {}
+unsafe impl Sync for PluginLibrary {}
+
+impl PluginLibrary {
+    // Loads the plugin library at `path` with dlopen.
+    #[cfg(unix)]
+    pub fn open(path: &str) -> Result<Arc<Self>, Error> {
+        let path_c = std::ffi::CString::new(path)
+            .map_err(|_| Error::Unexpected(format!("Invalid plugin path {}", path)))?;
+        let entry_c = std::ffi::CString::new(CKB_VM_PLUGIN_ENTRY).expect("no NUL in entry");
+        unsafe {
+            let handle = libc::dlopen(path_c.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(Error::Unexpected(format!(
+                    "Failed to load plugin {}: {}",
+                    path,
+                    dl_error()
+                )));
+            }
+            let symbol = libc::dlsym(handle, entry_c.as_ptr());
+            if symbol.is_null() {
+                close(handle);
+                return Err(Error::Unexpected(format!(
+                    "Plugin {} doesn't export {}",
+                    path, CKB_VM_PLUGIN_ENTRY
+                )));
+            }
+            let entry = std::mem::transmute::<*mut c_void, CkbVmPluginEntry>(symbol);
+            match Self::from_descriptor(handle, entry()) {
+                Ok(library) => Ok(Arc::new(library)),
+                Err(e) => {
+                    close(handle);
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Wraps a descriptor linked into the host itself, e.g. for plugins also
+    /// built as static libraries.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor is trusted the same way as one returned by the entry
+    /// point of a loaded library, so the caller must guarantee that:
+    ///
+    /// * `name` is either NULL or a NUL-terminated string living as long as
+    ///   the descriptor;
+    /// * `create`, `destroy`, `initialize` and `ecall` are implemented with
+    ///   the C calling convention and the signatures of
+    ///   [`CKB_VM_PLUGIN_ABI_VERSION`] as declared in
+    ///   include/ckb_vm_plugin.h, and can be called from any thread;
+    /// * `destroy` accepts every instance returned by `create`, and
+    ///   `initialize`/`ecall` only access the machine through the host table
+    ///   and only during the call.
+    ///
+    /// The ABI version field is checked, a mismatch is returned as an error.
+    pub unsafe fn from_static(
+        descriptor: &'static CkbVmPluginDescriptor,
+    ) -> Result<Arc<Self>, Error> {
+        Self::from_descriptor(std::ptr::null_mut(), descriptor).map(Arc::new)
+    }
+
+    unsafe fn from_descriptor(
+        handle: *mut c_void,
+        descriptor: *const CkbVmPluginDescriptor,
+    ) -> Result<Self, Error> {
+        let descriptor = descriptor
+            .as_ref()
+            .ok_or_else(|| Error::Unexpected(String::from("Plugin returns no descriptor")))?;
+        if descriptor.abi_version != CKB_VM_PLUGIN_ABI_VERSION {
+            return Err(Error::Unexpected(format!(
+                "Plugin ABI version {} is not supported, expecting {}",
+                descriptor.abi_version, CKB_VM_PLUGIN_ABI_VERSION
+            )));
+        }
+        let name = if descriptor.name.is_null() {
+            String::from("unnamed")
+        } else {
+            CStr::from_ptr(descriptor.name)
+                .to_string_lossy()
+                .into_owned()
+        };
+        Ok(Self {
+            handle,
+            descriptor,
+            name,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Creates an instance of the plugin, a syscall module to register in a
+    // machine.
+    pub fn instantiate(self: &Arc<Self>, config: &[u8]) -> Result<PluginSyscalls, Error> {
+        let instance = unsafe { (self.descriptor.create)(config.as_ptr(), config.len()) };
+        if instance.is_null() {
+            return Err(Error::Unexpected(format!(
+                "Plugin {} failed to create an instance",
+                self.name
+            )));
+        }
+        Ok(PluginSyscalls {
+            library: Arc::clone(self),
+            instance,
+        })
+    }
+}
+
+impl Drop for PluginLibrary {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { close(self.handle) }
+        }
+    }
+}
+
+#[cfg(unix)]
+unsafe fn close(handle: *mut c_void) {
+    libc::dlclose(handle);
+}
+
+// Libraries are only opened on unix.
+#[cfg(not(unix))]
+unsafe fn close(_handle: *mut c_void) {}
+
+#[cfg(unix)]
+unsafe fn dl_error() -> String {
+    let error = libc::dlerror();
+    if error.is_null() {
+        String::from("unknown error")
+    } else {
+        CStr::from_ptr(error).to_string_lossy().into_owned()
+    }
+}
+
+// Syscall module backed by an instance of a plugin.
+pub struct PluginSyscalls {
+    library: Arc<PluginLibrary>,
+    instance: *mut c_void,
+}
+
+unsafe impl Send for PluginSyscalls {}
+unsafe impl Sync for PluginSyscalls {}
+
+impl PluginSyscalls {
+    pub fn library(&self) -> &Arc<PluginLibrary> {
+        &self.library
+    }
+}
+
+impl Drop for PluginSyscalls {
+    fn drop(&mut self) {
+        unsafe { (self.library.descriptor.destroy)(self.instance) }
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for PluginSyscalls {
+    fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        let initialize = match self.library.descriptor.initialize {
+            Some(initialize) => initialize,
+            None => return Ok(()),
+        };
+        let host = host::<Mac>();
+        let r = unsafe { initialize(self.instance, &host, machine as *mut Mac as *mut c_void) };
+        if r < 0 {
+            return Err(Error::External(format!(
+                "plugin {} initialize returns {}",
+                self.library.name, r
+            )));
+        }
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let host = host::<Mac>();
+        let r = unsafe {
+            (self.library.descriptor.ecall)(
+                self.instance,
+                &host,
+                machine as *mut Mac as *mut c_void,
+            )
+        };
+        if r < 0 {
+            return Err(Error::External(format!(
+                "plugin {} ecall returns {}",
+                self.library.name, r
+            )));
+        }
+        Ok(r > 0)
+    }
+
+    fn name(&self) -> &'static str {
+        "PluginSyscalls"
+    }
+}
+
+impl<Inner: SupportMachine> DefaultMachineBuilder<Inner> {
+    // Registers a new instance of the plugin created from `config`.
+    pub fn syscall_plugin(
+        self,
+        library: &Arc<PluginLibrary>,
+        config: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(self.syscall(Box::new(library.instantiate(config)?)))
+    }
+}
+
+fn host<Mac: SupportMachine>() -> CkbVmPluginHost {
+    CkbVmPluginHost {
+        abi_version: CKB_VM_PLUGIN_ABI_VERSION,
+        reg: host_register::<Mac>,
+        set_register: host_set_register::<Mac>,
+        pc: host_pc::<Mac>,
+        cycles: host_cycles::<Mac>,
+        max_cycles: host_max_cycles::<Mac>,
+        add_cycles: host_add_cycles::<Mac>,
+        load_bytes: host_load_bytes::<Mac>,
+        store_bytes: host_store_bytes::<Mac>,
+    }
+}
+
+// The machine pointers come from PluginSyscalls, which only passes valid
+// machines for the duration of a call.
+unsafe fn machine<'a, Mac>(machine: *mut c_void) -> &'a mut Mac {
+    &mut *(machine as *mut Mac)
+}
+
+unsafe extern "C" fn host_register<Mac: SupportMachine>(m: *mut c_void, index: usize) -> u64 {
+    machine::<Mac>(m)
+        .registers()
+        .get(index)
+        .map_or(0, |r| r.to_u64())
+}
+
+unsafe extern "C" fn host_set_register<Mac: SupportMachine>(
+    m: *mut c_void,
+    index: usize,
+    value: u64,
+) -> c_int {
+    if index >= RISCV_GENERAL_REGISTER_NUMBER {
+        return CKB_VM_PLUGIN_ERROR_INVALID_ARGUMENT;
+    }
+    machine::<Mac>(m).set_register(index, Mac::REG::from_u64(value));
+    CKB_VM_PLUGIN_OK
+}
+
+unsafe extern "C" fn host_pc<Mac: SupportMachine>(m: *mut c_void) -> u64 {
+    machine::<Mac>(m).pc().to_u64()
+}
+
+unsafe extern "C" fn host_cycles<Mac: SupportMachine>(m: *mut c_void) -> u64 {
+    machine::<Mac>(m).cycles()
+}
+
+unsafe extern "C" fn host_max_cycles<Mac: SupportMachine>(m: *mut c_void) -> u64 {
+    machine::<Mac>(m).max_cycles()
+}
+
+unsafe extern "C" fn host_add_cycles<Mac: SupportMachine>(m: *mut c_void, cycles: u64) -> c_int {
+    match machine::<Mac>(m).add_cycles(cycles) {
+        Ok(()) => CKB_VM_PLUGIN_OK,
+        Err(_) => CKB_VM_PLUGIN_ERROR_CYCLES_EXCEEDED,
+    }
+}
+
+unsafe extern "C" fn host_load_bytes<Mac: SupportMachine>(
+    m: *mut c_void,
+    addr: u64,
+    buffer: *mut u8,
+    length: usize,
+) -> c_int {
+    if length == 0 {
+        return CKB_VM_PLUGIN_OK;
+    }
+    if buffer.is_null() {
+        return CKB_VM_PLUGIN_ERROR_INVALID_ARGUMENT;
+    }
+    match machine::<Mac>(m)
+        .memory_mut()
+        .load_bytes(addr, length as u64)
+    {
+        Ok(data) => {
+            slice::from_raw_parts_mut(buffer, length).copy_from_slice(&data);
+            CKB_VM_PLUGIN_OK
+        }
+        Err(_) => CKB_VM_PLUGIN_ERROR_MEMORY,
+    }
+}
+
+unsafe extern "C" fn host_store_bytes<Mac: SupportMachine>(
+    m: *mut c_void,
+    addr: u64,
+    buffer: *const u8,
+    length: usize,
+) -> c_int {
+    if length == 0 {
+        return CKB_VM_PLUGIN_OK;
+    }
+    if buffer.is_null() {
+        return CKB_VM_PLUGIN_ERROR_INVALID_ARGUMENT;
+    }
+    match machine::<Mac>(m)
+        .memory_mut()
+        .store_bytes(addr, slice::from_raw_parts(buffer, length))
+    {
+        Ok(()) => CKB_VM_PLUGIN_OK,
+        Err(_) => CKB_VM_PLUGIN_ERROR_MEMORY,
+    }
+}
@@ -0,0 +1,113 @@
+use super::super::{Error, Memory, Register};
+use super::Syscalls;
+use crate::chacha::{chacha20_block, KEY_LENGTH, NONCE_LENGTH};
+use crate::machine::SupportMachine;
+use crate::registers::{A0, A1, A7};
+use std::sync::{Arc, Mutex};
+
+pub const RANDOM_SYSCALL_NUMBER: u64 = 1104;
+
+pub const RANDOM_SEED_LENGTH: usize = KEY_LENGTH;
+
+// Deterministic random generator producing the ChaCha20 key stream of the
+// seed, so the same seed gives the same bytes on every platform. Block n of
+// the stream uses the low 32 bits of n as block counter and the high 32 bits
+// as nonce.
+#[derive(Debug, Clone)]
+pub struct ChaChaRng {
+    seed: [u8; RANDOM_SEED_LENGTH],
+    // Bytes generated so far.
+    position: u64,
+}
+
+impl ChaChaRng {
+    pub fn new(seed: [u8; RANDOM_SEED_LENGTH]) -> Self {
+        Self { seed, position: 0 }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Moves to an offset of the stream, e.g. to resume a suspended machine.
+    pub fn seek(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut filled = 0;
+        while filled < dest.len() {
+            let block_index = self.position / 64;
+            let offset = (self.position % 64) as usize;
+            let mut nonce = [0u8; NONCE_LENGTH];
+            nonce[..4].copy_from_slice(&((block_index >> 32) as u32).to_le_bytes());
+            let block = chacha20_block(&self.seed, block_index as u32, &nonce);
+            let length = (64 - offset).min(dest.len() - filled);
+            dest[filled..filled + length].copy_from_slice(&block[offset..offset + length]);
+            filled += length;
+            self.position = self.position.wrapping_add(length as u64);
+        }
+    }
+}
+
+// RandomSyscalls fills guest buffers with bytes of a ChaChaRng seeded by the
+// machine creator, typically from a hash of the transaction, so scripts get
+// randomness which every node reproduces:
+//
+// * random(a0 = buffer, a1 = length) writes length bytes at buffer and
+//   returns 0 in a0.
+//
+// let syscalls = RandomSyscalls::new(blake2b(&transaction_hash));
+// let rng = syscalls.rng();
+// let mut machine = DefaultMachineBuilder::new(core)
+//     .syscall(Box::new(syscalls))
+//     .build();
+//
+// The generator is shared with the host, whose position() can be saved with
+// a snapshot of the machine and restored with seek().
+pub struct RandomSyscalls {
+    rng: Arc<Mutex<ChaChaRng>>,
+}
+
+impl RandomSyscalls {
+    pub fn new(seed: [u8; RANDOM_SEED_LENGTH]) -> Self {
+        Self {
+            rng: Arc::new(Mutex::new(ChaChaRng::new(seed))),
+        }
+    }
+
+    pub fn rng(&self) -> Arc<Mutex<ChaChaRng>> {
+        Arc::clone(&self.rng)
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for RandomSyscalls {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != RANDOM_SYSCALL_NUMBER {
+            return Ok(false);
+        }
+        let addr = machine.registers()[A0].to_u64();
+        let length = machine.registers()[A1].to_u64();
+        if length > machine.memory().memory_size() as u64 {
+            return Err(Error::MemOutOfBound);
+        }
+        let mut rng = self
+            .rng
+            .lock()
+            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        let mut data = vec![0; length as usize];
+        let position = rng.position();
+        rng.fill_bytes(&mut data);
+        // Failed calls don't consume the stream.
+        if let Err(e) = machine.memory_mut().store_bytes(addr, &data) {
+            rng.seek(position);
+            return Err(e);
+        }
+        machine.set_register(A0, Mac::REG::from_u64(0));
+        Ok(true)
+    }
+}
@@ -1,4 +1,14 @@
-use ckb_vm::Error;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::privileged::{CSR_MEPC, CSR_MTVEC};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::memory::reservation::StrictReservationMemory;
+use ckb_vm::registers::{A0, A1, A7, SP, T0, T1, T2};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory, ISA_A, ISA_IMC,
+    ISA_PRIV,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_s, pack_u, to_riscv};
 pub mod machine_build;
 
 #[test]
@@ -48,3 +58,104 @@ pub fn test_amo_compare() {
         assert_eq!(ret_asm.unwrap(), 0);
     }
 }
+
+fn lr_sc_program(between: Instruction) -> Bytes {
+    let (t0, t1, t2) = (T0 as u8, T1 as u8, T2 as u8);
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, SP as u8, -16),
+        pack_r(insts::OP_LR_D, t1, t0, 0),
+        between,
+        pack_r(insts::OP_SC_D, t2, t0, t1),
+        pack_i(insts::OP_ADDI, A0 as u8, t2, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+fn run_lr_sc<M: Memory<REG = u64>>(program: &Bytes) -> Result<i8, Error> {
+    let core_machine = DefaultCoreMachine::<u64, M>::new(ISA_IMC | ISA_A, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(program, &["lr_sc".into()])?;
+    machine.run()
+}
+
+#[test]
+pub fn test_strict_reservation() {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    // SC succeeds after an unrelated store in both modes.
+    let program = lr_sc_program(pack_s(insts::OP_SD, t0, 0, 8));
+    assert_eq!(run_lr_sc::<SparseMemory<u64>>(&program), Ok(0));
+    assert_eq!(
+        run_lr_sc::<StrictReservationMemory<SparseMemory<u64>>>(&program),
+        Ok(0)
+    );
+    // A store to the reserved bytes only breaks the reservation in strict mode.
+    let program = lr_sc_program(pack_s(insts::OP_SD, t0, t1, 0));
+    assert_eq!(run_lr_sc::<SparseMemory<u64>>(&program), Ok(0));
+    assert_eq!(
+        run_lr_sc::<StrictReservationMemory<SparseMemory<u64>>>(&program),
+        Ok(1)
+    );
+    // So does a trap.
+    let program = lr_sc_program(pack_r(insts::OP_EBREAK, 0, 0, 0));
+    assert_eq!(run_lr_sc::<SparseMemory<u64>>(&program), Ok(0));
+    assert_eq!(
+        run_lr_sc::<StrictReservationMemory<SparseMemory<u64>>>(&program),
+        Ok(1)
+    );
+}
+
+#[test]
+pub fn test_strict_reservation_guest_trap() {
+    let (t0, t1, t2) = (T0 as u8, T1 as u8, T2 as u8);
+    // Takes a reservation in user mode, then faults on a load. The guest trap
+    // handler tries the SC.
+    let code: Vec<u8> = [
+        pack_u(insts::OP_AUIPC, t0, 0),
+        pack_i(insts::OP_ADDI, t1, t0, 36),
+        pack_i(insts::OP_CSRRW, 0, t1, i32::from(CSR_MTVEC)),
+        pack_i(insts::OP_ADDI, t1, t0, 24),
+        pack_i(insts::OP_CSRRW, 0, t1, i32::from(CSR_MEPC)),
+        pack_r(insts::OP_MRET, 0, 0, 0),
+        // User mode, at offset 24.
+        pack_i(insts::OP_ADDI, t2, SP as u8, -16),
+        pack_r(insts::OP_LR_D, t1, t2, 0),
+        pack_i(insts::OP_LD_VERSION1, A1 as u8, 0, -8),
+        // Trap handler, at offset 36.
+        pack_r(insts::OP_SC_D, A0 as u8, t2, t1),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    fn run<M: Memory<REG = u64>>(program: &Bytes) -> Result<i8, Error> {
+        let core_machine =
+            DefaultCoreMachine::<u64, M>::new(ISA_IMC | ISA_A | ISA_PRIV, VERSION2, u64::MAX);
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .guest_memory_faults(true)
+            .build();
+        machine.load_program(program, &["lr_sc".into()])?;
+        machine.run()
+    }
+    assert_eq!(run::<SparseMemory<u64>>(&program), Ok(0));
+    assert_eq!(
+        run::<StrictReservationMemory<SparseMemory<u64>>>(&program),
+        Ok(1)
+    );
+}
+
+#[test]
+pub fn test_strict_reservation_32_bit() {
+    let mut memory = StrictReservationMemory::<SparseMemory<u32>>::new_with_memory(1 << 20);
+    assert!(!memory.reserved());
+    memory.set_lr(&0x1000);
+    assert!(memory.reserved());
+    memory.trap();
+    assert!(!memory.reserved());
+}
@@ -0,0 +1,117 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::abort::{extract_abort_message, AbortKind, AbortMessage};
+use ckb_vm::machine::report::ExitReason;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A2, A3, A7, SP};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Memory, Register, SparseMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load(exit_code: i32) -> Machine {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, 0, exit_code),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i: &Instruction| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["main".into()])
+        .unwrap();
+    machine
+}
+
+// Address below the stack, never touched by the programs.
+fn scratch(machine: &Machine) -> u64 {
+    machine.registers()[SP].to_u64() - 0x1000
+}
+
+#[test]
+pub fn test_abort_message_in_report() {
+    let panic = b"panicked at src/main.rs:3:5:\nindex out of bounds\n\0";
+    let mut machine = load(-1);
+    let address = scratch(&machine);
+    machine.memory_mut().store_bytes(address, panic).unwrap();
+    machine.set_register(A1, address);
+    let report = machine.run_with_report(false);
+    assert_eq!(report.exit_reason, ExitReason::Exit(-1));
+    assert_eq!(
+        report.abort_message,
+        Some(AbortMessage {
+            kind: AbortKind::RustPanic,
+            address,
+            message: "panicked at src/main.rs:3:5:\nindex out of bounds".to_string(),
+        })
+    );
+
+    // Nothing is extracted for programs exiting normally.
+    let mut machine = load(0);
+    let address = scratch(&machine);
+    machine.memory_mut().store_bytes(address, panic).unwrap();
+    machine.set_register(A1, address);
+    assert_eq!(machine.run_with_report(false).abort_message, None);
+}
+
+#[test]
+pub fn test_abort_message_conventions() {
+    // A C assertion message passed as a NUL-terminated string.
+    let mut machine = load(1);
+    let address = scratch(&machine);
+    let assertion = b"assertion \"len > 0\" failed: file \"main.c\", line 7\0";
+    machine
+        .memory_mut()
+        .store_bytes(address, assertion)
+        .unwrap();
+    machine.set_register(A2, address);
+    let message = extract_abort_message(&mut machine).unwrap();
+    assert_eq!(message.kind, AbortKind::Assertion);
+    assert_eq!(
+        message.message,
+        "assertion \"len > 0\" failed: file \"main.c\", line 7"
+    );
+
+    // A (pointer, length) pair, the string isn't NUL-terminated.
+    let mut machine = load(1);
+    let address = scratch(&machine);
+    machine
+        .memory_mut()
+        .store_bytes(address, b"abort() called\x01\x02")
+        .unwrap();
+    machine.set_register(A2, address);
+    machine.set_register(A3, 14);
+    let message = extract_abort_message(&mut machine).unwrap();
+    assert_eq!(message.kind, AbortKind::Abort);
+    assert_eq!(message.message, "abort() called");
+
+    // Messages formatted into a buffer on the stack. Only the arguments lie
+    // above the initial SP, so grow the stack first.
+    let mut machine = load(1);
+    let sp = machine.registers()[SP].to_u64() - 64;
+    machine.set_register(SP, sp);
+    machine
+        .memory_mut()
+        .store_bytes(sp + 24, b"\x01\x02panicked at 'boom', lib.rs:1:1\0")
+        .unwrap();
+    let message = extract_abort_message(&mut machine).unwrap();
+    assert_eq!(message.kind, AbortKind::RustPanic);
+    assert_eq!(message.address, sp + 26);
+    assert_eq!(message.message, "panicked at 'boom', lib.rs:1:1");
+
+    // Readable strings which follow no known convention are ignored.
+    let mut machine = load(1);
+    let address = scratch(&machine);
+    machine
+        .memory_mut()
+        .store_bytes(address, b"hello world\0")
+        .unwrap();
+    machine.set_register(A2, address);
+    assert_eq!(extract_abort_message(&mut machine), None);
+}
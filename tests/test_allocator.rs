@@ -0,0 +1,127 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A7, S1, S2, T0};
+use ckb_vm::syscalls::allocator::{
+    AllocatorStats, AllocatorSyscalls, GuestAllocator, FREE_SYSCALL_NUMBER, MALLOC_SYSCALL_NUMBER,
+    REALLOC_SYSCALL_NUMBER,
+};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Instruction, SparseMemory,
+    ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_s, to_riscv};
+use std::sync::{Arc, Mutex};
+
+const ARENA: u64 = 0x20_0000;
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn li(register: usize, value: u64) -> Instruction {
+    pack_i(insts::OP_ADDI, register as u8, 0, value as i32)
+}
+
+fn mv(rd: usize, rs: usize) -> Instruction {
+    pack_i(insts::OP_ADDI, rd as u8, rs as u8, 0)
+}
+
+fn ecall() -> Instruction {
+    pack_i(insts::OP_ECALL, 0, 0, 0)
+}
+
+fn machine(code: &[Instruction]) -> (Machine, Arc<Mutex<GuestAllocator>>) {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let syscalls = AllocatorSyscalls::new(ARENA, 0x1_0000);
+    let allocator = syscalls.allocator();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(syscalls))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["allocator".into()])
+        .unwrap();
+    (machine, allocator)
+}
+
+#[test]
+pub fn test_allocator_syscalls() {
+    let (mut machine, allocator) = machine(&[
+        // p = malloc(100), then a block right after p.
+        li(A0, 100),
+        li(A7, MALLOC_SYSCALL_NUMBER),
+        ecall(),
+        mv(S1, A0),
+        li(A0, 16),
+        ecall(),
+        mv(S2, A0),
+        li(T0, 42),
+        pack_s(insts::OP_SD, S1 as u8, T0 as u8, 0),
+        // p = realloc(p, 2000) can't grow in place, the content moves.
+        mv(A0, S1),
+        li(A1, 2000),
+        li(A7, REALLOC_SYSCALL_NUMBER),
+        ecall(),
+        mv(S1, A0),
+        pack_i(insts::OP_LD_VERSION1, T0 as u8, S1 as u8, 0),
+        // free(NULL) is a no-op.
+        li(A0, 0),
+        li(A7, FREE_SYSCALL_NUMBER),
+        ecall(),
+        mv(A0, T0),
+        li(A7, 93),
+        ecall(),
+    ]);
+    assert_eq!(machine.run(), Ok(42));
+    assert_eq!(machine.registers()[S1], ARENA + 128);
+    assert_eq!(machine.registers()[S2], ARENA + 112);
+
+    let allocator = allocator.lock().unwrap();
+    assert_eq!(
+        allocator.allocations().collect::<Vec<_>>(),
+        vec![(ARENA + 112, 16), (ARENA + 128, 2000)]
+    );
+    assert_eq!(
+        allocator.stats(),
+        AllocatorStats {
+            allocated: 2016,
+            peak: 2128,
+            allocations: 3,
+            frees: 1,
+            failures: 0,
+        }
+    );
+}
+
+#[test]
+pub fn test_allocator_invalid_free() {
+    let (mut machine, _) = machine(&[
+        li(A0, 0x123),
+        li(A7, FREE_SYSCALL_NUMBER),
+        ecall(),
+        li(A7, 93),
+        ecall(),
+    ]);
+    assert!(matches!(machine.run(), Err(Error::Unexpected(_))));
+}
+
+#[test]
+pub fn test_guest_allocator() {
+    let mut allocator = GuestAllocator::new(ARENA + 8, 100);
+    assert_eq!(allocator.arena(), (ARENA + 16, ARENA + 96));
+    let a = allocator.allocate(30).unwrap();
+    let b = allocator.allocate(16).unwrap();
+    let c = allocator.allocate(1).unwrap();
+    assert_eq!((a, b, c), (ARENA + 16, ARENA + 48, ARENA + 64));
+    assert_eq!(allocator.allocate(32), None);
+    assert_eq!(allocator.stats().failures, 1);
+
+    // Freed neighbours merge back into one block.
+    assert_eq!(allocator.free(a), Some(32));
+    assert_eq!(allocator.free(b), Some(16));
+    assert_eq!(allocator.free(b), None);
+    assert_eq!(allocator.allocate(48), Some(ARENA + 16));
+}
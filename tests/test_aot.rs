@@ -0,0 +1,165 @@
+#![cfg(all(has_asm, unix, target_arch = "x86_64"))]
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::hash::program_hash;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::artifact::ArtifactKey;
+use ckb_vm::machine::asm::aot::AotCode;
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, A7, SP, T0, T1};
+use ckb_vm::{Bytes, CoreMachine, DefaultMachineBuilder, Error, SupportMachine, ISA_IMC};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_s, pack_u, to_riscv};
+use std::fs;
+use std::sync::Arc;
+
+// Sums 1 to 100 in a loop, with a store left to the interpreter, then mixes
+// the sum with a few other integer operations, and exits with its low 7 bits.
+fn program() -> Bytes {
+    let (a0, a1, t0, t1) = (A0 as u8, A1 as u8, T0 as u8, T1 as u8);
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 100),
+        pack_i(insts::OP_ADDI, a0, 0, 0),
+        // loop:
+        pack_r(insts::OP_ADD, a0, a0, t0),
+        pack_s(insts::OP_SD, SP as u8, a0, -8),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_s(insts::OP_BNE, t0, 0, -12),
+        // a0 = (5050 * 3 >> 2) ^ (0x12345 < a0), that is 3787, and
+        // exits with 3787 & 0x7f = 75.
+        pack_i(insts::OP_ADDI, t1, 0, 3),
+        pack_r(insts::OP_MUL, a0, a0, t1),
+        pack_i(insts::OP_SRAI, a0, a0, 2),
+        pack_u(insts::OP_LUI, a1, 0x12000),
+        pack_i(insts::OP_ADDIW, a1, a1, 0x345),
+        pack_r(insts::OP_SLT, t1, a1, a0),
+        pack_r(insts::OP_XOR, a0, a0, t1),
+        pack_i(insts::OP_ANDI, a0, a0, 0x7f),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+fn machine(program: &Bytes, max_cycles: u64) -> AsmMachine {
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION2, max_cycles);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .predecode(1)
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine.load_program(program, &["aot".into()]).unwrap();
+    machine
+}
+
+fn key(program: &Bytes) -> ArtifactKey {
+    ArtifactKey {
+        program_hash: program_hash(program),
+        version: VERSION2,
+        isa: ISA_IMC,
+        offset: 0,
+    }
+}
+
+#[test]
+pub fn test_aot_matches_asm() {
+    let program = program();
+    let mut expected = machine(&program, u64::MAX);
+    let expected_result = expected.run();
+    assert_eq!(expected_result, Ok(75));
+
+    let mut first = machine(&program, u64::MAX);
+    let code = AotCode::compile(first.machine.predecoded().unwrap(), key(&program)).unwrap();
+    // The loop, the block after it, and the code after the store.
+    assert!(code.blocks().len() >= 3);
+    let code = Arc::new(code);
+    first.set_aot_code(Arc::clone(&code)).unwrap();
+    assert_eq!(first.run(), expected_result);
+    assert_eq!(first.machine.cycles(), expected.machine.cycles());
+    assert_eq!(first.machine.registers(), expected.machine.registers());
+
+    // Machines share the code.
+    let mut second = machine(&program, u64::MAX);
+    second.set_aot_code(code).unwrap();
+    assert_eq!(second.run(), expected_result);
+    assert_eq!(second.machine.cycles(), expected.machine.cycles());
+}
+
+#[test]
+pub fn test_aot_serialization() {
+    let program = program();
+    let key = key(&program);
+    let compiled = {
+        let machine = machine(&program, u64::MAX);
+        AotCode::compile(machine.machine.predecoded().unwrap(), key).unwrap()
+    };
+    let data = compiled.serialize();
+    let code = AotCode::deserialize(&data, &key).unwrap();
+    assert_eq!(code.code(), compiled.code());
+    assert_eq!(code.blocks().len(), compiled.blocks().len());
+
+    let mut machine = machine(&program, u64::MAX);
+    machine.set_aot_code(Arc::new(code)).unwrap();
+    assert_eq!(machine.run(), Ok(75));
+
+    let mut other = key;
+    other.offset = 0x1000;
+    assert!(AotCode::deserialize(&data, &other).is_err());
+    let mut corrupted = data.clone();
+    corrupted[20] ^= 1;
+    assert!(AotCode::deserialize(&corrupted, &key).is_err());
+}
+
+#[test]
+pub fn test_aot_cycles_exceeded() {
+    let program = program();
+    let mut machine = machine(&program, 100);
+    let code = AotCode::compile(machine.machine.predecoded().unwrap(), key(&program)).unwrap();
+    machine.set_aot_code(Arc::new(code)).unwrap();
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+}
+
+#[test]
+pub fn test_aot_perf_map() {
+    let program = program();
+    let mut machine = machine(&program, u64::MAX);
+    let code = AotCode::compile(machine.machine.predecoded().unwrap(), key(&program)).unwrap();
+    let code = Arc::new(code);
+    let path = std::env::temp_dir().join(format!("ckb-vm-perf-{}.map", std::process::id()));
+    let _ = fs::remove_file(&path);
+    machine.set_perf_map(Some(path.clone()));
+    machine.set_aot_code(Arc::clone(&code)).unwrap();
+    assert_eq!(machine.run(), Ok(75));
+
+    let map = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let mut expected = vec![];
+    code.write_perf_map(&mut expected).unwrap();
+    assert_eq!(map.as_bytes(), &expected[..]);
+    let lines: Vec<Vec<&str>> = map.lines().map(|l| l.split(' ').collect()).collect();
+    assert_eq!(lines.len(), code.blocks().len());
+    let mut next = None;
+    let mut total = 0;
+    for (line, block) in lines.iter().zip(code.blocks()) {
+        let start = u64::from_str_radix(line[0], 16).unwrap();
+        let length = u64::from_str_radix(line[1], 16).unwrap();
+        assert_ne!(start, 0);
+        assert!(length > 0);
+        assert_eq!(line[2], format!("guest_{:x}", block.address));
+        // Blocks cover the native code one after the other.
+        if let Some(next) = next {
+            assert_eq!(start, next);
+        }
+        next = Some(start + length);
+        total += length;
+    }
+    assert_eq!(total, code.code().len() as u64);
+
+    // Nothing is written unless asked for.
+    let mut machine = self::machine(&program, u64::MAX);
+    machine.set_aot_code(code).unwrap();
+    assert!(!path.exists());
+}
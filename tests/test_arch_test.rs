@@ -0,0 +1,46 @@
+use ckb_vm::arch_test::{run_arch_test, Signature, SignatureMismatch};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{Bytes, Error, ISA_IMC};
+use std::fs;
+
+#[test]
+pub fn test_signature_format() {
+    let signature = Signature::parse("deadbeef\n00000001\n\n").unwrap();
+    assert_eq!(signature, Signature(vec![0xdeadbeef, 1]));
+    assert_eq!(signature.to_string(), "deadbeef\n00000001\n");
+    assert!(Signature::parse("xyz").is_err());
+}
+
+#[test]
+pub fn test_signature_mismatches() {
+    let actual = Signature(vec![1, 2, 3]);
+    let expected = Signature(vec![1, 4]);
+    assert_eq!(
+        actual.mismatches(&expected),
+        vec![
+            SignatureMismatch {
+                offset: 4,
+                actual: Some(2),
+                expected: Some(4)
+            },
+            SignatureMismatch {
+                offset: 8,
+                actual: Some(3),
+                expected: None
+            }
+        ]
+    );
+    assert!(actual.mismatches(&actual).is_empty());
+}
+
+#[test]
+pub fn test_arch_test_requires_signature_symbols() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let result = run_arch_test::<u64>(&buffer, ISA_IMC, VERSION2, u64::MAX);
+    assert_eq!(
+        result,
+        Err(Error::Unexpected(String::from(
+            "Missing symbol: begin_signature"
+        )))
+    );
+}
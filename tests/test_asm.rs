@@ -1,11 +1,15 @@
 #![cfg(has_asm)]
 use ckb_vm::cost_model::constant_cycles;
 use ckb_vm::decoder::build_decoder;
+use ckb_vm::machine::asm::cycle_check::check_cycles;
 use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
 use ckb_vm::machine::{CoreMachine, VERSION0, VERSION1};
 use ckb_vm::memory::Memory;
 use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
-use ckb_vm::{Debugger, DefaultMachineBuilder, Error, Register, SupportMachine, Syscalls, ISA_IMC};
+use ckb_vm::{
+    Debugger, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, Instruction,
+    Register, SparseMemory, SupportMachine, Syscalls, ISA_IMC,
+};
 use std::fs;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
@@ -435,3 +439,50 @@ fn test_zero_address() {
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 0);
 }
+
+fn cycle_check_machines(
+    interpreter_cycles: fn(Instruction) -> u64,
+) -> (
+    DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+    AsmMachine,
+) {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::max_value());
+    let mut interpreter = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(interpreter_cycles))
+        .build();
+    interpreter
+        .load_program(&buffer, &vec!["simple64".into()])
+        .unwrap();
+    let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::max_value());
+    let core = DefaultMachineBuilder::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&buffer, &vec!["simple64".into()])
+        .unwrap();
+    (interpreter, machine)
+}
+
+#[test]
+fn test_asm_cycle_check() {
+    let (mut interpreter, mut machine) = cycle_check_machines(constant_cycles);
+    assert_eq!(check_cycles(&mut interpreter, &mut machine), Ok(None));
+    assert_eq!(machine.machine.exit_code(), 0);
+    assert_eq!(interpreter.cycles(), machine.machine.cycles());
+}
+
+#[test]
+fn test_asm_cycle_check_mismatch() {
+    let (mut interpreter, mut machine) = cycle_check_machines(|_| 2);
+    let entry = *machine.machine.pc();
+    let mismatch = check_cycles(&mut interpreter, &mut machine)
+        .unwrap()
+        .unwrap();
+    assert_eq!(mismatch.block, 0);
+    assert_eq!(mismatch.address, entry);
+    assert_eq!(mismatch.interpreter.pc, mismatch.asm.pc);
+    assert_eq!(mismatch.interpreter.cycles, mismatch.asm.cycles * 2);
+}
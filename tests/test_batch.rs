@@ -0,0 +1,123 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::isa::Isa;
+use ckb_vm::machine::batch::{run_batch, BatchJob};
+use ckb_vm::machine::config::MachineConfig;
+use ckb_vm::machine::report::ExitReason;
+use ckb_vm::machine::{VERSION0, VERSION2};
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, Error, Register, SparseMemory, SupportMachine, Syscalls, ISA_A,
+    ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, to_riscv};
+
+type Core = DefaultCoreMachine<u64, SparseMemory<u64>>;
+
+// Syscall 1111 returns the value configured for the job.
+struct ValueSyscall(u64);
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ValueSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1111 {
+            return Ok(false);
+        }
+        machine.set_register(A0, Mac::REG::from_u64(self.0));
+        Ok(true)
+    }
+}
+
+fn program(code: &[Instruction]) -> Bytes {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    minimal_elf::<u64>(&code)
+}
+
+// Exits with the value returned by syscall 1111.
+fn exit_with_syscall_value() -> Bytes {
+    program(&[
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 1111),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ])
+}
+
+#[test]
+pub fn test_run_batch() {
+    let mut jobs: Vec<BatchJob<u64>> = (0..16)
+        .map(|i| BatchJob::new(exit_with_syscall_value(), vec!["main".into()], 1000, i % 4))
+        .collect();
+    // Spins until it runs out of cycles.
+    jobs.push(BatchJob::new(
+        program(&[pack_b(insts::OP_BEQ, 0, 0, 0)]),
+        vec!["main".into()],
+        100,
+        0,
+    ));
+    jobs.push(BatchJob::new(
+        Bytes::from_static(b"not an elf"),
+        vec![],
+        100,
+        0,
+    ));
+    let config = MachineConfig::new().instruction_cycle_func(Box::new(constant_cycles));
+    let results = run_batch::<Core, _, _>(config, 4, jobs, |builder, value| {
+        builder.syscall(Box::new(ValueSyscall(*value)))
+    })
+    .unwrap();
+
+    assert_eq!(results.jobs.len(), 18);
+    for (i, job) in results.jobs[..16].iter().enumerate() {
+        let report = job.as_ref().unwrap();
+        assert_eq!(report.exit_reason, ExitReason::Exit((i % 4) as i8));
+        assert_eq!(report.cycles, 4);
+    }
+    assert_eq!(
+        results.jobs[16].as_ref().unwrap().exit_reason,
+        ExitReason::CyclesExceeded
+    );
+    assert!(results.jobs[17].is_err());
+    assert_eq!(results.succeeded(), 4);
+    assert_eq!(results.failed(), 14);
+    assert!(results.total_cycles() >= 16 * 4 + 100);
+}
+
+#[test]
+pub fn test_run_batch_edge_cases() {
+    let config = MachineConfig::new();
+    let results = run_batch::<Core, u64, _>(config, 4, vec![], |builder, _| builder).unwrap();
+    assert!(results.jobs.is_empty());
+    assert_eq!(results.total_cycles(), 0);
+
+    // Invalid configs fail the whole batch.
+    let isa = Isa::new(ISA_IMC | ISA_A, VERSION2).unwrap();
+    let config = MachineConfig::new().isa(isa).version(VERSION0);
+    let jobs = vec![BatchJob::new(exit_with_syscall_value(), vec![], 1000, 0)];
+    assert!(run_batch::<Core, u64, _>(config, 1, jobs, |builder, _| builder).is_err());
+
+    // A panicking setup only fails its own jobs.
+    let jobs: Vec<BatchJob<u64>> = (0..8)
+        .map(|i| BatchJob::new(exit_with_syscall_value(), vec!["main".into()], 1000, i))
+        .collect();
+    let results = run_batch::<Core, _, _>(MachineConfig::new(), 2, jobs, |builder, value| {
+        if *value == 3 {
+            panic!("bad job");
+        }
+        builder.syscall(Box::new(ValueSyscall(*value)))
+    })
+    .unwrap();
+    assert_eq!(
+        results.jobs[3],
+        Err(Error::Unexpected("A batch worker panicked".to_string()))
+    );
+    assert_eq!(results.jobs[0].as_ref().unwrap().result(), Ok(0));
+    assert_eq!(results.jobs[7].as_ref().unwrap().result(), Ok(7));
+}
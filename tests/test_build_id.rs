@@ -0,0 +1,91 @@
+use ckb_vm::elf_writer::{minimal_elf, minimal_elf_with_build_id};
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::elf_adaptor::build_id;
+use ckb_vm::machine::program::ProgramInfo;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Register, SparseMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn code(exit_code: i32) -> Vec<u8> {
+    [
+        pack_i(insts::OP_ADDI, A0 as u8, 0, exit_code),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect()
+}
+
+fn new_machine() -> Machine {
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    DefaultMachineBuilder::new(core_machine).build()
+}
+
+#[test]
+pub fn test_build_id_extraction() {
+    let id: Vec<u8> = (0..20).collect();
+    assert_eq!(
+        build_id(&minimal_elf_with_build_id::<u64>(&code(0), &id)),
+        Some(id.clone())
+    );
+    assert_eq!(
+        build_id(&minimal_elf_with_build_id::<u32>(&code(0), &id[..8])),
+        Some(id[..8].to_vec())
+    );
+    assert_eq!(build_id(&minimal_elf::<u64>(&code(0))), None);
+    assert_eq!(build_id(b"not an elf"), None);
+}
+
+#[test]
+pub fn test_build_id_in_program_info_and_reports() {
+    let id = vec![0xde, 0xad, 0xbe, 0xef, 0x01];
+    let program = minimal_elf_with_build_id::<u64>(&code(3), &id);
+    let mut machine = new_machine();
+    assert_eq!(machine.program_info(), None);
+    machine.load_program(&program, &["main".into()]).unwrap();
+    assert_eq!(
+        machine.program_info(),
+        Some(&ProgramInfo {
+            entry: machine.pc().to_u64(),
+            size: program.len() as u64,
+            build_id: Some(id.clone()),
+            load_bias: 0,
+        })
+    );
+    assert_eq!(
+        machine.program_info().unwrap().build_id_hex(),
+        Some("deadbeef01".to_string())
+    );
+    assert!(machine
+        .dump(&[])
+        .unwrap()
+        .contains("build-id: deadbeef01\n"));
+
+    let report = machine.run_with_report(false);
+    assert_eq!(report.result(), Ok(3));
+    assert_eq!(report.build_id, Some(id));
+
+    // A new build of the program replaces the build ID.
+    let mut machine = new_machine();
+    machine.load_program(&program, &["main".into()]).unwrap();
+    machine
+        .reload_program(&minimal_elf_with_build_id::<u64>(&code(4), &[7; 20]))
+        .unwrap();
+    assert_eq!(machine.program_info().unwrap().build_id, Some(vec![7; 20]));
+    assert_eq!(machine.run_with_report(false).build_id, Some(vec![7; 20]));
+
+    // Programs without a build ID.
+    let mut machine = new_machine();
+    machine
+        .load_program(&minimal_elf::<u64>(&code(0)), &["main".into()])
+        .unwrap();
+    assert_eq!(machine.program_info().unwrap().build_id, None);
+    assert!(!machine.dump(&[]).unwrap().contains("build-id"));
+}
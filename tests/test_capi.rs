@@ -0,0 +1,113 @@
+#![cfg(feature = "capi")]
+use ckb_vm::capi::*;
+use ckb_vm::machine::VERSION1;
+use ckb_vm::registers::{A0, A5, A7};
+use ckb_vm::{ISA_IMC, RISCV_MAX_MEMORY};
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+extern "C" fn custom_syscall(data: *mut c_void, core: *mut CkbVmCore) -> c_int {
+    let mut code = 0;
+    unsafe {
+        assert_eq!(ckb_vm_core_register(core, A7, &mut code), CKB_VM_OK);
+    }
+    if code != 1111 {
+        return 0;
+    }
+    let mut result: u64 = 0;
+    for i in A0..=A5 {
+        let mut value = 0;
+        unsafe {
+            assert_eq!(ckb_vm_core_register(core, i, &mut value), CKB_VM_OK);
+        }
+        result = result.wrapping_add(value);
+    }
+    unsafe {
+        *(data as *mut u64) += 1;
+        assert_eq!(ckb_vm_core_set_register(core, A0, result), CKB_VM_OK);
+    }
+    1
+}
+
+#[test]
+pub fn test_capi_run_simple() {
+    let program = std::fs::read("tests/programs/simple64").unwrap();
+    let arg = b"simple";
+    let argv = [arg.as_ptr()];
+    let argv_lengths = [arg.len()];
+    unsafe {
+        let machine = ckb_vm_machine_new(ISA_IMC, VERSION1, u64::max_value(), RISCV_MAX_MEMORY);
+        assert_eq!(
+            ckb_vm_machine_load_program(
+                machine,
+                program.as_ptr(),
+                program.len(),
+                1,
+                argv.as_ptr(),
+                argv_lengths.as_ptr()
+            ),
+            CKB_VM_OK
+        );
+        let mut exit_code = -1;
+        assert_eq!(ckb_vm_machine_run(machine, &mut exit_code), CKB_VM_OK);
+        assert_eq!(exit_code, 0);
+        assert!(ckb_vm_core_cycles(ckb_vm_machine_core(machine)) > 0);
+        ckb_vm_machine_free(machine);
+    }
+}
+
+#[test]
+pub fn test_capi_syscall_and_resume() {
+    let program = std::fs::read("tests/programs/syscall64").unwrap();
+    let mut calls: u64 = 0;
+    unsafe {
+        let machine = ckb_vm_machine_new(ISA_IMC, VERSION1, 10, RISCV_MAX_MEMORY);
+        assert_eq!(
+            ckb_vm_machine_add_syscall(
+                machine,
+                custom_syscall,
+                &mut calls as *mut u64 as *mut c_void
+            ),
+            CKB_VM_OK
+        );
+        assert_eq!(
+            ckb_vm_machine_load_program(
+                machine,
+                program.as_ptr(),
+                program.len(),
+                0,
+                ptr::null(),
+                ptr::null()
+            ),
+            CKB_VM_OK
+        );
+        let mut exit_code = -1;
+        assert_eq!(
+            ckb_vm_machine_run(machine, &mut exit_code),
+            CKB_VM_ERROR_CYCLES_EXCEEDED
+        );
+        assert!(!ckb_vm_machine_last_error(machine).is_null());
+        assert_eq!(
+            ckb_vm_machine_resume(machine, u64::max_value(), &mut exit_code),
+            CKB_VM_OK
+        );
+        assert_eq!(exit_code, 39);
+        assert_eq!(calls, 1);
+        ckb_vm_machine_free(machine);
+    }
+}
+
+#[test]
+pub fn test_capi_panic() {
+    // The memory size must be a multiple of the page size, the assertion
+    // failing inside ckb_vm_machine_new must not unwind into the caller.
+    let machine = ckb_vm_machine_new(ISA_IMC, VERSION1, u64::max_value(), 1);
+    assert!(machine.is_null());
+    unsafe {
+        assert_eq!(ckb_vm_core_cycles(ckb_vm_machine_core(machine)), 0);
+        assert_eq!(
+            ckb_vm_machine_run(machine, ptr::null_mut()),
+            CKB_VM_ERROR_INVALID_ARGUMENT
+        );
+    }
+}
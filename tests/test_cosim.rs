@@ -0,0 +1,120 @@
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::instructions::instruction_length;
+use ckb_vm::machine::cosim::{
+    parse_spike_commit, run_lockstep, CommitRecord, DivergenceKind, LockstepOptions,
+    ReferenceModel, SpikeCommitLog,
+};
+use ckb_vm::machine::{DefaultMachine, VERSION1};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Memory, Register, SparseMemory,
+    SupportMachine, ISA_IMC,
+};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Cursor;
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load_simple64() -> Machine {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine
+}
+
+// Records commits of simple64 by stepping a separate machine.
+fn record_simple64() -> VecDeque<CommitRecord> {
+    let mut machine = load_simple64();
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    let mut records = VecDeque::new();
+    machine.set_running(true);
+    while machine.running() {
+        let pc = machine.pc().to_u64();
+        let before = machine.registers().to_vec();
+        let instruction = decoder.decode(machine.memory_mut(), pc).unwrap();
+        let length = instruction_length(instruction) as u64;
+        let bytes = machine.memory_mut().load_bytes(pc, length).unwrap();
+        let raw = bytes
+            .iter()
+            .rev()
+            .fold(0u32, |acc, byte| (acc << 8) | u32::from(*byte));
+        machine.step(&mut decoder).unwrap();
+        let writes = (1..32)
+            .filter(|i| machine.registers()[*i] != before[*i])
+            .map(|i| (i, machine.registers()[i]))
+            .collect();
+        records.push_back(CommitRecord {
+            privilege: Some(0),
+            pc,
+            instruction: raw,
+            writes,
+        });
+    }
+    records
+}
+
+#[test]
+pub fn test_parse_spike_commit() {
+    assert_eq!(
+        parse_spike_commit("core   0: 3 0x0000000080000004 (0x00a00513) x10 0x000000000000000a"),
+        Some(CommitRecord {
+            privilege: Some(3),
+            pc: 0x80000004,
+            instruction: 0x00a00513,
+            writes: vec![(10, 0xa)],
+        })
+    );
+    assert_eq!(
+        parse_spike_commit(
+            "core   0: 0 0x0000000000010100 (0x4501) c768_mstatus 0x8 mem 0x0000000000011000 0x1"
+        ),
+        Some(CommitRecord {
+            privilege: Some(0),
+            pc: 0x10100,
+            instruction: 0x4501,
+            writes: vec![],
+        })
+    );
+    // Disassembly printed without --log-commits
+    assert_eq!(
+        parse_spike_commit("core   0: 0x0000000080000004 (0x00a00513) li      a0, 10"),
+        None
+    );
+
+    let log = "bbl loader\ncore   0: 3 0x0000000000001000 (0x00000297) x5  0x0000000000001000\n\
+               core   0: 0 0x0000000000010100 (0x4501) x10 0x0000000000000000\n";
+    let mut commits = SpikeCommitLog::new(Cursor::new(log), true);
+    assert_eq!(commits.next_commit().unwrap().unwrap().pc, 0x10100);
+    assert_eq!(commits.next_commit().unwrap(), None);
+}
+
+#[test]
+pub fn test_lockstep() {
+    let mut reference = record_simple64();
+    let mut machine = load_simple64();
+    let result = run_lockstep(&mut machine, &mut reference, &LockstepOptions::default());
+    assert_eq!(result.unwrap(), None);
+    assert_eq!(machine.exit_code(), 0);
+
+    let mut reference = record_simple64();
+    reference[5].writes = vec![(10, 0xdead)];
+    let mut machine = load_simple64();
+    let divergence = run_lockstep(&mut machine, &mut reference, &LockstepOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(divergence.step, 5);
+    assert!(matches!(divergence.kind, DivergenceKind::Register { .. }));
+
+    let mut reference = record_simple64();
+    reference.truncate(10);
+    let mut machine = load_simple64();
+    let divergence = run_lockstep(&mut machine, &mut reference, &LockstepOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(divergence.step, 10);
+    assert_eq!(divergence.kind, DivergenceKind::ReferenceStopped);
+}
@@ -0,0 +1,177 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::csr::{CounterCsrs, Counters, CsrProvider, CSR_CYCLE, CSR_INSTRET};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A2, A3, A7, T0};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Instruction,
+    SparseMemory, SupportMachine, TraceMachine, ISA_IMC, ISA_ZICSR,
+};
+use ckb_vm_definitions::encoding::{pack_i, to_riscv};
+
+type Core = DefaultCoreMachine<u64, SparseMemory<u64>>;
+
+// A custom read/write CSR.
+const CSR_SCRATCH: u16 = 0x800;
+
+#[derive(Default)]
+struct ScratchCsrs {
+    scratch: u64,
+}
+
+impl<Mac: SupportMachine> CsrProvider<Mac> for ScratchCsrs {
+    fn read_csr(&mut self, machine: &mut Mac, counters: Counters, csr: u16) -> Option<u64> {
+        match csr {
+            CSR_SCRATCH => Some(self.scratch),
+            _ => CounterCsrs.read_csr(machine, counters, csr),
+        }
+    }
+
+    fn write_csr(&mut self, machine: &mut Mac, csr: u16, value: u64) -> Option<()> {
+        match csr {
+            CSR_SCRATCH => {
+                self.scratch = value;
+                Some(())
+            }
+            _ => CounterCsrs.write_csr(machine, csr, value),
+        }
+    }
+}
+
+fn csr(op: u16, rd: usize, rs1: usize, csr: u16) -> Instruction {
+    pack_i(op, rd as u8, rs1 as u8, i32::from(csr))
+}
+
+fn program(body: &[Instruction]) -> Bytes {
+    let code: Vec<u8> = body
+        .iter()
+        .chain(&[
+            pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+            pack_i(insts::OP_ECALL, 0, 0, 0),
+        ])
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    minimal_elf::<u64>(&code)
+}
+
+fn machine(
+    program: &Bytes,
+    isa: u64,
+    provider: Option<Box<dyn CsrProvider<Core>>>,
+) -> DefaultMachine<Core> {
+    let core = Core::new(isa, VERSION2, 1000);
+    let mut builder =
+        DefaultMachineBuilder::new(core).instruction_cycle_func(Box::new(constant_cycles));
+    if let Some(provider) = provider {
+        builder = builder.csr_provider(provider);
+    }
+    let mut machine = builder.build();
+    machine.load_program(program, &["csr".into()]).unwrap();
+    machine
+}
+
+#[test]
+pub fn test_csr_counters() {
+    let program = program(&[
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 7),
+        pack_i(insts::OP_ADDI, T0 as u8, T0 as u8, 1),
+        // rdcycle and rdinstret.
+        csr(insts::OP_CSRRS, A0, 0, CSR_CYCLE),
+        csr(insts::OP_CSRRS, A1, 0, CSR_INSTRET),
+    ]);
+    let mut machine = machine(&program, ISA_IMC | ISA_ZICSR, None);
+    // The cycle read counts itself, instret only counts retired
+    // instructions.
+    assert_eq!(machine.run(), Ok(3));
+    assert_eq!(machine.registers()[A1], 3);
+    assert_eq!(machine.instret(), 6);
+
+    let mut machine = TraceMachine::new(self::machine(&program, ISA_IMC | ISA_ZICSR, None));
+    assert_eq!(machine.run(), Ok(3));
+    assert_eq!(machine.registers()[A1], 3);
+}
+
+#[test]
+pub fn test_csr_counters_mid_block() {
+    // Straight line code only ended by the exit ECALL, the counters are read
+    // in the middle of it and must not see the cost of what follows.
+    let addi = pack_i(insts::OP_ADDI, T0 as u8, T0 as u8, 1);
+    let program = program(&[
+        addi,
+        addi,
+        csr(insts::OP_CSRRS, A1, 0, CSR_CYCLE),
+        addi,
+        addi,
+        addi,
+        csr(insts::OP_CSRRS, A2, 0, CSR_CYCLE),
+        csr(insts::OP_CSRRS, A3, 0, CSR_INSTRET),
+        addi,
+    ]);
+    let check = |registers: &[u64]| {
+        assert_eq!(registers[A1], 3);
+        assert_eq!(registers[A2], 7);
+        assert_eq!(registers[A3], 7);
+    };
+
+    let mut machine = machine(&program, ISA_IMC | ISA_ZICSR, None);
+    assert_eq!(machine.run(), Ok(0));
+    check(machine.registers());
+    assert_eq!(machine.cycles(), 11);
+
+    let mut machine = TraceMachine::new(self::machine(&program, ISA_IMC | ISA_ZICSR, None));
+    assert_eq!(machine.run(), Ok(0));
+    check(machine.registers());
+    assert_eq!(machine.machine.cycles(), 11);
+
+    // Stepping charges one instruction at a time.
+    let mut machine = self::machine(&program, ISA_IMC | ISA_ZICSR, None);
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    machine.set_running(true);
+    while machine.running() {
+        machine.step(&mut decoder).unwrap();
+    }
+    check(machine.registers());
+    assert_eq!(machine.cycles(), 11);
+}
+
+#[test]
+pub fn test_csr_provider() {
+    let program = program(&[
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 7),
+        csr(insts::OP_CSRRW, 0, T0, CSR_SCRATCH),
+        csr(insts::OP_CSRRSI, A2, 8, CSR_SCRATCH),
+        csr(insts::OP_CSRRS, A0, 0, CSR_SCRATCH),
+    ]);
+    let provider = Box::new(ScratchCsrs::default());
+    let mut machine = machine(&program, ISA_IMC | ISA_ZICSR, Some(provider));
+    assert_eq!(machine.run(), Ok(15));
+    assert_eq!(machine.registers()[A2], 7);
+
+    // The scratch CSR doesn't exist without the provider.
+    let mut machine = self::machine(&program, ISA_IMC | ISA_ZICSR, None);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::InvalidInstruction { .. })
+    ));
+}
+
+#[test]
+pub fn test_csr_invalid() {
+    // Counters are read only.
+    let program = program(&[csr(insts::OP_CSRRW, 0, T0, CSR_CYCLE)]);
+    let mut machine = machine(&program, ISA_IMC | ISA_ZICSR, None);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::InvalidInstruction { .. })
+    ));
+
+    // CSR instructions don't decode without ISA_ZICSR.
+    let program = self::program(&[csr(insts::OP_CSRRS, A0, 0, CSR_CYCLE)]);
+    let mut machine = self::machine(&program, ISA_IMC, None);
+    assert!(matches!(
+        machine.run(),
+        Err(Error::InvalidInstruction { .. })
+    ));
+}
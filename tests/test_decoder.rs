@@ -0,0 +1,359 @@
+use ckb_vm::ckb_vm_definitions::encoding;
+use ckb_vm::decoder::{build_decoder, encoding_length, InstructionDecoder, Operands};
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{
+    extract_opcode, instruction_length, insts, Instruction, Itype, Rtype, Stype, Utype,
+};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::elf_adaptor::uses_compressed_instructions;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, SP, ZERO};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory, Memory, SparseMemory,
+    TraceMachine, ISA_A, ISA_B, ISA_IMC, ISA_MOP, RISCV_PAGESIZE,
+};
+use std::fs;
+
+#[test]
+pub fn test_decode_bytes() {
+    let decoder = InstructionDecoder::new::<u64>(ISA_IMC, VERSION2);
+    // addi a0, zero, -5
+    let i = decoder.decode(&[0x13, 0x05, 0xb0, 0xff], 0x1000).unwrap();
+    assert_eq!(i.pc, 0x1000);
+    assert_eq!(i.opcode, insts::OP_ADDI);
+    assert_eq!(i.name, "ADDI");
+    assert_eq!(i.length, 4);
+    assert_eq!(
+        i.operands,
+        Operands::I {
+            rd: A0,
+            rs1: ZERO,
+            imm: -5
+        }
+    );
+
+    // c.li a0, 5
+    let i = decoder.decode(&[0x15, 0x45], 0x1004).unwrap();
+    assert_eq!(i.opcode, insts::OP_ADDI);
+    assert_eq!(i.length, 2);
+}
+
+#[test]
+pub fn test_decode_all() {
+    let decoder = InstructionDecoder::new::<u64>(ISA_IMC | ISA_MOP, VERSION2);
+    // c.li a0, 5; ecall; a truncated 32-bit instruction
+    let code = [0x15, 0x45, 0x73, 0x00, 0x00, 0x00, 0x13, 0x05];
+    let decoded: Vec<_> = decoder.decode_all(&code, 0x2000).collect();
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[1].as_ref().unwrap().pc, 0x2002);
+    assert_eq!(decoded[1].as_ref().unwrap().opcode, insts::OP_ECALL);
+    assert_eq!(decoded[2], Err(Error::MemOutOfBound));
+}
+
+#[test]
+pub fn test_decode_invalid_instruction() {
+    let decoder = InstructionDecoder::new::<u64>(ISA_IMC, VERSION2);
+    assert_eq!(
+        decoder.decode(&[0xff, 0xff, 0xff, 0xff], 0x10),
+        Err(Error::InvalidInstruction {
+            pc: 0x10,
+            instruction: 0xffffffff
+        })
+    );
+}
+
+#[test]
+pub fn test_encoding_schema_matches_instruction_types() {
+    assert_eq!(
+        encoding::pack_r(insts::OP_ADD, 10, 11, 12),
+        Rtype::new(insts::OP_ADD, 10, 11, 12).0
+    );
+    assert_eq!(
+        encoding::pack_i(insts::OP_ADDI, 10, 2, -16),
+        Itype::new_s(insts::OP_ADDI, 10, 2, -16).0
+    );
+    assert_eq!(
+        encoding::pack_s(insts::OP_SD, SP as u8, A1 as u8, -8),
+        Stype::new_s(insts::OP_SD, -8, SP, A1).0
+    );
+    assert_eq!(
+        encoding::pack_u(insts::OP_LUI, 10, 0x12345000),
+        Utype::new_s(insts::OP_LUI, 10, 0x12345000).0
+    );
+    let i = encoding::with_length(encoding::pack_b(insts::OP_BNE, 14, 15, -2048), 4);
+    assert_eq!(encoding::opcode(i), insts::OP_BNE);
+    assert_eq!(encoding::length(i), instruction_length(i));
+    assert_eq!(encoding::unpack_b(i), (14, 15, -2048));
+    assert_eq!(
+        encoding::unpack_r5(encoding::pack_r5(insts::OP_ADC, 1, 2, 3, 4, 5)),
+        (1, 2, 3, 4, 5)
+    );
+}
+
+#[test]
+pub fn test_encoding_round_trip() {
+    let decoder = build_decoder::<u64>(ISA_IMC | ISA_A | ISA_B, VERSION2);
+    for name in [
+        "simple64",
+        "amo_compare",
+        "clmul_bug",
+        "clzw_bug",
+        "mulw64",
+        "orc_bug",
+        "pcnt",
+        "sbinvi_aot_load_imm_bug",
+        "sc_after_sc",
+    ] {
+        let buffer = fs::read(format!("tests/programs/{}", name)).unwrap();
+        let elf = goblin_v040::elf::Elf::parse(&buffer).unwrap();
+        let text = elf
+            .section_headers
+            .iter()
+            .find(|h| elf.shdr_strtab.get_at(h.sh_name) == Some(".text"))
+            .unwrap();
+        let start = text.sh_offset as usize;
+        let code = &buffer[start..start + text.sh_size as usize];
+        let mut checked = 0;
+        let mut offset = 0;
+        while offset + 2 <= code.len() {
+            let instruction = match decoder.decode_bytes(&code[offset..], offset as u64) {
+                Ok(i) => i,
+                Err(_) => {
+                    offset += 2;
+                    continue;
+                }
+            };
+            let length = instruction_length(instruction) as usize;
+            if length == 4 {
+                let raw = u32::from_le_bytes(code[offset..offset + 4].try_into().unwrap());
+                assert_eq!(
+                    encoding::to_riscv(instruction),
+                    Some(raw),
+                    "{} at 0x{:x}",
+                    name,
+                    offset
+                );
+                checked += 1;
+            }
+            offset += length;
+        }
+        assert!(checked > 0, "{}", name);
+    }
+}
+
+#[test]
+pub fn test_encoding_compressed_and_unsupported() {
+    let decoder = build_decoder::<u64>(ISA_IMC, VERSION2);
+    // c.li a0, 5 is encoded as addi a0, zero, 5
+    let i = decoder.decode_bytes(&[0x15, 0x45], 0).unwrap();
+    assert_eq!(encoding::to_riscv(i), Some(0x00500513));
+
+    let adc = encoding::pack_r(insts::OP_ADC, 10, 10, 11);
+    assert_eq!(encoding::to_riscv(adc), None);
+    let out_of_range = encoding::pack_i(insts::OP_ADDI, 10, 0, 4096);
+    assert_eq!(encoding::to_riscv(out_of_range), None);
+    let bad_register = encoding::pack_r(insts::OP_ADD, 32, 0, 0);
+    assert_eq!(encoding::to_riscv(bad_register), None);
+}
+
+#[test]
+pub fn test_decode_without_compressed() {
+    let mut memory = FlatMemory::<u64>::new_with_memory(RISCV_PAGESIZE * 2);
+    // c.li a0, 5; addi a0, zero, -5 at a 2-byte aligned PC; addi a0, zero, -5
+    // at the end of the first page
+    let code = [0x15, 0x45, 0x13, 0x05, 0xb0, 0xff];
+    memory.store_bytes(0x100, &code).unwrap();
+    memory
+        .store_bytes(RISCV_PAGESIZE as u64 - 4, &code[2..])
+        .unwrap();
+
+    let mut expected = build_decoder::<u64>(ISA_IMC, VERSION2);
+    let mut decoder = build_decoder::<u64>(ISA_IMC, VERSION2);
+    decoder.set_compressed(false);
+    for pc in [0x100, 0x102, RISCV_PAGESIZE as u64 - 4] {
+        assert_eq!(
+            decoder.decode(&mut memory, pc),
+            expected.decode(&mut memory, pc)
+        );
+    }
+    let i = decoder.decode(&mut memory, 0x100).unwrap();
+    assert_eq!(instruction_length(i), 2);
+
+    assert!(uses_compressed_instructions(
+        &fs::read("tests/programs/simple64").unwrap()
+    ));
+    assert!(!uses_compressed_instructions(&minimal_elf::<u64>(&code)));
+    assert!(uses_compressed_instructions(&[]));
+}
+
+#[test]
+pub fn test_denied_opcodes() {
+    let mut memory = FlatMemory::<u64>::new_with_memory(RISCV_PAGESIZE);
+    // lr.w a0, (a1); addi a0, zero, -5
+    memory
+        .store_bytes(0x100, &[0x2f, 0xa5, 0x05, 0x10, 0x13, 0x05, 0xb0, 0xff])
+        .unwrap();
+    let mut decoder = build_decoder::<u64>(ISA_IMC | ISA_A, VERSION2);
+    assert!(decoder.decode(&mut memory, 0x100).is_ok());
+    // Cached instructions are denied as well.
+    decoder.set_denied_opcodes(insts::OP_LR_W..=insts::OP_AMOMAXU_D);
+    assert_eq!(
+        decoder.decode(&mut memory, 0x100),
+        Err(Error::DeniedInstruction {
+            pc: 0x100,
+            opcode: insts::OP_LR_W
+        })
+    );
+    assert!(decoder.decode(&mut memory, 0x104).is_ok());
+
+    // Fused instructions are checked after fusion.
+    let buffer: Bytes = fs::read("tests/programs/mop_adc").unwrap().into();
+    let run = |denied: Vec<insts::InstructionOpcode>| {
+        let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(
+            ISA_IMC | ISA_B | ISA_MOP,
+            VERSION2,
+            u64::MAX,
+        );
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .deny_opcodes(denied)
+            .build();
+        machine.load_program(&buffer, &["mop_adc".into()]).unwrap();
+        machine.run()
+    };
+    assert_eq!(run(vec![]), Ok(0));
+    match run(vec![insts::OP_ADC]) {
+        Err(Error::DeniedInstruction { opcode, .. }) => assert_eq!(opcode, insts::OP_ADC),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+// Decodes two made-up long instructions: a 48-bit "li rd, imm32" and a
+// 64-bit "addi rd, rs1, imm32".
+fn long_factory(bits: u64, length: u8, _version: u32) -> Option<Instruction> {
+    let rd = ((bits >> 7) & 0x1f) as usize;
+    match length {
+        6 => Some(Itype::new_s(insts::OP_ADDI, rd, 0, (bits >> 16) as i32).0),
+        8 => {
+            let rs1 = ((bits >> 15) & 0x1f) as usize;
+            Some(Itype::new_s(insts::OP_ADDI, rd, rs1, (bits >> 32) as i32).0)
+        }
+        _ => None,
+    }
+}
+
+// li a0, 20; addi a0, a0, 22
+const LONG_CODE: [u8; 14] = [0x1f, 0x05, 20, 0, 0, 0, 0x3f, 0x05, 0x05, 0x00, 22, 0, 0, 0];
+
+#[test]
+pub fn test_encoding_length() {
+    assert_eq!(encoding_length(0x4515), Some(2));
+    assert_eq!(encoding_length(0x0513), Some(4));
+    assert_eq!(encoding_length(0x051f), Some(6));
+    assert_eq!(encoding_length(0x055f), Some(6));
+    assert_eq!(encoding_length(0x053f), Some(8));
+    assert_eq!(encoding_length(0x057f), None);
+}
+
+#[test]
+pub fn test_decode_long_instructions() {
+    let mut decoder = build_decoder::<u64>(ISA_IMC, VERSION2);
+    assert_eq!(
+        decoder.decode_bytes(&LONG_CODE, 0x100),
+        Err(Error::InvalidInstruction {
+            pc: 0x100,
+            instruction: 0x0014051f
+        })
+    );
+    decoder.add_long_instruction_factory(long_factory);
+    let i = decoder.decode_bytes(&LONG_CODE, 0x100).unwrap();
+    assert_eq!(instruction_length(i), 6);
+    assert_eq!(Itype(i).immediate_s(), 20);
+    let i = decoder.decode_bytes(&LONG_CODE[6..], 0x106).unwrap();
+    assert_eq!(instruction_length(i), 8);
+    assert_eq!(Itype(i).rs1(), A0);
+    assert_eq!(
+        decoder.decode_bytes(&LONG_CODE[6..12], 0x106),
+        Err(Error::MemOutOfBound)
+    );
+
+    // Long instructions may cross pages.
+    let mut memory = FlatMemory::<u64>::new_with_memory(RISCV_PAGESIZE * 2);
+    let pc = RISCV_PAGESIZE as u64 - 2;
+    memory.store_bytes(pc, &LONG_CODE[6..]).unwrap();
+    let i = decoder.decode(&mut memory, pc).unwrap();
+    assert_eq!(extract_opcode(i), insts::OP_ADDI);
+    assert_eq!(instruction_length(i), 8);
+    assert_eq!(Itype(i).immediate_s(), 22);
+}
+
+#[test]
+pub fn test_decode_truncated_long_instruction() {
+    // The first 32 bits of "li a0, 20" in the last word of memory.
+    let mut memory = FlatMemory::<u64>::new_with_memory(RISCV_PAGESIZE);
+    let pc = RISCV_PAGESIZE as u64 - 4;
+    memory.store_bytes(pc, &LONG_CODE[..4]).unwrap();
+    let invalid = Err(Error::InvalidInstruction {
+        pc,
+        instruction: 0x0014051f,
+    });
+
+    let mut decoder = build_decoder::<u64>(ISA_IMC, VERSION2);
+    assert_eq!(decoder.decode(&mut memory, pc), invalid);
+    assert_eq!(decoder.decode_bytes(&LONG_CODE[..4], pc), invalid);
+
+    let mut decoder = build_decoder::<u64>(ISA_IMC, VERSION2);
+    decoder.add_long_instruction_factory(long_factory);
+    assert_eq!(decoder.decode(&mut memory, pc), Err(Error::MemOutOfBound));
+    assert_eq!(
+        decoder.decode_bytes(&LONG_CODE[..4], pc),
+        Err(Error::MemOutOfBound)
+    );
+}
+
+#[test]
+pub fn test_run_long_instructions() {
+    let mut code = LONG_CODE.to_vec();
+    for i in [
+        encoding::pack_i(insts::OP_ADDI, 17, 0, 93),
+        encoding::pack_i(insts::OP_ECALL, 0, 0, 0),
+    ] {
+        code.extend_from_slice(&encoding::to_riscv(i).unwrap().to_le_bytes());
+    }
+    let program = minimal_elf::<u64>(&code);
+    let build = || {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+        DefaultMachineBuilder::new(core_machine)
+            .long_instruction_factory(long_factory)
+            .build()
+    };
+
+    let mut machine = build();
+    machine.load_program(&program, &["long".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(42));
+
+    let mut machine = TraceMachine::new(build());
+    machine.load_program(&program, &["long".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(42));
+
+    #[cfg(has_asm)]
+    {
+        let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION2, u64::MAX);
+        let core = DefaultMachineBuilder::new(asm_core)
+            .long_instruction_factory(long_factory)
+            .build();
+        let mut machine = AsmMachine::new(core);
+        machine.load_program(&program, &["long".into()]).unwrap();
+        assert_eq!(machine.run(), Ok(42));
+    }
+
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&program, &["long".into()]).unwrap();
+    assert!(matches!(
+        machine.run(),
+        Err(Error::InvalidInstruction { .. })
+    ));
+}
@@ -0,0 +1,243 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::devices::clint::{Clint, CLINT_BASE, CLINT_MTIME, CLINT_MTIMECMP};
+use ckb_vm::devices::plic::{
+    Plic, PLIC_BASE, PLIC_CLAIM, PLIC_ENABLE, PLIC_PENDING, PLIC_PRIORITY, PLIC_THRESHOLD,
+};
+use ckb_vm::devices::{highest_priority_interrupt, IRQ_MACHINE_EXTERNAL, IRQ_MACHINE_TIMER};
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::memory::mmio::{MmioDevice, MmioMemory};
+use ckb_vm::registers::{A0, T0, T1};
+use ckb_vm::syscalls::{TrapAction, TrapHandler};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory,
+    SupportMachine, TraceMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_j, pack_s, pack_u, to_riscv};
+
+type DeviceMemory = MmioMemory<SparseMemory<u64>>;
+
+pub struct ExitOnInterrupt {}
+
+impl<Mac: SupportMachine> TrapHandler<Mac> for ExitOnInterrupt {
+    fn interrupt(&mut self, _machine: &mut Mac, irq: u64) -> TrapAction {
+        TrapAction::Terminate(irq as i8)
+    }
+}
+
+// Sets mtimecmp to 100, then spins until the timer interrupt.
+fn timer_program() -> Bytes {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, t0, (CLINT_BASE + CLINT_MTIMECMP) as i32),
+        pack_i(insts::OP_ADDI, t1, 0, 100),
+        pack_s(insts::OP_SD, t0, t1, 0),
+        pack_i(insts::OP_ADDI, A0 as u8, A0 as u8, 1),
+        pack_j(insts::OP_JAL, 0, -4),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+#[test]
+pub fn test_clint_timer_interrupt() {
+    let program = timer_program();
+    let core_machine = DefaultCoreMachine::<u64, DeviceMemory>::new(ISA_IMC, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .trap_handler(Box::new(ExitOnInterrupt {}))
+        .build();
+    machine
+        .memory_mut()
+        .attach(CLINT_BASE, Box::new(Clint::new(10)))
+        .unwrap();
+    machine.load_program(&program, &["timer".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(IRQ_MACHINE_TIMER as i8));
+    // mtime reaches 100 after 1000 cycles, the interrupt is taken at the end
+    // of the basic block crossing it.
+    assert!(machine.cycles() >= 1000 && machine.cycles() <= 1002);
+    let mtime = machine
+        .memory_mut()
+        .load64(&(CLINT_BASE + CLINT_MTIME))
+        .unwrap();
+    assert_eq!(mtime, 100);
+
+    let core_machine = DefaultCoreMachine::<u64, DeviceMemory>::new(ISA_IMC, VERSION2, 10_000);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .trap_handler(Box::new(ExitOnInterrupt {}))
+            .build(),
+    );
+    machine
+        .machine
+        .memory_mut()
+        .attach(CLINT_BASE, Box::new(Clint::new(10)))
+        .unwrap();
+    machine.load_program(&program, &["timer".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(IRQ_MACHINE_TIMER as i8));
+    assert!(machine.machine.cycles() >= 1000 && machine.machine.cycles() <= 1002);
+}
+
+#[test]
+pub fn test_clint_registers() {
+    let mut clint = Clint::new(4);
+    assert_eq!(clint.pending_interrupts(), 0);
+    clint.tick(41);
+    assert_eq!(clint.load(CLINT_MTIME, 8), Ok(10));
+    // 32-bit halves, as used by RV32 guests.
+    clint.store(CLINT_MTIMECMP, 4, 12).unwrap();
+    clint.store(CLINT_MTIMECMP + 4, 4, 0).unwrap();
+    assert_eq!(clint.mtimecmp(), 12);
+    assert_eq!(clint.pending_interrupts(), 0);
+    clint.tick(48);
+    assert_eq!(clint.pending_interrupts(), 1 << IRQ_MACHINE_TIMER);
+    // Writing mtime moves it relative to the cycles.
+    clint.store(CLINT_MTIME, 8, 0).unwrap();
+    assert_eq!(clint.pending_interrupts(), 0);
+    clint.tick(52);
+    assert_eq!(clint.mtime(), 1);
+
+    assert_eq!(
+        highest_priority_interrupt((1 << IRQ_MACHINE_TIMER) | (1 << IRQ_MACHINE_EXTERNAL)),
+        Some(IRQ_MACHINE_EXTERNAL)
+    );
+    assert_eq!(highest_priority_interrupt(0), None);
+
+    // Accesses to other addresses go to memory.
+    let mut memory = DeviceMemory::new_with_memory(1 << 20);
+    memory.attach(CLINT_BASE, Box::new(Clint::new(1))).unwrap();
+    assert!(memory
+        .attach(CLINT_BASE + 8, Box::new(Clint::new(1)))
+        .is_err());
+    memory.store64(&0x100, &42).unwrap();
+    assert_eq!(memory.load64(&0x100), Ok(42));
+    memory.tick(7);
+    assert_eq!(memory.load64(&(CLINT_BASE + CLINT_MTIME)), Ok(7));
+    assert_eq!(memory.pending_interrupts(), 0);
+}
+
+// Raises its interrupt line once `at` cycles are consumed, until any store
+// to it acknowledges the interrupt.
+pub struct Alarm {
+    at: u64,
+    cycles: u64,
+    acknowledged: bool,
+}
+
+impl MmioDevice for Alarm {
+    fn size(&self) -> u64 {
+        8
+    }
+
+    fn load(&mut self, _offset: u64, _size: u8) -> Result<u64, Error> {
+        Ok(self.cycles)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u8, _value: u64) -> Result<(), Error> {
+        self.acknowledged = true;
+        Ok(())
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    fn irq_line(&self) -> bool {
+        self.cycles >= self.at && !self.acknowledged
+    }
+}
+
+const ALARM_BASE: u64 = 0x1000_0000;
+
+fn alarm(at: u64) -> Box<Alarm> {
+    Box::new(Alarm {
+        at,
+        cycles: 0,
+        acknowledged: false,
+    })
+}
+
+// Claims the external interrupt, acknowledges the device and exits with the
+// claimed source.
+pub struct ClaimOnInterrupt {}
+
+impl TrapHandler<DefaultCoreMachine<u64, DeviceMemory>> for ClaimOnInterrupt {
+    fn interrupt(
+        &mut self,
+        machine: &mut DefaultCoreMachine<u64, DeviceMemory>,
+        irq: u64,
+    ) -> TrapAction {
+        assert_eq!(irq, IRQ_MACHINE_EXTERNAL);
+        let memory = machine.memory_mut();
+        let source = memory.load32(&(PLIC_BASE + PLIC_CLAIM)).unwrap();
+        memory.store64(&ALARM_BASE, &0).unwrap();
+        memory.store32(&(PLIC_BASE + PLIC_CLAIM), &source).unwrap();
+        TrapAction::Terminate(source as i8)
+    }
+}
+
+#[test]
+pub fn test_plic_routing() {
+    let mut memory = DeviceMemory::new_with_memory(1 << 20);
+    memory.attach(PLIC_BASE, Box::new(Plic::new(8))).unwrap();
+    memory.attach_source(ALARM_BASE, 3, alarm(10)).unwrap();
+    memory.attach_source(ALARM_BASE + 8, 5, alarm(20)).unwrap();
+    assert!(memory.attach_source(ALARM_BASE + 16, 3, alarm(0)).is_err());
+    assert!(memory.attach_source(ALARM_BASE + 16, 0, alarm(0)).is_err());
+    // Source 5 has the highest priority, source 3 is masked by the threshold
+    // until it is lowered.
+    memory.store32(&(PLIC_BASE + 3 * 4), &1).unwrap();
+    memory.store32(&(PLIC_BASE + 5 * 4), &2).unwrap();
+    memory
+        .store32(&(PLIC_BASE + PLIC_ENABLE), &((1 << 3) | (1 << 5)))
+        .unwrap();
+    memory.store32(&(PLIC_BASE + PLIC_THRESHOLD), &1).unwrap();
+
+    memory.tick(15);
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_PENDING)), Ok(1 << 3));
+    assert_eq!(memory.pending_interrupts(), 0);
+    memory.tick(25);
+    assert_eq!(memory.pending_interrupts(), 1 << IRQ_MACHINE_EXTERNAL);
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_CLAIM)), Ok(5));
+    assert_eq!(memory.pending_interrupts(), 0);
+    // The line is still high, but the source is not pending again before it
+    // is completed.
+    memory.tick(26);
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_PENDING)), Ok(1 << 3));
+    memory.store64(&(ALARM_BASE + 8), &0).unwrap();
+    memory.store32(&(PLIC_BASE + PLIC_CLAIM), &5).unwrap();
+    memory.tick(27);
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_PENDING)), Ok(1 << 3));
+
+    memory.store32(&(PLIC_BASE + PLIC_THRESHOLD), &0).unwrap();
+    assert_eq!(memory.pending_interrupts(), 1 << IRQ_MACHINE_EXTERNAL);
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_CLAIM)), Ok(3));
+    assert_eq!(memory.load32(&(PLIC_BASE + PLIC_CLAIM)), Ok(0));
+}
+
+#[test]
+pub fn test_plic_external_interrupt() {
+    // The timer of the program fires long after the alarm.
+    let program = timer_program();
+    let core_machine = DefaultCoreMachine::<u64, DeviceMemory>::new(ISA_IMC, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .trap_handler(Box::new(ClaimOnInterrupt {}))
+        .build();
+    let mut plic = Plic::new(4);
+    plic.store(PLIC_PRIORITY + 2 * 4, 4, 1).unwrap();
+    plic.store(PLIC_ENABLE, 4, 1 << 2).unwrap();
+    let memory = machine.memory_mut();
+    memory.attach(PLIC_BASE, Box::new(plic)).unwrap();
+    memory
+        .attach(CLINT_BASE, Box::new(Clint::new(1_000)))
+        .unwrap();
+    memory.attach_source(ALARM_BASE, 2, alarm(500)).unwrap();
+    machine.load_program(&program, &["plic".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(2));
+    assert!(machine.cycles() >= 500 && machine.cycles() <= 502);
+}
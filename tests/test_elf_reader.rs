@@ -0,0 +1,90 @@
+use ckb_vm::elf::ElfLoader;
+use ckb_vm::elf_writer::{minimal_elf, DEFAULT_LOAD_ADDRESS};
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{VERSION0, VERSION1, VERSION2};
+use ckb_vm::memory::lazy::LazyMemory;
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory,
+    SupportMachine, ISA_IMC, RISCV_PAGESIZE,
+};
+use ckb_vm_definitions::encoding::{pack_i, to_riscv};
+use std::io::Cursor;
+
+// Exits with 7.
+fn program() -> Bytes {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 7),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+#[test]
+pub fn test_elf_loader_matches_load_elf() {
+    let program = program();
+    for version in [VERSION0, VERSION1, VERSION2] {
+        let mut expected = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, version, 0);
+        let expected_bytes = expected.load_elf(&program, true).unwrap();
+
+        let mut loader = ElfLoader::new(Cursor::new(program.to_vec()), version, 64).unwrap();
+        assert_eq!(loader.load_segments().len(), 1);
+        let mut machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, version, 0);
+        assert_eq!(loader.load(&mut machine, true), Ok(expected_bytes));
+        assert_eq!(machine.pc(), expected.pc());
+        assert_eq!(*machine.pc(), loader.entry());
+        let page = RISCV_PAGESIZE as u64;
+        assert_eq!(
+            machine.memory_mut().load_bytes(DEFAULT_LOAD_ADDRESS, page),
+            expected.memory_mut().load_bytes(DEFAULT_LOAD_ADDRESS, page)
+        );
+        assert_eq!(
+            machine.memory_mut().fetch_flag(DEFAULT_LOAD_ADDRESS / page),
+            expected
+                .memory_mut()
+                .fetch_flag(DEFAULT_LOAD_ADDRESS / page)
+        );
+    }
+}
+
+#[test]
+pub fn test_elf_loader_on_demand() {
+    let loader = ElfLoader::new(Cursor::new(program().to_vec()), VERSION2, 64).unwrap();
+    let core =
+        DefaultCoreMachine::<u64, LazyMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine.update_pc(loader.entry());
+    machine.commit_pc();
+    machine.memory_mut().set_fault_handler(Box::new(loader));
+    assert!(!machine.memory().is_mapped(DEFAULT_LOAD_ADDRESS));
+    assert_eq!(machine.run(), Ok(7));
+    assert!(machine.memory().is_mapped(DEFAULT_LOAD_ADDRESS));
+    assert_eq!(machine.memory().faults(), 1);
+    // Pages outside of the segments read as zero.
+    assert_eq!(machine.memory_mut().load64(&0x40000), Ok(0));
+}
+
+#[test]
+pub fn test_elf_loader_truncated() {
+    let program = program();
+    let bits = ElfLoader::new(Cursor::new(program.to_vec()), VERSION2, 32);
+    assert!(matches!(bits, Err(Error::ElfBits)));
+
+    let truncated = program[..program.len() - 4].to_vec();
+    let mut loader = ElfLoader::new(Cursor::new(truncated), VERSION2, 64).unwrap();
+    let mut machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, 0);
+    assert_eq!(
+        loader.load(&mut machine, true),
+        Err(Error::ElfSegmentAddrOrSizeError)
+    );
+
+    let headers_only = program[..64].to_vec();
+    assert!(matches!(
+        ElfLoader::new(Cursor::new(headers_only), VERSION2, 64),
+        Err(Error::ElfParseError(_))
+    ));
+}
@@ -0,0 +1,71 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::error::ErrorContext;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A7};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Register, SparseMemory,
+    TraceMachine, ISA_IMC, RISCV_MAX_MEMORY,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_u, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+// Loads from right past the end of memory.
+fn build(error_context: bool, max_cycles: u64) -> Machine {
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, A0 as u8, RISCV_MAX_MEMORY as i32),
+        pack_i(insts::OP_LD_VERSION1, A1 as u8, A0 as u8, 8),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, max_cycles);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .error_context(error_context)
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["error".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_error_context() {
+    let mut machine = build(true, 100);
+    let entry = machine.pc().to_u64();
+    let error = machine.run().unwrap_err();
+    assert_eq!(error.root(), &Error::MemOutOfBound);
+    let expected = ErrorContext {
+        pc: entry + 4,
+        opcode: insts::OP_LD_VERSION1,
+        address: Some(RISCV_MAX_MEMORY as u64 + 8),
+        cycles: 2,
+    };
+    assert_eq!(error.context(), Some(&expected));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "memory error: out of bound at pc=0x{:x} opcode=ld_version1 address=0x{:x} cycles=2",
+            entry + 4,
+            RISCV_MAX_MEMORY + 8
+        )
+    );
+
+    let mut machine = TraceMachine::new(build(true, 100));
+    assert_eq!(machine.run().unwrap_err().context(), Some(&expected));
+}
+
+#[test]
+pub fn test_error_context_disabled() {
+    let mut machine = build(false, 100);
+    assert_eq!(machine.run(), Err(Error::MemOutOfBound));
+
+    // Exceeding max cycles suspends the run, it keeps its bare error.
+    let mut machine = build(true, 1);
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+}
@@ -0,0 +1,261 @@
+use ckb_vm::float::{
+    DiffFloat, FloatBackend, FloatMode, HostFloat, IntFormat, Precision, RoundingMode, SoftFloat,
+    CANONICAL_NAN_32, CANONICAL_NAN_64, FLAG_DIVIDE_BY_ZERO, FLAG_INEXACT, FLAG_INVALID,
+    FLAG_OVERFLOW, FLAG_UNDERFLOW,
+};
+
+const RNE: RoundingMode = RoundingMode::NearestEven;
+
+fn d(value: f64) -> u64 {
+    value.to_bits()
+}
+
+fn s(value: f32) -> u64 {
+    u64::from(value.to_bits())
+}
+
+#[test]
+fn test_soft_float_arithmetic() {
+    let p = Precision::Double;
+    let mut f = SoftFloat;
+    let mut flags = 0;
+    assert_eq!(f.add(p, d(1.0), d(2.0), RNE, &mut flags), d(3.0));
+    assert_eq!(flags, 0);
+    assert_eq!(f.add(p, d(0.1), d(0.2), RNE, &mut flags), d(0.1 + 0.2));
+    assert_eq!(flags, FLAG_INEXACT);
+
+    let mut flags = 0;
+    assert_eq!(f.div(p, d(1.0), d(0.0), RNE, &mut flags), d(f64::INFINITY));
+    assert_eq!(flags, FLAG_DIVIDE_BY_ZERO);
+    let mut flags = 0;
+    assert_eq!(f.div(p, d(0.0), d(0.0), RNE, &mut flags), CANONICAL_NAN_64);
+    assert_eq!(flags, FLAG_INVALID);
+
+    let mut flags = 0;
+    assert_eq!(f.sqrt(p, d(2.0), RNE, &mut flags), d(2f64.sqrt()));
+    assert_eq!(flags, FLAG_INEXACT);
+    let mut flags = 0;
+    assert_eq!(f.sqrt(p, d(-0.0), RNE, &mut flags), d(-0.0));
+    assert_eq!(
+        f.sub(p, d(1.0), d(1.0), RoundingMode::Down, &mut flags),
+        d(-0.0)
+    );
+    assert_eq!(flags, 0);
+
+    // Signaling NaNs raise invalid and give the canonical NaN.
+    let mut flags = 0;
+    assert_eq!(
+        f.mul(p, 0x7ff0_0000_0000_0001, d(1.0), RNE, &mut flags),
+        CANONICAL_NAN_64
+    );
+    assert_eq!(flags, FLAG_INVALID);
+    // Inf * 0 is invalid even with a quiet NaN addend.
+    let mut flags = 0;
+    assert_eq!(
+        f.fma(
+            p,
+            d(f64::INFINITY),
+            d(0.0),
+            CANONICAL_NAN_64,
+            RNE,
+            &mut flags
+        ),
+        CANONICAL_NAN_64
+    );
+    assert_eq!(flags, FLAG_INVALID);
+
+    let p = Precision::Single;
+    let mut flags = 0;
+    assert_eq!(f.mul(p, s(1.5), s(-4.0), RNE, &mut flags), s(-6.0));
+    assert_eq!(f.fma(p, s(2.0), s(3.0), s(1.0), RNE, &mut flags), s(7.0));
+    assert_eq!(flags, 0);
+}
+
+#[test]
+fn test_soft_float_rounding_and_exceptions() {
+    let p = Precision::Single;
+    let mut f = SoftFloat;
+    let mut flags = 0;
+    let down = f.div(p, s(1.0), s(3.0), RoundingMode::TowardZero, &mut flags);
+    let up = f.div(p, s(1.0), s(3.0), RoundingMode::Up, &mut flags);
+    assert_eq!(up, down + 1);
+    assert_eq!(up, s(1.0 / 3.0));
+    let negative = f.div(p, s(-1.0), s(3.0), RoundingMode::Down, &mut flags);
+    assert_eq!(negative, up | 0x8000_0000);
+
+    // Overflow rounds to the largest finite value toward zero.
+    let p = Precision::Double;
+    let mut flags = 0;
+    assert_eq!(
+        f.mul(p, d(f64::MAX), d(2.0), RoundingMode::TowardZero, &mut flags),
+        d(f64::MAX)
+    );
+    assert_eq!(flags, FLAG_OVERFLOW | FLAG_INEXACT);
+    let mut flags = 0;
+    assert_eq!(
+        f.add(p, d(f64::MAX), d(f64::MAX), RNE, &mut flags),
+        d(f64::INFINITY)
+    );
+    assert_eq!(flags, FLAG_OVERFLOW | FLAG_INEXACT);
+
+    // Half of the smallest subnormal rounds to even, to 0.
+    let mut flags = 0;
+    assert_eq!(f.mul(p, 1, d(0.5), RNE, &mut flags), 0);
+    assert_eq!(flags, FLAG_UNDERFLOW | FLAG_INEXACT);
+    // Exact subnormal results don't underflow.
+    let mut flags = 0;
+    assert_eq!(f.mul(p, 2, d(0.5), RNE, &mut flags), 1);
+    assert_eq!(flags, 0);
+    // Tininess is detected after rounding.
+    let mut flags = 0;
+    let below_min_normal = d(f64::MIN_POSITIVE) - 1;
+    assert_eq!(
+        f.mul(
+            p,
+            below_min_normal,
+            d(1.0 + f64::EPSILON),
+            RoundingMode::Up,
+            &mut flags
+        ),
+        d(f64::MIN_POSITIVE)
+    );
+    assert_eq!(flags, FLAG_INEXACT);
+}
+
+#[test]
+fn test_soft_float_conversions_and_comparisons() {
+    let p = Precision::Double;
+    let mut f = SoftFloat;
+    let mut flags = 0;
+    assert_eq!(f.float_to_int(p, d(2.5), IntFormat::W, RNE, &mut flags), 2);
+    assert_eq!(
+        f.float_to_int(
+            p,
+            d(2.5),
+            IntFormat::L,
+            RoundingMode::NearestMaxMagnitude,
+            &mut flags
+        ),
+        3
+    );
+    assert_eq!(
+        f.float_to_int(p, d(-2.5), IntFormat::W, RoundingMode::Down, &mut flags),
+        (-3i64) as u64
+    );
+    assert_eq!(flags, FLAG_INEXACT);
+    let mut flags = 0;
+    assert_eq!(
+        f.float_to_int(p, CANONICAL_NAN_64, IntFormat::Wu, RNE, &mut flags),
+        u64::MAX
+    );
+    assert_eq!(
+        f.float_to_int(p, d(-1.0), IntFormat::Lu, RNE, &mut flags),
+        0
+    );
+    assert_eq!(
+        f.float_to_int(p, d(1e10), IntFormat::W, RNE, &mut flags),
+        i32::MAX as u64
+    );
+    assert_eq!(flags, FLAG_INVALID);
+
+    let mut flags = 0;
+    assert_eq!(
+        f.int_to_float(p, u64::MAX, IntFormat::Lu, RNE, &mut flags),
+        d(18446744073709551616.0)
+    );
+    assert_eq!(flags, FLAG_INEXACT);
+    assert_eq!(
+        f.int_to_float(Precision::Single, u64::MAX, IntFormat::W, RNE, &mut flags),
+        s(-1.0)
+    );
+    let mut flags = 0;
+    assert_eq!(
+        f.convert(p, Precision::Single, d(0.1), RNE, &mut flags),
+        s(0.1)
+    );
+    assert_eq!(flags, FLAG_INEXACT);
+    assert_eq!(
+        f.convert(Precision::Single, p, 0x7f80_0001, RNE, &mut flags),
+        CANONICAL_NAN_64
+    );
+    assert_eq!(
+        f.convert(p, Precision::Single, CANONICAL_NAN_64, RNE, &mut flags),
+        CANONICAL_NAN_32
+    );
+
+    let mut flags = 0;
+    assert_eq!(f.fmin(p, d(-0.0), d(0.0), &mut flags), d(-0.0));
+    assert_eq!(f.fmax(p, d(-0.0), d(0.0), &mut flags), d(0.0));
+    assert_eq!(f.fmin(p, CANONICAL_NAN_64, d(1.0), &mut flags), d(1.0));
+    assert!(f.feq(p, d(-0.0), d(0.0), &mut flags));
+    assert!(!f.feq(p, CANONICAL_NAN_64, CANONICAL_NAN_64, &mut flags));
+    assert_eq!(flags, 0);
+    assert!(!f.flt(p, CANONICAL_NAN_64, d(1.0), &mut flags));
+    assert_eq!(flags, FLAG_INVALID);
+    assert!(f.fle(p, d(-1.0), d(-1.0), &mut flags));
+
+    assert_eq!(f.classify(p, d(f64::NEG_INFINITY)), 1 << 0);
+    assert_eq!(f.classify(p, d(-0.0)), 1 << 3);
+    assert_eq!(f.classify(p, 1), 1 << 5);
+    assert_eq!(f.classify(p, d(1.0)), 1 << 6);
+    assert_eq!(f.classify(p, 0x7ff0_0000_0000_0001), 1 << 8);
+    assert_eq!(f.classify(p, CANONICAL_NAN_64), 1 << 9);
+}
+
+// Values covering all exponents, with special values and short fractions
+// giving exact results more often.
+fn operand(p: Precision, state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    let bits = match *state % 8 {
+        0 => [0, 1, 0x7ff0_0000_0000_0000, 0x3ff0_0000_0000_0000][(*state >> 8) as usize % 4],
+        1 => *state & 0xffff_f000_0000_0000,
+        _ => *state,
+    };
+    match p {
+        Precision::Single => (bits >> 32) as u32 as u64,
+        Precision::Double => bits,
+    }
+}
+
+#[test]
+fn test_differential_soft_host() {
+    let mut diff = DiffFloat::new(SoftFloat, HostFloat);
+    let mut state = 0x2545_f491_4f6c_dd1d;
+    let mut flags = 0;
+    for &p in &[Precision::Single, Precision::Double] {
+        for _ in 0..20000 {
+            let a = operand(p, &mut state);
+            let b = operand(p, &mut state);
+            let c = operand(p, &mut state);
+            diff.add(p, a, b, RNE, &mut flags);
+            diff.sub(p, a, b, RNE, &mut flags);
+            diff.mul(p, a, b, RNE, &mut flags);
+            diff.div(p, a, b, RNE, &mut flags);
+            diff.sqrt(p, a, RNE, &mut flags);
+            diff.fma(p, a, b, c, RNE, &mut flags);
+        }
+    }
+    // Results always agree, flags may only differ as HostFloat documents.
+    for mismatch in diff.take_mismatches() {
+        assert_eq!(mismatch.expected.0, mismatch.actual.0, "{:?}", mismatch);
+        let differing = mismatch.expected.1 ^ mismatch.actual.1;
+        assert_eq!(
+            differing & !(FLAG_INEXACT | FLAG_UNDERFLOW),
+            0,
+            "{:?}",
+            mismatch
+        );
+    }
+    assert!(diff.mismatches().is_empty());
+
+    // Other rounding modes run on SoftFloat.
+    let mut host = FloatMode::Host.backend();
+    let mut flags = 0;
+    let p = Precision::Single;
+    assert_eq!(
+        host.div(p, s(1.0), s(3.0), RoundingMode::Up, &mut flags),
+        s(1.0 / 3.0)
+    );
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
+use ckb_vm::fuzzing::{MachineState, MemoryImage, RegisterFile};
+use ckb_vm::isa::Isa;
+use ckb_vm::registers::SP;
+use ckb_vm::{
+    CoreMachine, MachineConfig, Memory, Register, SparseMemory, SupportMachine, RISCV_MAX_MEMORY,
+    RISCV_PAGESIZE,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn inputs() -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..64)
+        .map(|i| {
+            let mut data = vec![0u8; 256 + i * 512];
+            rng.fill(&mut data[..]);
+            data
+        })
+        .collect()
+}
+
+#[test]
+pub fn test_arbitrary_registers() {
+    for data in inputs() {
+        let mut u = Unstructured::new(&data);
+        let registers = RegisterFile::<u64>::arbitrary(&mut u).unwrap();
+        assert_eq!(registers.0.len(), 32);
+        assert_eq!(registers.0[0], 0);
+    }
+}
+
+#[test]
+pub fn test_arbitrary_memory_image() {
+    for data in inputs() {
+        let mut u = Unstructured::new(&data);
+        let image = MemoryImage::arbitrary(&mut u).unwrap();
+        let mut memory = SparseMemory::<u64>::new_with_memory(RISCV_MAX_MEMORY);
+        image.apply(&mut memory).unwrap();
+        for page in &image.pages {
+            assert_eq!(page.address % RISCV_PAGESIZE as u64, 0);
+            assert!(page.data.len() <= RISCV_PAGESIZE);
+            let loaded = memory
+                .load_bytes(page.address, page.data.len() as u64)
+                .unwrap();
+            assert_eq!(&loaded[..], &page.data[..]);
+        }
+    }
+}
+
+#[test]
+pub fn test_arbitrary_config() {
+    for data in inputs() {
+        let mut u = Unstructured::new(&data);
+        let config = MachineConfig::arbitrary(&mut u).unwrap();
+        assert!(Isa::new(config.isa.mask(), config.version).is_ok());
+        assert!(config.checked_isa().is_ok());
+        assert!(config.memory_size <= RISCV_MAX_MEMORY);
+    }
+}
+
+#[test]
+pub fn test_arbitrary_machine_state() {
+    for data in inputs() {
+        let mut u = Unstructured::new(&data);
+        let mut state = MachineState::<u64>::arbitrary(&mut u).unwrap();
+        state.config.max_cycles = 10_000;
+        state.cycles = 0;
+        let pc = state.pc;
+        let sp = state.registers.0[SP];
+        let memory_size = state.config.memory_size as u64;
+        let mut machine = state.build::<SparseMemory<u64>>().unwrap();
+        assert_eq!(machine.pc().to_u64(), pc);
+        assert!(pc < memory_size);
+        assert_eq!(machine.registers()[SP].to_u64(), sp);
+        assert!(sp <= memory_size);
+        // Any outcome is fine, as long as the machine does not panic.
+        let _ = machine.run();
+        assert!(machine.cycles() <= 10_000);
+    }
+}
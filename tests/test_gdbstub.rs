@@ -0,0 +1,113 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::gdbstub::GdbStub;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Register, SparseMemory,
+    WXorXMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, to_riscv};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// Sends `packet`, and returns the reply of the stub.
+fn request(stream: &mut TcpStream, packet: &str) -> String {
+    let sum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(stream, "${}#{:02x}", packet, sum).unwrap();
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'+');
+    if packet == "k" {
+        return String::new();
+    }
+    stream.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'$');
+    let mut reply = Vec::new();
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'#' {
+            break;
+        }
+        reply.push(byte[0]);
+    }
+    let mut sum = [0u8; 2];
+    stream.read_exact(&mut sum).unwrap();
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(reply).unwrap()
+}
+
+fn hex_u64(value: u64) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[test]
+pub fn test_gdbstub_session() {
+    let a0 = A0 as u8;
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, a0, 0, 5),
+        pack_i(insts::OP_ADDI, a0, a0, 1),
+        pack_i(insts::OP_ADDI, a0, a0, 1),
+        pack_i(insts::OP_ADDI, a0, a0, 1),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program: Bytes = minimal_elf::<u64>(&code);
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&program, &["gdb".into()]).unwrap();
+    let entry = machine.pc().to_u64();
+    let stack = machine.registers()[ckb_vm::registers::SP].to_u64() - 16;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut replies = vec![];
+        for packet in [
+            "qSupported:swbreak+".to_string(),
+            "?".to_string(),
+            format!("Z0,{:x},4", entry + 8),
+            "c".to_string(),
+            "p20".to_string(),
+            "pa".to_string(),
+            format!("Pa={}", hex_u64(10)),
+            format!("M{:x},4:deadbeef", stack),
+            format!("m{:x},4", stack),
+            "s".to_string(),
+            "p20".to_string(),
+            "c".to_string(),
+            "k".to_string(),
+        ] {
+            replies.push(request(&mut stream, &packet));
+        }
+        replies
+    });
+    let exit_code = GdbStub::new(&mut machine).serve(&listener).unwrap();
+    let replies = client.join().unwrap();
+
+    assert_eq!(replies[0], "PacketSize=4000");
+    assert_eq!(replies[1], "S05");
+    assert_eq!(replies[2], "OK");
+    // Stops before the breakpoint is executed.
+    assert_eq!(replies[3], "S05");
+    assert_eq!(replies[4], hex_u64(entry + 8));
+    assert_eq!(replies[5], hex_u64(6));
+    assert_eq!(replies[6], "OK");
+    assert_eq!(replies[7], "OK");
+    assert_eq!(replies[8], "deadbeef");
+    assert_eq!(replies[9], "S05");
+    assert_eq!(replies[10], hex_u64(entry + 12));
+    // The program exits with a0 = 10 + 1 + 1.
+    assert_eq!(replies[11], "W0c");
+    assert_eq!(exit_code, Some(12));
+}
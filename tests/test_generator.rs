@@ -0,0 +1,59 @@
+use ckb_vm::generator::{generate_program_from_seed, GeneratorOptions};
+use ckb_vm::machine::cosim::{run_lockstep, LockstepOptions, MachineReference};
+use ckb_vm::machine::{VERSION1, VERSION2};
+use ckb_vm::{
+    run, DefaultCoreMachine, DefaultMachineBuilder, FlatMemory, SparseMemory, SupportMachine,
+    ISA_A, ISA_B, ISA_IMC, RISCV_MAX_MEMORY,
+};
+
+#[test]
+pub fn test_generated_programs_exit() {
+    let options = GeneratorOptions::default();
+    for seed in 0..64 {
+        let program = generate_program_from_seed::<u64>(seed, &options);
+        let result = run::<u64, SparseMemory<u64>>(&program, &["fuzz".into()], RISCV_MAX_MEMORY);
+        assert_eq!(result, Ok(0), "seed {}", seed);
+        let program = generate_program_from_seed::<u32>(seed, &options);
+        let result = run::<u32, SparseMemory<u32>>(&program, &["fuzz".into()], RISCV_MAX_MEMORY);
+        assert_eq!(result, Ok(0), "seed {}", seed);
+    }
+}
+
+#[test]
+pub fn test_generated_programs_are_reproducible() {
+    let options = GeneratorOptions {
+        compressed: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        generate_program_from_seed::<u64>(7, &options),
+        generate_program_from_seed::<u64>(7, &options)
+    );
+    assert_ne!(
+        generate_program_from_seed::<u64>(7, &options),
+        generate_program_from_seed::<u64>(8, &options)
+    );
+}
+
+#[test]
+pub fn test_generated_programs_lockstep() {
+    let options = GeneratorOptions::default();
+    let isa = ISA_IMC | ISA_A | ISA_B;
+    for seed in 0..16 {
+        let program = generate_program_from_seed::<u64>(seed, &options);
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(isa, VERSION2, u64::MAX);
+        let mut machine = DefaultMachineBuilder::new(core_machine).build();
+        machine.load_program(&program, &["fuzz".into()]).unwrap();
+
+        let core_machine = DefaultCoreMachine::<u64, FlatMemory<u64>>::new(isa, VERSION1, u64::MAX);
+        let mut reference = DefaultMachineBuilder::new(core_machine).build();
+        reference.load_program(&program, &["fuzz".into()]).unwrap();
+        let mut reference = MachineReference::new(reference);
+
+        let divergence = run_lockstep(&mut machine, &mut reference, &LockstepOptions::default());
+        assert_eq!(divergence, Ok(None), "seed {}", seed);
+        assert!(!machine.running());
+        assert_eq!(machine.exit_code(), 0);
+    }
+}
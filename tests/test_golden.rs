@@ -0,0 +1,53 @@
+use ckb_vm::golden::{check_golden, run_golden_case, GoldenCase, GoldenMode, GoldenState};
+use ckb_vm::machine::{VERSION0, VERSION2};
+use std::fs;
+
+fn cases() -> Vec<GoldenCase> {
+    ["simple64", "mulw64", "amo_compare", "invalid_read64"]
+        .iter()
+        .map(|name| {
+            let program = fs::read(format!("tests/programs/{}", name)).unwrap().into();
+            GoldenCase::new(name, program)
+        })
+        .collect()
+}
+
+#[test]
+pub fn test_golden_state_format() {
+    let state = run_golden_case::<u64>(&cases()[0], VERSION2).unwrap();
+    assert_eq!(state.result, "exit 0");
+    let text = format!("{}\n{}", state, state);
+    assert_eq!(
+        GoldenState::parse_all(&text).unwrap(),
+        vec![state.clone(), state]
+    );
+    assert!(GoldenState::parse_all("x32 0x0").is_err());
+
+    // VERSION0 fails to load amo_compare, which is recorded as its outcome.
+    let state = run_golden_case::<u64>(&cases()[2], VERSION0).unwrap();
+    assert!(state.result.starts_with("load error: "), "{}", state.result);
+}
+
+#[test]
+pub fn test_golden_update_and_check() {
+    let dir = std::env::temp_dir().join(format!("ckb-vm-golden-{}", std::process::id()));
+    let cases = cases();
+    assert!(check_golden::<u64>(&dir, &cases, GoldenMode::Check).is_err());
+    let mismatches = check_golden::<u64>(&dir, &cases, GoldenMode::Update).unwrap();
+    assert!(mismatches.is_empty());
+    let mismatches = check_golden::<u64>(&dir, &cases, GoldenMode::Check).unwrap();
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+
+    // Tamper with the recorded cycles of simple64 under VERSION0.
+    let path = dir.join("simple64.golden");
+    let mut states = GoldenState::parse_all(&fs::read_to_string(&path).unwrap()).unwrap();
+    states[0].cycles += 1;
+    let text: Vec<String> = states.iter().map(|s| s.to_string()).collect();
+    fs::write(&path, text.join("\n")).unwrap();
+    let mismatches = check_golden::<u64>(&dir, &cases, GoldenMode::Check).unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].case, "simple64");
+    assert_eq!(mismatches[0].version, VERSION0);
+    assert!(mismatches[0].to_string().contains("cycles"));
+    fs::remove_dir_all(&dir).unwrap();
+}
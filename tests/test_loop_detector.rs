@@ -0,0 +1,78 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::loops::LoopDetectorOptions;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7, T0, T1};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Register, SparseMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, pack_u, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load(code: &[Instruction]) -> Machine {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .loop_detector(LoopDetectorOptions {
+            window: 1000,
+            max_pcs: 8,
+        })
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["loop".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_loop_detector_spin() {
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 1),
+        pack_i(insts::OP_ADDI, T1 as u8, 0, 3),
+        pack_b(insts::OP_BEQ, 0, 0, 0),
+    ]);
+    let entry = machine.pc().to_u64();
+    assert_eq!(
+        machine.run(),
+        Err(Error::InfiniteLoop {
+            start: entry + 8,
+            end: entry + 8,
+            iterations: 1000,
+        })
+    );
+}
+
+#[test]
+pub fn test_loop_detector_no_progress() {
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 1),
+        pack_i(insts::OP_ADDI, T1 as u8, T1 as u8, 0),
+        pack_b(insts::OP_BNE, T0 as u8, 0, -4),
+    ]);
+    let entry = machine.pc().to_u64();
+    match machine.run() {
+        Err(Error::InfiniteLoop { start, end, .. }) => {
+            assert_eq!((start, end), (entry + 4, entry + 8));
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+pub fn test_loop_detector_counting_loop() {
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 0),
+        pack_u(insts::OP_LUI, T1 as u8, 0x10000),
+        pack_i(insts::OP_ADDI, T0 as u8, T0 as u8, 1),
+        pack_b(insts::OP_BNE, T0 as u8, T1 as u8, -4),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]);
+    assert_eq!(machine.run(), Ok(0));
+}
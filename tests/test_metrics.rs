@@ -0,0 +1,202 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{VERSION0, VERSION2};
+use ckb_vm::registers::{A0, A7, RA, T0};
+use ckb_vm::{
+    DefaultCoreMachine, DefaultMachineBuilder, MetricsSink, SparseMemory, SupportMachine,
+    TraceMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_j, pack_r, pack_u, to_riscv};
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+pub struct Counters {
+    executions: u64,
+    cycles: u64,
+    hits: u64,
+    misses: u64,
+    jalr_hits: u64,
+    suspensions: u64,
+    errors: Vec<&'static str>,
+}
+
+pub struct CountingSink {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl MetricsSink for CountingSink {
+    fn execution(&mut self, cycles: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.executions += 1;
+        counters.cycles += cycles;
+    }
+
+    fn trace_cache_hit(&mut self) {
+        self.counters.lock().unwrap().hits += 1;
+    }
+
+    fn trace_cache_miss(&mut self) {
+        self.counters.lock().unwrap().misses += 1;
+    }
+
+    fn jalr_cache_hit(&mut self) {
+        self.counters.lock().unwrap().jalr_hits += 1;
+    }
+
+    fn suspension(&mut self) {
+        self.counters.lock().unwrap().suspensions += 1;
+    }
+
+    fn error(&mut self, kind: &'static str) {
+        self.counters.lock().unwrap().errors.push(kind);
+    }
+}
+
+#[test]
+pub fn test_metrics_sink_trace_machine() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let counters = Arc::new(Mutex::new(Counters::default()));
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .metrics_sink(Box::new(CountingSink {
+                counters: counters.clone(),
+            }))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+
+    let counters = counters.lock().unwrap();
+    assert_eq!(counters.executions, 1);
+    assert_eq!(counters.cycles, 708);
+    assert!(counters.misses > 0);
+    assert!(counters.hits > 0);
+    assert_eq!(counters.suspensions, 0);
+    assert!(counters.errors.is_empty());
+}
+
+#[test]
+pub fn test_metrics_sink_suspension() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let counters = Arc::new(Mutex::new(Counters::default()));
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 700);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .metrics_sink(Box::new(CountingSink {
+            counters: counters.clone(),
+        }))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert!(machine.run().is_err());
+
+    let counters = counters.lock().unwrap();
+    assert_eq!(counters.executions, 1);
+    assert_eq!(counters.suspensions, 1);
+    assert!(counters.errors.is_empty());
+}
+
+#[test]
+pub fn test_metrics_sink_jalr_cache() {
+    let (t0, ra) = (T0 as u8, RA as u8);
+    // Calls a function returning with JALR 10 times.
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 10),
+        pack_j(insts::OP_JAL, ra, 16),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_b(insts::OP_BNE, t0, 0, -8),
+        pack_j(insts::OP_JAL, 0, 12),
+        pack_i(insts::OP_JALR_VERSION1, 0, ra, 0),
+        pack_i(insts::OP_ADDI, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let counters = Arc::new(Mutex::new(Counters::default()));
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .metrics_sink(Box::new(CountingSink {
+                counters: counters.clone(),
+            }))
+            .build(),
+    );
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &vec!["jalr".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+
+    let counters = counters.lock().unwrap();
+    // The first return resolves the target, the other 9 hit the cache.
+    assert_eq!(counters.jalr_hits, 9);
+    assert!(counters.hits >= counters.jalr_hits);
+}
+
+#[test]
+pub fn test_trace_relayout() {
+    let (t0, ra) = (T0 as u8, RA as u8);
+    // Same as above, with enough calls for the trace cache to be laid out
+    // again several times.
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, t0, 0x10000),
+        pack_j(insts::OP_JAL, ra, 16),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_b(insts::OP_BNE, t0, 0, -8),
+        pack_j(insts::OP_JAL, 0, 12),
+        pack_i(insts::OP_JALR_VERSION1, 0, ra, 0),
+        pack_i(insts::OP_ADDI, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A0 as u8, t0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let counters = Arc::new(Mutex::new(Counters::default()));
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .metrics_sink(Box::new(CountingSink {
+                counters: counters.clone(),
+            }))
+            .build(),
+    );
+    machine
+        .load_program(&program, &vec!["jalr".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut interpreter = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    interpreter
+        .load_program(&program, &vec!["jalr".into()])
+        .unwrap();
+    assert_eq!(interpreter.run(), Ok(0));
+    assert_eq!(machine.machine.cycles(), interpreter.cycles());
+
+    let counters = counters.lock().unwrap();
+    // Moved traces are still found, only the JALR cache is dropped at each
+    // relayout.
+    assert!(counters.misses <= 8);
+    assert!(counters.jalr_hits >= 0x10000 - 8);
+}
@@ -0,0 +1,46 @@
+use ckb_vm::machine::VERSION2;
+use ckb_vm::microbench::{
+    loop_program, run_microbench, run_microbenches, Backend, BenchClass, MicrobenchOptions,
+};
+use ckb_vm::{DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, ISA_B, ISA_IMC};
+
+#[test]
+pub fn test_loop_program_retired_instructions() {
+    let body = [BenchClass::Alu.instruction(); 4];
+    let (program, retired) = loop_program(&body, 10).unwrap();
+    assert_eq!(retired, 4 + 10 * 6 + 3);
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC | ISA_B, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&program, &["main".into()]).unwrap();
+    let report = machine.run_with_report(false);
+    assert_eq!(report.result(), Ok(0));
+    assert_eq!(report.instructions_retired, retired);
+
+    assert!(loop_program(&body, 0).is_err());
+    assert!(loop_program(&body, u32::MAX).is_err());
+}
+
+#[test]
+pub fn test_microbenches() {
+    for backend in [Backend::Interpreter, Backend::Trace] {
+        let options = MicrobenchOptions {
+            backend,
+            iterations: 100,
+            ..Default::default()
+        };
+        let results = run_microbenches(&options).unwrap();
+        assert_eq!(results.len(), BenchClass::ALL.len());
+        for result in results {
+            assert_eq!(result.instructions, 4 + 100 * 18 + 3);
+            assert!(result.cycles >= result.instructions);
+        }
+    }
+    let options = MicrobenchOptions {
+        iterations: 10,
+        cost_model: |_| 1,
+        ..Default::default()
+    };
+    let result = run_microbench(BenchClass::Divide, &options).unwrap();
+    assert_eq!(result.cycles, result.instructions);
+}
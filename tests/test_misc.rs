@@ -1,16 +1,52 @@
+use ckb_vm::chrome_trace::{ChromeTracer, TraceClock};
 use ckb_vm::cost_model::constant_cycles;
-use ckb_vm::machine::VERSION0;
-use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{
+    blank_instruction, execute_instruction, insts, HandlerTable, Instruction, Utype,
+};
+use ckb_vm::machine::arena::{Arena, TraceCacheConfig, TraceEviction};
+use ckb_vm::machine::artifact::{
+    decode_artifact, encode_artifact, ArtifactCache, ArtifactKey, DirectoryArtifactCache,
+};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::AsmMachine;
+use ckb_vm::machine::budget::CyclesBudget;
+use ckb_vm::machine::cache::{Cache, CacheConfig, CacheSimulator};
+use ckb_vm::machine::call::{find_symbol, CallArg};
+use ckb_vm::machine::callgrind::CallgrindProfiler;
+use ckb_vm::machine::compare::{compare_machines, Difference};
+use ckb_vm::machine::instrumented::{
+    InstrumentHandler, InstrumentedMachine, MemoryAccess, MemoryAccessKind,
+};
+use ckb_vm::machine::layout::AddressSpaceLayout;
+use ckb_vm::machine::policy::{AllowList, DenyList, LoadPolicy};
+use ckb_vm::machine::symbols::Symbols;
+use ckb_vm::machine::trace::TraceStats;
+use ckb_vm::machine::{VERSION0, VERSION1};
+use ckb_vm::memory::flat::MappedFlatMemory;
+use ckb_vm::memory::lazy::{LazyMemory, PageContent};
+use ckb_vm::memory::metered::MeteredMemory;
+use ckb_vm::memory::paged::PagedMemory;
+use ckb_vm::memory::quota::QuotaMemory;
+use ckb_vm::memory::region::RegionMemory;
+use ckb_vm::memory::shared::{PagePool, SharedMemory};
+use ckb_vm::memory::subpage::SubPageMemory;
+use ckb_vm::memory::zeroed::ZeroedBuffer;
+use ckb_vm::memory::{Pod, FLAG_EXECUTABLE, FLAG_FREEZED};
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7, RA, SP, T0, T1, T2};
+use ckb_vm::syscalls::{InvalidInstructionAction, TrapAction, TrapHandler};
 use ckb_vm::{
-    run, CoreMachine, Debugger, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory,
-    Memory, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory, ISA_IMC,
-    RISCV_MAX_MEMORY, RISCV_PAGESIZE,
+    run, Bytes, CoreMachine, Debugger, DefaultCoreMachine, DefaultMachineBuilder, Error, Event,
+    EventObserver, FlatMemory, Memory, Register, SparseMemory, SupportMachine, Syscalls,
+    TraceMachine, WXorXMemory, ISA_IMC, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
 };
 #[cfg(has_asm)]
 use ckb_vm_definitions::asm::AsmCoreMachine;
+use ckb_vm_definitions::encoding::{pack_i, pack_s, pack_u, to_riscv};
 use rand::{thread_rng, Rng};
 use std::fs;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 
 #[test]
@@ -68,6 +104,55 @@ pub fn test_custom_syscall() {
     assert_eq!(result.unwrap(), 39);
 }
 
+pub struct ConstantSyscall {
+    value: u64,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ConstantSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1111 {
+            return Ok(false);
+        }
+        machine.set_register(A0, Mac::REG::from_u64(self.value));
+        Ok(true)
+    }
+
+    fn name(&self) -> &'static str {
+        "constant"
+    }
+}
+
+#[test]
+pub fn test_syscall_priority() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let builder = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(CustomSyscall {}))
+        .syscall_with_priority(-1, Box::new(ConstantSyscall { value: 1 }))
+        .syscall_with_priority(10, Box::new(ConstantSyscall { value: 42 }));
+    let order: Vec<_> = builder
+        .dispatch_order()
+        .iter()
+        .map(|info| (info.priority, info.name))
+        .collect();
+    assert_eq!(order[0], (10, "constant"));
+    assert_eq!(order[1].0, 0);
+    assert!(order[1].1.ends_with("CustomSyscall"));
+    assert_eq!(order[2], (-1, "constant"));
+
+    let mut machine = builder.build();
+    assert_eq!(machine.dispatch_order().len(), 3);
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(42));
+}
+
 pub struct CustomDebugger {
     pub value: Arc<AtomicU8>,
 }
@@ -230,6 +315,97 @@ fn assert_memory_store_empty_bytes<M: Memory>(memory: &mut M) {
     assert!(memory.store_bytes(0, &[]).is_ok());
 }
 
+#[test]
+pub fn test_memory_typed_accessors() {
+    assert_memory_typed_accessors(&mut FlatMemory::<u64>::new());
+    assert_memory_typed_accessors(&mut SparseMemory::<u64>::new());
+    assert_memory_typed_accessors(&mut WXorXMemory::<FlatMemory<u64>>::new());
+    #[cfg(has_asm)]
+    assert_memory_typed_accessors(&mut AsmCoreMachine::new(ISA_IMC, VERSION0, 200_000));
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PodArgs {
+    addr: u64,
+    len: u32,
+    flags: [u8; 4],
+}
+
+unsafe impl Pod for PodArgs {}
+
+fn assert_memory_typed_accessors<M: Memory>(memory: &mut M) {
+    // C string crossing a page boundary
+    let addr = RISCV_PAGESIZE as u64 - 3;
+    memory.store_bytes(addr, b"hello\0world").unwrap();
+    assert_eq!(memory.load_c_string(addr, 64).unwrap(), &b"hello"[..]);
+    assert_eq!(memory.load_c_string(addr, 5), Err(Error::MemOutOfBound));
+    let end = memory.memory_size() as u64 - 4;
+    memory.store_bytes(end, b"abcd").unwrap();
+    assert_eq!(memory.load_c_string(end, 64), Err(Error::MemOutOfBound));
+
+    // Length-prefixed slice
+    memory.store_bytes(0x100, &3u64.to_le_bytes()).unwrap();
+    memory.store_bytes(0x108, b"xyz").unwrap();
+    assert_eq!(memory.load_length_prefixed(0x100, 3).unwrap(), &b"xyz"[..]);
+    assert_eq!(
+        memory.load_length_prefixed(0x100, 2),
+        Err(Error::MemOutOfBound)
+    );
+
+    // Plain-old-data struct
+    let args = PodArgs {
+        addr: 0x1234_5678,
+        len: 42,
+        flags: [1, 2, 3, 4],
+    };
+    memory.store_pod(0x200, &args).unwrap();
+    assert_eq!(memory.load_pod::<PodArgs>(0x200), Ok(args));
+    assert_eq!(memory.load_pod::<u32>(0x208), Ok(42));
+    assert_eq!(
+        memory.load_pod::<PodArgs>(0x204),
+        Err(Error::MemUnalignedAccess)
+    );
+    assert_eq!(
+        memory.load_pod::<PodArgs>(memory.memory_size() as u64 - 8),
+        Err(Error::MemOutOfBound)
+    );
+}
+
+#[test]
+pub fn test_memory_copy_bytes() {
+    assert_memory_copy_bytes(&mut FlatMemory::<u64>::new());
+    assert_memory_copy_bytes(&mut SparseMemory::<u64>::new());
+    assert_memory_copy_bytes(&mut WXorXMemory::<FlatMemory<u64>>::new());
+    #[cfg(has_asm)]
+    assert_memory_copy_bytes(&mut AsmCoreMachine::new(ISA_IMC, VERSION0, 200_000));
+}
+
+fn assert_memory_copy_bytes<M: Memory>(memory: &mut M) {
+    let data: Vec<u8> = (0..RISCV_PAGESIZE * 2).map(|i| i as u8).collect();
+    memory.store_bytes(0x100, &data).unwrap();
+    memory
+        .copy_bytes(0x10000, 0x100, data.len() as u64)
+        .unwrap();
+    assert_eq!(
+        memory.load_bytes(0x10000, data.len() as u64).unwrap(),
+        &data[..]
+    );
+    // Overlapping ranges behave like memmove.
+    memory.copy_bytes(0x110, 0x100, 0x40).unwrap();
+    assert_eq!(memory.load_bytes(0x110, 0x40).unwrap(), &data[..0x40]);
+    assert!(memory.copy_bytes(0x100, 0x200, 0).is_ok());
+    let end = memory.memory_size() as u64;
+    assert_eq!(
+        memory.copy_bytes(0x100, end - 4, 8),
+        Err(Error::MemOutOfBound)
+    );
+    assert_eq!(
+        memory.copy_bytes(end - 4, 0x100, 8),
+        Err(Error::MemOutOfBound)
+    );
+}
+
 #[test]
 pub fn test_memory_load_bytes() {
     let mut rng = thread_rng();
@@ -425,3 +601,1654 @@ pub fn test_outofcycles_in_syscall() {
     assert_eq!(machine.cycles(), 108);
     assert_eq!(machine.registers()[A0], 39);
 }
+
+// Reads a value which only becomes available once `ready` is set.
+pub struct PendingSyscall {
+    ready: Arc<AtomicBool>,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for PendingSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1112 {
+            return Ok(false);
+        }
+        if !self.ready.load(Ordering::SeqCst) {
+            return Err(Error::SyscallRetry(1112));
+        }
+        machine.set_register(A0, Mac::REG::from_u64(42));
+        Ok(true)
+    }
+}
+
+#[test]
+pub fn test_syscall_retry() {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 1112),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    for trace in [false, true] {
+        let ready = Arc::new(AtomicBool::new(false));
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .syscall(Box::new(PendingSyscall {
+                    ready: Arc::clone(&ready),
+                }))
+                .build(),
+        );
+        machine.load_program(&program, &["retry".into()]).unwrap();
+        let entry = *machine.pc();
+        let loaded = machine.machine.cycles();
+        // Each attempt stops on the ECALL, charging only what ran before it.
+        for _ in 0..2 {
+            let result = if trace {
+                machine.run()
+            } else {
+                machine.machine.run()
+            };
+            assert_eq!(result, Err(Error::SyscallRetry(1112)));
+            assert_eq!(*machine.pc(), entry + 4);
+            assert_eq!(machine.machine.cycles(), loaded + 1);
+        }
+
+        ready.store(true, Ordering::SeqCst);
+        assert_eq!(machine.run(), Ok(42));
+        assert_eq!(machine.machine.cycles(), loaded + 4);
+    }
+}
+
+#[test]
+pub fn test_machine_dump() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine.run().unwrap();
+    let dump = machine.dump(&[(0x10000, 20)]).unwrap();
+    assert!(dump.contains("pc  : 0x"));
+    assert!(dump.contains("cycles: 708 / "));
+    assert!(dump.contains("memory 0x10000 - 0x10014:"));
+    assert!(dump.contains("00010010: "));
+    assert_eq!(dump.lines().count(), 9 + 1 + 1 + 2);
+}
+
+#[test]
+pub fn test_hexdump() {
+    let dump = ckb_vm::memory::hexdump(0x100, b"\x7fELF\x02\x01\x01\x00abcdefgh0");
+    assert_eq!(
+        dump,
+        "00000100: 7f45 4c46 0201 0100 6162 6364 6566 6768  .ELF....abcdefgh\n\
+         00000110: 30                                       0\n"
+    );
+}
+
+pub struct TerminatingTrapHandler {}
+
+impl<Mac: SupportMachine> TrapHandler<Mac> for TerminatingTrapHandler {
+    fn ebreak(&mut self, _machine: &mut Mac) -> TrapAction {
+        TrapAction::Terminate(42)
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> TrapAction {
+        TrapAction::Terminate(machine.registers()[A0].to_i8())
+    }
+}
+
+#[test]
+pub fn test_trap_handler_ebreak() {
+    let buffer = fs::read("tests/programs/ebreak64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .trap_handler(Box::new(TerminatingTrapHandler {}))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["ebreak".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(42));
+}
+
+#[test]
+pub fn test_trap_handler_unknown_ecall() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .trap_handler(Box::new(TerminatingTrapHandler {}))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(4));
+}
+
+// Emulates custom-0 instructions as adding 10 to A0, and traps custom-1 ones
+// in the guest.
+pub struct CustomInstructionHandler {}
+
+impl<Mac: SupportMachine> TrapHandler<Mac> for CustomInstructionHandler {
+    fn invalid_instruction(
+        &mut self,
+        machine: &mut Mac,
+        pc: u64,
+        instruction: u32,
+    ) -> InvalidInstructionAction {
+        match instruction & 0x7f {
+            0x0b => {
+                let value = machine.registers()[A0].to_u64() + 10;
+                machine.set_register(A0, Mac::REG::from_u64(value));
+                InvalidInstructionAction::Skip(4)
+            }
+            0x2b => InvalidInstructionAction::GuestTrap,
+            _ => InvalidInstructionAction::Trap(Error::InvalidInstruction { pc, instruction }),
+        }
+    }
+}
+
+#[test]
+pub fn test_trap_handler_invalid_instruction() {
+    let build = |custom: u32| {
+        let mut code: Vec<u8> = vec![];
+        code.extend_from_slice(
+            &to_riscv(pack_i(insts::OP_ADDI, A0 as u8, 0, 1))
+                .unwrap()
+                .to_le_bytes(),
+        );
+        code.extend_from_slice(&custom.to_le_bytes());
+        for i in [
+            pack_i(insts::OP_ADDI, A0 as u8, A0 as u8, 2),
+            pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+            pack_i(insts::OP_ECALL, 0, 0, 0),
+        ] {
+            code.extend_from_slice(&to_riscv(i).unwrap().to_le_bytes());
+        }
+        minimal_elf::<u64>(&code)
+    };
+    let custom0 = build(0x0000_000b);
+    let custom1 = build(0x0000_002b);
+    let new_machine = || {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+        DefaultMachineBuilder::new(core_machine)
+            .trap_handler(Box::new(CustomInstructionHandler {}))
+            .build()
+    };
+
+    let mut machine = new_machine();
+    machine.load_program(&custom0, &["custom".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(13));
+
+    let mut machine = TraceMachine::new(new_machine());
+    machine.load_program(&custom0, &["custom".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(13));
+
+    #[cfg(has_asm)]
+    {
+        let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+        let core = DefaultMachineBuilder::new(asm_core)
+            .trap_handler(Box::new(CustomInstructionHandler {}))
+            .build();
+        let mut machine = AsmMachine::new(core);
+        machine.load_program(&custom0, &["custom".into()]).unwrap();
+        assert_eq!(machine.run(), Ok(13));
+    }
+
+    // Without a trap vector in the guest, the instruction stays invalid.
+    let mut machine = new_machine();
+    machine.load_program(&custom1, &["custom".into()]).unwrap();
+    let pc = *machine.pc();
+    assert_eq!(
+        machine.run(),
+        Err(Error::InvalidInstruction {
+            pc: pc + 4,
+            instruction: 0x2b
+        })
+    );
+
+    // Without a trap handler, so it is as well.
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&custom0, &["custom".into()]).unwrap();
+    assert!(matches!(
+        machine.run(),
+        Err(Error::InvalidInstruction {
+            instruction: 0x0b,
+            ..
+        })
+    ));
+}
+
+#[test]
+pub fn test_run_until() {
+    // Address of main in tests/programs/simple64
+    let main = 0x10146;
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = ckb_vm::TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run_until(main), Ok(None));
+    assert_eq!(*machine.pc(), main);
+    let cycles = machine.machine.cycles();
+    assert!(cycles > 0 && cycles < 708);
+    assert_eq!(machine.run_until(main), Ok(Some(0)));
+    assert_eq!(machine.machine.cycles(), 708);
+}
+
+#[test]
+pub fn test_call_guest_function() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let entry = *machine.pc();
+
+    let main = find_symbol(&buffer, "main").unwrap().unwrap();
+    assert_eq!(machine.call::<i32>(main, &mut []), Ok(0));
+    assert_eq!(*machine.pc(), entry);
+
+    // void *memset(void *s, int c, size_t n)
+    let memset = find_symbol(&buffer, "memset").unwrap().unwrap();
+    let mut data = [0u8; 12];
+    let address: u64 = machine
+        .call(
+            memset,
+            &mut [
+                CallArg::BufferMut(&mut data),
+                CallArg::Int(0x41),
+                CallArg::Int(8),
+            ],
+        )
+        .unwrap();
+    assert_ne!(address, 0);
+    assert_eq!(&data, b"AAAAAAAA\0\0\0\0");
+    assert_eq!(find_symbol(&buffer, "no_such_symbol"), Ok(None));
+
+    // The program still runs to completion after the calls.
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[derive(Default)]
+pub struct CountingHandler {
+    instructions: u64,
+    loads: u64,
+    stores: u64,
+    syscalls: Vec<u64>,
+}
+
+impl<M: CoreMachine> InstrumentHandler<M> for CountingHandler {
+    fn instruction(&mut self, _machine: &M, _pc: u64, _instruction: ckb_vm::Instruction) {
+        self.instructions += 1;
+    }
+
+    fn memory(&mut self, _machine: &M, access: &MemoryAccess) {
+        match access.kind {
+            MemoryAccessKind::Load => self.loads += 1,
+            MemoryAccessKind::Store => self.stores += 1,
+            MemoryAccessKind::Atomic => (),
+        }
+    }
+
+    fn syscall(&mut self, _machine: &M, code: u64) {
+        self.syscalls.push(code);
+    }
+}
+
+#[test]
+pub fn test_instrumented_machine() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = InstrumentedMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+        CountingHandler::default(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.cycles(), 708);
+
+    let handler = machine.handler;
+    assert_eq!(handler.instructions, 708);
+    assert!(handler.loads > 0);
+    assert!(handler.stores > 0);
+    assert_eq!(handler.syscalls.last(), Some(&93));
+}
+
+#[test]
+pub fn test_cache_simulation() {
+    // 2 sets of 2 ways, with 16 byte lines.
+    let mut cache = Cache::new(CacheConfig::new(64, 16, 2)).unwrap();
+    assert!(!cache.access(0x00, 8));
+    assert!(cache.access(0x08, 8));
+    assert!(!cache.access(0x20, 4));
+    assert!(cache.access(0x00, 4));
+    // Evicts 0x20, the least recently used line of set 0.
+    assert!(!cache.access(0x40, 4));
+    assert!(!cache.access(0x20, 4));
+    assert!(!cache.access(0x10, 32));
+    assert_eq!(cache.stats().hits, 2);
+    assert_eq!(cache.stats().misses, 5);
+    assert!(Cache::new(CacheConfig::new(64, 24, 2)).is_err());
+
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let symbols = Symbols::from_elf(&buffer).unwrap();
+    assert_eq!(symbols.lookup(0x10146), Some("main"));
+    assert_eq!(symbols.lookup(0x1055d), Some("main"));
+    assert_eq!(symbols.lookup(0x1055e), Some("exit"));
+    // frame_dummy has no size, it extends to main.
+    assert_eq!(symbols.lookup(0x10140), Some("frame_dummy"));
+    assert_eq!(symbols.lookup(0x100), None);
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = InstrumentedMachine::new(
+        DefaultMachineBuilder::new(core_machine).build(),
+        CacheSimulator::new(CacheConfig::default(), CacheConfig::default(), symbols).unwrap(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+
+    let simulator = &machine.handler;
+    assert_eq!(simulator.icache().accesses(), 708);
+    assert!(simulator.icache().misses > 0);
+    assert!(simulator.dcache().accesses() > 0);
+    let functions = simulator.function_stats();
+    let main = functions.iter().find(|f| f.name == "main").unwrap();
+    assert!(main.icache.hits > 0);
+    assert_eq!(
+        functions.iter().map(|f| f.icache.accesses()).sum::<u64>(),
+        708
+    );
+}
+
+#[test]
+pub fn test_callgrind_profile() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = InstrumentedMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+        CallgrindProfiler::new(Symbols::from_elf(&buffer).unwrap()),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    let cycles = machine.cycles();
+    machine.handler.finish(cycles);
+
+    let mut output = vec![];
+    machine.handler.write(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("# callgrind format\n"));
+    assert!(output.contains(&format!("summary: {}\n", cycles)));
+    assert!(output.contains("\nfn=main\n0x10146 1\n"));
+    // _start calls main once.
+    assert!(output.contains("\ncfn=main\ncalls=1 0x10146\n"));
+}
+
+#[test]
+pub fn test_compare_machines() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let mut machines: Vec<_> = (0..2)
+        .map(|_| {
+            let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(
+                ISA_IMC,
+                VERSION0,
+                u64::max_value(),
+            );
+            let mut machine = DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build();
+            machine
+                .load_program(&buffer, &vec!["simple".into()])
+                .unwrap();
+            machine.run().unwrap();
+            machine
+        })
+        .collect();
+    let mut right = machines.pop().unwrap();
+    let mut left = machines.pop().unwrap();
+    ckb_vm::assert_machines_eq!(left, right);
+
+    right.set_register(A0, 42);
+    right.memory_mut().store_bytes(0x1000, &[1, 2, 3]).unwrap();
+    let diff = compare_machines(&mut left, &mut right).unwrap();
+    assert_eq!(
+        diff.0,
+        vec![
+            Difference::Register(A0, 0, 42),
+            Difference::Memory(0x1000, vec![0; 16], {
+                let mut data = vec![0; 16];
+                data[..3].copy_from_slice(&[1, 2, 3]);
+                data
+            }),
+        ]
+    );
+    let text = diff.to_string();
+    assert!(text.contains("a0: 0x0 != 0x2a"));
+    assert!(text.contains("> 00001000: 0102 03"));
+}
+
+#[test]
+pub fn test_trace_machine_traces() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = ckb_vm::TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert!(machine.traces().is_empty());
+    machine.run().unwrap();
+
+    let traces = machine.traces();
+    // Entry point of tests/programs/simple64
+    assert_eq!(traces.iter().filter(|t| t.address == 0x100c0).count(), 1);
+    for (a, b) in traces.iter().zip(traces.iter().skip(1)) {
+        assert!(a.address < b.address);
+    }
+    for trace in &traces {
+        let decoded = trace.decoded().unwrap();
+        assert_eq!(decoded.len(), trace.instructions.len());
+        assert_eq!(decoded[0].pc, trace.address);
+        let length: u64 = decoded.iter().map(|i| u64::from(i.length)).sum();
+        assert_eq!(length, trace.length as u64);
+        assert_eq!(trace.cycles, trace.instructions.len() as u64);
+    }
+}
+
+#[test]
+pub fn test_cycles_budget() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let budget = CyclesBudget::new();
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 700);
+    let mut machine = ckb_vm::TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .cycles_budget(budget.clone())
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    // Top up the budget from another thread before the limit is reached.
+    let handle = budget.clone();
+    std::thread::spawn(move || {
+        handle.add(200);
+        handle.reduce(50);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.machine.cycles(), 708);
+    assert_eq!(machine.machine.max_cycles(), 850);
+
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let budget = CyclesBudget::new();
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .cycles_budget(budget.clone())
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    budget.reduce(u64::max_value() - 100);
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+    assert_eq!(machine.max_cycles(), 100);
+}
+
+pub struct RecordingObserver {
+    events: Arc<std::sync::Mutex<Vec<Event>>>,
+}
+
+impl EventObserver for RecordingObserver {
+    fn on_event(&mut self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+#[test]
+pub fn test_event_observer() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = ckb_vm::TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .syscall(Box::new(CustomSyscall {}))
+            .observer(Box::new(RecordingObserver {
+                events: events.clone(),
+            }))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(39));
+
+    let events = events.lock().unwrap();
+    let loaded = events
+        .iter()
+        .position(|e| matches!(e, Event::ProgramLoaded { .. }))
+        .unwrap();
+    assert!(loaded > 1);
+    assert_eq!(events[0], Event::LoadStarted);
+    assert!(events[1..loaded]
+        .iter()
+        .all(|e| matches!(e, Event::MemoryPermissionChanged { .. })));
+    assert_eq!(events[loaded + 1], Event::RunStarted { cycles: 0 });
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::TraceCompiled { .. })));
+    let syscalls: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::SyscallEntered { code } => Some((true, *code)),
+            Event::SyscallExited { code } => Some((false, *code)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        syscalls,
+        vec![(true, 1111), (false, 1111), (true, 93), (false, 93)]
+    );
+    assert_eq!(
+        events.last(),
+        Some(&Event::Terminated {
+            exit_code: 39,
+            cycles: 0
+        })
+    );
+}
+
+// Freezes `page` on syscall 1112.
+struct FreezeSyscall {
+    page: u64,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for FreezeSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1112 {
+            return Ok(false);
+        }
+        machine.memory_mut().set_flag(self.page, FLAG_FREEZED)?;
+        Ok(true)
+    }
+}
+
+// Returns the permission changes reported while loading, and during the
+// syscall freezing `page`.
+fn permission_changes<M: Memory<REG = u64>>(page: u64) -> (Vec<Event>, Vec<Event>) {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 1112),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let core_machine = DefaultCoreMachine::<u64, M>::new(ISA_IMC, VERSION1, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(FreezeSyscall { page }))
+        .observer(Box::new(RecordingObserver {
+            events: events.clone(),
+        }))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["freeze".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    // Flag changes are only recorded during loads and syscalls.
+    machine.memory_mut().store64(&0x1000, &1).unwrap();
+    assert!(machine.memory_mut().take_flag_changes().is_empty());
+
+    let events = events.lock().unwrap();
+    let position = |event: &Event| events.iter().position(|e| e == event).unwrap();
+    let loaded = events
+        .iter()
+        .position(|e| matches!(e, Event::ProgramLoaded { .. }))
+        .unwrap();
+    let entered = position(&Event::SyscallEntered { code: 1112 });
+    let exited = position(&Event::SyscallExited { code: 1112 });
+    (
+        events[1..loaded].to_vec(),
+        events[entered + 1..exited].to_vec(),
+    )
+}
+
+#[test]
+pub fn test_event_observer_permission_changes() {
+    let (sparse_load, sparse_syscall) = permission_changes::<SparseMemory<u64>>(0x100);
+    assert!(!sparse_load.is_empty());
+    assert_eq!(
+        sparse_syscall,
+        vec![Event::MemoryPermissionChanged {
+            page: 0x100,
+            old: 0,
+            new: FLAG_FREEZED
+        }]
+    );
+    let (flat_load, _) = permission_changes::<FlatMemory<u64>>(0x100);
+    assert_eq!(flat_load, sparse_load);
+
+    // Pages above memory_size are reported too.
+    let page = 1 << 40;
+    let (paged_load, paged_syscall) = permission_changes::<PagedMemory<u64>>(page);
+    assert_eq!(paged_load, sparse_load);
+    assert_eq!(
+        paged_syscall,
+        vec![Event::MemoryPermissionChanged {
+            page,
+            old: 0,
+            new: FLAG_FREEZED
+        }]
+    );
+}
+
+#[test]
+pub fn test_chrome_trace() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let tracer = ChromeTracer::new(TraceClock::Cycles);
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = ckb_vm::TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .syscall(Box::new(CustomSyscall {}))
+            .observer(Box::new(tracer.clone()))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(39));
+
+    let json = tracer.to_json();
+    assert!(json
+        .starts_with("{\"traceEvents\":[{\"name\":\"load\",\"cat\":\"vm\",\"ph\":\"B\",\"ts\":0,"));
+    assert!(json.ends_with("\"exit_code\":39}}]}"));
+    // Every span is closed.
+    assert_eq!(
+        json.matches("\"ph\":\"B\"").count(),
+        json.matches("\"ph\":\"E\"").count()
+    );
+    assert_eq!(json.matches("\"name\":\"run\"").count(), 2);
+    assert!(json.contains("\"name\":\"syscall\",\"cat\":\"vm\",\"ph\":\"B\""));
+    assert!(json.contains("\"code\":1111"));
+    assert!(json.contains("\"name\":\"decode\""));
+    let end = format!("\"ph\":\"E\",\"ts\":{},", machine.machine.cycles());
+    assert!(json.contains(&end));
+}
+
+#[test]
+pub fn test_minimal_elf() {
+    // li a0, 7; li a7, 93; ecall
+    let code: Vec<u8> = [0x00700513u32, 0x05d00893, 0x00000073]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+    let result = run::<u64, SparseMemory<u64>>(
+        &minimal_elf::<u64>(&code),
+        &vec!["minimal".into()],
+        RISCV_MAX_MEMORY,
+    );
+    assert_eq!(result.unwrap(), 7);
+    let result = run::<u32, SparseMemory<u32>>(
+        &minimal_elf::<u32>(&code),
+        &vec!["minimal".into()],
+        RISCV_MAX_MEMORY,
+    );
+    assert_eq!(result.unwrap(), 7);
+}
+
+type HandlerMachine = ckb_vm::DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load_imm_twice(machine: &mut HandlerMachine, inst: Instruction) -> Result<(), Error> {
+    let i = Utype(inst);
+    machine.set_register(i.rd(), u64::from(i.immediate_u()) * 2);
+    Ok(())
+}
+
+#[test]
+pub fn test_handler_table() {
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    let inst = Utype::new(insts::OP_CUSTOM_LOAD_UIMM, A0, 21).0;
+
+    let mut table = HandlerTable::new();
+    table.execute(inst, &mut machine).unwrap();
+    assert_eq!(machine.registers()[A0], 21);
+    table
+        .register(insts::OP_CUSTOM_LOAD_UIMM, load_imm_twice)
+        .unwrap();
+    table.execute(inst, &mut machine).unwrap();
+    assert_eq!(machine.registers()[A0], 42);
+    // The default table used by execute_instruction is left unchanged.
+    execute_instruction(inst, &mut machine).unwrap();
+    assert_eq!(machine.registers()[A0], 21);
+
+    assert_eq!(
+        table.execute(blank_instruction(insts::OP_UNLOADED), &mut machine),
+        Err(Error::InvalidOp(insts::OP_UNLOADED))
+    );
+    assert_eq!(
+        table.register(insts::MAXIMUM_OPCODE + 1, load_imm_twice),
+        Err(Error::InvalidOp(insts::MAXIMUM_OPCODE + 1))
+    );
+}
+
+#[test]
+pub fn test_block_cycles_exceeded() {
+    // Cycles are charged per basic block, but the instruction exceeding max
+    // cycles is still the one reported, so exactly max cycles are consumed.
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    for max_cycles in [1, 100, 355, 707] {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, max_cycles);
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build();
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+        assert_eq!(machine.cycles(), max_cycles);
+
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, max_cycles);
+        let mut machine = ckb_vm::TraceMachine::new(
+            DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build(),
+        );
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+        assert_eq!(machine.machine.cycles(), max_cycles);
+    }
+}
+
+#[test]
+pub fn test_machine_arena() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let mut arena = Arena::new();
+    for _ in 0..2 {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+        let mut machine = ckb_vm::TraceMachine::new(
+            DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .arena(arena)
+                .build(),
+        );
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        assert_eq!(machine.machine.cycles(), 708);
+        arena = machine.machine.take_arena();
+        assert!(arena.capacity() > 0);
+    }
+
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .arena(arena)
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.cycles(), 708);
+}
+
+#[test]
+pub fn test_flat_memory_zeroed() {
+    let memories: Vec<MappedFlatMemory<u64>> = (0..64)
+        .map(|_| MappedFlatMemory::new_with_memory(RISCV_MAX_MEMORY))
+        .collect();
+    for mut memory in memories {
+        let last = RISCV_MAX_MEMORY as u64 - 8;
+        assert_eq!(memory.load64(&last), Ok(0));
+        memory.store64(&last, &0x1122334455667788).unwrap();
+        assert_eq!(memory.load64(&last), Ok(0x1122334455667788));
+        assert_eq!(memory.len(), RISCV_MAX_MEMORY);
+        assert!(memory[..RISCV_MAX_MEMORY - 8].iter().all(|b| *b == 0));
+        #[cfg(unix)]
+        assert!(memory.is_mapped());
+    }
+    assert!(ZeroedBuffer::new(0).iter().next().is_none());
+
+    // FlatMemory itself still dereferences to a Vec.
+    let memory = FlatMemory::<u64>::new();
+    let data: &Vec<u8> = &memory;
+    assert!(data.capacity() >= RISCV_MAX_MEMORY);
+    assert!(data.iter().all(|b| *b == 0));
+}
+
+#[test]
+pub fn test_register_borrowed_ops() {
+    let (a, b) = (0xf0f0_1234_5678_9abcu64, 0x0ff0_00ff_ff00_0013u64);
+    assert_eq!(a.bit_and(&b), a & b);
+    assert_eq!(a.bit_or(&b), a | b);
+    assert_eq!(a.bit_xor(&b), a ^ b);
+    assert_eq!(a.logical_shl(&(b & 0x3f)), a << (b & 0x3f));
+    assert_eq!(a.logical_shr(&(b & 0x3f)), a >> (b & 0x3f));
+    let (a, b) = (a as u32, b as u32);
+    assert_eq!(a.bit_and(&b), a & b);
+    assert_eq!(a.logical_shl(&(b & 0x1f)), a << (b & 0x1f));
+    assert_eq!(a.logical_shr(&(b & 0x1f)), a >> (b & 0x1f));
+}
+
+#[test]
+pub fn test_predecode() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    for workers in [1, 3, 8] {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .predecode(workers)
+            .build();
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        let entry = machine.pc().to_u64();
+        let predecoded = machine.predecoded().unwrap();
+        assert!(!predecoded.is_empty());
+        assert_eq!(predecoded.get(entry + 1), None);
+        let instruction = predecoded.get(entry).unwrap();
+        let mut decoder = build_decoder::<u64>(ISA_IMC, VERSION0);
+        assert_eq!(decoder.decode(machine.memory_mut(), entry), Ok(instruction));
+        assert_eq!(machine.run(), Ok(0));
+        assert_eq!(machine.cycles(), 708);
+    }
+}
+
+#[test]
+pub fn test_predecode_artifact_cache() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let directory = std::env::temp_dir().join(format!("ckb-vm-artifacts-{}", std::process::id()));
+    let cache = DirectoryArtifactCache::new(&directory).unwrap();
+    let key = ArtifactKey {
+        program_hash: ckb_vm::hash::program_hash(&buffer),
+        version: VERSION1,
+        isa: ISA_IMC,
+        offset: 0,
+    };
+    let run = || {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .predecode(2)
+            .artifact_cache(Box::new(DirectoryArtifactCache::new(&directory).unwrap()))
+            .build();
+        machine
+            .load_program(&buffer, &vec!["simple".into()])
+            .unwrap();
+        let predecoded = machine.predecoded().unwrap().ranges().to_vec();
+        assert_eq!(machine.run(), Ok(0));
+        predecoded
+    };
+
+    // The first machine decodes and stores the artifact, the next one reuses
+    // it.
+    let decoded = run();
+    let artifact = cache.load(&key).unwrap();
+    assert_eq!(
+        decode_artifact(&artifact, &key).unwrap().ranges(),
+        &decoded[..]
+    );
+    assert_eq!(run(), decoded);
+
+    // Artifacts of another machine or altered ones are rejected, a corrupt
+    // artifact is replaced.
+    let other = ArtifactKey {
+        version: VERSION0,
+        ..key
+    };
+    assert!(decode_artifact(&artifact, &other).is_err());
+    let mut corrupt = artifact.clone();
+    corrupt[100] ^= 1;
+    assert!(decode_artifact(&corrupt, &key).is_err());
+    cache.store(&key, &corrupt).unwrap();
+    assert_eq!(run(), decoded);
+    assert_eq!(cache.load(&key), Some(artifact.clone()));
+    let code = decode_artifact(&artifact, &key).unwrap();
+    assert_eq!(encode_artifact(&code, &key), artifact);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+pub fn test_load_policy() {
+    let simple: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let other: Bytes = fs::read("tests/programs/mulw64").unwrap().into();
+    let simple_hash = ckb_vm::hash::program_hash(&simple);
+    let load = |policy: Box<dyn LoadPolicy>, program: &Bytes| {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+        let mut machine = DefaultMachineBuilder::new(core_machine)
+            .load_policy(policy)
+            .build();
+        let result = machine.load_program(program, &vec!["policy".into()]);
+        if result.is_err() {
+            // Nothing was loaded.
+            assert_eq!(*machine.pc(), 0);
+            assert_eq!(machine.registers()[SP], 0);
+            assert_eq!(machine.memory_mut().fetch_flag(0x10), Ok(0));
+        }
+        result.map(|_| ())
+    };
+
+    let allow = || Box::new(AllowList::new([simple_hash]));
+    assert_eq!(load(allow(), &simple), Ok(()));
+    let hash = ckb_vm::hash::program_hash(&other);
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(load(allow(), &other), Err(Error::ProgramNotAllowed(hex)));
+
+    let deny = || Box::new(DenyList::new([simple_hash]));
+    assert!(matches!(
+        load(deny(), &simple),
+        Err(Error::ProgramNotAllowed(_))
+    ));
+    assert_eq!(load(deny(), &other), Ok(()));
+
+    let small = Box::new(|_: &[u8; 32], program: &[u8]| program.len() < 1024);
+    assert!(load(small, &simple).is_err());
+}
+
+#[test]
+pub fn test_shared_memory() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let pool = PagePool::new();
+    let mut machines: Vec<_> = (0..3)
+        .map(|_| {
+            let mut core_machine = DefaultCoreMachine::<u64, WXorXMemory<SharedMemory<u64>>>::new(
+                ISA_IMC,
+                VERSION1,
+                u64::MAX,
+            );
+            core_machine.memory_mut().inner_mut().set_pool(pool.clone());
+            let mut machine = DefaultMachineBuilder::new(core_machine).build();
+            machine
+                .load_program(&buffer, &vec!["simple".into()])
+                .unwrap();
+            machine
+        })
+        .collect();
+    let shared = pool.len();
+    assert!(shared > 0);
+    let entry = *machines[0].pc();
+    for machine in machines.iter_mut() {
+        assert_eq!(machine.memory_mut().inner_mut().shared_pages(), shared);
+        assert_eq!(machine.run(), Ok(0));
+    }
+
+    // Writing to a shared page copies it, other machines keep the original.
+    let original = machines[1].memory_mut().load32(&entry).unwrap();
+    let memory = machines[0].memory_mut().inner_mut();
+    memory.store32(&entry, &0).unwrap();
+    assert_eq!(memory.shared_pages(), shared - 1);
+    assert_eq!(machines[1].memory_mut().load32(&entry), Ok(original));
+    assert_eq!(machines[2].memory_mut().load32(&entry), Ok(original));
+
+    drop(machines);
+    assert!(pool.is_empty());
+}
+
+#[test]
+pub fn test_address_space_layout() {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let layout = AddressSpaceLayout {
+        stack_start: Some(0x100000),
+        stack_size: Some(0x10000),
+        load_base: Some(0x20000),
+        heap_start: None,
+    };
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 100);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .layout(layout)
+        .build();
+    assert_eq!(machine.layout(), layout);
+    machine.load_program(&program, &["layout".into()]).unwrap();
+    assert_eq!(*machine.pc(), 0x20000 + (program.len() - code.len()) as u64);
+    let sp = machine.registers()[SP];
+    assert!(sp > 0x100000 && sp <= 0x110000);
+    assert_eq!(
+        machine.layout(),
+        AddressSpaceLayout {
+            heap_start: Some(0x21000),
+            ..layout
+        }
+    );
+    assert_eq!(machine.run(), Ok(0));
+
+    // Defaults are preserved.
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 100);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&program, &["layout".into()]).unwrap();
+    let layout = machine.layout();
+    assert_eq!(layout.stack_size, Some(RISCV_MAX_MEMORY as u64 / 4));
+    assert_eq!(layout.stack_start, Some(RISCV_MAX_MEMORY as u64 / 4 * 3));
+    assert_eq!(layout.load_base, Some(0x10000));
+
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 100);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .layout(AddressSpaceLayout {
+            load_base: Some(0x20010),
+            ..Default::default()
+        })
+        .build();
+    assert!(machine.load_program(&program, &["layout".into()]).is_err());
+}
+
+#[test]
+pub fn test_region_memory() {
+    let mut memory = RegionMemory::<SparseMemory<u64>>::empty();
+    memory.add_region(0x10000, 0x10000).unwrap();
+    memory.add_region(0x4000_0000, 1 << 20).unwrap();
+    assert!(memory.add_region(0x18000, 0x10000).is_err());
+    assert!(memory.add_region(0x50000, 0x800).is_err());
+    assert_eq!(
+        memory.regions(),
+        vec![(0x10000, 0x10000), (0x4000_0000, 1 << 20)]
+    );
+    assert_eq!(memory.memory_size(), 0x4010_0000);
+
+    // Code at 0x10000, stores to the data window, then loads from the hole
+    // between the regions.
+    let (t0, t1, t2) = (T0 as u8, T1 as u8, T2 as u8);
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, t0, 0x4000_0000),
+        pack_i(insts::OP_ADDI, t1, 0, 42),
+        pack_s(insts::OP_SD, t0, t1, 8),
+        pack_u(insts::OP_LUI, t2, 0x3000_0000),
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, t2, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let core_machine = DefaultCoreMachine::<u64, WXorXMemory<RegionMemory<SparseMemory<u64>>>>::new(
+        ISA_IMC, VERSION1, 100,
+    );
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .layout(AddressSpaceLayout {
+            stack_start: Some(0x4008_0000),
+            stack_size: Some(0x80000),
+            ..Default::default()
+        })
+        .build();
+    *machine.memory_mut().inner_mut() = memory;
+    machine.load_program(&program, &["regions".into()]).unwrap();
+    assert_eq!(machine.run(), Err(Error::MemOutOfBound));
+    assert_eq!(machine.memory_mut().load64(&0x4000_0008), Ok(42));
+    // Page flags are translated to the regions as well.
+    assert_eq!(
+        machine.memory_mut().store64(&0x10000, &0),
+        Err(Error::MemWriteOnExecutablePage)
+    );
+    // WXorXMemory is bounded by the end of the highest region, not by
+    // RISCV_MAX_MEMORY.
+    let memory = machine.memory_mut();
+    assert_eq!(memory.store32(&0x400f_fffc, &7), Ok(()));
+    assert_eq!(memory.load32(&0x400f_fffc), Ok(7));
+    assert_eq!(
+        memory.store_bytes(0x400f_fffc, &[0; 8]),
+        Err(Error::MemOutOfBound)
+    );
+}
+
+#[test]
+pub fn test_paged_memory() {
+    // Stores across a page boundary at a high canonical address, then exits
+    // with the value loaded back.
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, t0, i32::MIN),
+        pack_i(insts::OP_ADDI, t1, 0, 42),
+        pack_s(insts::OP_SD, t0, t1, -4),
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, t0, -4),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let core_machine = DefaultCoreMachine::<u64, PagedMemory<u64>>::new(ISA_IMC, VERSION1, 100);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .layout(AddressSpaceLayout {
+            stack_start: Some(0x7fff_ff00_0000),
+            stack_size: Some(8 << 20),
+            load_base: Some(0x7fff_0000_0000),
+            heap_start: None,
+        })
+        .build();
+    machine.load_program(&program, &["paged".into()]).unwrap();
+    assert!(machine.registers()[SP] > 0x7fff_ff00_0000);
+    assert_eq!(machine.run(), Ok(42));
+    assert_eq!(machine.memory_mut().load64(&0xffff_ffff_7fff_fffc), Ok(42));
+    // Untouched pages read as zeros and are not allocated.
+    let pages = machine.memory().allocated_pages();
+    assert_eq!(machine.memory_mut().load64(&0x1234_5678_9000), Ok(0));
+    assert_eq!(machine.memory().allocated_pages(), pages);
+    assert_eq!(
+        machine.memory_mut().load64(&0xffff_ffff_ffff_fffc),
+        Err(Error::MemOutOfBound)
+    );
+}
+
+#[test]
+pub fn test_reload_program() {
+    let build = |tail: Instruction| {
+        let code: Vec<u8> = [
+            pack_i(insts::OP_ADDI, T0 as u8, 0, 7),
+            pack_s(insts::OP_SD, SP as u8, T0 as u8, 0),
+            tail,
+            pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+            pack_i(insts::OP_ECALL, 0, 0, 0),
+        ]
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+        minimal_elf::<u64>(&code)
+    };
+    let old = build(pack_i(insts::OP_ADDI, A0 as u8, 0, 1));
+    // The new code reads back the value stored on the stack by the old one.
+    let new = build(pack_i(insts::OP_LD_VERSION1, A0 as u8, SP as u8, 0));
+    let entry = 0x10000 + old.len() as u64 - 20;
+
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION1, 100);
+    let mut machine = ckb_vm::TraceMachine::new(DefaultMachineBuilder::new(core_machine).build());
+    machine.load_program(&old, &["reload".into()]).unwrap();
+    assert_eq!(machine.run_until(entry + 8), Ok(None));
+    machine.reload_program(&new).unwrap();
+    assert_eq!(machine.run(), Ok(7));
+    // The new code is executable and protected as usual.
+    assert_eq!(
+        machine.memory_mut().store64(&entry, &0),
+        Err(Error::MemWriteOnExecutablePage)
+    );
+
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION1, 100);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&old, &["reload".into()]).unwrap();
+    assert_eq!(machine.run_until(entry + 8), Ok(None));
+    assert_eq!(machine.reload_program(&new), Ok(new.len() as u64));
+    assert_eq!(machine.run(), Ok(7));
+}
+
+#[test]
+pub fn test_metered_memory() {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 3),
+        pack_s(insts::OP_SD, SP as u8, T0 as u8, -8),
+        pack_s(insts::OP_SW, SP as u8, T0 as u8, -12),
+        pack_s(insts::OP_SB, SP as u8, T0 as u8, -13),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let build = |limit: Option<u64>| {
+        let core_machine = DefaultCoreMachine::<u64, MeteredMemory<SparseMemory<u64>>>::new(
+            ISA_IMC, VERSION1, 100,
+        );
+        let mut machine = DefaultMachineBuilder::new(core_machine).build();
+        machine.load_program(&program, &["metered".into()]).unwrap();
+        // The arguments pushed on the stack are metered too.
+        assert!(machine.memory().written() > 0);
+        machine.memory_mut().reset();
+        machine.memory_mut().set_limit(limit);
+        machine
+    };
+
+    let mut machine = build(None);
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.memory().written(), 13);
+
+    let mut machine = build(Some(13));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.memory().written(), 13);
+
+    // The word store goes past the limit and writes nothing.
+    let mut machine = build(Some(10));
+    let sp = machine.registers()[SP];
+    assert_eq!(machine.run(), Err(Error::MemWriteLimitExceeded));
+    assert_eq!(machine.memory().written(), 8);
+    assert_eq!(machine.memory_mut().load32(&(sp - 12)), Ok(0));
+    assert_eq!(machine.memory_mut().load64(&(sp - 8)), Ok(3));
+}
+
+#[test]
+pub fn test_quota_memory() {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 3),
+        pack_s(insts::OP_SD, SP as u8, T0 as u8, -8),
+        pack_i(insts::OP_ADDI, T1 as u8, SP as u8, -2048),
+        pack_i(insts::OP_ADDI, T1 as u8, T1 as u8, -2048),
+        // A page below the stack page.
+        pack_s(insts::OP_SD, T1 as u8, T0 as u8, -8),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let build = |quota: Option<u64>| {
+        let core_machine =
+            DefaultCoreMachine::<u64, QuotaMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION1, 100);
+        let mut machine = DefaultMachineBuilder::new(core_machine).build();
+        machine.load_program(&program, &["quota".into()]).unwrap();
+        // The code and the arguments on the stack.
+        let loaded = machine.memory().touched_pages();
+        assert_eq!(loaded, 2);
+        machine
+            .memory_mut()
+            .set_quota(quota.map(|quota| loaded + quota));
+        machine
+    };
+
+    let mut machine = build(None);
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.memory().touched_pages(), 3);
+
+    let mut machine = build(Some(1));
+    assert_eq!(machine.run(), Ok(0));
+
+    let mut machine = build(Some(0));
+    assert_eq!(machine.run(), Err(Error::MemQuotaExceeded));
+    assert_eq!(machine.memory().touched_pages(), 2);
+
+    // The quota is independent of the address space, which is left usable
+    // as a whole.
+    let mut memory = QuotaMemory::<SparseMemory<u64>>::new_with_memory(1 << 20);
+    memory.set_quota(Some(1));
+    assert_eq!(memory.store64(&0x1000, &1), Ok(()));
+    assert_eq!(memory.store64(&0x1008, &2), Ok(()));
+    assert_eq!(memory.load64(&0x1000), Ok(1));
+    assert_eq!(memory.load64(&0x8000), Err(Error::MemQuotaExceeded));
+    // Spanning a touched and a new page.
+    assert_eq!(
+        memory.store_bytes(0x1ffc, &[1; 8]),
+        Err(Error::MemQuotaExceeded)
+    );
+    memory.set_quota(Some(2));
+    assert_eq!(memory.store_bytes(0x1ffc, &[1; 8]), Ok(()));
+    assert_eq!(memory.touched_pages(), 2);
+    assert_eq!(memory.load64(&(1 << 20)), Err(Error::MemOutOfBound));
+}
+
+#[test]
+pub fn test_sub_page_memory() {
+    let text = Bytes::from(vec![0x13; 100]);
+    let rodata = Bytes::from(vec![7; 40]);
+    let data = Bytes::from(vec![9; 8]);
+    // Text, rodata and data packed into a single page, as left by linkers
+    // with tight layouts.
+    let load = |memory: &mut dyn FnMut(u64, u8, Bytes, u64) -> Result<(), Error>| {
+        memory(0x1000, FLAG_EXECUTABLE | FLAG_FREEZED, text.clone(), 0)?;
+        memory(0x1000, FLAG_FREEZED, rodata.clone(), 128)?;
+        memory(0x1000, 0, data.clone(), 192)
+    };
+    let mut wxorx = WXorXMemory::<SparseMemory<u64>>::new_with_memory(1 << 20);
+    assert_eq!(
+        load(&mut |addr, flags, source, offset| {
+            wxorx.init_pages(addr, 0x1000, flags, Some(source), offset)
+        }),
+        Err(Error::MemWriteOnFreezedPage)
+    );
+    let mut memory = SubPageMemory::<SparseMemory<u64>>::new_with_memory(1 << 20);
+    load(&mut |addr, flags, source, offset| {
+        memory.init_pages(addr, 0x1000, flags, Some(source), offset)
+    })
+    .unwrap();
+    assert_eq!(memory.line_size(), 64);
+    assert_eq!(memory.load_bytes(0x1000, 100).unwrap(), text);
+    assert_eq!(memory.load_bytes(0x1080, 40).unwrap(), rodata);
+    assert_eq!(memory.load8(&0x10c0).unwrap(), 9);
+    assert_eq!(
+        memory.fetch_line_flag(0x1040),
+        Ok(FLAG_EXECUTABLE | FLAG_FREEZED)
+    );
+    assert_eq!(memory.fetch_line_flag(0x1080), Ok(FLAG_FREEZED));
+    assert_eq!(memory.fetch_line_flag(0x1fc0), Ok(0));
+    // The page holds the flags of all its lines.
+    assert_eq!(
+        memory.fetch_flag(1).unwrap() & (FLAG_EXECUTABLE | FLAG_FREEZED),
+        FLAG_EXECUTABLE | FLAG_FREEZED
+    );
+
+    // Only the text lines are executable, and only the others writable.
+    assert!(memory.execute_load32(0x1060).is_ok());
+    assert_eq!(
+        memory.execute_load32(0x1080),
+        Err(Error::MemWriteOnExecutablePage)
+    );
+    assert_eq!(
+        memory.store8(&0x1010, &1),
+        Err(Error::MemWriteOnExecutablePage)
+    );
+    assert_eq!(memory.store8(&0x10c8, &1), Ok(()));
+    // Accesses spanning lines are checked as a whole.
+    assert_eq!(
+        memory.store_bytes(0x107c, &[0; 8]),
+        Err(Error::MemWriteOnExecutablePage)
+    );
+    // Freezed lines can't be loaded again.
+    assert_eq!(
+        memory.init_pages(0x1000, 0x1000, 0, Some(data.clone()), 136),
+        Err(Error::MemWriteOnFreezedPage)
+    );
+
+    // Hosts protect data at line granularity.
+    memory.protect(0x2000, 64, FLAG_FREEZED).unwrap();
+    assert_eq!(memory.fetch_line_flag(0x2000), Ok(FLAG_FREEZED));
+    assert_eq!(
+        memory.protect(0x2020, 8, 0),
+        Err(Error::MemWriteOnFreezedPage)
+    );
+
+    assert!(memory.set_line_size(48).is_err());
+    memory.set_line_size(16).unwrap();
+    assert_eq!(memory.line_size(), 16);
+    // Lines take the flags of their page.
+    assert_eq!(
+        memory.fetch_line_flag(0x10c0),
+        Ok(FLAG_EXECUTABLE | FLAG_FREEZED)
+    );
+}
+
+#[test]
+pub fn test_lazy_memory() {
+    let code: Vec<u8> = [
+        pack_u(insts::OP_LUI, T0 as u8, 0x40000),
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, T0 as u8, 8),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let build = || {
+        let core_machine =
+            DefaultCoreMachine::<u64, LazyMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION1, 100);
+        let mut machine = DefaultMachineBuilder::new(core_machine).build();
+        machine.load_program(&program, &["lazy".into()]).unwrap();
+        machine
+    };
+
+    // Without a handler, unmapped pages read as zeros.
+    let mut machine = build();
+    assert_eq!(machine.run(), Ok(0));
+    assert!(!machine.memory().is_mapped(0x40000));
+
+    let mut machine = build();
+    machine
+        .memory_mut()
+        .set_fault_handler(Box::new(|page_addr: u64| {
+            if page_addr != 0x40000 {
+                return Ok(None);
+            }
+            let mut data = vec![0u8; 16];
+            data[8] = 42;
+            Ok(Some(PageContent {
+                flags: 0,
+                data: Bytes::from(data),
+            }))
+        }));
+    assert_eq!(machine.run(), Ok(42));
+    assert!(machine.memory().is_mapped(0x40000));
+    assert_eq!(machine.memory().faults(), 1);
+    // Mapped pages no longer fault.
+    assert_eq!(machine.memory_mut().load64(&0x40008), Ok(42));
+    assert_eq!(machine.memory_mut().load64(&0x40ff8), Ok(0));
+    assert_eq!(machine.memory().faults(), 1);
+    assert_eq!(
+        machine.memory_mut().load8(&0x41000),
+        Err(Error::MemOutOfBound)
+    );
+}
+
+fn run_with_trace_cache(code: &[Instruction], config: TraceCacheConfig) -> TraceStats {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let program = minimal_elf::<u64>(&code);
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .trace_cache(config)
+            .build(),
+    );
+    machine.load_program(&program, &["trace".into()]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    machine.trace_stats()
+}
+
+#[test]
+pub fn test_trace_cache_associativity() {
+    let t0 = T0 as u8;
+    // The loop alternates between the traces at 4 and 12, which share a set
+    // of a cache of 2 slots.
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 50),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_s(insts::OP_BEQ, t0, 0, 8),
+        pack_u(insts::OP_JAL, 0, -8),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ];
+    let direct = run_with_trace_cache(
+        &code,
+        TraceCacheConfig {
+            slots: 2,
+            ways: 1,
+            eviction: TraceEviction::Fifo,
+        },
+    );
+    assert_eq!(direct.hits, 0);
+    assert_eq!(direct.misses, 1 + 49 * 2 + 1);
+
+    let associative = run_with_trace_cache(
+        &code,
+        TraceCacheConfig {
+            slots: 2,
+            ways: 2,
+            eviction: TraceEviction::Fifo,
+        },
+    );
+    // The entry, the first JAL, the loop and the exit.
+    assert_eq!(associative.misses, 4);
+    assert_eq!(associative.evictions, 2);
+    assert_eq!(associative.hits, 49 * 2 - 2);
+
+    // Enough iterations for the cache to be laid out again by hotness, moved
+    // traces are still found.
+    let mut code = code;
+    code[0] = pack_u(insts::OP_LUI, t0, 0x10000);
+    let relayout = run_with_trace_cache(
+        &code,
+        TraceCacheConfig {
+            slots: 2,
+            ways: 2,
+            eviction: TraceEviction::Fifo,
+        },
+    );
+    assert_eq!(relayout.misses, 4);
+    assert_eq!(relayout.evictions, 2);
+    // Also when hot traces are packed across the sets of the default cache.
+    let relayout = run_with_trace_cache(&code, TraceCacheConfig::default());
+    assert_eq!(relayout.misses, 4);
+    assert_eq!(relayout.evictions, 0);
+
+    let invalid = TraceCacheConfig {
+        slots: 3,
+        ..TraceCacheConfig::default()
+    };
+    assert!(invalid.validate().is_err());
+}
+
+#[test]
+pub fn test_trace_cache_jalr() {
+    let (t0, t2, ra) = (T0 as u8, T2 as u8, RA as u8);
+    // The function at 32 returns to 12 twice. In between, the trace at 36
+    // replaces the one at 12 in a cache of 2 slots, so the second return
+    // can't reuse the target cached by the JALR.
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 2),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_u(insts::OP_JAL, ra, 24),
+        pack_i(insts::OP_ADDI, t2, t2, 1),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_s(insts::OP_BNE, t0, 0, 16),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_JALR_VERSION1, 0, ra, 0),
+        pack_i(insts::OP_ADDI, t2, t2, 1),
+        pack_u(insts::OP_JAL, 0, -8),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let program = minimal_elf::<u64>(&code);
+    let run = |config: TraceCacheConfig| {
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, 10_000);
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .trace_cache(config)
+                .build(),
+        );
+        machine.load_program(&program, &["jalr".into()]).unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        machine.trace_stats()
+    };
+
+    // The second return finds its target in the JALR cache.
+    let stats = run(TraceCacheConfig::default());
+    assert_eq!(stats.jalr_hits, 1);
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 5);
+
+    let stats = run(TraceCacheConfig {
+        slots: 2,
+        ways: 1,
+        eviction: TraceEviction::Fifo,
+    });
+    assert_eq!(stats.jalr_hits, 0);
+    // Only the second call of the function hits, the trace at 12 is decoded
+    // again.
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 6);
+    assert_eq!(stats.evictions, 4);
+}
+
+#[test]
+pub fn test_trace_cache_lru() {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    // The trace at 4 runs between every other trace, LRU keeps it in the
+    // cache while FIFO evicts it in turn.
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 40),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_i(insts::OP_ANDI, t1, t0, 1),
+        pack_s(insts::OP_BEQ, t1, 0, 12),
+        pack_u(insts::OP_JAL, 0, -12),
+        pack_i(insts::OP_ADDI, 0, 0, 0),
+        pack_s(insts::OP_BNE, t0, 0, -20),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ];
+    let config = TraceCacheConfig {
+        slots: 2,
+        ways: 2,
+        eviction: TraceEviction::Fifo,
+    };
+    let fifo = run_with_trace_cache(&code, config);
+    let lru = run_with_trace_cache(
+        &code,
+        TraceCacheConfig {
+            eviction: TraceEviction::Lru,
+            ..config
+        },
+    );
+    assert!(lru.misses < fifo.misses);
+    assert_eq!(lru.hits + lru.misses, fifo.hits + fifo.misses);
+    // Only the first run of the trace at 4 misses.
+    assert_eq!(lru.hits, 38);
+}
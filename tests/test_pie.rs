@@ -0,0 +1,144 @@
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::elf_adaptor::{self, R_RISCV_RELATIVE};
+use ckb_vm::machine::layout::AddressSpaceLayout;
+use ckb_vm::machine::symbols::Symbols;
+use ckb_vm::machine::{VERSION0, VERSION2};
+use ckb_vm::registers::{A0, A7, T0, T1};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory,
+    ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_u, to_riscv};
+
+const ENTRY: u64 = 176;
+const SLOT: u64 = 200;
+const RELA: u64 = 208;
+const DYNAMIC: u64 = 232;
+const FILE_SIZE: u64 = 296;
+
+// A static position independent executable linked at 0, holding in a single
+// segment its code, a pointer to the code at SLOT and the relocation of the
+// pointer. The program exits with the difference between the pointer and
+// the address of the code, 0 once relocated.
+fn pie(relocation_type: u64) -> Bytes {
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+    elf.extend_from_slice(&[0; 8]);
+    // ET_DYN, EM_RISCV, version
+    elf.extend_from_slice(&3u16.to_le_bytes());
+    elf.extend_from_slice(&243u16.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    // Entry, program header offset, section header offset, flags
+    for value in [ENTRY, 64, 0] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    for value in [64u16, 56, 2, 64, 0, 0] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+    // PT_LOAD, R+X and PT_DYNAMIC, R
+    for (p_type, p_flags, offset, size, align) in [
+        (1u32, 5u32, 0, FILE_SIZE, 0x1000),
+        (2, 4, DYNAMIC, FILE_SIZE - DYNAMIC, 8),
+    ] {
+        elf.extend_from_slice(&p_type.to_le_bytes());
+        elf.extend_from_slice(&p_flags.to_le_bytes());
+        for value in [offset, offset, offset, size, size, align] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    assert_eq!(elf.len() as u64, ENTRY);
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    for instruction in [
+        pack_u(insts::OP_AUIPC, t0, 0),
+        pack_i(insts::OP_LD_VERSION1, t1, t0, (SLOT - ENTRY) as i32),
+        pack_r(insts::OP_SUB, A0 as u8, t1, t0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ] {
+        elf.extend_from_slice(&to_riscv(instruction).unwrap().to_le_bytes());
+    }
+    elf.resize(SLOT as usize, 0);
+    // The linker leaves the pointer to the relocation.
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    for value in [SLOT, relocation_type, ENTRY] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+    // DT_RELA, DT_RELASZ, DT_RELAENT, DT_NULL
+    for value in [7, RELA, 8, 24, 9, 24, 0, 0] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+    assert_eq!(elf.len() as u64, FILE_SIZE);
+    Bytes::from(elf)
+}
+
+#[test]
+pub fn test_pie_relocated_to_load_base() {
+    let program = pie(R_RISCV_RELATIVE);
+    assert!(elf_adaptor::is_position_independent(&program));
+    assert_eq!(
+        elf_adaptor::relative_relocations(&program),
+        Ok(vec![(SLOT, ENTRY)])
+    );
+    for version in [VERSION0, VERSION2] {
+        let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, version, u64::MAX);
+        let mut machine = DefaultMachineBuilder::new(core)
+            .layout(AddressSpaceLayout {
+                load_base: Some(0x20000),
+                ..Default::default()
+            })
+            .build();
+        machine.load_program(&program, &["pie".into()]).unwrap();
+        assert_eq!(machine.load_bias(), 0x20000);
+        assert_eq!(*machine.pc(), 0x20000 + ENTRY);
+        assert_eq!(
+            machine.memory_mut().load64(&(0x20000 + SLOT)),
+            Ok(0x20000 + ENTRY)
+        );
+        let symbols = Symbols::new(vec![(ENTRY, 20, "_start".to_string())]);
+        let symbols = symbols.relocated(machine.load_bias());
+        assert_eq!(symbols.lookup(*machine.pc()), Some("_start"));
+        assert_eq!(machine.run(), Ok(0));
+    }
+}
+
+#[test]
+pub fn test_pie_without_load_base() {
+    // Without a load base, the program loads at its link address and its
+    // relocations are left alone.
+    let program = pie(R_RISCV_RELATIVE);
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine.load_program(&program, &["pie".into()]).unwrap();
+    assert_eq!(machine.load_bias(), 0);
+    assert_eq!(machine.memory_mut().load64(&SLOT), Ok(0));
+    assert_eq!(machine.run(), Ok(-(ENTRY as i64) as i8));
+
+    // With the link address as load base, relocations are applied.
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .layout(AddressSpaceLayout {
+            load_base: Some(0),
+            ..Default::default()
+        })
+        .build();
+    machine.load_program(&program, &["pie".into()]).unwrap();
+    assert_eq!(machine.load_bias(), 0);
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+pub fn test_pie_unsupported_relocation() {
+    // R_RISCV_64 needs a symbol, which no dynamic linker resolves.
+    let program = pie(2);
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .layout(AddressSpaceLayout {
+            load_base: Some(0x20000),
+            ..Default::default()
+        })
+        .build();
+    assert!(matches!(
+        machine.load_program(&program, &["pie".into()]),
+        Err(Error::ElfParseError(_))
+    ));
+}
@@ -0,0 +1,134 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, A7, SP};
+use ckb_vm::syscalls::plugin::{
+    CkbVmPluginDescriptor, CkbVmPluginHost, PluginLibrary, CKB_VM_PLUGIN_ABI_VERSION,
+    CKB_VM_PLUGIN_OK,
+};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory,
+    SupportMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, to_riscv};
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+
+// Syscall 2000 adds the configured value to a0, and stores the result at
+// the address in a1.
+struct Adder {
+    value: u64,
+}
+
+unsafe extern "C" fn create(config: *const u8, config_length: usize) -> *mut c_void {
+    if config_length != 8 {
+        return std::ptr::null_mut();
+    }
+    let mut value = [0u8; 8];
+    value.copy_from_slice(std::slice::from_raw_parts(config, 8));
+    Box::into_raw(Box::new(Adder {
+        value: u64::from_le_bytes(value),
+    })) as *mut c_void
+}
+
+unsafe extern "C" fn destroy(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Adder));
+    DESTROYED.fetch_add(1, Ordering::SeqCst);
+}
+
+unsafe extern "C" fn ecall(
+    instance: *mut c_void,
+    host: *const CkbVmPluginHost,
+    machine: *mut c_void,
+) -> c_int {
+    let adder = &*(instance as *const Adder);
+    let host = &*host;
+    if (host.reg)(machine, A7) != 2000 {
+        return 0;
+    }
+    if (host.add_cycles)(machine, 10) != CKB_VM_PLUGIN_OK {
+        return -1;
+    }
+    let result = (host.reg)(machine, A0) + adder.value;
+    let address = (host.reg)(machine, A1);
+    let bytes = result.to_le_bytes();
+    if (host.store_bytes)(machine, address, bytes.as_ptr(), 8) != CKB_VM_PLUGIN_OK {
+        return -2;
+    }
+    (host.set_register)(machine, A0, result);
+    1
+}
+
+fn descriptor(abi_version: u32) -> &'static CkbVmPluginDescriptor {
+    Box::leak(Box::new(CkbVmPluginDescriptor {
+        abi_version,
+        name: b"adder\0".as_ptr() as *const c_char,
+        create,
+        destroy,
+        initialize: None,
+        ecall,
+    }))
+}
+
+fn program() -> ckb_vm::Bytes {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 5),
+        pack_i(insts::OP_ADDI, A1 as u8, SP as u8, -16),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 2000),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i: &Instruction| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+#[test]
+pub fn test_syscall_plugin() {
+    let library =
+        unsafe { PluginLibrary::from_static(descriptor(CKB_VM_PLUGIN_ABI_VERSION)) }.unwrap();
+    assert_eq!(library.name(), "adder");
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, 100);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .syscall_plugin(&library, &37u64.to_le_bytes())
+        .unwrap()
+        .build();
+    machine.load_program(&program(), &["main".into()]).unwrap();
+    let address = machine.registers()[SP] - 16;
+    assert_eq!(machine.run(), Ok(42));
+    assert_eq!(machine.cycles(), 10);
+    assert_eq!(machine.memory_mut().load64(&address), Ok(42));
+    drop(machine);
+    assert_eq!(DESTROYED.load(Ordering::SeqCst), 1);
+
+    // Plugins reject bad configs.
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, 100);
+    assert!(DefaultMachineBuilder::new(core)
+        .syscall_plugin(&library, b"bad")
+        .is_err());
+
+    // Syscalls the plugin fails abort execution.
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, 5);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .syscall_plugin(&library, &1u64.to_le_bytes())
+        .unwrap()
+        .build();
+    machine.load_program(&program(), &["main".into()]).unwrap();
+    assert_eq!(
+        machine.run(),
+        Err(Error::External("plugin adder ecall returns -1".to_string()))
+    );
+}
+
+#[test]
+pub fn test_syscall_plugin_loading() {
+    assert!(
+        unsafe { PluginLibrary::from_static(descriptor(CKB_VM_PLUGIN_ABI_VERSION + 1)) }.is_err()
+    );
+    #[cfg(unix)]
+    assert!(PluginLibrary::open("/nonexistent/libplugin.so").is_err());
+}
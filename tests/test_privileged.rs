@@ -0,0 +1,136 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::privileged::{
+    CSR_MCAUSE, CSR_MEPC, CSR_MSCRATCH, CSR_MSTATUS, CSR_MTVAL, CSR_MTVEC,
+};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, A2, A3, A7, T0, T1};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Instruction,
+    SparseMemory, WXorXMemory, ISA_IMC, ISA_PRIV,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_j, pack_r, pack_s, pack_u, to_riscv};
+
+fn csr(op: u16, rd: usize, rs1: usize, csr: u16) -> Instruction {
+    pack_i(op, rd as u8, rs1 as u8, i32::from(csr))
+}
+
+// Installs a trap handler, drops to user mode and runs `user`. The handler
+// saves mcause, mepc and mtval in a1, a2 and a3, then exits with a0 from
+// machine mode.
+fn trap_program(user: Instruction) -> Bytes {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    let code: Vec<u8> = [
+        pack_u(insts::OP_AUIPC, t0, 0),
+        pack_i(insts::OP_ADDI, t1, t0, 36),
+        csr(insts::OP_CSRRW, 0, T1, CSR_MTVEC),
+        pack_i(insts::OP_ADDI, t1, t0, 24),
+        csr(insts::OP_CSRRW, 0, T1, CSR_MEPC),
+        pack_r(insts::OP_MRET, 0, 0, 0),
+        // User mode, at offset 24.
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 5),
+        user,
+        pack_j(insts::OP_JAL, 0, 0),
+        // Trap handler, at offset 36.
+        csr(insts::OP_CSRRS, A1, 0, CSR_MCAUSE),
+        csr(insts::OP_CSRRS, A2, 0, CSR_MEPC),
+        csr(insts::OP_CSRRS, A3, 0, CSR_MTVAL),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+fn run(program: &Bytes, isa: u64) -> (Result<i8, Error>, Vec<u64>) {
+    run_with_faults(program, isa, false)
+}
+
+fn run_with_faults(
+    program: &Bytes,
+    isa: u64,
+    guest_memory_faults: bool,
+) -> (Result<i8, Error>, Vec<u64>) {
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .guest_memory_faults(guest_memory_faults)
+        .build();
+    machine.load_program(program, &["priv".into()]).unwrap();
+    let result = machine.run();
+    let registers = machine.registers().to_vec();
+    (result, registers)
+}
+
+#[test]
+pub fn test_user_ecall_traps_to_guest() {
+    let program = trap_program(pack_i(insts::OP_ECALL, 0, 0, 0));
+    let (result, registers) = run(&program, ISA_IMC | ISA_PRIV);
+    assert_eq!(result, Ok(5));
+    assert_eq!(registers[A1], 8);
+    // mepc points at the ECALL.
+    assert_eq!(registers[A2], registers[T0] + 28);
+
+    let program = trap_program(pack_i(insts::OP_EBREAK, 0, 0, 0));
+    let (result, registers) = run(&program, ISA_IMC | ISA_PRIV);
+    assert_eq!(result, Ok(5));
+    assert_eq!(registers[A1], 3);
+    assert_eq!(registers[A3], registers[T0] + 28);
+}
+
+#[test]
+pub fn test_user_csr_access_is_illegal() {
+    let user = csr(insts::OP_CSRRS, A1, 0, CSR_MSCRATCH);
+    let program = trap_program(user);
+    let (result, registers) = run(&program, ISA_IMC | ISA_PRIV);
+    assert_eq!(result, Ok(5));
+    assert_eq!(registers[A1], 2);
+    assert_eq!(registers[A3], u64::from(to_riscv(user).unwrap()));
+
+    // Without ISA_PRIV the CSR instructions do not decode.
+    let (result, _) = run(&program, ISA_IMC);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+}
+
+#[test]
+pub fn test_csr_without_trap_vector() {
+    // Before a trap vector is installed, invalid accesses are host errors.
+    let code: Vec<u8> = [
+        csr(insts::OP_CSRRWI, 0, 8, CSR_MSTATUS),
+        csr(insts::OP_CSRRS, A0, 0, CSR_MSTATUS),
+        csr(insts::OP_CSRRW, 0, A0, 0x7c0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let (result, registers) = run(&minimal_elf::<u64>(&code), ISA_IMC | ISA_PRIV);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+    // Only MIE is set, MPP is left at user mode.
+    assert_eq!(registers[A0], 8);
+}
+
+#[test]
+pub fn test_memory_faults_trap_to_guest() {
+    // Out of bound load.
+    let program = trap_program(pack_i(insts::OP_LD_VERSION1, A1 as u8, 0, -8));
+    let (result, registers) = run_with_faults(&program, ISA_IMC | ISA_PRIV, true);
+    assert_eq!(result, Ok(5));
+    assert_eq!(registers[A1], 5);
+    assert_eq!(registers[A2], registers[T0] + 28);
+    assert_eq!(registers[A3], u64::MAX - 7);
+
+    // Store to the executable page of the program.
+    let program = trap_program(pack_s(insts::OP_SD, T0 as u8, A0 as u8, 8));
+    let (result, registers) = run_with_faults(&program, ISA_IMC | ISA_PRIV, true);
+    assert_eq!(result, Ok(5));
+    assert_eq!(registers[A1], 7);
+    assert_eq!(registers[A3], registers[T0] + 8);
+
+    // Memory errors go to the host unless enabled.
+    let (result, _) = run(&program, ISA_IMC | ISA_PRIV);
+    assert_eq!(result, Err(Error::MemWriteOnExecutablePage));
+}
@@ -0,0 +1,99 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::profiler::{Profiler, ProfilerOptions};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A7, T0};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, TraceMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, to_riscv};
+
+type Core = DefaultCoreMachine<u64, SparseMemory<u64>>;
+
+// Counts t0 down from 10 in a loop of two instructions, then exits.
+fn build() -> DefaultMachine<Core> {
+    let t0 = T0 as u8;
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 10),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_b(insts::OP_BNE, t0, 0, -4),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core = Core::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(|_| 1))
+        .profiler(ProfilerOptions::default())
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["profiler".into()])
+        .unwrap();
+    machine
+}
+
+fn check(profiler: &Profiler, entry: u64) {
+    assert_eq!(profiler.instructions(), 23);
+    assert_eq!(profiler.cycles(), 23);
+    assert_eq!(
+        profiler.opcodes(),
+        vec![
+            (insts::OP_ADDI, 12, 12),
+            (insts::OP_BNE, 10, 10),
+            (insts::OP_ECALL, 1, 1)
+        ]
+    );
+    assert_eq!(profiler.regions(), vec![(entry & !0xff, 23)]);
+
+    let blocks = profiler.hot_blocks(2);
+    assert_eq!(blocks.len(), 2);
+    let hottest = &blocks[0];
+    assert_eq!(hottest.address, entry + 4);
+    assert_eq!(hottest.executions, 9);
+    assert_eq!(hottest.instructions, 18);
+    assert_eq!(hottest.cycles, 18);
+    assert_eq!(hottest.code.len(), 2);
+    assert_eq!(hottest.code[0], (entry + 4, "addi t0, t0, -1".to_string()));
+    assert_eq!(hottest.code[1].0, entry + 8);
+    assert!(hottest.code[1].1.starts_with("bne t0, zero"));
+    assert_eq!(blocks[1].address, entry);
+    assert_eq!(blocks[1].executions, 1);
+    assert_eq!(blocks[1].instructions, 3);
+
+    let mut dump = vec![];
+    profiler.write(&mut dump, 1).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    assert!(dump.starts_with("instructions: 23 cycles: 23\n"));
+    assert!(dump.contains("ADDI"));
+    assert!(!dump.contains("BNE "));
+    assert!(dump.contains(&format!("0x{:x}: addi t0, t0, -1", entry + 4)));
+}
+
+#[test]
+pub fn test_profiler() {
+    let mut machine = build();
+    let entry = machine.pc().to_owned();
+    assert_eq!(machine.run(), Ok(0));
+    check(machine.profiler().unwrap(), entry);
+
+    machine.profiler_mut().unwrap().clear();
+    assert_eq!(machine.profiler().unwrap().instructions(), 0);
+    assert!(machine.profiler().unwrap().hot_blocks(10).is_empty());
+}
+
+#[test]
+pub fn test_profiler_trace() {
+    let mut machine = TraceMachine::new(build());
+    let entry = machine.pc().to_owned();
+    assert_eq!(machine.run(), Ok(0));
+    check(machine.machine.profiler().unwrap(), entry);
+}
+
+#[test]
+pub fn test_profiler_disabled() {
+    let core = Core::new(ISA_IMC, VERSION2, u64::MAX);
+    let machine = DefaultMachineBuilder::new(core).build();
+    assert!(machine.profiler().is_none());
+}
@@ -0,0 +1,88 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::provenance::{run_provenance, ProvenanceTracker};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7, SP, T0, T1, T2};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Register, SparseMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_s, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load(code: &[Instruction]) -> Machine {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["provenance".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_provenance_registers_and_memory() {
+    let (t0, t1, t2, sp) = (T0 as u8, T1 as u8, T2 as u8, SP as u8);
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, t0, 0, 5),
+        pack_i(insts::OP_ADDI, t1, t0, 1),
+        pack_s(insts::OP_SD, sp, t1, -16),
+        pack_s(insts::OP_SB, sp, t0, -13),
+        pack_i(insts::OP_LD_VERSION1, t2, sp, -16),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]);
+    let entry = machine.pc().to_u64();
+    let sp_value = machine.registers()[SP].to_u64();
+    let mut tracker = ProvenanceTracker::default();
+    assert_eq!(run_provenance(&mut machine, &mut tracker), Ok(0));
+
+    assert_eq!(tracker.register_writer(T0), Some(entry));
+    assert_eq!(tracker.register_writer(T1), Some(entry + 4));
+    assert_eq!(tracker.register_writer(T2), Some(entry + 16));
+    assert_eq!(tracker.register_writer(A7), Some(entry + 24));
+    // The syscall result.
+    assert_eq!(tracker.register_writer(A0), Some(entry + 28));
+    // Set by the loader.
+    assert_eq!(tracker.register_writer(SP), None);
+
+    assert_eq!(tracker.memory_writer(sp_value - 13), Some(entry + 12));
+    assert_eq!(tracker.memory_writer(sp_value - 16), Some(entry + 12));
+    assert_eq!(tracker.memory_writer(sp_value - 12), Some(entry + 8));
+    assert_eq!(tracker.memory_writer(sp_value - 8), None);
+    assert_eq!(
+        tracker.memory_writers(sp_value - 16, 16),
+        vec![(sp_value - 16, entry + 12), (sp_value - 12, entry + 8)]
+    );
+
+    tracker.clear();
+    assert_eq!(tracker.register_writer(T0), None);
+    assert_eq!(tracker.memory_writer(sp_value - 16), None);
+}
+
+#[test]
+pub fn test_provenance_after_error() {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, t0, 0, -16),
+        pack_i(insts::OP_ADDI, t1, 0, 1),
+        pack_s(insts::OP_SD, t0, t1, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]);
+    let entry = machine.pc().to_u64();
+    let mut tracker = ProvenanceTracker::default();
+    assert_eq!(
+        run_provenance(&mut machine, &mut tracker),
+        Err(Error::MemOutOfBound)
+    );
+    // The bad address comes from the first instruction, the failed store
+    // wrote nothing.
+    assert_eq!(tracker.register_writer(T0), Some(entry));
+    assert_eq!(tracker.memory_writer(0u64.wrapping_sub(16)), None);
+}
@@ -0,0 +1,99 @@
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::machine::qemu::{
+    compare_qemu_trace, QemuCompareOptions, QemuDivergenceKind, QemuTrace,
+};
+use ckb_vm::machine::{DefaultMachine, VERSION1};
+use ckb_vm::registers::A0;
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, SupportMachine,
+    ISA_IMC,
+};
+use std::fmt::Write;
+use std::fs;
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load_simple64() -> Machine {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine
+}
+
+fn write_state(log: &mut String, machine: &Machine) {
+    writeln!(log, " pc       {:016x}", machine.pc()).unwrap();
+    writeln!(log, " mhartid  0000000000000000").unwrap();
+    for (i, value) in machine.registers().iter().enumerate() {
+        write!(log, " x{}/r{} {:016x}", i, i, value).unwrap();
+        if i % 4 == 3 {
+            writeln!(log).unwrap();
+        }
+    }
+}
+
+// Renders a log in the format of `qemu-riscv64 -one-insn-per-tb -d in_asm,cpu`
+// by stepping simple64.
+fn qemu_log_simple64() -> String {
+    let mut machine = load_simple64();
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    let mut log = String::new();
+    machine.set_running(true);
+    while machine.running() {
+        writeln!(log, "----------------\nIN: \nPriv: 0; Virt: 0").unwrap();
+        writeln!(log, "0x{:016x}:  00000013          nop\n", machine.pc()).unwrap();
+        write_state(&mut log, &machine);
+        machine.step(&mut decoder).unwrap();
+    }
+    log
+}
+
+#[test]
+pub fn test_parse_qemu_trace() {
+    let log = "IN: \n0x00000000000100b0:  1141          addi            sp,sp,-16\n\n \
+               pc       00000000000100b0\n \
+               x0/zero  0000000000000000 x1/ra    0000000000000001\n \
+               x10/a0   00000000000000ff\n";
+    let trace = QemuTrace::parse(log).unwrap();
+    assert_eq!(trace.instructions.len(), 1);
+    assert_eq!(trace.instructions[0].pc, 0x100b0);
+    assert_eq!(trace.instructions[0].instruction, 0x1141);
+    assert_eq!(trace.instructions[0].disassembly, "addi sp,sp,-16");
+    assert_eq!(trace.states.len(), 1);
+    assert_eq!(trace.states[0].pc, 0x100b0);
+    assert_eq!(trace.states[0].registers[1], 1);
+    assert_eq!(trace.states[0].registers[A0], 0xff);
+}
+
+#[test]
+pub fn test_compare_qemu_trace() {
+    let trace = QemuTrace::parse(&qemu_log_simple64()).unwrap();
+    let mut machine = load_simple64();
+    let result = compare_qemu_trace(&mut machine, &trace, &QemuCompareOptions::default());
+    assert_eq!(result.unwrap(), None);
+
+    let mut bad_trace = trace.clone();
+    bad_trace.states[10].registers[A0] ^= 1;
+    let mut machine = load_simple64();
+    let divergence = compare_qemu_trace(&mut machine, &bad_trace, &QemuCompareOptions::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(divergence.state, 10);
+    assert!(matches!(
+        divergence.kind,
+        QemuDivergenceKind::Registers(ref registers) if registers[0].0 == A0
+    ));
+    assert_eq!(divergence.context.len(), 8);
+    assert!(divergence.to_string().contains("a0: qemu"));
+
+    let mut machine = load_simple64();
+    let options = QemuCompareOptions {
+        ignored_registers: vec![A0],
+        ..Default::default()
+    };
+    let result = compare_qemu_trace(&mut machine, &bad_trace, &options);
+    assert_eq!(result.unwrap(), None);
+}
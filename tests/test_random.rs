@@ -0,0 +1,118 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A7, SP};
+use ckb_vm::syscalls::random::{ChaChaRng, RandomSyscalls, RANDOM_SYSCALL_NUMBER};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory, ISA_IMC,
+    RISCV_MAX_MEMORY,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_u, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+// Fills `length` bytes below the stack pointer, then exits with the first
+// byte.
+fn build(seed: [u8; 32], length: i32) -> Machine {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, SP as u8, -64),
+        pack_i(insts::OP_ADDI, A1 as u8, 0, length),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, RANDOM_SYSCALL_NUMBER as i32),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_LB_VERSION1, A0 as u8, SP as u8, -64),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let syscalls = RandomSyscalls::new(seed);
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(syscalls))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["random".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_chacha_rng() {
+    // RFC 8439 A.1, test vector #1: all zero key and nonce, block 0.
+    let mut rng = ChaChaRng::new([0; 32]);
+    let mut bytes = [0u8; 8];
+    rng.fill_bytes(&mut bytes);
+    assert_eq!(bytes, [0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90]);
+
+    // The output doesn't depend on how it is requested.
+    let mut whole = [0u8; 200];
+    ChaChaRng::new([7; 32]).fill_bytes(&mut whole);
+    let mut rng = ChaChaRng::new([7; 32]);
+    let mut pieces = [0u8; 200];
+    for chunk in pieces.chunks_mut(33) {
+        rng.fill_bytes(chunk);
+    }
+    assert_eq!(whole, pieces);
+    assert_eq!(rng.position(), 200);
+
+    let mut rng = ChaChaRng::new([7; 32]);
+    rng.seek(130);
+    let mut tail = [0u8; 70];
+    rng.fill_bytes(&mut tail);
+    assert_eq!(tail[..], whole[130..]);
+
+    let mut other = [0u8; 200];
+    ChaChaRng::new([8; 32]).fill_bytes(&mut other);
+    assert_ne!(whole, other);
+}
+
+#[test]
+pub fn test_random_syscall() {
+    let seed = [42; 32];
+    let mut machine = build(seed, 40);
+    let result = machine.run();
+    let mut expected = [0u8; 40];
+    let mut rng = ChaChaRng::new(seed);
+    rng.fill_bytes(&mut expected);
+    assert_eq!(result, Ok(expected[0] as i8));
+    let addr = machine.registers()[SP] - 64;
+    let written = machine.memory_mut().load_bytes(addr, 40).unwrap();
+    assert_eq!(written[..], expected[..]);
+
+    // Machines seeded the same way see the same bytes.
+    let mut again = build(seed, 40);
+    assert_eq!(again.run(), result);
+}
+
+#[test]
+pub fn test_random_syscall_shared_rng() {
+    let seed = [1; 32];
+    let syscalls = RandomSyscalls::new(seed);
+    let rng = syscalls.rng();
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, A0 as u8, SP as u8, -64),
+        pack_i(insts::OP_ADDI, A1 as u8, 0, 16),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, RANDOM_SYSCALL_NUMBER as i32),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+        // The second call writes past the end of memory.
+        pack_u(insts::OP_LUI, A0 as u8, RISCV_MAX_MEMORY as i32),
+        pack_i(insts::OP_ADDI, A0 as u8, A0 as u8, -8),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(syscalls))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["random".into()])
+        .unwrap();
+    // Only the successful call consumed the stream.
+    assert_eq!(machine.run(), Err(Error::MemOutOfBound));
+    assert_eq!(rng.lock().unwrap().position(), 16);
+}
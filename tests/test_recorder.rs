@@ -0,0 +1,123 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::recorder::{read_header, record, RecordEntry, ReplayMachine};
+use ckb_vm::machine::{DefaultMachine, VERSION1};
+use ckb_vm::memory::recording::RecordingMemory;
+use ckb_vm::registers::{A0, A7, SP, T0};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, SparseMemory,
+    SupportMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_s, to_riscv};
+
+type Core = DefaultCoreMachine<u64, RecordingMemory<SparseMemory<u64>>>;
+
+// Sums n to 1, storing each partial sum on the stack, and exits with the sum.
+fn program(n: i32) -> Bytes {
+    let (a0, t0) = (A0 as u8, T0 as u8);
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, n),
+        pack_i(insts::OP_ADDI, a0, 0, 0),
+        // loop:
+        pack_r(insts::OP_ADD, a0, a0, t0),
+        pack_s(insts::OP_SD, SP as u8, a0, -8),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        pack_s(insts::OP_BNE, t0, 0, -12),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    minimal_elf::<u64>(&code)
+}
+
+fn machine(program: &Bytes) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine.load_program(program, &["recorder".into()]).unwrap();
+    machine
+}
+
+fn entries(mut data: &[u8]) -> Vec<RecordEntry> {
+    read_header(&mut data).unwrap();
+    let mut entries = vec![];
+    while let Some(entry) = RecordEntry::read(&mut data).unwrap() {
+        entries.push(entry);
+    }
+    entries
+}
+
+#[test]
+pub fn test_record_and_replay() {
+    let program = program(3);
+    let mut recorded = machine(&program);
+    let mut data = vec![];
+    assert_eq!(record(&mut recorded, &mut data), Ok(6));
+
+    let entries = entries(&data);
+    // 2 instructions, 3 loop iterations of 4 and the exit syscall.
+    assert_eq!(entries.len(), 2 + 3 * 4 + 2 + 1);
+    assert_eq!(entries.last(), Some(&RecordEntry::Exit(6)));
+    let sp = recorded.registers()[SP];
+    match &entries[3] {
+        RecordEntry::Step(step) => {
+            assert!(step.registers.is_empty());
+            assert_eq!(step.stores, vec![(sp - 8, 3u64.to_le_bytes().to_vec())]);
+        }
+        entry => panic!("Unexpected entry {:?}", entry),
+    }
+    match &entries[0] {
+        RecordEntry::Step(step) => assert_eq!(step.registers, vec![(T0 as u8, 3)]),
+        entry => panic!("Unexpected entry {:?}", entry),
+    }
+
+    let mut replay = ReplayMachine::new(machine(&program));
+    assert_eq!(replay.replay(&data[..]), Ok(None));
+    assert_eq!(replay.machine.registers(), recorded.registers());
+    assert_eq!(replay.machine.cycles(), recorded.cycles());
+}
+
+#[test]
+pub fn test_replay_divergence() {
+    let mut data = vec![];
+    record(&mut machine(&program(3)), &mut data).unwrap();
+
+    let mut replay = ReplayMachine::new(machine(&program(4)));
+    let divergence = replay.replay(&data[..]).unwrap().unwrap();
+    assert_eq!(divergence.step, 0);
+    match divergence.actual {
+        RecordEntry::Step(step) => assert_eq!(step.registers, vec![(T0 as u8, 4)]),
+        entry => panic!("Unexpected entry {:?}", entry),
+    }
+
+    // The record ends before the run.
+    let short = &data[..data.len() - 2];
+    let mut replay = ReplayMachine::new(machine(&program(3)));
+    let divergence = replay.replay(short).unwrap().unwrap();
+    assert_eq!(divergence.expected, None);
+    assert_eq!(divergence.actual, RecordEntry::Exit(6));
+}
+
+#[test]
+pub fn test_replay_invalid_record() {
+    let mut data = vec![];
+    record(&mut machine(&program(3)), &mut data).unwrap();
+
+    let mut corrupted = data.clone();
+    corrupted[0] ^= 1;
+    let mut replay = ReplayMachine::new(machine(&program(3)));
+    assert!(matches!(
+        replay.replay(&corrupted[..]),
+        Err(Error::Unexpected(_))
+    ));
+
+    // Truncated in the middle of an entry.
+    let mut replay = ReplayMachine::new(machine(&program(3)));
+    assert!(matches!(replay.replay(&data[..14]), Err(Error::IO { .. })));
+
+    let mut trailing = data.clone();
+    trailing.extend_from_slice(&[1, 6]);
+    let mut replay = ReplayMachine::new(machine(&program(3)));
+    assert!(replay.replay(&trailing[..]).is_err());
+}
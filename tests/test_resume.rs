@@ -3,11 +3,16 @@ pub mod machine_build;
 use bytes::Bytes;
 use ckb_vm::cost_model::constant_cycles;
 use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::float::CSR_FCSR;
+use ckb_vm::machine::privileged::{CsrFile, PrivilegeMode, CSR_MSCRATCH, CSR_MTVEC};
 use ckb_vm::machine::trace::TraceMachine;
-use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, SupportMachine, VERSION0, VERSION1};
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachine, Machine as _, SupportMachine, VERSION0, VERSION1, VERSION2,
+};
 use ckb_vm::memory::{sparse::SparseMemory, wxorx::WXorXMemory};
-use ckb_vm::snapshot::{make_snapshot, resume, Snapshot};
-use ckb_vm::{DefaultMachineBuilder, Error, ISA_IMC};
+use ckb_vm::snapshot::{make_snapshot, resume, SealedSnapshot, Snapshot};
+use ckb_vm::{CoreMachine, DefaultMachineBuilder, Error, ISA_D, ISA_F, ISA_IMC, ISA_PRIV};
+use serde::Serialize;
 use std::fs::File;
 use std::io::Read;
 
@@ -145,6 +150,189 @@ pub fn resume_interpreter_2_interpreter(version: u32, except_cycles: u64) {
     assert_eq!(cycles1 + cycles2, except_cycles);
 }
 
+#[test]
+fn test_resume_sealed_snapshot() {
+    let buffer = load_program();
+    let key = [7u8; 32];
+
+    let mut machine1 = MachineTy::Interpreter.build(VERSION1, 8126917 - 30);
+    machine1
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    assert_eq!(machine1.run().unwrap_err(), Error::CyclesExceeded);
+    let cycles1 = machine1.cycles();
+    let sealed = machine1.snapshot().unwrap().seal(&key);
+    assert_ne!(sealed, machine1.snapshot().unwrap().seal(&key));
+
+    let mut tampered = sealed.clone();
+    tampered.payload[0] ^= 1;
+    assert!(tampered.open(&key).is_err());
+    assert!(sealed.open(&[8u8; 32]).is_err());
+    let downgraded = SealedSnapshot {
+        version: VERSION0,
+        ..sealed.clone()
+    };
+    assert!(downgraded.open(&key).is_err());
+
+    let mut machine2 = MachineTy::Interpreter.build(VERSION1, 30);
+    machine2.resume(&sealed.open(&key).unwrap()).unwrap();
+    assert_eq!(machine2.run(), Ok(0));
+    assert_eq!(cycles1 + machine2.cycles(), 8126917);
+}
+
+// Layout of Snapshot before privileged, floating point and vector state were
+// added to it.
+#[derive(Serialize)]
+struct BaselineSnapshot {
+    version: u32,
+    registers: [u64; 32],
+    pc: u64,
+    page_indices: Vec<u64>,
+    page_flags: Vec<u8>,
+    pages: Vec<Vec<u8>>,
+}
+
+#[test]
+fn test_resume_baseline_snapshot() {
+    let buffer = load_program();
+
+    let mut machine1 = MachineTy::Interpreter.build(VERSION1, 8126917 - 30);
+    machine1
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    assert_eq!(machine1.run().unwrap_err(), Error::CyclesExceeded);
+    let cycles1 = machine1.cycles();
+    let snapshot = machine1.snapshot().unwrap();
+    let baseline = bincode::serialize(&BaselineSnapshot {
+        version: snapshot.version,
+        registers: snapshot.registers,
+        pc: snapshot.pc,
+        page_indices: snapshot.page_indices.clone(),
+        page_flags: snapshot.page_flags.clone(),
+        pages: snapshot.pages.clone(),
+    })
+    .unwrap();
+    // Snapshots without the extensions keep the baseline encoding.
+    assert_eq!(bincode::serialize(&snapshot).unwrap(), baseline);
+
+    let decoded: Snapshot = bincode::deserialize(&baseline).unwrap();
+    let mut machine2 = MachineTy::Interpreter.build(VERSION1, 30);
+    machine2.resume(&decoded).unwrap();
+    assert_eq!(machine2.run(), Ok(0));
+    assert_eq!(cycles1 + machine2.cycles(), 8126917);
+}
+
+#[test]
+fn test_resume_privileged_state() {
+    let build = |isa| {
+        let core_machine =
+            DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 1000);
+        DefaultMachineBuilder::new(core_machine).build()
+    };
+    let key = [7u8; 32];
+
+    let mut machine1 = build(ISA_IMC | ISA_PRIV);
+    let state = machine1.privileged_mut().unwrap();
+    state.write_csr(CSR_MTVEC, 0x1000).unwrap();
+    state.write_csr(CSR_MSCRATCH, 42).unwrap();
+    state.set_mode(PrivilegeMode::User);
+    let snapshot = make_snapshot(&mut machine1).unwrap();
+
+    let mut machine2 = build(ISA_IMC | ISA_PRIV);
+    resume(&mut machine2, &snapshot).unwrap();
+    assert_eq!(machine2.privileged(), machine1.privileged());
+    assert_eq!(machine2.privileged().unwrap().mode(), PrivilegeMode::User);
+
+    let mut machine3 = build(ISA_IMC | ISA_PRIV);
+    resume(&mut machine3, &snapshot.seal(&key).open(&key).unwrap()).unwrap();
+    assert_eq!(machine3.privileged(), machine1.privileged());
+
+    let encoded = bincode::serialize(&snapshot).unwrap();
+    let decoded: Snapshot = bincode::deserialize(&encoded).unwrap();
+    assert_eq!(decoded.version, VERSION2);
+    let mut machine5 = build(ISA_IMC | ISA_PRIV);
+    resume(&mut machine5, &decoded).unwrap();
+    assert_eq!(machine5.privileged(), machine1.privileged());
+
+    let mut machine4 = build(ISA_IMC);
+    assert!(resume(&mut machine4, &snapshot).is_err());
+}
+
+#[test]
+fn test_resume_float_state() {
+    let build = |isa| {
+        let core_machine =
+            DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 1000);
+        DefaultMachineBuilder::new(core_machine).build()
+    };
+    let key = [7u8; 32];
+
+    let mut machine1 = build(ISA_IMC | ISA_F | ISA_D);
+    let registers = machine1.float_registers_mut().unwrap();
+    registers.set_register(3, 0x4009_21fb_5444_2d18);
+    registers.write_csr(CSR_FCSR, 0b010_00001).unwrap();
+    let snapshot = make_snapshot(&mut machine1).unwrap();
+
+    let mut machine2 = build(ISA_IMC | ISA_F | ISA_D);
+    resume(&mut machine2, &snapshot).unwrap();
+    assert_eq!(machine2.float_registers(), machine1.float_registers());
+
+    let mut machine3 = build(ISA_IMC | ISA_F | ISA_D);
+    resume(&mut machine3, &snapshot.seal(&key).open(&key).unwrap()).unwrap();
+    let registers = machine3.float_registers().unwrap();
+    assert_eq!(registers.register(3), 0x4009_21fb_5444_2d18);
+    assert_eq!(registers.read_csr(CSR_FCSR), Some(0b010_00001));
+
+    let encoded = bincode::serialize(&snapshot).unwrap();
+    let mut machine5 = build(ISA_IMC | ISA_F | ISA_D);
+    resume(&mut machine5, &bincode::deserialize(&encoded).unwrap()).unwrap();
+    assert_eq!(machine5.float_registers(), machine1.float_registers());
+
+    let mut machine4 = build(ISA_IMC);
+    assert!(resume(&mut machine4, &snapshot).is_err());
+}
+
+#[cfg(feature = "rvv")]
+#[test]
+fn test_resume_vector_state() {
+    use ckb_vm::machine::vector::{CSR_VCSR, CSR_VSTART};
+    use ckb_vm::ISA_V;
+
+    let build = |isa| {
+        let core_machine =
+            DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 1000);
+        DefaultMachineBuilder::new(core_machine).build()
+    };
+    let key = [7u8; 32];
+
+    let mut machine1 = build(ISA_IMC | ISA_V);
+    let registers = machine1.vector_registers_mut().unwrap();
+    // e32, m2 with an application vector length of 5.
+    assert_eq!(registers.set_vtype(0b010_001, 5), 5);
+    registers.register_mut(2)[..4].copy_from_slice(&[1, 2, 3, 4]);
+    registers.write_csr(CSR_VSTART, 3).unwrap();
+    registers.write_csr(CSR_VCSR, 0b101).unwrap();
+    let snapshot = make_snapshot(&mut machine1).unwrap();
+
+    let mut machine2 = build(ISA_IMC | ISA_V);
+    resume(&mut machine2, &snapshot).unwrap();
+    assert_eq!(machine2.vector_registers(), machine1.vector_registers());
+
+    let mut machine3 = build(ISA_IMC | ISA_V);
+    resume(&mut machine3, &snapshot.seal(&key).open(&key).unwrap()).unwrap();
+    assert_eq!(machine3.vector_registers(), machine1.vector_registers());
+    let registers = machine3.vector_registers().unwrap();
+    assert_eq!((registers.vtype(), registers.vl()), (0b010_001, 5));
+
+    let encoded = bincode::serialize(&snapshot).unwrap();
+    let mut machine5 = build(ISA_IMC | ISA_V);
+    resume(&mut machine5, &bincode::deserialize(&encoded).unwrap()).unwrap();
+    assert_eq!(machine5.vector_registers(), machine1.vector_registers());
+
+    let mut machine4 = build(ISA_IMC);
+    assert!(resume(&mut machine4, &snapshot).is_err());
+}
+
 pub fn resume_interpreter_2_asm(version: u32, except_cycles: u64) {
     let buffer = load_program();
 
@@ -0,0 +1,174 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::float::{CSR_FCSR, CSR_FFLAGS, CSR_FRM};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A6, A7, SP, T0};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Instruction,
+    SparseMemory, WXorXMemory, ISA_D, ISA_F, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_r4, pack_r5, pack_s, to_riscv};
+
+const RNE: u8 = 0;
+const RTZ: u8 = 1;
+const DYN: u8 = 7;
+
+fn csr(op: u16, rd: usize, rs1: usize, csr: u16) -> Instruction {
+    pack_i(op, rd as u8, rs1 as u8, i32::from(csr))
+}
+
+// Runs `code` followed by an exit with a0.
+fn run(code: &[Instruction], isa: u64) -> (Result<i8, Error>, Vec<u64>) {
+    let code: Vec<u8> = code
+        .iter()
+        .chain(&[
+            pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+            pack_i(insts::OP_ECALL, 0, 0, 0),
+        ])
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let program: Bytes = minimal_elf::<u64>(&code);
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine.load_program(&program, &["rvf".into()]).unwrap();
+    let result = machine.run();
+    let registers = machine.registers().to_vec();
+    (result, registers)
+}
+
+#[test]
+pub fn test_rvf_arithmetic() {
+    let (t0, a1, a2, a3, a4) = (T0 as u8, A1 as u8, A2 as u8, A3 as u8, A4 as u8);
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 1),
+        pack_r4(insts::OP_FCVT_D_L, 1, t0, 2, RNE),
+        pack_i(insts::OP_ADDI, t0, 0, 3),
+        pack_r4(insts::OP_FCVT_D_L, 2, t0, 2, RNE),
+        // f3 = 1.0 / 3.0, a1 = bits of f3
+        pack_r4(insts::OP_FDIV_D, 3, 1, 2, DYN),
+        pack_r(insts::OP_FMV_X_D, a1, 3, 0),
+        // a2 = bits of f3 as a single
+        pack_r4(insts::OP_FCVT_S_D, 4, 3, 1, RNE),
+        pack_r(insts::OP_FMV_X_W, a2, 4, 0),
+        // a3 = 3.0 * 3.0 + 1.0 as an integer
+        pack_r5(insts::OP_FMADD_D, 5, 2, 2, 1, RNE),
+        pack_r4(insts::OP_FCVT_L_D, a3, 5, 2, RTZ),
+        // a4 = 1.0 <= 3.0
+        pack_r(insts::OP_FLE_D, a4, 1, 2),
+        csr(insts::OP_CSRRS, A5, 0, CSR_FFLAGS),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+    ];
+    let (result, registers) = run(&code, ISA_IMC | ISA_F | ISA_D);
+    assert_eq!(result, Ok(0));
+    let third = 1.0f64 / 3.0;
+    assert_eq!(registers[A1], third.to_bits());
+    // FMV.X.W sign extends the single.
+    assert_eq!(registers[A2], (third as f32).to_bits() as i32 as i64 as u64);
+    assert_eq!(registers[A3], 10);
+    assert_eq!(registers[A4], 1);
+    // Only the division is inexact.
+    assert_eq!(registers[A5], 1);
+}
+
+#[test]
+pub fn test_rvf_dynamic_rounding_mode() {
+    let (t0, a1, a6) = (T0 as u8, A1 as u8, A6 as u8);
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 1),
+        pack_r4(insts::OP_FCVT_D_L, 1, t0, 2, RNE),
+        pack_i(insts::OP_ADDI, t0, 0, 3),
+        pack_r4(insts::OP_FCVT_D_L, 2, t0, 2, RNE),
+        // Rounds up.
+        csr(insts::OP_CSRRWI, 0, 3, CSR_FRM),
+        pack_r4(insts::OP_FDIV_D, 3, 1, 2, DYN),
+        pack_r(insts::OP_FMV_X_D, a1, 3, 0),
+        // Static rounding modes ignore frm.
+        pack_r4(insts::OP_FDIV_D, 3, 1, 2, RNE),
+        pack_r(insts::OP_FMV_X_D, a6, 3, 0),
+        csr(insts::OP_CSRRS, A2, 0, CSR_FCSR),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+    ];
+    let (result, registers) = run(&code, ISA_IMC | ISA_F | ISA_D);
+    assert_eq!(result, Ok(0));
+    let third = 1.0f64 / 3.0;
+    assert_eq!(registers[A1], third.to_bits() + 1);
+    assert_eq!(registers[A6], third.to_bits());
+    assert_eq!(registers[A2], 3 << 5 | 1);
+
+    // DYN is illegal when frm holds a reserved mode.
+    let code = [
+        csr(insts::OP_CSRRWI, 0, 5, CSR_FRM),
+        pack_r4(insts::OP_FADD_D, 3, 1, 2, DYN),
+    ];
+    let (result, _) = run(&code, ISA_IMC | ISA_F | ISA_D);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+}
+
+#[test]
+pub fn test_rvf_nan_boxing() {
+    let (t0, a1, a2, sp) = (T0 as u8, A1 as u8, A2 as u8, SP as u8);
+    let code = [
+        // 1.0f32, not NaN-boxed, reads as the canonical NaN.
+        pack_i(insts::OP_ADDI, t0, 0, 0x3f8),
+        pack_i(insts::OP_SLLI, t0, t0, 20),
+        pack_r(insts::OP_FMV_D_X, 1, t0, 0),
+        pack_r4(insts::OP_FADD_S, 2, 1, 1, RNE),
+        pack_r(insts::OP_FMV_X_W, a1, 2, 0),
+        // FLW boxes the loaded single, FSD stores the boxed bits.
+        pack_s(insts::OP_SW, sp, t0, -8),
+        pack_i(insts::OP_FLW, 3, sp, -8),
+        pack_s(insts::OP_FSD, sp, 3, -16),
+        pack_i(insts::OP_LD_VERSION1, a2, sp, -16),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+    ];
+    let (result, registers) = run(&code, ISA_IMC | ISA_F | ISA_D);
+    assert_eq!(result, Ok(0));
+    assert_eq!(registers[A1], 0x7fc0_0000);
+    assert_eq!(registers[A2], 0xffff_ffff_3f80_0000);
+}
+
+#[test]
+pub fn test_rvf_requires_isa() {
+    let code = [pack_r(insts::OP_FMV_D_X, 1, 0, 0)];
+    let (result, _) = run(&code, ISA_IMC);
+    assert!(result.is_err());
+    // FLD needs ISA_D.
+    let code = [pack_i(insts::OP_FLD, 1, SP as u8, -8)];
+    let (result, _) = run(&code, ISA_IMC | ISA_F);
+    assert!(result.is_err());
+    let code = [
+        pack_i(insts::OP_FLW, 1, SP as u8, -8),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+    ];
+    let (result, _) = run(&code, ISA_IMC | ISA_F);
+    assert_eq!(result, Ok(0));
+}
+
+#[test]
+pub fn test_rvf_decoding_round_trip() {
+    let decoder = build_decoder::<u64>(ISA_IMC | ISA_F | ISA_D, VERSION2);
+    for i in [
+        pack_i(insts::OP_FLD, 1, SP as u8, -8),
+        pack_s(insts::OP_FSW, SP as u8, 31, 2047),
+        pack_r4(insts::OP_FSQRT_S, 1, 2, 0, DYN),
+        pack_r4(insts::OP_FCVT_WU_D, A0 as u8, 2, 1, RTZ),
+        pack_r4(insts::OP_FCVT_S_LU, 1, A0 as u8, 3, RNE),
+        pack_r4(insts::OP_FCVT_D_S, 1, 2, 0, RNE),
+        pack_r5(insts::OP_FNMSUB_S, 1, 2, 3, 4, DYN),
+        pack_r(insts::OP_FSGNJX_D, 1, 2, 3),
+        pack_r(insts::OP_FCLASS_S, A0 as u8, 2, 0),
+        csr(insts::OP_CSRRS, A0, 0, CSR_FCSR),
+    ] {
+        let raw = to_riscv(i).unwrap();
+        let decoded = decoder.decode_bytes(&raw.to_le_bytes(), 0).unwrap();
+        assert_eq!(to_riscv(decoded), Some(raw));
+    }
+    // Rounding modes 5 and 6 are reserved.
+    let raw = to_riscv(pack_r4(insts::OP_FADD_D, 1, 2, 3, 5)).unwrap();
+    assert!(decoder.decode_bytes(&raw.to_le_bytes(), 0).is_err());
+}
@@ -0,0 +1,89 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::rvfi::{run_rvfi, write_rvfi, RvfiFormat, RvfiRecord, RVFI_DII_PACKET_SIZE};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, SupportMachine, ISA_IMC,
+};
+use std::fs;
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load_simple64() -> Machine {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_rvfi_records() {
+    let mut machine = load_simple64();
+    let mut records: Vec<RvfiRecord> = vec![];
+    let result = run_rvfi(&mut machine, |record| {
+        records.push(record.clone());
+        Ok(())
+    });
+    assert_eq!(result, Ok(0));
+    assert_eq!(records.len() as u64, machine.cycles());
+
+    // auipc gp, 2
+    assert_eq!(records[0].pc_rdata, 0x100c0);
+    assert_eq!(records[0].insn, 0x00002197);
+    assert_eq!(records[0].rd_addr, 3);
+    assert_eq!(records[0].rd_wdata, 0x120c0);
+    assert_eq!(records[0].ixl, 2);
+    // c.li a1, 0
+    assert_eq!(records[5].insn, 0x4581);
+    for (i, pair) in records.windows(2).enumerate() {
+        assert_eq!(pair[0].order, i as u64);
+        assert_eq!(pair[0].pc_wdata, pair[1].pc_rdata);
+        assert!(!pair[0].halt && !pair[0].trap);
+    }
+    assert!(records.last().unwrap().halt);
+
+    let store = records
+        .iter()
+        .find(|r| r.mem_wmask != 0)
+        .expect("simple64 stores to memory");
+    assert_eq!(store.rd_addr, 0);
+    assert_eq!(store.mem_rmask, 0);
+    assert_eq!(store.mem_wdata, store.rs2_rdata & mask(store.mem_wmask));
+    let load = records
+        .iter()
+        .find(|r| r.mem_rmask != 0)
+        .expect("simple64 loads from memory");
+    assert_eq!(load.mem_wmask, 0);
+    assert_eq!(load.mem_rdata & mask(load.mem_rmask), load.mem_rdata);
+}
+
+fn mask(byte_mask: u8) -> u64 {
+    if byte_mask == 0xff {
+        u64::MAX
+    } else {
+        (1u64 << (byte_mask.count_ones() * 8)) - 1
+    }
+}
+
+#[test]
+pub fn test_rvfi_formats() {
+    let mut text = vec![];
+    write_rvfi(&mut load_simple64(), &mut text, RvfiFormat::Text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    let first = text.lines().next().unwrap();
+    assert!(first.starts_with("order=0 insn=0x00002197 trap=0 halt=0"));
+    assert!(first.contains("rd_addr=3 rd_wdata=0x120c0 pc_rdata=0x100c0 pc_wdata=0x100c4"));
+
+    let mut packets = vec![];
+    write_rvfi(&mut load_simple64(), &mut packets, RvfiFormat::DiiPacket).unwrap();
+    assert_eq!(packets.len(), text.lines().count() * RVFI_DII_PACKET_SIZE);
+    let second = &packets[RVFI_DII_PACKET_SIZE..RVFI_DII_PACKET_SIZE * 2];
+    assert_eq!(&second[0..8], &1u64.to_le_bytes());
+    assert_eq!(&second[8..16], &0x100c4u64.to_le_bytes());
+    assert_eq!(second[84], 3);
+}
@@ -0,0 +1,206 @@
+#![cfg(feature = "rvv")]
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::vector::CSR_VLENB;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7, SP, T0, T1, T2, T3};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Instruction,
+    SparseMemory, WXorXMemory, ISA_IMC, ISA_V,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_r4, pack_s, to_riscv};
+
+// vtype values, vsew in bits 5:3 and vlmul in bits 2:0.
+const E8M1: i32 = 0b000_000;
+const E32M1: i32 = 0b010_000;
+const E64M2: i32 = 0b011_001;
+// vm is 0 for masked instructions.
+const MASKED: u8 = 0;
+const UNMASKED: u8 = 1;
+
+// Runs `code` followed by an exit with a0.
+fn run(code: &[Instruction], isa: u64) -> (Result<i8, Error>, Vec<u64>) {
+    let code: Vec<u8> = code
+        .iter()
+        .chain(&[
+            pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+            pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+            pack_i(insts::OP_ECALL, 0, 0, 0),
+        ])
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let program: Bytes = minimal_elf::<u64>(&code);
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, 10_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine.load_program(&program, &["rvv".into()]).unwrap();
+    let result = machine.run();
+    let registers = machine.registers().to_vec();
+    (result, registers)
+}
+
+// Stores the words 1, 2, 3 and 4 at sp - 32, and sets t1 to their address.
+fn store_words() -> Vec<Instruction> {
+    let (sp, t1, t2) = (SP as u8, T1 as u8, T2 as u8);
+    let mut code = vec![pack_i(insts::OP_ADDI, t1, sp, -32)];
+    for k in 0..4 {
+        code.push(pack_i(insts::OP_ADDI, t2, 0, k + 1));
+        code.push(pack_s(insts::OP_SW, t1, t2, 4 * k));
+    }
+    code
+}
+
+#[test]
+pub fn test_rvv_integer_arithmetic() {
+    let (t0, t1, t3, a1, a2, a3, a4) = (
+        T0 as u8, T1 as u8, T3 as u8, A1 as u8, A2 as u8, A3 as u8, A4 as u8,
+    );
+    let mut code = store_words();
+    code.extend([
+        // AVL is x0, vl is VLMAX.
+        pack_i(insts::OP_VSETVLI, t3, 0, E8M1),
+        pack_i(insts::OP_ADDI, t0, 0, 4),
+        pack_i(insts::OP_VSETVLI, a1, t0, E32M1),
+        pack_r4(insts::OP_VLE32_V, 1, t1, 0, UNMASKED),
+        // v3 = (v1 + 5) * 4
+        pack_r4(insts::OP_VADD_VI, 2, 1, 5, UNMASKED),
+        pack_r4(insts::OP_VMUL_VX, 3, 2, t0, UNMASKED),
+        pack_r4(insts::OP_VSE32_V, 3, t1, 0, UNMASKED),
+        pack_i(insts::OP_LW_VERSION1, a2, t1, 0),
+        pack_i(insts::OP_LW_VERSION1, a3, t1, 12),
+        // a4 = v7[0] + sum of v3, v7 being zero.
+        pack_r4(insts::OP_VREDSUM_VS, 4, 3, 7, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a4, 4, 0, UNMASKED),
+        pack_i(insts::OP_CSRRS, A5 as u8, 0, i32::from(CSR_VLENB)),
+    ]);
+    let (result, registers) = run(&code, ISA_IMC | ISA_V);
+    assert_eq!(result, Ok(0));
+    assert_eq!(registers[T3], 16);
+    assert_eq!(registers[A1], 4);
+    assert_eq!(registers[A2], 24);
+    assert_eq!(registers[A3], 36);
+    assert_eq!(registers[A4], 24 + 28 + 32 + 36);
+    assert_eq!(registers[A5], 16);
+}
+
+#[test]
+pub fn test_rvv_masked_instructions() {
+    let (t0, t1, t2, a1, a2) = (T0 as u8, T1 as u8, T2 as u8, A1 as u8, A2 as u8);
+    let mut code = store_words();
+    code.extend([
+        pack_i(insts::OP_ADDI, t0, 0, 4),
+        pack_i(insts::OP_VSETVLI, 0, t0, E32M1),
+        pack_r4(insts::OP_VLE32_V, 1, t1, 0, UNMASKED),
+        // v0 = v1 < 3, so only the first two elements are active.
+        pack_i(insts::OP_ADDI, t2, 0, 3),
+        pack_r4(insts::OP_VMSLT_VX, 0, 1, t2, UNMASKED),
+        pack_r4(insts::OP_VMV_V_I, 5, 0, 0, UNMASKED),
+        pack_r4(insts::OP_VADD_VV, 5, 1, 1, MASKED),
+        pack_r4(insts::OP_VREDSUM_VS, 6, 5, 7, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a1, 6, 0, UNMASKED),
+        // VMERGE takes the immediate where the mask is set: -1, -1, 3, 4.
+        pack_r4(insts::OP_VMERGE_VIM, 8, 1, 0x1f, MASKED),
+        pack_r4(insts::OP_VREDSUM_VS, 9, 8, 7, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a2, 9, 0, UNMASKED),
+    ]);
+    let (result, registers) = run(&code, ISA_IMC | ISA_V);
+    assert_eq!(result, Ok(0));
+    assert_eq!(registers[A1], 2 + 4);
+    assert_eq!(registers[A2], 5);
+}
+
+#[test]
+pub fn test_rvv_division() {
+    let (t0, t2, a1, a2, a3) = (T0 as u8, T2 as u8, A1 as u8, A2 as u8, A3 as u8);
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 1),
+        pack_i(insts::OP_VSETVLI, 0, t0, E32M1),
+        pack_i(insts::OP_ADDI, t2, 0, 7),
+        pack_r4(insts::OP_VMV_S_X, 1, 0, t2, UNMASKED),
+        // Division by zero returns all ones, the remainder the dividend.
+        pack_r4(insts::OP_VDIVU_VX, 2, 1, 0, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a1, 2, 0, UNMASKED),
+        pack_r4(insts::OP_VREM_VX, 3, 1, 0, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a2, 3, 0, UNMASKED),
+        // Signed division of 7 by -2 rounds toward zero.
+        pack_i(insts::OP_ADDI, t2, 0, -2),
+        pack_r4(insts::OP_VDIV_VX, 4, 1, t2, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, a3, 4, 0, UNMASKED),
+    ];
+    let (result, registers) = run(&code, ISA_IMC | ISA_V);
+    assert_eq!(result, Ok(0));
+    // VMV.X.S sign extends the element.
+    assert_eq!(registers[A1], u64::MAX);
+    assert_eq!(registers[A2], 7);
+    assert_eq!(registers[A3], -3i64 as u64);
+}
+
+#[test]
+pub fn test_rvv_illegal_configurations() {
+    let (t0, a1) = (T0 as u8, A1 as u8);
+    // Machines start with vill set.
+    let code = [pack_r4(insts::OP_VADD_VV, 1, 2, 3, UNMASKED)];
+    let (result, _) = run(&code, ISA_IMC | ISA_V);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+
+    // SEW of 128 bits is not supported, vill is set and vl cleared.
+    let code = [
+        pack_i(insts::OP_ADDI, t0, 0, 4),
+        pack_i(insts::OP_VSETVLI, a1, t0, 0b100_000),
+        pack_r4(insts::OP_VADD_VV, 1, 2, 3, UNMASKED),
+    ];
+    let (result, _) = run(&code, ISA_IMC | ISA_V);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+
+    // Register groups of LMUL = 2 must be aligned.
+    let code = [
+        pack_i(insts::OP_VSETVLI, a1, 0, E64M2),
+        pack_r4(insts::OP_VADD_VV, 2, 4, 6, UNMASKED),
+        pack_r4(insts::OP_VADD_VV, 1, 4, 6, UNMASKED),
+    ];
+    let (result, registers) = run(&code, ISA_IMC | ISA_V);
+    assert!(matches!(result, Err(Error::InvalidInstruction { .. })));
+    assert_eq!(registers[A1], 4);
+}
+
+#[test]
+pub fn test_rvv_requires_isa() {
+    let code = [pack_i(insts::OP_VSETVLI, A1 as u8, 0, E8M1)];
+    let (result, _) = run(&code, ISA_IMC);
+    assert!(result.is_err());
+}
+
+#[test]
+pub fn test_rvv_decoding_round_trip() {
+    let decoder = build_decoder::<u64>(ISA_IMC | ISA_V, VERSION2);
+    for i in [
+        pack_i(insts::OP_VSETVLI, A1 as u8, A0 as u8, 0x7ff),
+        pack_i(insts::OP_VSETIVLI, A1 as u8, 31, 0x3ff),
+        pack_r(insts::OP_VSETVL, A1 as u8, A0 as u8, A2 as u8),
+        pack_r4(insts::OP_VLE64_V, 8, A0 as u8, 0, MASKED),
+        pack_r4(insts::OP_VSE16_V, 31, SP as u8, 0, UNMASKED),
+        pack_r4(insts::OP_VSUB_VV, 1, 2, 3, MASKED),
+        pack_r4(insts::OP_VRSUB_VI, 1, 2, 0x10, UNMASKED),
+        pack_r4(insts::OP_VSRA_VX, 1, 2, A0 as u8, UNMASKED),
+        pack_r4(insts::OP_VMSGTU_VI, 0, 2, 3, UNMASKED),
+        pack_r4(insts::OP_VMERGE_VXM, 4, 2, A0 as u8, MASKED),
+        pack_r4(insts::OP_VMV_V_V, 4, 0, 2, UNMASKED),
+        pack_r4(insts::OP_VMV_X_S, A0 as u8, 2, 0, UNMASKED),
+        pack_r4(insts::OP_VMULHSU_VV, 1, 2, 3, UNMASKED),
+        pack_r4(insts::OP_VNMSAC_VX, 1, 2, A0 as u8, MASKED),
+        pack_i(insts::OP_CSRRS, A0 as u8, 0, i32::from(CSR_VLENB)),
+    ] {
+        let raw = to_riscv(i).unwrap();
+        let decoded = decoder.decode_bytes(&raw.to_le_bytes(), 0).unwrap();
+        assert_eq!(to_riscv(decoded), Some(raw));
+    }
+    // Strided loads are not supported.
+    let raw = to_riscv(pack_r4(insts::OP_VLE8_V, 1, A0 as u8, 0, UNMASKED)).unwrap();
+    assert!(decoder
+        .decode_bytes(&(raw | 0b10 << 26).to_le_bytes(), 0)
+        .is_err());
+}
@@ -0,0 +1,88 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::scheduler::{Schedulable, Scheduler};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7, T0};
+use ckb_vm::{DefaultCoreMachine, DefaultMachineBuilder, Error, SparseMemory, ISA_IMC};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+// Loops `iterations` times then exits with `exit_code`, in
+// 2 * iterations + 4 cycles.
+fn countdown(iterations: i32, exit_code: i32, max_cycles: u64) -> Machine {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, T0 as u8, 0, iterations),
+        pack_i(insts::OP_ADDI, T0 as u8, T0 as u8, -1),
+        pack_b(insts::OP_BNE, T0 as u8, 0, -4),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, exit_code),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, max_cycles);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["countdown".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_scheduler_priorities() {
+    let mut scheduler = Scheduler::new(100);
+    let a = scheduler.add(countdown(1000, 1, u64::MAX), 1).unwrap();
+    let b = scheduler.add(countdown(1000, 2, u64::MAX), 1).unwrap();
+    let c = scheduler.add(countdown(1000, 3, u64::MAX), 2).unwrap();
+    assert!(scheduler.add(countdown(1, 4, u64::MAX), 0).is_err());
+
+    // Equal virtual time goes to the machine added first, c runs two slices
+    // for each slice of a and b.
+    let order: Vec<_> = (0..8).map(|_| scheduler.step().unwrap()).collect();
+    assert_eq!(order, vec![a, b, c, c, a, b, c, c]);
+    assert_eq!(scheduler.stats(a).unwrap().cycles, 200);
+    assert_eq!(scheduler.stats(c).unwrap().cycles, 400);
+    assert_eq!(scheduler.stats(c).unwrap().preemptions, 4);
+
+    scheduler.run();
+    assert_eq!(scheduler.pending(), 0);
+    assert_eq!(scheduler.result(a), Some(&Ok(1)));
+    assert_eq!(scheduler.result(b), Some(&Ok(2)));
+    assert_eq!(scheduler.result(c), Some(&Ok(3)));
+    for id in [a, b, c] {
+        assert_eq!(scheduler.stats(id).unwrap().cycles, 2004);
+        assert_eq!(scheduler.machine(id).unwrap().cycles(), 2004);
+        assert_eq!(scheduler.stats(id).unwrap().slices, 21);
+    }
+    assert_eq!(scheduler.step(), None);
+}
+
+#[test]
+pub fn test_scheduler_max_cycles() {
+    let mut scheduler = Scheduler::new(100);
+    let a = scheduler.add(countdown(1000, 1, 250), 1).unwrap();
+    let b = scheduler.add(countdown(10, 2, u64::MAX), 1).unwrap();
+    scheduler.run();
+    // The machine's own limit is still enforced, and restored.
+    assert_eq!(scheduler.result(a), Some(&Err(Error::CyclesExceeded)));
+    assert_eq!(scheduler.stats(a).unwrap().cycles, 250);
+    assert_eq!(scheduler.machine(a).unwrap().max_cycles(), 250);
+    assert_eq!(scheduler.result(b), Some(&Ok(2)));
+
+    // Machines added later start with the virtual time of running ones.
+    let mut scheduler = Scheduler::new(100);
+    let a = scheduler.add(countdown(1000, 1, u64::MAX), 1).unwrap();
+    for _ in 0..5 {
+        assert_eq!(scheduler.step(), Some(a));
+    }
+    let b = scheduler.add(countdown(1000, 2, u64::MAX), 1).unwrap();
+    assert_eq!(scheduler.step(), Some(a));
+    assert_eq!(scheduler.step(), Some(b));
+    assert_eq!(scheduler.step(), Some(a));
+}
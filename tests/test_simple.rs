@@ -1,7 +1,10 @@
+use ckb_vm::machine::report::{ExitReason, ReportExit};
 use ckb_vm::machine::VERSION0;
+use ckb_vm::memory::MemoryFill;
 use ckb_vm::{
-    run, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory, Instruction, SparseMemory,
-    SupportMachine, ISA_IMC, RISCV_MAX_MEMORY,
+    run, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory, Instruction,
+    Isa, MachineConfig, Memory, SparseMemory, SupportMachine, TraceMachine, ISA_IMC, ISA_MOP,
+    RISCV_MAX_MEMORY,
 };
 use std::fs;
 
@@ -105,3 +108,184 @@ pub fn test_simple_cycles_overflow() {
     assert!(result.is_err());
     assert_eq!(result.unwrap_err(), Error::CyclesOverflow);
 }
+
+#[test]
+pub fn test_simple_machine_config() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let config = MachineConfig::new()
+        .version(VERSION0)
+        .max_cycles(708)
+        .instruction_cycle_func(Box::new(dummy_cycle_func));
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new_with_config(
+            config,
+        )
+        .unwrap()
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(SupportMachine::cycles(&machine), 708);
+}
+
+#[test]
+pub fn test_simple_memory_fill() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let build = |fill| {
+        let config = MachineConfig::new().memory_fill(fill);
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new_with_config(config)
+            .unwrap()
+            .build()
+    };
+    let mut machine = build(MemoryFill::Byte(0xAA));
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(
+        &machine.memory_mut().load_bytes(0, 4).unwrap()[..],
+        &[0xAA; 4]
+    );
+
+    let mut zero = build(MemoryFill::default());
+    assert_eq!(&zero.memory_mut().load_bytes(0, 4).unwrap()[..], &[0; 4]);
+
+    let mut random1 = build(MemoryFill::Random(7));
+    let mut random2 = build(MemoryFill::Random(7));
+    let data = random1.memory_mut().load_bytes(0x1000, 64).unwrap();
+    assert_eq!(data, random2.memory_mut().load_bytes(0x1000, 64).unwrap());
+    assert_ne!(&data[..], &[0; 64]);
+}
+
+#[test]
+pub fn test_simple_huge_pages() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    // Hosts without huge page support fall back to regular pages.
+    let config = MachineConfig::new().huge_pages(true);
+    let mut machine =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, FlatMemory<u64>>>::new_with_config(config)
+            .unwrap()
+            .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    // Sparse memory allocates pages lazily, and never uses huge pages.
+    assert!(!SparseMemory::<u64>::new().use_huge_pages());
+}
+
+#[test]
+pub fn test_simple_machine_config_invalid_isa() {
+    let config = MachineConfig::new()
+        .version(VERSION0)
+        .isa(Isa::from(ISA_MOP));
+    let result =
+        DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new_with_config(
+            config,
+        );
+    assert_eq!(result.err(), Some(Error::InvalidIsa(ISA_MOP)));
+}
+
+#[test]
+pub fn test_simple_machine_wide_isa() {
+    // Core machines keep the whole 64-bit mask, bits past ISA_ZICSR included.
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(1 << 40, VERSION0, 0);
+    assert_eq!(core.isa(), 1 << 40);
+    let result = DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_isa(
+        Isa::from(1 << 40),
+        VERSION0,
+        0,
+        RISCV_MAX_MEMORY,
+    );
+    assert_eq!(result.err(), Some(Error::InvalidIsa(1 << 40)));
+}
+
+#[test]
+pub fn test_simple_run_with_report() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(dummy_cycle_func))
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let report = machine.run_with_report(true);
+    assert_eq!(report.exit_reason, ExitReason::Exit(0));
+    assert_eq!(report.cycles, 708);
+    assert_eq!(report.instructions_retired, 708);
+    assert!(report.peak_memory > 0);
+    let opcode_stats = report.opcode_stats.unwrap();
+    assert_eq!(opcode_stats.values().sum::<u64>(), 708);
+}
+
+#[test]
+pub fn test_simple_run_with_report_cycles_exceeded() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, 700);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(dummy_cycle_func))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let report = machine.run_with_report(false);
+    assert_eq!(report.exit_reason, ExitReason::CyclesExceeded);
+    assert_eq!(report.instructions_retired, 700);
+    assert_eq!(report.result(), Err(Error::CyclesExceeded));
+    assert!(report.opcode_stats.is_none());
+}
+
+#[test]
+pub fn test_simple_run_with_report_to_report() {
+    let buffer = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(dummy_cycle_func))
+        .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    let report = machine.run_with_report(true).to_report();
+    assert_eq!(report.exit, ReportExit::Exit { code: 0 });
+    assert_eq!(report.cycles, 708);
+    assert_eq!(report.instructions_retired, 708);
+    let opcode_stats = report.opcode_stats.unwrap();
+    assert_eq!(opcode_stats.values().sum::<u64>(), 708);
+    assert_eq!(report.memory.memory_size, RISCV_MAX_MEMORY as u64);
+    assert!(report.memory.peak_memory > 0);
+    // The exit syscall.
+    assert_eq!(report.syscalls.len(), 1);
+    assert_eq!(report.syscalls[&93].calls, 1);
+    assert_eq!(report.abort_message, None);
+
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value()),
+    )
+    .build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    machine.update_pc(0);
+    machine.commit_pc();
+    let report = machine.run_with_report(false).to_report();
+    let error = Error::InvalidInstruction {
+        pc: 0,
+        instruction: 0,
+    };
+    assert_eq!(
+        report.exit,
+        ReportExit::Error {
+            kind: "invalid_instruction".to_string(),
+            message: error.to_string(),
+        }
+    );
+    assert!(report.opcode_stats.is_none());
+    assert!(report.syscalls.is_empty());
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "proptest")]
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::strategies::{instructions, program, InstructionOptions};
+use ckb_vm::{run, SparseMemory, ISA_A, ISA_B, ISA_IMC, RISCV_MAX_MEMORY};
+use ckb_vm_definitions::encoding::to_riscv;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn test_generated_instructions_round_trip(
+        generated in instructions::<u64>(ISA_IMC | ISA_A | ISA_B, InstructionOptions::all(), 1..32)
+    ) {
+        let decoder = build_decoder::<u64>(ISA_IMC | ISA_A | ISA_B, VERSION2);
+        for raw in generated {
+            let instruction = decoder.decode_bytes(&raw.to_le_bytes(), 0).unwrap();
+            prop_assert_eq!(to_riscv(instruction), Some(raw));
+        }
+    }
+
+    #[test]
+    fn test_generated_programs_exit_64(
+        program in program::<u64>(ISA_IMC | ISA_B, InstructionOptions::default(), 0..64)
+    ) {
+        let result = run::<u64, SparseMemory<u64>>(&program, &vec!["main".into()], RISCV_MAX_MEMORY);
+        prop_assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_generated_programs_exit_32(
+        program in program::<u32>(ISA_IMC | ISA_B, InstructionOptions::default(), 0..64)
+    ) {
+        let result = run::<u32, SparseMemory<u32>>(&program, &vec!["main".into()], RISCV_MAX_MEMORY);
+        prop_assert_eq!(result.unwrap(), 0);
+    }
+}
@@ -0,0 +1,161 @@
+use ckb_vm::decoder::DecodedInstruction;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::symbolic::{
+    lift, run_symbolic, BinaryOp, BranchDecision, Condition, Operand, Operation, SymbolicHooks,
+};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7, SP, T0, T1};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Register, SparseMemory, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, pack_s, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn program() -> Vec<Instruction> {
+    let (t0, t1, sp) = (T0 as u8, T1 as u8, SP as u8);
+    vec![
+        pack_i(insts::OP_ADDI, t0, 0, 5),
+        pack_s(insts::OP_SD, sp, t0, -8),
+        pack_i(insts::OP_LD_VERSION1, t1, sp, -8),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_b(insts::OP_BEQ, t0, t1, 8),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 1),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+}
+
+fn load(code: &[Instruction]) -> Machine {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["symbolic".into()])
+        .unwrap();
+    machine
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+    branches: Vec<BranchDecision>,
+    force: Option<bool>,
+}
+
+impl<M> SymbolicHooks<M> for Recorder {
+    fn register_write(&mut self, _machine: &M, index: usize, value: u64) {
+        self.events.push(format!("w x{}={}", index, value));
+    }
+
+    fn memory_read(&mut self, _machine: &M, _address: u64, size: u8, value: u64) {
+        self.events.push(format!("load{} {}", size, value));
+    }
+
+    fn memory_write(&mut self, _machine: &M, _address: u64, size: u8, value: u64) {
+        self.events.push(format!("store{} {}", size, value));
+    }
+
+    fn branch(&mut self, _machine: &M, branch: &BranchDecision) -> Option<bool> {
+        self.branches.push(branch.clone());
+        self.force
+    }
+}
+
+#[test]
+pub fn test_symbolic_lift() {
+    let code = program();
+    let lifted: Vec<Operation> = code
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            lift(
+                &DecodedInstruction::from_instruction(0x1000 + i as u64 * 4, *instruction).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        lifted[0],
+        Operation::Binary {
+            op: BinaryOp::Add,
+            word: false,
+            rd: T0,
+            rs1: 0,
+            rs2: Operand::Immediate(5),
+        }
+    );
+    assert_eq!(
+        lifted[1],
+        Operation::Store {
+            base: SP,
+            offset: -8,
+            src: T0,
+            size: 8,
+        }
+    );
+    assert_eq!(
+        lifted[2],
+        Operation::Load {
+            rd: T1,
+            base: SP,
+            offset: -8,
+            size: 8,
+            signed: true,
+        }
+    );
+    assert_eq!(
+        lifted[4],
+        Operation::Branch {
+            condition: Condition::Eq,
+            rs1: T0,
+            rs2: T1,
+            target: 0x1018,
+        }
+    );
+    assert_eq!(lifted[7], Operation::Ecall);
+}
+
+#[test]
+pub fn test_symbolic_hooks() {
+    let mut machine = load(&program());
+    let entry = machine.pc().to_u64();
+    let mut recorder = Recorder::default();
+    assert_eq!(run_symbolic(&mut machine, &mut recorder), Ok(0));
+    assert_eq!(
+        &recorder.events[..5],
+        &[
+            format!("w x{}=5", T0),
+            "store8 5".to_string(),
+            "load8 5".to_string(),
+            format!("w x{}=5", T1),
+            format!("w x{}=0", A0),
+        ]
+    );
+    assert_eq!(
+        recorder.branches,
+        vec![BranchDecision {
+            pc: entry + 16,
+            condition: Condition::Eq,
+            rs1: T0,
+            rs2: T1,
+            target: entry + 24,
+            taken: true,
+        }]
+    );
+}
+
+#[test]
+pub fn test_symbolic_forced_branch() {
+    let mut machine = load(&program());
+    let mut recorder = Recorder {
+        force: Some(false),
+        ..Default::default()
+    };
+    assert_eq!(run_symbolic(&mut machine, &mut recorder), Ok(1));
+    assert!(recorder.events.contains(&format!("w x{}=1", A0)));
+}
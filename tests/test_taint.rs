@@ -0,0 +1,118 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::taint::{run_taint, TaintReport, TaintSink, TaintTracker};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A7, SP, T0, T1, T2};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, Register, SparseMemory,
+    SupportMachine, Syscalls, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, pack_s, to_riscv};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+// Loads 4 bytes into the buffer at A0, following the CKB convention.
+struct LoadData;
+
+impl<Mac: SupportMachine> Syscalls<Mac> for LoadData {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1111 {
+            return Ok(false);
+        }
+        let buffer = machine.registers()[A0].to_u64();
+        let length = machine.registers()[A1].to_u64();
+        machine.memory_mut().store_bytes(buffer, &[4, 3, 2, 1])?;
+        machine
+            .memory_mut()
+            .store_bytes(length, &4u64.to_le_bytes())?;
+        machine.set_register(A0, Mac::REG::zero());
+        Ok(true)
+    }
+}
+
+fn load(code: &[Instruction]) -> Machine {
+    let code: Vec<u8> = code
+        .iter()
+        .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+        .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .syscall(Box::new(LoadData))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["taint".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_taint_memory_source() {
+    let (t0, t1, t2, sp) = (T0 as u8, T1 as u8, T2 as u8, SP as u8);
+    let mut machine = load(&[
+        pack_i(insts::OP_LD_VERSION1, t0, sp, -8),
+        pack_i(insts::OP_ADDI, t1, t0, 1),
+        pack_b(insts::OP_BEQ, t1, 0, 4),
+        pack_s(insts::OP_SD, sp, t1, -16),
+        pack_i(insts::OP_LD_VERSION1, t2, sp, -16),
+        pack_i(insts::OP_ADDI, t1, 0, 0),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]);
+    let entry = machine.pc().to_u64();
+    let sp_value = machine.registers()[SP].to_u64();
+    let mut tracker = TaintTracker::default();
+    tracker.taint_memory(sp_value - 8, 8, 0b1);
+    assert_eq!(run_taint(&mut machine, &mut tracker), Ok(0));
+    assert_eq!(
+        tracker.reports,
+        vec![TaintReport {
+            pc: entry + 8,
+            sink: TaintSink::Branch,
+            labels: 0b1
+        }]
+    );
+    assert_eq!(tracker.register_labels(T0), 0b1);
+    assert_eq!(tracker.register_labels(T1), 0);
+    assert_eq!(tracker.register_labels(T2), 0b1);
+    assert_eq!(tracker.memory_labels(sp_value - 16, 8), 0b1);
+    assert_eq!(tracker.memory_labels(sp_value - 24, 8), 0);
+}
+
+#[test]
+pub fn test_taint_syscall_source() {
+    let (a0, a1, a7, t0, t1, sp) = (A0 as u8, A1 as u8, A7 as u8, T0 as u8, T1 as u8, SP as u8);
+    let mut machine = load(&[
+        pack_i(insts::OP_ADDI, a0, sp, -64),
+        pack_i(insts::OP_ADDI, a1, sp, -8),
+        pack_i(insts::OP_ADDI, t0, 0, 16),
+        pack_s(insts::OP_SD, a1, t0, 0),
+        pack_i(insts::OP_ADDI, a7, 0, 1111),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+        pack_i(insts::OP_ADDI, a0, sp, -64),
+        pack_i(insts::OP_LW_VERSION1, t1, a0, 0),
+        pack_r(insts::OP_ADD, a0, t1, 0),
+        pack_i(insts::OP_ADDI, a7, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]);
+    let entry = machine.pc().to_u64();
+    let sp_value = machine.registers()[SP].to_u64();
+    let mut tracker = TaintTracker::default();
+    tracker.taint_syscall(1111, 0b10, true);
+    assert_eq!(run_taint(&mut machine, &mut tracker), Ok(4));
+    assert_eq!(tracker.memory_labels(sp_value - 64, 4), 0b10);
+    assert_eq!(tracker.memory_labels(sp_value - 60, 12), 0);
+    assert_eq!(
+        tracker.reports,
+        vec![TaintReport {
+            pc: entry + 40,
+            sink: TaintSink::SyscallArgument(A0),
+            labels: 0b10
+        }]
+    );
+}
@@ -0,0 +1,125 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::template::MachineTemplate;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::memory::shared::SharedMemory;
+use ckb_vm::memory::wxorx::WXorXMemory;
+use ckb_vm::registers::{A0, A1, A7, SP, T0};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, Memory,
+    Register, SupportMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_r, pack_s, to_riscv};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SharedMemory<u64>>>;
+type Machine = DefaultMachine<Core>;
+
+// Increments the counter right below SP and exits with its new value.
+fn template() -> MachineTemplate<Core> {
+    let code: Vec<u8> = [
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, SP as u8, -8),
+        pack_i(insts::OP_ADDI, A0 as u8, A0 as u8, 1),
+        pack_s(insts::OP_SD, SP as u8, A0 as u8, -8),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i: &Instruction| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core = Core::new(ISA_IMC, VERSION2, 1000);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["main".into()])
+        .unwrap();
+    // Setup done before the template is taken.
+    let sp = machine.registers()[SP].to_u64();
+    machine.memory_mut().store64(&(sp - 8), &5).unwrap();
+    MachineTemplate::new(machine)
+}
+
+#[test]
+pub fn test_template_instances() {
+    let template = template();
+    assert!(template.program_info().is_some());
+    for _ in 0..3 {
+        let mut machine = template
+            .instantiate(|builder| builder.instruction_cycle_func(Box::new(constant_cycles)))
+            .unwrap();
+        assert!(machine.memory_mut().inner_mut().shared_pages() > 0);
+        // Instances don't see the writes of each other.
+        assert_eq!(machine.run(), Ok(6));
+        assert_eq!(machine.cycles(), 5);
+    }
+    assert_eq!(template.inner().cycles(), 0);
+}
+
+#[test]
+pub fn test_template_max_cycles() {
+    let mut template = template();
+    let mut machine = template
+        .instantiate(|builder| builder.instruction_cycle_func(Box::new(constant_cycles)))
+        .unwrap();
+    machine.set_max_cycles(3);
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+
+    // Instances start with the max cycles of the template.
+    template.inner_mut().set_max_cycles(3);
+    let mut machine = template
+        .instantiate(|builder| builder.instruction_cycle_func(Box::new(constant_cycles)))
+        .unwrap();
+    assert_eq!(machine.max_cycles(), 3);
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+}
+
+#[test]
+pub fn test_fork() {
+    // Stores 1 below SP, then exits with the stored value plus a1, which
+    // differs between forks.
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, T0 as u8, 0, 1),
+        pack_s(insts::OP_SD, SP as u8, T0 as u8, -8),
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, SP as u8, -8),
+        pack_r(insts::OP_ADD, A0 as u8, A0 as u8, A1 as u8),
+        pack_s(insts::OP_SD, SP as u8, A0 as u8, -8),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i: &Instruction| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core = Core::new(ISA_IMC, VERSION2, 1000);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["main".into()])
+        .unwrap();
+    let entry = machine.pc().to_u64();
+    let sp = machine.registers()[SP];
+    assert_eq!(machine.run_until(entry + 8), Ok(None));
+
+    let fork = |machine: &Machine, a1: u64| {
+        let mut child = machine
+            .fork(|builder| builder.instruction_cycle_func(Box::new(constant_cycles)))
+            .unwrap();
+        assert!(child.memory_mut().inner_mut().shared_pages() > 0);
+        child.set_register(A1, a1);
+        child
+    };
+    let mut first = fork(&machine, 10);
+    let mut second = fork(&machine, 20);
+    assert_eq!(first.cycles(), 2);
+    assert_eq!(first.instret(), 2);
+    // Writes of the parent after forking are not visible to its forks.
+    machine.set_register(A1, 5);
+    assert_eq!(machine.run(), Ok(6));
+    assert_eq!(first.run(), Ok(11));
+    // Nor are writes of a fork visible to the others or to the parent.
+    assert_eq!(second.run(), Ok(21));
+    assert_eq!(machine.memory_mut().load64(&(sp - 8)), Ok(6));
+    assert_eq!(first.cycles(), machine.cycles());
+    assert_eq!(first.instret(), machine.instret());
+}
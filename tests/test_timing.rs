@@ -0,0 +1,97 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::{insts, Instruction};
+use ckb_vm::machine::timing::{PipelineConfig, PipelineTimingModel, TimingModel};
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A7, SP, T0, T1, T2};
+use ckb_vm::{DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, TraceMachine, ISA_IMC};
+use ckb_vm_definitions::encoding::{pack_b, pack_i, pack_r, to_riscv, with_length};
+
+type Machine = DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>;
+
+fn load(model: PipelineTimingModel) -> Machine {
+    let (t0, t1, t2) = (T0 as u8, T1 as u8, T2 as u8);
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 3),
+        pack_i(insts::OP_LD_VERSION1, t1, SP as u8, 0),
+        // Uses the loaded value right away.
+        pack_r(insts::OP_ADD, t2, t1, t0),
+        pack_i(insts::OP_ADDI, t0, t0, -1),
+        // Taken twice.
+        pack_b(insts::OP_BNE, t0, 0, -4),
+        pack_r(insts::OP_MUL, t2, t2, t2),
+        pack_i(insts::OP_ADDI, A0 as u8, 0, 0),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_r(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i: &Instruction| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .timing_model(Box::new(model))
+        .build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["timing".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+pub fn test_pipeline_timing_model() {
+    let mut machine = load(PipelineTimingModel::default());
+    let report = machine.run_with_report(false);
+    assert_eq!(report.result(), Ok(0));
+    assert_eq!(report.instructions_retired, 13);
+    // A load-use stall, two taken branches, the multiplication and the
+    // ECALL.
+    assert_eq!(report.timing_cycles, Some(13 + 1 + 2 * 2 + 2 + 4));
+    assert_eq!(report.to_report().timing_cycles, report.timing_cycles);
+    assert_eq!(machine.timing_model().unwrap().cycles(), 24);
+
+    let config = PipelineConfig {
+        load_use_penalty: 0,
+        taken_branch_penalty: 0,
+        jump_penalty: 0,
+        indirect_jump_penalty: 0,
+        mul_latency: 1,
+        div_latency: 1,
+        system_penalty: 0,
+    };
+    let mut machine = TraceMachine::new(load(PipelineTimingModel::new(config)));
+    let report = machine.run_with_report(false);
+    assert_eq!(report.timing_cycles, Some(13));
+
+    // Without a timing model.
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    assert_eq!(machine.run_with_report(false).timing_cycles, None);
+}
+
+#[test]
+pub fn test_pipeline_timing_model_stalls() {
+    let (t0, t1) = (T0 as u8, T1 as u8);
+    let mut model = PipelineTimingModel::default();
+    let load = with_length(pack_i(insts::OP_LW_VERSION1, t0, SP as u8, 0), 4);
+    let independent = with_length(pack_i(insts::OP_ADDI, t1, t1, 1), 4);
+    let dependent = with_length(pack_i(insts::OP_ADDI, t1, t0, 1), 4);
+    let branch = with_length(pack_b(insts::OP_BEQ, t0, t1, 8), 4);
+
+    model.retire(load, 0x100, 0x104);
+    model.retire(independent, 0x104, 0x108);
+    model.retire(dependent, 0x108, 0x10c);
+    assert_eq!(model.cycles(), 3);
+    model.retire(load, 0x10c, 0x110);
+    model.retire(dependent, 0x110, 0x114);
+    assert_eq!(model.cycles(), 6);
+    // Not taken, then taken.
+    model.retire(branch, 0x114, 0x118);
+    model.retire(branch, 0x118, 0x120);
+    assert_eq!(model.cycles(), 10);
+    assert_eq!(model.instructions(), 7);
+    assert_eq!(model.stalls(), 3);
+
+    model.reset();
+    assert_eq!(model.cycles(), 0);
+}
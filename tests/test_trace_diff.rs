@@ -0,0 +1,84 @@
+use ckb_vm::machine::cosim::{CommitRecord, MachineReference};
+use ckb_vm::machine::rvfi::RvfiRecord;
+use ckb_vm::machine::trace_diff::{diff_traces, record_trace, RegisterDelta, TraceMismatch};
+use ckb_vm::machine::VERSION1;
+use ckb_vm::registers::A0;
+use ckb_vm::{Bytes, DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, ISA_IMC};
+use std::fs;
+
+fn record_simple64() -> Vec<CommitRecord> {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION1, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine
+        .load_program(&buffer, &vec!["simple".into()])
+        .unwrap();
+    record_trace(&mut MachineReference::new(machine), 0).unwrap()
+}
+
+#[test]
+pub fn test_trace_diff_same() {
+    let trace = record_simple64();
+    assert!(trace.len() > 4);
+    assert_eq!(diff_traces(&trace, &record_simple64(), 3), None);
+}
+
+#[test]
+pub fn test_trace_diff_writes() {
+    let left = record_simple64();
+    let mut right = left.clone();
+    right[2].writes = vec![(A0, 0x1234)];
+    let diff = diff_traces(&left, &right, 1).unwrap();
+    assert_eq!(diff.index, 2);
+    assert!(matches!(diff.mismatch, TraceMismatch::Writes(_, _)));
+    let indices: Vec<usize> = diff.context.iter().map(|c| c.index).collect();
+    assert_eq!(indices, vec![1, 2, 3]);
+    assert_eq!(diff.context[1].right, Some(right[2].clone()));
+    assert!(diff.registers.contains(&RegisterDelta {
+        index: A0,
+        left: left[..3]
+            .iter()
+            .flat_map(|r| r.writes.iter())
+            .filter(|(i, _)| *i == A0)
+            .map(|(_, v)| *v)
+            .last(),
+        right: Some(0x1234),
+    }));
+    assert!(diff.to_string().starts_with("record 2: writes"));
+}
+
+#[test]
+pub fn test_trace_diff_pc_and_length() {
+    let left = record_simple64();
+    let mut right = left.clone();
+    right[0].pc += 4;
+    let diff = diff_traces(&left, &right, 2).unwrap();
+    assert_eq!(diff.index, 0);
+    assert_eq!(diff.mismatch, TraceMismatch::Pc(left[0].pc, left[0].pc + 4));
+    assert_eq!(diff.context.len(), 3);
+
+    let right = &left[..left.len() - 1];
+    let diff = diff_traces(&left, right, 2).unwrap();
+    assert_eq!(diff.index, right.len());
+    assert_eq!(
+        diff.mismatch,
+        TraceMismatch::Length(left.len(), right.len())
+    );
+    assert_eq!(diff.context.last().unwrap().right, None);
+}
+
+#[test]
+pub fn test_trace_diff_from_rvfi() {
+    let record = RvfiRecord {
+        insn: 0x00a00513,
+        pc_rdata: 0x1000,
+        rd_addr: A0 as u8,
+        rd_wdata: 10,
+        ..Default::default()
+    };
+    let commit = CommitRecord::from(&record);
+    assert_eq!(commit.pc, 0x1000);
+    assert_eq!(commit.instruction, 0x00a00513);
+    assert_eq!(commit.writes, vec![(A0, 10)]);
+}
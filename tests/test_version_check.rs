@@ -0,0 +1,64 @@
+use ckb_vm::machine::versions::{
+    check_versions, VersionCheckOptions, VersionDivergence, VersionDivergenceKind,
+};
+use ckb_vm::machine::{VERSION0, VERSION1, VERSION2};
+use ckb_vm::{Error, SparseMemory};
+use std::fs;
+
+fn check(program: &'static str, versions: Vec<u32>) -> Vec<VersionDivergence> {
+    let buffer = fs::read(format!("tests/programs/{}", program))
+        .unwrap()
+        .into();
+    let options = VersionCheckOptions {
+        versions,
+        ..Default::default()
+    };
+    check_versions::<u64, SparseMemory<u64>>(&buffer, &[program.into()], &options).unwrap()
+}
+
+fn execution(divergences: &[VersionDivergence]) -> Vec<&VersionDivergence> {
+    divergences
+        .iter()
+        .filter(|d| !matches!(d.kind, VersionDivergenceKind::Initial(_)))
+        .collect()
+}
+
+#[test]
+pub fn test_version_check_independent() {
+    assert_eq!(check("simple64", vec![VERSION1, VERSION2]), vec![]);
+    assert_eq!(
+        execution(&check("simple64", vec![VERSION0, VERSION1, VERSION2])),
+        Vec::<&VersionDivergence>::new()
+    );
+}
+
+#[test]
+pub fn test_version_check_read_at_boundary() {
+    let divergences = check("read_at_boundary64", vec![VERSION0, VERSION1, VERSION2]);
+    let divergences = execution(&divergences);
+    assert_eq!(divergences.len(), 2);
+    assert_eq!(divergences[0].baseline, VERSION0);
+    assert_eq!(divergences[0].version, VERSION1);
+    assert_eq!(divergences[1].version, VERSION2);
+    assert_eq!(
+        divergences[0].kind,
+        VersionDivergenceKind::Error {
+            baseline: Some(Error::MemOutOfBound),
+            actual: None,
+        }
+    );
+    assert!(divergences[0]
+        .to_string()
+        .starts_with("version 1 vs 0, step "));
+}
+
+#[test]
+pub fn test_version_check_jalr_bug() {
+    let divergences = check("jalr_bug", vec![VERSION1, VERSION0]);
+    let divergences = execution(&divergences);
+    assert_eq!(divergences.len(), 1);
+    assert_eq!(divergences[0].baseline, VERSION1);
+    assert_eq!(divergences[0].version, VERSION0);
+    assert!(divergences[0].step > 0);
+    assert_eq!(check("jalr_bug", vec![VERSION1, VERSION2]), vec![]);
+}
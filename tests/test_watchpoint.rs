@@ -0,0 +1,113 @@
+use ckb_vm::elf_writer::minimal_elf;
+use ckb_vm::instructions::insts;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::memory::watchpoint::{WatchKind, Watchpoint, WatchpointHit, WatchpointMemory};
+use ckb_vm::registers::{A0, A7, SP, T0};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Memory, SparseMemory,
+    TraceMachine, ISA_IMC,
+};
+use ckb_vm_definitions::encoding::{pack_i, pack_s, to_riscv};
+use std::sync::{Arc, Mutex};
+
+type Core = DefaultCoreMachine<u64, WatchpointMemory<SparseMemory<u64>>>;
+
+// Stores 5 at SP - 16 and reads it back, then stores it at SP - 8 and
+// exits with it. Returns the machine along with its SP.
+fn build() -> (DefaultMachine<Core>, u64) {
+    let (t0, sp) = (T0 as u8, SP as u8);
+    let code: Vec<u8> = [
+        pack_i(insts::OP_ADDI, t0, 0, 5),
+        pack_s(insts::OP_SD, sp, t0, -16),
+        pack_i(insts::OP_LD_VERSION1, A0 as u8, sp, -16),
+        pack_s(insts::OP_SD, sp, t0, -8),
+        pack_i(insts::OP_ADDI, A7 as u8, 0, 93),
+        pack_i(insts::OP_ECALL, 0, 0, 0),
+    ]
+    .iter()
+    .flat_map(|i| to_riscv(*i).unwrap().to_le_bytes())
+    .collect();
+    let core = Core::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine
+        .load_program(&minimal_elf::<u64>(&code), &["watchpoint".into()])
+        .unwrap();
+    let sp = machine.registers()[SP];
+    (machine, sp)
+}
+
+#[test]
+pub fn test_watchpoint_write() {
+    let (mut machine, sp) = build();
+    let entry = machine.pc().to_owned();
+    machine
+        .memory_mut()
+        .add_watchpoint(Watchpoint::new(sp - 8, 8, WatchKind::Write));
+    assert_eq!(
+        machine.run(),
+        Err(Error::Watchpoint {
+            addr: sp - 8,
+            pc: entry + 12
+        })
+    );
+    // The store didn't happen.
+    assert_eq!(machine.memory_mut().load64(&(sp - 8)), Ok(0));
+
+    let (machine, sp) = build();
+    let mut machine = TraceMachine::new(machine);
+    let entry = machine.pc().to_owned();
+    machine
+        .machine
+        .memory_mut()
+        .add_watchpoint(Watchpoint::new(sp - 12, 1, WatchKind::Access));
+    assert_eq!(
+        machine.run(),
+        Err(Error::Watchpoint {
+            addr: sp - 16,
+            pc: entry + 4
+        })
+    );
+}
+
+#[test]
+pub fn test_watchpoint_handler() {
+    let (mut machine, sp) = build();
+    let read = Watchpoint::new(sp - 16, 8, WatchKind::Read);
+    let write = Watchpoint::new(sp - 8, 8, WatchKind::Write);
+    let hits = Arc::new(Mutex::new(vec![]));
+    let memory = machine.memory_mut();
+    memory.add_watchpoint(read);
+    memory.add_watchpoint(write);
+    let recorded = Arc::clone(&hits);
+    memory.set_handler(Some(Box::new(move |hit: &WatchpointHit| {
+        recorded.lock().unwrap().push(*hit);
+        true
+    })));
+    assert_eq!(machine.run(), Ok(5));
+    assert_eq!(
+        *hits.lock().unwrap(),
+        vec![
+            WatchpointHit {
+                addr: sp - 16,
+                size: 8,
+                write: false,
+                watchpoint: read,
+            },
+            WatchpointHit {
+                addr: sp - 8,
+                size: 8,
+                write: true,
+                watchpoint: write,
+            },
+        ]
+    );
+
+    let (mut machine, sp) = build();
+    let memory = machine.memory_mut();
+    let write = Watchpoint::new(sp - 8, 8, WatchKind::Write);
+    memory.add_watchpoint(write);
+    assert!(memory.remove_watchpoint(&write));
+    assert!(!memory.remove_watchpoint(&write));
+    assert!(memory.watchpoints().is_empty());
+    assert_eq!(machine.run(), Ok(5));
+}